@@ -0,0 +1,69 @@
+// Batch math against its one at a time versions, run with `cargo bench --bench simd`.
+// Prints nanoseconds per element, best of a few runs to keep other processes out of it
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use ash_engine::math::{
+    simd::{self, scalar, Aabbs, Rotors, Vectors},
+    Aabb, Frustum, ModelMat, Rotor, Vector,
+};
+
+const COUNT: usize = 1 << 16;
+const RUNS: u32 = 20;
+
+fn best_of<F: FnMut()>(mut run: F) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            run();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn report(name: &str, scalar: Duration, batched: Duration) {
+    let per_element = |duration: Duration| duration.as_nanos() as f64 / COUNT as f64;
+    println!(
+        "{:<20} scalar {:>6.3} ns, batched {:>6.3} ns, {:.1}x",
+        name, per_element(scalar), per_element(batched), scalar.as_secs_f64() / batched.as_secs_f64(),
+    );
+}
+
+fn main() {
+    // scattered enough that nothing is constant folded
+    let value = |index: usize, salt: usize| ((index * 7919 + salt * 104729) % 2000) as f32 * 0.1 - 100.0;
+    let mut points = Vectors::with_capacity(COUNT);
+    let mut rotors = Rotors::default();
+    let mut aabbs = Aabbs::default();
+    for index in 0..COUNT {
+        let point = Vector::new(value(index, 0), value(index, 1), value(index, 2));
+        points.push(point);
+        rotors.push(Rotor::new(value(index, 3) + 101.0, value(index, 4), value(index, 5), value(index, 6)));
+        aabbs.push(Aabb { min: point, max: point + Vector::new(2.0, 2.0, 2.0) });
+    }
+
+    let model = ModelMat::from(Vector::new(1.0, 2.0, 1.0), Rotor::from_axis_angle(Vector::new(0.0, 1.0, 0.0), 0.5), Vector::new(1.0, 0.0, 3.0));
+    let mut out = Vectors::default();
+    report(
+        "transform_points",
+        best_of(|| scalar::transform_points(black_box(&model), black_box(&points), &mut out)),
+        best_of(|| simd::transform_points(black_box(&model), black_box(&points), &mut out)),
+    );
+
+    let mut normalized = rotors.clone();
+    report(
+        "normalize_rotors",
+        best_of(|| scalar::normalize_rotors(black_box(&mut normalized))),
+        best_of(|| simd::normalize_rotors(black_box(&mut normalized))),
+    );
+
+    let frustum = Frustum::from_proj_view(&ModelMat::identity().project(1.0, 1.0, 100.0, false));
+    let mut visible = vec![];
+    report(
+        "cull_aabbs",
+        best_of(|| scalar::cull_aabbs(black_box(&frustum), black_box(&aabbs), &mut visible)),
+        best_of(|| simd::cull_aabbs(black_box(&frustum), black_box(&aabbs), &mut visible)),
+    );
+}
@@ -0,0 +1,28 @@
+// Lists the precompiled shaders in shaders/spirv for the `embedded-shaders` feature,
+// see src/renderer/shader.rs. Compiling them is left to the compile_shaders binary
+
+use std::{env, fs, path::Path};
+
+fn main() {
+    println!("cargo:rerun-if-changed=shaders/spirv");
+    if env::var_os("CARGO_FEATURE_EMBEDDED_SHADERS").is_none() {
+        return;
+    }
+
+    let spirv_dir = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("shaders/spirv");
+    let mut entries: Vec<_> = fs::read_dir(&spirv_dir)
+        .unwrap_or_else(|err| panic!("{}: {}, run compile_shaders first", spirv_dir.display(), err))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "spv"))
+        .collect();
+    entries.sort();
+
+    let mut table = String::from("&[\n");
+    for path in entries {
+        // foo.frag.spv embedded as shaders/foo.frag
+        let name = path.file_stem().unwrap().to_str().unwrap();
+        table += &format!("    ({:?}, include_bytes!({:?})),\n", format!("shaders/{}", name), path);
+    }
+    table += "]\n";
+    fs::write(Path::new(&env::var("OUT_DIR").unwrap()).join("embedded_shaders.rs"), table).unwrap();
+}
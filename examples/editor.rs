@@ -0,0 +1,269 @@
+// Example game on the engine, a scene editor with weather and a day night cycle:
+//
+//     cargo run --example editor -- --width 1920 --vsync off
+
+use winit::event::MouseButton;
+
+use ash_engine::canvas::Canvas;
+use ash_engine::cli::{self, CommandLine};
+use ash_engine::config::{EngineConfig, CONFIG_PATH};
+use ash_engine::engine::{App, Engine};
+use ash_engine::events::AssetReloaded;
+use ash_engine::data_structures::bvh::Bvh;
+use ash_engine::gizmo::{self, Gizmo, GizmoInput};
+use ash_engine::label::{Label, LabelRenderer};
+use ash_engine::light::DayNightCycle;
+use ash_engine::logging;
+use ash_engine::math::{Aabb, ModelMat};
+use ash_engine::net::{self, Client, Server, Snapshot};
+use ash_engine::particles::ParticleSystem;
+use ash_engine::scene::{CameraState, Scene, SceneInstance};
+use ash_engine::scripting::ScriptSystem;
+
+/// loaded at startup when it exists unless `--scene` names another,
+/// F5 saves the scene back where it was loaded from with the current camera
+const SCENE_PATH: &str = "scenes/main.ron";
+
+struct Game {
+    scene: Scene,
+    scene_path: String,
+    scene_instance: SceneInstance,
+    day_night: DayNightCycle,
+    particles: ParticleSystem,
+    scripts: ScriptSystem,
+    gizmo: Gizmo,
+    /// scene object the gizmo edits, picked with the cursor while it's free
+    selected: Option<usize>,
+    /// object whose origin the last click was closest to, selected when no draw was under the cursor
+    origin_pick: Option<usize>,
+    /// names of the scene objects in the editor
+    labels: LabelRenderer,
+    /// the log overlay's
+    canvas: Canvas,
+    log_overlay: bool,
+    /// replicates the scene and camera with `--host`
+    server: Option<Server>,
+    /// follows a host's scene and camera with `--connect`
+    client: Option<Client>,
+}
+
+impl App for Game {
+    fn init(engine: &mut Engine) -> Self {
+        let scene_path = engine.command_line.scene.clone().unwrap_or_else(|| SCENE_PATH.to_owned());
+        let app = &mut engine.renderer;
+        let mut scene = if std::path::Path::new(&scene_path).exists() {
+            Scene::load(&scene_path)
+        } else {
+            Scene {
+                camera: CameraState::from_camera(&app.camera),
+                materials: vec![],
+                objects: vec![],
+                terrain: None,
+                scripts: vec![],
+                environment: None,
+            }
+        };
+        let mut particles = ParticleSystem::default();
+        let mut scene_instance = scene.instantiate(app, &mut particles, |app, path| app.load_geometry(path));
+
+        let labels = LabelRenderer::new(app);
+
+        let mut scripts = ScriptSystem::default();
+        scripts.scripts.hot_reload = engine.config.assets.hot_reload;
+        for path in scene.scripts.clone() {
+            scripts.load(&path, &mut scene, &mut scene_instance);
+        }
+
+        Game {
+            scene,
+            scene_path,
+            scene_instance,
+            // five minute days, starting mid morning
+            day_night: DayNightCycle::new(0.35, 300.0),
+            particles,
+            scripts,
+            gizmo: Gizmo::default(),
+            selected: None,
+            origin_pick: None,
+            canvas: Canvas::new(labels.font()),
+            log_overlay: false,
+            labels,
+            server: engine.command_line.host.as_deref().map(Server::bind),
+            client: engine.command_line.connect.as_deref().map(Client::connect),
+        }
+    }
+
+    fn fixed_update(&mut self, engine: &mut Engine, _dt: f32) {
+        if let Some(server) = &mut self.server {
+            server.tick(|tick| Snapshot {
+                tick,
+                camera: Some(CameraState::from_camera(&engine.renderer.camera)),
+                transforms: self.scene_instance.local_transforms(&self.scene),
+            });
+        }
+    }
+
+    fn update(&mut self, engine: &mut Engine, dt: f32) {
+        self.handle_input(engine);
+        self.handle_editor_input(engine);
+
+        self.update_scripts(engine, dt);
+
+        let app = &mut engine.renderer;
+        self.scene_instance.update_animations(dt);
+        let world_transforms = match &mut self.client {
+            Some(client) => {
+                client.update(dt);
+                if let Some(snapshot) = client.sample() {
+                    if let Some(camera) = snapshot.camera {
+                        camera.apply(&mut app.camera);
+                    }
+                    for (object, transform) in self.scene.objects.iter_mut().zip(snapshot.transforms) {
+                        object.transform = transform;
+                    }
+                }
+                // the host's transforms have its animations applied already
+                self.scene.world_transforms()
+            }
+            None => self.scene_instance.world_transforms(&self.scene),
+        };
+        // the selection is only shown in the editor
+        let outlined = self.selected.filter(|_| !app.in_game);
+        self.scene_instance.submit_draws(app, &world_transforms, outlined);
+        self.scene_instance.update_emitters(app, &mut self.particles, &world_transforms);
+        self.particles.update(dt);
+        app.gpu_particle_system.update(dt);
+        app.billboard_renderer.submit(self.particles.billboards());
+
+        app.weather.update(dt);
+        self.day_night.update(dt);
+        app.light = self.day_night.sun_light();
+        let mut clear_config = app.get_clear_config();
+        clear_config.clear_color = self.day_night.sky_color();
+        app.set_clear_config(clear_config);
+    }
+
+    fn draw_ui(&mut self, engine: &mut Engine) {
+        if self.log_overlay {
+            logging::draw_recent(&mut self.canvas, [8.0, 8.0], 24, 8.0);
+        }
+        self.canvas.submit(&mut engine.renderer);
+    }
+
+    fn on_exit(&mut self, engine: &mut Engine) {
+        self.scene_instance.release(&mut engine.renderer);
+    }
+}
+
+impl Game {
+    fn update_scripts(&mut self, engine: &mut Engine, dt: f32) {
+        self.scripts.scripts.hot_reload = engine.config.assets.hot_reload;
+        for path in self.scripts.reload_changed(&mut self.scene, &mut self.scene_instance) {
+            engine.events.publish(AssetReloaded { path });
+        }
+        self.scripts.update(
+            &mut self.scene,
+            &mut self.scene_instance,
+            &engine.renderer.input_state,
+            &engine.config.key_bindings,
+            dt,
+        );
+    }
+
+    fn handle_input(&mut self, engine: &mut Engine) {
+        let app = &mut engine.renderer;
+        let key_bindings = &engine.config.key_bindings;
+        if app.input_state.just_released(key_bindings.save_scene) {
+            self.scene.camera = CameraState::from_camera(&app.camera);
+            self.scene.save(&self.scene_path);
+        }
+
+        if app.input_state.just_released(key_bindings.cycle_weather) {
+            app.weather.precipitation = app.weather.precipitation.next();
+            log::info!("Weather: {:?}", app.weather.precipitation);
+        }
+
+        if app.input_state.just_released(key_bindings.toggle_log) {
+            self.log_overlay = !self.log_overlay;
+        }
+    }
+
+    fn handle_editor_input(&mut self, engine: &mut Engine) {
+        let app = &mut engine.renderer;
+        if app.in_game {
+            return;
+        }
+
+        if let Some(pick) = app.take_pick() {
+            self.selected = pick.pick_id.map(|index| index as usize).or(self.origin_pick);
+            if let Some(selected) = self.selected {
+                log::info!("Selected {}", self.scene.objects[selected].name);
+            }
+        }
+
+        if app.input_state.just_released(engine.config.key_bindings.cycle_gizmo) {
+            self.gizmo.mode = self.gizmo.mode.next();
+            log::info!("Gizmo: {:?}", self.gizmo.mode);
+        }
+
+        let viewport = app.get_viewport().extent;
+        let ray = app.get_viewport_cursor().and_then(|cursor| gizmo::cursor_ray(
+            &app.camera,
+            cursor,
+            viewport.width as f32,
+            viewport.height as f32,
+        ));
+        let input = GizmoInput {
+            ray,
+            pressed: app.input_state.is_mouse_button_pressed(MouseButton::Left),
+            just_pressed: app.input_state.mouse_button_just_pressed(MouseButton::Left),
+            snapping: app.input_state.is_key_pressed(engine.config.key_bindings.snap),
+        };
+        let world_transforms = self.scene_instance.world_transforms(&self.scene);
+
+        let labels: Vec<Label> = self.scene.objects.iter().zip(&world_transforms).enumerate().map(|(index, (object, world))| {
+            let translation = world.translation();
+            Label {
+                text: app.strings.intern(&object.name),
+                world_pos: [translation.x, translation.y, translation.z],
+                color: if self.selected == Some(index) { [1.0, 0.8, 0.2, 1.0] } else { [1.0; 4] },
+            }
+        }).collect();
+        self.labels.submit(app, &labels, None::<&Bvh<Aabb>>);
+
+        if let Some(selected) = self.selected {
+            let parent = self.scene.objects[selected].parent
+                .map_or(ModelMat::identity(), |parent| world_transforms[parent]);
+            let on_handle = self.gizmo.update(
+                &input,
+                app.camera.translation,
+                &world_transforms[selected],
+                &parent,
+                &mut self.scene.objects[selected].transform,
+            );
+            app.debug_line_renderer.submit(self.gizmo.lines(app.camera.translation, &world_transforms[selected], &parent));
+            if on_handle {
+                return;
+            }
+        }
+
+        if let (Some(ray), true) = (&input.ray, input.just_pressed) {
+            self.origin_pick = gizmo::pick_origin(ray, &world_transforms);
+            // exact to the pixel, arrives next frame
+            app.pick_at_cursor();
+        }
+    }
+}
+
+fn main() {
+    logging::init();
+    let command_line = CommandLine::parse(std::env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{}\n{}", err, cli::USAGE);
+        std::process::exit(2);
+    });
+    if let (Some(address), Some(true)) = (&command_line.host, command_line.headless) {
+        let scene_path = command_line.scene.as_deref().unwrap_or(SCENE_PATH);
+        net::run_headless_server(Scene::load(scene_path), address);
+    }
+    Engine::run::<Game>("Ash Window", EngineConfig::load(CONFIG_PATH), command_line);
+}
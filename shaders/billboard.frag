@@ -0,0 +1,14 @@
+#version 450
+
+#include "output.glsl"
+
+layout(location = 0) in vec2 fragCorner;
+layout(location = 1) in vec4 fragColor;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    // round with a soft falloff
+    float falloff = clamp(1.0 - length(fragCorner), 0.0, 1.0);
+    outColor = vec4(encodeOutput(fragColor.rgb), fragColor.a * falloff * falloff);
+}
@@ -0,0 +1,37 @@
+#version 450
+
+// xyz position, w size
+layout(location = 0) in vec4 iPositionSize;
+layout(location = 1) in vec4 iColor;
+
+layout(set = 0, binding = 0) uniform UniformBufferObject {
+    mat4 projView;
+    vec4 lightDirection;
+    vec4 lightColor;
+    vec4 cameraPosition;
+} global_ubo;
+
+layout(location = 0) out vec2 fragCorner;
+layout(location = 1) out vec4 fragColor;
+
+// two triangles
+const vec2 CORNERS[6] = vec2[](
+    vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+    vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(-1.0, 1.0)
+);
+
+void main() {
+    vec2 corner = CORNERS[gl_VertexIndex];
+    vec3 center = iPositionSize.xyz;
+
+    vec3 forward = normalize(global_ubo.cameraPosition.xyz - center);
+    vec3 right = cross(vec3(0.0, 1.0, 0.0), forward);
+    // looking straight up or down
+    right = dot(right, right) > 1e-6 ? normalize(right) : vec3(1.0, 0.0, 0.0);
+    vec3 up = cross(forward, right);
+
+    vec3 position = center + (right * corner.x + up * corner.y) * (0.5 * iPositionSize.w);
+    gl_Position = global_ubo.projView * vec4(position, 1.0);
+    fragCorner = corner;
+    fragColor = iColor;
+}
@@ -0,0 +1,29 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "output.glsl"
+
+// white near the camera fading to black, logarithmic so both near and far detail shows
+
+layout(location = 0) in vec2 fragTexCoord;
+layout(location = 1) in vec3 fragNormal;
+layout(location = 2) in vec4 fragTangent;
+layout(location = 3) in vec3 fragPosition;
+
+layout(set = 0, binding = 0) uniform UniformBufferObject {
+    mat4 projView;
+    vec4 lightDirection;
+    vec4 lightColor;
+    vec4 cameraPosition;
+} global_ubo;
+
+layout(location = 0) out vec4 outColor;
+
+// distance shaded black
+const float FAR_DISTANCE = 1000.0;
+
+void main() {
+    float distance = length(global_ubo.cameraPosition.xyz - fragPosition);
+    float shade = 1.0 - clamp(log2(1.0 + distance) / log2(1.0 + FAR_DISTANCE), 0.0, 1.0);
+    outColor = vec4(encodeOutput(vec3(shade)), 1.0);
+}
@@ -0,0 +1,11 @@
+#version 450
+
+#include "output.glsl"
+
+layout(location = 0) in vec4 fragColor;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    outColor = vec4(encodeOutput(fragColor.rgb), fragColor.a);
+}
@@ -0,0 +1,52 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "output.glsl"
+
+// the diffuse texture's mip level sampled, from blue where it is magnified
+// through green at its full resolution to red at its smallest mips
+
+layout(location = 0) in vec2 fragTexCoord;
+
+// size must match descriptor::MAX_TEXTURE_COUNT
+layout(set = 1, binding = 0) uniform sampler2D textures[20];
+
+struct Material {
+    // linear, pbr only like the fields after flags
+    vec4 albedo;
+    uint diffuseTexture;
+    uint normalTexture;
+    uint metallicRoughnessTexture;
+    uint occlusionTexture;
+    uint flags;
+    float metallic;
+    float roughness;
+};
+
+layout(std430, set = 1, binding = 1) readonly buffer Materials {
+    Material materials[];
+};
+
+layout(push_constant) uniform Draw {
+    uint materialIndex;
+} draw;
+
+layout(location = 0) out vec4 outColor;
+
+const vec3 LEVEL_COLORS[6] = vec3[](
+    vec3(0.0, 0.0, 1.0),
+    vec3(0.0, 1.0, 0.0),
+    vec3(0.5, 1.0, 0.0),
+    vec3(1.0, 1.0, 0.0),
+    vec3(1.0, 0.5, 0.0),
+    vec3(1.0, 0.0, 0.0)
+);
+
+void main() {
+    Material material = materials[draw.materialIndex];
+    // unclamped, below 0 the texture is magnified
+    float lod = textureQueryLod(textures[material.diffuseTexture], fragTexCoord).y;
+    float level = clamp(lod + 1.0, 0.0, 5.0);
+    vec3 color = mix(LEVEL_COLORS[int(floor(level))], LEVEL_COLORS[min(int(floor(level)) + 1, 5)], fract(level));
+    outColor = vec4(encodeOutput(color), 1.0);
+}
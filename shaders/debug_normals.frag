@@ -0,0 +1,53 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "output.glsl"
+
+#include "output.glsl"
+
+// world space normals after normal mapping, each axis from 0 to 1
+
+layout(location = 0) in vec2 fragTexCoord;
+layout(location = 1) in vec3 fragNormal;
+layout(location = 2) in vec4 fragTangent;
+
+// size must match descriptor::MAX_TEXTURE_COUNT
+layout(set = 1, binding = 0) uniform sampler2D textures[20];
+
+const uint MATERIAL_FLAG_NORMAL_MAP = 1;
+
+struct Material {
+    // linear, pbr only like the fields after flags
+    vec4 albedo;
+    uint diffuseTexture;
+    uint normalTexture;
+    uint metallicRoughnessTexture;
+    uint occlusionTexture;
+    uint flags;
+    float metallic;
+    float roughness;
+};
+
+layout(std430, set = 1, binding = 1) readonly buffer Materials {
+    Material materials[];
+};
+
+layout(push_constant) uniform Draw {
+    uint materialIndex;
+} draw;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    Material material = materials[draw.materialIndex];
+    vec3 normal = normalize(fragNormal);
+
+    if ((material.flags & MATERIAL_FLAG_NORMAL_MAP) != 0) {
+        vec3 tangent = normalize(fragTangent.xyz - normal * dot(normal, fragTangent.xyz));
+        vec3 bitangent = cross(normal, tangent) * fragTangent.w;
+        vec3 tangentNormal = texture(textures[material.normalTexture], fragTexCoord).xyz * 2.0 - 1.0;
+        normal = normalize(mat3(tangent, bitangent, normal) * tangentNormal);
+    }
+
+    outColor = vec4(encodeOutput(normal * 0.5 + 0.5), 1.0);
+}
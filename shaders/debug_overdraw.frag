@@ -0,0 +1,12 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// blended additively without depth testing, every layer of a pixel adds up
+// through red and yellow to white, saturating red after 10 layers, green after 20
+// and blue after 50. Best read with a black clear color
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    outColor = vec4(0.1, 0.05, 0.02, 1.0);
+}
@@ -0,0 +1,62 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "output.glsl"
+#include "fog.glsl"
+
+layout(input_attachment_index = 0, set = 0, binding = 0) uniform subpassInput gAlbedo;
+layout(input_attachment_index = 1, set = 0, binding = 1) uniform subpassInput gNormal;
+layout(input_attachment_index = 2, set = 0, binding = 2) uniform subpassInput gDepth;
+
+// must match gbuffer::LightingPushConstants
+layout(push_constant) uniform Lighting {
+    vec4 clearColor;
+    // towards the light
+    vec4 lightDirection;
+    // intensity scaled, ambient in w
+    vec4 lightColor;
+    float clearDepth;
+} lighting;
+
+layout(set = 1, binding = 0) uniform UniformBufferObject {
+    mat4 projView;
+    // towards the light
+    vec4 lightDirection;
+    // intensity scaled, ambient in w
+    vec4 lightColor;
+    vec4 cameraPosition;
+    vec4 wind;
+    float time;
+    // 0 dry to 1 soaked
+    float wetness;
+    // 0 without an environment
+    float environmentIntensity;
+    // see fog.glsl
+    uint fogMode;
+    vec4 fogColor;
+    vec4 fogParams;
+    mat4 inverseProjView;
+} global_ubo;
+
+layout(location = 0) in vec2 fragUv;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    // nothing was drawn here
+    if (subpassLoad(gDepth).r == lighting.clearDepth) {
+        outColor = vec4(encodeOutput(lighting.clearColor.rgb), lighting.clearColor.a);
+        return;
+    }
+
+    vec3 albedo = subpassLoad(gAlbedo).rgb;
+    vec3 normal = normalize(subpassLoad(gNormal).xyz);
+
+    vec3 light = max(dot(normal, lighting.lightDirection.xyz), 0.0) * lighting.lightColor.rgb
+        + lighting.lightColor.w;
+    // fogged from the depth buffer, the lighting subpass covers the scene's viewport
+    vec4 position = global_ubo.inverseProjView * vec4(fragUv * 2.0 - 1.0, subpassLoad(gDepth).r, 1.0);
+    vec3 color = applyFog(albedo * light, position.xyz / position.w, global_ubo.cameraPosition.xyz,
+        global_ubo.fogMode, global_ubo.fogColor, global_ubo.fogParams);
+    outColor = vec4(encodeOutput(color), 1.0);
+}
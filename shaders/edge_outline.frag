@@ -0,0 +1,123 @@
+#version 450
+
+#include "output.glsl"
+
+// Screen space outlines, see edge_outline.rs. Drawn over the scene with fullscreen.vert, each pixel
+// runs a Sobel filter over the distances to the camera and the normals of the pixels around it,
+// both reconstructed from the depth buffer, and covers itself in the outline color where either
+// changes sharply
+
+layout(set = 0, binding = 0) uniform sampler2D sceneDepth;
+
+layout(set = 1, binding = 0) uniform UniformBufferObject {
+    mat4 projView;
+    // towards the light
+    vec4 lightDirection;
+    // intensity scaled, ambient in w
+    vec4 lightColor;
+    vec4 cameraPosition;
+    vec4 wind;
+    float time;
+    // 0 dry to 1 soaked
+    float wetness;
+    // 0 without an environment
+    float environmentIntensity;
+    // see fog.glsl
+    uint fogMode;
+    vec4 fogColor;
+    vec4 fogParams;
+    mat4 inverseProjView;
+} global_ubo;
+
+// must match edge_outline::EdgePushConstants
+layout(push_constant) uniform Edges {
+    // straight alpha
+    vec4 color;
+    // of the letterboxed scene in the depth image
+    ivec2 viewportOffset;
+    ivec2 viewportSize;
+    // pixels between the filter's taps
+    int spacing;
+    // relative change in distance to the camera
+    float depthThreshold;
+    // change in normal
+    float normalThreshold;
+    float clearDepth;
+} edges;
+
+layout(location = 0) out vec4 outColor;
+
+// distance of sky taps relative to the center's
+const float SKY_DISTANCE_SCALE = 2.0;
+
+float depthAt(ivec2 pixel) {
+    pixel = clamp(pixel, edges.viewportOffset, edges.viewportOffset + edges.viewportSize - 1);
+    return texelFetch(sceneDepth, pixel, 0).r;
+}
+
+vec3 positionAt(ivec2 pixel) {
+    vec2 ndc = (vec2(pixel - edges.viewportOffset) + 0.5) / vec2(edges.viewportSize) * 2.0 - 1.0;
+    vec4 position = global_ubo.inverseProjView * vec4(ndc, depthAt(pixel), 1.0);
+    return position.xyz / position.w;
+}
+
+// of the neighbours on either side, the one closer in depth is more likely on the same surface
+vec3 tangentAlong(ivec2 pixel, ivec2 axis, vec3 center, float depth) {
+    float before = depthAt(pixel - axis);
+    float after = depthAt(pixel + axis);
+    return abs(before - depth) < abs(after - depth)
+        ? center - positionAt(pixel - axis)
+        : positionAt(pixel + axis) - center;
+}
+
+void main() {
+    ivec2 pixel = ivec2(gl_FragCoord.xy);
+    // the sky has no surface to outline, silhouettes are outlined on the geometry's side
+    if (depthAt(pixel) == edges.clearDepth) {
+        discard;
+    }
+
+    float centerDistance = length(positionAt(pixel) - global_ubo.cameraPosition.xyz);
+
+    // as in edge_outline::sobel, x and y gradients of each
+    float distanceX = 0.0;
+    float distanceY = 0.0;
+    vec3 normalX = vec3(0.0);
+    vec3 normalY = vec3(0.0);
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            ivec2 tap = pixel + ivec2(x, y) * edges.spacing;
+            float depth = depthAt(tap);
+            // the sky may be infinitely far, far enough behind the center makes the edge
+            float distanceToCamera = SKY_DISTANCE_SCALE * centerDistance;
+            vec3 normal = vec3(0.0);
+            if (depth != edges.clearDepth) {
+                vec3 position = positionAt(tap);
+                distanceToCamera = length(position - global_ubo.cameraPosition.xyz);
+                normal = normalize(cross(
+                    tangentAlong(tap, ivec2(1, 0), position, depth),
+                    tangentAlong(tap, ivec2(0, 1), position, depth)
+                ));
+            }
+
+            float weightX = float(x) * (y == 0 ? 2.0 : 1.0);
+            float weightY = float(y) * (x == 0 ? 2.0 : 1.0);
+            distanceX += weightX * distanceToCamera;
+            distanceY += weightY * distanceToCamera;
+            normalX += weightX * normal;
+            normalY += weightY * normal;
+        }
+    }
+
+    // relative, so far surfaces aren't outlined everywhere for being far apart
+    float depthEdge = length(vec2(distanceX, distanceY)) / max(centerDistance, 1e-4);
+    float normalEdge = sqrt(dot(normalX, normalX) + dot(normalY, normalY));
+    float edge = max(
+        smoothstep(edges.depthThreshold, edges.depthThreshold * 2.0, depthEdge),
+        smoothstep(edges.normalThreshold, edges.normalThreshold * 2.0, normalEdge)
+    );
+    if (edge <= 0.0) {
+        discard;
+    }
+    outColor = vec4(encodeOutput(edges.color.rgb), edges.color.a * edge);
+}
@@ -0,0 +1,26 @@
+// Fog blended over shaded colors, mirrors fog::Fog::amount. Values must match fog::FogMode,
+// the vectors come from fog::Fog::to_shader_vectors. World y points down
+
+const uint FOG_NONE = 0;
+const uint FOG_LINEAR = 1;
+const uint FOG_EXPONENTIAL = 2;
+
+// color with density in w, params are start, end, height and height falloff
+vec3 applyFog(vec3 color, vec3 position, vec3 cameraPosition, uint mode, vec4 fogColor, vec4 fogParams) {
+    if (mode == FOG_NONE) {
+        return color;
+    }
+
+    float distance = length(position - cameraPosition);
+    float amount;
+    if (mode == FOG_LINEAR) {
+        amount = clamp((distance - fogParams.x) / max(fogParams.y - fogParams.x, 1e-4), 0.0, 1.0);
+    } else {
+        // density integrated along the ray through fog thinning exponentially with altitude
+        float cameraDensity = fogColor.w * exp(-fogParams.w * (-cameraPosition.y - fogParams.z));
+        float falloff = fogParams.w * (cameraPosition.y - position.y);
+        float alongRay = abs(falloff) > 1e-4 ? (1.0 - exp(-falloff)) / falloff : 1.0;
+        amount = 1.0 - exp(-cameraDensity * distance * alongRay);
+    }
+    return mix(color, fogColor.rgb, amount);
+}
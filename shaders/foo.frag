@@ -1,13 +1,84 @@
 #version 450
 #extension GL_ARB_separate_shader_objects : enable
 
-// layout(location = 0) in vec3 fragColor;
-// layout(location = 1) in vec2 fragTexCoord;
+#include "output.glsl"
+#include "fog.glsl"
 
-// layout(set = 1, binding = 0) uniform sampler2D uTexSampler;
+layout(location = 0) in vec2 fragTexCoord;
+layout(location = 1) in vec3 fragNormal;
+layout(location = 2) in vec4 fragTangent;
+layout(location = 3) in vec3 fragPosition;
+
+// size must match descriptor::MAX_TEXTURE_COUNT
+layout(set = 1, binding = 0) uniform sampler2D textures[20];
+
+const uint MATERIAL_FLAG_NORMAL_MAP = 1;
+
+struct Material {
+    // linear, pbr only like the fields after flags
+    vec4 albedo;
+    uint diffuseTexture;
+    uint normalTexture;
+    uint metallicRoughnessTexture;
+    uint occlusionTexture;
+    uint flags;
+    float metallic;
+    float roughness;
+};
+
+layout(std430, set = 1, binding = 1) readonly buffer Materials {
+    Material materials[];
+};
+
+layout(push_constant) uniform Draw {
+    uint materialIndex;
+} draw;
+
+layout(set = 0, binding = 0) uniform UniformBufferObject {
+    mat4 projView;
+    // towards the light
+    vec4 lightDirection;
+    // intensity scaled, ambient in w
+    vec4 lightColor;
+    vec4 cameraPosition;
+    vec4 wind;
+    float time;
+    // 0 dry to 1 soaked
+    float wetness;
+    // 0 without an environment
+    float environmentIntensity;
+    // see fog.glsl
+    uint fogMode;
+    vec4 fogColor;
+    vec4 fogParams;
+} global_ubo;
 
 layout(location = 0) out vec4 outColor;
 
 void main() {
-    outColor = vec4(1.0, 1.0, 0.0, 1.0);
-}
\ No newline at end of file
+    Material material = materials[draw.materialIndex];
+    vec3 normal = normalize(fragNormal);
+
+    if ((material.flags & MATERIAL_FLAG_NORMAL_MAP) != 0) {
+        vec3 tangent = normalize(fragTangent.xyz - normal * dot(normal, fragTangent.xyz));
+        vec3 bitangent = cross(normal, tangent) * fragTangent.w;
+        vec3 tangentNormal = texture(textures[material.normalTexture], fragTexCoord).xyz * 2.0 - 1.0;
+        normal = normalize(mat3(tangent, bitangent, normal) * tangentNormal);
+    }
+
+    vec3 light = max(dot(normal, global_ubo.lightDirection.xyz), 0.0) * global_ubo.lightColor.rgb
+        + global_ubo.lightColor.w;
+    vec3 albedo = texture(textures[material.diffuseTexture], fragTexCoord).rgb;
+
+    // wet surfaces are darker and glossier
+    float wetness = global_ubo.wetness;
+    albedo *= 1.0 - 0.4 * wetness;
+    vec3 viewDirection = normalize(global_ubo.cameraPosition.xyz - fragPosition);
+    vec3 halfway = normalize(global_ubo.lightDirection.xyz + viewDirection);
+    float shininess = mix(16.0, 128.0, wetness);
+    float specular = wetness * pow(max(dot(normal, halfway), 0.0), shininess);
+
+    vec3 color = albedo * light + specular * global_ubo.lightColor.rgb;
+    color = applyFog(color, fragPosition, global_ubo.cameraPosition.xyz, global_ubo.fogMode, global_ubo.fogColor, global_ubo.fogParams);
+    outColor = vec4(encodeOutput(color), 1.0);
+}
@@ -2,20 +2,31 @@
 #extension GL_ARB_separate_shader_objects : enable
 
 layout(location = 0) in vec3 vPos;
-// layout(location = 1) in vec3 vColor;
-// layout(location = 2) in vec2 vTexCoord;
+layout(location = 1) in vec2 vTexCoord;
+layout(location = 2) in vec3 vNormal;
+layout(location = 3) in vec4 vTangent;
 
-// layout(location = 3) in mat4x3 iModel;
+layout(location = 4) in mat4x3 iModel;
 
 layout(set = 0, binding = 0) uniform UniformBufferObject {
     mat4 projView;
+    vec4 lightDirection;
+    vec4 lightColor;
 } global_ubo;
 
-// layout(location = 0) out vec3 fragColor;
-// layout(location = 1) out vec2 fragTexCoord;
+layout(location = 0) out vec2 fragTexCoord;
+layout(location = 1) out vec3 fragNormal;
+layout(location = 2) out vec4 fragTangent;
+layout(location = 3) out vec3 fragPosition;
+// picks the object's reflection probe in pbr.frag
+layout(location = 4) flat out vec3 fragObjectOrigin;
 
 void main() {
-    gl_Position = global_ubo.projView * vec4(vPos, 1.0);
-    // fragColor = vColor;
-    // fragTexCoord = vTexCoord;
-}
\ No newline at end of file
+    fragPosition = iModel * vec4(vPos, 1.0);
+    gl_Position = global_ubo.projView * vec4(fragPosition, 1.0);
+    fragTexCoord = vTexCoord;
+    // TODO: inverse transpose for non uniform scale
+    fragNormal = mat3(iModel) * vNormal;
+    fragTangent = vec4(mat3(iModel) * vTangent.xyz, vTangent.w);
+    fragObjectOrigin = iModel[3];
+}
@@ -0,0 +1,63 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec2 fragTexCoord;
+layout(location = 1) in vec3 fragNormal;
+layout(location = 2) in vec4 fragTangent;
+
+// size must match descriptor::MAX_TEXTURE_COUNT
+layout(set = 1, binding = 0) uniform sampler2D textures[20];
+
+const uint MATERIAL_FLAG_NORMAL_MAP = 1;
+
+struct Material {
+    // linear, pbr only like the fields after flags
+    vec4 albedo;
+    uint diffuseTexture;
+    uint normalTexture;
+    uint metallicRoughnessTexture;
+    uint occlusionTexture;
+    uint flags;
+    float metallic;
+    float roughness;
+};
+
+layout(std430, set = 1, binding = 1) readonly buffer Materials {
+    Material materials[];
+};
+
+layout(set = 0, binding = 0) uniform UniformBufferObject {
+    mat4 projView;
+    vec4 lightDirection;
+    vec4 lightColor;
+    vec4 cameraPosition;
+    vec4 wind;
+    float time;
+    // 0 dry to 1 soaked
+    float wetness;
+} global_ubo;
+
+layout(push_constant) uniform Draw {
+    uint materialIndex;
+} draw;
+
+layout(location = 0) out vec4 outAlbedo;
+layout(location = 1) out vec4 outNormal;
+
+void main() {
+    Material material = materials[draw.materialIndex];
+    vec3 normal = normalize(fragNormal);
+
+    if ((material.flags & MATERIAL_FLAG_NORMAL_MAP) != 0) {
+        vec3 tangent = normalize(fragTangent.xyz - normal * dot(normal, fragTangent.xyz));
+        vec3 bitangent = cross(normal, tangent) * fragTangent.w;
+        vec3 tangentNormal = texture(textures[material.normalTexture], fragTexCoord).xyz * 2.0 - 1.0;
+        normal = normalize(mat3(tangent, bitangent, normal) * tangentNormal);
+    }
+
+    // wet surfaces are darker
+    // TODO: wet specular, the lighting subpass has no view direction yet
+    vec3 albedo = texture(textures[material.diffuseTexture], fragTexCoord).rgb;
+    outAlbedo = vec4(albedo * (1.0 - 0.4 * global_ubo.wetness), 1.0);
+    outNormal = vec4(normal, 0.0);
+}
@@ -0,0 +1,36 @@
+// Shared by the image based lighting compute shaders, see ibl.rs
+
+const float PI = 3.14159265359;
+
+// through the center of texel `texel` of cube face `face`, faces in +x -x +y -y +z -z order
+vec3 cubeDirection(uint face, uvec2 texel, float size) {
+    vec2 uv = (vec2(texel) + 0.5) / size * 2.0 - 1.0;
+    vec3 direction;
+    switch (face) {
+        case 0: direction = vec3(1.0, -uv.y, -uv.x); break;
+        case 1: direction = vec3(-1.0, -uv.y, uv.x); break;
+        case 2: direction = vec3(uv.x, 1.0, uv.y); break;
+        case 3: direction = vec3(uv.x, -1.0, -uv.y); break;
+        case 4: direction = vec3(uv.x, -uv.y, 1.0); break;
+        default: direction = vec3(-uv.x, -uv.y, -1.0); break;
+    }
+    return normalize(direction);
+}
+
+// low discrepancy point `i` of `count` in the unit square
+vec2 hammersley(uint i, uint count) {
+    return vec2(float(i) / float(count), float(bitfieldReverse(i)) * 2.3283064365386963e-10);
+}
+
+// halfway vector around `normal` distributed by the ggx lobe of `roughness`
+vec3 importanceSampleGgx(vec2 xi, vec3 normal, float roughness) {
+    float a = roughness * roughness;
+    float phi = 2.0 * PI * xi.x;
+    float cosTheta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    float sinTheta = sqrt(1.0 - cosTheta * cosTheta);
+
+    vec3 up = abs(normal.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+    vec3 tangent = normalize(cross(up, normal));
+    vec3 bitangent = cross(normal, tangent);
+    return normalize(tangent * cos(phi) * sinTheta + bitangent * sin(phi) * sinTheta + normal * cosTheta);
+}
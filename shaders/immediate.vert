@@ -0,0 +1,18 @@
+#version 450
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec2 inTexCoord;
+layout(location = 2) in vec4 inColor;
+
+layout(set = 0, binding = 0) uniform UniformBufferObject {
+    mat4 projView;
+} global_ubo;
+
+layout(location = 0) out vec2 fragTexCoord;
+layout(location = 1) out vec4 fragColor;
+
+void main() {
+    gl_Position = global_ubo.projView * vec4(inPosition, 1.0);
+    fragTexCoord = inTexCoord;
+    fragColor = inColor;
+}
@@ -0,0 +1,38 @@
+#version 450
+
+#include "output.glsl"
+
+layout(location = 0) in vec2 fragTexCoord;
+
+layout(set = 0, binding = 0) uniform sampler2D minimap;
+
+layout(push_constant) uniform Sprite {
+    vec4 rect;
+    vec4 marker;
+} sprite;
+
+layout(location = 0) out vec4 outColor;
+
+const float BORDER = 0.01;
+// in texture coordinates
+const float MARKER_LENGTH = 0.06;
+
+void main() {
+    vec4 color = vec4(texture(minimap, fragTexCoord).rgb, 0.85);
+
+    vec2 edge = min(fragTexCoord, 1.0 - fragTexCoord);
+    if (min(edge.x, edge.y) < BORDER) {
+        color = vec4(1.0, 1.0, 1.0, 0.85);
+    }
+
+    // triangle around the player pointing along the heading
+    vec2 offset = fragTexCoord - sprite.marker.xy;
+    vec2 heading = sprite.marker.zw;
+    float along = dot(offset, heading) + MARKER_LENGTH / 3.0;
+    float across = abs(offset.x * heading.y - offset.y * heading.x);
+    if (along > 0.0 && along < MARKER_LENGTH && across < 0.5 * (MARKER_LENGTH - along)) {
+        color = vec4(1.0, 0.2, 0.1, 1.0);
+    }
+
+    outColor = vec4(encodeOutput(color.rgb), color.a);
+}
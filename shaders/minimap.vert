@@ -0,0 +1,22 @@
+#version 450
+
+layout(push_constant) uniform Sprite {
+    // min and max corner in normalized device coordinates
+    vec4 rect;
+    // player position in texture coordinates, then the heading
+    vec4 marker;
+} sprite;
+
+layout(location = 0) out vec2 fragTexCoord;
+
+// two triangles
+const vec2 CORNERS[6] = vec2[](
+    vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(1.0, 1.0),
+    vec2(0.0, 0.0), vec2(1.0, 1.0), vec2(0.0, 1.0)
+);
+
+void main() {
+    vec2 corner = CORNERS[gl_VertexIndex];
+    gl_Position = vec4(mix(sprite.rect.xy, sprite.rect.zw, corner), 0.0, 1.0);
+    fragTexCoord = corner;
+}
@@ -0,0 +1,17 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec4 fragClip;
+layout(location = 1) in vec4 fragPreviousClip;
+
+// in uv units, how far the surface moved on screen since the previous frame
+layout(location = 0) out vec2 outVelocity;
+
+void main() {
+    // was behind the camera
+    if (fragPreviousClip.w <= 0.0) {
+        outVelocity = vec2(0.0);
+        return;
+    }
+    outVelocity = (fragClip.xy / fragClip.w - fragPreviousClip.xy / fragPreviousClip.w) * 0.5;
+}
@@ -0,0 +1,57 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// Velocity of each object, see motion_blur.rs. Positions the vertex with this frame's and the
+// previous frame's model matrix and camera
+
+layout(location = 0) in vec3 vPos;
+
+layout(location = 4) in mat4x3 iModel;
+
+layout(set = 0, binding = 0) uniform UniformBufferObject {
+    mat4 projView;
+    // towards the light
+    vec4 lightDirection;
+    // intensity scaled, ambient in w
+    vec4 lightColor;
+    vec4 cameraPosition;
+    vec4 wind;
+    float time;
+    // 0 dry to 1 soaked
+    float wetness;
+    // 0 without an environment
+    float environmentIntensity;
+    // see fog.glsl
+    uint fogMode;
+    vec4 fogColor;
+    vec4 fogParams;
+    mat4 inverseProjView;
+    mat4 previousProjView;
+} global_ubo;
+
+// the previous model matrix of each of the frame's instances, laid out like iModel.
+// Plain floats, std430 would pad a mat4x3's columns
+layout(std430, set = 1, binding = 0) readonly buffer PreviousInstances {
+    float previousModels[];
+};
+
+layout(location = 0) out vec4 fragClip;
+layout(location = 1) out vec4 fragPreviousClip;
+
+mat4x3 previousModel() {
+    // counts from the draw's first instance, so indexes the frame's instances
+    uint first = uint(gl_InstanceIndex) * 12u;
+    mat4x3 model;
+    for (uint column = 0u; column < 4u; column++) {
+        uint offset = first + column * 3u;
+        model[column] = vec3(previousModels[offset], previousModels[offset + 1u], previousModels[offset + 2u]);
+    }
+    return model;
+}
+
+void main() {
+    vec4 position = vec4(vPos, 1.0);
+    fragClip = global_ubo.projView * vec4(iModel * position, 1.0);
+    fragPreviousClip = global_ubo.previousProjView * vec4(previousModel() * position, 1.0);
+    gl_Position = fragClip;
+}
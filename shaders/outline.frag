@@ -0,0 +1,14 @@
+#version 450
+
+#include "output.glsl"
+
+layout(push_constant) uniform PushConstants {
+    // straight alpha
+    vec4 color;
+} pc;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    outColor = vec4(encodeOutput(pc.color.rgb), pc.color.a);
+}
@@ -0,0 +1,14 @@
+#version 450
+
+layout(location = 0) in vec3 vPos;
+
+// already scaled up about the draw's origin
+layout(location = 4) in mat4x3 iModel;
+
+layout(set = 0, binding = 0) uniform UniformBufferObject {
+    mat4 projView;
+} global_ubo;
+
+void main() {
+    gl_Position = global_ubo.projView * vec4(iModel * vec4(vPos, 1.0), 1.0);
+}
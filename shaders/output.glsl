@@ -0,0 +1,59 @@
+// Encodes colors for the swapchain, include in fragment shaders drawing to it.
+// Shaded colors are treated as srgb encoded, like the textures they come from.
+// Specialized per pipeline, values must match swapchain::OutputTransfer
+
+layout(constant_id = 0) const uint OUTPUT_TRANSFER = 0;
+
+const uint OUTPUT_TRANSFER_NONE = 0;
+// the swapchain encodes on write
+const uint OUTPUT_TRANSFER_SRGB = 1;
+// hdr10, bt.2020 primaries with the st 2084 curve
+const uint OUTPUT_TRANSFER_PQ = 2;
+// scRGB, 1 is 80 nits
+const uint OUTPUT_TRANSFER_LINEAR = 3;
+
+// brightness of sdr white on hdr displays, must match swapchain::PAPER_WHITE_NITS
+const float PAPER_WHITE_NITS = 200.0;
+
+vec3 srgbToLinear(vec3 color) {
+    color = max(color, 0.0);
+    return mix(color / 12.92, pow((color + 0.055) / 1.055, vec3(2.4)), greaterThan(color, vec3(0.04045)));
+}
+
+vec3 linearToSrgb(vec3 color) {
+    color = max(color, 0.0);
+    return mix(color * 12.92, 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055, greaterThan(color, vec3(0.0031308)));
+}
+
+vec3 linearToPq(vec3 nits) {
+    const float m1 = 0.1593017578125;
+    const float m2 = 78.84375;
+    const float c1 = 0.8359375;
+    const float c2 = 18.8515625;
+    const float c3 = 18.6875;
+
+    vec3 y = pow(clamp(nits / 10000.0, 0.0, 1.0), vec3(m1));
+    return pow((c1 + c2 * y) / (1.0 + c3 * y), vec3(m2));
+}
+
+vec3 encodeOutput(vec3 color) {
+    if (OUTPUT_TRANSFER == OUTPUT_TRANSFER_NONE) {
+        return color;
+    }
+
+    vec3 linear = srgbToLinear(color);
+    if (OUTPUT_TRANSFER == OUTPUT_TRANSFER_SRGB) {
+        return linear;
+    }
+    if (OUTPUT_TRANSFER == OUTPUT_TRANSFER_LINEAR) {
+        return linear * (PAPER_WHITE_NITS / 80.0);
+    }
+
+    // bt.709 to bt.2020 primaries, columns
+    const mat3 toBt2020 = mat3(
+        0.6274, 0.0691, 0.0164,
+        0.3293, 0.9195, 0.0880,
+        0.0433, 0.0114, 0.8956
+    );
+    return linearToPq(toBt2020 * linear * PAPER_WHITE_NITS);
+}
@@ -0,0 +1,227 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "output.glsl"
+#include "fog.glsl"
+
+layout(location = 0) in vec2 fragTexCoord;
+layout(location = 1) in vec3 fragNormal;
+layout(location = 2) in vec4 fragTangent;
+layout(location = 3) in vec3 fragPosition;
+layout(location = 4) flat in vec3 fragObjectOrigin;
+
+// size must match descriptor::MAX_TEXTURE_COUNT
+layout(set = 1, binding = 0) uniform sampler2D textures[20];
+
+const uint MATERIAL_FLAG_NORMAL_MAP = 1;
+const uint MATERIAL_FLAG_METALLIC_ROUGHNESS_MAP = 2;
+const uint MATERIAL_FLAG_OCCLUSION_MAP = 4;
+
+struct Material {
+    // linear, pbr only like the fields after flags
+    vec4 albedo;
+    uint diffuseTexture;
+    uint normalTexture;
+    uint metallicRoughnessTexture;
+    uint occlusionTexture;
+    uint flags;
+    float metallic;
+    float roughness;
+};
+
+layout(std430, set = 1, binding = 1) readonly buffer Materials {
+    Material materials[];
+};
+
+// see ibl.rs
+layout(set = 2, binding = 0) uniform samplerCube irradianceMap;
+layout(set = 2, binding = 1) uniform samplerCube prefilteredMap;
+layout(set = 2, binding = 2) uniform sampler2D brdfLut;
+
+// see reflection_probe.rs, must match reflection_probe::MAX_REFLECTION_PROBES
+const uint MAX_REFLECTION_PROBES = 8;
+
+// must match reflection_probe::ShaderProbe
+struct ReflectionProbe {
+    // cube index into probeCubes in w
+    vec4 position;
+    vec4 boxMin;
+    vec4 boxMax;
+};
+
+layout(set = 2, binding = 3) uniform ReflectionProbes {
+    uint count;
+    ReflectionProbe probes[MAX_REFLECTION_PROBES];
+} reflectionProbes;
+// prefiltered like prefilteredMap
+layout(set = 2, binding = 4) uniform samplerCubeArray probeCubes;
+// see ssr.rs, linear colors premultiplied by their weight, traced the frame before.
+// Blurrier with each mip
+layout(set = 2, binding = 5) uniform sampler2D ssrMap;
+
+// must match ibl::PREFILTERED_MIP_LEVELS
+const float PREFILTERED_MAX_LOD = 4.0;
+const float PI = 3.14159265359;
+
+layout(push_constant) uniform Draw {
+    uint materialIndex;
+} draw;
+
+layout(set = 0, binding = 0) uniform UniformBufferObject {
+    mat4 projView;
+    // towards the light
+    vec4 lightDirection;
+    // intensity scaled, ambient in w
+    vec4 lightColor;
+    vec4 cameraPosition;
+    vec4 wind;
+    float time;
+    // 0 dry to 1 soaked
+    float wetness;
+    // 0 without an environment
+    float environmentIntensity;
+    // see fog.glsl
+    uint fogMode;
+    vec4 fogColor;
+    vec4 fogParams;
+} global_ubo;
+
+layout(location = 0) out vec4 outColor;
+
+float distributionGgx(float normalDotHalfway, float roughness) {
+    float a = roughness * roughness;
+    float denominator = normalDotHalfway * normalDotHalfway * (a * a - 1.0) + 1.0;
+    return a * a / (PI * denominator * denominator);
+}
+
+float geometrySchlickGgx(float normalDotDirection, float roughness) {
+    float k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    return normalDotDirection / (normalDotDirection * (1.0 - k) + k);
+}
+
+vec3 fresnelSchlick(float cosTheta, vec3 f0, float roughness) {
+    return f0 + (max(vec3(1.0 - roughness), f0) - f0) * pow(1.0 - cosTheta, 5.0);
+}
+
+// Karis' fit of the BRDF lut, which is black until an environment is set
+vec2 brdfApprox(float normalDotView, float roughness) {
+    const vec4 c0 = vec4(-1.0, -0.0275, -0.572, 0.022);
+    const vec4 c1 = vec4(1.0, 0.0425, 1.04, -0.04);
+    vec4 r = roughness * c0 + c1;
+    float a004 = min(r.x * r.x, exp2(-9.28 * normalDotView)) * r.x + r.y;
+    return vec2(-1.04, 1.04) * a004 + r.zw;
+}
+
+// nearest probe whose box holds `origin`, -1 outside every box, mirrors reflection_probe::nearest_probe
+int nearestProbe(vec3 origin) {
+    int nearest = -1;
+    float nearestDistanceSqr = 0.0;
+    for (uint i = 0; i < reflectionProbes.count; i++) {
+        ReflectionProbe probe = reflectionProbes.probes[i];
+        if (any(lessThan(origin, probe.boxMin.xyz)) || any(greaterThan(origin, probe.boxMax.xyz))) {
+            continue;
+        }
+        vec3 offset = origin - probe.position.xyz;
+        float distanceSqr = dot(offset, offset);
+        if (nearest < 0 || distanceSqr < nearestDistanceSqr) {
+            nearest = int(i);
+            nearestDistanceSqr = distanceSqr;
+        }
+    }
+    return nearest;
+}
+
+// from the probe to where the ray from `position` leaves its box, mirrors ReflectionProbe::box_project
+vec3 boxProject(ReflectionProbe probe, vec3 direction, vec3 position) {
+    vec3 exits = max((probe.boxMax.xyz - position) / direction, (probe.boxMin.xyz - position) / direction);
+    float distance = max(min(min(exits.x, exits.y), exits.z), 0.0);
+    return position + direction * distance - probe.position.xyz;
+}
+
+void main() {
+    Material material = materials[draw.materialIndex];
+    vec3 normal = normalize(fragNormal);
+
+    if ((material.flags & MATERIAL_FLAG_NORMAL_MAP) != 0) {
+        vec3 tangent = normalize(fragTangent.xyz - normal * dot(normal, fragTangent.xyz));
+        vec3 bitangent = cross(normal, tangent) * fragTangent.w;
+        vec3 tangentNormal = texture(textures[material.normalTexture], fragTexCoord).xyz * 2.0 - 1.0;
+        normal = normalize(mat3(tangent, bitangent, normal) * tangentNormal);
+    }
+
+    vec3 albedo = srgbToLinear(texture(textures[material.diffuseTexture], fragTexCoord).rgb) * material.albedo.rgb;
+    float metallic = material.metallic;
+    float roughness = material.roughness;
+    if ((material.flags & MATERIAL_FLAG_METALLIC_ROUGHNESS_MAP) != 0) {
+        vec4 metallicRoughness = texture(textures[material.metallicRoughnessTexture], fragTexCoord);
+        metallic *= metallicRoughness.b;
+        roughness *= metallicRoughness.g;
+    }
+    float occlusion = 1.0;
+    if ((material.flags & MATERIAL_FLAG_OCCLUSION_MAP) != 0) {
+        occlusion = texture(textures[material.occlusionTexture], fragTexCoord).r;
+    }
+
+    // wet surfaces are darker and glossier
+    float wetness = global_ubo.wetness;
+    albedo *= 1.0 - 0.4 * wetness;
+    roughness = clamp(roughness * (1.0 - 0.6 * wetness), 0.04, 1.0);
+
+    vec3 view = normalize(global_ubo.cameraPosition.xyz - fragPosition);
+    float normalDotView = max(dot(normal, view), 0.0001);
+    vec3 f0 = mix(vec3(0.04), albedo, metallic);
+
+    // cook torrance for the sun
+    vec3 light = global_ubo.lightDirection.xyz;
+    vec3 halfway = normalize(light + view);
+    float normalDotLight = max(dot(normal, light), 0.0);
+    vec3 fresnel = fresnelSchlick(max(dot(halfway, view), 0.0), f0, 0.0);
+    float geometry = geometrySchlickGgx(normalDotView, roughness) * geometrySchlickGgx(normalDotLight, roughness);
+    vec3 specular = distributionGgx(max(dot(normal, halfway), 0.0), roughness) * geometry * fresnel
+        / (4.0 * normalDotView * max(normalDotLight, 0.0001));
+    vec3 diffuse = (1.0 - fresnel) * (1.0 - metallic) * albedo / PI;
+    vec3 color = (diffuse + specular) * global_ubo.lightColor.rgb * normalDotLight;
+
+    // the environment replaces the flat ambient term, a reflection probe the environment's reflections
+    // and screen space reflections either where they hit
+    vec3 ambient = global_ubo.lightColor.w * albedo;
+    bool hasEnvironment = global_ubo.environmentIntensity > 0.0;
+    int probe = nearestProbe(fragObjectOrigin);
+    vec4 screenReflection = textureLod(
+        ssrMap,
+        gl_FragCoord.xy / vec2(textureSize(ssrMap, 0)),
+        roughness * float(textureQueryLevels(ssrMap) - 1)
+    );
+    if (hasEnvironment || probe >= 0 || screenReflection.a > 0.0) {
+        vec3 ambientFresnel = fresnelSchlick(normalDotView, f0, roughness);
+        vec3 irradiance = hasEnvironment
+            ? texture(irradianceMap, normal).rgb * global_ubo.environmentIntensity
+            : vec3(global_ubo.lightColor.w);
+        vec3 ambientDiffuse = (1.0 - ambientFresnel) * (1.0 - metallic) * albedo * irradiance;
+
+        vec3 reflected = reflect(-view, normal);
+        float lod = roughness * PREFILTERED_MAX_LOD;
+        vec3 prefiltered;
+        if (probe >= 0) {
+            // captured radiance, not scaled by the environment intensity
+            ReflectionProbe reflectionProbe = reflectionProbes.probes[probe];
+            vec3 direction = boxProject(reflectionProbe, reflected, fragPosition);
+            prefiltered = textureLod(probeCubes, vec4(direction, reflectionProbe.position.w), lod).rgb;
+        } else if (hasEnvironment) {
+            prefiltered = textureLod(prefilteredMap, reflected, lod).rgb * global_ubo.environmentIntensity;
+        } else {
+            prefiltered = vec3(global_ubo.lightColor.w);
+        }
+        prefiltered = prefiltered * (1.0 - screenReflection.a) + screenReflection.rgb;
+        vec2 brdf = hasEnvironment
+            ? texture(brdfLut, vec2(normalDotView, roughness)).rg
+            : brdfApprox(normalDotView, roughness);
+        ambient = ambientDiffuse + prefiltered * (ambientFresnel * brdf.x + brdf.y);
+    }
+    color += ambient * occlusion;
+
+    // reinhard, shaded colors are srgb encoded like the textures
+    vec3 encoded = linearToSrgb(color / (1.0 + color));
+    encoded = applyFog(encoded, fragPosition, global_ubo.cameraPosition.xyz, global_ubo.fogMode, global_ubo.fogColor, global_ubo.fogParams);
+    outColor = vec4(encodeOutput(encoded), 1.0);
+}
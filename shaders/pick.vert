@@ -0,0 +1,19 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec3 vPos;
+
+layout(location = 4) in mat4x3 iModel;
+
+layout(set = 0, binding = 0) uniform UniformBufferObject {
+    mat4 projView;
+} global_ubo;
+
+// 0 is left for pixels nothing covers
+layout(location = 0) flat out uint fragPickIndex;
+
+void main() {
+    gl_Position = global_ubo.projView * vec4(iModel * vec4(vPos, 1.0), 1.0);
+    // counts from the draw's first instance, so indexes the frame's instances
+    fragPickIndex = uint(gl_InstanceIndex) + 1;
+}
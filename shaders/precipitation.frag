@@ -0,0 +1,21 @@
+#version 450
+
+#include "output.glsl"
+
+layout(location = 0) in vec2 fragCorner;
+layout(location = 1) in float fragFade;
+
+// must match precipitation::DrawPushConstants
+layout(push_constant) uniform Draw {
+    vec4 color;
+    float halfWidth;
+    float streakSecs;
+} draw;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    // soft edges, round flakes and tapered streaks
+    float edge = clamp(1.0 - length(fragCorner), 0.0, 1.0);
+    outColor = vec4(encodeOutput(draw.color.rgb), draw.color.a * edge * fragFade);
+}
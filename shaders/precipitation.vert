@@ -0,0 +1,52 @@
+#version 450
+
+struct Particle {
+    // w is 1 while falling, fades to 0 after a hit
+    vec4 position;
+    vec4 velocity;
+};
+
+layout(set = 0, binding = 0) uniform UniformBufferObject {
+    mat4 projView;
+    vec4 lightDirection;
+    vec4 lightColor;
+    vec4 cameraPosition;
+} global_ubo;
+
+layout(std430, set = 1, binding = 0) readonly buffer Particles { Particle particles[]; };
+
+// must match precipitation::DrawPushConstants
+layout(push_constant) uniform Draw {
+    vec4 color;
+    float halfWidth;
+    float streakSecs;
+} draw;
+
+layout(location = 0) out vec2 fragCorner;
+layout(location = 1) out float fragFade;
+
+// two triangles, y runs from the head of the streak to its tail
+const vec2 CORNERS[6] = vec2[](
+    vec2(-1.0, 0.0), vec2(1.0, 0.0), vec2(1.0, 1.0),
+    vec2(-1.0, 0.0), vec2(1.0, 1.0), vec2(-1.0, 1.0)
+);
+
+void main() {
+    Particle p = particles[gl_InstanceIndex];
+    vec2 corner = CORNERS[gl_VertexIndex];
+
+    // streaks trail back along the velocity, flakes are as long as they are wide
+    vec3 velocity = p.velocity.xyz;
+    float speed = length(velocity);
+    vec3 axis = speed > 0.0 ? velocity / speed : vec3(0.0, 1.0, 0.0);
+    float streakLength = max(speed * draw.streakSecs, 2.0 * draw.halfWidth);
+
+    // faces the camera while staying aligned with the axis
+    vec3 toCamera = global_ubo.cameraPosition.xyz - p.position.xyz;
+    vec3 side = normalize(cross(axis, toCamera) + vec3(0.0, 0.0, 1e-6));
+
+    vec3 position = p.position.xyz + side * corner.x * draw.halfWidth - axis * corner.y * streakLength;
+    gl_Position = global_ubo.projView * vec4(position, 1.0);
+    fragCorner = vec2(corner.x, corner.y * 2.0 - 1.0);
+    fragFade = p.position.w;
+}
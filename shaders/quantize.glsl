@@ -0,0 +1,11 @@
+// Decodes quantized vertex attributes, matching src/quantize.rs.
+// Half floats and snorms are decoded by the vertex input already
+
+// unit vector from two octahedral encoded snorms
+vec3 octDecode(vec2 encoded) {
+    vec3 v = vec3(encoded, 1.0 - abs(encoded.x) - abs(encoded.y));
+    float fold = max(-v.z, 0.0);
+    // the lower half was folded over the diagonals
+    v.xy += mix(vec2(fold), vec2(-fold), greaterThanEqual(v.xy, vec2(0.0)));
+    return normalize(v);
+}
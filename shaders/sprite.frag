@@ -0,0 +1,20 @@
+#version 450
+
+#include "output.glsl"
+
+layout(location = 0) in vec2 fragTexCoord;
+layout(location = 1) in vec4 fragColor;
+
+// size must match descriptor::MAX_TEXTURE_COUNT
+layout(set = 1, binding = 0) uniform sampler2D textures[20];
+
+layout(push_constant) uniform Draw {
+    uint textureIndex;
+} draw;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    vec4 color = texture(textures[draw.textureIndex], fragTexCoord) * fragColor;
+    outColor = vec4(encodeOutput(color.rgb), color.a);
+}
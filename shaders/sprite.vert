@@ -0,0 +1,41 @@
+#version 450
+
+// world space center with w 0, or the corner in normalized device coordinates with w 1
+layout(location = 0) in vec4 inPosition;
+// offset along the camera's right and up
+layout(location = 1) in vec2 inCorner;
+layout(location = 2) in vec2 inTexCoord;
+layout(location = 3) in vec4 inColor;
+
+layout(set = 0, binding = 0) uniform UniformBufferObject {
+    mat4 projView;
+    vec4 lightDirection;
+    vec4 lightColor;
+    vec4 cameraPosition;
+} global_ubo;
+
+// set by the renderer, the near plane is at depth 1 when reversed
+layout(constant_id = 1) const bool REVERSE_Z = false;
+
+layout(location = 0) out vec2 fragTexCoord;
+layout(location = 1) out vec4 fragColor;
+
+void main() {
+    if (inPosition.w > 0.5) {
+        // on the near plane, in front of the scene
+        gl_Position = vec4(inPosition.xy, REVERSE_Z ? 1.0 : 0.0, 1.0);
+    } else {
+        vec3 center = inPosition.xyz;
+        vec3 toCamera = normalize(global_ubo.cameraPosition.xyz - center);
+        // world y points down
+        vec3 right = cross(toCamera, vec3(0.0, 1.0, 0.0));
+        // looking straight up or down
+        right = dot(right, right) > 1e-6 ? normalize(right) : vec3(1.0, 0.0, 0.0);
+        vec3 up = cross(toCamera, right);
+
+        vec3 position = center + right * inCorner.x + up * inCorner.y;
+        gl_Position = global_ubo.projView * vec4(position, 1.0);
+    }
+    fragTexCoord = inTexCoord;
+    fragColor = inColor;
+}
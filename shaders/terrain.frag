@@ -0,0 +1,69 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "output.glsl"
+#include "fog.glsl"
+
+layout(location = 0) in vec2 fragTexCoord;
+layout(location = 1) in vec3 fragNormal;
+layout(location = 2) in vec3 fragPosition;
+
+// size must match descriptor::MAX_TEXTURE_COUNT
+layout(set = 1, binding = 0) uniform sampler2D textures[20];
+
+// must match terrain::TerrainMaterial
+layout(push_constant) uniform Terrain {
+    uint splatTexture;
+    uint layerTextures[4];
+    float layerTiling;
+} terrain;
+
+layout(set = 0, binding = 0) uniform UniformBufferObject {
+    mat4 projView;
+    // towards the light
+    vec4 lightDirection;
+    // intensity scaled, ambient in w
+    vec4 lightColor;
+    vec4 cameraPosition;
+    vec4 wind;
+    float time;
+    // 0 dry to 1 soaked
+    float wetness;
+    // 0 without an environment
+    float environmentIntensity;
+    // see fog.glsl
+    uint fogMode;
+    vec4 fogColor;
+    vec4 fogParams;
+} global_ubo;
+
+layout(location = 0) out vec4 outColor;
+
+// the splat map's channels weigh the layers, normalized so unpainted areas aren't black
+vec3 splatAlbedo() {
+    vec4 weights = texture(textures[terrain.splatTexture], fragTexCoord);
+    weights /= max(dot(weights, vec4(1.0)), 1e-4);
+    vec2 layerTexCoord = fragTexCoord * terrain.layerTiling;
+    return texture(textures[terrain.layerTextures[0]], layerTexCoord).rgb * weights.r
+        + texture(textures[terrain.layerTextures[1]], layerTexCoord).rgb * weights.g
+        + texture(textures[terrain.layerTextures[2]], layerTexCoord).rgb * weights.b
+        + texture(textures[terrain.layerTextures[3]], layerTexCoord).rgb * weights.a;
+}
+
+void main() {
+    vec3 normal = normalize(fragNormal);
+    vec3 light = max(dot(normal, global_ubo.lightDirection.xyz), 0.0) * global_ubo.lightColor.rgb
+        + global_ubo.lightColor.w;
+
+    // wet ground is darker and glossier
+    float wetness = global_ubo.wetness;
+    vec3 albedo = splatAlbedo() * (1.0 - 0.4 * wetness);
+    vec3 viewDirection = normalize(global_ubo.cameraPosition.xyz - fragPosition);
+    vec3 halfway = normalize(global_ubo.lightDirection.xyz + viewDirection);
+    float shininess = mix(16.0, 128.0, wetness);
+    float specular = wetness * pow(max(dot(normal, halfway), 0.0), shininess);
+
+    vec3 color = albedo * light + specular * global_ubo.lightColor.rgb;
+    color = applyFog(color, fragPosition, global_ubo.cameraPosition.xyz, global_ubo.fogMode, global_ubo.fogColor, global_ubo.fogParams);
+    outColor = vec4(encodeOutput(color), 1.0);
+}
@@ -0,0 +1,26 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// terrain vertices are in world space, no instance transform
+layout(location = 0) in vec3 vPos;
+layout(location = 1) in vec2 vTexCoord;
+layout(location = 2) in vec3 vNormal;
+layout(location = 3) in vec4 vTangent;
+
+layout(set = 0, binding = 0) uniform UniformBufferObject {
+    mat4 projView;
+    vec4 lightDirection;
+    vec4 lightColor;
+    vec4 cameraPosition;
+} global_ubo;
+
+layout(location = 0) out vec2 fragTexCoord;
+layout(location = 1) out vec3 fragNormal;
+layout(location = 2) out vec3 fragPosition;
+
+void main() {
+    fragPosition = vPos;
+    gl_Position = global_ubo.projView * vec4(vPos, 1.0);
+    fragTexCoord = vTexCoord;
+    fragNormal = vNormal;
+}
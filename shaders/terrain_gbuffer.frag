@@ -0,0 +1,47 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec2 fragTexCoord;
+layout(location = 1) in vec3 fragNormal;
+layout(location = 2) in vec3 fragPosition;
+
+// size must match descriptor::MAX_TEXTURE_COUNT
+layout(set = 1, binding = 0) uniform sampler2D textures[20];
+
+// must match terrain::TerrainMaterial
+layout(push_constant) uniform Terrain {
+    uint splatTexture;
+    uint layerTextures[4];
+    float layerTiling;
+} terrain;
+
+layout(set = 0, binding = 0) uniform UniformBufferObject {
+    mat4 projView;
+    vec4 lightDirection;
+    vec4 lightColor;
+    vec4 cameraPosition;
+    vec4 wind;
+    float time;
+    // 0 dry to 1 soaked
+    float wetness;
+} global_ubo;
+
+layout(location = 0) out vec4 outAlbedo;
+layout(location = 1) out vec4 outNormal;
+
+// the splat map's channels weigh the layers, normalized so unpainted areas aren't black
+vec3 splatAlbedo() {
+    vec4 weights = texture(textures[terrain.splatTexture], fragTexCoord);
+    weights /= max(dot(weights, vec4(1.0)), 1e-4);
+    vec2 layerTexCoord = fragTexCoord * terrain.layerTiling;
+    return texture(textures[terrain.layerTextures[0]], layerTexCoord).rgb * weights.r
+        + texture(textures[terrain.layerTextures[1]], layerTexCoord).rgb * weights.g
+        + texture(textures[terrain.layerTextures[2]], layerTexCoord).rgb * weights.b
+        + texture(textures[terrain.layerTextures[3]], layerTexCoord).rgb * weights.a;
+}
+
+void main() {
+    // wet ground is darker
+    outAlbedo = vec4(splatAlbedo() * (1.0 - 0.4 * global_ubo.wetness), 1.0);
+    outNormal = vec4(normalize(fragNormal), 0.0);
+}
@@ -1,8 +1,12 @@
 use crate::utils;
 use core::ptr::null_mut;
 use core::mem::size_of;
+use std::collections::BTreeMap;
+use std::fmt::Write;
 
-// uses buddy allocation
+// uses buddy allocation, see debug.rs for validating its use
+
+pub mod debug;
 
 struct FreeListNode {
     next:           *mut FreeListNode,
@@ -19,11 +23,32 @@ pub struct Allocator {
     /// Heap is broken into a binary tree like structure.
     /// Use this to check if a tree section is free.
     free_tree:   Vec<usize>,
+    /// only kept once `enable_stats` is called
+    stats:       Option<AllocatorStats>,
+}
+
+/// A live allocation, recorded while stats are enabled
+#[derive(Clone, Debug)]
+pub struct Outstanding {
+    pub level: BlockLevel,
+    pub requested_size: usize,
+    pub tag: String,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct AllocatorStats {
+    /// sum of the handed out block sizes, includes the rounding up to a power of two
+    pub allocated_bytes: usize,
+    pub requested_bytes: usize,
+    pub peak_allocated_bytes: usize,
+    /// keyed by offset from `heap_start`
+    pub outstanding: BTreeMap<usize, Outstanding>,
 }
 
 pub type BlockSize = u16;
 pub type BlockLevel = u8;
-pub type FreeTreeIndex = u16;
+/// u32 so grown heaps with many levels still fit
+pub type FreeTreeIndex = u32;
 
 impl Allocator {
     /// uses provided ptr to manage the heap
@@ -35,13 +60,75 @@ impl Allocator {
 
         let mut free_list_heads = vec![null_mut(); block_levels as usize];
         free_list_heads[0] = heap_start as *mut FreeListNode;
+        // the heap's memory may hold anything
+        *free_list_heads[0] = FreeListNode { next: null_mut(), previous: null_mut(), free_tree_index: 0 };
 
         Self {
             heap_start,
             heap_size,
             free_list_heads,
             free_tree: vec![!0; utils::align_up(2 * block_count - 1, 8 * size_of::<usize>()) / 8],
+            stats: None,
+        }
+    }
+
+    /// starts recording usage and outstanding allocations,
+    /// must be called before anything is allocated
+    pub fn enable_stats(&mut self) {
+        assert!(self.is_empty(), "stats must be enabled before the first allocation");
+        self.stats = Some(AllocatorStats::default());
+    }
+
+    pub fn stats(&self) -> Option<&AllocatorStats> {
+        self.stats.as_ref()
+    }
+
+    /// true when every allocation has been freed and coalesced back into the whole heap
+    pub fn is_empty(&self) -> bool {
+        self.free_list_heads[0] as *mut u8 == self.heap_start
+    }
+
+    /// number of free blocks in each level's free list, level 0 is the whole heap
+    pub fn free_block_counts(&self) -> Vec<usize> {
+        self.free_list_heads.iter().map(|&head| {
+            let mut count = 0;
+            let mut node = head;
+            while !node.is_null() {
+                count += 1;
+                node = unsafe { (*node).next };
+            }
+            count
+        }).collect()
+    }
+
+    /// human readable summary of usage, free blocks and outstanding allocations
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "heap of {} bytes, free blocks per level {:?}", self.heap_size, self.free_block_counts()).unwrap();
+
+        let Some(stats) = &self.stats else {
+            writeln!(out, "stats disabled").unwrap();
+            return out;
+        };
+        writeln!(
+            out,
+            "{} bytes allocated ({} requested), peak {} bytes, {} outstanding",
+            stats.allocated_bytes,
+            stats.requested_bytes,
+            stats.peak_allocated_bytes,
+            stats.outstanding.len(),
+        ).unwrap();
+        for (offset, allocation) in &stats.outstanding {
+            writeln!(
+                out,
+                "  {:#x}: {} of {} bytes, '{}'",
+                offset,
+                allocation.requested_size,
+                self.heap_size >> allocation.level,
+                allocation.tag,
+            ).unwrap();
         }
+        out
     }
 
     pub fn get_block_levels(&self) -> BlockLevel {
@@ -53,6 +140,11 @@ impl Allocator {
     }
 
     pub unsafe fn allocate(&mut self, requested_size: usize) -> (*mut u8, BlockLevel, FreeTreeIndex) {
+        self.allocate_tagged(requested_size, "")
+    }
+
+    /// `tag` shows up in `dump` while the allocation is outstanding
+    pub unsafe fn allocate_tagged(&mut self, requested_size: usize, tag: &str) -> (*mut u8, BlockLevel, FreeTreeIndex) {
         let mut level = 0;
         while (self.heap_size >> (level as usize + 1)) >= requested_size && level + 1 < self.get_block_levels() {
             level += 1;
@@ -66,7 +158,7 @@ impl Allocator {
         if self.free_list_heads[level as usize].is_null() {
             return (null_mut(), BlockLevel::MAX, FreeTreeIndex::MAX);
         }
-        assert!((*self.free_list_heads[level as usize]).previous == null_mut());
+        assert!((*self.free_list_heads[level as usize]).previous.is_null());
 
         let allocated_node = self.free_list_heads[level as usize];
 
@@ -93,10 +185,101 @@ impl Allocator {
             utils::set_bit_false(&mut self.free_tree, left_free_tree_index as usize);
         }
 
+        if let Some(stats) = &mut self.stats {
+            stats.allocated_bytes += self.heap_size >> best_level;
+            stats.requested_bytes += requested_size;
+            stats.peak_allocated_bytes = stats.peak_allocated_bytes.max(stats.allocated_bytes);
+            stats.outstanding.insert(
+                allocated_node as usize - self.heap_start as usize,
+                Outstanding { level: best_level, requested_size, tag: tag.to_owned() },
+            );
+        }
+
         (allocated_node as *mut u8, best_level, left_free_tree_index)
     }
 
+    /// level and free tree index a block has after `grow` put a new root above it
+    pub fn grown_block(level: BlockLevel, free_tree_index: FreeTreeIndex) -> (BlockLevel, FreeTreeIndex) {
+        (level + 1, free_tree_index + (1 << level))
+    }
+
+    /// doubles the heap by putting a new root level above the current one, keeping the smallest block size.
+    /// The caller must have copied the current heap to the start of `new_heap_start`,
+    /// which must hold twice as many bytes. Blocks keep their offsets, while their levels and
+    /// free tree indices are remapped by `grown_block`
+    pub unsafe fn grow(&mut self, new_heap_start: *mut u8) {
+        let was_empty = self.is_empty();
+        let old_heap_start = self.heap_start;
+        let rebase = |node: *mut FreeListNode| if node.is_null() {
+            node
+        } else {
+            new_heap_start.add(node as usize - old_heap_start as usize) as *mut FreeListNode
+        };
+
+        // the free list nodes live in the copied heap
+        for (level, head) in self.free_list_heads.iter_mut().enumerate() {
+            *head = rebase(*head);
+            let mut node = *head;
+            while !node.is_null() {
+                (*node).next = rebase((*node).next);
+                (*node).previous = rebase((*node).previous);
+                (*node).free_tree_index = Self::grown_block(level as BlockLevel, (*node).free_tree_index).1;
+                node = (*node).next;
+            }
+        }
+
+        let block_levels = self.get_block_levels() + 1;
+        let mut free_tree = utils::new_bitmask_vec((1 << block_levels) - 1, true);
+        for index in 0..(1 << (block_levels - 1)) - 1 {
+            if !utils::get_bit(&self.free_tree, index) {
+                let level = (index + 1).ilog2() as BlockLevel;
+                let grown_index = Self::grown_block(level, index as FreeTreeIndex).1;
+                utils::set_bit_false(&mut free_tree, grown_index as usize);
+            }
+        }
+
+        self.free_list_heads.insert(0, null_mut());
+        if was_empty {
+            // the old root and the new half coalesce into the new root
+            self.free_list_heads[1] = null_mut();
+            let root = new_heap_start as *mut FreeListNode;
+            *root = FreeListNode { next: null_mut(), previous: null_mut(), free_tree_index: 0 };
+            self.free_list_heads[0] = root;
+        } else {
+            utils::set_bit_false(&mut free_tree, 0);
+            let new_half = new_heap_start.add(self.heap_size) as *mut FreeListNode;
+            *new_half = FreeListNode {
+                next: self.free_list_heads[1],
+                previous: null_mut(),
+                free_tree_index: 2,
+            };
+            if !self.free_list_heads[1].is_null() {
+                (*self.free_list_heads[1]).previous = new_half;
+            }
+            self.free_list_heads[1] = new_half;
+        }
+
+        if let Some(stats) = &mut self.stats {
+            for allocation in stats.outstanding.values_mut() {
+                allocation.level += 1;
+            }
+        }
+
+        self.free_tree = free_tree;
+        self.heap_start = new_heap_start;
+        self.heap_size *= 2;
+    }
+
     pub unsafe fn deallocate(&mut self, ptr: *mut u8, level: BlockLevel, free_tree_index: FreeTreeIndex) {
+        if let Some(stats) = &mut self.stats {
+            let allocation = stats.outstanding
+                .remove(&(ptr as usize - self.heap_start as usize))
+                .expect("deallocating a block that is not allocated");
+            assert!(allocation.level == level);
+            stats.allocated_bytes -= self.heap_size >> level;
+            stats.requested_bytes -= allocation.requested_size;
+        }
+
         utils::set_bit_true(&mut self.free_tree, free_tree_index as usize);
 
         let mut free_tree_index = free_tree_index;
@@ -111,8 +294,9 @@ impl Allocator {
                 free_tree_index = (free_tree_index - 1) >> 1;
                 utils::set_bit_true(&mut self.free_tree, free_tree_index as usize);
                 let block_size = self.heap_size >> level;
-                // get parent node
-                node = utils::align_down(node as usize, block_size << 1) as *mut FreeListNode;
+                // get parent node, relative to the heap as mapped or grown heaps needn't start aligned to their size
+                let heap_offset = node as usize - self.heap_start as usize;
+                node = self.heap_start.add(utils::align_down(heap_offset, block_size << 1)) as *mut FreeListNode;
 
                 // calculating buddy node based off of parent node
                 let buddy_node = (node as usize + is_left_as_usize * block_size as usize) as *mut FreeListNode;
@@ -202,7 +386,7 @@ fn test_failed_allocation() {
             allocator.allocate(heap_size)
         };
 
-        assert!(big1 == null_mut());
+        assert!(big1.is_null());
 
         unsafe {
             allocator.deallocate(big0, b0, fb0);
@@ -218,10 +402,103 @@ fn test_failed_allocation() {
         let (a, _, _) = unsafe { 
             allocator.allocate(heap_size / 8)
         };
-        assert!(a == null_mut());
+        assert!(a.is_null());
     }
     
     unsafe {
         alloc::alloc::dealloc(allocator.heap_start, heap_layout);
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_stats() {
+    let heap_size = 0x4000;
+    let heap_layout = unsafe { core::alloc::Layout::from_size_align_unchecked(heap_size, heap_size) };
+    let heap_start = unsafe { alloc::alloc::alloc(heap_layout) };
+
+    let mut allocator = unsafe {
+        Allocator::new(heap_start, heap_size, 4)
+    };
+    allocator.enable_stats();
+    assert!(allocator.free_block_counts() == [1, 0, 0, 0]);
+
+    let block_size = allocator.get_block_size() as usize;
+    let (small, s, fs) = unsafe { allocator.allocate_tagged(block_size - 1, "small") };
+    let (big, b, fb) = unsafe { allocator.allocate_tagged(heap_size / 2, "big") };
+    // splitting for the small block left one free block on every other level
+    assert!(allocator.free_block_counts() == [0, 0, 1, 1]);
+
+    let stats = allocator.stats().unwrap();
+    assert!(stats.allocated_bytes == block_size + heap_size / 2);
+    assert!(stats.requested_bytes == block_size - 1 + heap_size / 2);
+    assert!(stats.outstanding.len() == 2);
+    assert!(allocator.dump().contains("'small'") && allocator.dump().contains("'big'"));
+
+    unsafe { allocator.deallocate(big, b, fb) };
+    let stats = allocator.stats().unwrap();
+    assert!(stats.allocated_bytes == block_size);
+    assert!(stats.peak_allocated_bytes == block_size + heap_size / 2);
+    assert!(!allocator.dump().contains("'big'"));
+    assert!(!allocator.is_empty());
+
+    unsafe { allocator.deallocate(small, s, fs) };
+    assert!(allocator.is_empty());
+    assert!(allocator.stats().unwrap().outstanding.is_empty());
+
+    unsafe {
+        alloc::alloc::dealloc(allocator.heap_start, heap_layout);
+    }
+}
+
+#[test]
+fn test_grow() {
+    let heap_size = 0x4000;
+    let heap_layout = unsafe { core::alloc::Layout::from_size_align_unchecked(heap_size, heap_size) };
+    let grown_layout = unsafe { core::alloc::Layout::from_size_align_unchecked(2 * heap_size, heap_size) };
+    let heap_start = unsafe { alloc::alloc::alloc(heap_layout) };
+    let grown_heap_start = unsafe { alloc::alloc::alloc(grown_layout) };
+
+    let mut allocator = unsafe {
+        Allocator::new(heap_start, heap_size, 4)
+    };
+    allocator.enable_stats();
+    let block_size = allocator.get_block_size() as usize;
+    let (small, s, fs) = unsafe { allocator.allocate(block_size) };
+    let (half, h, fh) = unsafe { allocator.allocate(heap_size / 2) };
+    unsafe { *small = 7 };
+    // the heap is too fragmented for another half
+    assert!(unsafe { allocator.allocate(heap_size / 2) }.0.is_null());
+
+    unsafe {
+        grown_heap_start.copy_from_nonoverlapping(heap_start, heap_size);
+        allocator.grow(grown_heap_start);
+    }
+    assert!(allocator.heap_size == 2 * heap_size && allocator.get_block_size() as usize == block_size);
+    assert!(allocator.free_block_counts() == [0, 1, 0, 1, 1]);
+
+    // blocks keep their offsets and data
+    let small = unsafe { grown_heap_start.add(small as usize - heap_start as usize) };
+    let half = unsafe { grown_heap_start.add(half as usize - heap_start as usize) };
+    assert!(unsafe { *small } == 7);
+    let (new_half, nh, fnh) = unsafe { allocator.allocate(heap_size) };
+    assert!(new_half == unsafe { grown_heap_start.add(heap_size) });
+
+    let (s, fs) = Allocator::grown_block(s, fs);
+    let (h, fh) = Allocator::grown_block(h, fh);
+    unsafe {
+        allocator.deallocate(half, h, fh);
+        allocator.deallocate(new_half, nh, fnh);
+        allocator.deallocate(small, s, fs);
+    }
+    assert!(allocator.is_empty() && allocator.stats().unwrap().outstanding.is_empty());
+
+    // an empty heap grows into one free root
+    let (whole, w, fw) = unsafe { allocator.allocate(2 * heap_size) };
+    assert!(whole == grown_heap_start);
+    unsafe { allocator.deallocate(whole, w, fw) };
+
+    unsafe {
+        alloc::alloc::dealloc(heap_start, heap_layout);
+        alloc::alloc::dealloc(grown_heap_start, grown_layout);
+    }
+}
@@ -0,0 +1,269 @@
+// Validation around the buddy allocator for hunting memory bugs. Allocations get canary bytes on
+// both sides, checked when they're freed, and freed blocks are poisoned, checked before they're
+// handed out again. Overflows, writes after free and double frees panic with the tag and,
+// in debug builds with RUST_BACKTRACE set, the backtrace of the allocation involved:
+//
+//     let mut allocator = DebugAllocator::new(unsafe { Allocator::new(heap_start, heap_size, 8) });
+//     let (ptr, level, free_tree_index) = unsafe { allocator.allocate_tagged(64, "particles") };
+//     unsafe { allocator.deallocate(ptr, level, free_tree_index) };
+//     // checks every canary and every poisoned block at once
+//     unsafe { allocator.validate() };
+//
+// Allocations cost `2 * CANARY_SIZE` more bytes and are only aligned to `CANARY_SIZE`.
+// The allocator keeps its free lists at the start of free blocks, those bytes aren't poisoned
+
+use core::mem::size_of;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::utils;
+use super::{Allocator, BlockLevel, FreeListNode, FreeTreeIndex};
+
+/// before and at least after every allocation, keeps allocations aligned to it
+pub const CANARY_SIZE: usize = 16;
+pub const CANARY_BYTE: u8 = 0xca;
+/// what freed blocks are filled with
+pub const POISON_BYTE: u8 = 0xdd;
+
+#[derive(Debug)]
+struct DebugAllocation {
+    level: BlockLevel,
+    free_tree_index: FreeTreeIndex,
+    requested_size: usize,
+    tag: String,
+    #[cfg(debug_assertions)]
+    backtrace: std::backtrace::Backtrace,
+}
+
+impl DebugAllocation {
+    fn describe(&self) -> String {
+        #[cfg(debug_assertions)]
+        if self.backtrace.status() == std::backtrace::BacktraceStatus::Captured {
+            return format!("'{}' of {} bytes, allocated at\n{}", self.tag, self.requested_size, self.backtrace);
+        }
+        format!("'{}' of {} bytes", self.tag, self.requested_size)
+    }
+}
+
+/// Drop in for `Allocator` that validates its use, panicking on the first corruption found
+pub struct DebugAllocator {
+    pub inner: Allocator,
+    /// keyed by the block's offset from `heap_start`
+    allocations: BTreeMap<usize, DebugAllocation>,
+    /// the last allocation freed at each offset, until something is allocated over it
+    freed: BTreeMap<usize, DebugAllocation>,
+    /// a bit per smallest block, set while it's poisoned
+    poisoned: Vec<usize>,
+}
+
+impl DebugAllocator {
+    pub fn new(inner: Allocator) -> Self {
+        assert!(inner.is_empty(), "the allocator must be validated from its first allocation");
+        let block_count = inner.heap_size / inner.get_block_size() as usize;
+        Self {
+            inner,
+            allocations: BTreeMap::new(),
+            freed: BTreeMap::new(),
+            poisoned: utils::new_bitmask_vec(block_count, false),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allocations.is_empty()
+    }
+
+    pub unsafe fn allocate(&mut self, requested_size: usize) -> (*mut u8, BlockLevel, FreeTreeIndex) {
+        self.allocate_tagged(requested_size, "")
+    }
+
+    /// like `Allocator::allocate_tagged`, null when the canaries don't fit either
+    pub unsafe fn allocate_tagged(&mut self, requested_size: usize, tag: &str) -> (*mut u8, BlockLevel, FreeTreeIndex) {
+        let (block, level, free_tree_index) = self.inner.allocate_tagged(requested_size + 2 * CANARY_SIZE, tag);
+        if block.is_null() {
+            return (block, level, free_tree_index);
+        }
+
+        let offset = block as usize - self.inner.heap_start as usize;
+        let block_size = self.inner.heap_size >> level;
+        self.check_poison(offset..offset + block_size);
+        for smallest in self.smallest_blocks(offset..offset + block_size) {
+            utils::set_bit_false(&mut self.poisoned, smallest);
+        }
+        let overwritten: Vec<usize> = self.freed.range(offset..offset + block_size).map(|(&offset, _)| offset).collect();
+        for offset in overwritten {
+            self.freed.remove(&offset);
+        }
+
+        // the canary after the allocation runs to the end of the block
+        block.write_bytes(CANARY_BYTE, CANARY_SIZE);
+        block.add(CANARY_SIZE + requested_size).write_bytes(CANARY_BYTE, block_size - CANARY_SIZE - requested_size);
+
+        self.allocations.insert(offset, DebugAllocation {
+            level,
+            free_tree_index,
+            requested_size,
+            tag: tag.to_owned(),
+            #[cfg(debug_assertions)]
+            backtrace: std::backtrace::Backtrace::capture(),
+        });
+        (block.add(CANARY_SIZE), level, free_tree_index)
+    }
+
+    /// like `Allocator::deallocate`, panics on pointers that aren't allocated
+    /// and allocations whose canaries were overwritten
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8, level: BlockLevel, free_tree_index: FreeTreeIndex) {
+        let heap_start = self.inner.heap_start as usize;
+        assert!(
+            ptr as usize >= heap_start + CANARY_SIZE && (ptr as usize) < heap_start + self.inner.heap_size,
+            "Deallocating {:p}, which is outside of the heap", ptr,
+        );
+        let offset = ptr as usize - CANARY_SIZE - heap_start;
+        let Some(allocation) = self.allocations.remove(&offset) else {
+            match self.freed.get(&offset) {
+                Some(freed) => panic!("Double free of {:#x}, {}", offset, freed.describe()),
+                None => panic!("Deallocating {:#x}, which was never allocated", offset),
+            }
+        };
+        assert!(
+            allocation.level == level && allocation.free_tree_index == free_tree_index,
+            "Deallocating {:#x} with the wrong block, {}", offset, allocation.describe(),
+        );
+        self.check_canaries(offset, &allocation);
+
+        self.inner.deallocate(ptr.sub(CANARY_SIZE), level, free_tree_index);
+
+        // after the allocator wrote its free list node
+        let block_size = self.inner.heap_size >> level;
+        for smallest in self.smallest_blocks(offset..offset + block_size) {
+            let (start, end) = self.poisoned_range(smallest);
+            self.inner.heap_start.add(start).write_bytes(POISON_BYTE, end - start);
+            utils::set_bit_true(&mut self.poisoned, smallest);
+        }
+        self.freed.insert(offset, allocation);
+    }
+
+    /// doubles the heap like `Allocator::grow`, remapping the blocks of outstanding allocations
+    pub unsafe fn grow(&mut self, new_heap_start: *mut u8) {
+        self.inner.grow(new_heap_start);
+        for allocation in self.allocations.values_mut().chain(self.freed.values_mut()) {
+            (allocation.level, allocation.free_tree_index) = Allocator::grown_block(allocation.level, allocation.free_tree_index);
+        }
+        // the new half is free but not poisoned
+        let mut poisoned = utils::new_bitmask_vec(self.inner.heap_size / self.inner.get_block_size() as usize, false);
+        poisoned[..self.poisoned.len()].copy_from_slice(&self.poisoned);
+        self.poisoned = poisoned;
+    }
+
+    /// checks the canaries of every allocation and the poison of every freed block
+    pub unsafe fn validate(&self) {
+        for (&offset, allocation) in &self.allocations {
+            self.check_canaries(offset, allocation);
+        }
+        self.check_poison(0..self.inner.heap_size);
+    }
+
+    /// outstanding allocations with their tags, and backtraces when captured
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "{} outstanding allocations", self.allocations.len()).unwrap();
+        for (offset, allocation) in &self.allocations {
+            writeln!(out, "  {:#x}: {}", offset, allocation.describe()).unwrap();
+        }
+        out
+    }
+
+    unsafe fn check_canaries(&self, offset: usize, allocation: &DebugAllocation) {
+        let block = self.inner.heap_start.add(offset);
+        let block_size = self.inner.heap_size >> allocation.level;
+        let end = CANARY_SIZE + allocation.requested_size;
+        if let Some(index) = (0..CANARY_SIZE).find(|&index| *block.add(index) != CANARY_BYTE) {
+            panic!("Underflow {} bytes before {:#x}, {}", CANARY_SIZE - index, offset + CANARY_SIZE, allocation.describe());
+        }
+        if let Some(index) = (end..block_size).find(|&index| *block.add(index) != CANARY_BYTE) {
+            panic!("Overflow {} bytes past {:#x}, {}", index - end + 1, offset + CANARY_SIZE, allocation.describe());
+        }
+    }
+
+    /// panics on the first poisoned byte in `range` that was written to
+    unsafe fn check_poison(&self, range: std::ops::Range<usize>) {
+        for smallest in self.smallest_blocks(range) {
+            if !utils::get_bit(&self.poisoned, smallest) {
+                continue;
+            }
+            let (start, end) = self.poisoned_range(smallest);
+            let Some(written) = (start..end).find(|&index| *self.inner.heap_start.add(index) != POISON_BYTE) else {
+                continue;
+            };
+            match self.freed.range(..=written).next_back() {
+                Some((offset, freed)) => panic!("Write to {:#x} after {:#x} was freed, {}", written, offset, freed.describe()),
+                None => panic!("Write to {:#x} after it was freed", written),
+            }
+        }
+    }
+
+    fn smallest_blocks(&self, range: std::ops::Range<usize>) -> std::ops::Range<usize> {
+        let block_size = self.inner.get_block_size() as usize;
+        range.start / block_size..range.end / block_size
+    }
+
+    /// of a smallest block, past where the allocator may put a free list node
+    fn poisoned_range(&self, smallest: usize) -> (usize, usize) {
+        let block_size = self.inner.get_block_size() as usize;
+        (smallest * block_size + size_of::<FreeListNode>(), (smallest + 1) * block_size)
+    }
+}
+
+#[test]
+fn test_debug_allocator() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    let heap_size = 0x4000;
+    let heap_layout = unsafe { core::alloc::Layout::from_size_align_unchecked(heap_size, heap_size) };
+    let heap_start = unsafe { std::alloc::alloc(heap_layout) };
+    let mut allocator = DebugAllocator::new(unsafe { Allocator::new(heap_start, heap_size, 4) });
+
+    // writing every requested byte is fine
+    let (ptr, level, free_tree_index) = unsafe { allocator.allocate_tagged(100, "in bounds") };
+    assert!((ptr as usize).is_multiple_of(CANARY_SIZE));
+    unsafe {
+        ptr.write_bytes(1, 100);
+        allocator.validate();
+        allocator.deallocate(ptr, level, free_tree_index);
+        allocator.validate();
+    }
+    assert!(allocator.is_empty());
+
+    let mut expect_panic = |message: &str, misuse: &dyn Fn(&mut DebugAllocator)| {
+        let panic = catch_unwind(AssertUnwindSafe(|| misuse(&mut allocator))).unwrap_err();
+        let panic = panic.downcast_ref::<String>().unwrap();
+        assert!(panic.contains(message), "{}", panic);
+    };
+    expect_panic("Overflow 1 bytes", &|allocator| unsafe {
+        let (ptr, level, free_tree_index) = allocator.allocate_tagged(100, "overflowing");
+        *ptr.add(100) = 0;
+        allocator.deallocate(ptr, level, free_tree_index);
+    });
+    expect_panic("'underflowing'", &|allocator| unsafe {
+        let (ptr, level, free_tree_index) = allocator.allocate_tagged(100, "underflowing");
+        *ptr.sub(1) = 0;
+        allocator.deallocate(ptr, level, free_tree_index);
+    });
+    expect_panic("Double free", &|allocator| unsafe {
+        let (ptr, level, free_tree_index) = allocator.allocate_tagged(32, "freed twice");
+        allocator.deallocate(ptr, level, free_tree_index);
+        allocator.deallocate(ptr, level, free_tree_index);
+    });
+    expect_panic("after", &|allocator| unsafe {
+        let (ptr, level, free_tree_index) = allocator.allocate_tagged(0x1000, "used after free");
+        allocator.deallocate(ptr, level, free_tree_index);
+        *ptr.add(0x900) = 0;
+        allocator.validate();
+    });
+    expect_panic("never allocated", &|allocator| unsafe {
+        allocator.deallocate(heap_start.add(0x3000 + CANARY_SIZE), 2, 10);
+    });
+
+    unsafe {
+        std::alloc::dealloc(heap_start, heap_layout);
+    }
+}
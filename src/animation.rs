@@ -0,0 +1,297 @@
+// Keyframed transform animation for scene objects, separate from skinning.
+// A clip holds translation, rotation and scale tracks, a player steps through a clip
+//
+//     animation: Some((
+//         looping: true,
+//         rotation: (interpolation: Linear, keyframes: [(time: 0.0, value: (1.0, 0.0, 0.0, 0.0)), ...]),
+//     )),
+
+use serde::{Deserialize, Serialize};
+
+use crate::{math::Rotor, scene::Transform};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum Interpolation {
+    /// holds each keyframe's value until the next one
+    Step,
+    #[default]
+    Linear,
+    /// catmull-rom through the keyframes, smooth at each keyframe
+    Cubic,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Keyframe<T> {
+    /// seconds from the start of the clip
+    pub time: f32,
+    pub value: T,
+}
+
+/// keyframes must be sorted by time, an empty track leaves its property alone
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Track<T> {
+    pub interpolation: Interpolation,
+    pub keyframes: Vec<Keyframe<T>>,
+}
+
+/// values a track can interpolate, as components
+pub trait Animatable: Copy {
+    const COUNT: usize;
+    fn to_array(self) -> [f32; 4];
+    fn from_array(array: [f32; 4]) -> Self;
+}
+
+impl Animatable for (f32, f32, f32) {
+    const COUNT: usize = 3;
+
+    fn to_array(self) -> [f32; 4] {
+        [self.0, self.1, self.2, 0.0]
+    }
+
+    fn from_array(array: [f32; 4]) -> Self {
+        (array[0], array[1], array[2])
+    }
+}
+
+/// rotor components, like `Transform::rotation`
+impl Animatable for (f32, f32, f32, f32) {
+    const COUNT: usize = 4;
+
+    fn to_array(self) -> [f32; 4] {
+        [self.0, self.1, self.2, self.3]
+    }
+
+    fn from_array(array: [f32; 4]) -> Self {
+        (array[0], array[1], array[2], array[3])
+    }
+}
+
+fn catmull_rom(p0: f32, p1: f32, p2: f32, p3: f32, t: f32) -> f32 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    0.5 * (2.0 * p1 + (p2 - p0) * t + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2 + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3)
+}
+
+impl<T: Animatable> Track<T> {
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |keyframe| keyframe.time)
+    }
+
+    /// clamped to the first and last keyframes, `None` for an empty track
+    fn sample_array(&self, time: f32, align: fn(&[f32; 4], [f32; 4]) -> [f32; 4]) -> Option<[f32; 4]> {
+        let keyframes = &self.keyframes;
+        let first = keyframes.first()?;
+        let last = keyframes.last().unwrap();
+        if time <= first.time {
+            return Some(first.value.to_array());
+        }
+        if time >= last.time {
+            return Some(last.value.to_array());
+        }
+
+        // first keyframe after `time`, there is one before it too
+        let next = keyframes.partition_point(|keyframe| keyframe.time <= time);
+        let (a, b) = (&keyframes[next - 1], &keyframes[next]);
+        let t = (time - a.time) / (b.time - a.time);
+
+        let p1 = a.value.to_array();
+        let p2 = align(&p1, b.value.to_array());
+        let mut value = [0.0; 4];
+        match self.interpolation {
+            Interpolation::Step => return Some(p1),
+            Interpolation::Linear => {
+                for i in 0..T::COUNT {
+                    value[i] = p1[i] + (p2[i] - p1[i]) * t;
+                }
+            }
+            Interpolation::Cubic => {
+                // the ends repeat to keep the curve inside the clip's keyframes
+                let p0 = align(&p1, keyframes[next.saturating_sub(2)].value.to_array());
+                let p3 = align(&p2, keyframes[(next + 1).min(keyframes.len() - 1)].value.to_array());
+                for i in 0..T::COUNT {
+                    value[i] = catmull_rom(p0[i], p1[i], p2[i], p3[i], t);
+                }
+            }
+        }
+        Some(value)
+    }
+}
+
+fn no_align(_: &[f32; 4], value: [f32; 4]) -> [f32; 4] {
+    value
+}
+
+/// r and -r rotate the same, interpolating towards the closer one takes the short way round
+fn align_rotor(reference: &[f32; 4], value: [f32; 4]) -> [f32; 4] {
+    let dot: f32 = reference.iter().zip(&value).map(|(a, b)| a * b).sum();
+    if dot < 0.0 { value.map(|component| -component) } else { value }
+}
+
+impl Track<(f32, f32, f32)> {
+    pub fn sample(&self, time: f32) -> Option<(f32, f32, f32)> {
+        self.sample_array(time, no_align).map(<(f32, f32, f32)>::from_array)
+    }
+}
+
+impl Track<(f32, f32, f32, f32)> {
+    /// normalized, interpolating components is only exact at the keyframes
+    pub fn sample(&self, time: f32) -> Option<(f32, f32, f32, f32)> {
+        let [scalar, yx, zy, xz] = self.sample_array(time, align_rotor)?;
+        let mut rotor = Rotor::new(scalar, yx, zy, xz);
+        rotor /= rotor.norm_sqr().sqrt();
+        Some(rotor.components())
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Clip {
+    /// starts over after the last keyframe, otherwise holds it
+    pub looping: bool,
+    pub translation: Track<(f32, f32, f32)>,
+    pub rotation: Track<(f32, f32, f32, f32)>,
+    pub scale: Track<(f32, f32, f32)>,
+}
+
+impl Clip {
+    /// the last keyframe of any track
+    pub fn duration(&self) -> f32 {
+        self.translation.duration().max(self.rotation.duration()).max(self.scale.duration())
+    }
+
+    /// `base` with the animated properties replaced
+    pub fn sample(&self, time: f32, base: &Transform) -> Transform {
+        Transform {
+            translation: self.translation.sample(time).unwrap_or(base.translation),
+            rotation: self.rotation.sample(time).unwrap_or(base.rotation),
+            scale: self.scale.sample(time).unwrap_or(base.scale),
+        }
+    }
+}
+
+/// Plays a clip, time only advances while playing
+#[derive(Clone, Debug)]
+pub struct AnimationPlayer {
+    pub clip: Clip,
+    /// 1 is real time, negative plays backwards
+    pub speed: f32,
+    time: f32,
+    playing: bool,
+}
+
+impl AnimationPlayer {
+    /// starts playing from the beginning
+    pub fn new(clip: Clip) -> Self {
+        Self {
+            clip,
+            speed: 1.0,
+            time: 0.0,
+            playing: true,
+        }
+    }
+
+    pub fn play(&mut self) {
+        self.playing = true;
+    }
+
+    pub fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn get_time(&self) -> f32 {
+        self.time
+    }
+
+    /// wrapped into the clip when looping, clamped otherwise
+    pub fn seek(&mut self, time: f32) {
+        let duration = self.clip.duration();
+        self.time = if duration <= 0.0 {
+            0.0
+        } else if self.clip.looping {
+            time.rem_euclid(duration)
+        } else {
+            time.clamp(0.0, duration)
+        };
+    }
+
+    /// a clip that doesn't loop stops at its end
+    pub fn update(&mut self, dt: f32) {
+        if !self.playing {
+            return;
+        }
+        self.seek(self.time + dt * self.speed);
+        let at_end = if self.speed >= 0.0 { self.time >= self.clip.duration() } else { self.time <= 0.0 };
+        if !self.clip.looping && at_end {
+            self.playing = false;
+        }
+    }
+
+    pub fn sample(&self, base: &Transform) -> Transform {
+        self.clip.sample(self.time, base)
+    }
+}
+
+#[test]
+fn test_animation() {
+    fn keyframe<T>(time: f32, value: T) -> Keyframe<T> {
+        Keyframe { time, value }
+    }
+    fn track<T>(interpolation: Interpolation, keyframes: Vec<Keyframe<T>>) -> Track<T> {
+        Track { interpolation, keyframes }
+    }
+    let quarter_turn = crate::math::Bivector::new(0.0, 0.0, std::f32::consts::FRAC_PI_4).exp().components();
+
+    let clip = Clip {
+        looping: false,
+        translation: track(Interpolation::Linear, vec![
+            keyframe(0.0, (0.0, 0.0, 0.0)),
+            keyframe(2.0, (4.0, 0.0, 0.0)),
+        ]),
+        rotation: track(Interpolation::Linear, vec![
+            keyframe(0.0, (1.0, 0.0, 0.0, 0.0)),
+            keyframe(1.0, quarter_turn),
+        ]),
+        scale: Track::default(),
+    };
+    let base = Transform { scale: (2.0, 2.0, 2.0), ..Default::default() };
+
+    let mut player = AnimationPlayer::new(clip);
+    player.update(0.5);
+    let transform = player.sample(&base);
+    assert!((transform.translation.0 - 1.0).abs() < 1e-5 && transform.scale == (2.0, 2.0, 2.0));
+    let (scalar, _, _, xz) = transform.rotation;
+    let eighth_turn = crate::math::Bivector::new(0.0, 0.0, std::f32::consts::FRAC_PI_8).exp().components();
+    assert!((scalar - eighth_turn.0).abs() < 1e-2 && (xz - eighth_turn.3).abs() < 1e-2);
+    assert!((scalar * scalar + xz * xz - 1.0).abs() < 1e-5);
+
+    player.pause();
+    player.update(1.0);
+    assert!(player.get_time() == 0.5);
+    player.play();
+    player.update(5.0);
+    assert!(player.get_time() == 2.0 && !player.is_playing());
+    let (scalar, _, _, xz) = player.sample(&base).rotation;
+    assert!((scalar - quarter_turn.0).abs() < 1e-5 && (xz - quarter_turn.3).abs() < 1e-5);
+
+    player.clip.looping = true;
+    player.seek(4.5);
+    assert!(player.get_time() == 0.5);
+
+    // cubic curves pass through every keyframe and step holds values
+    let mut cubic = track(Interpolation::Cubic, vec![
+        keyframe(0.0, (0.0, 0.0, 0.0)),
+        keyframe(1.0, (1.0, 2.0, 0.0)),
+        keyframe(2.0, (0.0, 0.0, 3.0)),
+    ]);
+    assert!(cubic.sample(1.0) == Some((1.0, 2.0, 0.0)));
+    let (x, _, _) = cubic.sample(0.5).unwrap();
+    assert!(x > 0.0 && x < 1.0);
+    cubic.interpolation = Interpolation::Step;
+    assert!(cubic.sample(1.5) == Some((1.0, 2.0, 0.0)));
+    assert!(Track::<(f32, f32, f32)>::default().sample(1.0).is_none());
+}
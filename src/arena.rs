@@ -0,0 +1,126 @@
+// Bump allocation for data that only lives for a frame. Allocating moves an offset,
+// resetting at the start of the next frame frees everything at once
+
+use std::{alloc::Layout, cell::{Cell, RefCell}, mem::{align_of, size_of}};
+
+/// alignment of every chunk, types aligned beyond it are rejected
+const CHUNK_ALIGN: usize = 16;
+
+struct Chunk {
+    ptr: *mut u8,
+    layout: Layout,
+}
+
+impl Chunk {
+    fn new(size: usize) -> Self {
+        let layout = Layout::from_size_align(size.max(CHUNK_ALIGN), CHUNK_ALIGN).unwrap();
+        let ptr = unsafe { std::alloc::alloc(layout) };
+        if ptr.is_null() {
+            std::alloc::handle_alloc_error(layout);
+        }
+        Self { ptr, layout }
+    }
+}
+
+impl Drop for Chunk {
+    fn drop(&mut self) {
+        unsafe { std::alloc::dealloc(self.ptr, self.layout) };
+    }
+}
+
+/// Allocates through a shared reference so several slices can be alive at once,
+/// `reset` takes `&mut self` so none of them outlive it
+pub struct FrameArena {
+    /// the last one is bumped, earlier ones filled up during this frame
+    chunks: RefCell<Vec<Chunk>>,
+    /// into the last chunk
+    offset: Cell<usize>,
+    allocated_bytes: Cell<usize>,
+    peak_allocated_bytes: usize,
+}
+
+impl FrameArena {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            chunks: RefCell::new(vec![Chunk::new(capacity)]),
+            offset: Cell::new(0),
+            allocated_bytes: Cell::new(0),
+            peak_allocated_bytes: 0,
+        }
+    }
+
+    pub fn allocated_bytes(&self) -> usize {
+        self.allocated_bytes.get()
+    }
+
+    /// highest `allocated_bytes` of any frame before the last reset
+    pub fn peak_allocated_bytes(&self) -> usize {
+        self.peak_allocated_bytes
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.chunks.borrow().iter().map(|chunk| chunk.layout.size()).sum()
+    }
+
+    /// `count` default values, a full chunk is followed by one twice as large
+    // the slices never overlap, every allocation bumps past the previous one
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_slice<T: Copy + Default>(&self, count: usize) -> &mut [T] {
+        assert!(align_of::<T>() <= CHUNK_ALIGN);
+        let size = count * size_of::<T>();
+
+        let mut chunks = self.chunks.borrow_mut();
+        let mut offset = crate::utils::align_up(self.offset.get(), align_of::<T>());
+        if offset + size > chunks.last().unwrap().layout.size() {
+            let grown_size = (2 * chunks.last().unwrap().layout.size()).max(size);
+            chunks.push(Chunk::new(grown_size));
+            offset = 0;
+        }
+        let ptr = unsafe { chunks.last().unwrap().ptr.add(offset) as *mut T };
+        self.offset.set(offset + size);
+        self.allocated_bytes.set(self.allocated_bytes.get() + size);
+
+        unsafe {
+            for i in 0..count {
+                ptr.add(i).write(T::default());
+            }
+            std::slice::from_raw_parts_mut(ptr, count)
+        }
+    }
+
+    /// frees every allocation, chunks filled up this frame are merged into one large enough for all of them
+    pub fn reset(&mut self) {
+        let chunks = self.chunks.get_mut();
+        if chunks.len() > 1 {
+            let capacity = chunks.iter().map(|chunk| chunk.layout.size()).sum();
+            chunks.clear();
+            chunks.push(Chunk::new(capacity));
+        }
+        self.offset.set(0);
+        self.peak_allocated_bytes = self.peak_allocated_bytes.max(self.allocated_bytes.get());
+        self.allocated_bytes.set(0);
+    }
+}
+
+#[test]
+fn test_frame_arena() {
+    let mut arena = FrameArena::new(64);
+
+    let bytes = arena.alloc_slice::<u8>(3);
+    let floats = arena.alloc_slice::<f32>(4);
+    bytes.copy_from_slice(&[1, 2, 3]);
+    floats[3] = 1.5;
+    assert!(floats.as_ptr().align_offset(align_of::<f32>()) == 0);
+    assert!(bytes == [1, 2, 3] && floats == [0.0, 0.0, 0.0, 1.5]);
+
+    // spills into a new chunk, earlier slices stay valid
+    let large = arena.alloc_slice::<u64>(20);
+    large[19] = 7;
+    assert!(bytes == [1, 2, 3] && floats[3] == 1.5 && large[19] == 7);
+    assert!(arena.allocated_bytes() == 3 + 16 + 160);
+
+    arena.reset();
+    assert!(arena.allocated_bytes() == 0 && arena.peak_allocated_bytes() == 3 + 16 + 160);
+    assert!(arena.capacity() >= 3 + 16 + 160 && arena.chunks.borrow().len() == 1);
+    assert!(arena.alloc_slice::<u64>(20).iter().all(|&x| x == 0));
+}
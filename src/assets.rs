@@ -0,0 +1,238 @@
+// Assets loaded from files, shared by path and reference counted.
+// Released assets are kept until the frames in flight that may use them have finished,
+// changed files can be reloaded in place so handles keep working
+
+use std::{collections::HashMap, time::SystemTime};
+
+use crate::{
+    data_structures::{handle_map::{Handle, HandleMap}, interner::{StrId, StringInterner}},
+    renderer::deletion_queue::DeletionQueue,
+};
+
+pub struct Asset<T> {
+    path: StrId,
+    value: T,
+    ref_count: u32,
+    /// of the file when it was loaded
+    modified: Option<SystemTime>,
+}
+
+pub type AssetHandle<T> = Handle<Asset<T>>;
+
+/// Loading and destroying is left to the caller, the cache only decides when
+pub struct AssetCache<T> {
+    assets: HandleMap<Asset<T>>,
+    /// every path ever acquired, kept after their assets are released
+    paths: StringInterner,
+    by_path: HashMap<StrId, AssetHandle<T>>,
+    /// released or replaced values
+    retired: DeletionQueue<T>,
+    /// lets `reload_changed` poll the files' modification times
+    pub hot_reload: bool,
+}
+
+impl<T> Default for AssetCache<T> {
+    fn default() -> Self {
+        Self {
+            assets: HandleMap::default(),
+            paths: StringInterner::default(),
+            by_path: HashMap::new(),
+            retired: DeletionQueue::default(),
+            hot_reload: false,
+        }
+    }
+}
+
+fn read_modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+impl<T> AssetCache<T> {
+    /// live assets
+    pub fn len(&self) -> usize {
+        self.assets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.assets.is_empty()
+    }
+
+    /// the cached asset with one more reference, otherwise loads it,
+    /// `None` when the file doesn't exist or `load` fails
+    pub fn acquire<F: FnOnce(&str) -> Option<T>>(&mut self, path: &str, load: F) -> Option<AssetHandle<T>> {
+        if let Some(&handle) = self.paths.get(path).and_then(|path| self.by_path.get(&path)) {
+            self.assets.get_mut(handle).unwrap().ref_count += 1;
+            return Some(handle);
+        }
+
+        let modified = read_modified(path);
+        if modified.is_none() {
+            log::warn!("Missing asset {}", path);
+            return None;
+        }
+        let value = load(path)?;
+        let path = self.paths.intern(path);
+        let handle = self.assets.insert(Asset {
+            path,
+            value,
+            ref_count: 1,
+            modified,
+        });
+        self.by_path.insert(path, handle);
+        Some(handle)
+    }
+
+    /// an asset generated at runtime rather than loaded from a file, `name` takes the place of its path.
+    /// Acquiring the name shares it like any other asset, it's never reloaded
+    pub fn insert(&mut self, name: &str, value: T) -> AssetHandle<T> {
+        assert!(!self.is_cached(name), "Asset {} already exists", name);
+        let path = self.paths.intern(name);
+        let handle = self.assets.insert(Asset {
+            path,
+            value,
+            ref_count: 1,
+            modified: None,
+        });
+        self.by_path.insert(path, handle);
+        handle
+    }
+
+    /// whether `acquire` would share an already loaded asset
+    pub fn is_cached(&self, path: &str) -> bool {
+        self.paths.get(path).is_some_and(|path| self.by_path.contains_key(&path))
+    }
+
+    /// another reference to an asset already held
+    pub fn retain(&mut self, handle: AssetHandle<T>) {
+        self.assets.get_mut(handle).expect("Retaining a stale asset handle").ref_count += 1;
+    }
+
+    /// drops a reference, the last one retires the asset
+    pub fn release(&mut self, handle: AssetHandle<T>) {
+        let asset = self.assets.get_mut(handle).expect("Releasing a stale asset handle");
+        asset.ref_count -= 1;
+        if asset.ref_count == 0 {
+            let asset = self.assets.remove(handle).unwrap();
+            self.by_path.remove(&asset.path);
+            self.retired.push(asset.value);
+        }
+    }
+
+    /// `None` when the handle is stale
+    pub fn get(&self, handle: AssetHandle<T>) -> Option<&T> {
+        self.assets.get(handle).map(|asset| &asset.value)
+    }
+
+    /// `None` when the handle is stale
+    pub fn get_mut(&mut self, handle: AssetHandle<T>) -> Option<&mut T> {
+        self.assets.get_mut(handle).map(|asset| &mut asset.value)
+    }
+
+    pub fn get_path(&self, handle: AssetHandle<T>) -> Option<&str> {
+        self.get_path_id(handle).map(|path| self.paths.resolve(path))
+    }
+
+    /// the interned path, compares without comparing the strings
+    pub fn get_path_id(&self, handle: AssetHandle<T>) -> Option<StrId> {
+        self.assets.get(handle).map(|asset| asset.path)
+    }
+
+    pub fn ref_count(&self, handle: AssetHandle<T>) -> u32 {
+        self.assets.get(handle).map_or(0, |asset| asset.ref_count)
+    }
+
+    /// reloads assets whose files changed in place, `load` also gets the current value,
+    /// the old values are retired, returns the reloaded handles, does nothing unless `hot_reload` is set
+    pub fn reload_changed<F: FnMut(&str, &T) -> Option<T>>(&mut self, mut load: F) -> Vec<AssetHandle<T>> {
+        if !self.hot_reload {
+            return vec![];
+        }
+
+        let mut reloaded = vec![];
+        for &handle in self.by_path.values() {
+            let asset = self.assets.get_mut(handle).unwrap();
+            let path = self.paths.resolve(asset.path);
+            let modified = read_modified(path);
+            if modified.is_none() || modified == asset.modified {
+                continue;
+            }
+            asset.modified = modified;
+
+            log::info!("Reloading {}", path);
+            // a failed load keeps the old value
+            if let Some(value) = load(path, &asset.value) {
+                let old_value = std::mem::replace(&mut asset.value, value);
+                self.retired.push(old_value);
+                reloaded.push(handle);
+            }
+        }
+        reloaded
+    }
+
+    /// call once per frame after waiting for its fence,
+    /// destroys retired values no frame in flight can still use
+    pub fn collect_retired<F: FnMut(T)>(&mut self, destroy: F) {
+        self.retired.collect(destroy);
+    }
+
+    /// destroys everything regardless of references, the device must be idle
+    pub fn destroy_all<F: FnMut(T)>(&mut self, mut destroy: F) {
+        self.retired.destroy_all(&mut destroy);
+        let handles = self.by_path.drain().map(|(_, handle)| handle).collect::<Vec<_>>();
+        for handle in handles {
+            destroy(self.assets.remove(handle).unwrap().value);
+        }
+    }
+}
+
+#[test]
+fn test_asset_cache() {
+    use crate::renderer::MAX_FRAMES_IN_FLIGHT;
+
+    let dir = std::env::temp_dir().join(format!("ash_engine_assets_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("a.txt");
+    let path = path.to_str().unwrap();
+    std::fs::write(path, "first").unwrap();
+
+    let mut cache = AssetCache::<String>::default();
+    let load = |path: &str| std::fs::read_to_string(path).ok();
+    let mut destroyed = vec![];
+
+    assert!(cache.acquire(&format!("{}.missing", path), load).is_none());
+    let a = cache.acquire(path, load).unwrap();
+    assert!(cache.acquire(path, |_| panic!("loaded twice")) == Some(a));
+    assert!(cache.ref_count(a) == 2 && cache.get(a).unwrap() == "first" && cache.is_cached(path));
+
+    // changed files reload in place, the old value waits out the frames in flight
+    cache.hot_reload = true;
+    std::fs::write(path, "second").unwrap();
+    std::fs::File::options().write(true).open(path).unwrap()
+        .set_modified(SystemTime::now() + std::time::Duration::from_secs(10)).unwrap();
+    assert!(cache.reload_changed(|path, _| load(path)) == [a]);
+    assert!(cache.get(a).unwrap() == "second");
+    for _ in 0..MAX_FRAMES_IN_FLIGHT {
+        assert!(destroyed.is_empty());
+        cache.collect_retired(|value| destroyed.push(value));
+    }
+    assert!(destroyed == ["first"]);
+
+    cache.release(a);
+    assert!(cache.get(a).is_some());
+    cache.release(a);
+    assert!(cache.get(a).is_none() && cache.is_empty());
+    for _ in 0..MAX_FRAMES_IN_FLIGHT {
+        cache.collect_retired(|value| destroyed.push(value));
+    }
+    assert!(destroyed == ["first", "second"]);
+
+    // generated assets are shared by name without a file and never reloaded
+    let generated = cache.insert("generated:a", "generated".to_owned());
+    assert!(cache.acquire("generated:a", |_| panic!("loaded generated")) == Some(generated));
+    assert!(cache.reload_changed(|_, _| panic!("reloaded generated")).is_empty());
+    cache.release(generated);
+    cache.release(generated);
+    assert!(cache.is_empty());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,25 @@
+// Compiles every shader in shaders/ to shaders/spirv, for builds without the `runtime-shaders` feature:
+//
+//     cargo run --bin compile_shaders
+
+use ash_engine::renderer::shader::{self, ShaderStage};
+
+fn main() {
+    let compiler = shaderc::Compiler::new().expect("Failed to create shader compiler");
+
+    let mut paths: Vec<String> = std::fs::read_dir("shaders")
+        .expect("Run from the crate's root")
+        .map(|entry| entry.unwrap().path().to_string_lossy().into_owned())
+        .filter(|path| ShaderStage::from_path(path).is_some())
+        .collect();
+    paths.sort();
+
+    std::fs::create_dir_all("shaders/spirv").unwrap();
+    for path in &paths {
+        let spirv = shader::compile_glsl(&compiler, path, ShaderStage::from_path(path).unwrap());
+        let bytes: Vec<u8> = spirv.iter().flat_map(|word| word.to_le_bytes()).collect();
+        std::fs::write(shader::spirv_path(path), bytes).unwrap();
+        println!("{}", path);
+    }
+    println!("Compiled {} shaders", paths.len());
+}
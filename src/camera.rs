@@ -1,5 +1,17 @@
+pub mod controller;
+
 use crate::math::*;
 
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Projection {
+    /// the fly-cam's, `aspect_ratio` and the angles apply
+    Perspective,
+    /// 2D, a world unit per logical pixel of a screen `size` wide and high with the camera's
+    /// translation at its top left and y down, the angles are ignored.
+    /// The renderer keeps `size` at `VkApp::get_ui_size` so world and screen sprites line up
+    Pixels { size: [f32; 2] },
+}
+
 pub struct Camera {
     pub translation: Vector,
     
@@ -7,19 +19,34 @@ pub struct Camera {
     pub z_x_angle: f32,
     /// y axis to xz plane angle
     pub y_xz_angle: f32,
+    /// around the view direction, applied after the other angles
+    pub roll: f32,
 
     /// screen ratio width to height
     pub aspect_ratio: f32,
 
     pub near_z: f32,
     pub far_z: f32,
+    /// near depth at 1 and far at 0, must match the renderer's, see `VkApp::set_reverse_z`
+    pub reverse_z: bool,
+    pub projection: Projection,
 
     pub translation_speed: f32,
-    pub rotation_speed: f32,
 }
 
 impl Camera {
+    /// unit view direction, positive pitch looks down as world y points down
+    pub fn forward(&self) -> Vector {
+        let (yaw_sin, yaw_cos) = self.z_x_angle.sin_cos();
+        let (pitch_sin, pitch_cos) = self.y_xz_angle.sin_cos();
+        Vector::new(yaw_sin * pitch_cos, pitch_sin, yaw_cos * pitch_cos)
+    }
+
     pub fn calc_proj_view(&self) -> Mat {
+        if let Projection::Pixels { size } = self.projection {
+            return pixel_projection(self.translation, size, self.near_z, self.far_z, self.reverse_z);
+        }
+
         let plane = Vector::new(0.0, -1.0, 0.0).wedge(
             &Vector::new(self.z_x_angle.sin(), 0.0, self.z_x_angle.cos())
         );
@@ -28,13 +55,22 @@ impl Camera {
             .translate(-self.translation.x, -self.translation.y, -self.translation.z)
             .rotate(-self.y_xz_angle, plane.yx, plane.zy, plane.xz)
             .rotate(-self.z_x_angle, 0.0, 0.0, 1.0)
+            .rotate(-self.roll, 1.0, 0.0, 0.0)
             .project(
                 self.aspect_ratio,
                 self.near_z,
                 self.far_z,
+                self.reverse_z,
             )
     }
 }
 
-
-
+/// view from `translation` onto a screen of `size` pixels with the origin at its top left,
+/// view space z from `near_z` to `far_z` in front of it maps to the depth range
+pub fn pixel_projection(translation: Vector, size: [f32; 2], near_z: f32, far_z: f32, reverse_z: bool) -> Mat {
+    let [half_width, half_height] = [0.5 * size[0], 0.5 * size[1]];
+    let (near_z, far_z) = if reverse_z { (far_z, near_z) } else { (near_z, far_z) };
+    ModelMat::identity()
+        .translate(-translation.x - half_width, -translation.y - half_height, -translation.z)
+        .project_orthographic(half_width, half_height, near_z, far_z)
+}
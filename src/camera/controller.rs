@@ -0,0 +1,258 @@
+// Camera behaviors driven by the bound actions rather than raw keys:
+// flying around freely, orbiting a target and following a moving target
+
+use serde::Deserialize;
+
+use crate::{config::KeyBindings, input::InputState, math::Vector};
+use super::Camera;
+
+/// most the camera leans into turns
+const MAX_ROLL: f32 = 0.3;
+/// how quickly the lean follows the turn rate, per second
+const ROLL_RATE: f32 = 8.0;
+
+/// closest orbiting and following cameras get to their target
+const MIN_DISTANCE: f32 = 1.0;
+/// distance change per scroll line
+const ZOOM_FACTOR: f32 = 0.9;
+
+/// How mouse motion turns the camera
+#[derive(Clone, Copy, PartialEq, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LookSettings {
+    /// radians per pixel of mouse motion
+    pub sensitivity: f32,
+    /// seconds for the turn rate to get about two thirds of the way to the mouse's, 0 turns instantly
+    pub smoothing: f32,
+    /// extra sensitivity per 1000 pixels per second of mouse speed, fast flicks turn further
+    pub acceleration: f32,
+    pub invert_y: bool,
+    /// radians above or below the horizon, kept under a right angle so the camera can't flip over
+    pub max_pitch: f32,
+    /// radians of lean per radian per second of turning, 0 disables roll
+    pub roll: f32,
+}
+
+impl Default for LookSettings {
+    fn default() -> Self {
+        Self {
+            sensitivity: 0.003,
+            smoothing: 0.0,
+            acceleration: 0.0,
+            invert_y: false,
+            max_pitch: 1.5,
+            roll: 0.0,
+        }
+    }
+}
+
+/// What the bound actions ask of the camera this frame
+#[derive(Clone, Copy, Default, Debug)]
+pub struct CameraInput {
+    /// -1 to 1, along the horizontal view direction
+    pub forward: f32,
+    /// -1 to 1, perpendicular to `forward`
+    pub right: f32,
+    /// mouse motion in pixels
+    pub look: [f32; 2],
+    /// scroll lines, positive moves closer
+    pub zoom: f32,
+}
+
+impl CameraInput {
+    pub fn from_input(input_state: &InputState, key_bindings: &KeyBindings) -> Self {
+        let axis = |positive, negative| {
+            input_state.is_key_pressed(positive) as i32 as f32 - input_state.is_key_pressed(negative) as i32 as f32
+        };
+        Self {
+            forward: axis(key_bindings.forward, key_bindings.back),
+            right: axis(key_bindings.right, key_bindings.left),
+            look: input_state.delta_mouse_pos,
+            zoom: input_state.scroll_delta,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum CameraMode {
+    /// moves along the view with the movement keys
+    Fly,
+    /// looks at `target` from `distance` away, the mouse circles it and scrolling zooms
+    Orbit { target: Vector, distance: f32 },
+    /// trails `distance` behind `target` along the view, catching up over `smoothing` seconds
+    Follow { target: Vector, distance: f32, smoothing: f32 },
+}
+
+/// Moves and turns the camera according to its mode
+pub struct CameraController {
+    pub mode: CameraMode,
+    look: LookSettings,
+    /// radians per second, yaw then pitch
+    turn_rate: [f32; 2],
+}
+
+impl CameraController {
+    pub fn new(look: LookSettings) -> Self {
+        Self {
+            mode: CameraMode::Fly,
+            look,
+            turn_rate: [0.0, 0.0],
+        }
+    }
+
+    pub fn get_look_settings(&self) -> &LookSettings {
+        &self.look
+    }
+
+    pub fn set_look_settings(&mut self, look: LookSettings) {
+        self.look = look;
+    }
+
+    /// moves the orbited or followed target, no effect when flying
+    pub fn set_target(&mut self, new_target: Vector) {
+        match &mut self.mode {
+            CameraMode::Fly => {}
+            CameraMode::Orbit { target, .. } | CameraMode::Follow { target, .. } => *target = new_target,
+        }
+    }
+
+    /// fly, then orbit the point `distance` in front of the camera
+    pub fn cycle_mode(&mut self, camera: &Camera, distance: f32) {
+        self.mode = match self.mode {
+            CameraMode::Fly => {
+                let mut target = camera.translation;
+                target += camera.forward() * distance;
+                CameraMode::Orbit { target, distance }
+            }
+            CameraMode::Orbit { .. } | CameraMode::Follow { .. } => CameraMode::Fly,
+        };
+    }
+
+    pub fn update(&mut self, camera: &mut Camera, input: &CameraInput, dt: f32) {
+        if dt <= 0.0 {
+            return;
+        }
+        self.look(camera, input.look, dt);
+
+        let zoom = ZOOM_FACTOR.powf(input.zoom);
+        match &mut self.mode {
+            CameraMode::Fly => {
+                let (sin, cos) = camera.z_x_angle.sin_cos();
+                let dtranslation = camera.translation_speed * dt;
+                camera.translation += Vector::new(sin, 0.0, cos) * (input.forward * dtranslation);
+                camera.translation += Vector::new(cos, 0.0, -sin) * (input.right * dtranslation);
+            }
+            CameraMode::Orbit { target, distance } => {
+                *distance = (*distance * zoom).clamp(MIN_DISTANCE, camera.far_z / 2.0);
+                camera.translation = *target - camera.forward() * *distance;
+            }
+            CameraMode::Follow { target, distance, smoothing } => {
+                *distance = (*distance * zoom).clamp(MIN_DISTANCE, camera.far_z / 2.0);
+                let desired = *target - camera.forward() * *distance;
+                let follow = if *smoothing > 0.0 { 1.0 - (-dt / *smoothing).exp() } else { 1.0 };
+                camera.translation += (desired - camera.translation) * follow;
+            }
+        }
+    }
+
+    /// `mouse_delta` is the motion in pixels since the last call `dt` seconds ago
+    fn look(&mut self, camera: &mut Camera, mouse_delta: [f32; 2], dt: f32) {
+        let look = &self.look;
+
+        let [dx, dy] = mouse_delta;
+        let mouse_speed = (dx * dx + dy * dy).sqrt() / dt;
+        let sensitivity = look.sensitivity * (1.0 + look.acceleration * mouse_speed / 1000.0);
+        let invert = if look.invert_y { -1.0 } else { 1.0 };
+        let target_rate = [dx * sensitivity / dt, invert * dy * sensitivity / dt];
+
+        let follow = if look.smoothing > 0.0 { 1.0 - (-dt / look.smoothing).exp() } else { 1.0 };
+        for (rate, target) in self.turn_rate.iter_mut().zip(target_rate) {
+            *rate += (target - *rate) * follow;
+        }
+
+        camera.z_x_angle += self.turn_rate[0] * dt;
+        camera.y_xz_angle = (camera.y_xz_angle + self.turn_rate[1] * dt).clamp(-look.max_pitch, look.max_pitch);
+
+        let target_roll = (self.turn_rate[0] * look.roll).clamp(-MAX_ROLL, MAX_ROLL);
+        camera.roll += (target_roll - camera.roll) * (1.0 - (-dt * ROLL_RATE).exp());
+    }
+}
+
+#[cfg(test)]
+fn test_camera() -> Camera {
+    Camera {
+        translation: Vector::new(0.0, 0.0, 0.0),
+        z_x_angle: 0.0,
+        y_xz_angle: 0.0,
+        roll: 0.0,
+        aspect_ratio: 1.0,
+        near_z: 1.0,
+        far_z: 100.0,
+        reverse_z: false,
+        projection: super::Projection::Perspective,
+        translation_speed: 1.0,
+    }
+}
+
+#[test]
+fn test_camera_look() {
+    let mut camera = test_camera();
+    let mut controller = CameraController::new(LookSettings {
+        sensitivity: 0.01,
+        invert_y: true,
+        ..Default::default()
+    });
+    let look = |look| CameraInput { look, ..Default::default() };
+
+    controller.update(&mut camera, &look([10.0, 10.0]), 0.1);
+    assert!((camera.z_x_angle - 0.1).abs() < 1e-5 && (camera.y_xz_angle + 0.1).abs() < 1e-5);
+
+    // can't look past straight up
+    controller.update(&mut camera, &look([0.0, 1000.0]), 0.1);
+    assert!(camera.y_xz_angle == -1.5);
+
+    // smoothed motion lags behind and catches up once the mouse stops
+    controller.set_look_settings(LookSettings { sensitivity: 0.01, smoothing: 0.1, ..Default::default() });
+    let z_x_angle = camera.z_x_angle;
+    controller.update(&mut camera, &look([10.0, 0.0]), 0.1);
+    let lagging = camera.z_x_angle - z_x_angle;
+    assert!(lagging > 0.0 && lagging < 0.1);
+    for _ in 0..20 {
+        controller.update(&mut camera, &look([0.0, 0.0]), 0.1);
+    }
+    assert!((camera.z_x_angle - z_x_angle - 0.1).abs() < 0.05);
+    assert!(camera.roll == 0.0);
+}
+
+#[test]
+fn test_camera_modes() {
+    let mut camera = test_camera();
+    let mut controller = CameraController::new(LookSettings::default());
+
+    // flying forward at yaw 0 moves along +z
+    let forward = CameraInput { forward: 1.0, ..Default::default() };
+    controller.update(&mut camera, &forward, 2.0);
+    assert!((camera.translation.z - 2.0).abs() < 1e-5 && camera.translation.x == 0.0);
+
+    // the orbited point stays centered at the orbit distance
+    controller.cycle_mode(&camera, 10.0);
+    let CameraMode::Orbit { target, .. } = controller.mode else { panic!() };
+    assert!((target.z - 12.0).abs() < 1e-5);
+    let zoom_and_circle = CameraInput { look: [100.0, 50.0], zoom: 1.0, ..Default::default() };
+    controller.update(&mut camera, &zoom_and_circle, 0.1);
+    let clip = camera.calc_proj_view().transform_point(target);
+    assert!(clip[0].abs() < 1e-4 && clip[1].abs() < 1e-4);
+    assert!(((target - camera.translation).norm_sqr().sqrt() - 9.0).abs() < 1e-4);
+
+    // following eases towards the spot behind the target
+    controller.mode = CameraMode::Follow { target, distance: 9.0, smoothing: 0.5 };
+    controller.set_target(Vector::new(0.0, 0.0, 30.0));
+    let before = camera.translation;
+    controller.update(&mut camera, &CameraInput::default(), 0.1);
+    let moved = (camera.translation - before).norm_sqr().sqrt();
+    let remaining = (Vector::new(0.0, 0.0, 30.0) - camera.forward() * 9.0 - camera.translation).norm_sqr().sqrt();
+    assert!(moved > 0.0 && remaining > 0.0);
+
+    controller.cycle_mode(&camera, 10.0);
+    assert!(matches!(controller.mode, CameraMode::Fly));
+}
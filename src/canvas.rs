@@ -0,0 +1,119 @@
+// 2D drawing in logical pixels from the top left of the screen, for 2D games and tools.
+// A canvas collects rectangles, images and text over a frame and submits them as screen sprites,
+// layer by layer with each layer batched by texture. Meshes line up with it under a
+// `Projection::Pixels` camera, where a world unit is a logical pixel offset by the camera's translation:
+//
+//     app.camera.projection = Projection::Pixels { size: app.get_ui_size() };
+//     let mut canvas = Canvas::new(labels.font());
+//     canvas.rect([0.0, 0.0], [200.0, 24.0], [0.0, 0.0, 0.0, 0.5]);
+//     canvas.layer = 1;
+//     canvas.text([8.0, 4.0], "SCORE 100", 16.0, [1.0; 4]);
+//     canvas.submit(app);
+
+use crate::{
+    label::{solid_uv_rect, text_sprites, text_width},
+    renderer::{
+        sprite::{Sprite, SpriteFacing},
+        TextureHandle, VkApp,
+    },
+};
+
+/// logical pixels from the top left of a screen `screen_size` big to normalized device coordinates
+pub fn pixel_to_ndc(pixel: [f32; 2], screen_size: [f32; 2]) -> [f32; 2] {
+    [2.0 * pixel[0] / screen_size[0] - 1.0, 2.0 * pixel[1] / screen_size[1] - 1.0]
+}
+
+/// inverse of `pixel_to_ndc`
+pub fn ndc_to_pixel(ndc: [f32; 2], screen_size: [f32; 2]) -> [f32; 2] {
+    [0.5 * (ndc[0] + 1.0) * screen_size[0], 0.5 * (ndc[1] + 1.0) * screen_size[1]]
+}
+
+/// Draw into it during the frame and `submit` before the frame is drawn, it starts over empty
+pub struct Canvas {
+    /// index of the label font, see `LabelRenderer::font`
+    font: u32,
+    sprites: Vec<Sprite>,
+    /// of what is drawn from now on, higher layers over lower ones
+    pub layer: i32,
+}
+
+impl Canvas {
+    pub fn new(font: TextureHandle) -> Self {
+        Self {
+            font: font.index() as u32,
+            sprites: vec![],
+            layer: 0,
+        }
+    }
+
+    /// a solid rectangle of straight alpha `color`
+    pub fn rect(&mut self, top_left: [f32; 2], size: [f32; 2], color: [f32; 4]) {
+        let font = self.font;
+        self.push(top_left, size, solid_uv_rect(), color, font);
+    }
+
+    /// `uv_rect` of `texture` stretched over the rectangle, see `sprite::atlas_cell`,
+    /// tinted by straight alpha `color`
+    pub fn image(&mut self, top_left: [f32; 2], size: [f32; 2], texture: TextureHandle, uv_rect: [f32; 4], color: [f32; 4]) {
+        self.push(top_left, size, uv_rect, color, texture.index() as u32);
+    }
+
+    /// a line of `text`, `glyph_height` logical pixels high, returns its width for placing what follows
+    pub fn text(&mut self, top_left: [f32; 2], text: &str, glyph_height: f32, color: [f32; 4]) -> f32 {
+        let layer = self.layer;
+        self.sprites.extend(
+            text_sprites(text, top_left, glyph_height, color, self.font).map(|sprite| Sprite { layer, ..sprite })
+        );
+        text_width(text, glyph_height)
+    }
+
+    fn push(&mut self, top_left: [f32; 2], size: [f32; 2], uv_rect: [f32; 4], color: [f32; 4], texture: u32) {
+        self.sprites.push(Sprite {
+            facing: SpriteFacing::Screen,
+            position: [top_left[0] + 0.5 * size[0], top_left[1] + 0.5 * size[1], 0.0],
+            size,
+            uv_rect,
+            color,
+            texture,
+            layer: self.layer,
+        });
+    }
+
+    /// draws everything drawn into the canvas next frame, then empties it and goes back to layer 0
+    pub fn submit(&mut self, app: &mut VkApp) {
+        app.sprite_renderer.submit(self.sprites.drain(..));
+        self.layer = 0;
+    }
+}
+
+#[test]
+fn test_canvas() {
+    use crate::{camera::pixel_projection, math::Vector};
+
+    let screen_size = [200.0, 100.0];
+    assert!(pixel_to_ndc([0.0, 0.0], screen_size) == [-1.0, -1.0] && pixel_to_ndc([150.0, 50.0], screen_size) == [0.5, 0.0]);
+    let [x, y] = ndc_to_pixel(pixel_to_ndc([30.0, 70.0], screen_size), screen_size);
+    assert!((x - 30.0).abs() < 1e-4 && (y - 70.0).abs() < 1e-4);
+
+    // the camera's translation at the top left, a world unit a pixel, near z at depth 0 and far at 1
+    let translation = Vector::new(10.0, 20.0, -5.0);
+    let proj_view = pixel_projection(translation, screen_size, 1.0, 11.0, false);
+    let clip = |x: f32, y: f32, z: f32| proj_view.transform_point(Vector::new(x, y, z));
+    let near = |a: [f32; 4], b: [f32; 4]| a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-5);
+    assert!(near(clip(10.0, 20.0, -4.0), [-1.0, -1.0, 0.0, 1.0]));
+    assert!(near(clip(210.0, 120.0, 6.0), [1.0, 1.0, 1.0, 1.0]));
+    let [ndc_x, ndc_y] = pixel_to_ndc([30.0, 70.0], screen_size);
+    assert!(near(clip(40.0, 90.0, 1.0), [ndc_x, ndc_y, 0.5, 1.0]));
+    let reversed = pixel_projection(translation, screen_size, 1.0, 11.0, true);
+    assert!(near(reversed.transform_point(Vector::new(10.0, 20.0, -4.0)), [-1.0, -1.0, 1.0, 1.0]));
+
+    // placed by the top left, layered, text after the rectangle under it
+    let mut canvas = Canvas { font: 7, sprites: vec![], layer: 0 };
+    canvas.rect([10.0, 10.0], [40.0, 20.0], [1.0; 4]);
+    canvas.layer = 2;
+    let width = canvas.text([10.0, 10.0], "ab", 16.0, [1.0; 4]);
+    assert!(width == 24.0 && canvas.sprites.len() == 3);
+    assert!(canvas.sprites[0].position == [30.0, 20.0, 0.0] && canvas.sprites[0].layer == 0);
+    assert!(canvas.sprites[1].position == [16.0, 18.0, 0.0] && canvas.sprites[2].layer == 2);
+    assert!(canvas.sprites.iter().all(|sprite| sprite.texture == 7 && sprite.facing == SpriteFacing::Screen));
+}
@@ -0,0 +1,118 @@
+// Command line options overriding the config file, e.g. for running tests in other configurations:
+//
+//     ash_engine --width 1920 --height 1080 --vsync off --device "NVIDIA" --validation off --scene scenes/test.ron
+//
+// Values may also follow an `=`, as in `--vsync=off`. The overrides outlive config reloads
+
+use crate::config::EngineConfig;
+
+pub const USAGE: &str = "Options:
+    --width <pixels>         window width
+    --height <pixels>        window height
+    --vsync <on|off>         fifo presentation
+    --device <name>          part of the name of the gpu to use
+    --validation <on|off>    validation layer, debug builds only
+    --scene <path>           scene to load
+    --host <address>         replicate the scene to clients, e.g. 0.0.0.0:7777
+    --headless <on|off>      host without a window or renderer
+    --connect <address>      follow the scene of a host";
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct CommandLine {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub vsync: Option<bool>,
+    pub device: Option<String>,
+    pub validation: Option<bool>,
+    /// left to the app, the engine has no scene of its own
+    pub scene: Option<String>,
+    /// address to replicate the scene on, see net.rs
+    pub host: Option<String>,
+    /// hosts without opening a window, only with `host`
+    pub headless: Option<bool>,
+    /// address of a host whose scene to follow
+    pub connect: Option<String>,
+}
+
+fn parse_switch(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "on" | "true" | "1" => Some(true),
+        "off" | "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+impl CommandLine {
+    /// the arguments after the program name, errors name the offending option
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, String> {
+        let mut command_line = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (option, value) = match arg.split_once('=') {
+                Some((option, value)) => (option.to_owned(), Some(value.to_owned())),
+                None => (arg, None),
+            };
+            let value = value
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{} needs a value", option))?;
+            let invalid = || format!("Invalid value {} for {}", value, option);
+
+            match option.as_str() {
+                "--width" => command_line.width = Some(value.parse().map_err(|_| invalid())?),
+                "--height" => command_line.height = Some(value.parse().map_err(|_| invalid())?),
+                "--vsync" => command_line.vsync = Some(parse_switch(&value).ok_or_else(invalid)?),
+                "--device" => command_line.device = Some(value),
+                "--validation" => command_line.validation = Some(parse_switch(&value).ok_or_else(invalid)?),
+                "--scene" => command_line.scene = Some(value),
+                "--host" => command_line.host = Some(value),
+                "--headless" => command_line.headless = Some(parse_switch(&value).ok_or_else(invalid)?),
+                "--connect" => command_line.connect = Some(value),
+                _ => return Err(format!("Unknown option {}", option)),
+            }
+        }
+        Ok(command_line)
+    }
+
+    pub fn apply(&self, config: &mut EngineConfig) {
+        if let Some(width) = self.width {
+            config.window.width = width;
+        }
+        if let Some(height) = self.height {
+            config.window.height = height;
+        }
+        if let Some(vsync) = self.vsync {
+            config.graphics.vsync = vsync;
+        }
+        if let Some(device) = &self.device {
+            config.graphics.device = Some(device.clone());
+        }
+        if let Some(validation) = self.validation {
+            config.validation.enabled = validation;
+        }
+    }
+}
+
+#[test]
+fn test_command_line() {
+    let parse = |args: &[&str]| CommandLine::parse(args.iter().map(|arg| arg.to_string()));
+
+    let command_line = parse(&["--width", "1920", "--vsync=off", "--device", "NVIDIA GeForce", "--scene", "a.ron"]).unwrap();
+    assert!(command_line.width == Some(1920) && command_line.height.is_none());
+    assert!(command_line.vsync == Some(false) && command_line.scene.as_deref() == Some("a.ron"));
+
+    let mut config = EngineConfig::default();
+    config.graphics.vsync = true;
+    command_line.apply(&mut config);
+    assert!(config.window.width == 1920 && config.window.height == EngineConfig::default().window.height);
+    assert!(!config.graphics.vsync && config.graphics.device.as_deref() == Some("NVIDIA GeForce"));
+    assert!(config.validation.enabled);
+
+    let command_line = parse(&["--host", "0.0.0.0:7777", "--headless", "on"]).unwrap();
+    assert!(command_line.host.as_deref() == Some("0.0.0.0:7777") && command_line.headless == Some(true));
+
+    assert!(parse(&[]).unwrap() == CommandLine::default());
+    assert!(parse(&["--width"]).is_err());
+    assert!(parse(&["--width", "wide"]).is_err());
+    assert!(parse(&["--validation", "maybe"]).is_err());
+    assert!(parse(&["--fullscreen", "on"]).is_err());
+}
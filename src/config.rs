@@ -0,0 +1,428 @@
+// Engine settings read from `engine.toml` at startup, every field is optional:
+//
+//     [window]
+//     width = 1280
+//     height = 720
+//     fullscreen_mode = "Exclusive"
+//
+//     [graphics]
+//     vsync = true
+//     target_fps = 144.0
+//     render_scale = 0.75
+//     swapchain_format = "Hdr10"
+//     reverse_z = true
+//     device = "NVIDIA"
+//
+//     [camera]
+//     translation_speed = 3.0
+//
+//     [camera.look]
+//     sensitivity = 0.003
+//     invert_y = true
+//
+//     [assets]
+//     hot_reload = true
+//
+//     [streaming]
+//     load_radius = 512.0
+//
+//     [fog]
+//     mode = "Exponential"
+//     density = 0.02
+//     height_falloff = 0.1
+//
+//     [key_bindings]
+//     forward = "W"
+//
+//     [replay]
+//     record = "replays/bug.ron"
+//     play = "replays/smoke.ron"
+//     exit_after_playback = true
+//
+//     [validation]
+//     min_severity = "Info"
+//     ignored_messages = ["VUID-vkCmdDraw-None-02859"]
+//     panic_on_error = true
+//
+//     [logging]
+//     levels = "info,renderer=debug"
+//     file = "logs/engine.log"
+//
+// `ConfigWatcher` picks up edits while running, `apply` only touches reload safe settings.
+
+use std::time::SystemTime;
+
+use serde::Deserialize;
+use winit::event::VirtualKeyCode;
+
+use crate::{camera::controller::LookSettings, display::DisplayMode, logging::{Levels, LoggingConfig}, renderer::{debug::ValidationConfig, swapchain::SwapchainFormatPreference, VkApp}, streaming::StreamingSettings, fog::Fog};
+
+pub const CONFIG_PATH: &str = "engine.toml";
+
+/// applied at startup only, besides `fullscreen_mode` which is read on toggling
+#[derive(Clone, Copy, PartialEq, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowConfig {
+    pub width: u32,
+    pub height: u32,
+    /// "Windowed", "Borderless" or "Exclusive"
+    pub display_mode: DisplayMode,
+    /// what toggling fullscreen switches to from windowed
+    pub fullscreen_mode: DisplayMode,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            width: 1280,
+            height: 720,
+            display_mode: DisplayMode::Windowed,
+            fullscreen_mode: DisplayMode::Borderless,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GraphicsConfig {
+    /// fifo presentation, otherwise mailbox or immediate when available
+    pub vsync: bool,
+    /// frame rate cap, 0 is uncapped
+    pub target_fps: f32,
+    // TODO: multisampled attachments, only 1 is supported
+    pub msaa_samples: u32,
+    pub render_scale: f32,
+    /// lets auto quality drive the render scale
+    pub auto_render_scale: bool,
+    /// "Unorm", "Srgb", "Hdr10" or "ExtendedLinear"
+    pub swapchain_format: SwapchainFormatPreference,
+    /// near depth at 1 and far at 0, for precision far from the camera
+    pub reverse_z: bool,
+    /// width over height, e.g. 1.7778 for 16:9, the scene is letterboxed to it when set
+    pub aspect_ratio: Option<f32>,
+    /// runs compute passes on a dedicated compute queue when the device has one,
+    /// off to compare against running them on the graphics queue
+    pub async_compute: bool,
+    /// culls batched draws against the camera, on the gpu when the device can draw with indirect counts
+    pub gpu_culling: bool,
+    /// ray marches reflections of what's on screen for pbr materials
+    pub screen_space_reflections: bool,
+    /// blurs moving objects and camera motion
+    pub motion_blur: bool,
+    /// fraction of a 60 fps frame's motion blurred over, kept at any frame rate
+    pub motion_blur_strength: f32,
+    /// outlines silhouettes and creases of everything drawn, found in the depth buffer
+    pub edge_outlines: bool,
+    /// in pixels
+    pub edge_outline_thickness: f32,
+    /// linear rgb and alpha
+    pub edge_outline_color: [f32; 4],
+    /// writes breadcrumbs between passes and dumps them to a crash log when the device is lost,
+    /// applied at startup only
+    pub gpu_crash_diagnostics: bool,
+    /// part of the name of the gpu to use when it's suitable, applied at startup only
+    pub device: Option<String>,
+}
+
+impl Default for GraphicsConfig {
+    fn default() -> Self {
+        Self {
+            vsync: false,
+            target_fps: 0.0,
+            msaa_samples: 1,
+            render_scale: 1.0,
+            auto_render_scale: false,
+            swapchain_format: SwapchainFormatPreference::Unorm,
+            reverse_z: false,
+            aspect_ratio: None,
+            async_compute: true,
+            gpu_culling: true,
+            screen_space_reflections: false,
+            motion_blur: false,
+            motion_blur_strength: 0.5,
+            edge_outlines: false,
+            edge_outline_thickness: 1.0,
+            edge_outline_color: [0.0, 0.0, 0.0, 1.0],
+            gpu_crash_diagnostics: false,
+            device: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraConfig {
+    pub translation_speed: f32,
+    pub look: LookSettings,
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        Self {
+            translation_speed: 3.0,
+            look: LookSettings::default(),
+        }
+    }
+}
+
+/// winit key names, e.g. "W", "Escape", "F5"
+#[derive(Clone, Copy, PartialEq, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyBindings {
+    pub forward: VirtualKeyCode,
+    pub back: VirtualKeyCode,
+    pub left: VirtualKeyCode,
+    pub right: VirtualKeyCode,
+    /// toggles between game and cursor
+    pub toggle_cursor: VirtualKeyCode,
+    pub save_scene: VirtualKeyCode,
+    /// clear, rain, snow
+    pub cycle_weather: VirtualKeyCode,
+    /// flying, orbiting the point in front of the camera
+    pub cycle_camera: VirtualKeyCode,
+    /// translating, rotating or scaling the selected object
+    pub cycle_gizmo: VirtualKeyCode,
+    /// held while dragging gizmo handles
+    pub snap: VirtualKeyCode,
+    /// with alt held, between windowed and the window's fullscreen mode
+    pub toggle_fullscreen: VirtualKeyCode,
+    /// lit, depth, normals, overdraw, mip level
+    pub cycle_debug_view: VirtualKeyCode,
+    /// screen space reflections on and off
+    pub toggle_ssr: VirtualKeyCode,
+    /// the recent log records over the scene
+    pub toggle_log: VirtualKeyCode,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            forward: VirtualKeyCode::W,
+            back: VirtualKeyCode::S,
+            left: VirtualKeyCode::A,
+            right: VirtualKeyCode::D,
+            toggle_cursor: VirtualKeyCode::Escape,
+            save_scene: VirtualKeyCode::F5,
+            cycle_weather: VirtualKeyCode::F6,
+            cycle_camera: VirtualKeyCode::F7,
+            cycle_gizmo: VirtualKeyCode::F8,
+            snap: VirtualKeyCode::LControl,
+            toggle_fullscreen: VirtualKeyCode::Return,
+            cycle_debug_view: VirtualKeyCode::F9,
+            toggle_ssr: VirtualKeyCode::F10,
+            toggle_log: VirtualKeyCode::F3,
+        }
+    }
+}
+
+impl KeyBindings {
+    /// every binding by its field name, which is how scripts query actions
+    pub fn actions(&self) -> [(&'static str, VirtualKeyCode); 14] {
+        [
+            ("forward", self.forward),
+            ("back", self.back),
+            ("left", self.left),
+            ("right", self.right),
+            ("toggle_cursor", self.toggle_cursor),
+            ("save_scene", self.save_scene),
+            ("cycle_weather", self.cycle_weather),
+            ("cycle_camera", self.cycle_camera),
+            ("cycle_gizmo", self.cycle_gizmo),
+            ("snap", self.snap),
+            ("toggle_fullscreen", self.toggle_fullscreen),
+            ("cycle_debug_view", self.cycle_debug_view),
+            ("toggle_ssr", self.toggle_ssr),
+            ("toggle_log", self.toggle_log),
+        ]
+    }
+
+    fn keys(&self) -> [VirtualKeyCode; 14] {
+        self.actions().map(|(_, key)| key)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AssetsConfig {
+    /// polls loaded assets' files every frame and reloads the changed ones
+    pub hot_reload: bool,
+}
+
+/// applied at startup only, playing takes precedence over recording
+#[derive(Clone, PartialEq, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReplayConfig {
+    /// input recording written when the window closes
+    pub record: Option<String>,
+    /// input recording fed to the engine instead of the live input
+    pub play: Option<String>,
+    /// closes the window once playback finished, e.g. for smoke tests
+    pub exit_after_playback: bool,
+}
+
+/// while the window is minimized or hidden nothing is drawn
+#[derive(Clone, Copy, PartialEq, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SuspendConfig {
+    /// skips updates too, otherwise the app keeps simulating
+    pub pause_simulation: bool,
+    /// seconds suspended before the swapchain is released, negative keeps it
+    pub release_after: f32,
+    /// seconds between frames while suspended
+    pub frame_interval: f32,
+}
+
+impl Default for SuspendConfig {
+    fn default() -> Self {
+        Self {
+            pause_simulation: false,
+            release_after: 5.0,
+            frame_interval: 0.1,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EngineConfig {
+    pub window: WindowConfig,
+    pub graphics: GraphicsConfig,
+    pub camera: CameraConfig,
+    pub assets: AssetsConfig,
+    pub key_bindings: KeyBindings,
+    pub replay: ReplayConfig,
+    pub suspend: SuspendConfig,
+    pub streaming: StreamingSettings,
+    pub fog: Fog,
+    pub validation: ValidationConfig,
+    pub logging: LoggingConfig,
+}
+
+impl EngineConfig {
+    /// missing file means default settings, an invalid one is logged and ignored
+    pub fn load(path: &str) -> Self {
+        match std::fs::read_to_string(path) {
+            Ok(source) => Self::parse(&source).unwrap_or_else(|err| {
+                log::error!("Invalid config {}, using defaults: {}", path, err);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let config: Self = toml::from_str(source).map_err(|err| err.to_string())?;
+
+        // input state only tracks the first 128 key codes
+        for key in config.key_bindings.keys() {
+            if key as usize >= crate::input::KEY_CODE_COUNT {
+                return Err(format!("Key {:?} can't be bound", key));
+            }
+        }
+        Levels::parse(&config.logging.levels)?;
+        if config.graphics.msaa_samples != 1 {
+            log::warn!("MSAA is not supported yet, ignoring msaa_samples = {}", config.graphics.msaa_samples);
+        }
+        Ok(config)
+    }
+
+    /// applies the settings that can change while running,
+    /// key bindings and streaming settings are read from the config on use
+    pub fn apply(&self, app: &mut VkApp) {
+        crate::logging::logger().configure(&self.logging);
+        app.camera.translation_speed = self.camera.translation_speed;
+        app.camera_controller.set_look_settings(self.camera.look);
+
+        app.texture_assets.hot_reload = self.assets.hot_reload;
+        app.set_vsync(self.graphics.vsync);
+        app.frame_limiter.set_target_fps(self.graphics.target_fps);
+        app.set_swapchain_format_preference(self.graphics.swapchain_format);
+        app.set_reverse_z(self.graphics.reverse_z);
+        app.set_fixed_aspect_ratio(self.graphics.aspect_ratio);
+        app.set_async_compute(self.graphics.async_compute);
+        app.gpu_culling.enabled = self.graphics.gpu_culling;
+        app.ssr.enabled = self.graphics.screen_space_reflections;
+        app.motion_blur.enabled = self.graphics.motion_blur;
+        app.motion_blur.strength = self.graphics.motion_blur_strength;
+        app.edge_outlines.enabled = self.graphics.edge_outlines;
+        app.edge_outlines.thickness = self.graphics.edge_outline_thickness;
+        app.edge_outlines.color = self.graphics.edge_outline_color;
+        app.fog = self.fog;
+        app.auto_quality.enabled = self.graphics.auto_render_scale && app.gpu_profiler.is_supported();
+        if !app.auto_quality.enabled {
+            app.set_render_scale(self.graphics.render_scale);
+        }
+    }
+}
+
+/// polls the config file's modification time
+pub struct ConfigWatcher {
+    path: String,
+    modified: Option<SystemTime>,
+}
+
+impl ConfigWatcher {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_owned(),
+            modified: Self::read_modified(path),
+        }
+    }
+
+    fn read_modified(path: &str) -> Option<SystemTime> {
+        std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+    }
+
+    /// the reloaded config when the file changed since the last poll
+    pub fn poll(&mut self) -> Option<EngineConfig> {
+        let modified = Self::read_modified(&self.path);
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+
+        log::info!("Reloading {}", self.path);
+        Some(EngineConfig::load(&self.path))
+    }
+}
+
+#[test]
+fn test_parse_config() {
+    let config = EngineConfig::parse("
+        [graphics]
+        vsync = true
+        render_scale = 0.5
+        swapchain_format = \"Hdr10\"
+        aspect_ratio = 1.75
+
+        [camera.look]
+        invert_y = true
+
+        [window]
+        display_mode = \"Borderless\"
+
+        [key_bindings]
+        forward = \"Up\"
+
+        [fog]
+        mode = \"Linear\"
+        end = 100.0
+
+        [logging]
+        levels = \"warn,assets=debug\"
+    ").unwrap();
+    assert!(config.graphics.vsync && config.graphics.render_scale == 0.5);
+    assert!(config.graphics.swapchain_format == SwapchainFormatPreference::Hdr10);
+    assert!(config.graphics.aspect_ratio == Some(1.75) && !config.graphics.reverse_z);
+    assert!(config.key_bindings.forward == VirtualKeyCode::Up);
+    assert!(config.key_bindings.back == VirtualKeyCode::S);
+    assert!(config.window.display_mode == DisplayMode::Borderless && config.window.width == WindowConfig::default().width);
+    assert!(config.camera.look.invert_y && config.camera.look.sensitivity == LookSettings::default().sensitivity);
+    assert!(config.fog.mode == crate::fog::FogMode::Linear && config.fog.end == 100.0 && config.fog.follow_sky);
+
+    assert!(EngineConfig::parse("").unwrap() == EngineConfig::default());
+    assert!(EngineConfig::parse("[graphics]\nvsinc = true").is_err());
+    assert!(EngineConfig::parse("[key_bindings]\nforward = \"NotAKey\"").is_err());
+    assert!(config.logging.levels == "warn,assets=debug" && config.logging.file.is_none());
+    assert!(EngineConfig::parse("[logging]\nlevels = \"loud\"").is_err());
+}
@@ -0,0 +1,4 @@
+pub mod aabb_tree;
+pub mod bvh;
+pub mod handle_map;
+pub mod interner;
@@ -0,0 +1,397 @@
+// Dynamic bounding volume tree for the broad phase of collisions and picking.
+// Leaves hold boxes grown by a margin, so objects moving within their margin don't touch the tree.
+// Leaves are inserted next to the sibling that grows the tree's surface area least
+// and the tree is kept height balanced by rotations on the way back up
+
+use crate::math::{Aabb, Ray};
+
+const NULL: u32 = u32::MAX;
+
+/// refers to a leaf of an `AabbTree`, stays valid until the leaf is removed
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct ProxyId(u32);
+
+struct Node<T> {
+    /// grown by the tree's margin for leaves, bounding the children otherwise
+    aabb: Aabb,
+    parent: u32,
+    /// both `NULL` for leaves
+    children: [u32; 2],
+    /// 0 for leaves, -1 while free
+    height: i32,
+    /// leaves only
+    data: Option<T>,
+}
+
+impl<T> Node<T> {
+    fn is_leaf(&self) -> bool {
+        self.children[0] == NULL
+    }
+}
+
+pub struct AabbTree<T> {
+    nodes: Vec<Node<T>>,
+    free_nodes: Vec<u32>,
+    root: u32,
+    /// leaves' boxes are grown by this much on every side
+    margin: f32,
+    leaf_count: usize,
+}
+
+impl<T: Copy> AabbTree<T> {
+    pub fn new(margin: f32) -> Self {
+        Self {
+            nodes: vec![],
+            free_nodes: vec![],
+            root: NULL,
+            margin,
+            leaf_count: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.leaf_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaf_count == 0
+    }
+
+    /// 0 when empty or holding one leaf
+    pub fn height(&self) -> i32 {
+        if self.root == NULL { 0 } else { self.nodes[self.root as usize].height }
+    }
+
+    fn allocate_node(&mut self, node: Node<T>) -> u32 {
+        match self.free_nodes.pop() {
+            Some(index) => {
+                self.nodes[index as usize] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() as u32 - 1
+            }
+        }
+    }
+
+    fn free_node(&mut self, index: u32) {
+        let node = &mut self.nodes[index as usize];
+        node.height = -1;
+        node.data = None;
+        self.free_nodes.push(index);
+    }
+
+    pub fn insert(&mut self, aabb: Aabb, data: T) -> ProxyId {
+        let leaf = self.allocate_node(Node {
+            aabb: aabb.expanded(self.margin),
+            parent: NULL,
+            children: [NULL; 2],
+            height: 0,
+            data: Some(data),
+        });
+        self.insert_leaf(leaf);
+        self.leaf_count += 1;
+        ProxyId(leaf)
+    }
+
+    pub fn remove(&mut self, id: ProxyId) {
+        assert!(self.nodes[id.0 as usize].data.is_some(), "Removing a removed proxy");
+        self.remove_leaf(id.0);
+        self.free_node(id.0);
+        self.leaf_count -= 1;
+    }
+
+    /// moves the leaf when `aabb` left its grown box, returns whether it moved
+    pub fn update(&mut self, id: ProxyId, aabb: Aabb) -> bool {
+        if self.nodes[id.0 as usize].aabb.contains(&aabb) {
+            return false;
+        }
+        self.remove_leaf(id.0);
+        self.nodes[id.0 as usize].aabb = aabb.expanded(self.margin);
+        self.insert_leaf(id.0);
+        true
+    }
+
+    pub fn get_data(&self, id: ProxyId) -> T {
+        self.nodes[id.0 as usize].data.expect("Getting a removed proxy")
+    }
+
+    /// the leaf's box grown by the margin
+    pub fn get_fat_aabb(&self, id: ProxyId) -> &Aabb {
+        &self.nodes[id.0 as usize].aabb
+    }
+
+    fn insert_leaf(&mut self, leaf: u32) {
+        if self.root == NULL {
+            self.root = leaf;
+            self.nodes[leaf as usize].parent = NULL;
+            return;
+        }
+
+        // descend towards the cheapest sibling, the cost of a node being
+        // the surface area it adds to the tree
+        let leaf_aabb = self.nodes[leaf as usize].aabb;
+        let mut index = self.root;
+        while !self.nodes[index as usize].is_leaf() {
+            let node = &self.nodes[index as usize];
+            let combined_area = node.aabb.union(&leaf_aabb).surface_area();
+            // pairing with this node creates a parent bounding both
+            let cost = 2.0 * combined_area;
+            // descending grows this node by as much
+            let inheritance_cost = 2.0 * (combined_area - node.aabb.surface_area());
+
+            let child_cost = |child: u32| {
+                let child = &self.nodes[child as usize];
+                let area = child.aabb.union(&leaf_aabb).surface_area();
+                if child.is_leaf() {
+                    area + inheritance_cost
+                } else {
+                    area - child.aabb.surface_area() + inheritance_cost
+                }
+            };
+            let [child0, child1] = node.children;
+            let (cost0, cost1) = (child_cost(child0), child_cost(child1));
+
+            if cost < cost0 && cost < cost1 {
+                break;
+            }
+            index = if cost0 < cost1 { child0 } else { child1 };
+        }
+
+        let sibling = index;
+        let old_parent = self.nodes[sibling as usize].parent;
+        let new_parent = self.allocate_node(Node {
+            aabb: leaf_aabb.union(&self.nodes[sibling as usize].aabb),
+            parent: old_parent,
+            children: [sibling, leaf],
+            height: self.nodes[sibling as usize].height + 1,
+            data: None,
+        });
+        self.replace_child(old_parent, sibling, new_parent);
+        self.nodes[sibling as usize].parent = new_parent;
+        self.nodes[leaf as usize].parent = new_parent;
+
+        self.refit_ancestors(new_parent);
+    }
+
+    fn remove_leaf(&mut self, leaf: u32) {
+        if leaf == self.root {
+            self.root = NULL;
+            return;
+        }
+
+        let parent = self.nodes[leaf as usize].parent;
+        let grandparent = self.nodes[parent as usize].parent;
+        let [child0, child1] = self.nodes[parent as usize].children;
+        let sibling = if child0 == leaf { child1 } else { child0 };
+
+        self.replace_child(grandparent, parent, sibling);
+        self.nodes[sibling as usize].parent = grandparent;
+        self.free_node(parent);
+        if grandparent != NULL {
+            self.refit_ancestors(grandparent);
+        }
+    }
+
+    /// `parent`'s `old_child` becomes `new_child`, the root when `parent` is `NULL`
+    fn replace_child(&mut self, parent: u32, old_child: u32, new_child: u32) {
+        if parent == NULL {
+            self.root = new_child;
+            return;
+        }
+        let children = &mut self.nodes[parent as usize].children;
+        if children[0] == old_child {
+            children[0] = new_child;
+        } else {
+            children[1] = new_child;
+        }
+    }
+
+    /// balances and refits `index` and its ancestors
+    fn refit_ancestors(&mut self, mut index: u32) {
+        while index != NULL {
+            index = self.balance(index);
+            self.refit(index);
+            index = self.nodes[index as usize].parent;
+        }
+    }
+
+    fn refit(&mut self, index: u32) {
+        let [child0, child1] = self.nodes[index as usize].children;
+        let (child0, child1) = (&self.nodes[child0 as usize], &self.nodes[child1 as usize]);
+        let aabb = child0.aabb.union(&child1.aabb);
+        let height = 1 + child0.height.max(child1.height);
+        let node = &mut self.nodes[index as usize];
+        node.aabb = aabb;
+        node.height = height;
+    }
+
+    /// rotates the taller child of `a` up when the children's heights differ by more than 1,
+    /// returns the node now in `a`'s place
+    fn balance(&mut self, a: u32) -> u32 {
+        if self.nodes[a as usize].is_leaf() || self.nodes[a as usize].height < 2 {
+            return a;
+        }
+
+        let [b, c] = self.nodes[a as usize].children;
+        let height_difference = self.nodes[c as usize].height - self.nodes[b as usize].height;
+        let (up, other_slot) = if height_difference > 1 {
+            (c, 1)
+        } else if height_difference < -1 {
+            (b, 0)
+        } else {
+            return a;
+        };
+
+        // `up` takes `a`'s place with `a` as its first child,
+        // `a` keeps the shorter of `up`'s children in place of `up`
+        let [f, g] = self.nodes[up as usize].children;
+        let (taller, shorter) = if self.nodes[f as usize].height > self.nodes[g as usize].height { (f, g) } else { (g, f) };
+
+        let a_parent = self.nodes[a as usize].parent;
+        self.replace_child(a_parent, a, up);
+        self.nodes[up as usize].parent = a_parent;
+        self.nodes[up as usize].children = [a, taller];
+        self.nodes[a as usize].parent = up;
+        self.nodes[a as usize].children[other_slot] = shorter;
+        self.nodes[shorter as usize].parent = a;
+
+        self.refit(a);
+        self.refit(up);
+        up
+    }
+
+    /// leaves whose grown boxes intersect `aabb`
+    pub fn query_aabb(&self, aabb: &Aabb, results: &mut Vec<ProxyId>) {
+        results.clear();
+        if self.root == NULL {
+            return;
+        }
+        let mut stack = vec![self.root];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index as usize];
+            if !node.aabb.intersects(aabb) {
+                continue;
+            }
+            if node.is_leaf() {
+                results.push(ProxyId(index));
+            } else {
+                stack.extend(node.children);
+            }
+        }
+    }
+
+    /// every pair of leaves whose grown boxes intersect, once with the smaller id first,
+    /// for the narrow phase to test exactly
+    pub fn query_pairs(&self, pairs: &mut Vec<(ProxyId, ProxyId)>) {
+        pairs.clear();
+        let mut overlaps = vec![];
+        for (index, node) in self.nodes.iter().enumerate() {
+            if node.data.is_none() {
+                continue;
+            }
+            self.query_aabb(&node.aabb, &mut overlaps);
+            let id = ProxyId(index as u32);
+            pairs.extend(overlaps.iter().filter(|&&other| other > id).map(|&other| (id, other)));
+        }
+    }
+
+    /// leaves whose grown boxes `ray` hits within `max_distance`, nearest entry first,
+    /// so picking can stop at the first exact hit
+    pub fn query_ray(&self, ray: &Ray, max_distance: f32, results: &mut Vec<(ProxyId, f32)>) {
+        results.clear();
+        if self.root == NULL {
+            return;
+        }
+        let mut stack = vec![self.root];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index as usize];
+            match ray.intersect_aabb(&node.aabb) {
+                Some(distance) if distance <= max_distance => {
+                    if node.is_leaf() {
+                        results.push((ProxyId(index), distance));
+                    } else {
+                        stack.extend(node.children);
+                    }
+                }
+                _ => {}
+            }
+        }
+        results.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+    }
+
+    /// parent links, heights and bounds, returns the leaf count
+    #[cfg(test)]
+    fn validate(&self, index: u32, parent: u32) -> usize {
+        let node = &self.nodes[index as usize];
+        assert!(node.parent == parent);
+        if node.is_leaf() {
+            assert!(node.height == 0 && node.data.is_some());
+            return 1;
+        }
+        let [child0, child1] = node.children;
+        let (height0, height1) = (self.nodes[child0 as usize].height, self.nodes[child1 as usize].height);
+        assert!(node.height == 1 + height0.max(height1) && (height0 - height1).abs() <= 1);
+        assert!(node.aabb.contains(&self.nodes[child0 as usize].aabb) && node.aabb.contains(&self.nodes[child1 as usize].aabb));
+        self.validate(child0, index) + self.validate(child1, index)
+    }
+}
+
+impl<T: Copy> Default for AabbTree<T> {
+    /// a tenth of a unit margin
+    fn default() -> Self {
+        Self::new(0.1)
+    }
+}
+
+#[test]
+fn test_aabb_tree() {
+    use crate::math::Vector;
+    // unit cube with its min corner at `min`
+    let unit_box = |min: Vector| Aabb { min, max: min + Vector::new(1.0, 1.0, 1.0) };
+
+    let mut tree = AabbTree::new(0.1);
+    // a row of boxes 2 apart along x, a pseudo random order so insertion is not sorted
+    let mut ids = vec![];
+    for i in 0..64 {
+        let x = ((i * 37) % 64) as f32 * 2.0;
+        ids.push(tree.insert(unit_box(Vector::new(x, 0.0, 0.0)), x as usize));
+    }
+    assert!(tree.len() == 64 && tree.validate(tree.root, NULL) == 64);
+    // balanced, 64 leaves fit in a tree of height 6
+    assert!(tree.height() <= 8);
+
+    let mut results = vec![];
+    tree.query_aabb(&Aabb { min: Vector::new(1.5, 0.0, 0.0), max: Vector::new(6.5, 1.0, 1.0) }, &mut results);
+    let mut found: Vec<usize> = results.iter().map(|&id| tree.get_data(id)).collect();
+    found.sort();
+    assert!(found == [2, 4, 6]);
+
+    // nothing overlaps until one box moves onto another
+    let mut pairs = vec![];
+    tree.query_pairs(&mut pairs);
+    assert!(pairs.is_empty());
+    // moves within the margin leave the tree alone
+    assert!(!tree.update(ids[0], unit_box(Vector::new(0.05, 0.0, 0.0))));
+    let x_of_1 = tree.get_data(ids[1]) as f32;
+    assert!(tree.update(ids[0], unit_box(Vector::new(x_of_1 + 0.5, 0.0, 0.0))));
+    tree.query_pairs(&mut pairs);
+    assert!(pairs.len() == 1 && pairs[0] == (ids[0], ids[1]));
+    assert!(tree.validate(tree.root, NULL) == 64);
+
+    // the ray along x from the left hits every box in order
+    let ray = Ray { origin: Vector::new(-5.0, 0.5, 0.5), direction: Vector::new(1.0, 0.0, 0.0) };
+    let mut hits = vec![];
+    tree.query_ray(&ray, 20.0, &mut hits);
+    let hit_data: Vec<usize> = hits.iter().map(|&(id, _)| tree.get_data(id)).collect();
+    assert!(hit_data[..3] == [2, 4, 6] && hits.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+    let missing = Ray { origin: Vector::new(-5.0, 5.0, 0.5), direction: Vector::new(1.0, 0.0, 0.0) };
+    tree.query_ray(&missing, f32::INFINITY, &mut hits);
+    assert!(hits.is_empty());
+
+    for id in ids {
+        tree.remove(id);
+    }
+    assert!(tree.is_empty() && tree.root == NULL);
+}
@@ -0,0 +1,347 @@
+// Bounding volume hierarchy for ray queries over a scene, for picking, line of sight checks
+// and anything else casting rays on the cpu. Unlike `AabbTree` it is built once over all its
+// primitives and only refit as they move, which keeps the boxes tight but grows them looser
+// the further primitives move from where they were built, rebuild then.
+// Bvhs are primitives themselves, so meshes picked precisely nest a bvh of their triangles
+// in the scene's, other objects are only their boxes:
+//
+//     let mut builder = BvhBuilder::new();
+//     builder.push(SceneShape::Bounds(bounds.transformed(&world).aabb));
+//     builder.push(SceneShape::Triangles(BvhBuilder::from_primitives(triangles).build()));
+//     let bvh = builder.build();
+//     if let Some(hit) = bvh.raycast(&ray, f32::INFINITY) { select(hit.primitive) }
+
+use crate::math::{Aabb, ModelMat, Ray, Vector};
+
+/// what a `Bvh` holds
+pub trait Primitive {
+    fn aabb(&self) -> Aabb;
+    /// distance along the ray to the nearest hit within `max_distance`
+    fn raycast(&self, ray: &Ray, max_distance: f32) -> Option<f32>;
+}
+
+impl Primitive for Aabb {
+    fn aabb(&self) -> Aabb {
+        *self
+    }
+
+    fn raycast(&self, ray: &Ray, max_distance: f32) -> Option<f32> {
+        ray.intersect_aabb(self).filter(|&distance| distance <= max_distance)
+    }
+}
+
+/// hit from either side
+#[derive(Clone, Copy, Debug)]
+pub struct Triangle {
+    pub vertices: [Vector; 3],
+}
+
+impl Triangle {
+    pub fn transformed(&self, model: &ModelMat) -> Self {
+        let transform = |point: Vector| model.translation()
+            + model.axis(0) * point.x
+            + model.axis(1) * point.y
+            + model.axis(2) * point.z;
+        Self { vertices: self.vertices.map(transform) }
+    }
+}
+
+impl Primitive for Triangle {
+    fn aabb(&self) -> Aabb {
+        let [a, b, c] = self.vertices;
+        Aabb { min: a, max: a }.union(&Aabb { min: b, max: b }).union(&Aabb { min: c, max: c })
+    }
+
+    /// Möller–Trumbore
+    fn raycast(&self, ray: &Ray, max_distance: f32) -> Option<f32> {
+        let [a, b, c] = self.vertices;
+        let (edge0, edge1) = (b - a, c - a);
+        let p = ray.direction.cross(&edge1);
+        let determinant = edge0.dot(&p);
+        // parallel to the triangle
+        if determinant.abs() < 1e-8 {
+            return None;
+        }
+        let inverse = 1.0 / determinant;
+        let offset = ray.origin - a;
+        let u = offset.dot(&p) * inverse;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = offset.cross(&edge0);
+        let v = ray.direction.dot(&q) * inverse;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let distance = edge1.dot(&q) * inverse;
+        (0.0..=max_distance).contains(&distance).then_some(distance)
+    }
+}
+
+/// an object of a scene bvh
+pub enum SceneShape {
+    /// world space box, for objects that don't need precise hits
+    Bounds(Aabb),
+    /// world space triangles, refit with `Bvh::refit` after transforming them
+    Triangles(Bvh<Triangle>),
+}
+
+impl Primitive for SceneShape {
+    fn aabb(&self) -> Aabb {
+        match self {
+            SceneShape::Bounds(aabb) => *aabb,
+            SceneShape::Triangles(bvh) => bvh.aabb(),
+        }
+    }
+
+    fn raycast(&self, ray: &Ray, max_distance: f32) -> Option<f32> {
+        match self {
+            SceneShape::Bounds(aabb) => aabb.raycast(ray, max_distance),
+            SceneShape::Triangles(bvh) => bvh.raycast(ray, max_distance).map(|hit| hit.distance),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Hit {
+    /// index of the primitive in the order pushed to the builder
+    pub primitive: usize,
+    pub distance: f32,
+}
+
+/// Leaves hold up to this many primitives by default
+const MAX_LEAF_SIZE: usize = 4;
+
+pub struct BvhBuilder<P> {
+    primitives: Vec<P>,
+    max_leaf_size: usize,
+}
+
+impl<P: Primitive> Default for BvhBuilder<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P: Primitive> BvhBuilder<P> {
+    pub fn new() -> Self {
+        Self::from_primitives(vec![])
+    }
+
+    pub fn from_primitives(primitives: Vec<P>) -> Self {
+        Self { primitives, max_leaf_size: MAX_LEAF_SIZE }
+    }
+
+    /// fewer makes deeper trees testing fewer primitives per ray
+    pub fn max_leaf_size(mut self, max_leaf_size: usize) -> Self {
+        assert!(max_leaf_size > 0);
+        self.max_leaf_size = max_leaf_size;
+        self
+    }
+
+    /// returns the primitive's index in the bvh
+    pub fn push(&mut self, primitive: P) -> usize {
+        self.primitives.push(primitive);
+        self.primitives.len() - 1
+    }
+
+    /// splits nodes at the median of their primitives' centers along their longest axis
+    pub fn build(self) -> Bvh<P> {
+        let mut bvh = Bvh {
+            nodes: vec![],
+            order: (0..self.primitives.len() as u32).collect(),
+            centers: self.primitives.iter().map(|primitive| {
+                let aabb = primitive.aabb();
+                (aabb.min + aabb.max) * 0.5
+            }).collect(),
+            primitives: self.primitives,
+        };
+        if !bvh.primitives.is_empty() {
+            bvh.build_node(0, bvh.primitives.len(), self.max_leaf_size);
+        }
+        bvh
+    }
+}
+
+struct Node {
+    aabb: Aabb,
+    /// leaves' first entry of `order`, interior nodes' second child, the first directly follows
+    first: u32,
+    /// primitives of leaves, 0 for interior nodes
+    count: u32,
+}
+
+/// Built by `BvhBuilder`, primitives keep their indices through rebuilds and refits
+pub struct Bvh<P> {
+    /// depth first, children after their parents
+    nodes: Vec<Node>,
+    /// primitive indices, each leaf's contiguous
+    order: Vec<u32>,
+    /// of the primitives' boxes when built
+    centers: Vec<Vector>,
+    primitives: Vec<P>,
+}
+
+impl<P: Primitive> Bvh<P> {
+    pub fn len(&self) -> usize {
+        self.primitives.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.primitives.is_empty()
+    }
+
+    pub fn get_primitive(&self, index: usize) -> &P {
+        &self.primitives[index]
+    }
+
+    /// `refit` after moving primitives
+    pub fn get_primitive_mut(&mut self, index: usize) -> &mut P {
+        &mut self.primitives[index]
+    }
+
+    /// bounds every primitive, panics when empty
+    pub fn aabb(&self) -> Aabb {
+        self.nodes.first().expect("Bounds of an empty bvh").aabb
+    }
+
+    /// returns the node's index
+    fn build_node(&mut self, start: usize, end: usize, max_leaf_size: usize) -> usize {
+        let aabb = self.order[start..end]
+            .iter()
+            .map(|&index| self.primitives[index as usize].aabb())
+            .reduce(|a, b| a.union(&b))
+            .unwrap();
+        let index = self.nodes.len();
+        self.nodes.push(Node { aabb, first: start as u32, count: (end - start) as u32 });
+        if end - start <= max_leaf_size {
+            return index;
+        }
+
+        let size = aabb.max - aabb.min;
+        let axis = if size.x >= size.y && size.x >= size.z { 0 } else if size.y >= size.z { 1 } else { 2 };
+        let component = |center: Vector| [center.x, center.y, center.z][axis];
+        let middle = (start + end) / 2;
+        let centers = &self.centers;
+        self.order[start..end].select_nth_unstable_by(middle - start, |&a, &b| {
+            component(centers[a as usize]).total_cmp(&component(centers[b as usize]))
+        });
+
+        self.build_node(start, middle, max_leaf_size);
+        let second = self.build_node(middle, end, max_leaf_size);
+        self.nodes[index].first = second as u32;
+        self.nodes[index].count = 0;
+        index
+    }
+
+    /// recomputes the boxes after primitives moved, the tree's shape stays as built
+    pub fn refit(&mut self) {
+        for index in (0..self.nodes.len()).rev() {
+            let Node { first, count, .. } = self.nodes[index];
+            self.nodes[index].aabb = if count > 0 {
+                self.order[first as usize..(first + count) as usize]
+                    .iter()
+                    .map(|&primitive| self.primitives[primitive as usize].aabb())
+                    .reduce(|a, b| a.union(&b))
+                    .unwrap()
+            } else {
+                self.nodes[index + 1].aabb.union(&self.nodes[first as usize].aabb)
+            };
+        }
+    }
+
+    /// the nearest primitive `ray` hits within `max_distance`
+    pub fn raycast(&self, ray: &Ray, max_distance: f32) -> Option<Hit> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let mut nearest: Option<Hit> = None;
+        let mut max_distance = max_distance;
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if node.aabb.raycast(ray, max_distance).is_none() {
+                continue;
+            }
+            if node.count == 0 {
+                // the nearer child is popped first, so hits in it prune the other
+                let children = [index + 1, node.first as usize];
+                let [near, far] = children.map(|child| self.nodes[child].aabb.raycast(ray, max_distance));
+                if near.unwrap_or(f32::INFINITY) <= far.unwrap_or(f32::INFINITY) {
+                    stack.extend([children[1], children[0]]);
+                } else {
+                    stack.extend(children);
+                }
+                continue;
+            }
+            for &primitive in &self.order[node.first as usize..(node.first + node.count) as usize] {
+                if let Some(distance) = self.primitives[primitive as usize].raycast(ray, max_distance) {
+                    max_distance = distance;
+                    nearest = Some(Hit { primitive: primitive as usize, distance });
+                }
+            }
+        }
+        nearest
+    }
+
+    /// whether nothing is between `from` and `to`
+    pub fn line_of_sight(&self, from: Vector, to: Vector) -> bool {
+        let offset = to - from;
+        let distance = offset.norm_sqr().sqrt();
+        if distance < 1e-6 {
+            return true;
+        }
+        let ray = Ray { origin: from, direction: offset / distance };
+        self.raycast(&ray, distance).is_none()
+    }
+}
+
+impl<P: Primitive> Primitive for Bvh<P> {
+    fn aabb(&self) -> Aabb {
+        Bvh::aabb(self)
+    }
+
+    fn raycast(&self, ray: &Ray, max_distance: f32) -> Option<f32> {
+        Bvh::raycast(self, ray, max_distance).map(|hit| hit.distance)
+    }
+}
+
+#[test]
+fn test_bvh() {
+    // unit cube with its min corner at `min`
+    let unit_box = |min: Vector| Aabb { min, max: min + Vector::new(1.0, 1.0, 1.0) };
+    let along_x = |y: f32| Ray { origin: Vector::new(-5.0, y, 0.5), direction: Vector::new(1.0, 0.0, 0.0) };
+
+    // a row of boxes 2 apart along x, pushed out of order
+    let mut builder = BvhBuilder::new().max_leaf_size(2);
+    for i in 0..64 {
+        builder.push(SceneShape::Bounds(unit_box(Vector::new(((i * 37) % 64) as f32 * 2.0, 0.0, 0.0))));
+    }
+    // a triangle in front of them all, facing the ray
+    let triangle = Triangle {
+        vertices: [Vector::new(-1.0, 0.0, 0.0), Vector::new(-1.0, 4.0, 0.0), Vector::new(-1.0, 0.0, 4.0)],
+    };
+    let mesh = builder.push(SceneShape::Triangles(BvhBuilder::from_primitives(vec![triangle]).build()));
+    let mut bvh = builder.build();
+    assert!(bvh.len() == 65);
+
+    // the nearest hit, the triangle, then the first box behind it
+    assert!(bvh.raycast(&along_x(0.5), f32::INFINITY) == Some(Hit { primitive: mesh, distance: 4.0 }));
+    // above the triangle's hypotenuse at y 3.9, z 0.5
+    let hit = bvh.raycast(&along_x(3.9), f32::INFINITY);
+    assert!(hit.is_none());
+    let SceneShape::Triangles(triangles) = bvh.get_primitive_mut(mesh) else { unreachable!() };
+    *triangles.get_primitive_mut(0) = triangle.transformed(ModelMat::identity().translate(200.0, 0.0, 0.0));
+    triangles.refit();
+    bvh.refit();
+    let hit = bvh.raycast(&along_x(0.5), f32::INFINITY).unwrap();
+    assert!(hit.distance == 5.0 && matches!(bvh.get_primitive(hit.primitive), SceneShape::Bounds(aabb) if aabb.min.x == 0.0));
+    assert!(bvh.raycast(&along_x(0.5), 4.0).is_none());
+
+    // the moved triangle is the only thing past the boxes
+    assert!(!bvh.line_of_sight(Vector::new(130.0, 0.5, 0.5), Vector::new(210.0, 0.5, 0.5)));
+    assert!(bvh.line_of_sight(Vector::new(130.0, 0.5, 0.5), Vector::new(190.0, 0.5, 0.5)));
+    assert!(bvh.line_of_sight(Vector::new(-5.0, 5.0, 0.5), Vector::new(300.0, 5.0, 0.5)));
+
+    assert!(BvhBuilder::<Aabb>::new().build().raycast(&along_x(0.5), f32::INFINITY).is_none());
+}
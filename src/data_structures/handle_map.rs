@@ -0,0 +1,192 @@
+// Slot map with generational handles. Removing a value bumps its slot's generation,
+// so handles to it stop resolving instead of aliasing whatever reuses the slot
+
+use std::{fmt, hash::{Hash, Hasher}, marker::PhantomData};
+
+/// refers to a value of a `HandleMap<T>`, the generation tells apart values reusing a slot
+pub struct Handle<T> {
+    index: u16,
+    generation: u16,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    pub const fn from_raw_parts(index: u16, generation: u16) -> Self {
+        Self { index, generation, marker: PhantomData }
+    }
+
+    /// slot of the value, stable while the value lives so it can index gpu side arrays
+    pub const fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub const fn generation(&self) -> u16 {
+        self.generation
+    }
+}
+
+// derives would require the same traits of `T`
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.index, self.generation) == (other.index, other.generation)
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> PartialOrd for Handle<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Handle<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.index, self.generation).cmp(&(other.index, other.generation))
+    }
+}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.index, self.generation).hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}v{}", self.index, self.generation)
+    }
+}
+
+struct Slot<T> {
+    /// wraps around, a handle has to outlive 65536 reuses of its slot to alias
+    generation: u16,
+    value: Option<T>,
+}
+
+pub struct HandleMap<T> {
+    slots: Vec<Slot<T>>,
+    free_indices: Vec<u16>,
+    len: usize,
+}
+
+impl<T> Default for HandleMap<T> {
+    fn default() -> Self {
+        Self {
+            slots: vec![],
+            free_indices: vec![],
+            len: 0,
+        }
+    }
+}
+
+impl<T> HandleMap<T> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// highest index handed out so far plus one
+    pub fn slot_count(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// reuses the most recently freed slot
+    pub fn insert(&mut self, value: T) -> Handle<T> {
+        self.len += 1;
+        if let Some(index) = self.free_indices.pop() {
+            let slot = &mut self.slots[index as usize];
+            slot.value = Some(value);
+            return Handle::from_raw_parts(index, slot.generation);
+        }
+
+        assert!(self.slots.len() <= u16::MAX as usize, "Out of handle map slots");
+        self.slots.push(Slot { generation: 0, value: Some(value) });
+        Handle::from_raw_parts((self.slots.len() - 1) as u16, 0)
+    }
+
+    /// `None` when the handle is stale
+    pub fn remove(&mut self, handle: Handle<T>) -> Option<T> {
+        let slot = self.slots.get_mut(handle.index as usize)?;
+        if slot.generation != handle.generation {
+            return None;
+        }
+        let value = slot.value.take()?;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free_indices.push(handle.index);
+        self.len -= 1;
+        Some(value)
+    }
+
+    pub fn contains(&self, handle: Handle<T>) -> bool {
+        self.get(handle).is_some()
+    }
+
+    /// `None` when the handle is stale
+    pub fn get(&self, handle: Handle<T>) -> Option<&T> {
+        let slot = self.slots.get(handle.index as usize)?;
+        if slot.generation != handle.generation {
+            return None;
+        }
+        slot.value.as_ref()
+    }
+
+    /// `None` when the handle is stale
+    pub fn get_mut(&mut self, handle: Handle<T>) -> Option<&mut T> {
+        let slot = self.slots.get_mut(handle.index as usize)?;
+        if slot.generation != handle.generation {
+            return None;
+        }
+        slot.value.as_mut()
+    }
+
+    /// live values in slot order
+    pub fn iter(&self) -> impl Iterator<Item = (Handle<T>, &T)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let value = slot.value.as_ref()?;
+            Some((Handle::from_raw_parts(index as u16, slot.generation), value))
+        })
+    }
+
+    /// live values in slot order
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Handle<T>, &mut T)> {
+        self.slots.iter_mut().enumerate().filter_map(|(index, slot)| {
+            let value = slot.value.as_mut()?;
+            Some((Handle::from_raw_parts(index as u16, slot.generation), value))
+        })
+    }
+}
+
+#[test]
+fn test_handle_map() {
+    let mut map = HandleMap::default();
+    let a = map.insert('a');
+    let b = map.insert('b');
+    assert!(map.len() == 2 && map.get(a) == Some(&'a') && map.get(b) == Some(&'b'));
+
+    assert!(map.remove(a) == Some('a'));
+    assert!(map.remove(a).is_none());
+    assert!(map.get(a).is_none());
+
+    // reuses a's slot, yet the stale handle does not alias the new value
+    let c = map.insert('c');
+    assert!(c.index() == a.index() && c != a);
+    assert!(map.get(a).is_none() && map.get_mut(a).is_none() && !map.contains(a));
+    assert!(map.get(c) == Some(&'c'));
+
+    *map.get_mut(b).unwrap() = 'B';
+    assert!(map.iter().map(|(_, &value)| value).collect::<String>() == "cB");
+    assert!(map.len() == 2 && map.slot_count() == 2);
+}
@@ -0,0 +1,105 @@
+// Interned strings, stored once in a bump arena and referred to by `StrId`, which compares and
+// hashes like the u32 it is. For strings that are compared or looked up often, e.g. asset paths
+// and debug labels:
+//
+//     let id = strings.intern("images/crate.png");
+//     assert!(strings.intern("images/crate.png") == id);
+//     assert!(strings.resolve(id) == "images/crate.png");
+//
+// Strings live as long as the interner, only interning a string for the first time allocates
+
+use std::collections::HashMap;
+
+use crate::arena::FrameArena;
+
+/// first chunk of the arena, later ones double
+const ARENA_CAPACITY: usize = 4096;
+
+/// an interned string, only meaningful to the interner that made it
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct StrId(u32);
+
+impl StrId {
+    /// in order of interning
+    pub const fn index(&self) -> u32 {
+        self.0
+    }
+}
+
+pub struct StringInterner {
+    /// by id. Point into `arena` rather than being static, they mustn't outlive it
+    strings: Vec<&'static str>,
+    ids: HashMap<&'static str, StrId>,
+    /// never reset, so the strings stay where they were written
+    arena: FrameArena,
+}
+
+impl Default for StringInterner {
+    fn default() -> Self {
+        Self {
+            strings: vec![],
+            ids: HashMap::new(),
+            arena: FrameArena::new(ARENA_CAPACITY),
+        }
+    }
+}
+
+impl StringInterner {
+    /// the id of `string`, copying it into the arena the first time
+    pub fn intern(&mut self, string: &str) -> StrId {
+        if let Some(&id) = self.ids.get(string) {
+            return id;
+        }
+
+        let bytes = self.arena.alloc_slice::<u8>(string.len());
+        bytes.copy_from_slice(string.as_bytes());
+        // the arena's chunks don't move or get freed before it's dropped
+        let interned: &'static str = unsafe { &*(std::str::from_utf8_unchecked(bytes) as *const str) };
+
+        let id = StrId(self.strings.len() as u32);
+        self.strings.push(interned);
+        self.ids.insert(interned, id);
+        id
+    }
+
+    /// the id of `string` when it was interned, without interning it
+    pub fn get(&self, string: &str) -> Option<StrId> {
+        self.ids.get(string).copied()
+    }
+
+    pub fn resolve(&self, id: StrId) -> &str {
+        self.strings[id.0 as usize]
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+
+    /// bytes of every interned string
+    pub fn interned_bytes(&self) -> usize {
+        self.arena.allocated_bytes()
+    }
+}
+
+#[test]
+fn test_string_interner() {
+    let mut strings = StringInterner::default();
+    let a = strings.intern("images/a.png");
+    let b = strings.intern("images/b.png");
+    let empty = strings.intern("");
+    assert!(a != b && strings.intern("images/a.png") == a && strings.intern(String::from("images/b.png").as_str()) == b);
+    assert!(strings.resolve(a) == "images/a.png" && strings.resolve(b) == "images/b.png" && strings.resolve(empty).is_empty());
+    assert!(strings.get("images/a.png") == Some(a) && strings.get("images/c.png").is_none());
+    assert!(strings.len() == 3 && strings.interned_bytes() == 24);
+
+    // strings interned before the arena grew stay where they were
+    let a_ptr = strings.resolve(a).as_ptr();
+    let long = "x".repeat(2 * ARENA_CAPACITY);
+    let long_id = strings.intern(&long);
+    assert!(strings.resolve(long_id) == long && strings.resolve(a).as_ptr() == a_ptr);
+    assert!(strings.resolve(a) == "images/a.png");
+}
@@ -0,0 +1,139 @@
+// Switching the window between windowed, borderless fullscreen and exclusive fullscreen.
+// Winit resizes the window on every switch, its resize events renew the swapchain like any other resize.
+// The windowed size and position are remembered on leaving windowed mode and restored on return.
+// `Display` is the window's size and dpi scale, which changes on moving to another monitor
+
+use serde::Deserialize;
+use winit::{
+    dpi::{LogicalSize, PhysicalPosition, PhysicalSize},
+    monitor::VideoMode,
+    window::{Fullscreen, Window},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Deserialize)]
+pub enum DisplayMode {
+    #[default]
+    Windowed,
+    /// covers the monitor at its current video mode
+    Borderless,
+    /// takes the monitor over at its largest video mode, falls back to borderless without one
+    Exclusive,
+}
+
+impl DisplayMode {
+    /// windowed to `fullscreen_mode` and any fullscreen back to windowed
+    pub fn toggled(self, fullscreen_mode: DisplayMode) -> DisplayMode {
+        match self {
+            DisplayMode::Windowed => fullscreen_mode,
+            DisplayMode::Borderless | DisplayMode::Exclusive => DisplayMode::Windowed,
+        }
+    }
+}
+
+/// The window's inner size in pixels and how many pixels a logical pixel covers,
+/// kept up to date by the engine's window events
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Display {
+    pub physical_size: PhysicalSize<u32>,
+    pub scale_factor: f64,
+}
+
+impl Display {
+    pub fn new(window: &Window) -> Self {
+        Self { physical_size: window.inner_size(), scale_factor: window.scale_factor() }
+    }
+
+    /// what ui is laid out in
+    pub fn logical_size(&self) -> LogicalSize<f32> {
+        self.physical_size.to_logical(self.scale_factor)
+    }
+
+    /// logical pixels to physical pixels
+    pub fn to_physical(&self, logical: f32) -> f32 {
+        logical * self.scale_factor as f32
+    }
+}
+
+/// largest then fastest, then the deepest colors
+fn best_video_mode(video_modes: impl Iterator<Item = VideoMode>) -> Option<VideoMode> {
+    video_modes.max_by_key(|video_mode| {
+        let size = video_mode.size();
+        (size.width * size.height, video_mode.refresh_rate_millihertz(), video_mode.bit_depth())
+    })
+}
+
+/// Tracks the window's display mode, switch it only through `set_mode`
+pub struct DisplayState {
+    mode: DisplayMode,
+    windowed_size: PhysicalSize<u32>,
+    /// None where the platform doesn't report window positions
+    windowed_position: Option<PhysicalPosition<i32>>,
+}
+
+impl DisplayState {
+    /// for a window that was just created windowed
+    pub fn new(window: &Window) -> Self {
+        Self {
+            mode: DisplayMode::Windowed,
+            windowed_size: window.inner_size(),
+            windowed_position: window.outer_position().ok(),
+        }
+    }
+
+    pub fn get_mode(&self) -> DisplayMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, window: &Window, mode: DisplayMode) {
+        if mode == self.mode {
+            return;
+        }
+        if self.mode == DisplayMode::Windowed {
+            self.windowed_size = window.inner_size();
+            self.windowed_position = window.outer_position().ok();
+        }
+
+        let fullscreen = match mode {
+            DisplayMode::Windowed => None,
+            DisplayMode::Borderless => Some(Fullscreen::Borderless(window.current_monitor())),
+            DisplayMode::Exclusive => {
+                let video_mode = window
+                    .current_monitor()
+                    .and_then(|monitor| best_video_mode(monitor.video_modes()));
+                match video_mode {
+                    Some(video_mode) => Some(Fullscreen::Exclusive(video_mode)),
+                    None => {
+                        log::warn!("No video mode for exclusive fullscreen, going borderless");
+                        Some(Fullscreen::Borderless(window.current_monitor()))
+                    }
+                }
+            }
+        };
+        window.set_fullscreen(fullscreen);
+
+        if mode == DisplayMode::Windowed {
+            window.set_inner_size(self.windowed_size);
+            if let Some(position) = self.windowed_position {
+                window.set_outer_position(position);
+            }
+        }
+        log::info!("Display mode: {:?}", mode);
+        self.mode = mode;
+    }
+
+    pub fn toggle(&mut self, window: &Window, fullscreen_mode: DisplayMode) {
+        self.set_mode(window, self.mode.toggled(fullscreen_mode));
+    }
+}
+
+#[test]
+fn test_toggle_display_mode() {
+    assert!(DisplayMode::Windowed.toggled(DisplayMode::Exclusive) == DisplayMode::Exclusive);
+    assert!(DisplayMode::Borderless.toggled(DisplayMode::Exclusive) == DisplayMode::Windowed);
+    assert!(DisplayMode::Exclusive.toggled(DisplayMode::Borderless) == DisplayMode::Windowed);
+
+    // a 4k monitor at 150%
+    let display = Display { physical_size: PhysicalSize::new(3840, 2160), scale_factor: 1.5 };
+    assert!(display.logical_size() == LogicalSize::new(2560.0, 1440.0));
+    assert!(display.to_physical(10.0) == 15.0);
+}
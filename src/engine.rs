@@ -0,0 +1,469 @@
+// Window, event loop and frame loop shared by every application built on the engine.
+// An application implements `App` and hands it to `Engine::run`, which never returns:
+//
+//     struct Game;
+//
+//     impl App for Game {
+//         fn init(_engine: &mut Engine) -> Self { Game }
+//         fn update(&mut self, engine: &mut Engine, dt: f32) {
+//             engine.renderer.weather.update(dt);
+//         }
+//     }
+//
+//     Engine::run::<Game>("Game", EngineConfig::load(CONFIG_PATH), CommandLine::default());
+//
+// Every frame the engine reloads the config and changed assets, toggles fullscreen and the cursor,
+// moves the camera while in game, then calls the app before drawing.
+// Input can be recorded and played back instead of the live input, see replay.rs.
+// While the window is minimized or hidden frames aren't drawn, see suspend.rs
+
+use std::time::{Duration, Instant};
+
+use ash::vk::Extent2D;
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::{DeviceEvent, Event, VirtualKeyCode, WindowEvent},
+    event_loop::{ControlFlow, EventLoop},
+    window::{CursorGrabMode, WindowBuilder},
+};
+
+use crate::{
+    camera::controller::CameraInput,
+    cli::CommandLine,
+    config::{ConfigWatcher, EngineConfig, CONFIG_PATH},
+    display::{Display, DisplayMode, DisplayState},
+    events::{AssetReloaded, EventBus, KeyAction, Resumed, ScaleFactorChanged, Suspended, WindowResized},
+    frame_pacing::FrameStats,
+    input::InputState,
+    logging,
+    renderer::{debug_view::DebugView, VkApp},
+    replay::{InputEvent, InputPlayer, InputRecorder, InputRecording, Replay},
+    suspend::{SuspendTracker, SuspendTransition},
+};
+
+/// how far in front of the camera the orbited point is when switching to orbiting
+const ORBIT_DISTANCE: f32 = 10.0;
+/// frames the title bar's frame time statistics cover
+const FRAME_STATS_WINDOW: usize = 300;
+/// seconds between title bar updates
+const TITLE_UPDATE_INTERVAL: f32 = 0.5;
+/// seconds between fixed updates by default
+pub const FIXED_TIMESTEP: f32 = 1.0 / 60.0;
+/// fixed updates run per frame at most, a long frame drops the rest instead of stalling the next ones
+const MAX_FIXED_UPDATES_PER_FRAME: u32 = 8;
+
+/// Callbacks of an application, all but `init` default to doing nothing.
+/// Each frame runs `on_event` for the frame's window events, then `fixed_update`
+/// as often as fixed timesteps passed, then `update` and `draw_ui`
+pub trait App: 'static {
+    /// once the renderer is up, before the first frame
+    fn init(engine: &mut Engine) -> Self where Self: Sized;
+
+    /// once per frame, `dt` is the previous frame's duration in seconds
+    fn update(&mut self, _engine: &mut Engine, _dt: f32) {}
+
+    /// `dt` is always `Engine::fixed_timestep`, e.g. for physics
+    fn fixed_update(&mut self, _engine: &mut Engine, _dt: f32) {}
+
+    /// after the engine handled the event
+    fn on_event(&mut self, _engine: &mut Engine, _event: &WindowEvent) {}
+
+    /// last before the frame is drawn, e.g. to submit sprites and debug lines
+    fn draw_ui(&mut self, _engine: &mut Engine) {}
+
+    /// when the window is closing, before the renderer is dropped, e.g. to release assets
+    fn on_exit(&mut self, _engine: &mut Engine) {}
+}
+
+pub struct Engine {
+    pub renderer: VkApp,
+    /// replaced when the config file changes
+    pub config: EngineConfig,
+    /// overrides `config`, also after reloads
+    pub command_line: CommandLine,
+    /// the engine publishes window, key and asset events, apps may add their own
+    pub events: EventBus,
+    config_watcher: ConfigWatcher,
+    display: DisplayState,
+    pub fixed_timestep: f32,
+    /// seconds not yet covered by fixed updates
+    fixed_time_accumulator: f32,
+    frame_stats: FrameStats,
+    /// shown in the title bar before the frame statistics
+    title: String,
+    replay: Replay,
+    /// closes the window after the current frame
+    exit_requested: bool,
+    suspend: SuspendTracker,
+}
+
+impl Engine {
+    /// opens the window and runs `A` until it is closed
+    pub fn run<A: App>(title: &str, mut config: EngineConfig, command_line: CommandLine) -> ! {
+        command_line.apply(&mut config);
+        logging::init();
+        logging::logger().configure(&config.logging);
+        let event_loop = EventLoop::new();
+        let window = WindowBuilder::new()
+            .with_title(title)
+            .with_inner_size(PhysicalSize {
+                width: config.window.width,
+                height: config.window.height,
+            })
+            .build(&event_loop)
+            .unwrap();
+
+        let mut display = DisplayState::new(&window);
+        display.set_mode(&window, config.window.display_mode);
+
+        let mut engine = Engine {
+            renderer: VkApp::new(window, &config),
+            display,
+            events: EventBus::default(),
+            config,
+            command_line,
+            config_watcher: ConfigWatcher::new(CONFIG_PATH),
+            fixed_timestep: FIXED_TIMESTEP,
+            fixed_time_accumulator: 0.0,
+            frame_stats: FrameStats::new(FRAME_STATS_WINDOW),
+            title: title.to_owned(),
+            replay: Replay::Live,
+            exit_requested: false,
+            suspend: SuspendTracker::default(),
+        };
+        if let Some(path) = &engine.config.replay.play {
+            engine.play_recording(InputRecording::load(path));
+        } else if engine.config.replay.record.is_some() {
+            engine.start_recording();
+        }
+        let mut app = A::init(&mut engine);
+
+        let mut end_frame_time = engine.renderer.start_instant.elapsed().as_secs_f32();
+        let mut title_update_time = 0.0;
+
+        event_loop.run(move |system_event, _, control_flow| {
+            match system_event {
+                Event::MainEventsCleared => {
+                    //timing
+                    let start_frame_time = end_frame_time;
+                    end_frame_time = engine.renderer.start_instant.elapsed().as_secs_f32();
+                    let dt = end_frame_time - start_frame_time;
+
+                    engine.update_suspension(end_frame_time);
+                    // exiting can't be undone
+                    if *control_flow != ControlFlow::Exit {
+                        *control_flow = if engine.suspend.is_suspended() {
+                            // wakes up now and then instead of spinning
+                            ControlFlow::WaitUntil(Instant::now() + Duration::from_secs_f32(engine.config.suspend.frame_interval))
+                        } else {
+                            ControlFlow::Poll
+                        };
+                    }
+
+                    // time paused passes between two frames, not within one
+                    if !(engine.suspend.is_suspended() && engine.config.suspend.pause_simulation) {
+                        engine.frame(&mut app, dt);
+                    }
+                    if engine.exit_requested {
+                        engine.close(&mut app);
+                        *control_flow = ControlFlow::Exit;
+                    }
+
+                    if end_frame_time - title_update_time >= TITLE_UPDATE_INTERVAL {
+                        title_update_time = end_frame_time;
+                        engine.update_title();
+                    }
+                }
+                Event::DeviceEvent { event: DeviceEvent::MouseMotion { delta, .. }, .. } => {
+                    engine.handle_input_event(InputEvent::MouseMotion([delta.0 as f32, delta.1 as f32]));
+                }
+                Event::WindowEvent { event, .. } => {
+                    if matches!(event, WindowEvent::CloseRequested) {
+                        engine.close(&mut app);
+                        *control_flow = ControlFlow::Exit;
+                    }
+                    engine.handle_window_event(&event);
+                    // the app sees the played back input through the input state only
+                    if !(engine.is_playing_back() && InputEvent::from_window_event(&event).is_some()) {
+                        app.on_event(&mut engine, &event);
+                    }
+                }
+                _ => {}
+            }
+        })
+    }
+
+    fn frame<A: App>(&mut self, app: &mut A, dt: f32) {
+        let dt = self.replay_frame(dt);
+        // what was published since the last frame, including this frame's window events
+        self.events.update();
+        self.reload_config();
+        for path in self.renderer.reload_changed_assets() {
+            self.events.publish(AssetReloaded { path });
+        }
+        self.handle_input(dt);
+
+        self.fixed_time_accumulator += dt;
+        let mut fixed_update_count = 0;
+        while self.fixed_time_accumulator >= self.fixed_timestep {
+            self.fixed_time_accumulator -= self.fixed_timestep;
+            fixed_update_count += 1;
+            if fixed_update_count > MAX_FIXED_UPDATES_PER_FRAME {
+                self.fixed_time_accumulator = 0.0;
+                break;
+            }
+            app.fixed_update(self, self.fixed_timestep);
+        }
+
+        app.update(self, dt);
+        app.draw_ui(self);
+
+        self.renderer.input_state.end_frame();
+
+        if !self.suspend.is_suspended() {
+            self.renderer.draw_frame();
+            self.renderer.frame_limiter.wait();
+        }
+
+        self.frame_stats.push(dt);
+    }
+
+    fn reload_config(&mut self) {
+        if let Some(mut config) = self.config_watcher.poll() {
+            self.command_line.apply(&mut config);
+            let (window, previous_window) = (config.window, self.config.window);
+            if (window.width, window.height, window.display_mode) != (previous_window.width, previous_window.height, previous_window.display_mode) {
+                log::info!("Window size and display mode changes apply on restart");
+            }
+            if config.validation != self.config.validation {
+                log::info!("Validation changes apply on restart");
+            }
+            if config.replay != self.config.replay {
+                log::info!("Replay changes apply on restart");
+            }
+            config.apply(&mut self.renderer);
+            self.config = config;
+        }
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        self.suspend.is_suspended()
+    }
+
+    /// `time` in seconds since startup
+    fn update_suspension(&mut self, time: f32) {
+        let suspended_for = self.suspend.suspended_for(time);
+        match self.suspend.update(time, self.config.suspend.release_after) {
+            Some(SuspendTransition::Suspended) => {
+                log::info!("Suspended");
+                self.events.publish(Suspended);
+            }
+            Some(SuspendTransition::Release) => self.renderer.release_swapchain(),
+            Some(SuspendTransition::Resumed) => {
+                log::info!("Resumed after {:.1}s", suspended_for);
+                self.events.publish(Resumed { suspended_for });
+            }
+            None => {}
+        }
+    }
+
+    /// after the current frame, the window closes the same way as when the user closes it
+    pub fn request_exit(&mut self) {
+        self.exit_requested = true;
+    }
+
+    /// lets `app` release what it holds and writes the input recording if the config asks for one
+    fn close<A: App>(&mut self, app: &mut A) {
+        app.on_exit(self);
+        if let (Some(recording), Some(path)) = (self.stop_recording(), &self.config.replay.record) {
+            recording.save(path);
+        }
+    }
+
+    /// replaces a recording in progress or a playback
+    pub fn start_recording(&mut self) {
+        log::info!("Recording input");
+        self.replay = Replay::Recording(InputRecorder::default());
+    }
+
+    /// `None` when not recording
+    pub fn stop_recording(&mut self) -> Option<InputRecording> {
+        match std::mem::take(&mut self.replay) {
+            Replay::Recording(recorder) => Some(recorder.finish()),
+            replay => {
+                self.replay = replay;
+                None
+            }
+        }
+    }
+
+    /// live input is ignored until the recording ends, which starts with nothing pressed
+    pub fn play_recording(&mut self, recording: InputRecording) {
+        log::info!("Playing back {} frames of input", recording.frames.len());
+        self.renderer.input_state = InputState::new();
+        self.replay = Replay::Playing(InputPlayer::new(recording));
+    }
+
+    pub fn is_playing_back(&self) -> bool {
+        matches!(self.replay, Replay::Playing(_))
+    }
+
+    /// records or plays back the frame's input, returns the frame's dt
+    fn replay_frame(&mut self, dt: f32) -> f32 {
+        match &mut self.replay {
+            Replay::Live => dt,
+            Replay::Recording(recorder) => {
+                recorder.record_frame(dt);
+                dt
+            }
+            Replay::Playing(player) => match player.next_frame() {
+                Some(frame) => {
+                    let (recorded_dt, events) = (frame.dt, frame.events.clone());
+                    for event in events {
+                        self.apply_input_event(event);
+                    }
+                    recorded_dt
+                }
+                None => {
+                    log::info!("Playback finished");
+                    self.replay = Replay::Live;
+                    if self.config.replay.exit_after_playback {
+                        self.request_exit();
+                    }
+                    dt
+                }
+            },
+        }
+    }
+
+    pub fn get_display_mode(&self) -> DisplayMode {
+        self.display.get_mode()
+    }
+
+    pub fn set_display_mode(&mut self, mode: DisplayMode) {
+        self.display.set_mode(&self.renderer.window, mode);
+    }
+
+    /// fullscreen and cursor toggling and camera controls
+    fn handle_input(&mut self, dt: f32) {
+        let app = &mut self.renderer;
+        let key_bindings = &self.config.key_bindings;
+
+        let alt_pressed = app.input_state.is_key_pressed(VirtualKeyCode::LAlt)
+            || app.input_state.is_key_pressed(VirtualKeyCode::RAlt);
+        if alt_pressed && app.input_state.just_released(key_bindings.toggle_fullscreen) {
+            self.display.toggle(&app.window, self.config.window.fullscreen_mode);
+        }
+
+        if app.input_state.just_released(key_bindings.toggle_cursor) {
+            app.in_game = !app.in_game;
+            app.window.set_cursor_visible(!app.in_game);
+            //NOTE: CursorGrabMode::Locked Not implemented by winit
+            app.window.set_cursor_grab(
+                if app.in_game {
+                    CursorGrabMode::Confined
+                } else {
+                    CursorGrabMode::None
+                }
+            ).unwrap();
+
+            if !app.in_game {
+                app.window.set_cursor_position(
+                    PhysicalPosition {
+                        x: app.swapchain_extent.width / 2,
+                        y: app.swapchain_extent.height / 2,
+                    }
+                ).unwrap();
+            }
+        }
+
+        if app.input_state.just_released(key_bindings.cycle_debug_view) {
+            let debug_view = &mut app.debug_view_renderer;
+            debug_view.view = debug_view.view.next();
+            if debug_view.view != DebugView::Lit && debug_view.get_pipeline().is_none() {
+                log::warn!("Debug views need the forward render path");
+            }
+            log::info!("Debug view: {:?}", debug_view.view);
+        }
+
+        if app.input_state.just_released(key_bindings.toggle_ssr) {
+            app.ssr.enabled = !app.ssr.enabled;
+            log::info!("Screen space reflections: {}", if app.ssr.enabled { "on" } else { "off" });
+        }
+
+        if !app.in_game {
+            return;
+        }
+
+        if app.input_state.just_released(key_bindings.cycle_camera) {
+            app.camera_controller.cycle_mode(&app.camera, ORBIT_DISTANCE);
+            log::info!("Camera: {:?}", app.camera_controller.mode);
+        }
+
+        let camera_input = CameraInput::from_input(&app.input_state, key_bindings);
+        app.camera_controller.update(&mut app.camera, &camera_input, dt);
+    }
+
+    fn handle_window_event(&mut self, event: &WindowEvent) {
+        if let Some(event) = InputEvent::from_window_event(event) {
+            self.handle_input_event(event);
+            return;
+        }
+        match *event {
+            WindowEvent::Resized(PhysicalSize { width, height }) => {
+                self.renderer.display.physical_size = PhysicalSize { width, height };
+                self.renderer.request_resize(Extent2D { width, height });
+                self.suspend.set_minimized(width == 0 || height == 0);
+                self.events.publish(WindowResized { width, height });
+            }
+            // moved to a monitor with another dpi or its scale changed, ui follows on the next frame
+            WindowEvent::ScaleFactorChanged { scale_factor, ref new_inner_size } => {
+                let PhysicalSize { width, height } = **new_inner_size;
+                self.renderer.display = Display { physical_size: **new_inner_size, scale_factor };
+                self.renderer.request_resize(Extent2D { width, height });
+                self.events.publish(ScaleFactorChanged { scale_factor });
+            }
+            WindowEvent::Occluded(occluded) => self.suspend.set_occluded(occluded),
+            _ => {}
+        }
+    }
+
+    /// live input, ignored while playing back
+    fn handle_input_event(&mut self, event: InputEvent) {
+        match &mut self.replay {
+            Replay::Live => {}
+            Replay::Recording(recorder) => recorder.record(event),
+            Replay::Playing(_) => return,
+        }
+        self.apply_input_event(event);
+    }
+
+    fn apply_input_event(&mut self, event: InputEvent) {
+        if let InputEvent::Key { key, pressed } = event {
+            if self.renderer.input_state.is_key_pressed(key) != pressed {
+                self.events.publish(KeyAction { key, pressed });
+            }
+        }
+        event.apply(&mut self.renderer.input_state);
+    }
+
+    fn update_title(&self) {
+        let mut title = format!(
+            "{} - fps: {:.0}, 1% low: {:.0}, {:.2} ms",
+            self.title,
+            self.frame_stats.average_fps(),
+            self.frame_stats.one_percent_low_fps(),
+            self.frame_stats.average_frame_time() * 1000.0,
+        );
+        let profiler = &self.renderer.pipeline_statistics_profiler;
+        if let (true, Some(statistics)) = (profiler.enabled, profiler.frame_statistics) {
+            let scene_extent = self.renderer.get_scene_extent();
+            title += &format!(
+                ", {} triangles, {:.1}x overdraw",
+                statistics.input_assembly_primitives,
+                statistics.overdraw(scene_extent.width * scene_extent.height),
+            );
+        }
+        self.renderer.window.set_title(&title);
+    }
+}
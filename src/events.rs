@@ -0,0 +1,147 @@
+// Typed event queues, so subsystems talk without holding references to each other.
+// Events published during a frame become readable after the next `update`, which the engine
+// calls at the start of every frame, and stay readable for that frame only:
+//
+//     events.publish(WindowResized { width, height });
+//     ...
+//     for resized in events.read::<WindowResized>() {
+//         log::info!("{}x{}", resized.width, resized.height);
+//     }
+
+use std::{any::{Any, TypeId}, collections::HashMap};
+
+use winit::event::VirtualKeyCode;
+
+/// the window's new inner size in pixels
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct WindowResized {
+    pub width: u32,
+    pub height: u32,
+}
+
+/// the window's dpi scale changed, e.g. on moving to another monitor, ui is laid out again
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ScaleFactorChanged {
+    pub scale_factor: f64,
+}
+
+/// a loaded asset's file changed and it was loaded again
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AssetReloaded {
+    pub path: String,
+}
+
+/// two bodies began touching, by the physics' body indices
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CollisionStarted {
+    pub a: usize,
+    pub b: usize,
+}
+
+/// a key was pressed or released, repeats aren't published
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct KeyAction {
+    pub key: VirtualKeyCode,
+    pub pressed: bool,
+}
+
+/// the window was minimized or hidden, frames stop being drawn
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Suspended;
+
+/// the window is visible again after a `Suspended`
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Resumed {
+    /// how long the window was suspended, in seconds
+    pub suspended_for: f32,
+}
+
+/// what the bus needs of a queue without knowing its event type
+trait Queue {
+    fn update(&mut self);
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+struct EventQueue<E> {
+    /// published before the last update
+    readable: Vec<E>,
+    /// published since the last update
+    pending: Vec<E>,
+}
+
+impl<E: 'static> Queue for EventQueue<E> {
+    fn update(&mut self) {
+        std::mem::swap(&mut self.readable, &mut self.pending);
+        self.pending.clear();
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// One queue per event type, created on first publish
+#[derive(Default)]
+pub struct EventBus {
+    queues: HashMap<TypeId, Box<dyn Queue>>,
+}
+
+impl EventBus {
+    pub fn publish<E: 'static>(&mut self, event: E) {
+        self.queues
+            .entry(TypeId::of::<E>())
+            .or_insert_with(|| Box::new(EventQueue::<E> { readable: vec![], pending: vec![] }))
+            .as_any_mut()
+            .downcast_mut::<EventQueue<E>>()
+            .unwrap()
+            .pending
+            .push(event);
+    }
+
+    /// published before the last update, in publishing order
+    pub fn read<E: 'static>(&self) -> &[E] {
+        match self.queues.get(&TypeId::of::<E>()) {
+            Some(queue) => &queue.as_any().downcast_ref::<EventQueue<E>>().unwrap().readable,
+            None => &[],
+        }
+    }
+
+    /// drops the readable events and makes the pending ones readable
+    pub fn update(&mut self) {
+        for queue in self.queues.values_mut() {
+            queue.update();
+        }
+    }
+}
+
+#[test]
+fn test_event_bus() {
+    let mut events = EventBus::default();
+    assert!(events.read::<WindowResized>().is_empty());
+
+    events.publish(WindowResized { width: 800, height: 600 });
+    events.publish(KeyAction { key: VirtualKeyCode::A, pressed: true });
+    events.publish(WindowResized { width: 1024, height: 768 });
+    // not readable before the update
+    assert!(events.read::<WindowResized>().is_empty());
+
+    events.update();
+    let resized = events.read::<WindowResized>();
+    assert!(resized.len() == 2 && resized[1] == WindowResized { width: 1024, height: 768 });
+    assert!(events.read::<KeyAction>() == [KeyAction { key: VirtualKeyCode::A, pressed: true }]);
+    assert!(events.read::<CollisionStarted>().is_empty());
+
+    // published while reading goes to the next frame
+    events.publish(AssetReloaded { path: "textures/grass.png".to_owned() });
+    events.update();
+    assert!(events.read::<WindowResized>().is_empty());
+    assert!(events.read::<AssetReloaded>()[0].path == "textures/grass.png");
+
+    events.update();
+    assert!(events.read::<AssetReloaded>().is_empty());
+}
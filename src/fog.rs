@@ -0,0 +1,104 @@
+// Fog blending distant surfaces into a flat color. Forward shading applies it per fragment, the
+// deferred lighting subpass applies it from the depth buffer, both through shaders/fog.glsl which
+// mirrors `Fog::amount`. Following the sky's clear color hides where the geometry ends:
+//
+//     app.fog = Fog { mode: FogMode::Exponential, density: 0.02, height_falloff: 0.1, ..Default::default() };
+//
+// Heights are altitudes, world y points down
+
+use serde::Deserialize;
+
+/// values must match shaders/fog.glsl
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Deserialize)]
+pub enum FogMode {
+    #[default]
+    None = 0,
+    /// from none at `start` to full at `end`
+    Linear = 1,
+    /// thickens with distance by `density`, and below `height` by `height_falloff`
+    Exponential = 2,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Fog {
+    pub mode: FogMode,
+    /// encoded like the clear color
+    pub color: [f32; 3],
+    /// uses the clear color instead of `color`
+    pub follow_sky: bool,
+    /// per world unit, exponential only
+    pub density: f32,
+    /// distances of linear fog
+    pub start: f32,
+    pub end: f32,
+    /// altitude where exponential fog has `density`
+    pub height: f32,
+    /// how fast exponential fog thins with altitude, per world unit, 0 for the same everywhere
+    pub height_falloff: f32,
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Self {
+            mode: FogMode::None,
+            color: [0.6, 0.65, 0.7],
+            follow_sky: true,
+            density: 0.01,
+            start: 50.0,
+            end: 300.0,
+            height: 0.0,
+            height_falloff: 0.0,
+        }
+    }
+}
+
+impl Fog {
+    /// mode, color with density in w and start, end, height and height falloff, as the shaders expect them
+    pub fn to_shader_vectors(&self, clear_color: [f32; 4]) -> (u32, [f32; 4], [f32; 4]) {
+        let [r, g, b] = if self.follow_sky {
+            [clear_color[0], clear_color[1], clear_color[2]]
+        } else {
+            self.color
+        };
+        (
+            self.mode as u32,
+            [r, g, b, self.density],
+            [self.start, self.end, self.height, self.height_falloff],
+        )
+    }
+
+    /// 0 clear to 1 fully fogged, of a point `distance` from a camera, at altitudes `point_height` and `camera_height`
+    pub fn amount(&self, distance: f32, camera_height: f32, point_height: f32) -> f32 {
+        match self.mode {
+            FogMode::None => 0.0,
+            FogMode::Linear => ((distance - self.start) / (self.end - self.start).max(1e-4)).clamp(0.0, 1.0),
+            FogMode::Exponential => {
+                // density integrated along the ray through fog thinning exponentially with altitude
+                let camera_density = self.density * (-self.height_falloff * (camera_height - self.height)).exp();
+                let falloff = self.height_falloff * (point_height - camera_height);
+                let along_ray = if falloff.abs() > 1e-4 { (1.0 - (-falloff).exp()) / falloff } else { 1.0 };
+                1.0 - (-camera_density * distance * along_ray).exp()
+            }
+        }
+    }
+}
+
+#[test]
+fn test_fog() {
+    let linear = Fog { mode: FogMode::Linear, start: 10.0, end: 20.0, ..Default::default() };
+    assert!(linear.amount(5.0, 0.0, 0.0) == 0.0 && linear.amount(15.0, 0.0, 0.0) == 0.5 && linear.amount(25.0, 0.0, 0.0) == 1.0);
+
+    let uniform = Fog { mode: FogMode::Exponential, density: 0.1, ..Default::default() };
+    assert!((uniform.amount(10.0, 0.0, 0.0) - (1.0 - (-1.0f32).exp())).abs() < 1e-6);
+    // a ray rising out of height fog crosses less of it than one staying low
+    let height = Fog { height_falloff: 0.5, ..uniform };
+    assert!(height.amount(10.0, 0.0, 5.0) < height.amount(10.0, 0.0, 0.0));
+    assert!(height.amount(10.0, 0.0, 0.0) < height.amount(10.0, 0.0, -5.0));
+    // level rays match the closed form within the epsilon
+    assert!((height.amount(10.0, 0.0, 1e-5) - uniform.amount(10.0, 0.0, 0.0)).abs() < 1e-4);
+
+    assert!(Fog::default().amount(1000.0, 0.0, 0.0) == 0.0);
+    let (mode, color, _) = Fog::default().to_shader_vectors([0.1, 0.2, 0.3, 1.0]);
+    assert!(mode == 0 && color == [0.1, 0.2, 0.3, 0.01]);
+}
@@ -0,0 +1,144 @@
+// Caps the frame rate when vsync doesn't and keeps frame time statistics.
+// The limiter sleeps most of the way to the next frame's deadline, then spins,
+// sleeping alone oversleeps by up to the scheduler's granularity
+
+use std::{collections::VecDeque, time::{Duration, Instant}};
+
+/// left to spin before a deadline, sleeps wake up this late at worst on most systems
+const SPIN_MARGIN: Duration = Duration::from_micros(1500);
+
+pub struct FrameLimiter {
+    /// 0 is uncapped
+    target_fps: f32,
+    deadline: Option<Instant>,
+}
+
+impl FrameLimiter {
+    pub fn new(target_fps: f32) -> Self {
+        Self {
+            target_fps: target_fps.max(0.0),
+            deadline: None,
+        }
+    }
+
+    pub fn get_target_fps(&self) -> f32 {
+        self.target_fps
+    }
+
+    /// 0 uncaps
+    pub fn set_target_fps(&mut self, target_fps: f32) {
+        let target_fps = target_fps.max(0.0);
+        if target_fps != self.target_fps {
+            self.target_fps = target_fps;
+            self.deadline = None;
+        }
+    }
+
+    fn frame_period(&self) -> Option<Duration> {
+        (self.target_fps > 0.0).then(|| Duration::from_secs_f32(1.0 / self.target_fps))
+    }
+
+    /// the deadline after `deadline`, a frame that missed its deadline by a whole period
+    /// starts a new schedule instead of rushing the following frames
+    fn next_deadline(deadline: Instant, period: Duration, now: Instant) -> Instant {
+        if now < deadline + period { deadline + period } else { now + period }
+    }
+
+    /// call once per frame, blocks until the frame's time is up
+    pub fn wait(&mut self) {
+        let Some(period) = self.frame_period() else {
+            return;
+        };
+        let now = Instant::now();
+        let Some(deadline) = self.deadline else {
+            // the first frame only starts the schedule
+            self.deadline = Some(now + period);
+            return;
+        };
+
+        if let Some(sleep) = deadline.checked_duration_since(now).and_then(|left| left.checked_sub(SPIN_MARGIN)) {
+            std::thread::sleep(sleep);
+        }
+        while Instant::now() < deadline {
+            std::hint::spin_loop();
+        }
+        self.deadline = Some(Self::next_deadline(deadline, period, Instant::now()));
+    }
+}
+
+/// frame times over a sliding window
+pub struct FrameStats {
+    /// seconds, oldest first
+    frame_times: VecDeque<f32>,
+    capacity: usize,
+}
+
+impl FrameStats {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0);
+        Self {
+            frame_times: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, frame_time: f32) {
+        if self.frame_times.len() == self.capacity {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(frame_time);
+    }
+
+    /// in seconds, 0 without frames
+    pub fn average_frame_time(&self) -> f32 {
+        if self.frame_times.is_empty() {
+            return 0.0;
+        }
+        self.frame_times.iter().sum::<f32>() / self.frame_times.len() as f32
+    }
+
+    /// 0 without frames
+    pub fn average_fps(&self) -> f32 {
+        let average_frame_time = self.average_frame_time();
+        if average_frame_time > 0.0 { 1.0 / average_frame_time } else { 0.0 }
+    }
+
+    /// fps of the slowest 1% of frames averaged, at least the slowest frame
+    pub fn one_percent_low_fps(&self) -> f32 {
+        let mut frame_times: Vec<f32> = self.frame_times.iter().copied().collect();
+        frame_times.sort_by(|a, b| b.total_cmp(a));
+        if frame_times.is_empty() {
+            return 0.0;
+        }
+        let count = (frame_times.len() / 100).max(1);
+        1.0 / (frame_times[..count].iter().sum::<f32>() / count as f32)
+    }
+}
+
+#[test]
+fn test_frame_pacing() {
+    let mut stats = FrameStats::new(200);
+    assert!(stats.average_fps() == 0.0 && stats.one_percent_low_fps() == 0.0);
+    // the oldest frames fall out of the window
+    stats.push(1.0);
+    for _ in 0..198 {
+        stats.push(0.01);
+    }
+    stats.push(0.05);
+    stats.push(0.05);
+    assert!((stats.average_fps() - 1.0 / 0.0104).abs() < 0.5);
+    assert!((stats.one_percent_low_fps() - 20.0).abs() < 1e-3);
+
+    let period = Duration::from_millis(10);
+    let start = Instant::now();
+    let deadline = FrameLimiter::next_deadline(start, period, start + Duration::from_millis(3));
+    assert!(deadline == start + period);
+    // too late to catch up
+    let late = start + Duration::from_millis(25);
+    assert!(FrameLimiter::next_deadline(start, period, late) == late + period);
+
+    let mut limiter = FrameLimiter::new(200.0);
+    limiter.wait();
+    limiter.wait();
+    assert!(start.elapsed() >= Duration::from_millis(5));
+}
@@ -1,354 +1,1103 @@
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
 pub struct Vertex {
     pub x: f32, pub y: f32, pub z: f32,
     
     pub u: f32, pub v: f32,
+
+    pub nx: f32, pub ny: f32, pub nz: f32,
+
+    /// `tw` is the handedness of the bitangent,
+    /// left as 0.0 to have the tangents generated by `create_geometry`
+    pub tx: f32, pub ty: f32, pub tz: f32, pub tw: f32,
+}
+
+/// vertex layout matching `Vertex`, used by the default pipeline
+pub const VERTEX_ATTRIBUTES: [crate::renderer::pipeline::Attribute; 4] = {
+    use crate::renderer::pipeline::Attribute::*;
+    [F32x3, F32x2, F32x3, F32x4]
+};
+
+/// `Vertex` quantized to half its size, for dense meshes, see `crate::quantize`
+#[repr(C)]
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct CompressedVertex {
+    /// half floats, w is 1
+    pub position: [u16; 4],
+    /// half floats
+    pub uv: [u16; 2],
+    /// octahedral encoded snorms
+    pub normal: [i16; 2],
+    /// snorms, w is the bitangent's handedness
+    pub tangent: [i16; 4],
+}
+
+impl CompressedVertex {
+    /// positions keep about 3 significant digits, so keep meshes near their origin.
+    /// Generate the tangents first, `create_geometry` can't for compressed vertices
+    pub fn from_vertex(vertex: &Vertex) -> Self {
+        use crate::quantize::{encode_normal, f32_to_f16, to_snorm16};
+
+        Self {
+            position: [f32_to_f16(vertex.x), f32_to_f16(vertex.y), f32_to_f16(vertex.z), f32_to_f16(1.0)],
+            uv: [f32_to_f16(vertex.u), f32_to_f16(vertex.v)],
+            normal: encode_normal(Vector::new(vertex.nx, vertex.ny, vertex.nz)),
+            tangent: [vertex.tx, vertex.ty, vertex.tz, vertex.tw].map(to_snorm16),
+        }
+    }
+}
+
+/// vertex layout matching `CompressedVertex`, the normal needs `octDecode` from shaders/quantize.glsl
+pub const COMPRESSED_VERTEX_ATTRIBUTES: [crate::renderer::pipeline::Attribute; 4] = {
+    use crate::renderer::pipeline::Attribute::*;
+    [F16x4, F16x2, Snorm16x2, Snorm16x4]
+};
+
+/// per instance model matrix, matching `ModelMat`
+pub const INSTANCE_ATTRIBUTES: [crate::renderer::pipeline::Attribute; 1] = {
+    use crate::renderer::pipeline::Attribute::*;
+    [F32x4x3]
+};
+
+/// Generates per vertex tangents from uv derivatives of the triangles,
+/// orthogonalized against the vertex normal.
+pub fn generate_tangents(vertices: &mut [Vertex], indices: &[Index]) {
+    let mut tangents = vec![[0.0_f32; 3]; vertices.len()];
+    let mut bitangents = vec![[0.0_f32; 3]; vertices.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let [i0, i1, i2] = [triangle[0] as usize, triangle[1] as usize, triangle[2] as usize];
+        let (v0, v1, v2) = (&vertices[i0], &vertices[i1], &vertices[i2]);
+
+        let e1 = [v1.x - v0.x, v1.y - v0.y, v1.z - v0.z];
+        let e2 = [v2.x - v0.x, v2.y - v0.y, v2.z - v0.z];
+        let (du1, dv1) = (v1.u - v0.u, v1.v - v0.v);
+        let (du2, dv2) = (v2.u - v0.u, v2.v - v0.v);
+
+        let det = du1 * dv2 - du2 * dv1;
+        if det.abs() < f32::EPSILON {
+            continue;
+        }
+        let r = 1.0 / det;
+
+        let tangent = [
+            (e1[0] * dv2 - e2[0] * dv1) * r,
+            (e1[1] * dv2 - e2[1] * dv1) * r,
+            (e1[2] * dv2 - e2[2] * dv1) * r,
+        ];
+        let bitangent = [
+            (e2[0] * du1 - e1[0] * du2) * r,
+            (e2[1] * du1 - e1[1] * du2) * r,
+            (e2[2] * du1 - e1[2] * du2) * r,
+        ];
+
+        for i in [i0, i1, i2] {
+            for c in 0..3 {
+                tangents[i][c] += tangent[c];
+                bitangents[i][c] += bitangent[c];
+            }
+        }
+    }
+
+    for (i, vertex) in vertices.iter_mut().enumerate() {
+        let n = [vertex.nx, vertex.ny, vertex.nz];
+        let t = tangents[i];
+
+        // Gram-Schmidt
+        let n_dot_t = n[0] * t[0] + n[1] * t[1] + n[2] * t[2];
+        let mut t = [t[0] - n[0] * n_dot_t, t[1] - n[1] * n_dot_t, t[2] - n[2] * n_dot_t];
+        let norm_sqr = t[0] * t[0] + t[1] * t[1] + t[2] * t[2];
+        if norm_sqr < f32::EPSILON {
+            // degenerate uvs, any vector orthogonal to the normal will do
+            t = if n[0].abs() < 0.9 { [0.0, -n[2], n[1]] } else { [n[2], 0.0, -n[0]] };
+        }
+        let norm = (t[0] * t[0] + t[1] * t[1] + t[2] * t[2]).sqrt();
+
+        let b = bitangents[i];
+        let n_cross_t = [
+            n[1] * t[2] - n[2] * t[1],
+            n[2] * t[0] - n[0] * t[2],
+            n[0] * t[1] - n[1] * t[0],
+        ];
+        let handedness = n_cross_t[0] * b[0] + n_cross_t[1] * b[1] + n_cross_t[2] * b[2];
+
+        vertex.tx = t[0] / norm;
+        vertex.ty = t[1] / norm;
+        vertex.tz = t[2] / norm;
+        vertex.tw = if handedness < 0.0 { -1.0 } else { 1.0 };
+    }
 }
 
+use std::borrow::Cow;
 use std::rc::Rc;
 use core::mem::size_of;
-use crate::{allocator, utils};
+use crate::{allocator, data_structures::{bvh::Triangle, handle_map::{Handle, HandleMap}}, math::{Aabb, ModelMat, Vector}};
+use crate::renderer::{buffer::Buffer, deletion_queue::DeletionQueue, device::DeviceFeatures, MAX_FRAMES_IN_FLIGHT};
 
 use ash::vk;
 
-type GeometryId = u16;
-type Index = u32;
+/// stale ids of destroyed geometry are detected instead of drawing whatever reuses the slot
+pub type GeometryId = Handle<Geometry>;
+pub type Index = u32;
 
 // TODO: configurable
-const VK_INDEX_TYPE: vk::IndexType = vk::IndexType::UINT32;
+pub const VK_INDEX_TYPE: vk::IndexType = vk::IndexType::UINT32;
+
+/// per frame in flight
+pub const MAX_INDIRECT_COMMAND_COUNT: usize = 0x1000;
+
+/// levels of detail a mesh can have at most
+pub const MAX_LOD_LEVELS: usize = 4;
+/// fraction of a switch distance the camera must move past it before the level switches,
+/// so objects sitting near the distance don't pop back and forth
+pub const LOD_HYSTERESIS: f32 = 0.1;
+
+pub type LodMeshId = Handle<LodMesh>;
+
+#[derive(Clone, Copy, Debug)]
+pub struct LodLevel {
+    pub geometry: GeometryId,
+    /// camera distance up to which this level is drawn, from the object's bounding sphere center
+    pub switch_distance: f32,
+}
+
+/// One logical mesh drawn with coarser geometries further away, finest first.
+/// Past the last level's switch distance it isn't drawn
+pub struct LodMesh {
+    levels: Vec<LodLevel>,
+}
+
+impl LodMesh {
+    pub fn get_levels(&self) -> &[LodLevel] {
+        &self.levels
+    }
+}
+
+/// the level to draw at `distance`, None past the last switch distance.
+/// `previous` is the level drawn last frame, it's kept until `distance` is `hysteresis` past its range
+pub fn select_lod_level(switch_distances: &[f32], distance: f32, previous: Option<usize>, hysteresis: f32) -> Option<usize> {
+    let level = switch_distances.iter().position(|&switch_distance| distance <= switch_distance);
+    let Some(previous) = previous else {
+        return level;
+    };
+
+    let near = if previous == 0 { 0.0 } else { switch_distances[previous - 1] * (1.0 - hysteresis) };
+    let far = switch_distances[previous] * (1.0 + hysteresis);
+    if (near..=far).contains(&distance) {
+        Some(previous)
+    } else {
+        level
+    }
+}
+
+/// what an instance of a mesh drew last frame, keep one per instance for the hysteresis
+#[derive(Clone, Copy, Default, Debug)]
+pub struct LodState {
+    pub level: Option<usize>,
+}
+
+/// level counts of the meshes submitted during a frame
+#[derive(Clone, Copy, Default, Debug)]
+pub struct LodStats {
+    pub drawn_per_level: [usize; MAX_LOD_LEVELS],
+    pub frustum_culled: usize,
+    /// past their last switch distance
+    pub distance_culled: usize,
+}
+
+/// object space triangles of a mesh, for building a bvh of it
+pub fn triangles(vertices: &[Vertex], indices: &[Index]) -> Vec<Triangle> {
+    indices
+        .chunks_exact(3)
+        .map(|triangle| Triangle {
+            vertices: [0, 1, 2].map(|corner| {
+                let vertex = &vertices[triangle[corner] as usize];
+                Vector::new(vertex.x, vertex.y, vertex.z)
+            }),
+        })
+        .collect()
+}
+
+/// Object space bounds of a geometry's vertices, shared by culling, picking and physics.
+/// The sphere is centered on the box, so it is conservative rather than minimal
+#[derive(Clone, Copy, Debug)]
+pub struct Bounds {
+    pub aabb: Aabb,
+    pub center: Vector,
+    pub radius: f32,
+}
+
+impl Bounds {
+    /// empty `vertices` give a point at the origin
+    pub fn from_vertices(vertices: &[Vertex]) -> Self {
+        if vertices.is_empty() {
+            let origin = Vector::new(0.0, 0.0, 0.0);
+            return Self { aabb: Aabb { min: origin, max: origin }, center: origin, radius: 0.0 };
+        }
+
+        let mut min = Vector::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = Vector::new(f32::MIN, f32::MIN, f32::MIN);
+        for vertex in vertices {
+            min = Vector::new(min.x.min(vertex.x), min.y.min(vertex.y), min.z.min(vertex.z));
+            max = Vector::new(max.x.max(vertex.x), max.y.max(vertex.y), max.z.max(vertex.z));
+        }
+
+        let center = (min + max) * 0.5;
+        let radius_sqr = vertices
+            .iter()
+            .map(|vertex| (Vector::new(vertex.x, vertex.y, vertex.z) - center).norm_sqr())
+            .fold(0.0, f32::max);
+
+        Self { aabb: Aabb { min, max }, center, radius: radius_sqr.sqrt() }
+    }
+
+    /// world space bounds of an instance, the box stays axis aligned and grows with rotation
+    pub fn transformed(&self, model: &ModelMat) -> Self {
+        let center = model.translation()
+            + model.axis(0) * self.center.x
+            + model.axis(1) * self.center.y
+            + model.axis(2) * self.center.z;
+
+        let half = (self.aabb.max - self.aabb.min) * 0.5;
+        let mut extent = Vector::new(0.0, 0.0, 0.0);
+        let mut max_scale_sqr = 0.0_f32;
+        for (axis, half) in [half.x, half.y, half.z].into_iter().enumerate() {
+            let axis = model.axis(axis);
+            extent += Vector::new(axis.x.abs(), axis.y.abs(), axis.z.abs()) * half;
+            max_scale_sqr = max_scale_sqr.max(axis.norm_sqr());
+        }
+
+        Self {
+            aabb: Aabb { min: center - extent, max: center + extent },
+            center,
+            radius: self.radius * max_scale_sqr.sqrt(),
+        }
+    }
+}
 
 /// referred by a geometry id from user and used internally for binding that geometry.
 /// A slice of these is used to quickly iterate and call vkCmdDrawIndexed.
 /// They are also used to deallocate the underlying geometry
 #[derive(Clone)]
-struct Geometry {
+pub struct Geometry {
+    /// in vertices, as drawing reads it
     vertex_offset:  i32,
     first_index:    u32,
     index_count:    u32,
+    bounds:         Bounds,
+    dealloc:        GeometryDealloc,
 }
 
-impl Default for Geometry {
-    fn default() -> Self {
-        Self { vertex_offset: i32::MIN, first_index: 0, index_count: 0 }
+impl Geometry {
+    fn indirect_command(&self, first_instance: u32, instance_count: u32) -> vk::DrawIndexedIndirectCommand {
+        vk::DrawIndexedIndirectCommand {
+            index_count: self.index_count,
+            instance_count,
+            first_index: self.first_index,
+            vertex_offset: self.vertex_offset,
+            first_instance,
+        }
     }
 }
 
 #[derive(Clone)]
 struct GeometryDealloc {
-    vertex_block_level:     allocator::BlockLevel,
-    vertex_free_tree_index: allocator::FreeTreeIndex,
-    index_block_level:      allocator::BlockLevel,
-    index_free_tree_index:  allocator::FreeTreeIndex,
+    /// in bytes, of the vertex heap block, the vertices start at the first whole vertex in it
+    vertex_block_offset: vk::DeviceSize,
+    /// block level and free tree index in the vertex and index heap
+    blocks: [(allocator::BlockLevel, allocator::FreeTreeIndex); 2],
 }
 
-impl Default for GeometryDealloc {
-    fn default() -> Self {
-        use allocator::{BlockLevel, FreeTreeIndex};
-
-        Self { 
-            vertex_block_level:     BlockLevel::MAX,
-            vertex_free_tree_index: FreeTreeIndex::MAX,
-            index_block_level:      BlockLevel::MAX,
-            index_free_tree_index:  FreeTreeIndex::MAX,
-        }
-    } 
+/// the first whole vertex at or after `block_offset` bytes into the vertex heap,
+/// as an index for drawing and in bytes for writing it
+fn first_vertex(block_offset: vk::DeviceSize) -> (i32, vk::DeviceSize) {
+    let vertex_size = size_of::<Vertex>() as vk::DeviceSize;
+    let vertex_offset = block_offset.div_ceil(vertex_size);
+    (vertex_offset as i32, vertex_offset * vertex_size)
 }
 
-/// Holds static geometry. 
-/// User provides vertex and index data and system loads data onto device local memory
-/// System also returns back geometry id which refers to the loaded geometry
-pub struct GeometrySystem {
-    device:                     Rc<ash::Device>,
-    due_vertex_buffer_copies:   Vec<vk::BufferCopy>,
-    due_index_buffer_copies:    Vec<vk::BufferCopy>,
-    
-    id_to_geometry:             Vec<Geometry>,
-    id_to_geometry_dealloc:     Vec<GeometryDealloc>,
-    id_exists:                  Vec<usize>,
-    available_ids:              Vec<GeometryId>,
-    geometry_count:             usize,
-
-    vertex_buffer:              vk::Buffer,
-    index_buffer:               vk::Buffer,
-    memory:                     vk::DeviceMemory,
-    
-    staging_buffer:             vk::Buffer,
-    staging_memory:             vk::DeviceMemory,
+const VERTEX_HEAP: usize = 0;
+const INDEX_HEAP: usize = 1;
+/// levels a heap is split into at first, growing adds levels above while the smallest block stays
+const HEAP_BLOCK_LEVELS: allocator::BlockLevel = 8;
 
-    vertex_allocator:           allocator::Allocator,
-    index_allocator:            allocator::Allocator,
+/// Vertices or indices in a device local buffer, staged in a persistently mapped buffer
+/// of the same size whose memory the allocator manages. With resizable BAR the device local
+/// buffer is mapped and written directly instead.
+/// Both double in size when an allocation doesn't fit
+struct GeometryHeap {
+    usage: vk::BufferUsageFlags,
+    buffer: Buffer,
+    /// `None` when `buffer` is host visible and written directly
+    staging_buffer: Option<Buffer>,
+    allocator: allocator::Allocator,
+    /// staged since the last upload
+    due_copies: Vec<vk::BufferCopy>,
+    /// replaced by growing since the last upload, oldest first,
+    /// each one's contents go to the next one or `buffer` on upload
+    replaced_buffers: Vec<Buffer>,
 }
 
-impl GeometrySystem {
-    pub fn new(
-        device: Rc<ash::Device>, 
-        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties, 
-        vertex_buffer_size: vk::DeviceSize,
-        index_buffer_size: vk::DeviceSize,
+impl GeometryHeap {
+    fn new(
+        device: Rc<ash::Device>,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        usage: vk::BufferUsageFlags,
+        size: vk::DeviceSize,
+        direct: bool,
     ) -> Self {
-        let vertex_buffer = {
-            let info = vk::BufferCreateInfo::builder()
-                .size(vertex_buffer_size)
-                .usage(vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST)
-                .sharing_mode(vk::SharingMode::EXCLUSIVE); // configurable
-            unsafe { device.create_buffer(&info, None) }.expect("Failed to create buffer handle")
-        };
+        let mut buffer = Self::new_buffer(device.clone(), physical_device_memory_properties, usage, size, direct);
+        let mut staging_buffer = (!direct).then(|| Self::new_staging_buffer(device, physical_device_memory_properties, size));
 
-        let index_buffer = {
-            let info = vk::BufferCreateInfo::builder()
-                .size(index_buffer_size)
-                .usage(vk::BufferUsageFlags::INDEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST)
-                .sharing_mode(vk::SharingMode::EXCLUSIVE); // configurable
-            unsafe { device.create_buffer(&info, None) }.expect("Failed to create buffer handle")
-        };
+        let heap_start = unsafe { staging_buffer.as_mut().unwrap_or(&mut buffer).map() };
+        let mut allocator = unsafe { crate::allocator::Allocator::new(
+            heap_start,
+            size as usize,
+            HEAP_BLOCK_LEVELS,
+        ) };
+        if cfg!(debug_assertions) {
+            allocator.enable_stats();
+        }
 
-        let staging_buffer = {
-            let info = vk::BufferCreateInfo::builder()
-                .size(vertex_buffer_size + index_buffer_size)
-                .usage(vk::BufferUsageFlags::TRANSFER_SRC)
-                .sharing_mode(vk::SharingMode::EXCLUSIVE); // configurable
-            unsafe { device.create_buffer(&info, None) }.expect("Failed to create buffer handle")
+        Self {
+            usage,
+            buffer,
+            staging_buffer,
+            allocator,
+            due_copies: vec![],
+            replaced_buffers: vec![],
+        }
+    }
+
+    fn new_buffer(
+        device: Rc<ash::Device>,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        usage: vk::BufferUsageFlags,
+        size: vk::DeviceSize,
+        direct: bool,
+    ) -> Buffer {
+        let memory_properties = if direct {
+            vk::MemoryPropertyFlags::DEVICE_LOCAL
+                | vk::MemoryPropertyFlags::HOST_VISIBLE
+                | vk::MemoryPropertyFlags::HOST_COHERENT
+        } else {
+            vk::MemoryPropertyFlags::DEVICE_LOCAL
         };
+        Buffer::new(
+            size,
+            // source of the copy into its replacement when growing
+            usage | vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::TRANSFER_SRC,
+            memory_properties,
+            device,
+            physical_device_memory_properties,
+        )
+    }
 
-        let vertex_mem_requirements = unsafe { device.get_buffer_memory_requirements(vertex_buffer) };
-        let index_mem_requirements = unsafe { device.get_buffer_memory_requirements(index_buffer) };
-        let staging_mem_requirements = unsafe { device.get_buffer_memory_requirements(staging_buffer) };
+    fn new_staging_buffer(
+        device: Rc<ash::Device>,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        size: vk::DeviceSize,
+    ) -> Buffer {
+        Buffer::new(
+            size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            // TODO: optimize with host caches and memory flushes
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            device,
+            physical_device_memory_properties,
+        )
+    }
 
-        let memory = {
-            let mem_type_index = crate::renderer::device::find_mem_type_index(
-                vertex_mem_requirements.memory_type_bits & index_mem_requirements.memory_type_bits,
-                vk::MemoryPropertyFlags::DEVICE_LOCAL,
-                &physical_device_memory_properties,
-            );
-            let alloc_info = vk::MemoryAllocateInfo::builder()
-                .allocation_size(vertex_mem_requirements.size + index_mem_requirements.size)
-                .memory_type_index(mem_type_index);
+    /// offset into both buffers of an allocation
+    fn offset_of(&self, ptr: *mut u8) -> vk::DeviceSize {
+        ptr as vk::DeviceSize - self.allocator.heap_start as vk::DeviceSize
+    }
 
-            unsafe { device.allocate_memory(&alloc_info, None) }
-                .expect("Failed to allocate device memory")
+    /// doubles both buffers, the staged contents move right away and the device contents on the next upload.
+    /// Blocks keep their offsets, their levels and free tree indices change as by `Allocator::grown_block`
+    fn grow(
+        &mut self,
+        device: &Rc<ash::Device>,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        retired_buffers: &mut DeletionQueue<Buffer>,
+    ) {
+        let size = 2 * self.buffer.size;
+        log::info!("Growing geometry heap of {:?} to {} bytes", self.usage, size);
+
+        let Some(old_staging_buffer) = &mut self.staging_buffer else {
+            let mut buffer = Self::new_buffer(device.clone(), physical_device_memory_properties, self.usage, size, true);
+            unsafe {
+                let heap_start = buffer.map();
+                // reads device local memory back, slow but growing is rare
+                heap_start.copy_from_nonoverlapping(self.allocator.heap_start, self.allocator.heap_size);
+                self.allocator.grow(heap_start);
+                self.buffer.unmap();
+            }
+            // frames in flight may still draw from the old buffer
+            retired_buffers.push(std::mem::replace(&mut self.buffer, buffer));
+            return;
         };
 
-        let staging_memory = {
-            let mem_type_index = crate::renderer::device::find_mem_type_index(
-                staging_mem_requirements.memory_type_bits,
-                // TODO: optimize with host caches and memory flushes
-                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT, 
-                &physical_device_memory_properties,
+        let mut staging_buffer = Self::new_staging_buffer(device.clone(), physical_device_memory_properties, size);
+        unsafe {
+            let heap_start = staging_buffer.map();
+            heap_start.copy_from_nonoverlapping(self.allocator.heap_start, self.allocator.heap_size);
+            self.allocator.grow(heap_start);
+            old_staging_buffer.unmap();
+        }
+        // uploads of frames in flight may still read the old staging buffer
+        retired_buffers.push(std::mem::replace(old_staging_buffer, staging_buffer));
+
+        let buffer = Self::new_buffer(device.clone(), physical_device_memory_properties, self.usage, size, false);
+        self.replaced_buffers.push(std::mem::replace(&mut self.buffer, buffer));
+    }
+
+    /// copies replaced buffers' contents, then staged regions, into `buffer`,
+    /// retiring the replaced buffers
+    unsafe fn cmd_upload(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        retired_buffers: &mut DeletionQueue<Buffer>,
+    ) {
+        let replaced_buffers = std::mem::take(&mut self.replaced_buffers);
+        for (i, replaced) in replaced_buffers.iter().enumerate() {
+            let dst = replaced_buffers.get(i + 1).map_or(self.buffer.handle, |next| next.handle);
+            // earlier uploads and growth copies wrote the source
+            cmd_transfer_barrier(device, command_buffer, vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_READ);
+            device.cmd_copy_buffer(
+                command_buffer,
+                replaced.handle,
+                dst,
+                &[vk::BufferCopy { src_offset: 0, dst_offset: 0, size: replaced.size }],
             );
-            let alloc_info = vk::MemoryAllocateInfo::builder()
-                .allocation_size(staging_mem_requirements.size)
-                .memory_type_index(mem_type_index);
+        }
+        if !replaced_buffers.is_empty() {
+            // staged regions land on top of the copied contents
+            cmd_transfer_barrier(device, command_buffer, vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE);
+        }
+        // frames in flight may still draw from the replaced buffers
+        replaced_buffers.into_iter().for_each(|buffer| retired_buffers.push(buffer));
 
-            unsafe { device.allocate_memory(&alloc_info, None) }
-                .expect("Failed to allocate device memory")
-        };
+        if let (Some(staging_buffer), false) = (&self.staging_buffer, self.due_copies.is_empty()) {
+            device.cmd_copy_buffer(
+                command_buffer,
+                staging_buffer.handle,
+                self.buffer.handle,
+                &self.due_copies,
+            );
+            self.due_copies.clear();
+        }
+    }
 
-        unsafe {
-            let bind_infos = [
-                vk::BindBufferMemoryInfo::builder()
-                    .memory(memory)
-                    .memory_offset(0)
-                    .buffer(vertex_buffer)
-                    .build(),
-                vk::BindBufferMemoryInfo::builder()
-                    .memory(memory)
-                    .memory_offset(vertex_buffer_size)
-                    .buffer(index_buffer)
-                    .build(),
-
-                vk::BindBufferMemoryInfo::builder()
-                    .memory(staging_memory)
-                    .memory_offset(0)
-                    .buffer(staging_buffer)
-                    .build()
-            ];
-
-            device
-                .bind_buffer_memory2(&bind_infos)
-                .expect("Failed to associate memory with buffer");
-        };
+    // caller must ensure only called once
+    unsafe fn destroy(&mut self) {
+        for buffer in &mut self.replaced_buffers {
+            buffer.destroy();
+        }
+        if let Some(staging_buffer) = &mut self.staging_buffer {
+            staging_buffer.unmap();
+            staging_buffer.destroy();
+        } else {
+            self.buffer.unmap();
+        }
+        self.buffer.destroy();
+    }
+}
 
-        let staging_mapped_ptr = unsafe { device
-            .map_memory(
-                staging_memory,
-                0, 
-                vertex_buffer_size + index_buffer_size,
-                vk::MemoryMapFlags::empty(),
-            )
-            .unwrap() as *mut u8
-        };
+/// makes transfer writes recorded so far visible to `dst_stage`
+unsafe fn cmd_transfer_barrier(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    dst_stage: vk::PipelineStageFlags,
+    dst_access: vk::AccessFlags,
+) {
+    let barrier = vk::MemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .dst_access_mask(dst_access)
+        .build();
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        dst_stage,
+        vk::DependencyFlags::empty(),
+        &[barrier],
+        &[],
+        &[],
+    );
+}
 
-        let block_levels = 8;
-        let vertex_allocator = unsafe { crate::allocator::Allocator::new(
-            staging_mapped_ptr,
-            vertex_buffer_size as usize,
-            block_levels,
-        ) };
+/// Holds static geometry. 
+/// User provides vertex and index data and system loads data onto device local memory
+/// System also returns back geometry id which refers to the loaded geometry.
+/// The vertex and index buffers grow as needed, so their handles change, bind them every frame
+pub struct GeometrySystem {
+    device:                     Rc<ash::Device>,
+    physical_device_memory_properties: vk::PhysicalDeviceMemoryProperties,
 
-        let index_allocator = unsafe { crate::allocator::Allocator::new(
-            (staging_mapped_ptr as vk::DeviceSize + vertex_buffer_size) as *mut u8,
-            vertex_buffer_size as usize,
-            block_levels,
-        ) };
+    geometries:                 HandleMap<Geometry>,
+    lod_meshes:                 HandleMap<LodMesh>,
 
-        let max_id_count = 100;
-        let id_to_geometry = vec![Default::default(); max_id_count];
-        let id_to_geometry_dealloc = vec![Default::default(); max_id_count];
-        let available_ids = (0..max_id_count as GeometryId).collect::<Vec<_>>();
+    /// indexed by `VERTEX_HEAP` and `INDEX_HEAP`
+    heaps:                      [GeometryHeap; 2],
+    /// replaced by growing
+    retired_buffers:            DeletionQueue<Buffer>,
+    /// destroyed, their blocks are deallocated once no frame in flight draws them
+    retired_geometries:         DeletionQueue<Geometry>,
 
-        Self {
-            device,
-            due_vertex_buffer_copies: vec![], // aaaaaaaaaaa
-            due_index_buffer_copies: vec![], // TODO: optimize
+    /// device local, one region of draw records per frame in flight,
+    /// filled from the staging buffer, directly with resizable BAR or later by a compute culling pass
+    indirect_buffer:            Buffer,
+    /// host visible, same regions as `indirect_buffer`, `None` when that is host visible
+    indirect_staging_buffer:    Option<Buffer>,
+    multi_draw_indirect:        bool,
+}
 
-            id_to_geometry,
-            id_to_geometry_dealloc,
-            id_exists: utils::new_bitmask_vec(max_id_count, false),
-            available_ids,
-            geometry_count: 0,
+impl GeometrySystem {
+    /// the buffers start at the given sizes and double whenever an allocation doesn't fit,
+    /// they are written without staging when the device has resizable BAR
+    pub fn new(
+        device: Rc<ash::Device>, 
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties, 
+        vertex_buffer_size: vk::DeviceSize,
+        index_buffer_size: vk::DeviceSize,
+        device_features: &DeviceFeatures,
+    ) -> Self {
+        log::info!(
+            "Uploading geometry {}",
+            if device_features.rebar { "directly to device local memory" } else { "through staging buffers" },
+        );
+        let heaps = [
+            GeometryHeap::new(
+                device.clone(),
+                physical_device_memory_properties,
+                vk::BufferUsageFlags::VERTEX_BUFFER,
+                vertex_buffer_size,
+                device_features.rebar,
+            ),
+            GeometryHeap::new(
+                device.clone(),
+                physical_device_memory_properties,
+                vk::BufferUsageFlags::INDEX_BUFFER,
+                index_buffer_size,
+                device_features.rebar,
+            ),
+        ];
+
+        let indirect_buffer_size = (MAX_FRAMES_IN_FLIGHT * MAX_INDIRECT_COMMAND_COUNT
+            * size_of::<vk::DrawIndexedIndirectCommand>()) as vk::DeviceSize;
+        let host_visible = vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+        let indirect_buffer = Buffer::new(
+            indirect_buffer_size,
+            vk::BufferUsageFlags::INDIRECT_BUFFER
+                | vk::BufferUsageFlags::TRANSFER_DST
+                | vk::BufferUsageFlags::STORAGE_BUFFER,
+            if device_features.rebar {
+                vk::MemoryPropertyFlags::DEVICE_LOCAL | host_visible
+            } else {
+                vk::MemoryPropertyFlags::DEVICE_LOCAL
+            },
+            device.clone(),
+            physical_device_memory_properties,
+        );
+        let indirect_staging_buffer = (!device_features.rebar).then(|| Buffer::new(
+            indirect_buffer_size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            host_visible,
+            device.clone(),
+            physical_device_memory_properties,
+        ));
 
-            vertex_buffer,
-            vertex_allocator,
+        Self {
+            device,
+            physical_device_memory_properties: *physical_device_memory_properties,
 
-            index_buffer,
-            index_allocator,
+            geometries: HandleMap::default(),
+            lod_meshes: HandleMap::default(),
 
-            memory,
+            heaps,
+            retired_buffers: DeletionQueue::default(),
+            retired_geometries: DeletionQueue::default(),
 
-            staging_buffer,
-            staging_memory,
+            indirect_buffer,
+            indirect_staging_buffer,
+            multi_draw_indirect: device_features.multi_draw_indirect,
         }
     }
 
+    /// vertex and index buffer, as `cmd_bind_resources` binds them, until one grows
+    pub fn get_buffers(&self) -> (vk::Buffer, vk::Buffer) {
+        (self.heaps[VERTEX_HEAP].buffer.handle, self.heaps[INDEX_HEAP].buffer.handle)
+    }
+
     pub unsafe fn cmd_bind_resources(&self, command_buffer: vk::CommandBuffer) {
+        let (vertex_buffer, index_buffer) = self.get_buffers();
         self.device.cmd_bind_vertex_buffers(
             command_buffer, 
             0, 
-            &[self.vertex_buffer], 
+            &[vertex_buffer], 
             &[0]
         );
         self.device.cmd_bind_index_buffer(
             command_buffer, 
-            self.index_buffer, 
+            index_buffer, 
             0,
             VK_INDEX_TYPE,
         );
     }
 
+    /// allocates from one of the heaps, growing it until `size` bytes fit
+    fn allocate(&mut self, heap: usize, size: usize, tag: &str) -> (*mut u8, allocator::BlockLevel, allocator::FreeTreeIndex) {
+        loop {
+            let allocation = unsafe { self.heaps[heap].allocator.allocate_tagged(size, tag) };
+            if !allocation.0.is_null() {
+                return allocation;
+            }
+
+            self.heaps[heap].grow(&self.device, &self.physical_device_memory_properties, &mut self.retired_buffers);
+            let retired_geometries = self.retired_geometries.iter_mut();
+            for geometry in self.geometries.iter_mut().map(|(_, geometry)| geometry).chain(retired_geometries) {
+                let (level, free_tree_index) = geometry.dealloc.blocks[heap];
+                geometry.dealloc.blocks[heap] = allocator::Allocator::grown_block(level, free_tree_index);
+            }
+        }
+    }
+
     pub fn create_geometry(
         &mut self, 
         vertices: &[Vertex], 
         indices: &[Index]
     ) -> GeometryId {
-        let id = self.available_ids.pop().unwrap();
-        self.geometry_count += 1;
-
+        // generated before writing, mapped device local memory is slow to read back
+        let vertices: Cow<[Vertex]> = if vertices.iter().all(|vertex| vertex.tw == 0.0) {
+            let mut generated = vertices.to_vec();
+            generate_tangents(&mut generated, indices);
+            Cow::Owned(generated)
+        } else {
+            Cow::Borrowed(vertices)
+        };
         let vertices_size = vertices.len() * size_of::<Vertex>();
         let indices_size = indices.len() * size_of::<Index>();
 
-        let (vertex_ptr, vertex_block_level, vertex_free_tree_index) = unsafe { self.vertex_allocator.allocate(vertices_size) };
-        let (index_ptr, index_block_level, index_free_tree_index) = unsafe { self.index_allocator.allocate(indices_size) };
+        // blocks start at powers of two, which a vertex's size doesn't divide, so the vertices
+        // may start up to a vertex after the block
+        let (vertex_block_ptr, vertex_block_level, vertex_free_tree_index) = self.allocate(
+            VERTEX_HEAP,
+            vertices_size + size_of::<Vertex>() - 1,
+            &format!("geometry of {} vertices", vertices.len()),
+        );
+        let (index_ptr, index_block_level, index_free_tree_index) =
+            self.allocate(INDEX_HEAP, indices_size, &format!("geometry of {} indices", indices.len()));
+
+        let vertex_block_offset = self.heaps[VERTEX_HEAP].offset_of(vertex_block_ptr);
+        let (vertex_offset, vertices_byte_offset) = first_vertex(vertex_block_offset);
+        let index_offset = self.heaps[INDEX_HEAP].offset_of(index_ptr);
 
         unsafe {
+            let vertex_ptr = vertex_block_ptr.add((vertices_byte_offset - vertex_block_offset) as usize);
             (vertex_ptr as *mut Vertex).copy_from(vertices.as_ptr(), vertices.len());
             (index_ptr as *mut Index).copy_from(indices.as_ptr(), indices.len());
         }
 
-        assert!(!utils::get_bit(&self.id_exists, id as usize));
-        let vertex_offset = vertex_ptr as vk::DeviceSize - self.vertex_allocator.heap_start as vk::DeviceSize;
-        let index_offset = index_ptr as vk::DeviceSize - self.index_allocator.heap_start as vk::DeviceSize;
-
-        self.id_to_geometry[id as usize] = Geometry {
-            vertex_offset: vertex_offset as i32,
-            first_index: index_offset as u32 / size_of::<u32>() as u32,
+        let id = self.geometries.insert(Geometry {
+            vertex_offset,
+            first_index: index_offset as u32 / size_of::<Index>() as u32,
             index_count: indices.len() as u32,
-        };
-        self.id_to_geometry_dealloc[id as usize] = GeometryDealloc {
-            vertex_block_level,
-            vertex_free_tree_index,
-            index_block_level,
-            index_free_tree_index,
-        };
-
-        self.due_vertex_buffer_copies.push(vk::BufferCopy{
-            src_offset: vertex_offset + 0,
-            dst_offset: vertex_offset,
-            size: vertices_size as vk::DeviceSize,
-        });
-        self.due_index_buffer_copies.push(vk::BufferCopy{
-            src_offset: index_offset + self.vertex_allocator.heap_size as vk::DeviceSize,
-            dst_offset: index_offset,
-            size: indices_size as vk::DeviceSize,
+            bounds: Bounds::from_vertices(&vertices),
+            dealloc: GeometryDealloc {
+                vertex_block_offset,
+                blocks: [
+                    (vertex_block_level, vertex_free_tree_index),
+                    (index_block_level, index_free_tree_index),
+                ],
+            },
         });
 
+        // written directly otherwise
+        if self.heaps[VERTEX_HEAP].staging_buffer.is_some() {
+            self.heaps[VERTEX_HEAP].due_copies.push(vk::BufferCopy{
+                src_offset: vertices_byte_offset,
+                dst_offset: vertices_byte_offset,
+                size: vertices_size as vk::DeviceSize,
+            });
+            self.heaps[INDEX_HEAP].due_copies.push(vk::BufferCopy{
+                src_offset: index_offset,
+                dst_offset: index_offset,
+                size: indices_size as vk::DeviceSize,
+            });
+        }
+
         id
     }
 
+    /// `create_geometry` after `mesh_optimize::optimize_mesh`, for imported meshes,
+    /// logs how the vertex cache efficiency changed
+    pub fn create_optimized_geometry(&mut self, vertices: &[Vertex], indices: &[Index]) -> GeometryId {
+        let (vertices, indices, [before, after]) = crate::mesh_optimize::optimize_mesh(vertices, indices);
+        log::debug!(
+            "Optimized mesh of {} triangles: {} -> {} vertices, ACMR {:.3} -> {:.3}, ATVR {:.3} -> {:.3}",
+            after.triangle_count,
+            before.vertex_count,
+            after.vertex_count,
+            before.acmr,
+            after.acmr,
+            before.atvr,
+            after.atvr,
+        );
+        self.create_geometry(&vertices, &indices)
+    }
+
+    /// copies created geometries and the contents of grown buffers to the device,
+    /// record outside of any render pass before drawing
     pub fn cmd_upload_geometries(&mut self, command_buffer: vk::CommandBuffer) {
+        let has_uploads = self.heaps.iter().any(|heap| !heap.due_copies.is_empty() || !heap.replaced_buffers.is_empty());
+        if !has_uploads {
+            return;
+        }
+
         unsafe {
-            self.device.cmd_copy_buffer(
-                command_buffer, 
-                self.staging_buffer, 
-                self.vertex_buffer, 
-                &self.due_vertex_buffer_copies,
+            for heap in &mut self.heaps {
+                heap.cmd_upload(&self.device, command_buffer, &mut self.retired_buffers);
+            }
+            cmd_transfer_barrier(
+                &self.device,
+                command_buffer,
+                vk::PipelineStageFlags::VERTEX_INPUT,
+                vk::AccessFlags::VERTEX_ATTRIBUTE_READ | vk::AccessFlags::INDEX_READ,
             );
+        }
+    }
 
-            self.device.cmd_copy_buffer(
-                command_buffer, 
-                self.staging_buffer, 
-                self.index_buffer, 
-                &self.due_index_buffer_copies,
-            );
+    /// call once per frame after waiting for its fence, destroys buffers replaced by growing
+    /// and deallocates destroyed geometries that no frame in flight can still use
+    pub fn collect_retired(&mut self) {
+        self.retired_buffers.collect(|mut buffer| unsafe { buffer.destroy() });
+        let heaps = &mut self.heaps;
+        self.retired_geometries.collect(|geometry| Self::deallocate(heaps, &geometry));
+    }
+
+    /// false once the geometry was destroyed, even when its slot is reused
+    pub fn contains_geometry(&self, id: GeometryId) -> bool {
+        self.geometries.contains(id)
+    }
+
+    /// object space, computed from the vertices when the geometry was created
+    pub fn get_bounds(&self, id: GeometryId) -> &Bounds {
+        &self.geometries.get(id).expect("Getting bounds of a stale geometry id").bounds
+    }
+
+    /// registers geometries as the levels of one mesh, they are destroyed along with it.
+    /// Switch distances must increase from level to level
+    pub fn create_lod_mesh(&mut self, levels: &[LodLevel]) -> LodMeshId {
+        assert!(!levels.is_empty() && levels.len() <= MAX_LOD_LEVELS, "A mesh needs 1 to {} levels of detail", MAX_LOD_LEVELS);
+        assert!(
+            levels.windows(2).all(|pair| pair[0].switch_distance < pair[1].switch_distance),
+            "Level of detail switch distances must increase",
+        );
+        self.lod_meshes.insert(LodMesh { levels: levels.to_vec() })
+    }
+
+    pub fn get_lod_mesh(&self, id: LodMeshId) -> &LodMesh {
+        self.lod_meshes.get(id).expect("Getting a stale mesh id")
+    }
+
+    /// destroys the mesh's geometries too
+    pub fn destroy_lod_mesh(&mut self, id: LodMeshId) {
+        let mesh = self.lod_meshes.remove(id).expect("Destroying a stale mesh id");
+        for level in mesh.levels {
+            self.destroy_geometry(level.geometry);
         }
-        self.due_vertex_buffer_copies.clear();
-        self.due_index_buffer_copies.clear();
     }
 
+    /// the id is stale right away, the geometry's memory is reused once no frame in flight draws it
     pub fn destroy_geometry(&mut self, id: GeometryId) {
-        self.geometry_count -= 1;
+        let geometry = self.geometries.remove(id).expect("Destroying a stale geometry id");
+        self.retired_geometries.push(geometry);
+    }
+
+    fn deallocate(heaps: &mut [GeometryHeap; 2], geometry: &Geometry) {
+        let [(vertex_block_level, vertex_free_tree_index), (index_block_level, index_free_tree_index)] = geometry.dealloc.blocks;
 
         unsafe {
-            self.vertex_allocator.deallocate(
-                (self.id_to_geometry[id as usize].vertex_offset + self.vertex_allocator.heap_start as i32) as *mut u8, 
-                self.id_to_geometry_dealloc[id as usize].vertex_block_level,
-                self.id_to_geometry_dealloc[id as usize].vertex_free_tree_index,
+            let vertex_allocator = &mut heaps[VERTEX_HEAP].allocator;
+            vertex_allocator.deallocate(
+                vertex_allocator.heap_start.add(geometry.dealloc.vertex_block_offset as usize),
+                vertex_block_level,
+                vertex_free_tree_index,
             );
-            self.index_allocator.deallocate(
-                (self.id_to_geometry[id as usize].first_index * size_of::<Index>() as u32 + self.index_allocator.heap_start as u32) as *mut u8, 
-                self.id_to_geometry_dealloc[id as usize].index_block_level,
-                self.id_to_geometry_dealloc[id as usize].index_free_tree_index,
+            let index_allocator = &mut heaps[INDEX_HEAP].allocator;
+            index_allocator.deallocate(
+                index_allocator.heap_start.add(geometry.first_index as usize * size_of::<Index>()),
+                index_block_level,
+                index_free_tree_index,
             );
         }
-
-        self.id_to_geometry[id as usize] = Default::default();
-        self.available_ids.push(id);
     }
 
-    pub fn cmd_draw_geometry(&self, command_buffer: vk::CommandBuffer, id: GeometryId) {
-        assert!(utils::get_bit(&self.id_exists, id as usize));
-        let geometry = &self.id_to_geometry[id as usize];
+    /// instances index the bound instance buffer
+    pub fn cmd_draw_geometry(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        id: GeometryId,
+        first_instance: u32,
+        instance_count: u32,
+    ) {
+        let geometry = self.geometries.get(id).expect("Drawing a stale geometry id");
 
         unsafe { self.device.cmd_draw_indexed(
             command_buffer, 
             geometry.index_count, 
-            1, // optimize using instancing
+            instance_count,
             geometry.first_index, 
             geometry.vertex_offset,
-            0, 
+            first_instance, 
         ) };
     }
 
+    /// draw record of `cmd_draw_geometry` with the same arguments
+    pub fn indirect_command(
+        &self,
+        id: GeometryId,
+        first_instance: u32,
+        instance_count: u32,
+    ) -> vk::DrawIndexedIndirectCommand {
+        self.geometries.get(id).expect("Drawing a stale geometry id").indirect_command(first_instance, instance_count)
+    }
+
+    fn indirect_frame_offset(frame: usize) -> vk::DeviceSize {
+        (frame * MAX_INDIRECT_COMMAND_COUNT * size_of::<vk::DrawIndexedIndirectCommand>()) as vk::DeviceSize
+    }
+
+    /// stages `frame`'s draw records, or writes them directly with resizable BAR,
+    /// the frame's previous commands must have finished executing
+    pub fn write_indirect_commands(&mut self, frame: usize, commands: &[vk::DrawIndexedIndirectCommand]) {
+        assert!(commands.len() <= MAX_INDIRECT_COMMAND_COUNT, "Out of indirect command slots");
+        self.indirect_staging_buffer
+            .as_mut()
+            .unwrap_or(&mut self.indirect_buffer)
+            .copy_from_slice(commands, frame * MAX_INDIRECT_COMMAND_COUNT);
+    }
+
+    /// copies the first `command_count` staged draw records of `frame` to the device,
+    /// record outside of any render pass
+    pub fn cmd_upload_indirect_commands(&self, command_buffer: vk::CommandBuffer, frame: usize, command_count: usize) {
+        // written directly, submitting makes host writes visible
+        let Some(indirect_staging_buffer) = &self.indirect_staging_buffer else {
+            return;
+        };
+        if command_count == 0 {
+            return;
+        }
+
+        let offset = Self::indirect_frame_offset(frame);
+        let size = (command_count * size_of::<vk::DrawIndexedIndirectCommand>()) as vk::DeviceSize;
+        unsafe {
+            self.device.cmd_copy_buffer(
+                command_buffer,
+                indirect_staging_buffer.handle,
+                self.indirect_buffer.handle,
+                &[vk::BufferCopy {
+                    src_offset: offset,
+                    dst_offset: offset,
+                    size,
+                }],
+            );
+
+            let barrier = vk::BufferMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::INDIRECT_COMMAND_READ)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .buffer(self.indirect_buffer.handle)
+                .offset(offset)
+                .size(size)
+                .build();
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::DRAW_INDIRECT,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[barrier],
+                &[],
+            );
+        }
+    }
+
+    /// draws `command_count` of `frame`'s uploaded draw records starting at `first_command`,
+    /// in one call when the device supports multi draw indirect
+    pub fn cmd_draw_geometry_indirect(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+        first_command: u32,
+        command_count: u32,
+    ) {
+        let stride = size_of::<vk::DrawIndexedIndirectCommand>() as u32;
+        let offset = Self::indirect_frame_offset(frame) + (first_command * stride) as vk::DeviceSize;
+
+        unsafe {
+            if self.multi_draw_indirect {
+                self.device.cmd_draw_indexed_indirect(
+                    command_buffer,
+                    self.indirect_buffer.handle,
+                    offset,
+                    command_count,
+                    stride,
+                );
+            } else {
+                for command in 0..command_count {
+                    self.device.cmd_draw_indexed_indirect(
+                        command_buffer,
+                        self.indirect_buffer.handle,
+                        offset + (command * stride) as vk::DeviceSize,
+                        1,
+                        stride,
+                    );
+                }
+            }
+        }
+    }
+
     /// destroys all resources owned by this geometry system
     pub unsafe fn destroy_resources(&mut self) {
-        unsafe {
-            self.device.unmap_memory(self.staging_memory);
+        let heaps = &mut self.heaps;
+        self.retired_geometries.destroy_all(|geometry| Self::deallocate(heaps, &geometry));
+        let [vertex_heap, index_heap] = &mut self.heaps;
+        let leaked = !(self.geometries.is_empty() && self.lod_meshes.is_empty()
+            && vertex_heap.allocator.is_empty() && index_heap.allocator.is_empty());
+        if leaked {
+            log::error!(
+                "{} geometries and {} meshes were not destroyed\nvertices: {}indices: {}",
+                self.geometries.len(),
+                self.lod_meshes.len(),
+                vertex_heap.allocator.dump(),
+                index_heap.allocator.dump(),
+            );
+        }
+        // the memory goes with the heaps either way, a leak is a bug to catch while developing
+        debug_assert!(!leaked, "Geometry leaked, see the log");
 
-            self.device.destroy_buffer(self.vertex_buffer, None);
-            self.device.destroy_buffer(self.index_buffer, None);
-            self.device.destroy_buffer(self.staging_buffer, None);
+        unsafe {
+            self.indirect_buffer.destroy();
+            if let Some(indirect_staging_buffer) = &mut self.indirect_staging_buffer {
+                indirect_staging_buffer.destroy();
+            }
 
-            self.device.free_memory(self.memory, None);
-            self.device.free_memory(self.staging_memory, None);
+            vertex_heap.destroy();
+            index_heap.destroy();
+            self.retired_buffers.destroy_all(|mut buffer| buffer.destroy());
         }
 
     }
 }
+
+#[test]
+fn test_generate_tangents() {
+    // quad in the xy plane facing +z, u along +x, v along +y
+    let vertex = |x: f32, y: f32| Vertex {
+        x, y, z: 0.0,
+        u: x, v: y,
+        nx: 0.0, ny: 0.0, nz: 1.0,
+        ..Default::default()
+    };
+    let mut vertices = [vertex(0.0, 0.0), vertex(1.0, 0.0), vertex(1.0, 1.0), vertex(0.0, 1.0)];
+    generate_tangents(&mut vertices, &[0, 1, 2, 2, 3, 0]);
+
+    for v in &vertices {
+        assert!((v.tx - 1.0).abs() < 1e-5 && v.ty.abs() < 1e-5 && v.tz.abs() < 1e-5);
+        assert!(v.tw == 1.0);
+    }
+
+    // mirrored uvs flip the handedness
+    let mut vertices = vertices.map(|v| Vertex { u: -v.u, tw: 0.0, ..v });
+    generate_tangents(&mut vertices, &[0, 1, 2, 2, 3, 0]);
+    for v in &vertices {
+        assert!((v.tx + 1.0).abs() < 1e-5);
+        assert!(v.tw == -1.0);
+    }
+}
+
+#[test]
+fn test_first_vertex() {
+    let vertex_size = size_of::<Vertex>() as vk::DeviceSize;
+    let geometry = |vertex_block_offset, first_index, index_count| Geometry {
+        vertex_offset: first_vertex(vertex_block_offset).0,
+        first_index,
+        index_count,
+        bounds: Bounds::from_vertices(&[Vertex::default()]),
+        dealloc: GeometryDealloc { vertex_block_offset, blocks: [(0, 0); 2] },
+    };
+
+    // packed one after another, the second's vertices are offset by the first's vertex count
+    let first = geometry(0, 0, 3);
+    let second = geometry(3 * vertex_size, 3, 6);
+    assert!(first.vertex_offset == 0 && second.vertex_offset == 3);
+    let command = second.indirect_command(5, 2);
+    assert!(command.index_count == 6 && command.first_index == 3 && command.vertex_offset == 3);
+    assert!(command.first_instance == 5 && command.instance_count == 2);
+
+    // blocks not on a vertex boundary start at the next whole vertex
+    assert!(first_vertex(256) == (6, 6 * vertex_size));
+    assert!(first_vertex(4 * vertex_size) == (4, 4 * vertex_size));
+}
+
+#[test]
+fn test_bounds() {
+    let vertex = |x: f32, y: f32, z: f32| Vertex { x, y, z, ..Default::default() };
+    let bounds = Bounds::from_vertices(&[vertex(-1.0, 0.0, 2.0), vertex(3.0, 2.0, 2.0), vertex(1.0, 1.0, 4.0)]);
+    assert!(bounds.aabb.min.x == -1.0 && bounds.aabb.min.y == 0.0 && bounds.aabb.min.z == 2.0);
+    assert!(bounds.aabb.max.x == 3.0 && bounds.aabb.max.y == 2.0 && bounds.aabb.max.z == 4.0);
+    assert!(bounds.center.x == 1.0 && bounds.center.y == 1.0 && bounds.center.z == 3.0);
+    // the box's corners are the furthest vertices
+    assert!((bounds.radius - 6.0_f32.sqrt()).abs() < 1e-5);
+
+    // scaled by 2 on x and rotated a quarter turn about z, moving x onto y
+    let rotation = crate::math::Rotor::from_axis_angle(Vector::new(0.0, 0.0, 1.0), std::f32::consts::FRAC_PI_2);
+    let model = ModelMat::from(Vector::new(2.0, 1.0, 1.0), rotation, Vector::new(10.0, 0.0, 0.0));
+    let world = bounds.transformed(&model);
+    let close = |a: f32, b: f32| (a - b).abs() < 1e-4;
+    assert!(close(world.aabb.max.x - world.aabb.min.x, 2.0) && close(world.aabb.max.y - world.aabb.min.y, 8.0));
+    assert!(close(world.aabb.max.z - world.aabb.min.z, 2.0));
+    assert!(close(world.radius, 2.0 * bounds.radius));
+    assert!(close(world.center.z, 3.0));
+}
+
+#[test]
+fn test_select_lod_level() {
+    let distances = [10.0, 30.0, 100.0];
+    assert!(select_lod_level(&distances, 5.0, None, LOD_HYSTERESIS) == Some(0));
+    assert!(select_lod_level(&distances, 50.0, None, LOD_HYSTERESIS) == Some(2));
+    assert!(select_lod_level(&distances, 150.0, None, LOD_HYSTERESIS).is_none());
+
+    // just past a switch distance keeps the previous level, further switches
+    assert!(select_lod_level(&distances, 10.5, Some(0), LOD_HYSTERESIS) == Some(0));
+    assert!(select_lod_level(&distances, 11.5, Some(0), LOD_HYSTERESIS) == Some(1));
+    // same coming back closer
+    assert!(select_lod_level(&distances, 9.5, Some(1), LOD_HYSTERESIS) == Some(1));
+    assert!(select_lod_level(&distances, 8.5, Some(1), LOD_HYSTERESIS) == Some(0));
+    assert!(select_lod_level(&distances, 105.0, Some(2), LOD_HYSTERESIS) == Some(2));
+    // jumps over several levels at once
+    assert!(select_lod_level(&distances, 50.0, Some(0), LOD_HYSTERESIS) == Some(2));
+}
+
+#[test]
+fn test_compressed_vertex() {
+    use crate::quantize::{decode_normal, f16_to_f32, from_snorm16};
+
+    let bindings = crate::renderer::pipeline::get_binding_descs(&COMPRESSED_VERTEX_ATTRIBUTES, &[]);
+    assert!(bindings[0].stride as usize == size_of::<CompressedVertex>() && 2 * size_of::<CompressedVertex>() == size_of::<Vertex>());
+
+    let normal = Vector::new(0.0, -0.6, 0.8);
+    let vertex = Vertex {
+        x: 1.5, y: -0.25, z: 3.0,
+        u: 0.5, v: 0.75,
+        nx: normal.x, ny: normal.y, nz: normal.z,
+        tx: 1.0, ty: 0.0, tz: 0.0, tw: -1.0,
+    };
+    let compressed = CompressedVertex::from_vertex(&vertex);
+    assert!(compressed.position.map(f16_to_f32) == [1.5, -0.25, 3.0, 1.0]);
+    assert!(compressed.uv.map(f16_to_f32) == [0.5, 0.75]);
+    assert!(decode_normal(compressed.normal).dot(&normal) > 0.9999);
+    assert!(compressed.tangent.map(from_snorm16) == [1.0, 0.0, 0.0, -1.0]);
+}
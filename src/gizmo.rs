@@ -0,0 +1,394 @@
+// Translate, rotate and scale handles for editing a scene object's transform with the cursor.
+// Handles are hit tested and dragged with the cursor's picking ray and drawn as debug lines.
+// Translation and rotation follow the parent's axes so they map directly onto the object's
+// transform, scaling follows the object's own axes
+
+use std::f32::consts::{PI, TAU};
+
+use crate::{
+    camera::Camera,
+    math::{ModelMat, Ray, Rotor, Vector},
+    renderer::debug_lines::DebugLine,
+    scene::Transform,
+};
+
+/// of the x, y and z handles
+const AXIS_COLORS: [[f32; 4]; 3] = [
+    [0.9, 0.2, 0.2, 1.0],
+    [0.3, 0.9, 0.3, 1.0],
+    [0.2, 0.4, 1.0, 1.0],
+];
+const HIGHLIGHT_COLOR: [f32; 4] = [1.0, 0.9, 0.2, 1.0];
+/// how close the ray has to pass a handle, relative to the gizmo's size
+const HIT_TOLERANCE: f32 = 0.08;
+/// objects are picked when the ray passes their origin within this angle
+const PICK_ANGLE: f32 = 0.03;
+const CIRCLE_SEGMENTS: usize = 48;
+/// scales aren't dragged below this
+const MIN_SCALE: f32 = 0.01;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum GizmoMode {
+    #[default]
+    Translate,
+    Rotate,
+    Scale,
+}
+
+impl GizmoMode {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Translate => Self::Rotate,
+            Self::Rotate => Self::Scale,
+            Self::Scale => Self::Translate,
+        }
+    }
+}
+
+/// steps dragged values snap to while snapping, 0 doesn't snap
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct GizmoSnap {
+    pub translation: f32,
+    /// radians
+    pub rotation: f32,
+    pub scale: f32,
+}
+
+impl Default for GizmoSnap {
+    fn default() -> Self {
+        Self {
+            translation: 0.25,
+            rotation: PI / 12.0,
+            scale: 0.1,
+        }
+    }
+}
+
+fn snap(value: f32, step: f32) -> f32 {
+    if step > 0.0 { (value / step).round() * step } else { value }
+}
+
+/// the cursor's state for a frame, see `Gizmo::update`
+pub struct GizmoInput {
+    /// picking ray under the cursor, `None` while it's outside the window
+    pub ray: Option<Ray>,
+    /// the drag button is held
+    pub pressed: bool,
+    /// the drag button went down this frame
+    pub just_pressed: bool,
+    pub snapping: bool,
+}
+
+/// where the handles are and which way they point
+#[derive(Clone, Copy, Debug)]
+struct Frame {
+    origin: Vector,
+    /// unit
+    axes: [Vector; 3],
+    /// world units per unit of the transform along each axis
+    axis_scales: [f32; 3],
+    /// handle length
+    size: f32,
+}
+
+#[derive(Clone, Copy)]
+struct Drag {
+    axis: usize,
+    /// as the drag started, handles stay put while dragging
+    frame: Frame,
+    start_transform: Transform,
+    /// where the ray first grabbed the handle
+    grab: Vector,
+}
+
+/// Keeps the hovered and dragged handle between frames,
+/// `update` once per frame with the selected object then draw its `lines`
+pub struct Gizmo {
+    pub mode: GizmoMode,
+    pub snap: GizmoSnap,
+    /// handle length as a fraction of the distance to the camera, keeps the size on screen
+    pub screen_size: f32,
+    hovered: Option<usize>,
+    drag: Option<Drag>,
+}
+
+impl Default for Gizmo {
+    fn default() -> Self {
+        Self {
+            mode: GizmoMode::default(),
+            snap: GizmoSnap::default(),
+            screen_size: 0.15,
+            hovered: None,
+            drag: None,
+        }
+    }
+}
+
+impl Gizmo {
+    pub fn is_dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    /// `world` is the object's world transform, `parent` its parent's or the identity for roots
+    fn frame(&self, world: &ModelMat, parent: &ModelMat, camera_position: Vector) -> Frame {
+        let basis = if self.mode == GizmoMode::Scale { world } else { parent };
+        let origin = world.translation();
+        let mut axes = [Vector::new(1.0, 0.0, 0.0), Vector::new(0.0, 1.0, 0.0), Vector::new(0.0, 0.0, 1.0)];
+        let mut axis_scales = [1.0; 3];
+        for (axis, (unit, scale)) in axes.iter_mut().zip(&mut axis_scales).enumerate() {
+            let length = basis.axis(axis).norm_sqr().sqrt();
+            // collapsed axes keep the world's
+            if length > 1e-6 {
+                *unit = basis.axis(axis) / length;
+                *scale = length;
+            }
+        }
+        Frame {
+            origin,
+            axes,
+            axis_scales,
+            size: (origin - camera_position).norm_sqr().sqrt() * self.screen_size,
+        }
+    }
+
+    /// the point on `axis`'s line or plane under `ray`
+    fn grab_point(&self, frame: &Frame, axis: usize, ray: &Ray) -> Option<Vector> {
+        let direction = frame.axes[axis];
+        match self.mode {
+            GizmoMode::Translate | GizmoMode::Scale => {
+                let (_, line_t) = ray.closest_to_line(frame.origin, direction)?;
+                Some(frame.origin + direction * line_t)
+            }
+            GizmoMode::Rotate => ray.intersect_plane(frame.origin, direction).map(|t| ray.at(t)),
+        }
+    }
+
+    /// the handle closest to `ray` within reach
+    fn hit_test(&self, frame: &Frame, ray: &Ray) -> Option<usize> {
+        (0..3)
+            .filter_map(|axis| {
+                let direction = frame.axes[axis];
+                let distance = match self.mode {
+                    GizmoMode::Translate | GizmoMode::Scale => {
+                        let (ray_t, line_t) = ray.closest_to_line(frame.origin, direction)?;
+                        if ray_t < 0.0 || !(0.0..=frame.size).contains(&line_t) {
+                            return None;
+                        }
+                        (ray.at(ray_t) - (frame.origin + direction * line_t)).norm_sqr().sqrt()
+                    }
+                    // the circles
+                    GizmoMode::Rotate => {
+                        let t = ray.intersect_plane(frame.origin, direction)?;
+                        ((ray.at(t) - frame.origin).norm_sqr().sqrt() - frame.size).abs()
+                    }
+                };
+                (distance < HIT_TOLERANCE * frame.size).then_some((axis, distance))
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(axis, _)| axis)
+    }
+
+    /// `drag`'s start transform with the handle moved to `point`
+    fn dragged(&self, drag: &Drag, point: Vector, snapping: bool) -> Transform {
+        let Drag { axis, frame, start_transform, grab } = *drag;
+        let direction = frame.axes[axis];
+        let mut transform = start_transform;
+        match self.mode {
+            GizmoMode::Translate => {
+                let (x, y, z) = transform.translation;
+                let mut translation = [x, y, z];
+                translation[axis] += (point - grab).dot(&direction) / frame.axis_scales[axis];
+                if snapping {
+                    translation[axis] = snap(translation[axis], self.snap.translation);
+                }
+                transform.translation = (translation[0], translation[1], translation[2]);
+            }
+            GizmoMode::Rotate => {
+                let from = grab - frame.origin;
+                let to = point - frame.origin;
+                let mut angle = direction.dot(&from.cross(&to)).atan2(from.dot(&to));
+                if snapping {
+                    angle = snap(angle, self.snap.rotation);
+                }
+                // around the parent's axis, in the parent's frame
+                let mut local_axis = [0.0; 3];
+                local_axis[axis] = 1.0;
+                let (scalar, yx, zy, xz) = transform.rotation;
+                let rotation = Rotor::from_axis_angle(Vector::new(local_axis[0], local_axis[1], local_axis[2]), angle)
+                    * Rotor::new(scalar, yx, zy, xz);
+                transform.rotation = rotation.components();
+            }
+            GizmoMode::Scale => {
+                let grabbed = (grab - frame.origin).dot(&direction);
+                if grabbed.abs() > 1e-6 {
+                    let (x, y, z) = transform.scale;
+                    let mut scale = [x, y, z];
+                    scale[axis] *= (point - frame.origin).dot(&direction) / grabbed;
+                    if snapping {
+                        scale[axis] = snap(scale[axis], self.snap.scale);
+                    }
+                    scale[axis] = scale[axis].max(MIN_SCALE);
+                    transform.scale = (scale[0], scale[1], scale[2]);
+                }
+            }
+        }
+        transform
+    }
+
+    /// hovers and drags the handles of the object with `transform`, see `frame` for `world` and `parent`.
+    /// Returns whether the cursor is on a handle, clicks then shouldn't pick other objects
+    pub fn update(
+        &mut self,
+        input: &GizmoInput,
+        camera_position: Vector,
+        world: &ModelMat,
+        parent: &ModelMat,
+        transform: &mut Transform,
+    ) -> bool {
+        if !input.pressed {
+            self.drag = None;
+        }
+        let Some(ray) = &input.ray else {
+            self.hovered = None;
+            return self.drag.is_some();
+        };
+
+        if let Some(drag) = &self.drag {
+            if let Some(point) = self.grab_point(&drag.frame, drag.axis, ray) {
+                *transform = self.dragged(drag, point, input.snapping);
+            }
+            return true;
+        }
+
+        let frame = self.frame(world, parent, camera_position);
+        self.hovered = self.hit_test(&frame, ray);
+        if let (Some(axis), true) = (self.hovered, input.just_pressed) {
+            if let Some(grab) = self.grab_point(&frame, axis, ray) {
+                self.drag = Some(Drag {
+                    axis,
+                    frame,
+                    start_transform: *transform,
+                    grab,
+                });
+            }
+        }
+        self.hovered.is_some()
+    }
+
+    /// the handles of the object at `world`, see `frame`
+    pub fn lines(&self, camera_position: Vector, world: &ModelMat, parent: &ModelMat) -> Vec<DebugLine> {
+        let frame = self.frame(world, parent, camera_position);
+        let highlighted = self.drag.as_ref().map(|drag| drag.axis).or(self.hovered);
+        let line = |start: Vector, end: Vector, color| DebugLine {
+            start: [start.x, start.y, start.z],
+            end: [end.x, end.y, end.z],
+            color,
+        };
+
+        let mut lines = vec![];
+        for (axis, &axis_color) in AXIS_COLORS.iter().enumerate() {
+            let color = if highlighted == Some(axis) { HIGHLIGHT_COLOR } else { axis_color };
+            let direction = frame.axes[axis] * frame.size;
+            let side = frame.axes[(axis + 1) % 3] * frame.size;
+            let other_side = frame.axes[(axis + 2) % 3] * frame.size;
+            let tip = frame.origin + direction;
+            match self.mode {
+                GizmoMode::Translate => {
+                    lines.push(line(frame.origin, tip, color));
+                    // arrow head
+                    let base = tip - direction * 0.15;
+                    lines.push(line(tip, base + side * 0.06, color));
+                    lines.push(line(tip, base - side * 0.06, color));
+                    lines.push(line(tip, base + other_side * 0.06, color));
+                    lines.push(line(tip, base - other_side * 0.06, color));
+                }
+                GizmoMode::Scale => {
+                    lines.push(line(frame.origin, tip, color));
+                    // box end
+                    let corners = [side + other_side, side - other_side, -side - other_side, -side + other_side];
+                    for (corner, next_corner) in corners.iter().zip(corners.iter().cycle().skip(1)) {
+                        lines.push(line(tip + *corner * 0.05, tip + *next_corner * 0.05, color));
+                    }
+                }
+                GizmoMode::Rotate => {
+                    let point = |segment: usize| {
+                        let (sin, cos) = (segment as f32 * TAU / CIRCLE_SEGMENTS as f32).sin_cos();
+                        frame.origin + side * cos + other_side * sin
+                    };
+                    for segment in 0..CIRCLE_SEGMENTS {
+                        lines.push(line(point(segment), point(segment + 1), color));
+                    }
+                }
+            }
+        }
+        lines
+    }
+}
+
+/// the object whose origin `ray` passes closest to, by angle, within `PICK_ANGLE`
+pub fn pick_origin(ray: &Ray, world_transforms: &[ModelMat]) -> Option<usize> {
+    world_transforms
+        .iter()
+        .enumerate()
+        .filter_map(|(index, world)| {
+            let offset = world.translation() - ray.origin;
+            let distance = offset.norm_sqr().sqrt();
+            if distance < 1e-6 {
+                return None;
+            }
+            let angle = (offset.dot(&ray.direction) / distance).clamp(-1.0, 1.0).acos();
+            (angle < PICK_ANGLE).then_some((index, angle))
+        })
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(index, _)| index)
+}
+
+/// picking ray through the cursor at `cursor` pixels from the top left of a `width` by `height` window
+pub fn cursor_ray(camera: &Camera, cursor: [f32; 2], width: f32, height: f32) -> Option<Ray> {
+    let ndc = [2.0 * cursor[0] / width - 1.0, 2.0 * cursor[1] / height - 1.0];
+    Ray::from_ndc(&camera.calc_proj_view(), ndc)
+}
+
+#[test]
+fn test_gizmo() {
+    // camera 10 units in front of the object looking along +z, handles 1.5 long
+    let camera_position = Vector::new(0.0, 0.0, -10.0);
+    let ray_at = |x: f32, y: f32| Some(Ray { origin: Vector::new(x, y, -10.0), direction: Vector::new(0.0, 0.0, 1.0) });
+    let input = |ray, pressed, just_pressed, snapping| GizmoInput { ray, pressed, just_pressed, snapping };
+    let identity = ModelMat::identity();
+
+    let mut gizmo = Gizmo::default();
+    let mut transform = Transform::default();
+    assert!(!gizmo.update(&input(ray_at(0.5, 0.6), false, false, false), camera_position, &identity, &identity, &mut transform));
+    assert!(gizmo.update(&input(ray_at(0.5, 0.0), true, true, false), camera_position, &identity, &identity, &mut transform));
+    assert!(gizmo.is_dragging() && gizmo.hovered == Some(0));
+    gizmo.update(&input(ray_at(1.3, 0.4), true, false, false), camera_position, &identity, &identity, &mut transform);
+    assert!((transform.translation.0 - 0.8).abs() < 1e-5 && transform.translation.1 == 0.0);
+    gizmo.update(&input(ray_at(1.3, 0.4), true, false, true), camera_position, &identity, &identity, &mut transform);
+    assert!((transform.translation.0 - 0.75).abs() < 1e-5);
+    gizmo.update(&input(ray_at(1.3, 0.4), false, false, false), camera_position, &identity, &identity, &mut transform);
+    assert!(!gizmo.is_dragging());
+
+    // a quarter turn around z, x turns towards y
+    gizmo.mode = GizmoMode::Rotate;
+    let mut transform = Transform::default();
+    gizmo.update(&input(ray_at(1.5, 0.0), true, true, false), camera_position, &identity, &identity, &mut transform);
+    assert!(gizmo.is_dragging() && gizmo.hovered == Some(2));
+    gizmo.update(&input(ray_at(0.1, 1.5), true, false, true), camera_position, &identity, &identity, &mut transform);
+    let x = transform.to_model_mat().axis(0);
+    assert!(x.x.abs() < 1e-5 && (x.y - 1.0).abs() < 1e-5);
+    assert!((transform.rotation.0 - std::f32::consts::FRAC_PI_4.cos()).abs() < 1e-5);
+    gizmo.update(&input(None, false, false, false), camera_position, &identity, &identity, &mut transform);
+
+    // scaling follows the object's axes, here rotated onto y
+    gizmo.mode = GizmoMode::Scale;
+    let world = transform.to_model_mat();
+    gizmo.update(&input(ray_at(0.0, 1.0), true, true, false), camera_position, &world, &identity, &mut transform);
+    gizmo.update(&input(ray_at(0.0, 2.0), true, false, false), camera_position, &world, &identity, &mut transform);
+    assert!((transform.scale.0 - 2.0).abs() < 1e-5 && transform.scale.1 == 1.0);
+    assert!(gizmo.lines(camera_position, &world, &identity).len() == 3 * 5);
+
+    let objects = [identity, Transform { translation: (3.0, 0.0, 0.0), ..Default::default() }.to_model_mat()];
+    assert!(pick_origin(&ray_at(3.05, 0.0).unwrap(), &objects) == Some(1));
+    assert!(pick_origin(&ray_at(1.5, 0.0).unwrap(), &objects).is_none());
+}
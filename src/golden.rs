@@ -0,0 +1,189 @@
+// Golden image tests, renders a scene and compares the result to a stored image.
+// The render tests in tests/golden.rs are ignored by default since they need a vulkan device and a display:
+//
+//     cargo test --test golden -- --ignored
+//
+//     let (width, height, pixels) = render_scene(3, |app| {
+//         let cube = ...;
+//         move |app: &mut VkApp| app.submit_draw(cube, material, transform)
+//     });
+//     check_golden("lit_cube", width, height, &pixels, &Tolerance::default());
+//
+// Goldens are committed under tests/goldens and a missing one fails the test,
+// set UPDATE_GOLDENS=1 to write new goldens or overwrite them after an intended change in output.
+
+use ash::vk;
+use winit::{event_loop::EventLoopBuilder, window::WindowBuilder, dpi::PhysicalSize};
+
+use crate::renderer::VkApp;
+
+const GOLDENS_DIR: &str = "tests/goldens";
+pub const GOLDEN_WIDTH: u32 = 320;
+pub const GOLDEN_HEIGHT: u32 = 240;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Tolerance {
+    /// channel differences up to this much are not counted as mismatches
+    pub channel: u8,
+    /// fraction of pixels allowed to mismatch, absorbs driver rasterization differences
+    pub mismatched_fraction: f32,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self {
+            channel: 2,
+            mismatched_fraction: 0.001,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ImageDiff {
+    pub max_channel_diff: u8,
+    pub mismatched_pixels: usize,
+    pub pixel_count: usize,
+    /// root mean square error over all channels, in 0..=255
+    pub rmse: f32,
+}
+
+impl ImageDiff {
+    pub fn passes(&self, tolerance: &Tolerance) -> bool {
+        self.mismatched_pixels as f32 <= tolerance.mismatched_fraction * self.pixel_count as f32
+    }
+}
+
+/// both images are tightly packed rgba8 of the same size
+pub fn compare_rgba8(actual: &[u8], expected: &[u8], tolerance: &Tolerance) -> ImageDiff {
+    assert!(actual.len() == expected.len());
+
+    let mut max_channel_diff = 0;
+    let mut mismatched_pixels = 0;
+    let mut squared_error_sum = 0.0;
+    for (actual, expected) in actual.chunks_exact(4).zip(expected.chunks_exact(4)) {
+        let mut mismatched = false;
+        for c in 0..4 {
+            let diff = actual[c].abs_diff(expected[c]);
+            max_channel_diff = max_channel_diff.max(diff);
+            mismatched |= diff > tolerance.channel;
+            squared_error_sum += (diff as f32) * (diff as f32);
+        }
+        mismatched_pixels += mismatched as usize;
+    }
+
+    ImageDiff {
+        max_channel_diff,
+        mismatched_pixels,
+        pixel_count: actual.len() / 4,
+        rmse: (squared_error_sum / actual.len().max(1) as f32).sqrt(),
+    }
+}
+
+fn save_rgba8(path: &str, width: u32, height: u32, pixels: &[u8]) {
+    std::fs::create_dir_all(GOLDENS_DIR).unwrap();
+    image::save_buffer(path, pixels, width, height, image::ColorType::RGBA(8))
+        .unwrap_or_else(|err| panic!("Failed to write {}: {}", path, err));
+}
+
+/// panics with the diff on mismatch, the actual image is written next to the golden.
+/// Also panics when the golden is missing, unless UPDATE_GOLDENS=1 which writes it instead
+pub fn check_golden(name: &str, width: u32, height: u32, pixels: &[u8], tolerance: &Tolerance) {
+    let golden_path = format!("{}/{}.png", GOLDENS_DIR, name);
+    if std::env::var("UPDATE_GOLDENS").is_ok_and(|update| update == "1") {
+        log::info!("Writing golden {}", golden_path);
+        save_rgba8(&golden_path, width, height, pixels);
+        return;
+    }
+
+    let golden = match image::open(&golden_path) {
+        Ok(golden) => golden.to_rgba(),
+        Err(err) => {
+            let actual_path = format!("{}/{}.actual.png", GOLDENS_DIR, name);
+            save_rgba8(&actual_path, width, height, pixels);
+            panic!(
+                "Golden {} is missing ({}), actual written to {}, rerun with UPDATE_GOLDENS=1 to accept it",
+                golden_path, err, actual_path,
+            );
+        }
+    };
+
+    assert!(
+        golden.width() == width && golden.height() == height,
+        "Golden {} is {}x{}, rendered {}x{}", golden_path, golden.width(), golden.height(), width, height,
+    );
+
+    let diff = compare_rgba8(pixels, &golden.into_raw(), tolerance);
+    if !diff.passes(tolerance) {
+        let actual_path = format!("{}/{}.actual.png", GOLDENS_DIR, name);
+        save_rgba8(&actual_path, width, height, pixels);
+        panic!("Golden {} mismatch: {:?}, actual written to {}", golden_path, diff, actual_path);
+    }
+}
+
+/// Renders `frame_count` frames of the scene `setup` builds and returns the last one,
+/// the closure `setup` returns submits the scene's draws before each frame.
+/// The engine has no offscreen target yet so an invisible window stands in for headless rendering,
+/// nothing time based may feed the scene for the output to be deterministic
+pub fn render_scene<S, F>(frame_count: u32, setup: S) -> (u32, u32, Vec<u8>)
+where
+    S: FnOnce(&mut VkApp) -> F,
+    F: FnMut(&mut VkApp),
+{
+    let mut event_loop_builder = EventLoopBuilder::new();
+    // tests don't run on the main thread
+    #[cfg(windows)] {
+        use winit::platform::windows::EventLoopBuilderExtWindows;
+        event_loop_builder.with_any_thread(true);
+    }
+    #[cfg(all(unix, not(target_os = "macos")))] {
+        use winit::platform::x11::EventLoopBuilderExtX11;
+        event_loop_builder.with_any_thread(true);
+    }
+    let event_loop = event_loop_builder.build();
+
+    let window = WindowBuilder::new()
+        .with_title("Golden")
+        .with_visible(false)
+        .with_resizable(false)
+        .with_inner_size(PhysicalSize {
+            width: GOLDEN_WIDTH,
+            height: GOLDEN_HEIGHT,
+        })
+        .build(&event_loop)
+        .unwrap();
+
+    // misuse fails the test instead of scrolling by in the log
+    let mut config = crate::config::EngineConfig::default();
+    config.validation.panic_on_error = true;
+    let mut app = VkApp::new(window, &config);
+    app.request_resize(vk::Extent2D {
+        width: GOLDEN_WIDTH,
+        height: GOLDEN_HEIGHT,
+    });
+    let mut submit = setup(&mut app);
+
+    for _ in 0..frame_count {
+        submit(&mut app);
+        app.draw_frame();
+    }
+    app.screenshot().unwrap_or_else(|err| panic!("Screenshot failed: {}", err))
+}
+
+#[test]
+fn test_compare_rgba8() {
+    let tolerance = Tolerance::default();
+    let expected = [10, 20, 30, 255, 0, 0, 0, 255];
+
+    let diff = compare_rgba8(&expected, &expected, &tolerance);
+    assert!(diff.max_channel_diff == 0 && diff.mismatched_pixels == 0 && diff.rmse == 0.0);
+
+    // within channel tolerance
+    let diff = compare_rgba8(&[12, 19, 30, 255, 0, 0, 0, 255], &expected, &tolerance);
+    assert!(diff.max_channel_diff == 2 && diff.mismatched_pixels == 0);
+    assert!(diff.passes(&tolerance));
+
+    let diff = compare_rgba8(&[10, 20, 30, 255, 255, 0, 0, 255], &expected, &tolerance);
+    assert!(diff.max_channel_diff == 255 && diff.mismatched_pixels == 1);
+    assert!(!diff.passes(&tolerance));
+    assert!(diff.passes(&Tolerance { channel: 2, mismatched_fraction: 0.5 }));
+}
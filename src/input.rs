@@ -1,52 +1,157 @@
-const KEY_CODE_COUNT: usize = 128;
-type KeysBitmask = u128;
+use winit::event::{MouseButton, VirtualKeyCode};
+
+/// covers every `VirtualKeyCode`
+pub const KEY_CODE_COUNT: usize = 256;
+
+/// one bit per key code
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct KeysBitmask([u64; KEY_CODE_COUNT / 64]);
+
+impl KeysBitmask {
+    #[inline(always)]
+    fn word_and_bit(key_code: VirtualKeyCode) -> (usize, u64) {
+        let key_code = key_code as usize;
+        (key_code / 64, 1 << (key_code % 64))
+    }
+
+    #[inline(always)]
+    pub fn get(&self, key_code: VirtualKeyCode) -> bool {
+        let (word, bit) = Self::word_and_bit(key_code);
+        self.0[word] & bit != 0
+    }
+
+    #[inline(always)]
+    pub fn set(&mut self, key_code: VirtualKeyCode, value: bool) {
+        let (word, bit) = Self::word_and_bit(key_code);
+        if value {
+            self.0[word] |= bit;
+        } else {
+            self.0[word] &= !bit;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|&word| word == 0)
+    }
+}
+
+/// one bit per button, other buttons aren't tracked
+fn mouse_button_bit(button: MouseButton) -> u8 {
+    match button {
+        MouseButton::Left => 1,
+        MouseButton::Right => 2,
+        MouseButton::Middle => 4,
+        MouseButton::Other(_) => 0,
+    }
+}
 
 pub struct InputState {
-    pub keys_pressed_bitmask: KeysBitmask,
-    pub previous_keys_pressed_bitmask: KeysBitmask,
+    keys_pressed: KeysBitmask,
+    /// as they were when the last frame ended
+    previous_keys_pressed: KeysBitmask,
+    mouse_buttons_pressed: u8,
+    previous_mouse_buttons_pressed: u8,
     pub delta_mouse_pos: [f32; 2],
+    /// in window pixels from the top left, `None` while outside the window
+    pub cursor_pos: Option<[f32; 2]>,
+    /// scroll wheel lines since the last frame, positive away from the user
+    pub scroll_delta: f32,
 }
 
 impl InputState {
     pub fn new() -> Self {
         Self {
-            keys_pressed_bitmask: 0b0,
-            previous_keys_pressed_bitmask: 0b0,
+            keys_pressed: KeysBitmask::default(),
+            previous_keys_pressed: KeysBitmask::default(),
+            mouse_buttons_pressed: 0,
+            previous_mouse_buttons_pressed: 0,
             delta_mouse_pos: [0.0, 0.0],
+            cursor_pos: None,
+            scroll_delta: 0.0,
         }
     }
 
     #[inline(always)]
-    pub fn is_key_pressed(&mut self, key_code: winit::event::VirtualKeyCode) -> bool {
-        let key_code_usize = key_code as usize;
-        assert!(
-            key_code_usize < KEY_CODE_COUNT,
-            "key_code: {:?} not supported",
-            key_code
-        );
-        self.keys_pressed_bitmask & (1 << key_code_usize) != 0
+    pub fn is_key_pressed(&self, key_code: VirtualKeyCode) -> bool {
+        self.keys_pressed.get(key_code)
+    }
+
+    /// pressed when the last frame ended
+    #[inline(always)]
+    pub fn was_key_pressed(&self, key_code: VirtualKeyCode) -> bool {
+        self.previous_keys_pressed.get(key_code)
     }
 
+    /// went down this frame
     #[inline(always)]
-    pub fn was_key_pressed(&mut self, key_code: winit::event::VirtualKeyCode) -> bool {
-        let key_code_usize = key_code as usize;
-        assert!(
-            key_code_usize < KEY_CODE_COUNT,
-            "key_code: {:?} not supported",
-            key_code
-        );
-        self.previous_keys_pressed_bitmask & (1 << key_code_usize) != 0
+    pub fn just_pressed(&self, key_code: VirtualKeyCode) -> bool {
+        self.is_key_pressed(key_code) && !self.was_key_pressed(key_code)
     }
 
+    /// went up this frame
     #[inline(always)]
-    pub fn set_key_pressed(&mut self, key_code: winit::event::VirtualKeyCode, pressed: bool) {
-        let key_code_usize = key_code as usize;
-        assert!(
-            key_code_usize < KEY_CODE_COUNT,
-            "key_code: {:?} not supported",
-            key_code
-        );
-        self.keys_pressed_bitmask &= !(1 << key_code_usize);
-        self.keys_pressed_bitmask |= (pressed as KeysBitmask) << key_code_usize;
+    pub fn just_released(&self, key_code: VirtualKeyCode) -> bool {
+        !self.is_key_pressed(key_code) && self.was_key_pressed(key_code)
     }
+
+    #[inline(always)]
+    pub fn set_key_pressed(&mut self, key_code: VirtualKeyCode, pressed: bool) {
+        self.keys_pressed.set(key_code, pressed);
+    }
+
+    #[inline(always)]
+    pub fn is_mouse_button_pressed(&self, button: MouseButton) -> bool {
+        self.mouse_buttons_pressed & mouse_button_bit(button) != 0
+    }
+
+    /// went down this frame
+    #[inline(always)]
+    pub fn mouse_button_just_pressed(&self, button: MouseButton) -> bool {
+        self.is_mouse_button_pressed(button) && self.previous_mouse_buttons_pressed & mouse_button_bit(button) == 0
+    }
+
+    #[inline(always)]
+    pub fn set_mouse_button_pressed(&mut self, button: MouseButton, pressed: bool) {
+        if pressed {
+            self.mouse_buttons_pressed |= mouse_button_bit(button);
+        } else {
+            self.mouse_buttons_pressed &= !mouse_button_bit(button);
+        }
+    }
+
+    /// call once input for the frame has been handled,
+    /// remembers the pressed keys and resets the mouse and scroll deltas
+    pub fn end_frame(&mut self) {
+        self.previous_keys_pressed = self.keys_pressed;
+        self.previous_mouse_buttons_pressed = self.mouse_buttons_pressed;
+        self.delta_mouse_pos = [0.0, 0.0];
+        self.scroll_delta = 0.0;
+    }
+}
+
+#[test]
+fn test_input_state() {
+    // the last key code
+    assert!((VirtualKeyCode::Cut as usize) < KEY_CODE_COUNT);
+
+    let mut input_state = InputState::new();
+    input_state.set_key_pressed(VirtualKeyCode::Cut, true);
+    input_state.set_key_pressed(VirtualKeyCode::W, true);
+    assert!(input_state.just_pressed(VirtualKeyCode::Cut) && input_state.is_key_pressed(VirtualKeyCode::W));
+    assert!(!input_state.is_key_pressed(VirtualKeyCode::Copy));
+
+    input_state.end_frame();
+    input_state.set_key_pressed(VirtualKeyCode::Cut, false);
+    assert!(input_state.just_released(VirtualKeyCode::Cut) && !input_state.just_pressed(VirtualKeyCode::W));
+    assert!(input_state.is_key_pressed(VirtualKeyCode::W) && !input_state.just_released(VirtualKeyCode::W));
+
+    input_state.set_key_pressed(VirtualKeyCode::W, false);
+    input_state.end_frame();
+    assert!(input_state.keys_pressed.is_empty() && !input_state.just_released(VirtualKeyCode::Cut));
+
+    input_state.set_mouse_button_pressed(MouseButton::Left, true);
+    assert!(input_state.mouse_button_just_pressed(MouseButton::Left));
+    assert!(!input_state.is_mouse_button_pressed(MouseButton::Right));
+    input_state.end_frame();
+    assert!(input_state.is_mouse_button_pressed(MouseButton::Left) && !input_state.mouse_button_just_pressed(MouseButton::Left));
 }
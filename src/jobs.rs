@@ -0,0 +1,333 @@
+// Thread pool shared by the engine's subsystems.
+// Every worker owns a queue it pushes and pops at the back, idle workers steal from the front
+// of the others' queues. Jobs spawned off the workers go through a shared injector queue.
+// Scopes let jobs borrow from the caller and return once all of them finished,
+// waiting threads run queued jobs meanwhile, so scopes nest and the main thread helps out
+//
+//     job_system.scope(|scope| {
+//         for chunk in draws.chunks(64) {
+//             scope.spawn("record", || record(chunk));
+//         }
+//     });
+
+use std::{
+    any::Any,
+    cell::Cell,
+    collections::VecDeque,
+    marker::PhantomData,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, RwLock,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// told about every job that runs, e.g. to profile them
+pub trait JobHooks: Send + Sync {
+    /// `worker` is `None` when a thread waiting on a scope ran the job
+    fn job_finished(&self, name: &'static str, worker: Option<usize>, elapsed: Duration);
+}
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+struct Task {
+    name: &'static str,
+    job: Job,
+}
+
+struct Shared {
+    /// one per worker, the owner works at the back, thieves at the front
+    queues: Vec<Mutex<VecDeque<Task>>>,
+    /// jobs spawned from threads that aren't workers
+    injector: Mutex<VecDeque<Task>>,
+    /// tasks in any queue
+    queued: AtomicUsize,
+    sleep_lock: Mutex<()>,
+    wake: Condvar,
+    shutdown: AtomicBool,
+    hooks: RwLock<Option<Arc<dyn JobHooks>>>,
+}
+
+thread_local! {
+    /// the pool and index of the worker running on this thread
+    static WORKER: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+}
+
+impl Shared {
+    fn id(self: &Arc<Self>) -> usize {
+        Arc::as_ptr(self) as usize
+    }
+
+    /// this thread's worker index in this pool
+    fn current_worker(self: &Arc<Self>) -> Option<usize> {
+        WORKER.with(|worker| match worker.get() {
+            Some((pool, index)) if pool == self.id() => Some(index),
+            _ => None,
+        })
+    }
+
+    fn push(self: &Arc<Self>, task: Task) {
+        match self.current_worker() {
+            Some(index) => self.queues[index].lock().unwrap().push_back(task),
+            None => self.injector.lock().unwrap().push_back(task),
+        }
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        // taking the lock orders the wake up after a worker's check of `queued`
+        let _guard = self.sleep_lock.lock().unwrap();
+        self.wake.notify_one();
+    }
+
+    /// own queue first, then the injector, then steals
+    fn find_task(&self, worker: Option<usize>) -> Option<Task> {
+        let task = worker
+            .and_then(|index| self.queues[index].lock().unwrap().pop_back())
+            .or_else(|| self.injector.lock().unwrap().pop_front())
+            .or_else(|| {
+                let start = worker.map_or(0, |index| index + 1);
+                (0..self.queues.len())
+                    .map(|offset| (start + offset) % self.queues.len())
+                    .filter(|&victim| Some(victim) != worker)
+                    .find_map(|victim| self.queues[victim].lock().unwrap().pop_front())
+            });
+        if task.is_some() {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+        }
+        task
+    }
+
+    fn run(&self, task: Task, worker: Option<usize>) {
+        let start = Instant::now();
+        // scoped jobs resume their panics on the scope, detached ones would take the worker down
+        if panic::catch_unwind(AssertUnwindSafe(task.job)).is_err() {
+            log::error!("Job {} panicked", task.name);
+        }
+        if let Some(hooks) = &*self.hooks.read().unwrap() {
+            hooks.job_finished(task.name, worker, start.elapsed());
+        }
+    }
+
+    fn worker_loop(self: Arc<Self>, index: usize) {
+        WORKER.with(|worker| worker.set(Some((self.id(), index))));
+        loop {
+            if let Some(task) = self.find_task(Some(index)) {
+                self.run(task, Some(index));
+                continue;
+            }
+
+            let guard = self.sleep_lock.lock().unwrap();
+            if self.shutdown.load(Ordering::SeqCst) {
+                break;
+            }
+            if self.queued.load(Ordering::SeqCst) == 0 {
+                drop(self.wake.wait(guard).unwrap());
+            }
+        }
+    }
+}
+
+/// Work stealing thread pool, dropping it finishes the running jobs and drops the queued ones
+pub struct JobSystem {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl JobSystem {
+    /// at least one worker
+    pub fn new(worker_count: usize) -> Self {
+        let worker_count = worker_count.max(1);
+        let shared = Arc::new(Shared {
+            queues: (0..worker_count).map(|_| Mutex::new(VecDeque::new())).collect(),
+            injector: Mutex::new(VecDeque::new()),
+            queued: AtomicUsize::new(0),
+            sleep_lock: Mutex::new(()),
+            wake: Condvar::new(),
+            shutdown: AtomicBool::new(false),
+            hooks: RwLock::new(None),
+        });
+
+        let workers = (0..worker_count)
+            .map(|index| {
+                let shared = shared.clone();
+                thread::Builder::new()
+                    .name(format!("job worker {}", index))
+                    .spawn(move || shared.worker_loop(index))
+                    .expect("Failed to spawn job worker")
+            })
+            .collect();
+
+        Self { shared, workers }
+    }
+
+    /// a worker per core besides the calling thread's, which helps while waiting on scopes
+    pub fn with_available_parallelism() -> Self {
+        let core_count = thread::available_parallelism().map_or(1, |count| count.get());
+        Self::new(core_count - 1)
+    }
+
+    pub fn get_worker_count(&self) -> usize {
+        self.workers.len()
+    }
+
+    pub fn set_hooks(&self, hooks: Option<Arc<dyn JobHooks>>) {
+        *self.shared.hooks.write().unwrap() = hooks;
+    }
+
+    /// runs `job` on a worker some time later
+    pub fn spawn<F: FnOnce() + Send + 'static>(&self, name: &'static str, job: F) {
+        self.shared.push(Task { name, job: Box::new(job) });
+    }
+
+    /// jobs spawned on the scope may borrow what outlives it, returns once they all finished.
+    /// A panicking job's panic is resumed here
+    pub fn scope<'env, F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&Scope<'env>) -> R,
+    {
+        let scope = Scope {
+            shared: self.shared.clone(),
+            state: Arc::new(ScopeState {
+                pending: AtomicUsize::new(0),
+                panic: Mutex::new(None),
+            }),
+            _env: PhantomData,
+        };
+        // jobs may still borrow from the caller when `f` panics
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        scope.wait();
+
+        if let Some(payload) = scope.state.panic.lock().unwrap().take() {
+            panic::resume_unwind(payload);
+        }
+        match result {
+            Ok(result) => result,
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+
+    /// calls `f` with every item's index, `chunk_size` items per job
+    pub fn parallel_for<T, F>(&self, name: &'static str, items: &mut [T], chunk_size: usize, f: F)
+    where
+        T: Send,
+        F: Fn(usize, &mut T) + Sync,
+    {
+        let chunk_size = chunk_size.max(1);
+        let f = &f;
+        self.scope(|scope| {
+            for (chunk_index, chunk) in items.chunks_mut(chunk_size).enumerate() {
+                scope.spawn(name, move || {
+                    for (offset, item) in chunk.iter_mut().enumerate() {
+                        f(chunk_index * chunk_size + offset, item);
+                    }
+                });
+            }
+        });
+    }
+}
+
+impl Drop for JobSystem {
+    fn drop(&mut self) {
+        {
+            let _guard = self.shared.sleep_lock.lock().unwrap();
+            self.shared.shutdown.store(true, Ordering::SeqCst);
+            self.shared.wake.notify_all();
+        }
+        for worker in self.workers.drain(..) {
+            worker.join().unwrap();
+        }
+    }
+}
+
+struct ScopeState {
+    pending: AtomicUsize,
+    panic: Mutex<Option<Box<dyn Any + Send>>>,
+}
+
+/// spawns jobs borrowing from `'env`, see `JobSystem::scope`
+pub struct Scope<'env> {
+    shared: Arc<Shared>,
+    state: Arc<ScopeState>,
+    /// invariant, so borrows can't be shortened to escape the scope
+    _env: PhantomData<&'env mut &'env ()>,
+}
+
+impl<'env> Scope<'env> {
+    pub fn spawn<F: FnOnce() + Send + 'env>(&self, name: &'static str, job: F) {
+        self.state.pending.fetch_add(1, Ordering::SeqCst);
+        let state = self.state.clone();
+        let job: Box<dyn FnOnce() + Send + 'env> = Box::new(move || {
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(job)) {
+                *state.panic.lock().unwrap() = Some(payload);
+            }
+            state.pending.fetch_sub(1, Ordering::SeqCst);
+        });
+        // the scope doesn't return before the job finished, so what it borrows outlives it
+        let job: Job = unsafe { std::mem::transmute::<Box<dyn FnOnce() + Send + 'env>, Job>(job) };
+        self.shared.push(Task { name, job });
+    }
+
+    /// runs queued jobs until the scope's jobs finished
+    fn wait(&self) {
+        let worker = self.shared.current_worker();
+        while self.state.pending.load(Ordering::SeqCst) > 0 {
+            match self.shared.find_task(worker) {
+                Some(task) => self.shared.run(task, worker),
+                // the remaining jobs are running on other threads
+                None => thread::yield_now(),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_job_system() {
+    let job_system = JobSystem::new(3);
+
+    let mut squares = vec![0; 1000];
+    job_system.parallel_for("square", &mut squares, 64, |index, square| *square = index * index);
+    assert!(squares.iter().enumerate().all(|(index, &square)| square == index * index));
+
+    // nested scopes borrowing from the caller
+    let sum = AtomicUsize::new(0);
+    job_system.scope(|scope| {
+        for i in 0..8 {
+            let sum = &sum;
+            let job_system = &job_system;
+            scope.spawn("outer", move || {
+                job_system.scope(|scope| {
+                    for j in 0..8 {
+                        scope.spawn("inner", move || {
+                            sum.fetch_add(i * 8 + j, Ordering::SeqCst);
+                        });
+                    }
+                });
+            });
+        }
+    });
+    assert!(sum.load(Ordering::SeqCst) == (0..64).sum::<usize>());
+
+    struct CountHooks(AtomicUsize);
+    impl JobHooks for CountHooks {
+        fn job_finished(&self, _: &'static str, _: Option<usize>, _: Duration) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+    let hooks = Arc::new(CountHooks(AtomicUsize::new(0)));
+    job_system.set_hooks(Some(hooks.clone()));
+    let (sender, receiver) = std::sync::mpsc::channel();
+    job_system.spawn("detached", move || sender.send(7).unwrap());
+    assert!(receiver.recv().unwrap() == 7);
+    job_system.scope(|scope| scope.spawn("counted", || {}));
+    // the detached job's hook may still be running
+    while hooks.0.load(Ordering::SeqCst) < 2 {
+        thread::yield_now();
+    }
+
+    let panicked = panic::catch_unwind(AssertUnwindSafe(|| {
+        job_system.scope(|scope| scope.spawn("panics", || panic!("job panic")));
+    }));
+    assert!(panicked.is_err());
+    // still usable afterwards
+    job_system.scope(|scope| scope.spawn("after panic", || {}));
+}
@@ -0,0 +1,257 @@
+// Text labels at points in the world, e.g. entity names and positions while debugging.
+// Labels are projected to the screen and drawn as screen sprites, one per character, from a
+// built in 5x7 pixel font, so they keep their size on screen however far away they are.
+// With a bvh of the scene, labels behind something fade instead of showing through at full strength:
+//
+//     let labels = LabelRenderer::new(app);
+//     let text = app.strings.intern(&object.name);
+//     labels.submit(app, &[Label { text, world_pos, color: [1.0; 4] }], Some(&bvh));
+
+use ash::vk;
+
+use crate::{
+    data_structures::{bvh::{Bvh, Primitive}, interner::StrId},
+    math::{Mat, Ray, Vector},
+    renderer::{
+        sampler::SamplerDesc,
+        sprite::{atlas_cell, Sprite, SpriteFacing},
+        texture::{DecodedTexture, TextureType},
+        TextureHandle, VkApp,
+    },
+};
+
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+/// a glyph and a pixel of spacing right of and below it
+const CELL_SIZE: [u32; 2] = [GLYPH_WIDTH + 1, GLYPH_HEIGHT + 1];
+const ATLAS_COLUMNS: u32 = 8;
+const ATLAS_ROWS: u32 = 8;
+const ATLAS_WIDTH: u32 = ATLAS_COLUMNS * CELL_SIZE[0];
+const ATLAS_HEIGHT: u32 = ATLAS_ROWS * CELL_SIZE[1];
+
+/// rows top to bottom, the highest of the 5 bits is the leftmost pixel.
+/// Lower case letters are drawn upper case, characters not listed as '?'
+const GLYPHS: [(char, [u8; GLYPH_HEIGHT as usize]); 58] = [
+    (' ', [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000]),
+    ('0', [0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110]),
+    ('1', [0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110]),
+    ('2', [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111]),
+    ('3', [0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110]),
+    ('4', [0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010]),
+    ('5', [0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110]),
+    ('6', [0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110]),
+    ('7', [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000]),
+    ('8', [0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110]),
+    ('9', [0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100]),
+    ('A', [0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001]),
+    ('B', [0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110]),
+    ('C', [0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110]),
+    ('D', [0b11100, 0b10010, 0b10001, 0b10001, 0b10001, 0b10010, 0b11100]),
+    ('E', [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111]),
+    ('F', [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000]),
+    ('G', [0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111]),
+    ('H', [0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001]),
+    ('I', [0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110]),
+    ('J', [0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100]),
+    ('K', [0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001]),
+    ('L', [0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111]),
+    ('M', [0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001]),
+    ('N', [0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001]),
+    ('O', [0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110]),
+    ('P', [0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000]),
+    ('Q', [0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101]),
+    ('R', [0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001]),
+    ('S', [0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110]),
+    ('T', [0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100]),
+    ('U', [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110]),
+    ('V', [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100]),
+    ('W', [0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010]),
+    ('X', [0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001]),
+    ('Y', [0b10001, 0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100]),
+    ('Z', [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111]),
+    ('.', [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b01100]),
+    (',', [0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b00100, 0b01000]),
+    (':', [0b00000, 0b01100, 0b01100, 0b00000, 0b01100, 0b01100, 0b00000]),
+    ('-', [0b00000, 0b00000, 0b00000, 0b11111, 0b00000, 0b00000, 0b00000]),
+    ('_', [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b11111]),
+    ('+', [0b00000, 0b00100, 0b00100, 0b11111, 0b00100, 0b00100, 0b00000]),
+    ('=', [0b00000, 0b00000, 0b11111, 0b00000, 0b11111, 0b00000, 0b00000]),
+    ('(', [0b00010, 0b00100, 0b01000, 0b01000, 0b01000, 0b00100, 0b00010]),
+    (')', [0b01000, 0b00100, 0b00010, 0b00010, 0b00010, 0b00100, 0b01000]),
+    ('[', [0b01110, 0b01000, 0b01000, 0b01000, 0b01000, 0b01000, 0b01110]),
+    (']', [0b01110, 0b00010, 0b00010, 0b00010, 0b00010, 0b00010, 0b01110]),
+    ('/', [0b00000, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b00000]),
+    ('!', [0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00000, 0b00100]),
+    ('?', [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b00000, 0b00100]),
+    ('#', [0b01010, 0b01010, 0b11111, 0b01010, 0b11111, 0b01010, 0b01010]),
+    ('\'', [0b00100, 0b00100, 0b01000, 0b00000, 0b00000, 0b00000, 0b00000]),
+    ('%', [0b11000, 0b11001, 0b00010, 0b00100, 0b01000, 0b10011, 0b00011]),
+    ('*', [0b00000, 0b00100, 0b10101, 0b01110, 0b10101, 0b00100, 0b00000]),
+    ('<', [0b00010, 0b00100, 0b01000, 0b10000, 0b01000, 0b00100, 0b00010]),
+    ('>', [0b01000, 0b00100, 0b00010, 0b00001, 0b00010, 0b00100, 0b01000]),
+    ('█', [0b11111, 0b11111, 0b11111, 0b11111, 0b11111, 0b11111, 0b11111]),
+];
+
+/// index into `GLYPHS` and the font atlas' cells
+fn glyph_index(c: char) -> usize {
+    let c = c.to_ascii_uppercase();
+    GLYPHS
+        .iter()
+        .position(|&(glyph, _)| glyph == c)
+        .unwrap_or_else(|| GLYPHS.iter().position(|&(glyph, _)| glyph == '?').unwrap())
+}
+
+/// rgba8 white glyphs on transparent cells, laid out for `atlas_cell`
+fn font_pixels() -> Vec<u8> {
+    let mut pixels = vec![0; (ATLAS_WIDTH * ATLAS_HEIGHT * 4) as usize];
+    for (index, (_, rows)) in GLYPHS.iter().enumerate() {
+        let cell_x = index as u32 % ATLAS_COLUMNS * CELL_SIZE[0];
+        let cell_y = index as u32 / ATLAS_COLUMNS * CELL_SIZE[1];
+        for (y, row) in rows.iter().enumerate() {
+            for x in 0..GLYPH_WIDTH {
+                if row >> (GLYPH_WIDTH - 1 - x) & 1 == 1 {
+                    let pixel = ((cell_y + y as u32) * ATLAS_WIDTH + cell_x + x) as usize * 4;
+                    pixels[pixel..pixel + 4].copy_from_slice(&[255; 4]);
+                }
+            }
+        }
+    }
+    pixels
+}
+
+#[derive(Clone, Debug)]
+pub struct Label {
+    /// interned in `app.strings`
+    pub text: StrId,
+    /// the text is centered on it
+    pub world_pos: [f32; 3],
+    /// straight alpha
+    pub color: [f32; 4],
+}
+
+/// where `world_pos` is on a screen of `screen_size` logical pixels from the top left,
+/// `None` behind the camera
+fn project(proj_view: &Mat, world_pos: [f32; 3], screen_size: [f32; 2]) -> Option<[f32; 2]> {
+    let [x, y, _, w] = proj_view.transform_point(Vector::new(world_pos[0], world_pos[1], world_pos[2]));
+    if w <= 1e-6 {
+        return None;
+    }
+    Some([
+        (0.5 * x / w + 0.5) * screen_size[0],
+        (0.5 * y / w + 0.5) * screen_size[1],
+    ])
+}
+
+/// `uv_rect` of the font's cells
+fn glyph_uv_rect(c: char) -> [f32; 4] {
+    atlas_cell(ATLAS_COLUMNS, ATLAS_ROWS, glyph_index(c) as u32)
+}
+
+/// `uv_rect` inside the font's full block, for solid quads sampling the font
+pub fn solid_uv_rect() -> [f32; 4] {
+    let [min_u, min_v, max_u, max_v] = glyph_uv_rect('█');
+    // inset to the glyph's middle, away from the spacing right of and below it
+    let (u, v) = (0.5 * (min_u + max_u), 0.5 * (min_v + max_v));
+    [u, v, u, v]
+}
+
+/// logical pixels `text` spans drawn `glyph_height` high
+pub fn text_width(text: &str, glyph_height: f32) -> f32 {
+    glyph_height * CELL_SIZE[0] as f32 / CELL_SIZE[1] as f32 * text.chars().count() as f32
+}
+
+/// a screen sprite per character of `text` in the font `font`, see `LabelRenderer::font`,
+/// the line's top left at `top_left`
+pub fn text_sprites(text: &str, top_left: [f32; 2], glyph_height: f32, color: [f32; 4], font: u32) -> impl Iterator<Item = Sprite> + '_ {
+    let size = [glyph_height * CELL_SIZE[0] as f32 / CELL_SIZE[1] as f32, glyph_height];
+    text.chars().enumerate().map(move |(index, c)| Sprite {
+        facing: SpriteFacing::Screen,
+        position: [top_left[0] + (index as f32 + 0.5) * size[0], top_left[1] + 0.5 * size[1], 0.0],
+        size,
+        uv_rect: glyph_uv_rect(c),
+        color,
+        texture: font,
+        layer: 0,
+    })
+}
+
+/// a screen sprite per character of `text`, the line centered on `center`
+fn layout(text: &str, center: [f32; 2], glyph_height: f32, color: [f32; 4], font: u32) -> impl Iterator<Item = Sprite> + '_ {
+    let top_left = [center[0] - 0.5 * text_width(text, glyph_height), center[1] - 0.5 * glyph_height];
+    text_sprites(text, top_left, glyph_height, color, font)
+}
+
+/// Holds the font, `submit` labels every frame they should show
+pub struct LabelRenderer {
+    font: TextureHandle,
+    /// logical pixels, multiples of the font's cell height of 8 stay crisp
+    pub glyph_height: f32,
+    /// multiplies the alpha of labels something is in front of
+    pub occluded_alpha: f32,
+    /// world units in front of a label that don't occlude it,
+    /// so labels at an object's origin aren't hidden by the object itself
+    pub occlusion_bias: f32,
+}
+
+impl LabelRenderer {
+    /// uploads the font, panics when the textures array is full
+    pub fn new(app: &mut VkApp) -> Self {
+        let mut font = DecodedTexture::from_pixels(TextureType::Diffuse, ATLAS_WIDTH, ATLAS_HEIGHT, font_pixels());
+        font.sampler_desc = SamplerDesc::point().with_address_mode(vk::SamplerAddressMode::CLAMP_TO_EDGE);
+        Self {
+            font: app.load_decoded_texture("label font", font).expect("No room for the label font"),
+            glyph_height: 16.0,
+            occluded_alpha: 0.25,
+            occlusion_bias: 1.0,
+        }
+    }
+
+    /// the font's texture, for `text_sprites`
+    pub fn font(&self) -> TextureHandle {
+        self.font
+    }
+
+    /// draws `labels` next frame over the scene,
+    /// with `occluders` the ones out of the camera's line of sight fade
+    pub fn submit<P: Primitive>(&self, app: &mut VkApp, labels: &[Label], occluders: Option<&Bvh<P>>) {
+        let proj_view = app.camera.calc_proj_view();
+        let screen_size = app.get_ui_size();
+        let camera_pos = app.camera.translation;
+        for label in labels {
+            let Some(center) = project(&proj_view, label.world_pos, screen_size) else {
+                continue;
+            };
+            let mut color = label.color;
+            if let Some(occluders) = occluders {
+                let offset = Vector::new(label.world_pos[0], label.world_pos[1], label.world_pos[2]) - camera_pos;
+                let distance = offset.norm_sqr().sqrt();
+                let ray = Ray { origin: camera_pos, direction: offset / distance.max(1e-6) };
+                if occluders.raycast(&ray, distance - self.occlusion_bias).is_some() {
+                    color[3] *= self.occluded_alpha;
+                }
+            }
+            let text = app.strings.resolve(label.text);
+            app.sprite_renderer.submit(layout(text, center, self.glyph_height, color, self.font.index() as u32));
+        }
+    }
+}
+
+#[test]
+fn test_label() {
+    assert!(GLYPHS.len() as u32 <= ATLAS_COLUMNS * ATLAS_ROWS);
+    assert!(glyph_index('a') == glyph_index('A') && glyph_index('~') == glyph_index('?'));
+
+    // the 1 in the font's second cell, its stem straight down the middle
+    let font = font_pixels();
+    let alpha = |x: u32, y: u32| font[((y * ATLAS_WIDTH + x) * 4 + 3) as usize];
+    let one = glyph_index('1') as u32 * CELL_SIZE[0];
+    assert!((0..GLYPH_HEIGHT).all(|y| alpha(one + 2, y) == 255) && alpha(one + 2, GLYPH_HEIGHT) == 0);
+    assert!(alpha(one, 0) == 0 && alpha(one + 1, 1) == 255);
+    let [u, v, ..] = solid_uv_rect();
+    assert!(alpha((u * ATLAS_WIDTH as f32) as u32, (v * ATLAS_HEIGHT as f32) as u32) == 255);
+
+    // two characters centered on the point
+    let sprites: Vec<Sprite> = layout("ab", [100.0, 50.0], 16.0, [1.0; 4], 3).collect();
+    assert!(sprites.len() == 2 && sprites.iter().all(|sprite| sprite.size == [12.0, 16.0] && sprite.texture == 3));
+    assert!(sprites[0].position == [94.0, 50.0, 0.0] && sprites[1].position == [106.0, 50.0, 0.0]);
+}
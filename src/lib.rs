@@ -0,0 +1,44 @@
+// Vulkan engine built on ash. Applications implement `engine::App` and run it with `engine::Engine::run`,
+// the renderer and the systems below are public for anything the app loop doesn't cover
+
+pub mod engine;
+pub mod renderer;
+pub mod math;
+pub mod quantize;
+pub mod mesh_optimize;
+pub mod input;
+pub mod replay;
+pub mod camera;
+pub mod geometry;
+pub mod primitives;
+pub mod utils;
+pub mod allocator;
+pub mod animation;
+pub mod arena;
+pub mod assets;
+pub mod data_structures;
+pub mod pixels;
+pub mod meta;
+pub mod scene;
+pub mod scripting;
+pub mod config;
+pub mod cli;
+pub mod display;
+pub mod ui;
+pub mod events;
+pub mod light;
+pub mod weather;
+pub mod fog;
+pub mod particles;
+pub mod terrain;
+pub mod streaming;
+pub mod frame_pacing;
+pub mod suspend;
+pub mod jobs;
+pub mod gizmo;
+pub mod label;
+pub mod canvas;
+pub mod logging;
+pub mod transform;
+pub mod net;
+pub mod golden;
@@ -0,0 +1,195 @@
+// Light animation helpers, flicker and pulse curves, color temperatures
+// and a day/night cycle driving the sun and sky
+
+use std::f32::consts::TAU;
+
+use crate::math::Vector;
+
+#[derive(Clone, Copy, Debug)]
+pub struct DirectionalLight {
+    /// towards the light, normalized
+    pub direction: Vector,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    /// flat light added everywhere
+    pub ambient: f32,
+}
+
+impl Default for DirectionalLight {
+    fn default() -> Self {
+        Self {
+            direction: normalize(Vector::new(0.3, 1.0, -0.5)),
+            color: [1.0, 1.0, 1.0],
+            intensity: 1.0,
+            ambient: 0.1,
+        }
+    }
+}
+
+impl DirectionalLight {
+    /// direction, and color scaled by intensity with the ambient term in w,
+    /// as the shaders expect them
+    pub fn to_shader_vectors(&self) -> ([f32; 4], [f32; 4]) {
+        let [r, g, b] = self.color.map(|c| c * self.intensity);
+        (
+            [self.direction.x, self.direction.y, self.direction.z, 0.0],
+            [r, g, b, self.ambient],
+        )
+    }
+}
+
+fn normalize(v: Vector) -> Vector {
+    let norm = v.norm_sqr().sqrt();
+    Vector::new(v.x / norm, v.y / norm, v.z / norm)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+fn lerp_rgb(a: [f32; 3], b: [f32; 3], t: f32) -> [f32; 3] {
+    [lerp(a[0], b[0], t), lerp(a[1], b[1], t), lerp(a[2], b[2], t)]
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// integer hash to 0..=1
+fn hash(n: u32) -> f32 {
+    let mut n = n.wrapping_mul(0x27d4eb2d);
+    n ^= n >> 15;
+    n = n.wrapping_mul(0x85ebca6b);
+    n ^= n >> 13;
+    n as f32 / u32::MAX as f32
+}
+
+/// Smooth value noise in 0..=1 for fire and candle like flicker,
+/// `seed` decorrelates lights sharing a clock
+pub fn flicker(time: f32, speed: f32, seed: u32) -> f32 {
+    let t = time * speed;
+    let i = t.floor();
+    let a = hash(seed.wrapping_add(i as i32 as u32));
+    let b = hash(seed.wrapping_add(i as i32 as u32).wrapping_add(1));
+    lerp(a, b, smoothstep(0.0, 1.0, t - i))
+}
+
+/// sine between `min` and `max`, starting at `min`
+pub fn pulse(time: f32, period: f32, min: f32, max: f32) -> f32 {
+    let t = 0.5 - 0.5 * (time / period * TAU).cos();
+    lerp(min, max, t)
+}
+
+/// Blackbody color of a temperature in kelvin, normalized so the brightest channel is 1.
+/// Fitted curve, good for 1000K..=40000K
+pub fn color_temperature_to_rgb(kelvin: f32) -> [f32; 3] {
+    let t = kelvin.clamp(1000.0, 40000.0) / 100.0;
+
+    let r = if t <= 66.0 {
+        255.0
+    } else {
+        329.698_73 * (t - 60.0).powf(-0.133_204_76)
+    };
+    let g = if t <= 66.0 {
+        99.470_8 * t.ln() - 161.119_57
+    } else {
+        288.122_16 * (t - 60.0).powf(-0.075_514_85)
+    };
+    let b = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.517_73 * (t - 10.0).ln() - 305.044_8
+    };
+
+    let rgb = [r, g, b].map(|c: f32| c.clamp(0.0, 255.0) / 255.0);
+    let max = rgb[0].max(rgb[1]).max(rgb[2]);
+    rgb.map(|c| c / max)
+}
+
+/// Moves the sun around the x axis over a day, `time_of_day` 0 and 1 are midnight, 0.5 is noon
+pub struct DayNightCycle {
+    pub time_of_day: f32,
+    pub day_length_secs: f32,
+    pub paused: bool,
+    /// sky clear colors
+    pub day_sky: [f32; 3],
+    pub dusk_sky: [f32; 3],
+    pub night_sky: [f32; 3],
+}
+
+impl DayNightCycle {
+    pub fn new(time_of_day: f32, day_length_secs: f32) -> Self {
+        Self {
+            time_of_day,
+            day_length_secs,
+            paused: false,
+            day_sky: [0.45, 0.65, 0.9],
+            dusk_sky: [0.8, 0.45, 0.3],
+            night_sky: [0.0, 0.0, 0.05],
+        }
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        if !self.paused {
+            self.time_of_day = (self.time_of_day + dt / self.day_length_secs).fract();
+        }
+    }
+
+    /// towards the sun, below the horizon at night
+    pub fn sun_direction(&self) -> Vector {
+        let angle = (self.time_of_day - 0.25) * TAU;
+        // world y points down, tilted so noon light isn't straight down
+        normalize(Vector::new(angle.cos(), -angle.sin(), -0.3))
+    }
+
+    /// 1 at noon, 0 once the sun is below the horizon
+    fn sun_height(&self) -> f32 {
+        (-self.sun_direction().y).max(0.0)
+    }
+
+    pub fn sun_light(&self) -> DirectionalLight {
+        let height = self.sun_height();
+        // low sun is redder
+        let kelvin = lerp(2000.0, 6500.0, smoothstep(0.0, 0.5, height));
+        DirectionalLight {
+            direction: self.sun_direction(),
+            color: color_temperature_to_rgb(kelvin),
+            intensity: smoothstep(0.0, 0.1, height),
+            ambient: lerp(0.02, 0.1, smoothstep(-0.1, 0.3, -self.sun_direction().y)),
+        }
+    }
+
+    pub fn sky_color(&self) -> [f32; 4] {
+        let height = -self.sun_direction().y;
+        let sky = if height < 0.0 {
+            lerp_rgb(self.dusk_sky, self.night_sky, smoothstep(0.0, 0.2, -height))
+        } else {
+            lerp_rgb(self.dusk_sky, self.day_sky, smoothstep(0.0, 0.3, height))
+        };
+        [sky[0], sky[1], sky[2], 1.0]
+    }
+}
+
+#[test]
+fn test_light_curves() {
+    let white = color_temperature_to_rgb(6600.0);
+    assert!(white.iter().all(|&c| c > 0.95));
+    let candle = color_temperature_to_rgb(1900.0);
+    assert!(candle[0] == 1.0 && candle[2] < 0.1);
+
+    for i in 0..100 {
+        let time = i as f32 * 0.37;
+        assert!((0.0..=1.0).contains(&flicker(time, 8.0, 7)));
+        assert!((0.5..=2.0).contains(&pulse(time, 1.5, 0.5, 2.0)));
+    }
+    assert!(pulse(0.0, 1.0, 0.5, 2.0) == 0.5);
+
+    let mut cycle = DayNightCycle::new(0.5, 60.0);
+    assert!(cycle.sun_direction().y < -0.9 && cycle.sun_light().intensity == 1.0);
+    cycle.update(30.0);
+    assert!(cycle.time_of_day == 0.0);
+    assert!(cycle.sun_light().intensity == 0.0);
+}
@@ -0,0 +1,388 @@
+// The `log` crate's logger for the engine and apps on it. Records are filtered per subsystem,
+// the module under the crate root they're logged from (renderer, allocator, assets, input, ...),
+// the crate for other crates, at levels that can change while running. What passes goes to stderr,
+// into a ring buffer of recent records for the log overlay and to a rotated file when configured:
+//
+//     logging::init();
+//     logging::logger().configure(&config.logging);
+//     logging::logger().set_level(Some("renderer"), log::LevelFilter::Debug);
+//     logging::draw_recent(&mut canvas, [8.0, 8.0], 20, 8.0);
+//
+// `RUST_LOG`, e.g. "warn,renderer=debug", overrides the configured levels from the environment
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::{self, File},
+    io::{self, Write},
+    sync::{Mutex, OnceLock, RwLock},
+    time::Instant,
+};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde::Deserialize;
+
+use crate::canvas::Canvas;
+
+/// `module_path!` of the crate's root
+const CRATE_NAME: &str = "ash_engine";
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+#[derive(Clone, PartialEq, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// a level for every subsystem then overrides, e.g. "info,renderer=debug,winit=warn",
+    /// see `Levels::parse`
+    pub levels: String,
+    /// also written here, `None` logs to stderr only
+    pub file: Option<String>,
+    /// bytes the file grows to before it's moved to `<file>.1`, the older ones up a number
+    pub max_file_size: u64,
+    /// moved files kept, the oldest is deleted
+    pub max_rotated_files: u32,
+    /// recent records kept for the log overlay
+    pub history: usize,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            levels: "info".to_owned(),
+            file: None,
+            max_file_size: 0x100000,
+            max_rotated_files: 3,
+            history: 256,
+        }
+    }
+}
+
+/// "renderer" of "ash_engine::renderer::texture", "winit" of "winit::platform_impl"
+pub fn subsystem(target: &str) -> &str {
+    let path = target.strip_prefix(CRATE_NAME).and_then(|path| path.strip_prefix("::")).unwrap_or(target);
+    path.split("::").next().unwrap()
+}
+
+/// The level of each subsystem
+#[derive(Clone, PartialEq, Debug)]
+pub struct Levels {
+    pub default: LevelFilter,
+    /// subsystem names and their levels
+    pub overrides: Vec<(String, LevelFilter)>,
+}
+
+impl Levels {
+    /// comma separated "level" for every subsystem and "subsystem=level" for one, later ones win.
+    /// Subsystems may be written as module paths, "ash_engine::renderer" is "renderer"
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let parse_level = |level: &str| level.trim().parse::<LevelFilter>().map_err(|_| format!("Invalid log level \"{}\"", level.trim()));
+        let mut levels = Self { default: LevelFilter::Info, overrides: vec![] };
+        for directive in spec.split(',').filter(|directive| !directive.trim().is_empty()) {
+            match directive.split_once('=') {
+                Some((name, level)) => levels.set(Some(subsystem(name.trim())), parse_level(level)?),
+                None => levels.set(None, parse_level(directive)?),
+            }
+        }
+        Ok(levels)
+    }
+
+    /// `None` sets every subsystem's, dropping their overrides
+    pub fn set(&mut self, subsystem: Option<&str>, level: LevelFilter) {
+        match subsystem {
+            Some(subsystem) => {
+                self.overrides.retain(|(name, _)| name != subsystem);
+                self.overrides.push((subsystem.to_owned(), level));
+            }
+            None => {
+                self.default = level;
+                self.overrides.clear();
+            }
+        }
+    }
+
+    pub fn get(&self, subsystem: &str) -> LevelFilter {
+        self.overrides
+            .iter()
+            .find(|(name, _)| name == subsystem)
+            .map_or(self.default, |&(_, level)| level)
+    }
+
+    /// most verbose of any subsystem, what the `log` macros check before formatting
+    fn max(&self) -> LevelFilter {
+        self.overrides.iter().map(|&(_, level)| level).fold(self.default, Ord::max)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LogRecord {
+    /// seconds since the logger was created
+    pub time: f32,
+    pub level: Level,
+    pub subsystem: String,
+    pub message: String,
+}
+
+impl fmt::Display for LogRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:9.3} {:<5} {}: {}", self.time, self.level, self.subsystem, self.message)
+    }
+}
+
+/// Appends lines to `path`, moving it aside once it would grow past `max_size`
+struct RotatingFile {
+    path: String,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_rotated: u32,
+}
+
+impl RotatingFile {
+    fn open(path: &str, max_size: u64, max_rotated: u32) -> io::Result<Self> {
+        let file = File::options().create(true).append(true).open(path)?;
+        Ok(Self {
+            path: path.to_owned(),
+            size: file.metadata()?.len(),
+            file,
+            max_size,
+            max_rotated,
+        })
+    }
+
+    fn rotated_path(&self, index: u32) -> String {
+        format!("{}.{}", self.path, index)
+    }
+
+    /// `path.1` to `path.2` and so on, the oldest is overwritten, then `path` to `path.1`
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.max_rotated > 0 {
+            for index in (1..self.max_rotated).rev() {
+                let _ = fs::rename(self.rotated_path(index), self.rotated_path(index + 1));
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+        self.file = File::create(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.size > 0 && self.size + len > self.max_size {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += len;
+        Ok(())
+    }
+}
+
+struct History {
+    records: VecDeque<LogRecord>,
+    capacity: usize,
+}
+
+/// Install with `init`, the same one is returned by `logger`
+pub struct Logger {
+    start: Instant,
+    levels: RwLock<Levels>,
+    history: Mutex<History>,
+    file: Mutex<Option<RotatingFile>>,
+    /// `file`'s path and sizes as last configured, to reopen it only when they change
+    file_config: Mutex<Option<(String, u64, u32)>>,
+}
+
+impl Logger {
+    fn new() -> Self {
+        let config = LoggingConfig::default();
+        Self {
+            start: Instant::now(),
+            levels: RwLock::new(Levels::parse(&config.levels).unwrap()),
+            history: Mutex::new(History { records: VecDeque::new(), capacity: config.history }),
+            file: Mutex::new(None),
+            file_config: Mutex::new(None),
+        }
+    }
+
+    /// applies `config`, `RUST_LOG` takes precedence over its levels.
+    /// An invalid level or a file that can't be opened is logged and left as it was
+    pub fn configure(&self, config: &LoggingConfig) {
+        let env_levels = std::env::var("RUST_LOG").ok().and_then(|spec| {
+            Levels::parse(&spec).map_err(|err| log::error!("Ignoring RUST_LOG: {}", err)).ok()
+        });
+        match env_levels.map_or_else(|| Levels::parse(&config.levels), Ok) {
+            Ok(levels) => self.set_levels(levels),
+            Err(err) => log::error!("Ignoring log levels: {}", err),
+        }
+
+        {
+            let mut history = self.history.lock().unwrap();
+            history.capacity = config.history;
+            let excess = history.records.len().saturating_sub(config.history);
+            history.records.drain(..excess);
+        }
+
+        let file_config = config.file.clone().map(|path| (path, config.max_file_size, config.max_rotated_files));
+        let mut current = self.file_config.lock().unwrap();
+        if *current == file_config {
+            return;
+        }
+        let file = file_config.as_ref().and_then(|(path, max_size, max_rotated)| {
+            RotatingFile::open(path, *max_size, *max_rotated)
+                .map_err(|err| log::error!("Can't log to {}: {}", path, err))
+                .ok()
+        });
+        if file.is_some() || file_config.is_none() {
+            *self.file.lock().unwrap() = file;
+            *current = file_config;
+        }
+    }
+
+    pub fn levels(&self) -> Levels {
+        self.levels.read().unwrap().clone()
+    }
+
+    pub fn set_levels(&self, levels: Levels) {
+        log::set_max_level(levels.max());
+        *self.levels.write().unwrap() = levels;
+    }
+
+    /// `None` sets every subsystem's, e.g. from a console command
+    pub fn set_level(&self, subsystem: Option<&str>, level: LevelFilter) {
+        let mut levels = self.levels.write().unwrap();
+        levels.set(subsystem, level);
+        log::set_max_level(levels.max());
+    }
+
+    /// the last `count` records that passed the filter, oldest first
+    pub fn recent(&self, count: usize) -> Vec<LogRecord> {
+        let history = self.history.lock().unwrap();
+        history.records.iter().skip(history.records.len().saturating_sub(count)).cloned().collect()
+    }
+
+    fn push(&self, record: LogRecord) {
+        let line = record.to_string();
+        eprintln!("{}", line);
+        if let Some(file) = self.file.lock().unwrap().as_mut() {
+            // stderr still has it, logging the failure would recurse
+            if let Err(err) = file.write_line(&line) {
+                eprintln!("Can't write to {}: {}", file.path, err);
+            }
+        }
+
+        let mut history = self.history.lock().unwrap();
+        if history.capacity == 0 {
+            return;
+        }
+        if history.records.len() == history.capacity {
+            history.records.pop_front();
+        }
+        history.records.push_back(record);
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.levels.read().unwrap().get(subsystem(metadata.target()))
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        self.push(LogRecord {
+            time: self.start.elapsed().as_secs_f32(),
+            level: record.level(),
+            subsystem: subsystem(record.target()).to_owned(),
+            message: record.args().to_string(),
+        });
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+        if let Some(file) = self.file.lock().unwrap().as_mut() {
+            let _ = file.file.flush();
+        }
+    }
+}
+
+/// the engine's logger, whether or not `init` installed it
+pub fn logger() -> &'static Logger {
+    LOGGER.get_or_init(Logger::new)
+}
+
+/// installs `logger` with the default config unless a logger is installed already, safe to call again
+pub fn init() {
+    let logger = logger();
+    if log::set_logger(logger).is_ok() {
+        logger.configure(&LoggingConfig::default());
+    }
+}
+
+fn level_color(level: Level) -> [f32; 4] {
+    match level {
+        Level::Error => [1.0, 0.3, 0.3, 1.0],
+        Level::Warn => [1.0, 0.8, 0.2, 1.0],
+        Level::Info => [1.0; 4],
+        Level::Debug | Level::Trace => [0.6, 0.6, 0.6, 1.0],
+    }
+}
+
+/// the last `line_count` records over a translucent background, top left at `top_left`
+pub fn draw_recent(canvas: &mut Canvas, top_left: [f32; 2], line_count: usize, glyph_height: f32) {
+    let records = logger().recent(line_count);
+    if records.is_empty() {
+        return;
+    }
+    let lines: Vec<String> = records.iter().map(LogRecord::to_string).collect();
+    let width = lines.iter().map(|line| crate::label::text_width(line, glyph_height)).fold(0.0, f32::max);
+    let line_height = glyph_height + 2.0;
+    canvas.rect(top_left, [width + 8.0, line_height * lines.len() as f32 + 8.0], [0.0, 0.0, 0.0, 0.6]);
+    for (index, (record, line)) in records.iter().zip(&lines).enumerate() {
+        let position = [top_left[0] + 4.0, top_left[1] + 4.0 + index as f32 * line_height];
+        canvas.text(position, line, glyph_height, level_color(record.level));
+    }
+}
+
+#[test]
+fn test_logging() {
+    assert!(subsystem("ash_engine::renderer::texture") == "renderer" && subsystem("ash_engine") == "ash_engine");
+    assert!(subsystem("winit::platform_impl") == "winit" && subsystem("assets") == "assets");
+
+    let levels = Levels::parse("warn, ash_engine::renderer=debug,input=trace").unwrap();
+    assert!(levels.get("renderer") == LevelFilter::Debug && levels.get("input") == LevelFilter::Trace);
+    assert!(levels.get("allocator") == LevelFilter::Warn && levels.max() == LevelFilter::Trace);
+    assert!(Levels::parse("").unwrap().default == LevelFilter::Info);
+    assert!(Levels::parse("renderer=loud").is_err());
+
+    // filtered per subsystem, the history keeps the latest
+    let logger = Logger::new();
+    logger.set_levels(Levels::parse("warn,assets=debug").unwrap());
+    logger.history.lock().unwrap().capacity = 2;
+    logger.set_level(Some("renderer"), LevelFilter::Error);
+    let log = |target: &str, level: Level, message: &str| {
+        logger.log(&Record::builder().target(target).level(level).args(format_args!("{}", message)).build());
+    };
+    log("ash_engine::assets", Level::Debug, "loaded");
+    log("ash_engine::renderer", Level::Warn, "dropped");
+    log("ash_engine::input", Level::Info, "dropped");
+    log("ash_engine::input", Level::Error, "failed");
+    log("ash_engine::allocator::debug", Level::Warn, "leaked");
+    let recent = logger.recent(8);
+    assert!(recent.len() == 2 && recent[0].message == "failed" && recent[1].subsystem == "allocator");
+    assert!(recent[0].to_string().ends_with("ERROR input: failed"));
+
+    // rotated once the file would grow past its size, the oldest dropped
+    let dir = std::env::temp_dir().join(format!("ash_engine_logging_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("engine.log").to_str().unwrap().to_owned();
+    let mut file = RotatingFile::open(&path, 8, 2).unwrap();
+    for line in ["first", "second", "third", "fourth"] {
+        file.write_line(line).unwrap();
+    }
+    file.file.flush().unwrap();
+    let read = |path: &str| fs::read_to_string(path).unwrap();
+    assert!(read(&path) == "fourth\n" && read(&format!("{}.1", path)) == "third\n" && read(&format!("{}.2", path)) == "second\n");
+    fs::remove_dir_all(&dir).unwrap();
+}
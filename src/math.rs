@@ -1,6 +1,22 @@
-//implement own sin cos
 use std::ops::*;
 
+pub mod deterministic;
+pub mod simd;
+
+/// platform independent with the `deterministic-math` feature, see deterministic.rs
+#[cfg(feature = "deterministic-math")]
+use deterministic as float;
+
+/// std's, may differ in the last bits between platforms
+#[cfg(not(feature = "deterministic-math"))]
+mod float {
+    pub fn sin_cos(x: f32) -> (f32, f32) { x.sin_cos() }
+    pub fn sin(x: f32) -> f32 { x.sin() }
+    pub fn atan2(y: f32, x: f32) -> f32 { y.atan2(x) }
+    pub fn asin(x: f32) -> f32 { x.asin() }
+    pub fn acos(x: f32) -> f32 { x.acos() }
+}
+
 //Plan: Explore R3,3 bivector generator basis
 //generates 6 shears, 3 pseudo-projections, 3 scales, 3 translation, 3 rotations
 
@@ -9,6 +25,7 @@ use std::ops::*;
 // Standard, for game logic
 
 //row_major
+#[repr(C)]
 #[derive(Clone, Copy, Default)]
 pub struct Mat {
     r0c0: f32,
@@ -32,6 +49,244 @@ pub struct Mat {
     r3c3: f32,
 }
 
+impl Mat {
+    /// clip space position of a world space point
+    pub fn transform_point(&self, point: Vector) -> [f32; 4] {
+        let Vector { x, y, z } = point;
+        [
+            self.r0c0 * x + self.r0c1 * y + self.r0c2 * z + self.r0c3,
+            self.r1c0 * x + self.r1c1 * y + self.r1c2 * z + self.r1c3,
+            self.r2c0 * x + self.r2c1 * y + self.r2c2 * z + self.r2c3,
+            self.r3c0 * x + self.r3c1 * y + self.r3c2 * z + self.r3c3,
+        ]
+    }
+
+    /// `None` when singular
+    pub fn inverse(&self) -> Option<Mat> {
+        let mut rows = [
+            [self.r0c0, self.r0c1, self.r0c2, self.r0c3],
+            [self.r1c0, self.r1c1, self.r1c2, self.r1c3],
+            [self.r2c0, self.r2c1, self.r2c2, self.r2c3],
+            [self.r3c0, self.r3c1, self.r3c2, self.r3c3],
+        ];
+        let mut inverse = [[0.0; 4]; 4];
+        for (i, row) in inverse.iter_mut().enumerate() {
+            row[i] = 1.0;
+        }
+
+        // gauss jordan with partial pivoting
+        for column in 0..4 {
+            let pivot = (column..4).max_by(|&a, &b| rows[a][column].abs().total_cmp(&rows[b][column].abs())).unwrap();
+            if rows[pivot][column].abs() < 1e-12 {
+                return None;
+            }
+            rows.swap(column, pivot);
+            inverse.swap(column, pivot);
+
+            let scale = 1.0 / rows[column][column];
+            for c in 0..4 {
+                rows[column][c] *= scale;
+                inverse[column][c] *= scale;
+            }
+            for row in 0..4 {
+                if row == column {
+                    continue;
+                }
+                let factor = rows[row][column];
+                for c in 0..4 {
+                    rows[row][c] -= factor * rows[column][c];
+                    inverse[row][c] -= factor * inverse[column][c];
+                }
+            }
+        }
+
+        let [r0, r1, r2, r3] = inverse;
+        Some(Mat {
+            r0c0: r0[0], r0c1: r0[1], r0c2: r0[2], r0c3: r0[3],
+            r1c0: r1[0], r1c1: r1[1], r1c2: r1[2], r1c3: r1[3],
+            r2c0: r2[0], r2c1: r2[1], r2c2: r2[2], r2c3: r2[3],
+            r3c0: r3[0], r3c1: r3[1], r3c2: r3[2], r3c3: r3[3],
+        })
+    }
+}
+
+/// Half line from `origin` along the unit `direction`
+#[derive(Clone, Copy, Debug)]
+pub struct Ray {
+    pub origin: Vector,
+    pub direction: Vector,
+}
+
+impl Ray {
+    /// from the near plane through a point in normalized device coordinates, e.g. the cursor's,
+    /// `None` when `proj_view` is singular. Works for reversed depth too
+    pub fn from_ndc(proj_view: &Mat, ndc: [f32; 2]) -> Option<Ray> {
+        let inverse = proj_view.inverse()?;
+        // w is the inverse of the clip w, so the larger one is nearer to the eye
+        let unproject = |depth| {
+            let [x, y, z, w] = inverse.transform_point(Vector::new(ndc[0], ndc[1], depth));
+            (Vector::new(x, y, z) / w, w)
+        };
+        let (mut near, near_w) = unproject(0.0);
+        let (mut far, far_w) = unproject(1.0);
+        if far_w.abs() > near_w.abs() {
+            std::mem::swap(&mut near, &mut far);
+        }
+        Some(Ray {
+            origin: near,
+            direction: (far - near).normalized(),
+        })
+    }
+
+    pub fn at(&self, t: f32) -> Vector {
+        self.origin + self.direction * t
+    }
+
+    /// distance along the ray to the plane through `point` facing `normal`,
+    /// `None` when the ray is parallel to it or points away
+    pub fn intersect_plane(&self, point: Vector, normal: Vector) -> Option<f32> {
+        let denominator = self.direction.dot(&normal);
+        if denominator.abs() < 1e-6 {
+            return None;
+        }
+        let t = (point - self.origin).dot(&normal) / denominator;
+        (t >= 0.0).then_some(t)
+    }
+
+    /// distances along the ray and along the line through `point` with unit `direction`
+    /// to their closest points, `None` when they're parallel
+    pub fn closest_to_line(&self, point: Vector, direction: Vector) -> Option<(f32, f32)> {
+        let along = self.direction.dot(&direction);
+        let denominator = 1.0 - along * along;
+        if denominator < 1e-6 {
+            return None;
+        }
+        let offset = point - self.origin;
+        let ray_offset = offset.dot(&self.direction);
+        let line_offset = offset.dot(&direction);
+        Some((
+            (ray_offset - along * line_offset) / denominator,
+            (along * ray_offset - line_offset) / denominator,
+        ))
+    }
+
+    /// distance along the ray to where it enters `aabb`, 0 from inside, `None` when it misses
+    pub fn intersect_aabb(&self, aabb: &Aabb) -> Option<f32> {
+        let origin = [self.origin.x, self.origin.y, self.origin.z];
+        let direction = [self.direction.x, self.direction.y, self.direction.z];
+        let min = [aabb.min.x, aabb.min.y, aabb.min.z];
+        let max = [aabb.max.x, aabb.max.y, aabb.max.z];
+
+        let (mut near, mut far) = (0.0_f32, f32::INFINITY);
+        for axis in 0..3 {
+            // infinities for axis parallel rays, which only hit when between the slab's planes
+            let inverse = 1.0 / direction[axis];
+            let t0 = (min[axis] - origin[axis]) * inverse;
+            let t1 = (max[axis] - origin[axis]) * inverse;
+            if t0.is_nan() || t1.is_nan() {
+                continue;
+            }
+            near = near.max(t0.min(t1));
+            far = far.min(t0.max(t1));
+        }
+        (near <= far).then_some(near)
+    }
+}
+
+/// Axis aligned box
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub min: Vector,
+    pub max: Vector,
+}
+
+impl Aabb {
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Vector::new(self.min.x.min(other.min.x), self.min.y.min(other.min.y), self.min.z.min(other.min.z)),
+            max: Vector::new(self.max.x.max(other.max.x), self.max.y.max(other.max.y), self.max.z.max(other.max.z)),
+        }
+    }
+
+    pub fn contains(&self, other: &Aabb) -> bool {
+        self.min.x <= other.min.x && self.min.y <= other.min.y && self.min.z <= other.min.z
+            && other.max.x <= self.max.x && other.max.y <= self.max.y && other.max.z <= self.max.z
+    }
+
+    /// touching counts
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.x <= other.max.x && other.min.x <= self.max.x
+            && self.min.y <= other.max.y && other.min.y <= self.max.y
+            && self.min.z <= other.max.z && other.min.z <= self.max.z
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let size = self.max - self.min;
+        2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+    }
+
+    /// grown by `margin` on every side
+    pub fn expanded(&self, margin: f32) -> Aabb {
+        let margin = Vector::new(margin, margin, margin);
+        Aabb { min: self.min - margin, max: self.max + margin }
+    }
+}
+
+/// Clip space bounds of a projection as world space planes,
+/// a plane (a, b, c, d) has a * x + b * y + c * z + d >= 0 on the inside
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    planes: [[f32; 4]; 6],
+}
+
+impl Frustum {
+    /// for 0 to 1 clip space depth
+    pub fn from_proj_view(proj_view: &Mat) -> Self {
+        let m = proj_view;
+        let row0 = [m.r0c0, m.r0c1, m.r0c2, m.r0c3];
+        let row1 = [m.r1c0, m.r1c1, m.r1c2, m.r1c3];
+        let row2 = [m.r2c0, m.r2c1, m.r2c2, m.r2c3];
+        let row3 = [m.r3c0, m.r3c1, m.r3c2, m.r3c3];
+        let add = |a: [f32; 4], b: [f32; 4]| [a[0] + b[0], a[1] + b[1], a[2] + b[2], a[3] + b[3]];
+        let sub = |a: [f32; 4], b: [f32; 4]| [a[0] - b[0], a[1] - b[1], a[2] - b[2], a[3] - b[3]];
+
+        Self {
+            planes: [
+                add(row3, row0),
+                sub(row3, row0),
+                add(row3, row1),
+                sub(row3, row1),
+                row2,
+                sub(row3, row2),
+            ],
+        }
+    }
+
+    /// conservative, boxes near the frustum's corners can pass without intersecting
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|&[a, b, c, d]| {
+            // the corner furthest along the plane's normal
+            let x = if a >= 0.0 { aabb.max.x } else { aabb.min.x };
+            let y = if b >= 0.0 { aabb.max.y } else { aabb.min.y };
+            let z = if c >= 0.0 { aabb.max.z } else { aabb.min.z };
+            a * x + b * y + c * z + d >= 0.0
+        })
+    }
+
+    /// planes as `[a, b, c, d]` with `a * x + b * y + c * z + d >= 0` inside, not normalized
+    pub fn get_planes(&self) -> &[[f32; 4]; 6] {
+        &self.planes
+    }
+
+    pub fn intersects_sphere(&self, center: Vector, radius: f32) -> bool {
+        self.planes.iter().all(|&[a, b, c, d]| {
+            a * center.x + b * center.y + c * center.z + d >= -radius * (a * a + b * b + c * c).sqrt()
+        })
+    }
+}
+
+// column major, laid out like a glsl mat4x3 for instance data
+#[repr(C)]
 #[derive(Clone, Copy, Debug)]
 pub struct ModelMat {
     r0c0: f32,
@@ -93,6 +348,39 @@ impl ModelMat {
         }
     }
 
+    pub fn translation(&self) -> Vector {
+        Vector::new(self.r0c3, self.r1c3, self.r2c3)
+    }
+
+    /// the transformed `axis` basis vector, 0 to 2 for x to z
+    pub fn axis(&self, axis: usize) -> Vector {
+        match axis {
+            0 => Vector::new(self.r0c0, self.r1c0, self.r2c0),
+            1 => Vector::new(self.r0c1, self.r1c1, self.r2c1),
+            2 => Vector::new(self.r0c2, self.r1c2, self.r2c2),
+            _ => panic!("No axis {}", axis),
+        }
+    }
+
+    /// world to view space of an eye at `eye` whose view x, y and z axes are the orthonormal
+    /// `right`, `down` and `forward`. A mirrored basis mirrors the view, like cube map faces do
+    pub fn look_along(eye: Vector, right: Vector, down: Vector, forward: Vector) -> Self {
+        ModelMat {
+            r0c0: right.x,
+            r0c1: right.y,
+            r0c2: right.z,
+            r0c3: -right.dot(&eye),
+            r1c0: down.x,
+            r1c1: down.y,
+            r1c2: down.z,
+            r1c3: -down.dot(&eye),
+            r2c0: forward.x,
+            r2c1: forward.y,
+            r2c2: forward.z,
+            r2c3: -forward.dot(&eye),
+        }
+    }
+
     pub fn scale(&mut self, x: f32, y: f32, z: f32) -> &mut Self {
         self.r0c0 *= x;
         self.r0c1 *= x;
@@ -124,7 +412,7 @@ impl ModelMat {
         let xz_xz = xz * xz;
         let yx_yx = yx * yx;
 
-        let (sin, cos) = angle.sin_cos();
+        let (sin, cos) = float::sin_cos(angle);
         let one_sub_cos = 1.0 - cos;
 
         let zy_sin = zy * sin;
@@ -182,12 +470,20 @@ impl ModelMat {
         self
     }
 
-    pub fn project(&self, aspect_ratio: f32, near_z: f32, far_z: f32) -> Mat {
+    /// depth goes from 0 at `near_z` to 1 at `far_z`, or from 1 to 0 when `reverse_z`,
+    /// which spreads a float depth buffer's precision evenly over distance
+    pub fn project(&self, aspect_ratio: f32, near_z: f32, far_z: f32, reverse_z: bool) -> Mat {
         let two_near_z = 2.0 * near_z;
     
         let proj_r0c0 = two_near_z / aspect_ratio;
         let proj_r1c1 = two_near_z;
-        let proj_r2c2 = far_z / (far_z - near_z);
+        let (proj_r2c2, proj_r2c3) = if reverse_z {
+            let scale = near_z / (far_z - near_z);
+            (-scale, scale * far_z)
+        } else {
+            let scale = far_z / (far_z - near_z);
+            (scale, -scale * near_z)
+        };
     
         Mat {
             r0c0: proj_r0c0 * self.r0c0,
@@ -203,7 +499,7 @@ impl ModelMat {
             r2c0: proj_r2c2 * self.r2c0,
             r2c1: proj_r2c2 * self.r2c1,
             r2c2: proj_r2c2 * self.r2c2,
-            r2c3: proj_r2c2 * (self.r2c3 - near_z),
+            r2c3: proj_r2c2 * self.r2c3 + proj_r2c3,
     
             r3c0: self.r2c0,
             r3c1: self.r2c1,
@@ -212,6 +508,35 @@ impl ModelMat {
         }
     }
 
+    /// view space box of the given half extents in front of the near plane to clip space
+    pub fn project_orthographic(&self, half_width: f32, half_height: f32, near_z: f32, far_z: f32) -> Mat {
+        let proj_r0c0 = 1.0 / half_width;
+        let proj_r1c1 = 1.0 / half_height;
+        let proj_r2c2 = 1.0 / (far_z - near_z);
+
+        Mat {
+            r0c0: proj_r0c0 * self.r0c0,
+            r0c1: proj_r0c0 * self.r0c1,
+            r0c2: proj_r0c0 * self.r0c2,
+            r0c3: proj_r0c0 * self.r0c3,
+
+            r1c0: proj_r1c1 * self.r1c0,
+            r1c1: proj_r1c1 * self.r1c1,
+            r1c2: proj_r1c1 * self.r1c2,
+            r1c3: proj_r1c1 * self.r1c3,
+
+            r2c0: proj_r2c2 * self.r2c0,
+            r2c1: proj_r2c2 * self.r2c1,
+            r2c2: proj_r2c2 * self.r2c2,
+            r2c3: proj_r2c2 * (self.r2c3 - near_z),
+
+            r3c0: 0.0,
+            r3c1: 0.0,
+            r3c2: 0.0,
+            r3c3: 1.0,
+        }
+    }
+
     pub fn from(scale: Vector, rotation: Rotor, translation: Vector) -> Self {
         let _1xz = rotation._1 * rotation.xz;
         let _1yx = rotation._1 * rotation.yx;
@@ -269,6 +594,22 @@ impl Vector {
         self.x * self.x + self.y * self.y + self.z * self.z
     }
 
+    pub fn dot(&self, rhs: &Vector) -> f32 {
+        self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
+    }
+
+    pub fn cross(&self, rhs: &Vector) -> Vector {
+        Vector {
+            x: self.y * rhs.z - self.z * rhs.y,
+            y: self.z * rhs.x - self.x * rhs.z,
+            z: self.x * rhs.y - self.y * rhs.x,
+        }
+    }
+
+    pub fn normalized(&self) -> Vector {
+        *self / self.norm_sqr().sqrt()
+    }
+
     pub fn wedge(&self, rhs: &Vector) -> Bivector {
         Bivector {
             yx: self.x * rhs.y - self.y * rhs.x,
@@ -278,6 +619,18 @@ impl Vector {
     }
 }
 
+impl Add for Vector {
+    type Output = Vector;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self {
+            x: self.x + rhs.x,
+            y: self.y + rhs.y,
+            z: self.z + rhs.z,
+        }
+    }
+}
+
 impl Sub for Vector {
     type Output = Vector;
 
@@ -366,8 +719,8 @@ impl Bivector {
             };
         }
         let norm = norm_sqr.sqrt();
-        let cos = norm.cos();
-        let sin_norm = norm.sin() / norm;
+        let (sin, cos) = float::sin_cos(norm);
+        let sin_norm = sin / norm;
 
         Rotor {
             _1: cos,
@@ -436,11 +789,113 @@ impl Rotor {
         Self { _1, yx, zy, xz }
     }
 
+    /// rotates by `angle` around the unit `axis`, counterclockwise looking against the axis
+    pub fn from_axis_angle(axis: Vector, angle: f32) -> Rotor {
+        (Bivector::new(axis.z, axis.x, axis.y) * (-0.5 * angle)).exp()
+    }
+
     pub fn norm_sqr(&self) -> f32 {
         self._1 * self._1 + self.yx * self.yx + self.zy * self.zy + self.xz * self.xz
     }
+
+    /// (1, yx, zy, xz)
+    pub fn components(&self) -> (f32, f32, f32, f32) {
+        (self._1, self.yx, self.zy, self.xz)
+    }
+
+    pub fn dot(&self, rhs: &Rotor) -> f32 {
+        self._1 * rhs._1 + self.yx * rhs.yx + self.zy * rhs.zy + self.xz * rhs.xz
+    }
+
+    /// normalized linear interpolation, along the shorter arc between `self` and `rhs`
+    pub fn nlerp(&self, rhs: &Rotor, t: f32) -> Rotor {
+        // r and -r rotate the same, pick the one closer to self
+        let rhs_t = if self.dot(rhs) < 0.0 { -t } else { t };
+        let mut rotor = Rotor {
+            _1: self._1 * (1.0 - t) + rhs._1 * rhs_t,
+            yx: self.yx * (1.0 - t) + rhs.yx * rhs_t,
+            zy: self.zy * (1.0 - t) + rhs.zy * rhs_t,
+            xz: self.xz * (1.0 - t) + rhs.xz * rhs_t,
+        };
+        rotor /= rotor.norm_sqr().sqrt();
+        rotor
+    }
+
+    /// spherical linear interpolation, constant angular speed along the shorter arc
+    /// between `self` and `rhs`, both unit
+    pub fn slerp(&self, rhs: &Rotor, t: f32) -> Rotor {
+        let dot = self.dot(rhs);
+        let (rhs_sign, cos) = if dot < 0.0 { (-1.0, -dot) } else { (1.0, dot) };
+        // nearly equal, the arc is a line
+        if cos > 0.9995 {
+            return self.nlerp(rhs, t);
+        }
+        let angle = float::acos(cos);
+        let sin = float::sin(angle);
+        let self_t = float::sin((1.0 - t) * angle) / sin;
+        let rhs_t = float::sin(t * angle) / sin * rhs_sign;
+        Rotor {
+            _1: self._1 * self_t + rhs._1 * rhs_t,
+            yx: self.yx * self_t + rhs.yx * rhs_t,
+            zy: self.zy * self_t + rhs.zy * rhs_t,
+            xz: self.xz * self_t + rhs.xz * rhs_t,
+        }
+    }
+
+    pub fn identity() -> Rotor {
+        Rotor::new(1.0, 0.0, 0.0, 0.0)
+    }
+
+    /// unit axis and angle in [0, pi] for `from_axis_angle`, the x axis for the identity
+    pub fn to_axis_angle(&self) -> (Vector, f32) {
+        // r and -r rotate the same, the non negative scalar gives the smaller angle
+        let sign = if self._1 < 0.0 { -1.0 } else { 1.0 };
+        let half_angle = float::acos((self._1 * sign).min(1.0));
+        let sin = float::sin(half_angle);
+        if sin < 1e-6 {
+            return (Vector::new(1.0, 0.0, 0.0), 0.0);
+        }
+        let axis = Vector::new(self.zy, self.xz, self.yx) * (-sign / sin);
+        (axis, 2.0 * half_angle)
+    }
+
+    /// `v` rotated
+    pub fn rotate(&self, v: Vector) -> Vector {
+        let mat = ModelMat::from(Vector::new(1.0, 1.0, 1.0), *self, Vector::new(0.0, 0.0, 0.0));
+        mat.axis(0) * v.x + mat.axis(1) * v.y + mat.axis(2) * v.z
+    }
+
+    /// turns +z to the direction of a camera with these angles, see `Camera::forward`
+    pub fn from_yaw_pitch(z_x_angle: f32, y_xz_angle: f32) -> Rotor {
+        // pitches then yaws, positive pitch turns z towards y, against the x axis' turning direction
+        Rotor::from_axis_angle(Vector::new(1.0, 0.0, 0.0), -y_xz_angle)
+            * Rotor::from_axis_angle(Vector::new(0.0, 1.0, 0.0), z_x_angle)
+    }
+
+    /// `z_x_angle` and `y_xz_angle` of the direction +z turns to, rolls around it are lost
+    pub fn to_yaw_pitch(&self) -> (f32, f32) {
+        let forward = self.rotate(Vector::new(0.0, 0.0, 1.0));
+        (float::atan2(forward.x, forward.z), float::asin(forward.y.clamp(-1.0, 1.0)))
+    }
+
+    /// shortest rotation turning the direction of `from` to the direction of `to`
+    pub fn from_rotation_between(from: Vector, to: Vector) -> Rotor {
+        let (from, to) = (from.normalized(), to.normalized());
+        let cos = from.dot(&to).clamp(-1.0, 1.0);
+        let axis = from.cross(&to);
+        if axis.norm_sqr() > 1e-12 {
+            return Rotor::from_axis_angle(axis.normalized(), float::acos(cos));
+        }
+        if cos > 0.0 {
+            return Rotor::identity();
+        }
+        // opposite, any axis perpendicular to `from` does
+        let other = if from.x.abs() < 0.9 { Vector::new(1.0, 0.0, 0.0) } else { Vector::new(0.0, 1.0, 0.0) };
+        Rotor::from_axis_angle(from.cross(&other).normalized(), std::f32::consts::PI)
+    }
 }
 
+/// `a * b` rotates by `a` then by `b`
 impl Mul for Rotor {
     type Output = Rotor;
 
@@ -471,3 +926,165 @@ impl DivAssign<f32> for Rotor {
         self.xz /= rhs;
     }
 }
+
+#[test]
+fn test_project_orthographic() {
+    let proj = ModelMat::identity()
+        .translate(-1.0, 0.0, 0.0)
+        .project_orthographic(2.0, 4.0, 1.0, 11.0);
+    let apply = |x: f32, y: f32, z: f32| [
+        proj.r0c0 * x + proj.r0c1 * y + proj.r0c2 * z + proj.r0c3,
+        proj.r1c0 * x + proj.r1c1 * y + proj.r1c2 * z + proj.r1c3,
+        proj.r2c0 * x + proj.r2c1 * y + proj.r2c2 * z + proj.r2c3,
+        proj.r3c0 * x + proj.r3c1 * y + proj.r3c2 * z + proj.r3c3,
+    ];
+
+    assert!(apply(1.0, 0.0, 1.0) == [0.0, 0.0, 0.0, 1.0]);
+    assert!(apply(3.0, -4.0, 11.0) == [1.0, -1.0, 1.0, 1.0]);
+    assert!(apply(-1.0, 2.0, 6.0) == [-1.0, 0.5, 0.5, 1.0]);
+}
+
+#[test]
+fn test_rotor_nlerp() {
+    let from = Bivector::new(0.0, 0.0, 0.0).exp();
+    let to = Bivector::new(0.0, 0.0, 0.6).exp();
+
+    let halfway = from.nlerp(&to, 0.5).components();
+    let expected = Bivector::new(0.0, 0.0, 0.3).exp().components();
+    assert!((halfway.0 - expected.0).abs() < 1e-5 && (halfway.3 - expected.3).abs() < 1e-5);
+
+    // the negated rotor is the same rotation, still the short way round
+    let negated = Rotor::new(-expected.0 * 2.0, 0.0, 0.0, -expected.3 * 2.0);
+    let (scalar, _, _, xz) = from.nlerp(&negated, 1.0).components();
+    assert!((scalar - expected.0).abs() < 1e-5 && (xz - expected.3).abs() < 1e-5);
+    assert!((from.nlerp(&to, 0.25).norm_sqr() - 1.0).abs() < 1e-5);
+}
+
+#[test]
+fn test_frustum_culling() {
+    for reverse_z in [false, true] {
+        frustum_culling(reverse_z);
+    }
+}
+
+#[cfg(test)]
+fn frustum_culling(reverse_z: bool) {
+    let proj_view = ModelMat::identity().project(1.0, 1.0, 100.0, reverse_z);
+    let frustum = Frustum::from_proj_view(&proj_view);
+    let aabb = |min: (f32, f32, f32), max: (f32, f32, f32)| Aabb {
+        min: Vector::new(min.0, min.1, min.2),
+        max: Vector::new(max.0, max.1, max.2),
+    };
+
+    assert!(frustum.intersects_aabb(&aabb((-1.0, -1.0, 10.0), (1.0, 1.0, 12.0))));
+    // straddling the near plane and the left side
+    assert!(frustum.intersects_aabb(&aabb((-1.0, -1.0, -1.0), (1.0, 1.0, 2.0))));
+    assert!(frustum.intersects_aabb(&aabb((-100.0, -1.0, 10.0), (-2.0, 1.0, 12.0))));
+
+    assert!(!frustum.intersects_aabb(&aabb((-1.0, -1.0, -12.0), (1.0, 1.0, -10.0))));
+    assert!(!frustum.intersects_aabb(&aabb((-1.0, -1.0, 101.0), (1.0, 1.0, 110.0))));
+    assert!(!frustum.intersects_aabb(&aabb((20.0, -1.0, 10.0), (30.0, 1.0, 12.0))));
+    assert!(!frustum.intersects_aabb(&aabb((-1.0, 20.0, 10.0), (1.0, 30.0, 12.0))));
+
+    assert!(frustum.intersects_sphere(Vector::new(0.0, 0.0, 10.0), 1.0));
+    assert!(frustum.intersects_sphere(Vector::new(20.0, 0.0, 10.0), 15.0));
+    assert!(!frustum.intersects_sphere(Vector::new(20.0, 0.0, 10.0), 1.0));
+    assert!(!frustum.intersects_sphere(Vector::new(0.0, 0.0, -10.0), 1.0));
+}
+
+
+#[test]
+fn test_picking_ray() {
+    let proj_view = ModelMat::identity()
+        .translate(0.0, 0.0, 5.0)
+        .project(1.5, 0.5, 100.0, false);
+    let inverse = proj_view.inverse().unwrap();
+    let [x, y, z, w] = inverse.transform_point(Vector::new(0.5, -0.25, 0.75));
+    let [x, y, z, w] = proj_view.transform_point(Vector::new(x / w, y / w, z / w));
+    assert!((x / w - 0.5).abs() < 1e-4 && (y / w + 0.25).abs() < 1e-4 && (z / w - 0.75).abs() < 1e-4);
+
+    // straight ahead through the center of the screen
+    let ray = Ray::from_ndc(&proj_view, [0.0, 0.0]).unwrap();
+    assert!((ray.origin.z + 4.5).abs() < 1e-4 && (ray.direction.z - 1.0).abs() < 1e-6);
+    let t = ray.intersect_plane(Vector::new(0.0, 0.0, 1.0), Vector::new(0.0, 0.0, -1.0)).unwrap();
+    assert!((t - 5.5).abs() < 1e-4);
+    // the ray crosses the x axis line at x = 0, 5 units from the near plane
+    let (ray_t, line_t) = ray.closest_to_line(Vector::new(2.0, 0.0, 0.5), Vector::new(1.0, 0.0, 0.0)).unwrap();
+    assert!((ray_t - 5.0).abs() < 1e-4 && (line_t + 2.0).abs() < 1e-4);
+
+    // reversed depth still starts on the near plane
+    let reversed = ModelMat::identity()
+        .translate(0.0, 0.0, 5.0)
+        .project(1.5, 0.5, 100.0, true);
+    let reversed_ray = Ray::from_ndc(&reversed, [0.3, 0.2]).unwrap();
+    let ray = Ray::from_ndc(&proj_view, [0.3, 0.2]).unwrap();
+    assert!((reversed_ray.origin - ray.origin).norm_sqr() < 1e-8);
+    assert!((reversed_ray.direction - ray.direction).norm_sqr() < 1e-8);
+
+    // right handed, z turns towards x around y
+    let rotation = Rotor::from_axis_angle(Vector::new(0.0, 1.0, 0.0), std::f32::consts::FRAC_PI_2);
+    let z = ModelMat::from(Vector::new(1.0, 1.0, 1.0), rotation, Vector::new(0.0, 0.0, 0.0)).axis(2);
+    assert!((z.x - 1.0).abs() < 1e-6 && z.z.abs() < 1e-6);
+}
+
+#[test]
+fn test_reverse_z_precision() {
+    let (near_z, far_z) = (0.1, 10_000.0);
+    let depth = |z: f32, reverse_z: bool| {
+        let [_, _, depth, w] = ModelMat::identity()
+            .project(1.0, near_z, far_z, reverse_z)
+            .transform_point(Vector::new(0.0, 0.0, z));
+        depth / w
+    };
+    for reverse_z in [false, true] {
+        let (near_depth, far_depth) = if reverse_z { (1.0, 0.0) } else { (0.0, 1.0) };
+        assert!((depth(near_z, reverse_z) - near_depth).abs() < 1e-6);
+        assert!((depth(far_z, reverse_z) - far_depth).abs() < 1e-6);
+    }
+
+    // a scene of surfaces half a unit apart, rounded to a 32 bit float depth buffer
+    let distances = (8..40).map(|i| i as f32 * 250.0);
+    let resolved = |reverse_z: bool| distances.clone()
+        .filter(|&z| depth(z, reverse_z) != depth(z + 0.5, reverse_z))
+        .count();
+    // standard depth has run out of precision that far away
+    assert!(resolved(false) == 0);
+    assert!(resolved(true) == distances.len());
+}
+
+#[test]
+fn test_rotor_conversions() {
+    let close = |a: Vector, b: Vector| (a - b).norm_sqr() < 1e-8;
+    let (x, y, z) = (Vector::new(1.0, 0.0, 0.0), Vector::new(0.0, 1.0, 0.0), Vector::new(0.0, 0.0, 1.0));
+
+    let rotation = Rotor::from_axis_angle(Vector::new(1.0, 2.0, -2.0) / 3.0, 2.0);
+    let (axis, angle) = rotation.to_axis_angle();
+    assert!(close(axis, Vector::new(1.0, 2.0, -2.0) / 3.0) && (angle - 2.0).abs() < 1e-5);
+    // the negated rotor gives the same rotation back
+    let (scalar, yx, zy, xz) = rotation.components();
+    let (axis, angle) = Rotor::new(-scalar, -yx, -zy, -xz).to_axis_angle();
+    assert!(close(axis, Vector::new(1.0, 2.0, -2.0) / 3.0) && (angle - 2.0).abs() < 1e-5);
+    assert!(Rotor::identity().to_axis_angle().1 == 0.0);
+
+    // matches `Camera::forward`
+    let (yaw, pitch) = (0.7_f32, 0.3_f32);
+    let forward = Vector::new(yaw.sin() * pitch.cos(), pitch.sin(), yaw.cos() * pitch.cos());
+    let rotation = Rotor::from_yaw_pitch(yaw, pitch);
+    assert!(close(rotation.rotate(z), forward));
+    let (to_yaw, to_pitch) = rotation.to_yaw_pitch();
+    assert!((to_yaw - yaw).abs() < 1e-5 && (to_pitch - pitch).abs() < 1e-5);
+
+    assert!(close(Rotor::from_rotation_between(z, x * 3.0).rotate(z), x));
+    assert!(close(Rotor::from_rotation_between(forward, y).rotate(forward), y));
+    assert!(close(Rotor::from_rotation_between(x, -x).rotate(x), -x));
+    assert!(close(Rotor::from_rotation_between(y, y).rotate(x), x));
+
+    // constant angular speed, unlike nlerp
+    let to = Rotor::from_axis_angle(y, 3.0);
+    for t in [0.25, 0.5, 0.9] {
+        let (axis, angle) = Rotor::identity().slerp(&to, t).to_axis_angle();
+        assert!(close(axis, y) && (angle - 3.0 * t).abs() < 1e-4);
+    }
+    let nlerp_angle = Rotor::identity().nlerp(&to, 0.25).to_axis_angle().1;
+    assert!((nlerp_angle - 0.75).abs() > 1e-2);
+}
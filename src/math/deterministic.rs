@@ -0,0 +1,135 @@
+// Trigonometry giving the same bits on every platform and build, for lockstep simulations.
+// std's sin, cos and friends call the platform's libm, whose results differ in the last bits
+// between platforms and versions. These only use addition, multiplication, division and square
+// roots, which IEEE 754 rounds exactly, in a fixed order, so every machine rounds them the same.
+// Rust never reorders or fuses float operations on its own, so the rest of the math is deterministic
+// already. The `deterministic-math` feature makes `Vector`, `Bivector`, `Rotor` and `ModelMat` use
+// these instead of std's, rendering keeps std's
+
+use std::f64::consts::{FRAC_PI_2, PI};
+
+/// `(sin, cos)` of `x` reduced to [-pi/4, pi/4], in f64 so the f32 results round correctly
+fn sin_cos_f64(x: f64) -> (f64, f64) {
+    let quadrant = (x / FRAC_PI_2).round();
+    let r = x - quadrant * FRAC_PI_2;
+    let r2 = r * r;
+
+    // taylor series, the terms left out are below f32 precision
+    let mut sin = 0.0;
+    let mut cos = 0.0;
+    for n in (0..8).rev() {
+        let n = n as f64;
+        sin = 1.0 - sin * r2 / ((2.0 * n + 2.0) * (2.0 * n + 3.0));
+        cos = 1.0 - cos * r2 / ((2.0 * n + 1.0) * (2.0 * n + 2.0));
+    }
+    let sin = sin * r;
+
+    match quadrant.rem_euclid(4.0) as u32 {
+        0 => (sin, cos),
+        1 => (cos, -sin),
+        2 => (-sin, -cos),
+        _ => (-cos, sin),
+    }
+}
+
+/// of `x` in [-1, 1], halving its angle first so the series converges fast
+fn atan_unit_f64(x: f64) -> f64 {
+    let t = x / (1.0 + (1.0 + x * x).sqrt());
+    let t2 = t * t;
+    let mut sum = 0.0;
+    for n in (0..20).rev() {
+        sum = 1.0 / (2 * n + 1) as f64 - t2 * sum;
+    }
+    2.0 * t * sum
+}
+
+fn atan_f64(x: f64) -> f64 {
+    if x.abs() <= 1.0 {
+        atan_unit_f64(x)
+    } else {
+        FRAC_PI_2.copysign(x) - atan_unit_f64(1.0 / x)
+    }
+}
+
+fn atan2_f64(y: f64, x: f64) -> f64 {
+    if x > 0.0 {
+        atan_f64(y / x)
+    } else if x < 0.0 {
+        atan_f64(y / x) + PI.copysign(y)
+    } else if y != 0.0 {
+        FRAC_PI_2.copysign(y)
+    } else {
+        0.0
+    }
+}
+
+pub fn sin_cos(x: f32) -> (f32, f32) {
+    if !x.is_finite() {
+        return (f32::NAN, f32::NAN);
+    }
+    let (sin, cos) = sin_cos_f64(x as f64);
+    (sin as f32, cos as f32)
+}
+
+pub fn sin(x: f32) -> f32 {
+    sin_cos(x).0
+}
+
+pub fn cos(x: f32) -> f32 {
+    sin_cos(x).1
+}
+
+/// angle of `(x, y)` from the x axis in [-pi, pi], 0 for the origin
+pub fn atan2(y: f32, x: f32) -> f32 {
+    if x.is_nan() || y.is_nan() {
+        return f32::NAN;
+    }
+    atan2_f64(y as f64, x as f64) as f32
+}
+
+/// `NaN` outside [-1, 1]
+pub fn asin(x: f32) -> f32 {
+    if x.is_nan() || x.abs() > 1.0 {
+        return f32::NAN;
+    }
+    let x = x as f64;
+    atan2_f64(x, (1.0 - x * x).sqrt()) as f32
+}
+
+/// `NaN` outside [-1, 1]
+pub fn acos(x: f32) -> f32 {
+    if x.is_nan() || x.abs() > 1.0 {
+        return f32::NAN;
+    }
+    let x = x as f64;
+    atan2_f64((1.0 - x * x).sqrt(), x) as f32
+}
+
+#[test]
+fn test_deterministic_math() {
+    // as close as std's
+    for i in -2000..2000 {
+        let x = i as f32 * 0.0173;
+        let (sin, cos) = sin_cos(x);
+        assert!((sin - x.sin()).abs() <= 1e-6 && (cos - x.cos()).abs() <= 1e-6, "{}", x);
+        let y = (i as f32 * 0.37).sin() * 3.0;
+        assert!((atan2(y, x) - y.atan2(x)).abs() <= 1e-6, "{} {}", y, x);
+        let unit = i as f32 / 2000.0;
+        assert!((asin(unit) - unit.asin()).abs() <= 1e-6 && (acos(unit) - unit.acos()).abs() <= 1e-6, "{}", unit);
+    }
+    assert!(sin(0.0) == 0.0 && cos(0.0) == 1.0 && atan2(0.0, 0.0) == 0.0);
+    assert!(atan2(1.0, 0.0) == std::f32::consts::FRAC_PI_2 && acos(-1.0) == std::f32::consts::PI);
+    assert!(acos(1.5).is_nan() && sin(f32::INFINITY).is_nan());
+
+    // the same bits on every platform, a hash of many results checked against this machine's.
+    // A change here changes every lockstep simulation, bump it only on purpose
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for i in -5000..5000 {
+        let x = i as f32 * 0.01 + 0.005;
+        let (sin, cos) = sin_cos(x);
+        for bits in [sin, cos, atan2(x, 1.5), asin(x / 50.0), acos(x / 50.0)].map(f32::to_bits) {
+            hash = (hash ^ bits as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+    assert!(hash == 0xa5375b8b716409d9, "{:#x}", hash);
+}
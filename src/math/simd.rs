@@ -0,0 +1,473 @@
+// Batch math over structure of arrays, four lanes at a time with SSE on x86_64 and NEON on aarch64,
+// both part of their baseline so nothing is detected at runtime. Other targets and the elements past
+// the last multiple of four go through the same math one at a time, `scalar` has the plain versions
+// for comparison, see benches/simd.rs. The compiler vectorizes the plain point transforms and
+// normalization well on its own, the batch versions keep it from depending on that, culling
+// gains the most as the plane's corner is picked once for every lane:
+//
+//     let mut aabbs = Aabbs::default();
+//     for chunk in chunks { aabbs.push(chunk.aabb) }
+//     let mut visible = vec![];
+//     simd::cull_aabbs(&frustum, &aabbs, &mut visible);
+//     let in_view = (0..aabbs.len()).filter(|&index| utils::get_bit(&visible, index));
+
+use super::{Aabb, Frustum, ModelMat, Rotor, Vector};
+use lanes::F32x4;
+
+pub const LANES: usize = 4;
+
+/// x86_64 always has SSE2
+#[cfg(target_arch = "x86_64")]
+mod lanes {
+    use std::arch::x86_64::*;
+    use std::ops::{Add, Div, Mul};
+
+    #[derive(Clone, Copy)]
+    pub struct F32x4(__m128);
+
+    impl F32x4 {
+        #[inline(always)]
+        pub fn splat(value: f32) -> Self {
+            Self(unsafe { _mm_set1_ps(value) })
+        }
+
+        /// the first four of `from`
+        #[inline(always)]
+        pub fn load(from: &[f32]) -> Self {
+            Self(unsafe { _mm_loadu_ps(from[..4].as_ptr()) })
+        }
+
+        #[inline(always)]
+        pub fn store(self, to: &mut [f32]) {
+            unsafe { _mm_storeu_ps(to[..4].as_mut_ptr(), self.0) }
+        }
+
+        #[inline(always)]
+        pub fn sqrt(self) -> Self {
+            Self(unsafe { _mm_sqrt_ps(self.0) })
+        }
+
+        /// a bit per lane, set where `self >= rhs`
+        #[inline(always)]
+        pub fn ge_mask(self, rhs: Self) -> u32 {
+            unsafe { _mm_movemask_ps(_mm_cmpge_ps(self.0, rhs.0)) as u32 }
+        }
+    }
+
+    impl Add for F32x4 {
+        type Output = Self;
+
+        #[inline(always)]
+        fn add(self, rhs: Self) -> Self {
+            Self(unsafe { _mm_add_ps(self.0, rhs.0) })
+        }
+    }
+
+    impl Mul for F32x4 {
+        type Output = Self;
+
+        #[inline(always)]
+        fn mul(self, rhs: Self) -> Self {
+            Self(unsafe { _mm_mul_ps(self.0, rhs.0) })
+        }
+    }
+
+    impl Div for F32x4 {
+        type Output = Self;
+
+        #[inline(always)]
+        fn div(self, rhs: Self) -> Self {
+            Self(unsafe { _mm_div_ps(self.0, rhs.0) })
+        }
+    }
+}
+
+/// aarch64 always has NEON
+#[cfg(target_arch = "aarch64")]
+mod lanes {
+    use std::arch::aarch64::*;
+    use std::ops::{Add, Div, Mul};
+
+    #[derive(Clone, Copy)]
+    pub struct F32x4(float32x4_t);
+
+    impl F32x4 {
+        #[inline(always)]
+        pub fn splat(value: f32) -> Self {
+            Self(unsafe { vdupq_n_f32(value) })
+        }
+
+        /// the first four of `from`
+        #[inline(always)]
+        pub fn load(from: &[f32]) -> Self {
+            Self(unsafe { vld1q_f32(from[..4].as_ptr()) })
+        }
+
+        #[inline(always)]
+        pub fn store(self, to: &mut [f32]) {
+            unsafe { vst1q_f32(to[..4].as_mut_ptr(), self.0) }
+        }
+
+        #[inline(always)]
+        pub fn sqrt(self) -> Self {
+            Self(unsafe { vsqrtq_f32(self.0) })
+        }
+
+        /// a bit per lane, set where `self >= rhs`
+        #[inline(always)]
+        pub fn ge_mask(self, rhs: Self) -> u32 {
+            let lane_bits = [1u32, 2, 4, 8];
+            unsafe { vaddvq_u32(vandq_u32(vcgeq_f32(self.0, rhs.0), vld1q_u32(lane_bits.as_ptr()))) }
+        }
+    }
+
+    impl Add for F32x4 {
+        type Output = Self;
+
+        #[inline(always)]
+        fn add(self, rhs: Self) -> Self {
+            Self(unsafe { vaddq_f32(self.0, rhs.0) })
+        }
+    }
+
+    impl Mul for F32x4 {
+        type Output = Self;
+
+        #[inline(always)]
+        fn mul(self, rhs: Self) -> Self {
+            Self(unsafe { vmulq_f32(self.0, rhs.0) })
+        }
+    }
+
+    impl Div for F32x4 {
+        type Output = Self;
+
+        #[inline(always)]
+        fn div(self, rhs: Self) -> Self {
+            Self(unsafe { vdivq_f32(self.0, rhs.0) })
+        }
+    }
+}
+
+/// one lane at a time, the compiler may still vectorize it
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod lanes {
+    use std::ops::{Add, Div, Mul};
+
+    #[derive(Clone, Copy)]
+    pub struct F32x4([f32; 4]);
+
+    impl F32x4 {
+        pub fn splat(value: f32) -> Self {
+            Self([value; 4])
+        }
+
+        /// the first four of `from`
+        pub fn load(from: &[f32]) -> Self {
+            Self(from[..4].try_into().unwrap())
+        }
+
+        pub fn store(self, to: &mut [f32]) {
+            to[..4].copy_from_slice(&self.0);
+        }
+
+        pub fn sqrt(self) -> Self {
+            Self(self.0.map(f32::sqrt))
+        }
+
+        /// a bit per lane, set where `self >= rhs`
+        pub fn ge_mask(self, rhs: Self) -> u32 {
+            (0..4).map(|lane| ((self.0[lane] >= rhs.0[lane]) as u32) << lane).sum()
+        }
+    }
+
+    impl Add for F32x4 {
+        type Output = Self;
+
+        fn add(self, rhs: Self) -> Self {
+            Self(std::array::from_fn(|lane| self.0[lane] + rhs.0[lane]))
+        }
+    }
+
+    impl Mul for F32x4 {
+        type Output = Self;
+
+        fn mul(self, rhs: Self) -> Self {
+            Self(std::array::from_fn(|lane| self.0[lane] * rhs.0[lane]))
+        }
+    }
+
+    impl Div for F32x4 {
+        type Output = Self;
+
+        fn div(self, rhs: Self) -> Self {
+            Self(std::array::from_fn(|lane| self.0[lane] / rhs.0[lane]))
+        }
+    }
+}
+
+/// `Vector`s as an array per component
+#[derive(Clone, Default, Debug)]
+pub struct Vectors {
+    pub x: Vec<f32>,
+    pub y: Vec<f32>,
+    pub z: Vec<f32>,
+}
+
+impl Vectors {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            x: Vec::with_capacity(capacity),
+            y: Vec::with_capacity(capacity),
+            z: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.x.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.x.is_empty()
+    }
+
+    pub fn push(&mut self, vector: Vector) {
+        self.x.push(vector.x);
+        self.y.push(vector.y);
+        self.z.push(vector.z);
+    }
+
+    pub fn get(&self, index: usize) -> Vector {
+        Vector::new(self.x[index], self.y[index], self.z[index])
+    }
+
+    /// new elements are zero
+    pub fn resize(&mut self, len: usize) {
+        self.x.resize(len, 0.0);
+        self.y.resize(len, 0.0);
+        self.z.resize(len, 0.0);
+    }
+}
+
+/// `Rotor`s as an array per component
+#[derive(Clone, Default, Debug)]
+pub struct Rotors {
+    pub _1: Vec<f32>,
+    pub yx: Vec<f32>,
+    pub zy: Vec<f32>,
+    pub xz: Vec<f32>,
+}
+
+impl Rotors {
+    pub fn len(&self) -> usize {
+        self._1.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self._1.is_empty()
+    }
+
+    pub fn push(&mut self, rotor: Rotor) {
+        self._1.push(rotor._1);
+        self.yx.push(rotor.yx);
+        self.zy.push(rotor.zy);
+        self.xz.push(rotor.xz);
+    }
+
+    pub fn get(&self, index: usize) -> Rotor {
+        Rotor::new(self._1[index], self.yx[index], self.zy[index], self.xz[index])
+    }
+}
+
+/// `Aabb`s as their corners' arrays
+#[derive(Clone, Default, Debug)]
+pub struct Aabbs {
+    pub min: Vectors,
+    pub max: Vectors,
+}
+
+impl Aabbs {
+    pub fn len(&self) -> usize {
+        self.min.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.is_empty()
+    }
+
+    pub fn push(&mut self, aabb: Aabb) {
+        self.min.push(aabb.min);
+        self.max.push(aabb.max);
+    }
+
+    pub fn get(&self, index: usize) -> Aabb {
+        Aabb { min: self.min.get(index), max: self.max.get(index) }
+    }
+}
+
+impl FromIterator<Aabb> for Aabbs {
+    fn from_iter<I: IntoIterator<Item = Aabb>>(iter: I) -> Self {
+        let mut aabbs = Self::default();
+        for aabb in iter {
+            aabbs.push(aabb);
+        }
+        aabbs
+    }
+}
+
+/// `out` gets `points` transformed by `model`, resized to fit
+pub fn transform_points(model: &ModelMat, points: &Vectors, out: &mut Vectors) {
+    out.resize(points.len());
+    let end = points.len() / LANES * LANES;
+
+    let m = |value: f32| F32x4::splat(value);
+    let (r0c0, r0c1, r0c2, r0c3) = (m(model.r0c0), m(model.r0c1), m(model.r0c2), m(model.r0c3));
+    let (r1c0, r1c1, r1c2, r1c3) = (m(model.r1c0), m(model.r1c1), m(model.r1c2), m(model.r1c3));
+    let (r2c0, r2c1, r2c2, r2c3) = (m(model.r2c0), m(model.r2c1), m(model.r2c2), m(model.r2c3));
+    let points_lanes = points.x.chunks_exact(LANES).zip(points.y.chunks_exact(LANES)).zip(points.z.chunks_exact(LANES));
+    let out_lanes = out.x.chunks_exact_mut(LANES).zip(out.y.chunks_exact_mut(LANES)).zip(out.z.chunks_exact_mut(LANES));
+    for (((x, y), z), ((out_x, out_y), out_z)) in points_lanes.zip(out_lanes) {
+        let (x, y, z) = (F32x4::load(x), F32x4::load(y), F32x4::load(z));
+        (r0c0 * x + r0c1 * y + r0c2 * z + r0c3).store(out_x);
+        (r1c0 * x + r1c1 * y + r1c2 * z + r1c3).store(out_y);
+        (r2c0 * x + r2c1 * y + r2c2 * z + r2c3).store(out_z);
+    }
+    transform_points_from(model, points, out, end);
+}
+
+/// scales every rotor to unit length, none may be zero
+pub fn normalize_rotors(rotors: &mut Rotors) {
+    let end = rotors.len() / LANES * LANES;
+    let one = F32x4::splat(1.0);
+    let Rotors { _1, yx, zy, xz } = rotors;
+    let lanes = _1.chunks_exact_mut(LANES).zip(yx.chunks_exact_mut(LANES)).zip(zy.chunks_exact_mut(LANES)).zip(xz.chunks_exact_mut(LANES));
+    for (((real_lanes, yx_lanes), zy_lanes), xz_lanes) in lanes {
+        let (real, yx, zy, xz) = (F32x4::load(real_lanes), F32x4::load(yx_lanes), F32x4::load(zy_lanes), F32x4::load(xz_lanes));
+        let inverse_norm = one / (real * real + yx * yx + zy * zy + xz * xz).sqrt();
+        (real * inverse_norm).store(real_lanes);
+        (yx * inverse_norm).store(yx_lanes);
+        (zy * inverse_norm).store(zy_lanes);
+        (xz * inverse_norm).store(xz_lanes);
+    }
+    normalize_rotors_from(rotors, end);
+}
+
+/// `visible` gets a bit per box, set when `Frustum::intersects_aabb` would be true for it
+pub fn cull_aabbs(frustum: &Frustum, aabbs: &Aabbs, visible: &mut Vec<usize>) {
+    *visible = crate::utils::new_bitmask_vec(aabbs.len(), false);
+    let end = aabbs.len() / LANES * LANES;
+    let zero = F32x4::splat(0.0);
+    // the corner furthest along each plane's normal, the same side for every box
+    let planes = frustum.get_planes().map(|[a, b, c, d]| {
+        let x = if a >= 0.0 { &aabbs.max.x[..end] } else { &aabbs.min.x[..end] };
+        let y = if b >= 0.0 { &aabbs.max.y[..end] } else { &aabbs.min.y[..end] };
+        let z = if c >= 0.0 { &aabbs.max.z[..end] } else { &aabbs.min.z[..end] };
+        (x, y, z, [F32x4::splat(a), F32x4::splat(b), F32x4::splat(c), F32x4::splat(d)])
+    });
+    for index in (0..end).step_by(LANES) {
+        let mut inside = 0b1111;
+        for (x, y, z, [a, b, c, d]) in &planes {
+            let lanes = index..index + LANES;
+            let distance = *a * F32x4::load(&x[lanes.clone()]) + *b * F32x4::load(&y[lanes.clone()]) + *c * F32x4::load(&z[lanes]) + *d;
+            inside &= distance.ge_mask(zero);
+        }
+        // lanes start at multiples of 4, which never straddle words
+        visible[index / usize::BITS as usize] |= (inside as usize) << (index % usize::BITS as usize);
+    }
+    cull_aabbs_from(frustum, aabbs, visible, end);
+}
+
+/// one element at a time, for comparison with the batch versions
+pub mod scalar {
+    use super::{Aabbs, Rotors, Vectors};
+    use crate::{math::{Frustum, ModelMat}, utils};
+
+    pub fn transform_points(model: &ModelMat, points: &Vectors, out: &mut Vectors) {
+        out.resize(points.len());
+        super::transform_points_from(model, points, out, 0);
+    }
+
+    pub fn normalize_rotors(rotors: &mut Rotors) {
+        super::normalize_rotors_from(rotors, 0);
+    }
+
+    pub fn cull_aabbs(frustum: &Frustum, aabbs: &Aabbs, visible: &mut Vec<usize>) {
+        *visible = utils::new_bitmask_vec(aabbs.len(), false);
+        super::cull_aabbs_from(frustum, aabbs, visible, 0);
+    }
+}
+
+/// the elements from `start` on one at a time, for what's past the last full set of lanes
+fn transform_points_from(model: &ModelMat, points: &Vectors, out: &mut Vectors, start: usize) {
+    for index in start..points.len() {
+        let point = points.get(index);
+        let transformed = model.translation()
+            + model.axis(0) * point.x
+            + model.axis(1) * point.y
+            + model.axis(2) * point.z;
+        out.x[index] = transformed.x;
+        out.y[index] = transformed.y;
+        out.z[index] = transformed.z;
+    }
+}
+
+fn normalize_rotors_from(rotors: &mut Rotors, start: usize) {
+    for index in start..rotors.len() {
+        let rotor = rotors.get(index);
+        let inverse_norm = 1.0 / rotor.norm_sqr().sqrt();
+        rotors._1[index] = rotor._1 * inverse_norm;
+        rotors.yx[index] = rotor.yx * inverse_norm;
+        rotors.zy[index] = rotor.zy * inverse_norm;
+        rotors.xz[index] = rotor.xz * inverse_norm;
+    }
+}
+
+fn cull_aabbs_from(frustum: &Frustum, aabbs: &Aabbs, visible: &mut [usize], start: usize) {
+    for index in start..aabbs.len() {
+        if frustum.intersects_aabb(&aabbs.get(index)) {
+            crate::utils::set_bit_true(visible, index);
+        }
+    }
+}
+
+#[test]
+fn test_simd_batches() {
+    let close = |a: f32, b: f32| (a - b).abs() <= 1e-5 * a.abs().max(1.0);
+
+    // 7 covers a full set of lanes and a partial one
+    let model = ModelMat::from(Vector::new(1.0, 2.0, 0.5), Rotor::from_axis_angle(Vector::new(0.0, 1.0, 0.0), 0.7), Vector::new(3.0, -1.0, 2.0));
+    let mut points = Vectors::default();
+    for index in 0..7 {
+        points.push(Vector::new(index as f32, 1.0 - index as f32, 0.5 * index as f32));
+    }
+    let (mut batched, mut one_at_a_time) = (Vectors::default(), Vectors::default());
+    transform_points(&model, &points, &mut batched);
+    scalar::transform_points(&model, &points, &mut one_at_a_time);
+    assert!(batched.len() == 7);
+    assert!((0..7).all(|index| {
+        let (a, b) = (batched.get(index), one_at_a_time.get(index));
+        close(a.x, b.x) && close(a.y, b.y) && close(a.z, b.z)
+    }));
+
+    let mut rotors = Rotors::default();
+    for index in 0..6 {
+        rotors.push(Rotor::new(1.0 + index as f32, 0.5, -2.0, index as f32));
+    }
+    normalize_rotors(&mut rotors);
+    assert!((0..6).all(|index| close(rotors.get(index).norm_sqr(), 1.0)));
+
+    // a row of boxes along z, the camera at the origin looking down +z into 1 to 100
+    let frustum = Frustum::from_proj_view(&ModelMat::identity().project(1.0, 1.0, 100.0, false));
+    let aabbs: Aabbs = (0..11)
+        .map(|index| {
+            let center = Vector::new(0.0, 0.0, index as f32 * 25.0 - 50.0);
+            Aabb { min: center - Vector::new(1.0, 1.0, 1.0), max: center + Vector::new(1.0, 1.0, 1.0) }
+        })
+        .collect();
+    let (mut batched, mut one_at_a_time) = (vec![], vec![]);
+    cull_aabbs(&frustum, &aabbs, &mut batched);
+    scalar::cull_aabbs(&frustum, &aabbs, &mut one_at_a_time);
+    assert!(batched == one_at_a_time);
+    let visible: Vec<usize> = (0..aabbs.len()).filter(|&index| crate::utils::get_bit(&batched, index)).collect();
+    assert!(visible == [2, 3, 4, 5, 6], "{:?}", visible);
+}
@@ -0,0 +1,371 @@
+// Optimizations for imported meshes, run once before their geometry is created:
+//
+// 1. bitwise equal vertices are merged
+// 2. triangles are reordered for the post transform vertex cache, with Forsyth's algorithm
+// 3. runs of those triangles are reordered so the ones facing outwards draw first, which reduces
+//    overdraw when the mesh is seen from outside, unless that costs too many cache misses
+// 4. vertices are reordered into the order triangles first use them, so neighbouring triangles
+//    fetch neighbouring vertices
+//
+// Cache efficiency is measured as the ACMR, transformed vertices per triangle, on a simulated FIFO cache
+
+use std::collections::HashMap;
+
+use crate::{geometry::{Index, Vertex}, math::Vector};
+
+/// entries of the simulated LRU cache the scores are tuned for
+const FORSYTH_CACHE_SIZE: usize = 32;
+const CACHE_DECAY_POWER: f32 = 1.5;
+/// the last triangle's vertices score the same, whatever order they were added in
+const LAST_TRIANGLE_SCORE: f32 = 0.75;
+/// vertices with few triangles left are preferred, so they leave the cache done
+const VALENCE_BOOST_SCALE: f32 = 2.0;
+const VALENCE_BOOST_POWER: f32 = 0.5;
+
+/// FIFO entries of the cache stats are measured with, about what current hardware has
+pub const STATS_CACHE_SIZE: usize = 16;
+/// ACMR the overdraw order may cost, relative to the cache optimized order
+pub const OVERDRAW_THRESHOLD: f32 = 1.05;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct VertexCacheStats {
+    pub vertex_count: usize,
+    pub triangle_count: usize,
+    /// average cache miss ratio, transformed vertices per triangle, 0.5 at best for large meshes and 3 at worst
+    pub acmr: f32,
+    /// average transformed to vertex ratio, 1 at best
+    pub atvr: f32,
+}
+
+/// of drawing `indices` with a `cache_size` entry FIFO cache
+pub fn analyze_vertex_cache(indices: &[Index], vertex_count: usize, cache_size: usize) -> VertexCacheStats {
+    let mut cache = FifoCache::new(vertex_count, cache_size);
+    let misses: usize = indices.iter().map(|&index| cache.access(index) as usize).sum();
+    let triangle_count = indices.len() / 3;
+    VertexCacheStats {
+        vertex_count,
+        triangle_count,
+        acmr: if triangle_count == 0 { 0.0 } else { misses as f32 / triangle_count as f32 },
+        atvr: if vertex_count == 0 { 0.0 } else { misses as f32 / vertex_count as f32 },
+    }
+}
+
+/// A vertex is cached while fewer than `size` misses happened since its own
+struct FifoCache {
+    /// miss count when each vertex was last added
+    added_at: Vec<usize>,
+    misses: usize,
+    size: usize,
+}
+
+impl FifoCache {
+    fn new(vertex_count: usize, size: usize) -> Self {
+        Self {
+            added_at: vec![usize::MAX; vertex_count],
+            misses: 0,
+            size,
+        }
+    }
+
+    /// true on a miss
+    fn access(&mut self, index: Index) -> bool {
+        let added_at = &mut self.added_at[index as usize];
+        if *added_at != usize::MAX && self.misses - *added_at < self.size {
+            return false;
+        }
+        *added_at = self.misses;
+        self.misses += 1;
+        true
+    }
+
+    /// every vertex misses again
+    fn flush(&mut self) {
+        self.misses += self.size;
+    }
+}
+
+/// merges bitwise equal vertices, unreferenced ones are kept
+pub fn deduplicate_vertices(vertices: &[Vertex], indices: &[Index]) -> (Vec<Vertex>, Vec<Index>) {
+    let bits = |vertex: &Vertex| [
+        vertex.x, vertex.y, vertex.z,
+        vertex.u, vertex.v,
+        vertex.nx, vertex.ny, vertex.nz,
+        vertex.tx, vertex.ty, vertex.tz, vertex.tw,
+    ].map(f32::to_bits);
+
+    let mut unique_indices = HashMap::with_capacity(vertices.len());
+    let mut unique_vertices = Vec::with_capacity(vertices.len());
+    let remap: Vec<Index> = vertices.iter().map(|vertex| {
+        *unique_indices.entry(bits(vertex)).or_insert_with(|| {
+            unique_vertices.push(*vertex);
+            (unique_vertices.len() - 1) as Index
+        })
+    }).collect();
+
+    (unique_vertices, indices.iter().map(|&index| remap[index as usize]).collect())
+}
+
+/// Forsyth's score of a vertex at `cache_position` in the LRU cache with `remaining` triangles not yet emitted
+fn vertex_score(cache_position: Option<usize>, remaining: usize) -> f32 {
+    if remaining == 0 {
+        return -1.0;
+    }
+    let cache_score = match cache_position {
+        None => 0.0,
+        Some(position) if position < 3 => LAST_TRIANGLE_SCORE,
+        Some(position) => {
+            (1.0 - (position - 3) as f32 / (FORSYTH_CACHE_SIZE - 3) as f32).powf(CACHE_DECAY_POWER)
+        }
+    };
+    cache_score + VALENCE_BOOST_SCALE * (remaining as f32).powf(-VALENCE_BOOST_POWER)
+}
+
+/// the triangles of `indices` reordered for the vertex cache, greedily emitting the triangle
+/// whose vertices score best, see "Linear-Speed Vertex Cache Optimisation" by Tom Forsyth
+pub fn optimize_vertex_cache(indices: &[Index], vertex_count: usize) -> Vec<Index> {
+    let triangle_count = indices.len() / 3;
+    let triangle = |t: usize| [indices[3 * t], indices[3 * t + 1], indices[3 * t + 2]];
+
+    // triangles using each vertex, the first `remaining[v]` of a vertex's aren't emitted yet
+    let mut offsets = vec![0; vertex_count + 1];
+    for &index in &indices[..3 * triangle_count] {
+        offsets[index as usize + 1] += 1;
+    }
+    for v in 0..vertex_count {
+        offsets[v + 1] += offsets[v];
+    }
+    let mut remaining: Vec<usize> = (0..vertex_count).map(|v| offsets[v + 1] - offsets[v]).collect();
+    let mut adjacency = vec![0; 3 * triangle_count];
+    let mut filled = offsets.clone();
+    for t in 0..triangle_count {
+        for index in triangle(t) {
+            adjacency[filled[index as usize]] = t;
+            filled[index as usize] += 1;
+        }
+    }
+
+    let mut cache_positions = vec![None; vertex_count];
+    let mut vertex_scores: Vec<f32> = remaining.iter().map(|&remaining| vertex_score(None, remaining)).collect();
+    let triangle_score = |vertex_scores: &[f32], t: usize| triangle(t).map(|index| vertex_scores[index as usize]).iter().sum::<f32>();
+    let mut triangle_scores: Vec<f32> = (0..triangle_count).map(|t| triangle_score(&vertex_scores, t)).collect();
+    let mut emitted = vec![false; triangle_count];
+
+    let mut cache: Vec<Index> = Vec::with_capacity(FORSYTH_CACHE_SIZE + 3);
+    let mut next_cache = Vec::with_capacity(FORSYTH_CACHE_SIZE + 3);
+    let mut output = Vec::with_capacity(3 * triangle_count);
+    // where to look for a triangle when none touch the cache
+    let mut scan = 0;
+    let mut best = (0..triangle_count).max_by(|&a, &b| triangle_scores[a].total_cmp(&triangle_scores[b]));
+
+    while let Some(t) = best {
+        emitted[t] = true;
+        let vertices = triangle(t);
+        output.extend_from_slice(&vertices);
+
+        for index in vertices {
+            let v = index as usize;
+            let triangles = &mut adjacency[offsets[v]..offsets[v] + remaining[v]];
+            // twice in a degenerate triangle, only once in the list
+            if let Some(position) = triangles.iter().position(|&other| other == t) {
+                triangles.swap(position, remaining[v] - 1);
+                remaining[v] -= 1;
+            }
+        }
+
+        // the triangle's vertices move to the front, the rest shift back
+        next_cache.clear();
+        for index in vertices {
+            if !next_cache.contains(&index) {
+                next_cache.push(index);
+            }
+        }
+        next_cache.extend(cache.iter().filter(|index| !vertices.contains(index)));
+        std::mem::swap(&mut cache, &mut next_cache);
+
+        for (position, &index) in cache.iter().enumerate() {
+            cache_positions[index as usize] = (position < FORSYTH_CACHE_SIZE).then_some(position);
+        }
+        for &index in &cache {
+            vertex_scores[index as usize] = vertex_score(cache_positions[index as usize], remaining[index as usize]);
+        }
+
+        best = None;
+        let mut best_score = f32::MIN;
+        for &index in &cache {
+            let v = index as usize;
+            for &other in &adjacency[offsets[v]..offsets[v] + remaining[v]] {
+                triangle_scores[other] = triangle_score(&vertex_scores, other);
+                if triangle_scores[other] > best_score {
+                    best_score = triangle_scores[other];
+                    best = Some(other);
+                }
+            }
+        }
+        // evicted vertices were rescored along with the triangles above
+        cache.truncate(FORSYTH_CACHE_SIZE);
+
+        if best.is_none() {
+            while scan < triangle_count && emitted[scan] {
+                scan += 1;
+            }
+            best = (scan < triangle_count).then_some(scan);
+        }
+    }
+
+    output
+}
+
+/// Splits `indices` into runs of triangles, each drawing about as well from a cold cache as
+/// the whole mesh does, then draws the runs facing outwards first. Keeps `indices` as they are
+/// if that raises the ACMR by more than `threshold`, see "Fast Triangle Reordering for Vertex
+/// Locality and Reduced Overdraw" by Sander et al.
+pub fn optimize_overdraw(indices: &[Index], vertices: &[Vertex], threshold: f32) -> Vec<Index> {
+    let triangle_count = indices.len() / 3;
+    if triangle_count == 0 {
+        return indices.to_vec();
+    }
+    let position = |index: Index| {
+        let vertex = &vertices[index as usize];
+        Vector::new(vertex.x, vertex.y, vertex.z)
+    };
+    let normal = |index: Index| {
+        let vertex = &vertices[index as usize];
+        Vector::new(vertex.nx, vertex.ny, vertex.nz)
+    };
+
+    // a run ends once its ACMR from a cold cache is within `threshold` of the mesh's,
+    // the last run of the mesh may not get there
+    let target_acmr = threshold * analyze_vertex_cache(indices, vertices.len(), STATS_CACHE_SIZE).acmr;
+    let mut cache = FifoCache::new(vertices.len(), STATS_CACHE_SIZE);
+    let mut run_starts = vec![0];
+    let mut run_misses = 0;
+    for t in 0..triangle_count {
+        run_misses += indices[3 * t..3 * t + 3].iter().filter(|&&index| cache.access(index)).count();
+        let run_triangles = t + 1 - run_starts.last().unwrap();
+        if run_misses as f32 / run_triangles as f32 <= target_acmr && t + 1 < triangle_count {
+            run_starts.push(t + 1);
+            run_misses = 0;
+            cache.flush();
+        }
+    }
+    run_starts.push(triangle_count);
+
+    // area weighted centroid and vertex normal of each run
+    let runs: Vec<(usize, usize, Vector, Vector)> = run_starts.windows(2).map(|run| {
+        let (mut centroid, mut run_normal, mut area) = (Vector::new(0.0, 0.0, 0.0), Vector::new(0.0, 0.0, 0.0), 0.0);
+        for t in run[0]..run[1] {
+            let [a, b, c] = [indices[3 * t], indices[3 * t + 1], indices[3 * t + 2]];
+            let triangle_area = 0.5 * (position(b) - position(a)).cross(&(position(c) - position(a))).norm_sqr().sqrt();
+            // degenerate runs still get a centroid
+            let weight = triangle_area.max(f32::EPSILON);
+            centroid += (position(a) + position(b) + position(c)) * (weight / 3.0);
+            run_normal += (normal(a) + normal(b) + normal(c)) * weight;
+            area += weight;
+        }
+        (run[0], run[1], centroid / area, run_normal)
+    }).collect();
+    if runs.len() == 1 {
+        return indices.to_vec();
+    }
+
+    let mesh_centroid = runs.iter().fold(Vector::new(0.0, 0.0, 0.0), |sum, run| sum + run.2) / runs.len() as f32;
+    let mut sorted: Vec<(f32, usize)> = runs.iter().enumerate().map(|(i, &(_, _, centroid, run_normal))| {
+        let length = run_normal.norm_sqr().sqrt();
+        let facing = if length > 0.0 { (centroid - mesh_centroid).dot(&run_normal) / length } else { 0.0 };
+        (facing, i)
+    }).collect();
+    // stable, most outward first
+    sorted.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    let mut output = Vec::with_capacity(indices.len());
+    for &(_, i) in &sorted {
+        let (start, end, ..) = runs[i];
+        output.extend_from_slice(&indices[3 * start..3 * end]);
+    }
+
+    let before = analyze_vertex_cache(indices, vertices.len(), STATS_CACHE_SIZE).acmr;
+    let after = analyze_vertex_cache(&output, vertices.len(), STATS_CACHE_SIZE).acmr;
+    if after > threshold * before {
+        log::debug!("Overdraw order kept out, ACMR {:.3} -> {:.3}", before, after);
+        return indices.to_vec();
+    }
+    output
+}
+
+/// vertices in the order `indices` first use them, unused ones dropped, `indices` are remapped
+pub fn optimize_vertex_fetch(vertices: &[Vertex], indices: &mut [Index]) -> Vec<Vertex> {
+    let mut remap = vec![Index::MAX; vertices.len()];
+    let mut reordered = Vec::with_capacity(vertices.len());
+    for index in indices.iter_mut() {
+        if remap[*index as usize] == Index::MAX {
+            remap[*index as usize] = reordered.len() as Index;
+            reordered.push(vertices[*index as usize]);
+        }
+        *index = remap[*index as usize];
+    }
+    reordered
+}
+
+/// every step in order, with the vertex cache stats before and after
+pub fn optimize_mesh(vertices: &[Vertex], indices: &[Index]) -> (Vec<Vertex>, Vec<Index>, [VertexCacheStats; 2]) {
+    assert!(indices.len().is_multiple_of(3), "Mesh indices must be whole triangles");
+    let before = analyze_vertex_cache(indices, vertices.len(), STATS_CACHE_SIZE);
+
+    let (vertices, indices) = deduplicate_vertices(vertices, indices);
+    let indices = optimize_vertex_cache(&indices, vertices.len());
+    let mut indices = optimize_overdraw(&indices, &vertices, OVERDRAW_THRESHOLD);
+    let vertices = optimize_vertex_fetch(&vertices, &mut indices);
+
+    let after = analyze_vertex_cache(&indices, vertices.len(), STATS_CACHE_SIZE);
+    (vertices, indices, [before, after])
+}
+
+#[test]
+fn test_optimize_mesh() {
+    // a grid of quads as unindexed triangles in a scrambled order
+    const SIZE: usize = 24;
+    let vertex = |x: usize, y: usize| Vertex {
+        x: x as f32, y: y as f32,
+        u: x as f32 / SIZE as f32, v: y as f32 / SIZE as f32,
+        nz: -1.0,
+        ..Default::default()
+    };
+    let mut triangles = vec![];
+    for y in 0..SIZE {
+        for x in 0..SIZE {
+            triangles.push([vertex(x, y), vertex(x + 1, y), vertex(x + 1, y + 1)]);
+            triangles.push([vertex(x + 1, y + 1), vertex(x, y + 1), vertex(x, y)]);
+        }
+    }
+    let count = triangles.len();
+    let scrambled: Vec<[Vertex; 3]> = (0..count).map(|i| triangles[i * 37 % count]).collect();
+    let vertices: Vec<Vertex> = scrambled.iter().flatten().copied().collect();
+    let indices: Vec<Index> = (0..vertices.len() as Index).collect();
+
+    let (optimized_vertices, optimized_indices, [before, after]) = optimize_mesh(&vertices, &indices);
+    assert!(before.acmr == 3.0 && before.atvr == 1.0);
+    assert!(optimized_vertices.len() == (SIZE + 1) * (SIZE + 1) && after.vertex_count == optimized_vertices.len());
+    // a regular grid is about 0.5 at best with a cache this size
+    assert!(after.acmr < 0.8 && after.atvr < 1.5, "{:?}", after);
+
+    // the same triangles with the same winding, vertices in order of first use
+    let key = |triangle: [&Vertex; 3]| {
+        let mut corners = triangle.map(|vertex| ((vertex.x as usize) << 16) | vertex.y as usize);
+        let first = (0..3).min_by_key(|&i| corners[i]).unwrap();
+        corners.rotate_left(first);
+        corners
+    };
+    let mut expected: Vec<_> = triangles.iter().map(|triangle| key(triangle.each_ref())).collect();
+    let mut actual: Vec<_> = optimized_indices
+        .chunks_exact(3)
+        .map(|triangle| key([0, 1, 2].map(|i| &optimized_vertices[triangle[i] as usize])))
+        .collect();
+    expected.sort();
+    actual.sort();
+    assert!(actual == expected);
+    let mut next = 0;
+    for &index in &optimized_indices {
+        assert!(index <= next);
+        next = next.max(index + 1);
+    }
+}
@@ -0,0 +1,197 @@
+// Import settings read from `.meta` sidecar files next to assets,
+// lets import behavior change per asset without touching code.
+//
+// Format is one `key = value` per line, `#` starts a comment:
+//
+//     srgb = false
+//     wrap = clamp
+//     filter = nearest
+//     anisotropy = 4
+//     mip_bias = -0.5
+//     mips = 4
+//
+// Only textures have settings, there is no mesh file loader to read them for meshes
+
+use ash::vk;
+
+use crate::renderer::sampler::SamplerDesc;
+
+pub fn meta_path(asset_path: &str) -> String {
+    asset_path.to_owned() + ".meta"
+}
+
+/// key value pairs in file order, malformed lines are skipped with a warning
+pub fn parse_meta(source: &str) -> Vec<(&str, &str)> {
+    let mut pairs = Vec::new();
+    for (line_index, line) in source.lines().enumerate() {
+        let line = match line.find('#') {
+            Some(comment_start) => &line[..comment_start],
+            None => line,
+        }.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        match line.split_once('=') {
+            Some((key, value)) => pairs.push((key.trim(), value.trim())),
+            None => log::warn!("Meta line {} is not `key = value`: {}", line_index + 1, line),
+        }
+    }
+    pairs
+}
+
+/// missing sidecar means default settings
+fn read_meta(asset_path: &str) -> Option<String> {
+    std::fs::read_to_string(meta_path(asset_path)).ok()
+}
+
+fn parse_bool(key: &str, value: &str) -> Option<bool> {
+    match value {
+        "true" | "yes" | "1" => Some(true),
+        "false" | "no" | "0" => Some(false),
+        _ => {
+            log::warn!("Meta `{}` expects a bool, got `{}`", key, value);
+            None
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MipPolicy {
+    /// full chain down to 1x1
+    Full,
+    /// base level only, for ui and lookup textures
+    None,
+    /// at most this many levels including the base
+    MaxLevels(u32),
+}
+
+impl MipPolicy {
+    pub fn level_count(self, width: u32, height: u32) -> u32 {
+        let full = crate::pixels::mip_level_count(width, height);
+        match self {
+            MipPolicy::Full => full,
+            MipPolicy::None => 1,
+            MipPolicy::MaxLevels(levels) => levels.clamp(1, full),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TextureImportSettings {
+    /// `Some(true)` stores the texture in an srgb format, so it samples as linear colors.
+    /// Mips are averaged in linear space when set or by default for diffuse and specular textures
+    pub srgb: Option<bool>,
+    pub address_mode: vk::SamplerAddressMode,
+    /// for magnification, minification and between mips
+    pub filter: vk::Filter,
+    pub max_anisotropy: f32,
+    pub mip_lod_bias: f32,
+    pub mip_policy: MipPolicy,
+}
+
+impl Default for TextureImportSettings {
+    fn default() -> Self {
+        Self {
+            srgb: None,
+            address_mode: vk::SamplerAddressMode::REPEAT,
+            filter: vk::Filter::LINEAR,
+            max_anisotropy: SamplerDesc::default().max_anisotropy,
+            mip_lod_bias: 0.0,
+            mip_policy: MipPolicy::Full,
+        }
+    }
+}
+
+impl TextureImportSettings {
+    pub fn load(asset_path: &str) -> Self {
+        match read_meta(asset_path) {
+            Some(source) => Self::parse(&source),
+            None => Self::default(),
+        }
+    }
+
+    pub fn parse(source: &str) -> Self {
+        let mut settings = Self::default();
+        for (key, value) in parse_meta(source) {
+            match key {
+                "srgb" => settings.srgb = parse_bool(key, value),
+                "wrap" => settings.address_mode = match value {
+                    "repeat" => vk::SamplerAddressMode::REPEAT,
+                    "mirror" => vk::SamplerAddressMode::MIRRORED_REPEAT,
+                    "clamp" => vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                    "border" => vk::SamplerAddressMode::CLAMP_TO_BORDER,
+                    _ => {
+                        log::warn!("Unknown wrap mode `{}`", value);
+                        settings.address_mode
+                    }
+                },
+                "filter" => settings.filter = match value {
+                    "linear" => vk::Filter::LINEAR,
+                    "nearest" => vk::Filter::NEAREST,
+                    _ => {
+                        log::warn!("Unknown filter `{}`", value);
+                        settings.filter
+                    }
+                },
+                "anisotropy" => match value.parse() {
+                    Ok(max_anisotropy) => settings.max_anisotropy = max_anisotropy,
+                    Err(_) => log::warn!("Meta `anisotropy` expects a number, got `{}`", value),
+                },
+                "mip_bias" => match value.parse() {
+                    Ok(mip_lod_bias) => settings.mip_lod_bias = mip_lod_bias,
+                    Err(_) => log::warn!("Meta `mip_bias` expects a number, got `{}`", value),
+                },
+                "mips" => settings.mip_policy = match value {
+                    "full" => MipPolicy::Full,
+                    "none" => MipPolicy::None,
+                    _ => match value.parse() {
+                        Ok(levels) => MipPolicy::MaxLevels(levels),
+                        Err(_) => {
+                            log::warn!("Unknown mip policy `{}`", value);
+                            settings.mip_policy
+                        }
+                    },
+                },
+                _ => log::warn!("Unknown texture meta key `{}`", key),
+            }
+        }
+        settings
+    }
+
+    pub fn sampler_desc(&self) -> SamplerDesc {
+        let mipmap_mode = match self.filter {
+            vk::Filter::NEAREST => vk::SamplerMipmapMode::NEAREST,
+            _ => vk::SamplerMipmapMode::LINEAR,
+        };
+        SamplerDesc {
+            mag_filter: self.filter,
+            min_filter: self.filter,
+            mipmap_mode,
+            max_anisotropy: self.max_anisotropy,
+            mip_lod_bias: self.mip_lod_bias,
+            ..Default::default()
+        }
+        .with_address_mode(self.address_mode)
+    }
+}
+
+#[test]
+fn test_parse_meta() {
+    let texture = TextureImportSettings::parse("
+        # ui atlas
+        srgb = false
+        wrap = clamp   # no bleeding at the edges
+        mips = 3
+        bogus line
+    ");
+    assert!(texture.srgb == Some(false));
+    assert!(texture.address_mode == vk::SamplerAddressMode::CLAMP_TO_EDGE);
+    assert!(texture.mip_policy == MipPolicy::MaxLevels(3));
+    assert!(texture.mip_policy.level_count(256, 256) == 3);
+    assert!(MipPolicy::MaxLevels(20).level_count(4, 4) == 3);
+    assert!(TextureImportSettings::parse("") == TextureImportSettings::default());
+    assert!(TextureImportSettings::default().sampler_desc() == SamplerDesc::default());
+    let pixel_art = TextureImportSettings::parse("filter = nearest\nanisotropy = 1\nwrap = clamp");
+    assert!(pixel_art.sampler_desc() == SamplerDesc::point().with_address_mode(vk::SamplerAddressMode::CLAMP_TO_EDGE));
+}
@@ -0,0 +1,435 @@
+// Replicates scene object transforms and the camera from a host to clients over UDP, enough to
+// follow one instance from another on a LAN. The host sends a snapshot of every object's transform
+// every few fixed ticks, clients draw them a little in the past, interpolating between the two
+// snapshots around that time, so late or lost packets don't make objects stutter:
+//
+//     ash_engine --host 0.0.0.0:7777 --scene scenes/main.ron
+//     ash_engine --host 0.0.0.0:7777 --headless on --scene scenes/main.ron   # no window or renderer
+//     ash_engine --connect 192.168.1.20:7777 --scene scenes/main.ron
+//
+// Both sides load the same scene, snapshots only carry transforms by object index.
+// Clients say hello every second, the host forgets the ones it hasn't heard from for a while.
+// Nothing is acknowledged or resent, every snapshot replaces the previous ones
+
+use std::{
+    collections::VecDeque,
+    io::ErrorKind,
+    net::{SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    engine::FIXED_TIMESTEP,
+    math::Rotor,
+    scene::{CameraState, Scene, Transform},
+};
+
+/// first in every packet, packets without it are ignored
+const PROTOCOL_ID: u32 = 0x4153_4801;
+const PACKET_HELLO: u8 = 0;
+const PACKET_SNAPSHOT: u8 = 1;
+/// largest udp payload
+const MAX_PACKET_SIZE: usize = 65507;
+
+/// fixed ticks between snapshots, 20 a second
+pub const SNAPSHOT_INTERVAL: u32 = 3;
+/// how often clients say hello
+const HELLO_INTERVAL: Duration = Duration::from_secs(1);
+/// clients the host hasn't heard from for this long are dropped
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+/// snapshots a client keeps for interpolating
+const MAX_BUFFERED_SNAPSHOTS: usize = 32;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Snapshot {
+    /// fixed ticks since the host started
+    pub tick: u32,
+    /// `None` from headless hosts
+    pub camera: Option<CameraState>,
+    /// relative to the parent, animations applied, in object order
+    pub transforms: Vec<Transform>,
+}
+
+impl Snapshot {
+    /// of the tick, in seconds since the host started
+    fn time(&self) -> f32 {
+        self.tick as f32 * FIXED_TIMESTEP
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = packet_header(PACKET_SNAPSHOT);
+        let write_floats = |bytes: &mut Vec<u8>, floats: &[f32]| bytes.extend(floats.iter().flat_map(|float| float.to_le_bytes()));
+        bytes.extend(self.tick.to_le_bytes());
+        match &self.camera {
+            Some(camera) => {
+                bytes.push(1);
+                let (x, y, z) = camera.translation;
+                write_floats(&mut bytes, &[x, y, z, camera.z_x_angle, camera.y_xz_angle, camera.near_z, camera.far_z]);
+            }
+            None => bytes.push(0),
+        }
+        let fitting = (MAX_PACKET_SIZE - bytes.len() - 4) / TRANSFORM_SIZE;
+        if self.transforms.len() > fitting {
+            log::warn!("Snapshot of {} transforms only fits {}", self.transforms.len(), fitting);
+        }
+        let transforms = &self.transforms[..self.transforms.len().min(fitting)];
+        bytes.extend((transforms.len() as u32).to_le_bytes());
+        for transform in transforms {
+            let Transform { translation: (x, y, z), rotation: (s, yx, zy, xz), scale: (sx, sy, sz) } = *transform;
+            write_floats(&mut bytes, &[x, y, z, s, yx, zy, xz, sx, sy, sz]);
+        }
+        bytes
+    }
+
+    /// `None` for malformed packets
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut reader = Reader(bytes);
+        let tick = reader.read_u32()?;
+        let camera = match reader.read_u8()? {
+            0 => None,
+            _ => Some(CameraState {
+                translation: (reader.read_f32()?, reader.read_f32()?, reader.read_f32()?),
+                z_x_angle: reader.read_f32()?,
+                y_xz_angle: reader.read_f32()?,
+                near_z: reader.read_f32()?,
+                far_z: reader.read_f32()?,
+            }),
+        };
+        let count = reader.read_u32()? as usize;
+        if count * TRANSFORM_SIZE != reader.0.len() {
+            return None;
+        }
+        let mut transforms = Vec::with_capacity(count);
+        for _ in 0..count {
+            transforms.push(Transform {
+                translation: (reader.read_f32()?, reader.read_f32()?, reader.read_f32()?),
+                rotation: (reader.read_f32()?, reader.read_f32()?, reader.read_f32()?, reader.read_f32()?),
+                scale: (reader.read_f32()?, reader.read_f32()?, reader.read_f32()?),
+            });
+        }
+        Some(Self { tick, camera, transforms })
+    }
+}
+
+/// bytes of an encoded `Transform`
+const TRANSFORM_SIZE: usize = 10 * 4;
+
+fn packet_header(kind: u8) -> Vec<u8> {
+    let mut bytes = PROTOCOL_ID.to_le_bytes().to_vec();
+    bytes.push(kind);
+    bytes
+}
+
+/// the packet's kind and the rest of it, `None` for other protocols
+fn split_packet(bytes: &[u8]) -> Option<(u8, &[u8])> {
+    let mut reader = Reader(bytes);
+    if reader.read_u32()? != PROTOCOL_ID {
+        return None;
+    }
+    Some((reader.read_u8()?, reader.0))
+}
+
+/// little endian values off the front of a packet
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn read<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let (bytes, rest) = self.0.split_first_chunk::<N>()?;
+        self.0 = rest;
+        Some(*bytes)
+    }
+
+    fn read_u8(&mut self) -> Option<u8> {
+        self.read::<1>().map(|[byte]| byte)
+    }
+
+    fn read_u32(&mut self) -> Option<u32> {
+        self.read().map(u32::from_le_bytes)
+    }
+
+    fn read_f32(&mut self) -> Option<f32> {
+        self.read().map(f32::from_le_bytes)
+    }
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+fn lerp3(a: (f32, f32, f32), b: (f32, f32, f32), t: f32) -> (f32, f32, f32) {
+    (lerp(a.0, b.0, t), lerp(a.1, b.1, t), lerp(a.2, b.2, t))
+}
+
+/// the shorter way around
+fn lerp_angle(a: f32, b: f32, t: f32) -> f32 {
+    use std::f32::consts::{PI, TAU};
+    a + ((b - a + PI).rem_euclid(TAU) - PI) * t
+}
+
+/// `t` of the way from `a` to `b`, objects only in one of them are taken from `b`
+fn interpolate(a: &Snapshot, b: &Snapshot, t: f32) -> Snapshot {
+    let transforms = b.transforms.iter().enumerate().map(|(index, to)| {
+        let Some(from) = a.transforms.get(index) else {
+            return *to;
+        };
+        let rotation = |(s, yx, zy, xz)| Rotor::new(s, yx, zy, xz);
+        Transform {
+            translation: lerp3(from.translation, to.translation, t),
+            rotation: rotation(from.rotation).nlerp(&rotation(to.rotation), t).components(),
+            scale: lerp3(from.scale, to.scale, t),
+        }
+    });
+    let camera = match (&a.camera, &b.camera) {
+        (Some(from), Some(to)) => Some(CameraState {
+            translation: lerp3(from.translation, to.translation, t),
+            z_x_angle: lerp_angle(from.z_x_angle, to.z_x_angle, t),
+            y_xz_angle: lerp(from.y_xz_angle, to.y_xz_angle, t),
+            ..*to
+        }),
+        _ => b.camera,
+    };
+    Snapshot { tick: b.tick, camera, transforms: transforms.collect() }
+}
+
+/// non blocking, errors besides having nothing to receive are logged and dropped
+struct Transport {
+    socket: UdpSocket,
+    buffer: Vec<u8>,
+}
+
+impl Transport {
+    /// panics when `address` can't be bound, port 0 picks a free one
+    fn bind(address: &str) -> Self {
+        let socket = UdpSocket::bind(address).unwrap_or_else(|err| panic!("Failed to bind {}: {}", address, err));
+        socket.set_nonblocking(true).unwrap();
+        Self { socket, buffer: vec![0; MAX_PACKET_SIZE] }
+    }
+
+    fn send(&self, to: SocketAddr, bytes: &[u8]) {
+        if let Err(err) = self.socket.send_to(bytes, to) {
+            log::warn!("Failed to send to {}: {}", to, err);
+        }
+    }
+
+    /// the next packet received of this protocol, its kind and the rest of it
+    fn receive(&mut self) -> Option<(SocketAddr, u8, Vec<u8>)> {
+        loop {
+            match self.socket.recv_from(&mut self.buffer) {
+                Ok((size, from)) => {
+                    if let Some((kind, rest)) = split_packet(&self.buffer[..size]) {
+                        return Some((from, kind, rest.to_vec()));
+                    }
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return None,
+                // e.g. the port of a client that went away was unreachable
+                Err(err) => log::debug!("Failed to receive: {}", err),
+            }
+        }
+    }
+}
+
+/// Sends snapshots to every client that said hello, `tick` once per fixed update
+pub struct Server {
+    transport: Transport,
+    /// and when each was last heard from
+    clients: Vec<(SocketAddr, Instant)>,
+    tick: u32,
+}
+
+impl Server {
+    pub fn bind(address: &str) -> Self {
+        let transport = Transport::bind(address);
+        log::info!("Hosting on {}", transport.socket.local_addr().unwrap());
+        Self { transport, clients: vec![], tick: 0 }
+    }
+
+    pub fn get_client_count(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// takes in hellos and every `SNAPSHOT_INTERVAL` ticks sends the snapshot `snapshot` makes
+    /// of the tick to the clients
+    pub fn tick<F: FnOnce(u32) -> Snapshot>(&mut self, snapshot: F) {
+        let now = Instant::now();
+        while let Some((from, kind, _)) = self.transport.receive() {
+            if kind != PACKET_HELLO {
+                continue;
+            }
+            match self.clients.iter_mut().find(|(client, _)| *client == from) {
+                Some((_, last_heard)) => *last_heard = now,
+                None => {
+                    log::info!("Client {} connected", from);
+                    self.clients.push((from, now));
+                }
+            }
+        }
+        self.clients.retain(|&(client, last_heard)| {
+            let timed_out = now - last_heard > CLIENT_TIMEOUT;
+            if timed_out {
+                log::info!("Client {} timed out", client);
+            }
+            !timed_out
+        });
+
+        if self.tick.is_multiple_of(SNAPSHOT_INTERVAL) && !self.clients.is_empty() {
+            let bytes = snapshot(self.tick).encode();
+            for &(client, _) in &self.clients {
+                self.transport.send(client, &bytes);
+            }
+        }
+        self.tick += 1;
+    }
+}
+
+/// Receives a host's snapshots, `update` every frame then `sample` what to show
+pub struct Client {
+    transport: Transport,
+    server: SocketAddr,
+    last_hello: Option<Instant>,
+    /// oldest first
+    snapshots: VecDeque<Snapshot>,
+    /// the host's time as far as the client can tell, `None` until the first snapshot
+    clock: Option<f32>,
+    /// seconds in the past snapshots are shown, covering a couple of late or lost ones
+    pub interpolation_delay: f32,
+}
+
+impl Client {
+    /// panics when `server` isn't an address
+    pub fn connect(server: &str) -> Self {
+        let server: SocketAddr = server.parse().unwrap_or_else(|err| panic!("Invalid address {}: {}", server, err));
+        let local = if server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        log::info!("Connecting to {}", server);
+        Self {
+            transport: Transport::bind(local),
+            server,
+            last_hello: None,
+            snapshots: VecDeque::new(),
+            clock: None,
+            interpolation_delay: 3.0 * SNAPSHOT_INTERVAL as f32 * FIXED_TIMESTEP,
+        }
+    }
+
+    /// says hello when due and takes in snapshots, `dt` in seconds since the last update
+    pub fn update(&mut self, dt: f32) {
+        let now = Instant::now();
+        if self.last_hello.is_none_or(|last_hello| now - last_hello >= HELLO_INTERVAL) {
+            self.transport.send(self.server, &packet_header(PACKET_HELLO));
+            self.last_hello = Some(now);
+        }
+
+        if let Some(clock) = &mut self.clock {
+            *clock += dt;
+        }
+        while let Some((from, kind, rest)) = self.transport.receive() {
+            if from != self.server || kind != PACKET_SNAPSHOT {
+                continue;
+            }
+            let Some(snapshot) = Snapshot::decode(&rest) else {
+                log::warn!("Malformed snapshot from {}", from);
+                continue;
+            };
+            // out of order ones are only late, the newer ones already cover them
+            if self.snapshots.back().is_some_and(|newest| newest.tick >= snapshot.tick) {
+                continue;
+            }
+            // resynchronize when drifting, e.g. after the host restarted
+            if self.clock.is_none_or(|clock| (clock - snapshot.time()).abs() > self.interpolation_delay) {
+                if self.clock.is_none() {
+                    log::info!("Receiving snapshots from {}", from);
+                }
+                self.clock = Some(snapshot.time());
+                self.snapshots.clear();
+            }
+            self.snapshots.push_back(snapshot);
+            if self.snapshots.len() > MAX_BUFFERED_SNAPSHOTS {
+                self.snapshots.pop_front();
+            }
+        }
+    }
+
+    /// the host's state `interpolation_delay` ago, `None` until the first snapshot arrives
+    pub fn sample(&self) -> Option<Snapshot> {
+        let time = self.clock? - self.interpolation_delay;
+        let next = self.snapshots.iter().position(|snapshot| snapshot.time() > time);
+        Some(match next {
+            Some(0) => self.snapshots[0].clone(),
+            Some(next) => {
+                let (a, b) = (&self.snapshots[next - 1], &self.snapshots[next]);
+                interpolate(a, b, (time - a.time()) / (b.time() - a.time()))
+            }
+            // lost the last few, holds the newest rather than guessing
+            None => self.snapshots.back()?.clone(),
+        })
+    }
+}
+
+/// hosts `scene` on `address` without a window or renderer, playing its animations. Never returns
+pub fn run_headless_server(scene: Scene, address: &str) -> ! {
+    let mut scene_instance = scene.instantiate_headless();
+    let mut server = Server::bind(address);
+    let timestep = Duration::from_secs_f32(FIXED_TIMESTEP);
+    let mut next_tick = Instant::now();
+    loop {
+        scene_instance.update_animations(FIXED_TIMESTEP);
+        server.tick(|tick| Snapshot {
+            tick,
+            camera: None,
+            transforms: scene_instance.local_transforms(&scene),
+        });
+
+        next_tick += timestep;
+        let now = Instant::now();
+        if next_tick > now {
+            std::thread::sleep(next_tick - now);
+        } else {
+            // fell behind, e.g. suspended, skips the missed ticks instead of rushing them
+            next_tick = now;
+        }
+    }
+}
+
+#[test]
+fn test_net() {
+    let transform = |x: f32, angle: f32| Transform {
+        translation: (x, 1.0, 2.0),
+        rotation: Rotor::from_axis_angle(crate::math::Vector::new(0.0, 1.0, 0.0), angle).components(),
+        scale: (1.0, 1.0, 1.0),
+    };
+    let camera = |x: f32, z_x_angle: f32| CameraState {
+        translation: (x, 0.0, 0.0),
+        z_x_angle,
+        y_xz_angle: 0.0,
+        near_z: 0.1,
+        far_z: 100.0,
+    };
+    let a = Snapshot { tick: 3, camera: Some(camera(0.0, 3.0)), transforms: vec![transform(0.0, 0.0)] };
+    let b = Snapshot { tick: 6, camera: Some(camera(2.0, -3.0)), transforms: vec![transform(4.0, 1.0), transform(9.0, 0.0)] };
+
+    let (kind, rest) = split_packet(&b.encode()).map(|(kind, rest)| (kind, rest.to_vec())).unwrap();
+    assert!(kind == PACKET_SNAPSHOT && Snapshot::decode(&rest) == Some(b.clone()));
+    assert!(Snapshot::decode(&rest[..rest.len() - 1]).is_none() && split_packet(b"hello").is_none());
+
+    // halfway, the new object as it is, the camera turning through pi rather than back through 0
+    let halfway = interpolate(&a, &b, 0.5);
+    assert!(halfway.transforms[0].translation == (2.0, 1.0, 2.0) && halfway.transforms[1] == b.transforms[1]);
+    let (s, yx, zy, xz) = halfway.transforms[0].rotation;
+    assert!((Rotor::new(s, yx, zy, xz).to_axis_angle().1 - 0.5).abs() < 1e-4);
+    let halfway_camera = halfway.camera.unwrap();
+    assert!(halfway_camera.translation == (1.0, 0.0, 0.0) && (halfway_camera.z_x_angle.abs() - std::f32::consts::PI).abs() < 1e-5);
+
+    // a client on the loopback hears from the host once it said hello
+    let mut server = Server::bind("127.0.0.1:0");
+    let mut client = Client::connect(&server.transport.socket.local_addr().unwrap().to_string());
+    client.update(0.0);
+    for _ in 0..100 {
+        server.tick(|tick| Snapshot { tick, camera: None, transforms: vec![transform(tick as f32, 0.0)] });
+        client.update(FIXED_TIMESTEP);
+        if client.sample().is_some() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    assert!(server.get_client_count() == 1);
+    assert!(client.sample().is_some_and(|snapshot| snapshot.transforms.len() == 1));
+}
@@ -0,0 +1,253 @@
+// CPU particles, emitters spawn particles that are simulated every update
+// and drawn as camera facing billboards
+
+use std::f32::consts::TAU;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{math::Vector, renderer::billboard::Billboard};
+
+/// over all emitters, further spawns are dropped
+pub const MAX_PARTICLE_COUNT: usize = 0x4000;
+
+pub type EmitterId = u16;
+
+/// What an emitter spawns, ranges are (min, max) and sampled uniformly per particle
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EmitterDesc {
+    /// particles per second
+    pub spawn_rate: f32,
+    /// seconds
+    pub lifetime: (f32, f32),
+    pub speed: (f32, f32),
+    /// center of the cone particles are launched in, normalized on use
+    pub direction: (f32, f32, f32),
+    /// half angle of the cone in radians
+    pub spread: f32,
+    /// vertical acceleration, positive falls as world y points down
+    pub gravity: f32,
+    /// world space width over the particle's life
+    pub size: (f32, f32),
+    pub start_color: (f32, f32, f32, f32),
+    pub end_color: (f32, f32, f32, f32),
+    /// simulated by a compute shader instead, for emitters spawning thousands of particles
+    pub gpu: bool,
+}
+
+impl Default for EmitterDesc {
+    fn default() -> Self {
+        Self {
+            spawn_rate: 20.0,
+            lifetime: (1.0, 2.0),
+            speed: (0.5, 1.0),
+            direction: (0.0, -1.0, 0.0),
+            spread: 0.3,
+            gravity: 0.0,
+            size: (0.2, 0.05),
+            start_color: (1.0, 0.6, 0.2, 1.0),
+            end_color: (0.5, 0.1, 0.0, 0.0),
+            gpu: false,
+        }
+    }
+}
+
+pub struct Emitter {
+    pub desc: EmitterDesc,
+    pub position: Vector,
+    pub enabled: bool,
+    /// fractional spawns carried over to the next update
+    spawn_accumulator: f32,
+}
+
+impl Emitter {
+    pub fn new(desc: EmitterDesc, position: Vector) -> Self {
+        Self {
+            desc,
+            position,
+            enabled: true,
+            spawn_accumulator: 0.0,
+        }
+    }
+
+    /// particles to spawn after `dt` more seconds
+    pub fn take_spawns(&mut self, dt: f32) -> u32 {
+        if !self.enabled {
+            self.spawn_accumulator = 0.0;
+            return 0;
+        }
+        self.spawn_accumulator += self.desc.spawn_rate * dt;
+        let spawns = self.spawn_accumulator.floor();
+        self.spawn_accumulator -= spawns;
+        spawns as u32
+    }
+}
+
+struct Particle {
+    position: Vector,
+    velocity: Vector,
+    age: f32,
+    lifetime: f32,
+    emitter: EmitterId,
+}
+
+/// xorshift, plenty for visual randomness
+struct Rng(u32);
+
+impl Rng {
+    fn next_f32(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 >> 8) as f32 / (1 << 24) as f32
+    }
+
+    fn range(&mut self, (min, max): (f32, f32)) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+/// uniformly distributed in the cone of half angle `spread` around `direction`
+fn sample_cone(rng: &mut Rng, direction: (f32, f32, f32), spread: f32) -> Vector {
+    let (x, y, z) = direction;
+    let norm = (x * x + y * y + z * z).sqrt();
+    let axis = Vector::new(x / norm, y / norm, z / norm);
+
+    // any two vectors perpendicular to the axis
+    let helper = if axis.x.abs() < 0.9 { Vector::new(1.0, 0.0, 0.0) } else { Vector::new(0.0, 1.0, 0.0) };
+    let tangent = cross(helper, axis);
+    let tangent = tangent / tangent.norm_sqr().sqrt();
+    let bitangent = cross(axis, tangent);
+
+    let cos_theta = lerp(spread.cos(), 1.0, rng.next_f32());
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+    let phi = rng.next_f32() * TAU;
+
+    let mut sample = axis * cos_theta;
+    sample += tangent * (sin_theta * phi.cos());
+    sample += bitangent * (sin_theta * phi.sin());
+    sample
+}
+
+fn cross(a: Vector, b: Vector) -> Vector {
+    Vector::new(a.y * b.z - a.z * b.y, a.z * b.x - a.x * b.z, a.x * b.y - a.y * b.x)
+}
+
+pub struct ParticleSystem {
+    emitters: Vec<Emitter>,
+    particles: Vec<Particle>,
+    rng: Rng,
+}
+
+impl Default for ParticleSystem {
+    fn default() -> Self {
+        Self {
+            emitters: vec![],
+            particles: vec![],
+            rng: Rng(0x2545f491),
+        }
+    }
+}
+
+impl ParticleSystem {
+    // TODO: removing emitters, disable them for now
+    pub fn add_emitter(&mut self, desc: EmitterDesc, position: Vector) -> EmitterId {
+        self.emitters.push(Emitter::new(desc, position));
+        (self.emitters.len() - 1) as EmitterId
+    }
+
+    pub fn get_emitter_mut(&mut self, id: EmitterId) -> &mut Emitter {
+        &mut self.emitters[id as usize]
+    }
+
+    pub fn particle_count(&self) -> usize {
+        self.particles.len()
+    }
+
+    /// ages, moves and spawns particles
+    pub fn update(&mut self, dt: f32) {
+        let emitters = &self.emitters;
+        self.particles.retain_mut(|particle| {
+            particle.age += dt;
+            if particle.age >= particle.lifetime {
+                return false;
+            }
+            particle.velocity.y += emitters[particle.emitter as usize].desc.gravity * dt;
+            particle.position += particle.velocity * dt;
+            true
+        });
+
+        for (id, emitter) in self.emitters.iter_mut().enumerate() {
+            for _ in 0..emitter.take_spawns(dt) {
+                if self.particles.len() >= MAX_PARTICLE_COUNT {
+                    continue;
+                }
+
+                let direction = sample_cone(&mut self.rng, emitter.desc.direction, emitter.desc.spread);
+                let speed = self.rng.range(emitter.desc.speed);
+                self.particles.push(Particle {
+                    position: emitter.position,
+                    velocity: direction * speed,
+                    age: 0.0,
+                    lifetime: self.rng.range(emitter.desc.lifetime),
+                    emitter: id as EmitterId,
+                });
+            }
+        }
+    }
+
+    /// one per live particle
+    pub fn billboards(&self) -> impl Iterator<Item = Billboard> + '_ {
+        self.particles.iter().map(|particle| {
+            let desc = &self.emitters[particle.emitter as usize].desc;
+            let t = particle.age / particle.lifetime;
+            let (r0, g0, b0, a0) = desc.start_color;
+            let (r1, g1, b1, a1) = desc.end_color;
+            Billboard {
+                position: [particle.position.x, particle.position.y, particle.position.z],
+                size: lerp(desc.size.0, desc.size.1, t),
+                color: [lerp(r0, r1, t), lerp(g0, g1, t), lerp(b0, b1, t), lerp(a0, a1, t)],
+            }
+        })
+    }
+}
+
+#[test]
+fn test_particle_system() {
+    let mut system = ParticleSystem::default();
+    let emitter = system.add_emitter(
+        EmitterDesc {
+            spawn_rate: 10.0,
+            lifetime: (1.0, 1.0),
+            speed: (1.0, 1.0),
+            direction: (0.0, 1.0, 0.0),
+            spread: 0.0,
+            ..Default::default()
+        },
+        Vector::new(0.0, 0.0, 0.0),
+    );
+
+    system.update(0.5);
+    assert!(system.particle_count() == 5);
+    system.update(0.25);
+    assert!(system.particle_count() == 7);
+
+    // the first five have flown straight up at 1 m/s for 0.25 seconds
+    let billboards = system.billboards().collect::<Vec<_>>();
+    assert!((billboards[0].position[1] - 0.25).abs() < 1e-5);
+
+    system.get_emitter_mut(emitter).enabled = false;
+    system.update(0.8);
+    assert!(system.particle_count() == 2);
+
+    let mut rng = Rng(1);
+    for _ in 0..100 {
+        let sample = sample_cone(&mut rng, (1.0, 1.0, 0.0), 0.2);
+        let cos_angle = (sample.x + sample.y) / 2f32.sqrt();
+        assert!((sample.norm_sqr() - 1.0).abs() < 1e-4 && cos_angle >= 0.2f32.cos() - 1e-4);
+    }
+}
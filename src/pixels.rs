@@ -0,0 +1,210 @@
+// CPU side pixel tools, for when the device can't do the work for us
+// (readbacks, formats that can't be blitted, asset cooking)
+
+/// one level of a mip chain, tightly packed rgba8
+pub struct Mip {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+pub fn mip_level_count(width: u32, height: u32) -> u32 {
+    32 - width.max(height).max(1).leading_zeros()
+}
+
+#[inline(always)]
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[inline(always)]
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// alpha is left untouched
+pub fn rgba8_srgb_to_linear(pixels: &mut [u8]) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        for c in &mut pixel[..3] {
+            *c = (srgb_to_linear(*c as f32 / 255.0) * 255.0 + 0.5) as u8;
+        }
+    }
+}
+
+/// alpha is left untouched
+pub fn rgba8_linear_to_srgb(pixels: &mut [u8]) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        for c in &mut pixel[..3] {
+            *c = (linear_to_srgb(*c as f32 / 255.0) * 255.0 + 0.5) as u8;
+        }
+    }
+}
+
+/// converts between bgra8 and rgba8, works both ways
+pub fn swap_red_blue(pixels: &mut [u8]) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        pixel.swap(0, 2);
+    }
+}
+
+/// an ieee 754 half, the channel type of R16G16B16A16_SFLOAT
+pub fn half_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+/// inverse of the st 2084 curve, 0..=1 to nits
+pub fn pq_to_nits(encoded: f32) -> f32 {
+    // as the standard gives them
+    const M1: f32 = 2610.0 / 16384.0;
+    const M2: f32 = 2523.0 / 4096.0 * 128.0;
+    const C1: f32 = 3424.0 / 4096.0;
+    const C2: f32 = 2413.0 / 4096.0 * 32.0;
+    const C3: f32 = 2392.0 / 4096.0 * 32.0;
+
+    let p = encoded.clamp(0.0, 1.0).powf(1.0 / M2);
+    10000.0 * ((p - C1).max(0.0) / (C2 - C3 * p)).powf(1.0 / M1)
+}
+
+/// linear bt.2020 primaries to linear bt.709 ones, colors outside bt.709 come out negative
+pub fn bt2020_to_bt709(rgb: [f32; 3]) -> [f32; 3] {
+    const ROWS: [[f32; 3]; 3] = [
+        [1.6605, -0.5876, -0.0728],
+        [-0.1246, 1.1329, -0.0083],
+        [-0.0182, -0.1006, 1.1187],
+    ];
+    ROWS.map(|row| row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2])
+}
+
+/// linear colors where 1 is sdr white into 0..=1, unchanged up to a knee and rolled off above it
+pub fn tonemap(linear: f32) -> f32 {
+    const KNEE: f32 = 0.8;
+    if linear <= KNEE {
+        return linear.max(0.0);
+    }
+    let over = (linear - KNEE) / (1.0 - KNEE);
+    KNEE + (1.0 - KNEE) * over / (1.0 + over)
+}
+
+/// Box filters the base level down to 1x1, returns levels 1..,
+/// `srgb`: averages in linear space so the mips don't darken
+pub fn generate_mips_rgba8(width: u32, height: u32, pixels: &[u8], srgb: bool) -> Vec<Mip> {
+    assert!(pixels.len() == (width * height * 4) as usize);
+
+    let level_count = mip_level_count(width, height);
+    let mut mips: Vec<Mip> = Vec::with_capacity(level_count as usize - 1);
+
+    let mut src_width = width;
+    let mut src_height = height;
+    let mut src = pixels;
+
+    for _ in 1..level_count {
+        let dst_width = (src_width / 2).max(1);
+        let dst_height = (src_height / 2).max(1);
+        let mut dst = vec![0; (dst_width * dst_height * 4) as usize];
+
+        for y in 0..dst_height {
+            let y0 = (2 * y).min(src_height - 1);
+            let y1 = (2 * y + 1).min(src_height - 1);
+            for x in 0..dst_width {
+                let x0 = (2 * x).min(src_width - 1);
+                let x1 = (2 * x + 1).min(src_width - 1);
+
+                let samples = [
+                    ((y0 * src_width + x0) * 4) as usize,
+                    ((y0 * src_width + x1) * 4) as usize,
+                    ((y1 * src_width + x0) * 4) as usize,
+                    ((y1 * src_width + x1) * 4) as usize,
+                ];
+                let dst_index = ((y * dst_width + x) * 4) as usize;
+
+                for c in 0..4 {
+                    let is_color = c != 3;
+                    let mut sum = 0.0;
+                    for &sample in &samples {
+                        let value = src[sample + c] as f32 / 255.0;
+                        sum += if srgb && is_color { srgb_to_linear(value) } else { value };
+                    }
+                    let average = sum / 4.0;
+                    let average = if srgb && is_color { linear_to_srgb(average) } else { average };
+                    dst[dst_index + c] = (average * 255.0 + 0.5) as u8;
+                }
+            }
+        }
+
+        mips.push(Mip {
+            width: dst_width,
+            height: dst_height,
+            pixels: dst,
+        });
+
+        src_width = dst_width;
+        src_height = dst_height;
+        src = &mips.last().unwrap().pixels;
+    }
+
+    mips
+}
+
+#[test]
+fn test_generate_mips() {
+    assert!(mip_level_count(1, 1) == 1);
+    assert!(mip_level_count(4, 3) == 3);
+    assert!(mip_level_count(1024, 1) == 11);
+
+    let pixels = [
+        0, 0, 0, 0,         255, 255, 255, 255,
+        255, 255, 255, 255, 0, 0, 0, 0,
+    ];
+    let mips = generate_mips_rgba8(2, 2, &pixels, false);
+    assert!(mips.len() == 1);
+    assert!(mips[0].width == 1 && mips[0].height == 1);
+    assert!(mips[0].pixels == [128, 128, 128, 128]);
+
+    // averaging in linear space is brighter than averaging the encoded values
+    let mips = generate_mips_rgba8(2, 2, &pixels, true);
+    assert!(mips[0].pixels[0] > 128);
+    assert!(mips[0].pixels[3] == 128);
+}
+
+#[test]
+fn test_srgb_round_trip() {
+    for i in 0..=255_u8 {
+        let c = i as f32 / 255.0;
+        let round_trip = linear_to_srgb(srgb_to_linear(c));
+        assert!((round_trip - c).abs() < 1e-4);
+    }
+}
+
+#[test]
+fn test_hdr_decoding() {
+    // 1, -2, 0.5, 65504, infinity
+    assert!(half_to_f32(0x3c00) == 1.0 && half_to_f32(0xc000) == -2.0 && half_to_f32(0x3800) == 0.5);
+    assert!(half_to_f32(0x7bff) == 65504.0 && half_to_f32(0x7c00) == f32::INFINITY);
+    assert!(half_to_f32(0x0001) == 2f32.powi(-24));
+
+    assert!(pq_to_nits(0.0) == 0.0 && (pq_to_nits(1.0) - 10000.0).abs() < 1.0);
+    // 100 nits encodes to about 0.508
+    assert!((pq_to_nits(0.5081) - 100.0).abs() < 0.5);
+
+    let white = bt2020_to_bt709([1.0; 3]);
+    assert!(white.iter().all(|c| (c - 1.0).abs() < 1e-3));
+
+    assert!(tonemap(-1.0) == 0.0 && tonemap(0.5) == 0.5);
+    assert!(tonemap(1.0) < 1.0 && tonemap(100.0) < 1.0 && tonemap(2.0) > tonemap(1.0));
+}
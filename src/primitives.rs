@@ -0,0 +1,378 @@
+// Procedural meshes for tests and demos that shouldn't need asset files, centered on the origin with
+// outward normals and uvs. Tangents are left for `create_geometry` to generate:
+//
+//     let (vertices, indices) = primitives::uv_sphere(0.5, 32, 16);
+//     let sphere = app.geometry_system.create_geometry(&vertices, &indices);
+//
+// Scenes refer to them by path, `geometry: Some("primitives/capsule")`, see `from_path`.
+// Round shapes stand along y, world y points down so their tops are at -y
+
+use std::{
+    collections::HashMap,
+    f32::consts::{PI, TAU},
+};
+
+use crate::geometry::{Index, Vertex};
+
+/// asset paths starting with this name a primitive
+pub const PATH_PREFIX: &str = "primitives/";
+
+struct MeshBuilder {
+    vertices: Vec<Vertex>,
+    indices: Vec<Index>,
+}
+
+/// a ring of a surface of revolution
+#[derive(Clone, Copy)]
+struct ProfilePoint {
+    radius: f32,
+    y: f32,
+    /// outward, radially and along y
+    normal: [f32; 2],
+    v: f32,
+}
+
+impl MeshBuilder {
+    fn new() -> Self {
+        Self { vertices: vec![], indices: vec![] }
+    }
+
+    fn vertex(&mut self, [x, y, z]: [f32; 3], [nx, ny, nz]: [f32; 3], [u, v]: [f32; 2]) -> Index {
+        self.vertices.push(Vertex { x, y, z, u, v, nx, ny, nz, ..Default::default() });
+        (self.vertices.len() - 1) as Index
+    }
+
+    /// wound so it faces along its vertices' normals, degenerate ones at poles and apexes are left out
+    fn triangle(&mut self, triangle: [Index; 3]) {
+        let normal = face_normal(&self.vertices, triangle);
+        if dot(normal, normal) < 1e-12 {
+            return;
+        }
+        let outward = triangle
+            .map(|index| &self.vertices[index as usize])
+            .iter()
+            .fold([0.0; 3], |sum, vertex| [sum[0] + vertex.nx, sum[1] + vertex.ny, sum[2] + vertex.nz]);
+        let [a, b, c] = triangle;
+        if dot(normal, outward) >= 0.0 {
+            self.indices.extend_from_slice(&[a, b, c]);
+        } else {
+            self.indices.extend_from_slice(&[a, c, b]);
+        }
+    }
+
+    /// `columns` by `rows` quads with uvs from (0, 0) to (1, 1), `point` gives the position and normal at a uv
+    fn grid(&mut self, columns: u32, rows: u32, point: impl Fn(f32, f32) -> ([f32; 3], [f32; 3])) {
+        let first = self.vertices.len() as Index;
+        for j in 0..=rows {
+            for i in 0..=columns {
+                let uv = [i as f32 / columns as f32, j as f32 / rows as f32];
+                let (position, normal) = point(uv[0], uv[1]);
+                self.vertex(position, normal, uv);
+            }
+        }
+        self.grid_triangles(first, columns, rows);
+    }
+
+    /// of a grid of vertices starting at `first`, row by row
+    fn grid_triangles(&mut self, first: Index, columns: u32, rows: u32) {
+        let side = columns + 1;
+        for j in 0..rows {
+            for i in 0..columns {
+                let a = first + j * side + i;
+                let (b, c, d) = (a + 1, a + side, a + side + 1);
+                self.triangle([a, b, c]);
+                self.triangle([b, d, c]);
+            }
+        }
+    }
+
+    /// `profile` swept around y, u goes around once
+    fn lathe(&mut self, sectors: u32, profile: &[ProfilePoint]) {
+        let first = self.vertices.len() as Index;
+        for point in profile {
+            for i in 0..=sectors {
+                let u = i as f32 / sectors as f32;
+                let (sin, cos) = (u * TAU).sin_cos();
+                self.vertex(
+                    [point.radius * cos, point.y, point.radius * sin],
+                    [point.normal[0] * cos, point.normal[1], point.normal[0] * sin],
+                    [u, point.v],
+                );
+            }
+        }
+        self.grid_triangles(first, sectors, profile.len() as u32 - 1);
+    }
+
+    /// flat disc at `y` facing along `normal_y`, uvs map the square around it
+    fn disc(&mut self, radius: f32, y: f32, normal_y: f32, sectors: u32) {
+        let normal = [0.0, normal_y, 0.0];
+        let center = self.vertex([0.0, y, 0.0], normal, [0.5, 0.5]);
+        for i in 0..=sectors {
+            let (sin, cos) = (i as f32 / sectors as f32 * TAU).sin_cos();
+            self.vertex([radius * cos, y, radius * sin], normal, [0.5 + 0.5 * cos, 0.5 + 0.5 * sin]);
+        }
+        for i in 0..sectors {
+            self.triangle([center, center + 1 + i, center + 2 + i]);
+        }
+    }
+
+    fn build(self) -> (Vec<Vertex>, Vec<Index>) {
+        (self.vertices, self.indices)
+    }
+}
+
+/// (b - a) x (c - a) of the triangle's positions, front faces point it at the viewer
+fn face_normal(vertices: &[Vertex], [a, b, c]: [Index; 3]) -> [f32; 3] {
+    let (a, b, c) = (&vertices[a as usize], &vertices[b as usize], &vertices[c as usize]);
+    cross([b.x - a.x, b.y - a.y, b.z - a.z], [c.x - a.x, c.y - a.y, c.z - a.z])
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let length = dot(v, v).sqrt();
+    v.map(|c| c / length)
+}
+
+/// with edges of `size`, each face has its own vertices for hard edges and the whole texture
+pub fn cube(size: f32) -> (Vec<Vertex>, Vec<Index>) {
+    let half = size / 2.0;
+    let mut builder = MeshBuilder::new();
+    // normal and the face's u and v axes
+    let faces = [
+        ([1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+        ([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+        ([0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+        ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+        ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+        ([0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ];
+    for (normal, u_axis, v_axis) in faces {
+        builder.grid(1, 1, |u, v| {
+            let position = [0, 1, 2].map(|axis| {
+                (normal[axis] + u_axis[axis] * (2.0 * u - 1.0) + v_axis[axis] * (2.0 * v - 1.0)) * half
+            });
+            (position, normal)
+        });
+    }
+    builder.build()
+}
+
+/// `width` along x by `depth` along z facing up, split into `subdivisions` quads per side
+pub fn plane(width: f32, depth: f32, subdivisions: u32) -> (Vec<Vertex>, Vec<Index>) {
+    let subdivisions = subdivisions.max(1);
+    let mut builder = MeshBuilder::new();
+    builder.grid(subdivisions, subdivisions, |u, v| {
+        ([(u - 0.5) * width, 0.0, (v - 0.5) * depth], [0.0, -1.0, 0.0])
+    });
+    builder.build()
+}
+
+/// `sectors` around and `stacks` from top to bottom, the texture wraps around once
+pub fn uv_sphere(radius: f32, sectors: u32, stacks: u32) -> (Vec<Vertex>, Vec<Index>) {
+    let (sectors, stacks) = (sectors.max(3), stacks.max(2));
+    let profile: Vec<_> = (0..=stacks)
+        .map(|j| {
+            let v = j as f32 / stacks as f32;
+            let (sin, cos) = (v * PI).sin_cos();
+            ProfilePoint { radius: radius * sin, y: -radius * cos, normal: [sin, -cos], v }
+        })
+        .collect();
+    let mut builder = MeshBuilder::new();
+    builder.lathe(sectors, &profile);
+    builder.build()
+}
+
+/// an icosahedron split `subdivisions` times, triangles of even size unlike `uv_sphere`.
+/// Uvs wrap around like `uv_sphere`'s, vertices on the seam are doubled
+pub fn icosphere(radius: f32, subdivisions: u32) -> (Vec<Vertex>, Vec<Index>) {
+    let t = (1.0 + 5.0f32.sqrt()) / 2.0;
+    let mut positions: Vec<[f32; 3]> = [
+        [-1.0, t, 0.0], [1.0, t, 0.0], [-1.0, -t, 0.0], [1.0, -t, 0.0],
+        [0.0, -1.0, t], [0.0, 1.0, t], [0.0, -1.0, -t], [0.0, 1.0, -t],
+        [t, 0.0, -1.0], [t, 0.0, 1.0], [-t, 0.0, -1.0], [-t, 0.0, 1.0],
+    ]
+    .into_iter()
+    .map(normalize)
+    .collect();
+    let mut triangles: Vec<[Index; 3]> = vec![
+        [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+        [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+        [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+        [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        let mut midpoints: HashMap<(Index, Index), Index> = HashMap::new();
+        let mut midpoint = |a: Index, b: Index| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                let (a, b) = (positions[a as usize], positions[b as usize]);
+                positions.push(normalize([a[0] + b[0], a[1] + b[1], a[2] + b[2]]));
+                (positions.len() - 1) as Index
+            })
+        };
+        triangles = triangles
+            .into_iter()
+            .flat_map(|[a, b, c]| {
+                let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+                [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    let mut builder = MeshBuilder::new();
+    for &normal in &positions {
+        let u = (normal[2].atan2(normal[0]) / TAU).rem_euclid(1.0);
+        let v = (-normal[1]).clamp(-1.0, 1.0).acos() / PI;
+        builder.vertex(normal.map(|c| c * radius), normal, [u, v]);
+    }
+    // triangles spanning the seam use copies of their vertices near u = 0 with u past 1
+    let mut wrapped: HashMap<Index, Index> = HashMap::new();
+    for triangle in triangles {
+        let us = triangle.map(|index| builder.vertices[index as usize].u);
+        let spans_seam = us.iter().fold(f32::MIN, |a, &b| a.max(b)) - us.iter().fold(f32::MAX, |a, &b| a.min(b)) > 0.5;
+        let triangle = triangle.map(|index| {
+            let vertex = builder.vertices[index as usize];
+            if !spans_seam || vertex.u >= 0.5 {
+                return index;
+            }
+            *wrapped.entry(index).or_insert_with(|| {
+                builder.vertices.push(Vertex { u: vertex.u + 1.0, ..vertex });
+                (builder.vertices.len() - 1) as Index
+            })
+        });
+        builder.triangle(triangle);
+    }
+    builder.build()
+}
+
+/// `height` along y with capped ends, the side wraps the texture around once
+pub fn cylinder(radius: f32, height: f32, sectors: u32) -> (Vec<Vertex>, Vec<Index>) {
+    let sectors = sectors.max(3);
+    let half = height / 2.0;
+    let mut builder = MeshBuilder::new();
+    builder.lathe(sectors, &[
+        ProfilePoint { radius, y: -half, normal: [1.0, 0.0], v: 0.0 },
+        ProfilePoint { radius, y: half, normal: [1.0, 0.0], v: 1.0 },
+    ]);
+    builder.disc(radius, -half, -1.0, sectors);
+    builder.disc(radius, half, 1.0, sectors);
+    builder.build()
+}
+
+/// `height` along y, pointing up with a capped base
+pub fn cone(radius: f32, height: f32, sectors: u32) -> (Vec<Vertex>, Vec<Index>) {
+    let sectors = sectors.max(3);
+    let half = height / 2.0;
+    let slant = (radius * radius + height * height).sqrt();
+    // perpendicular to the side, each sector gets its own apex vertex for smooth shading
+    let normal = [height / slant, -radius / slant];
+    let mut builder = MeshBuilder::new();
+    builder.lathe(sectors, &[
+        ProfilePoint { radius: 0.0, y: -half, normal, v: 0.0 },
+        ProfilePoint { radius, y: half, normal, v: 1.0 },
+    ]);
+    builder.disc(radius, half, 1.0, sectors);
+    builder.build()
+}
+
+/// `height` along y from tip to tip, a cylinder between hemispheres of `stacks` each.
+/// Uvs go from top to bottom by distance along the surface
+pub fn capsule(radius: f32, height: f32, sectors: u32, stacks: u32) -> (Vec<Vertex>, Vec<Index>) {
+    let (sectors, stacks) = (sectors.max(3), stacks.max(1));
+    let half_cylinder = (height / 2.0 - radius).max(0.0);
+    let cylinder = 2.0 * half_cylinder;
+    let length = PI * radius + cylinder;
+
+    let mut profile = vec![];
+    // top hemisphere from the tip down to the equator, then the bottom one from the equator
+    for j in 0..=stacks {
+        let angle = j as f32 / stacks as f32 * PI / 2.0;
+        let (sin, cos) = angle.sin_cos();
+        let v = angle * radius / length;
+        profile.push(ProfilePoint { radius: radius * sin, y: -half_cylinder - radius * cos, normal: [sin, -cos], v });
+    }
+    for j in 0..=stacks {
+        let angle = PI / 2.0 + j as f32 / stacks as f32 * PI / 2.0;
+        let (sin, cos) = angle.sin_cos();
+        let v = (angle * radius + cylinder) / length;
+        profile.push(ProfilePoint { radius: radius * sin, y: half_cylinder - radius * cos, normal: [sin, -cos], v });
+    }
+
+    let mut builder = MeshBuilder::new();
+    builder.lathe(sectors, &profile);
+    builder.build()
+}
+
+/// unit sized primitives by path, e.g. "primitives/cube", `None` for other paths
+pub fn from_path(path: &str) -> Option<(Vec<Vertex>, Vec<Index>)> {
+    Some(match path.strip_prefix(PATH_PREFIX)? {
+        "cube" => cube(1.0),
+        "plane" => plane(1.0, 1.0, 1),
+        "sphere" => uv_sphere(0.5, 32, 16),
+        "icosphere" => icosphere(0.5, 3),
+        "cylinder" => cylinder(0.5, 1.0, 32),
+        "cone" => cone(0.5, 1.0, 32),
+        "capsule" => capsule(0.5, 2.0, 32, 8),
+        _ => return None,
+    })
+}
+
+#[test]
+fn test_primitives() {
+    let meshes = [
+        ("cube", cube(2.0), 1.0),
+        ("plane", plane(2.0, 2.0, 4), 1.0),
+        ("uv_sphere", uv_sphere(1.0, 16, 8), 1.0),
+        ("icosphere", icosphere(1.0, 2), 1.5),
+        ("cylinder", cylinder(1.0, 2.0, 16), 1.0),
+        ("cone", cone(1.0, 2.0, 16), 1.0),
+        ("capsule", capsule(0.5, 3.0, 16, 4), 1.0),
+    ];
+    for (name, (vertices, indices), max_u) in &meshes {
+        assert!(!indices.is_empty() && indices.len().is_multiple_of(3), "{}", name);
+        assert!(indices.iter().all(|&index| (index as usize) < vertices.len()), "{}", name);
+        for vertex in vertices {
+            let normal = [vertex.nx, vertex.ny, vertex.nz];
+            assert!((dot(normal, normal) - 1.0).abs() < 1e-4, "{}", name);
+            assert!((0.0..=*max_u).contains(&vertex.u) && (0.0..=1.0).contains(&vertex.v), "{}", name);
+            assert!(vertex.tw == 0.0, "{}", name);
+        }
+        // front faces agree with the normals, and point away from the center on closed shapes
+        for triangle in indices.chunks_exact(3) {
+            let triangle = [triangle[0], triangle[1], triangle[2]];
+            let face = face_normal(vertices, triangle);
+            for index in triangle {
+                let vertex = &vertices[index as usize];
+                assert!(dot(face, [vertex.nx, vertex.ny, vertex.nz]) > 0.0, "{}", name);
+                if *name != "plane" {
+                    assert!(dot(face, [vertex.x, vertex.y, vertex.z]) > 0.0, "{}", name);
+                }
+            }
+        }
+    }
+
+    let (vertices, indices) = cube(2.0);
+    assert!(vertices.len() == 24 && indices.len() == 36);
+    assert!(vertices.iter().all(|v| v.x.abs() == 1.0 || v.y.abs() == 1.0 || v.z.abs() == 1.0));
+    let (vertices, _) = icosphere(2.0, 1);
+    assert!(vertices.iter().all(|v| (dot([v.x, v.y, v.z], [v.x, v.y, v.z]).sqrt() - 2.0).abs() < 1e-4));
+    let (vertices, _) = capsule(0.5, 3.0, 16, 4);
+    assert!(vertices.iter().all(|v| v.y.abs() <= 1.5 + 1e-5) && vertices.iter().any(|v| v.y == -1.5));
+    // the tip is up
+    let (vertices, _) = cone(1.0, 2.0, 8);
+    assert!(vertices.iter().filter(|v| v.x == 0.0 && v.z == 0.0).all(|v| v.y == -1.0 || v.y == 1.0));
+
+    assert!(from_path("primitives/capsule").is_some());
+    assert!(from_path("primitives/teapot").is_none() && from_path("meshes/cube.obj").is_none());
+}
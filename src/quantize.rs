@@ -0,0 +1,132 @@
+// Encodings for quantized vertex attributes, matching `pipeline::Attribute`'s 16 bit variants.
+// Positions and uvs become half floats, unit vectors like normals are octahedral encoded
+// into two snorms, see shaders/quantize.glsl for decoding what isn't decoded by the vertex input
+
+use crate::math::Vector;
+
+/// rounds to the nearest half float, too large values become infinities
+pub fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        // keeps nans nans
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+
+    // drops `shift` bits of `value`, rounding half to even
+    let round = |value: u32, shift: u32| {
+        let kept = value >> shift;
+        let rest = value & ((1 << shift) - 1);
+        let half = 1 << (shift - 1);
+        if rest > half || (rest == half && kept & 1 == 1) { kept + 1 } else { kept }
+    };
+
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if half_exponent <= 0 {
+        // subnormal, the implicit leading 1 becomes explicit
+        let shift = (14 - half_exponent) as u32;
+        if shift > 24 {
+            return sign;
+        }
+        return sign | round(mantissa | 0x80_0000, shift) as u16;
+    }
+    // a mantissa rounding up carries into the exponent, up to infinity
+    sign | round(((half_exponent as u32) << 23) | mantissa, 13) as u16
+}
+
+pub fn f16_to_f32(half: u16) -> f32 {
+    let sign = ((half & 0x8000) as u32) << 16;
+    let exponent = ((half >> 10) & 0x1f) as u32;
+    let mantissa = (half & 0x3ff) as u32;
+
+    let bits = match exponent {
+        0 if mantissa == 0 => sign,
+        // subnormal
+        0 => {
+            let magnitude = mantissa as f32 * (-24.0_f32).exp2();
+            return if sign != 0 { -magnitude } else { magnitude };
+        }
+        0x1f => sign | 0x7f80_0000 | (mantissa << 13),
+        _ => sign | ((exponent + 127 - 15) << 23) | (mantissa << 13),
+    };
+    f32::from_bits(bits)
+}
+
+/// `value` clamped to -1 to 1, as read by `*_SNORM` formats
+pub fn to_snorm16(value: f32) -> i16 {
+    (value.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16
+}
+
+pub fn from_snorm16(value: i16) -> f32 {
+    (value as f32 / i16::MAX as f32).max(-1.0)
+}
+
+/// unit `normal` folded onto the octahedron and flattened into the -1 to 1 square
+pub fn octahedral_encode(normal: Vector) -> [f32; 2] {
+    let sign_not_zero = |value: f32| if value >= 0.0 { 1.0 } else { -1.0 };
+    let l1_norm = normal.x.abs() + normal.y.abs() + normal.z.abs();
+    let (x, y) = (normal.x / l1_norm, normal.y / l1_norm);
+    if normal.z >= 0.0 {
+        [x, y]
+    } else {
+        // the lower half folds over the diagonals
+        [(1.0 - y.abs()) * sign_not_zero(x), (1.0 - x.abs()) * sign_not_zero(y)]
+    }
+}
+
+/// unit vector of an `octahedral_encode`d one
+pub fn octahedral_decode(encoded: [f32; 2]) -> Vector {
+    let [x, y] = encoded;
+    let z = 1.0 - x.abs() - y.abs();
+    let fold = (-z).max(0.0);
+    let x = if x >= 0.0 { x - fold } else { x + fold };
+    let y = if y >= 0.0 { y - fold } else { y + fold };
+    Vector::new(x, y, z).normalized()
+}
+
+/// two snorms for a `Snorm16x2` attribute
+pub fn encode_normal(normal: Vector) -> [i16; 2] {
+    octahedral_encode(normal).map(to_snorm16)
+}
+
+pub fn decode_normal(encoded: [i16; 2]) -> Vector {
+    octahedral_decode(encoded.map(from_snorm16))
+}
+
+#[test]
+fn test_quantize() {
+    assert!(f32_to_f16(1.0) == 0x3c00 && f32_to_f16(-2.0) == 0xc000 && f32_to_f16(0.0) == 0);
+    assert!(f32_to_f16(65504.0) == 0x7bff && f32_to_f16(1e6) == 0x7c00);
+    // rounds up past the largest half
+    assert!(f32_to_f16(65520.0) == 0x7c00);
+    // smallest subnormal, and half of it rounding to even zero
+    assert!(f32_to_f16((-24.0_f32).exp2()) == 1 && f32_to_f16((-25.0_f32).exp2()) == 0);
+    assert!(f32_to_f16(f32::NAN) & 0x7fff > 0x7c00 && f16_to_f32(f32_to_f16(f32::NAN)).is_nan());
+    for value in [0.1, -3.75, 1234.5, 6e-5, -1e-7] {
+        let decoded = f16_to_f32(f32_to_f16(value));
+        assert!((decoded - value).abs() <= value.abs() / 1024.0 + (-24.0_f32).exp2());
+    }
+    assert!(f16_to_f32(1) == (-24.0_f32).exp2() && f16_to_f32(0x8001) == -(-24.0_f32).exp2());
+
+    assert!(to_snorm16(1.0) == i16::MAX && to_snorm16(-2.0) == -i16::MAX && from_snorm16(i16::MIN) == -1.0);
+
+    for normal in [
+        Vector::new(0.0, 0.0, 1.0),
+        Vector::new(0.0, 0.0, -1.0),
+        Vector::new(1.0, -2.0, 3.0).normalized(),
+        Vector::new(-0.3, 0.5, -0.8).normalized(),
+        Vector::new(0.0, -1.0, 0.0),
+    ] {
+        let [x, y] = octahedral_encode(normal);
+        assert!(x.abs() <= 1.0 && y.abs() <= 1.0);
+        assert!((octahedral_decode([x, y]) - normal).norm_sqr() < 1e-10);
+        // snorms are precise to well under a tenth of a degree
+        assert!(decode_normal(encode_normal(normal)).dot(&normal) > 0.9999);
+    }
+}
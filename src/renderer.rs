@@ -3,11 +3,50 @@ pub mod device;
 pub mod swapchain;
 pub mod pipeline;
 pub mod descriptor;
-// pub mod texture;
+pub mod texture;
+pub mod sampler;
+pub mod buffer;
 pub mod image;
 pub mod render_pass;
-
-use crate::{camera::Camera, geometry};
+pub mod material;
+pub mod profiler;
+pub mod quality;
+pub mod gbuffer;
+pub mod render_graph;
+pub mod render_scale;
+pub mod render_target;
+pub mod batch;
+pub mod gpu_culling;
+pub mod draw_list;
+pub mod skinning;
+pub mod precipitation;
+pub mod billboard;
+pub mod letterbox;
+pub mod minimap;
+pub mod picking;
+pub mod outline;
+pub mod debug_view;
+pub mod gpu_particles;
+pub mod terrain;
+pub mod parallel_record;
+pub mod sprite;
+pub mod debug_lines;
+pub mod immediate;
+pub mod uniform_ring;
+pub mod deletion_queue;
+pub mod atlas;
+pub mod ibl;
+pub mod shader;
+pub mod async_compute;
+pub mod breadcrumbs;
+pub mod hiz;
+pub mod reflection_probe;
+pub mod ssr;
+pub mod motion_blur;
+pub mod edge_outline;
+pub mod synchronization;
+
+use crate::{arena::FrameArena, data_structures::interner::StringInterner, jobs::JobSystem, assets::{AssetCache, AssetHandle}, camera::{Camera, Projection, controller::CameraController}, light::DirectionalLight, weather::Weather, fog::Fog, geometry::{self, GeometryId}, math::{Frustum, ModelMat}};
 
 use raw_window_handle::{
     HasRawDisplayHandle, 
@@ -15,8 +54,10 @@ use raw_window_handle::{
 };
 
 use std::{
-    ffi::CString, 
+    ffi::{CStr, CString}, 
     rc::Rc, 
+    collections::HashMap,
+    sync::Arc,
     time, mem::size_of, 
 };
 
@@ -27,39 +68,70 @@ use ash::{
     extensions::{
         khr::{
             Surface, 
-            Win32Surface, 
-            Swapchain
+            Swapchain,
+            DynamicRendering,
         }, 
         ext::DebugUtils
     }, 
 };
 
-use self::descriptor::PerFrameUBO;
+pub const MAX_FRAMES_IN_FLIGHT: usize = 2;
+
+pub type TextureHandle = AssetHandle<texture::Texture>;
 
-pub const START_WINDOW_WIDTH: u32 = 1280;
-pub const START_WINDOW_HEIGHT: u32 = 720;
+/// initial size of the frame arena, it grows to the largest frame's needs
+const FRAME_ARENA_CAPACITY: usize = 0x10000;
 
-pub const MAX_FRAMES_IN_FLIGHT: usize = 2;
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RenderPath {
+    /// geometry is shaded as it is drawn
+    Forward,
+    /// geometry fills a g-buffer which a fullscreen lighting subpass shades
+    Deferred,
+}
+
+impl RenderPath {
+    /// subpass of the scene pass drawing translucent geometry over the shaded scene
+    pub fn translucent_subpass(self) -> u32 {
+        match self {
+            RenderPath::Forward => 0,
+            RenderPath::Deferred => 1,
+        }
+    }
+}
 
 pub struct VkApp {
     pub camera: Camera,
+    /// turns mouse motion into camera rotation
+    pub camera_controller: CameraController,
+    /// the scene's single directional light
+    pub light: DirectionalLight,
+    pub weather: Weather,
     pub input_state: crate::input::InputState,
     pub in_game: bool,
     pub start_instant: time::Instant,
 
     entry: ash::Entry,
     instance: ash::Instance,
-    shader_compiler: shaderc::Compiler,
+    shader_compiler: shader::ShaderCompiler,
 
     pub window: winit::window::Window,
+    /// updated by the engine as the window resizes and changes monitors
+    pub display: crate::display::Display,
     surface: Surface,
     surface_khr: vk::SurfaceKHR,
 
     debug_utils: DebugUtils,
     debug_messenger: vk::DebugUtilsMessengerEXT, 
+    /// read by the messenger's callback
+    _debug_message_filter: Box<debug::MessageFilter>,
 
     physical_device: vk::PhysicalDevice,
     device: Rc<ash::Device>,
+    pub device_features: device::DeviceFeatures,
+    /// only loaded on 1.2 devices, 1.3 devices use the core entry points
+    dynamic_rendering_khr: Option<DynamicRendering>,
+    pub sync: synchronization::Synchronization,
 
     graphics_command_pool: vk::CommandPool,
     descriptor_pool: vk::DescriptorPool,
@@ -80,14 +152,41 @@ pub struct VkApp {
     swapchain_images: Vec<vk::Image>,
     swapchain_image_views: Vec<vk::ImageView>,
     swapchain_image_format: vk::Format,
+    swapchain_color_space: vk::ColorSpaceKHR,
+    /// transfer source and destination usage aren't supported by every surface
+    swapchain_image_usage: vk::ImageUsageFlags,
+    /// only changes when the swapchain is renewed, request changes through `request_resize`
     pub swapchain_extent: vk::Extent2D,
+    vsync: bool,
+    swapchain_format_preference: swapchain::SwapchainFormatPreference,
+    resize_tracker: swapchain::ResizeTracker,
+    /// the swapchain and its resources are destroyed until the next renewal
+    swapchain_released: bool,
     swapchain_framebuffers: Vec<vk::Framebuffer>,
     swapchain_depth_format: vk::Format,
     swapchain_depth_image: vk::Image,
     swapchain_depth_image_memory: vk::DeviceMemory,
+    /// attached, with the stencil aspect if the format has one
     swapchain_depth_image_view: vk::ImageView,
+    /// only the depth aspect, for reading depth in shaders
+    swapchain_depth_sampled_view: vk::ImageView,
 
     render_pass: vk::RenderPass,
+    /// compatible with `render_pass` but clearing, for a loading pass's first frame on an image.
+    /// Null when `render_pass` doesn't load color
+    first_use_render_pass: vk::RenderPass,
+    /// per swapchain image, or the scene target's only element, wether it was drawn to since its creation
+    scene_color_used: Vec<bool>,
+    clear_config: render_pass::ClearConfig,
+    /// near depth at 1 and far at 0, for the projection, depth tests and depth clear
+    reverse_z: bool,
+    /// width over height the scene is letterboxed to, `None` fills the window
+    fixed_aspect_ratio: Option<f32>,
+
+    /// fraction of the swapchain resolution the scene is rendered at
+    render_scale: f32,
+    /// only exists while the render scale is below 1, blitted to the swapchain image each frame
+    scene_target: Option<render_scale::SceneTarget>,
 
     // Improve uniform buffer object and descriptor set system
     per_frame_ubo_set_layout: vk::DescriptorSetLayout,
@@ -96,10 +195,39 @@ pub struct VkApp {
     // proper texture system
     // and resource acquisition
     textures_set_layout: vk::DescriptorSetLayout,
-    // textures: Vec<texture::Texture>,
+    /// also holds the materials storage buffer
+    textures_set: vk::DescriptorSet,
+    /// rewrites the whole textures array
+    textures_update_template: vk::DescriptorUpdateTemplate,
+    /// a texture's handle index is its element in the textures array
+    pub texture_assets: AssetCache<texture::Texture>,
+    /// a white pixel in the elements no texture holds, and what `add_texture` gives when loading fails
+    fallback_texture: TextureHandle,
+    /// shared by the textures, outlives them
+    sampler_cache: sampler::SamplerCache,
 
     pipeline_layout: vk::PipelineLayout,
     pipeline: vk::Pipeline,
+    /// draws materials with pbr shading, compatible with the scene pipeline's layout
+    pbr_pipeline_layout: vk::PipelineLayout,
+    pbr_pipeline: vk::Pipeline,
+
+    render_path: RenderPath,
+    /// only exists on the deferred path
+    gbuffer: Option<gbuffer::GBuffer>,
+    gbuffer_set_layout: vk::DescriptorSetLayout,
+    gbuffer_set: vk::DescriptorSet,
+    ibl_set_layout: vk::DescriptorSetLayout,
+    /// bound as set 2 of the scene pipeline, black until `set_environment`
+    environment: ibl::Environment,
+    /// hdr file the environment was convolved from
+    environment_path: Option<String>,
+    /// scales the image based lighting of pbr materials, which fall back to
+    /// the light's flat ambient term until there is an environment
+    pub environment_intensity: f32,
+    pub fog: Fog,
+    lighting_pipeline_layout: vk::PipelineLayout,
+    lighting_pipeline: vk::Pipeline,
 
     graphics_command_buffers: Vec<vk::CommandBuffer>,
 
@@ -108,18 +236,86 @@ pub struct VkApp {
     in_flight_fences: Vec<vk::Fence>,
 
     pub geometry_system: geometry::GeometrySystem,
-
-    per_frame_uniform_buffer: descriptor::PerFrameUniformBuffer,
+    /// geometry loaded by path, see `load_geometry`
+    pub geometry_assets: AssetCache<GeometryId>,
+    pub material_system: material::MaterialSystem,
+    /// draws submitted through `submit_draw`, batched and drawn each frame
+    pub draw_batcher: batch::DrawBatcher,
+    /// culls the batched draws against the main camera
+    pub gpu_culling: gpu_culling::GpuCulling,
+    /// of the meshes submitted through `submit_lod_draw` the last frame
+    pub lod_stats: geometry::LodStats,
+    /// collected until the frame is built
+    frame_lod_stats: geometry::LodStats,
+    /// records the batches on worker threads when there are many
+    pub parallel_recorder: parallel_record::ParallelRecorder,
+    /// the frame's batches resolved for the workers
+    batch_draws: Vec<parallel_record::BatchDraw>,
+    pub skinning_system: skinning::SkinningSystem,
+    pub precipitation_system: precipitation::PrecipitationSystem,
+    /// depth pyramid of the previous frame, built at the start of each frame while enabled
+    pub hiz: hiz::HiZBuilder,
+    /// traced after the scene pass, reflected by pbr materials during the next frame
+    pub ssr: ssr::ScreenSpaceReflections,
+    /// velocities rendered before the scene pass, blurred over it after
+    pub motion_blur: motion_blur::MotionBlur,
+    pub edge_outlines: edge_outline::EdgeOutlines,
+    pub billboard_renderer: billboard::BillboardRenderer,
+    pub sprite_renderer: sprite::SpriteRenderer,
+    pub debug_line_renderer: debug_lines::DebugLineRenderer,
+    pub immediate_renderer: immediate::ImmediateRenderer,
+    pub gpu_particle_system: gpu_particles::GpuParticleSystem,
+    /// `None` without a dedicated compute queue family
+    async_compute: Option<async_compute::AsyncCompute>,
+    use_async_compute: bool,
+    /// `None` unless gpu crash diagnostics are on
+    breadcrumbs: Option<breadcrumbs::Breadcrumbs>,
+    pub minimap: minimap::Minimap,
+    pub render_targets: render_target::RenderTargetSystem,
+    /// add and remove probes through `add_reflection_probe` and `remove_reflection_probe`
+    pub reflection_probes: reflection_probe::ReflectionProbeSystem,
+    picking: picking::Picking,
+    pub outline_renderer: outline::OutlineRenderer,
+    /// replaces the shading of batched draws while its view isn't `Lit`
+    pub debug_view_renderer: debug_view::DebugViewRenderer,
+    /// set through `set_terrain`
+    pub terrain_renderer: terrain::TerrainRenderer,
+
+    pub gpu_profiler: profiler::GpuProfiler,
+    pub pipeline_statistics_profiler: profiler::PipelineStatisticsProfiler,
+    /// cpu time of the job system's jobs
+    pub job_profiler: Arc<profiler::JobProfiler>,
+    pub auto_quality: quality::AutoQuality,
+    /// waited on by the main loop after each frame
+    pub frame_limiter: crate::frame_pacing::FrameLimiter,
+
+    uniform_ring: uniform_ring::UniformRing,
+    /// this frame's dynamic offsets of the views' uniform buffer objects
+    view_ubo_offsets: [u32; descriptor::MAX_VIEW_COUNT],
+
+    /// systems queue their descriptor writes here, flushed once per frame
+    pub descriptor_write_batcher: descriptor::DescriptorWriteBatcher,
+
+    /// shared by every subsystem running work on other threads
+    pub job_system: JobSystem,
+
+    /// transient cpu allocations, reset when a frame starts
+    frame_arena: FrameArena,
+    /// debug names and labels, interned once instead of allocated every frame
+    pub strings: StringInterner,
 
     current_frame: usize,
+    /// swapchain image that was last handed to the presentation engine
+    presented_image_index: Option<u32>,
 }
 
 impl VkApp {
-    pub fn new(window: winit::window::Window) -> Self {
+    pub fn new(window: winit::window::Window, config: &crate::config::EngineConfig) -> Self {
         log::debug!("Creating app...");
 
         let entry = ash::Entry::linked();
-        let instance = Self::new_instance(&entry);
+        let api_version = device::get_instance_api_version(&entry);
+        let instance = Self::new_instance(&entry, api_version, window.raw_display_handle(), config.validation.enabled);
 
         let surface = Surface::new(&entry, &instance);
         let surface_khr = unsafe { ash_window::create_surface(
@@ -131,7 +327,8 @@ impl VkApp {
         ).expect("Failed to acquire vulkan window handle(surface)") };
         
         let debug_utils = DebugUtils::new(&entry, &instance);
-        let debug_messenger = debug::new_messenger(&debug_utils);
+        let (debug_messenger, debug_message_filter) =
+            debug::new_messenger(&debug_utils, &config.validation.clone().with_env_overrides());
 
         let (physical_device,
 
@@ -142,20 +339,45 @@ impl VkApp {
             &instance, 
             &surface, 
             surface_khr,
+            config.graphics.device.as_deref(),
         );
 
+        let mut device_features = device::query_device_features(&instance, physical_device, api_version);
+        device_features.buffer_marker &= config.graphics.gpu_crash_diagnostics;
+        log::info!("Device features: {:?}", device_features);
+
+        let compute_family_index = device::find_async_compute_family_index(&instance, physical_device);
         let (device, 
 
             graphics_queue, 
             present_queue,
             transfer_queue,
+            compute_queue,
         ) = device::new_logical_device_and_queues(
             &instance,
             physical_device,
             graphics_family_index,
             present_family_index,
-            transfer_family_index
+            transfer_family_index,
+            compute_family_index,
+            &device_features,
         );
+        let async_compute = compute_family_index.zip(compute_queue).map(|(family_index, queue)| {
+            async_compute::AsyncCompute::new(device.clone(), queue, family_index, graphics_family_index)
+        });
+        match compute_family_index {
+            Some(family_index) => log::info!("Async compute on queue family {}", family_index),
+            None => log::info!("No dedicated compute queue family, compute runs on the graphics queue"),
+        }
+        let dynamic_rendering_khr = if device_features.uses_dynamic_rendering_extension() {
+            Some(DynamicRendering::new(&instance, &device))
+        } else {
+            None
+        };
+        let sync = synchronization::Synchronization::new(&instance, device.clone(), &device_features);
+        if !sync.is_synchronization2() {
+            log::info!("No synchronization2, barriers and submissions fall back to the 1.0 commands");
+        }
 
         let graphics_command_pool = Self::new_command_pool(
             vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
@@ -177,51 +399,75 @@ impl VkApp {
             swapchain_khr, 
             swapchain_images,
             swapchain_image_views,
-            swapchain_image_format, 
-            swapchain_extent
+            swapchain_surface_format, 
+            swapchain_extent,
+            swapchain_image_usage,
         ) = swapchain::new_swapchain_and_images(
-            &instance, 
-            physical_device,
-            &device,
-            &surface, 
-            surface_khr, 
+            &swapchain::SwapchainContext {
+                instance: &instance,
+                physical_device,
+                device: &device,
+                surface: &surface,
+                surface_khr,
+                graphics_family_index,
+                present_family_index,
+            },
             vk::Extent2D{
-                width: START_WINDOW_WIDTH, 
-                height: START_WINDOW_HEIGHT
+                width: window.inner_size().width, 
+                height: window.inner_size().height,
             },
-            graphics_family_index,
-            present_family_index,
+            config.graphics.vsync,
+            config.graphics.swapchain_format,
         );
+        let swapchain_image_format = swapchain_surface_format.format;
+        let output_transfer = swapchain::OutputTransfer::of(swapchain_surface_format);
+        log::info!("Picked swapchain format {:?}, output transfer {:?}", swapchain_surface_format, output_transfer);
 
         let swapchain_depth_format = device::find_depth_format(&instance, physical_device);
         log::info!("Picked depth format {:?}", swapchain_depth_format);
-        let render_pass = render_pass::new_render_pass(
-            &device,
-            swapchain_image_format,
-            swapchain_depth_format,
-        );
-
+        let reverse_z = config.graphics.reverse_z;
+        if reverse_z && !device::is_float_depth_format(swapchain_depth_format) {
+            log::warn!("Reverse-Z without a float depth format gains no precision");
+        }
         let (
             per_frame_ubo_set_layout, 
             textures_set_layout,
-        ) = descriptor::new_descriptor_set_layouts(&device, 1);
-        
+        ) = descriptor::new_descriptor_set_layouts(&device, descriptor::MAX_TEXTURE_COUNT);
+        let gbuffer_set_layout = gbuffer::new_gbuffer_set_layout(&device);
+        let ibl_set_layout = ibl::new_ibl_set_layout(&device);
         
-        use pipeline::Attribute;
-        let shader_compiler = shaderc::Compiler::new().unwrap();
-        let (pipeline, pipeline_layout) = pipeline::new_pipeline_and_layout(
-            &device, 
-            &shader_compiler,
-            render_pass,
+        let render_path = RenderPath::Forward;
+        let clear_config = render_pass::ClearConfig {
+            clear_depth: render_pass::far_depth(reverse_z),
+            ..Default::default()
+        };
+        let shader_compiler = shader::ShaderCompiler::new();
+        let pass_context = pipeline::PassContext {
+            shader_compiler: &shader_compiler,
+            render_path,
+            color_format: swapchain_image_format,
+            depth_format: swapchain_depth_format,
             per_frame_ubo_set_layout,
             textures_set_layout,
-            "shaders/foo.vert",
-            "shaders/foo.frag",
-            &[
-                Attribute::F32x3,
-            ],
-            &[
-            ],
+            output_transfer,
+            reverse_z,
+        };
+        let (
+            render_pass,
+            pipeline,
+            pipeline_layout,
+            pbr_pipeline,
+            pbr_pipeline_layout,
+            lighting_pipeline,
+            lighting_pipeline_layout,
+        ) = Self::new_render_pass_and_pipelines(
+            &device,
+            &pass_context,
+            device_features.dynamic_rendering,
+            &clear_config,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+            gbuffer_set_layout,
+            ibl_set_layout,
         );
 
         let physical_device_memory_properties = unsafe { 
@@ -232,16 +478,41 @@ impl VkApp {
             device.clone(),
             &physical_device_memory_properties,
             0x1000,
-            0x1000
+            0x1000,
+            &device_features,
         );
 
-        let per_frame_uniform_buffer = descriptor::PerFrameUniformBuffer::new(
+        let physical_device_properties = unsafe {
+            instance.get_physical_device_properties(physical_device)
+        };
+        let graphics_queue_family = unsafe {
+            instance.get_physical_device_queue_family_properties(physical_device)
+        }[graphics_family_index as usize];
+        let gpu_profiler = profiler::GpuProfiler::new(device.clone(), &physical_device_properties, &graphics_queue_family);
+        let breadcrumbs = config.graphics.gpu_crash_diagnostics.then(|| breadcrumbs::Breadcrumbs::new(
+            &instance,
+            device.clone(),
+            &physical_device_memory_properties,
+            device_features.buffer_marker,
+        ));
+        let mut sampler_cache = sampler::SamplerCache::new(device.clone(), &physical_device_properties.limits);
+        let pipeline_statistics_profiler = profiler::PipelineStatisticsProfiler::new(device.clone(), &device_features);
+
+        let uniform_ring = uniform_ring::UniformRing::new(
             device.clone(),
             &physical_device_memory_properties,
+            &physical_device_properties.limits,
+            descriptor::UNIFORM_RING_FRAME_CAPACITY,
         );
 
-        let (swapchain_depth_image, swapchain_depth_image_memory, swapchain_depth_image_view) = Self::new_depth_resources(
+        let (
+            swapchain_depth_image,
+            swapchain_depth_image_memory,
+            swapchain_depth_image_view,
+            swapchain_depth_sampled_view,
+        ) = Self::new_depth_resources(
             &device,
+            &sync,
             &physical_device_memory_properties,
             transient_command_pool,
             graphics_queue,
@@ -254,16 +525,194 @@ impl VkApp {
             &device, 
             &swapchain_image_views,
             swapchain_depth_image_view,
+            &[],
             render_pass, 
             swapchain_extent,
         );
         
+        let scene_color_used = vec![false; swapchain_images.len()];
+
+        let mut descriptor_write_batcher = descriptor::DescriptorWriteBatcher::new();
         let descriptor_pool = descriptor::new_descriptor_pool(&device);
+        let gbuffer_set = gbuffer::new_gbuffer_set(&device, descriptor_pool, gbuffer_set_layout);
+        let environment = ibl::Environment::new(
+            device.clone(),
+            &physical_device_memory_properties,
+            ibl_set_layout,
+            transient_command_pool,
+            graphics_queue,
+        );
         let per_frame_ubo_set = descriptor::new_per_frame_ubo_set(
             &device, 
             descriptor_pool, 
             per_frame_ubo_set_layout, 
-            &per_frame_uniform_buffer,
+            &uniform_ring,
+            &mut descriptor_write_batcher,
+        );
+
+        let material_system = material::MaterialSystem::new(device.clone(), &physical_device_memory_properties);
+        let mut draw_batcher = batch::DrawBatcher::new(device.clone(), &physical_device_memory_properties);
+        draw_batcher.indirect = device_features.draw_indirect_first_instance;
+        let job_profiler = Arc::new(profiler::JobProfiler::default());
+        let job_system = JobSystem::with_available_parallelism();
+        job_system.set_hooks(Some(job_profiler.clone()));
+        let parallel_recorder = parallel_record::ParallelRecorder::new(device.clone(), graphics_family_index, &job_system);
+        let mut gpu_culling = gpu_culling::GpuCulling::new(
+            device.clone(),
+            &physical_device_memory_properties,
+            &device_features,
+            &shader_compiler,
+            &mut descriptor_write_batcher,
+            &draw_batcher,
+        );
+        gpu_culling.enabled = config.graphics.gpu_culling;
+        let skinning_system = skinning::SkinningSystem::new(
+            device.clone(),
+            &physical_device_memory_properties,
+            &shader_compiler,
+            &mut descriptor_write_batcher,
+        );
+        let mut precipitation_system = precipitation::PrecipitationSystem::new(
+            device.clone(),
+            &physical_device_memory_properties,
+            &shader_compiler,
+            &mut descriptor_write_batcher,
+        );
+        precipitation_system.renew_pipeline(&pass_context, render_pass);
+        precipitation_system.set_depth_view(&mut descriptor_write_batcher, swapchain_depth_sampled_view);
+        let mut hiz = hiz::HiZBuilder::new(device.clone(), &physical_device_memory_properties, &shader_compiler);
+        hiz.resize(&mut descriptor_write_batcher, swapchain_extent, swapchain_depth_sampled_view);
+        let mut ssr = ssr::ScreenSpaceReflections::new(
+            device.clone(),
+            &physical_device_memory_properties,
+            &shader_compiler,
+            per_frame_ubo_set_layout,
+            environment.get_set(),
+        );
+        ssr.enabled = config.graphics.screen_space_reflections;
+        ssr.resize(
+            &mut descriptor_write_batcher,
+            swapchain_extent,
+            swapchain_depth_sampled_view,
+            transient_command_pool,
+            graphics_queue,
+        );
+        let mut motion_blur = motion_blur::MotionBlur::new(
+            device.clone(),
+            &physical_device_memory_properties,
+            &shader_compiler,
+            &mut descriptor_write_batcher,
+            per_frame_ubo_set_layout,
+            swapchain_depth_format,
+        );
+        motion_blur.enabled = config.graphics.motion_blur;
+        motion_blur.strength = config.graphics.motion_blur_strength;
+        motion_blur.renew_pipeline(&shader_compiler, per_frame_ubo_set_layout, reverse_z);
+        motion_blur.resize(&mut descriptor_write_batcher, swapchain_extent, swapchain_depth_sampled_view);
+        let mut edge_outlines = edge_outline::EdgeOutlines::new(device.clone());
+        edge_outlines.enabled = config.graphics.edge_outlines;
+        edge_outlines.thickness = config.graphics.edge_outline_thickness;
+        edge_outlines.color = config.graphics.edge_outline_color;
+        edge_outlines.renew_pipeline(&shader_compiler, swapchain_image_format, per_frame_ubo_set_layout, output_transfer);
+        edge_outlines.resize(
+            &mut descriptor_write_batcher,
+            &swapchain_image_views,
+            swapchain_extent,
+            swapchain_depth_sampled_view,
+        );
+        let mut billboard_renderer = billboard::BillboardRenderer::new(device.clone(), &physical_device_memory_properties);
+        billboard_renderer.renew_pipeline(&pass_context, render_pass);
+        let mut sprite_renderer = sprite::SpriteRenderer::new(device.clone(), &physical_device_memory_properties);
+        sprite_renderer.renew_pipeline(&pass_context, render_pass);
+        let mut immediate_renderer = immediate::ImmediateRenderer::new(device.clone(), &physical_device_memory_properties);
+        immediate_renderer.renew_pipeline(&pass_context, render_pass);
+        let mut debug_line_renderer = debug_lines::DebugLineRenderer::new(device.clone(), &physical_device_memory_properties);
+        debug_line_renderer.renew_pipeline(&pass_context, render_pass);
+        let mut outline_renderer = outline::OutlineRenderer::new(device.clone(), &physical_device_memory_properties);
+        outline_renderer.renew_pipelines(&pass_context, render_pass);
+        let mut debug_view_renderer = debug_view::DebugViewRenderer::new(device.clone());
+        debug_view_renderer.renew_pipelines(&pass_context, render_pass);
+        let gpu_particle_system = gpu_particles::GpuParticleSystem::new(
+            device.clone(),
+            &physical_device_memory_properties,
+            &shader_compiler,
+            &mut descriptor_write_batcher,
+            &async_compute.as_ref().map_or(vec![], |async_compute| async_compute.get_queue_family_indices().to_vec()),
+        );
+        let mut minimap = minimap::Minimap::new(
+            device.clone(),
+            &physical_device_memory_properties,
+            &mut descriptor_write_batcher,
+            &pass_context,
+        );
+        minimap.renew_pipeline(&pass_context, render_pass);
+        let render_targets = render_target::RenderTargetSystem::new(
+            device.clone(),
+            &shader_compiler,
+            swapchain_depth_format,
+            per_frame_ubo_set_layout,
+            textures_set_layout,
+        );
+        let mut reflection_probes = reflection_probe::ReflectionProbeSystem::new(
+            device.clone(),
+            &physical_device_memory_properties,
+            &shader_compiler,
+            swapchain_depth_format,
+            per_frame_ubo_set_layout,
+            textures_set_layout,
+        );
+        reflection_probes.init_environment_set(
+            &mut descriptor_write_batcher,
+            environment.get_set(),
+            transient_command_pool,
+            graphics_queue,
+        );
+        let mut picking = picking::Picking::new(
+            device.clone(),
+            &physical_device_memory_properties,
+            swapchain_depth_format,
+        );
+        picking.renew_pipeline(
+            &shader_compiler,
+            swapchain_depth_format,
+            per_frame_ubo_set_layout,
+            reverse_z,
+        );
+        let mut terrain_renderer = terrain::TerrainRenderer::new(device.clone());
+        terrain_renderer.renew_pipeline(&pass_context, render_pass);
+        let textures_set = descriptor::new_textures_set(
+            &device,
+            descriptor_pool,
+            textures_set_layout,
+            material_system.get_buffer_info(),
+            &mut descriptor_write_batcher,
+        );
+        let textures_update_template = descriptor::new_texture_descriptor_update_template(
+            &device,
+            descriptor::MAX_TEXTURE_COUNT,
+            vk::PipelineLayout::null(),
+            textures_set_layout,
+        );
+        // every element samples it until a texture takes the element, and again once the texture is released
+        let mut texture_assets = AssetCache::default();
+        let fallback_texture = texture_assets.insert("fallback", texture::Texture::upload(
+            texture::DecodedTexture::from_pixels(texture::TextureType::Diffuse, 1, 1, vec![255; 4]),
+            device.clone(),
+            &texture::TransferContext {
+                sync: &sync,
+                physical_device_memory_properties: &physical_device_memory_properties,
+                command_pool: transient_command_pool,
+                queue: graphics_queue,
+                queue_family_index: graphics_family_index,
+            },
+            &mut sampler_cache,
+        ));
+        let fallback = texture_assets.get(fallback_texture).unwrap();
+        descriptor_write_batcher.queue_textures_update(
+            textures_set,
+            textures_update_template,
+            &[fallback.sampler; descriptor::MAX_TEXTURE_COUNT as usize],
+            &[fallback.image_view; descriptor::MAX_TEXTURE_COUNT as usize],
         );
 
         let mut image_available_semaphores = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT);
@@ -290,17 +739,24 @@ impl VkApp {
             translation: crate::math::Vector { x: 0.0, y: 0.0, z: -4.0 },
             z_x_angle: 0.0,
             y_xz_angle: 0.0,
+            roll: 0.0,
             near_z: 1.0,
             far_z: 100.0,
-            aspect_ratio: START_WINDOW_WIDTH as f32 / START_WINDOW_HEIGHT as f32,
-            translation_speed: 3.0,
-            rotation_speed: 0.2,
+            reverse_z,
+            projection: Projection::Perspective,
+            aspect_ratio: config.graphics.aspect_ratio
+                .unwrap_or(swapchain_extent.width as f32 / swapchain_extent.height as f32),
+            translation_speed: config.camera.translation_speed,
         };
+        let camera_controller = CameraController::new(config.camera.look);
 
         let input_state = crate::input::InputState::new();
 
-        Self {
+        let mut app = Self {
             camera,
+            camera_controller,
+            light: DirectionalLight::default(),
+            weather: Weather::default(),
             input_state, 
             in_game: false,
 
@@ -309,15 +765,20 @@ impl VkApp {
             instance,
             shader_compiler,
 
+            display: crate::display::Display::new(&window),
             window,
             surface,
             surface_khr,
 
             debug_utils,
             debug_messenger,
+            _debug_message_filter: debug_message_filter,
 
             physical_device,
             device,
+            device_features,
+            dynamic_rendering_khr,
+            sync,
 
             graphics_command_pool,
             transient_command_pool,
@@ -338,23 +799,66 @@ impl VkApp {
             swapchain_images,
             swapchain_image_views,
             swapchain_image_format,
+            swapchain_color_space: swapchain_surface_format.color_space,
+            swapchain_image_usage,
             swapchain_extent,
+            vsync: config.graphics.vsync,
+            async_compute,
+            use_async_compute: config.graphics.async_compute,
+            breadcrumbs,
+            swapchain_format_preference: config.graphics.swapchain_format,
+            resize_tracker: swapchain::ResizeTracker::default(),
+            swapchain_released: false,
             swapchain_framebuffers,
             swapchain_depth_format,
             swapchain_depth_image,
             swapchain_depth_image_memory,
             swapchain_depth_image_view,
+            swapchain_depth_sampled_view,
 
             render_pass,
+            // the default clear config clears
+            first_use_render_pass: vk::RenderPass::null(),
+            scene_color_used,
+            clear_config,
+            reverse_z,
+            fixed_aspect_ratio: config.graphics.aspect_ratio,
+
+            render_scale: 1.0,
+            scene_target: None,
 
             per_frame_ubo_set_layout,
             per_frame_ubo_set,
-            per_frame_uniform_buffer,
+            uniform_ring,
+            view_ubo_offsets: [0; descriptor::MAX_VIEW_COUNT],
+            descriptor_write_batcher,
+
+            frame_arena: FrameArena::new(FRAME_ARENA_CAPACITY),
+            strings: StringInterner::default(),
 
             textures_set_layout,
+            textures_set,
+            textures_update_template,
+            texture_assets,
+            fallback_texture,
+            sampler_cache,
 
             pipeline_layout,
             pipeline,
+            pbr_pipeline_layout,
+            pbr_pipeline,
+
+            render_path,
+            gbuffer: None,
+            gbuffer_set_layout,
+            gbuffer_set,
+            ibl_set_layout,
+            environment,
+            environment_path: None,
+            environment_intensity: 1.0,
+            fog: Fog::default(),
+            lighting_pipeline_layout,
+            lighting_pipeline,
    
             graphics_command_buffers,
 
@@ -363,8 +867,50 @@ impl VkApp {
             in_flight_fences,
 
             geometry_system,
+            geometry_assets: AssetCache::default(),
+            material_system,
+            draw_batcher,
+            gpu_culling,
+            lod_stats: Default::default(),
+            frame_lod_stats: Default::default(),
+            parallel_recorder,
+            batch_draws: vec![],
+            skinning_system,
+            precipitation_system,
+            hiz,
+            ssr,
+            motion_blur,
+            edge_outlines,
+            billboard_renderer,
+            sprite_renderer,
+            debug_line_renderer,
+            immediate_renderer,
+            gpu_particle_system,
+            minimap,
+            render_targets,
+            reflection_probes,
+            picking,
+            outline_renderer,
+            debug_view_renderer,
+            terrain_renderer,
+
+            gpu_profiler,
+            pipeline_statistics_profiler,
+            job_profiler,
+            job_system,
+            auto_quality: quality::AutoQuality::new(60.0, Default::default()),
+            frame_limiter: crate::frame_pacing::FrameLimiter::new(config.graphics.target_fps),
             current_frame: 0,
+            presented_image_index: None,
+        };
+
+        app.texture_assets.hot_reload = config.assets.hot_reload;
+        // auto quality is driven by gpu frame times
+        app.auto_quality.enabled = config.graphics.auto_render_scale && app.gpu_profiler.is_supported();
+        if !app.auto_quality.enabled {
+            app.set_render_scale(config.graphics.render_scale);
         }
+        app
     }
 
     pub fn execute_transient_commands<F: FnOnce(vk::CommandBuffer)>(
@@ -406,28 +952,33 @@ impl VkApp {
         }
     }
 
-    /// Create the depth buffer resources (image, memory and view).
+    /// Create the depth buffer resources (image, memory, attachment view and sampled view).
     /// 
     /// This function also transitions the image to be ready to be used
     /// as a depth/stencil attachement.
     fn new_depth_resources(
         device: &ash::Device,
+        sync: &synchronization::Synchronization,
         physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
         transition_command_pool: vk::CommandPool,
         transition_queue: vk::Queue,
         transition_family_index: u32,
         format: vk::Format,
         swapchain_extent: vk::Extent2D,
-    ) -> (vk::Image, vk::DeviceMemory, vk::ImageView) {
+    ) -> (vk::Image, vk::DeviceMemory, vk::ImageView, vk::ImageView) {
         let (image, memory) = image::new_image_and_memory(
             device,
             physical_device_memory_properties,
-            swapchain_extent.width,
-            swapchain_extent.height,
-            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
-            format,
-            vk::ImageTiling::OPTIMAL,
-            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            &image::ImageDesc {
+                width: swapchain_extent.width,
+                height: swapchain_extent.height,
+                // read by the lighting subpass of the deferred path and precipitation collisions
+                usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+                    | vk::ImageUsageFlags::INPUT_ATTACHMENT
+                    | vk::ImageUsageFlags::SAMPLED,
+                format,
+                ..Default::default()
+            },
         );
 
         Self::execute_transient_commands(
@@ -436,13 +987,16 @@ impl VkApp {
             transition_queue, 
             |transfer_command_buffer|
                 image::cmd_transition_image_layout(
-                    device,
-                    image,
+                    sync,
                     transfer_command_buffer,
                     transition_family_index,
-                    format,
-                    vk::ImageLayout::UNDEFINED,
-                    vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                    &image::ImageTransition {
+                        image,
+                        format,
+                        mip_levels: 1,
+                        old_layout: vk::ImageLayout::UNDEFINED,
+                        new_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                    },
                 )
         );
 
@@ -450,126 +1004,1109 @@ impl VkApp {
             device, 
             image, 
             format, 
-            vk::ImageAspectFlags::DEPTH
+            image::get_depth_aspect_mask(format),
+            1,
+        );
+        let sampled_view = image::new_image_view(
+            device, 
+            image, 
+            format, 
+            vk::ImageAspectFlags::DEPTH,
+            1,
         );
 
-        (image, memory, view)
+        (image, memory, view, sampled_view)
     }
 
 
-    // TODO: swapchain abstraction
-    pub fn renew_swapchain(&mut self) {
-        self.cleanup_swapchain();
+    /// null when the forward path uses dynamic rendering
+    fn new_scene_render_pass(
+        device: &ash::Device,
+        render_path: RenderPath,
+        dynamic_rendering: bool,
+        clear_config: &render_pass::ClearConfig,
+        color_final_layout: vk::ImageLayout,
+        color_format: vk::Format,
+        depth_format: vk::Format,
+    ) -> vk::RenderPass {
+        match render_path {
+            // TODO: deferred path with dynamic rendering, needs local read or separate passes
+            RenderPath::Forward if dynamic_rendering => vk::RenderPass::null(),
+            RenderPath::Forward => render_pass::new_render_pass(
+                device,
+                color_format,
+                depth_format,
+                color_final_layout,
+                clear_config,
+            ),
+            RenderPath::Deferred => render_pass::new_deferred_render_pass(
+                device,
+                color_format,
+                depth_format,
+                &gbuffer::GBUFFER_FORMATS,
+                color_final_layout,
+                clear_config,
+            ),
+        }
+    }
 
-        (
-            self.swapchain, 
-            self.swapchain_khr, 
-            self.swapchain_images, 
-            self.swapchain_image_views,
-            self.swapchain_image_format, 
-            self.swapchain_extent
-        ) = swapchain::new_swapchain_and_images(
-            &self.instance, 
-            self.physical_device, 
-            &self.device, 
-            &self.surface, 
-            self.surface_khr, 
-            self.swapchain_extent,
-            self.graphics_family_index,
-            self.present_family_index,
+    /// pbr pipeline handles are null on the deferred path, lighting pipeline handles on the forward path
+    fn new_render_pass_and_pipelines(
+        device: &ash::Device,
+        context: &pipeline::PassContext,
+        dynamic_rendering: bool,
+        clear_config: &render_pass::ClearConfig,
+        color_final_layout: vk::ImageLayout,
+        gbuffer_set_layout: vk::DescriptorSetLayout,
+        ibl_set_layout: vk::DescriptorSetLayout,
+    ) -> (
+        vk::RenderPass,
+        vk::Pipeline,
+        vk::PipelineLayout,
+        vk::Pipeline,
+        vk::PipelineLayout,
+        vk::Pipeline,
+        vk::PipelineLayout,
+    ) {
+        let pipeline::PassContext {
+            shader_compiler,
+            render_path,
+            color_format,
+            depth_format,
+            per_frame_ubo_set_layout,
+            textures_set_layout,
+            output_transfer,
+            reverse_z,
+        } = *context;
+        let render_pass = Self::new_scene_render_pass(
+            device,
+            render_path,
+            dynamic_rendering,
+            clear_config,
+            color_final_layout,
+            color_format,
+            depth_format,
+        );
+
+        let scene_set_layouts = [per_frame_ubo_set_layout, textures_set_layout, ibl_set_layout];
+        let (pipeline, pipeline_layout) = pipeline::new_pipeline_and_layout(
+            device, 
+            shader_compiler,
+            &pipeline::PipelineDesc {
+                render_pass,
+                color_formats: &[color_format],
+                depth_format,
+                set_layouts: &scene_set_layouts,
+                push_constant_ranges: &[material::MaterialPushConstants::RANGE],
+                vertex_shader_path: "shaders/foo.vert",
+                fragment_shader_path: match render_path {
+                    RenderPath::Forward => "shaders/foo.frag",
+                    RenderPath::Deferred => "shaders/gbuffer.frag",
+                },
+                vertex_attributes: &geometry::VERTEX_ATTRIBUTES,
+                instance_attributes: &geometry::INSTANCE_ATTRIBUTES,
+                color_attachment_count: match render_path {
+                    RenderPath::Forward => 1,
+                    RenderPath::Deferred => gbuffer::GBUFFER_FORMATS.len() as u32,
+                },
+                output_transfer: match render_path {
+                    RenderPath::Forward => output_transfer,
+                    RenderPath::Deferred => swapchain::OutputTransfer::None,
+                },
+                reverse_z,
+                ..Default::default()
+            },
         );
 
+        // TODO: pbr materials on the deferred path, the g-buffer has no room for their parameters
+        let (pbr_pipeline, pbr_pipeline_layout) = match render_path {
+            RenderPath::Forward => pipeline::new_pipeline_and_layout(
+                device,
+                shader_compiler,
+                &pipeline::PipelineDesc {
+                    render_pass,
+                    color_formats: &[color_format],
+                    depth_format,
+                    set_layouts: &scene_set_layouts,
+                    push_constant_ranges: &[material::MaterialPushConstants::RANGE],
+                    vertex_shader_path: "shaders/foo.vert",
+                    fragment_shader_path: "shaders/pbr.frag",
+                    vertex_attributes: &geometry::VERTEX_ATTRIBUTES,
+                    instance_attributes: &geometry::INSTANCE_ATTRIBUTES,
+                    output_transfer,
+                    reverse_z,
+                    ..Default::default()
+                },
+            ),
+            RenderPath::Deferred => (vk::Pipeline::null(), vk::PipelineLayout::null()),
+        };
+
+        let (lighting_pipeline, lighting_pipeline_layout) = match render_path {
+            RenderPath::Forward => (vk::Pipeline::null(), vk::PipelineLayout::null()),
+            RenderPath::Deferred => pipeline::new_pipeline_and_layout(
+                device,
+                shader_compiler,
+                &pipeline::PipelineDesc {
+                    render_pass,
+                    subpass: 1,
+                    set_layouts: &[gbuffer_set_layout, per_frame_ubo_set_layout],
+                    push_constant_ranges: &[gbuffer::LightingPushConstants::RANGE],
+                    vertex_shader_path: "shaders/fullscreen.vert",
+                    fragment_shader_path: "shaders/deferred_lighting.frag",
+                    cull_mode: vk::CullModeFlags::NONE,
+                    depth_test: false,
+                    depth_write: false,
+                    output_transfer,
+                    ..Default::default()
+                },
+            ),
+        };
+
         (
-            self.swapchain_depth_image,
-            self.swapchain_depth_image_memory,
-            self.swapchain_depth_image_view,
-        ) = Self::new_depth_resources(
+            render_pass,
+            pipeline,
+            pipeline_layout,
+            pbr_pipeline,
+            pbr_pipeline_layout,
+            lighting_pipeline,
+            lighting_pipeline_layout,
+        )
+    }
+
+    pub fn get_render_path(&self) -> RenderPath {
+        self.render_path
+    }
+
+    /// rebuilds the render pass, pipelines and swapchain resources for `render_path`
+    pub fn set_render_path(&mut self, render_path: RenderPath) {
+        if render_path == self.render_path {
+            return;
+        }
+        log::debug!("Switching to {:?} render path", render_path);
+
+        self.render_path = render_path;
+        self.renew_render_pass_and_pipelines();
+
+        // framebuffers and g-buffer depend on the render pass
+        self.renew_swapchain();
+    }
+
+    /// the scene render pass and every pipeline drawing in it, for the current render path and swapchain format
+    fn renew_render_pass_and_pipelines(&mut self) {
+        let output_transfer = self.output_transfer();
+        unsafe {
+            self.device.device_wait_idle().unwrap();
+            self.destroy_render_pass_and_pipelines();
+        }
+        let pass_context = pipeline::PassContext {
+            shader_compiler: &self.shader_compiler,
+            render_path: self.render_path,
+            color_format: self.swapchain_image_format,
+            depth_format: self.swapchain_depth_format,
+            per_frame_ubo_set_layout: self.per_frame_ubo_set_layout,
+            textures_set_layout: self.textures_set_layout,
+            output_transfer,
+            reverse_z: self.reverse_z,
+        };
+
+        (
+            self.render_pass,
+            self.pipeline,
+            self.pipeline_layout,
+            self.pbr_pipeline,
+            self.pbr_pipeline_layout,
+            self.lighting_pipeline,
+            self.lighting_pipeline_layout,
+        ) = Self::new_render_pass_and_pipelines(
             &self.device,
-            &self.physical_device_memory_properties,
-            self.graphics_command_pool,
-            self.graphics_queue,
-            self.graphics_family_index,
+            &pass_context,
+            self.device_features.dynamic_rendering,
+            &self.clear_config,
+            self.scene_color_final_layout(),
+            self.gbuffer_set_layout,
+            self.ibl_set_layout,
+        );
+        self.first_use_render_pass = self.new_first_use_render_pass();
+        self.precipitation_system.renew_pipeline(&pass_context, self.render_pass);
+        self.billboard_renderer.renew_pipeline(&pass_context, self.render_pass);
+        self.sprite_renderer.renew_pipeline(&pass_context, self.render_pass);
+        self.immediate_renderer.renew_pipeline(&pass_context, self.render_pass);
+        self.debug_line_renderer.renew_pipeline(&pass_context, self.render_pass);
+        self.minimap.renew_pipeline(&pass_context, self.render_pass);
+        self.picking.renew_pipeline(
+            &self.shader_compiler,
             self.swapchain_depth_format,
-            self.swapchain_extent,
+            self.per_frame_ubo_set_layout,
+            self.reverse_z,
         );
-
-        self.swapchain_framebuffers = swapchain::new_swapchain_framebuffers(
-            &self.device, 
-            &self.swapchain_image_views,
-            self.swapchain_depth_image_view,
-            self.render_pass, 
-            self.swapchain_extent
+        self.motion_blur.renew_pipeline(&self.shader_compiler, self.per_frame_ubo_set_layout, self.reverse_z);
+        self.edge_outlines.renew_pipeline(
+            &self.shader_compiler,
+            self.swapchain_image_format,
+            self.per_frame_ubo_set_layout,
+            output_transfer,
         );
+        self.outline_renderer.renew_pipelines(&pass_context, self.render_pass);
+        self.debug_view_renderer.renew_pipelines(&pass_context, self.render_pass);
+        self.terrain_renderer.renew_pipeline(&pass_context, self.render_pass);
     }
-    
-    fn cleanup_swapchain(&mut self) {
+
+    pub fn get_environment_path(&self) -> Option<&str> {
+        self.environment_path.as_deref()
+    }
+
+    /// convolves the hdr environment map at `path` into the image based lighting of pbr materials,
+    /// waiting for the gpu. Panics when the file can't be decoded
+    pub fn set_environment(&mut self, path: &str) {
+        let decoded = ibl::DecodedEnvironment::decode(path);
+        log::debug!("Convolving environment {} ({}x{})", path, decoded.width, decoded.height);
         unsafe {
-            //TODO:  = no good
             self.device.device_wait_idle().unwrap();
+        }
+        self.environment.convolve(
+            &decoded,
+            &self.physical_device_memory_properties,
+            &self.shader_compiler,
+            self.transient_command_pool,
+            self.graphics_queue,
+        );
+        self.environment_path = Some(path.to_owned());
+    }
 
-            self.device.destroy_image_view(self.swapchain_depth_image_view, None);
-            self.device.destroy_image(self.swapchain_depth_image, None);
-            self.device.free_memory(self.swapchain_depth_image_memory, None);
+    pub fn uses_dynamic_rendering(&self) -> bool {
+        self.render_pass == vk::RenderPass::null()
+    }
 
-            for i in 0..self.swapchain_images.len() {
-                self.device.destroy_framebuffer(self.swapchain_framebuffers[i], None);
-                self.device.destroy_image_view(self.swapchain_image_views[i], None);
-            }
+    pub fn get_clear_config(&self) -> render_pass::ClearConfig {
+        self.clear_config
+    }
 
-            self.swapchain.destroy_swapchain(self.swapchain_khr, None);
+    /// clear values take effect next frame, changed load ops rebuild the render pass
+    pub fn set_clear_config(&mut self, clear_config: render_pass::ClearConfig) {
+        // dynamic rendering picks up load ops when recording
+        let rebuild = self.clear_config.load_ops_differ(&clear_config) && !self.uses_dynamic_rendering();
+        self.clear_config = clear_config;
+        if !rebuild {
+            return;
         }
-    }
 
-    fn new_command_buffers(
-        device: &ash::Device, 
-        pool: vk::CommandPool,
-        count: usize,
-    ) -> Vec<vk::CommandBuffer> {
-        //allocate command command_buffers
-        let alloc_info = vk::CommandBufferAllocateInfo::builder()
-            .command_pool(pool)
-            .level(vk::CommandBufferLevel::PRIMARY)
-            .command_buffer_count(count as u32);
+        self.rebuild_scene_render_pass();
+        self.renew_swapchain();
+    }
 
-        unsafe { device.allocate_command_buffers(&alloc_info).unwrap() }
+    /// whether compute passes currently run on the dedicated compute queue
+    pub fn is_async_compute(&self) -> bool {
+        self.use_async_compute && self.async_compute.is_some()
     }
 
-    fn new_command_pool(
-        create_flags: vk::CommandPoolCreateFlags,
-        queue_family_index: u32,
-        device: &ash::Device,
-    ) -> vk::CommandPool {
-        let info = vk::CommandPoolCreateInfo::builder()
-            .queue_family_index(queue_family_index)
-            .flags(create_flags);
+    /// moves the compute passes between the dedicated compute queue and the graphics queue,
+    /// a no-op without a dedicated compute queue family
+    pub fn set_async_compute(&mut self, enabled: bool) {
+        if enabled == self.use_async_compute {
+            return;
+        }
+        log::debug!("Switching async compute {}", if enabled { "on" } else { "off" });
 
-        unsafe { device.create_command_pool(&info, None).expect("Failed to create command pool") }
+        // frames in flight may still be using the other queue's results
+        unsafe { self.device.device_wait_idle().unwrap(); }
+        self.use_async_compute = enabled;
     }
 
-    fn new_instance(entry: &ash::Entry) -> ash::Instance {
-        let app_name = CString::new("Vulkan Application").unwrap();
-        let engine_name = CString::new("No Engine").unwrap();
+    pub fn is_reverse_z(&self) -> bool {
+        self.reverse_z
+    }
 
-        let app_info = vk::ApplicationInfo::builder()
-            .application_name(&app_name)
-            .engine_name(&engine_name)
-            .application_version(vk::make_api_version(0, 0, 0, 1))
-            .engine_version(vk::make_api_version(0, 0, 0, 1))
-            .api_version(vk::make_api_version(0, 1, 0, 0));
-
-        let extension_name_ptrs = [
-            ash::extensions::khr::Surface::name().as_ptr(), 
-            Win32Surface::name().as_ptr(),
-            #[cfg(debug_assertions)] 
-            DebugUtils::name().as_ptr()
-        ];
-        let (_, layer_name_ptrs) = &debug::get_layer_names_and_ptrs();
+    /// flips the camera's depth mapping, the depth tests and the depth clear value together,
+    /// best paired with a float depth format
+    pub fn set_reverse_z(&mut self, reverse_z: bool) {
+        if reverse_z == self.reverse_z {
+            return;
+        }
+        log::debug!("Switching reverse-Z {}", if reverse_z { "on" } else { "off" });
+        if reverse_z && !device::is_float_depth_format(self.swapchain_depth_format) {
+            log::warn!("Reverse-Z without a float depth format gains no precision");
+        }
+
+        self.reverse_z = reverse_z;
+        self.camera.reverse_z = reverse_z;
+        self.clear_config.clear_depth = render_pass::far_depth(reverse_z);
+        self.renew_render_pass_and_pipelines();
+    }
+
+    pub fn get_fixed_aspect_ratio(&self) -> Option<f32> {
+        self.fixed_aspect_ratio
+    }
+
+    /// letterboxes or pillarboxes the scene to `aspect_ratio`, width over height,
+    /// `None` fills the window again
+    pub fn set_fixed_aspect_ratio(&mut self, aspect_ratio: Option<f32>) {
+        if aspect_ratio == self.fixed_aspect_ratio {
+            return;
+        }
+        log::debug!("Fixed aspect ratio {:?}", aspect_ratio);
+
+        self.fixed_aspect_ratio = aspect_ratio;
+        self.fit_camera_projection();
+    }
+
+    /// the camera's projection to the scene's aspect ratio and a pixel camera's size to the ui's
+    fn fit_camera_projection(&mut self) {
+        self.camera.aspect_ratio = self.get_aspect_ratio();
+        let ui_size = self.get_ui_size();
+        if let Projection::Pixels { size } = &mut self.camera.projection {
+            *size = ui_size;
+        }
+    }
+
+    /// of the scene, what the camera projects with
+    fn get_aspect_ratio(&self) -> f32 {
+        self.fixed_aspect_ratio
+            .unwrap_or(self.swapchain_extent.width as f32 / self.swapchain_extent.height as f32)
+    }
+
+    /// where the scene is drawn in the window, in window pixels
+    pub fn get_viewport(&self) -> vk::Rect2D {
+        letterbox::letterbox(self.swapchain_extent, self.fixed_aspect_ratio)
+    }
+
+    /// the cursor relative to the viewport's top left, `None` outside it, e.g. on the bars
+    pub fn get_viewport_cursor(&self) -> Option<[f32; 2]> {
+        letterbox::to_viewport(self.input_state.cursor_pos?, self.get_viewport())
+    }
+
+    /// for changes that keep the render pass compatible, so the pipelines are kept
+    fn rebuild_scene_render_pass(&mut self) {
+        unsafe {
+            self.device.device_wait_idle().unwrap();
+            self.device.destroy_render_pass(self.render_pass, None);
+            self.device.destroy_render_pass(self.first_use_render_pass, None);
+        }
+        // load and store ops and layouts don't affect render pass compatibility
+        self.render_pass = Self::new_scene_render_pass(
+            &self.device,
+            self.render_path,
+            self.device_features.dynamic_rendering,
+            &self.clear_config,
+            self.scene_color_final_layout(),
+            self.swapchain_image_format,
+            self.swapchain_depth_format,
+        );
+        self.first_use_render_pass = self.new_first_use_render_pass();
+    }
+
+    /// see `first_use_render_pass`, only the forward render pass loads color
+    fn new_first_use_render_pass(&self) -> vk::RenderPass {
+        if self.clear_config.color_load_op != render_pass::LoadOp::Load
+            || self.render_path != RenderPath::Forward
+            || self.uses_dynamic_rendering()
+        {
+            return vk::RenderPass::null();
+        }
+        render_pass::new_render_pass(
+            &self.device,
+            self.swapchain_image_format,
+            self.swapchain_depth_format,
+            self.scene_color_final_layout(),
+            &self.clear_config.first_use(),
+        )
+    }
+
+    /// index into `scene_color_used` of what the scene draws to
+    fn scene_color_index(&self, image_index: usize) -> usize {
+        if self.scene_target.is_some() { 0 } else { image_index }
+    }
+
+    pub fn get_render_scale(&self) -> f32 {
+        self.render_scale
+    }
+
+    fn is_render_scaled(&self) -> bool {
+        self.render_scale < render_scale::MAX_RENDER_SCALE
+    }
+
+    /// scaled scenes stay in attachment layout for the upscaling blit
+    fn scene_color_final_layout(&self) -> vk::ImageLayout {
+        if self.is_render_scaled() {
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+        } else {
+            vk::ImageLayout::PRESENT_SRC_KHR
+        }
+    }
+
+    /// clamped to `MIN_RENDER_SCALE..=MAX_RENDER_SCALE`, recreates the scene sized resources
+    pub fn set_render_scale(&mut self, render_scale: f32) {
+        let render_scale = render_scale::clamp_render_scale(render_scale);
+        if render_scale == self.render_scale {
+            return;
+        }
+        log::debug!("Render scale {} -> {}", self.render_scale, render_scale);
+
+        let was_scaled = self.is_render_scaled();
+        self.render_scale = render_scale;
+        if was_scaled != self.is_render_scaled() && !self.uses_dynamic_rendering() {
+            self.rebuild_scene_render_pass();
+        }
+
+        if self.resize_tracker.is_minimized() {
+            // renewed once the window is restored
+            self.resize_tracker.mark_out_of_date();
+        } else {
+            self.renew_swapchain();
+        }
+    }
+
+    /// extent of the scene's color, depth and g-buffer attachments
+    pub fn get_scene_extent(&self) -> vk::Extent2D {
+        render_scale::scale_extent(self.swapchain_extent, self.render_scale)
+    }
+
+    /// logical size of what screen sprites are placed in, see `ui::Anchor::place`
+    pub fn get_ui_size(&self) -> [f32; 2] {
+        let extent = letterbox::letterbox(self.swapchain_extent, self.fixed_aspect_ratio).extent;
+        let scale_factor = self.display.scale_factor as f32;
+        [extent.width as f32 / scale_factor, extent.height as f32 / scale_factor]
+    }
+
+    unsafe fn destroy_render_pass_and_pipelines(&mut self) {
+        self.device.destroy_pipeline(self.pipeline, None);
+        self.device.destroy_pipeline_layout(self.pipeline_layout, None);
+        self.device.destroy_pipeline(self.pbr_pipeline, None);
+        self.device.destroy_pipeline_layout(self.pbr_pipeline_layout, None);
+        if self.render_path == RenderPath::Deferred {
+            self.device.destroy_pipeline(self.lighting_pipeline, None);
+            self.device.destroy_pipeline_layout(self.lighting_pipeline_layout, None);
+        }
+        self.device.destroy_render_pass(self.render_pass, None);
+        self.device.destroy_render_pass(self.first_use_render_pass, None);
+        self.first_use_render_pass = vk::RenderPass::null();
+    }
+
+    /// drawn with the scene pipeline of the material's shading model next frame, only for that frame
+    pub fn submit_draw(&mut self, geometry: GeometryId, material: material::MaterialId, transform: ModelMat) {
+        self.submit_batched_draw(geometry, material, transform, None);
+    }
+
+    /// like `submit_draw`, picks of its pixels report `pick_id`
+    pub fn submit_pickable_draw(
+        &mut self,
+        geometry: GeometryId,
+        material: material::MaterialId,
+        transform: ModelMat,
+        pick_id: u32,
+    ) {
+        self.submit_batched_draw(geometry, material, transform, Some(pick_id));
+    }
+
+    /// like `submit_draw` with an outline around it, see `outline_renderer` for its look.
+    /// `pick_id` as for `submit_pickable_draw`
+    pub fn submit_outlined_draw(
+        &mut self,
+        geometry: GeometryId,
+        material: material::MaterialId,
+        transform: ModelMat,
+        pick_id: Option<u32>,
+    ) {
+        // TODO: pbr materials lose their shading while outlined, the stencil pipeline shades simply
+        let pipeline = batch::PipelineId::OutlineStencil;
+        self.draw_batcher.submit(batch::DrawKey { pipeline, material, geometry }, transform, pick_id);
+        self.outline_renderer.submit(geometry, transform);
+    }
+
+    fn submit_batched_draw(
+        &mut self,
+        geometry: GeometryId,
+        material: material::MaterialId,
+        transform: ModelMat,
+        pick_id: Option<u32>,
+    ) {
+        let pipeline = match self.material_system.get_material(material).map(|material| material.shading) {
+            Some(material::ShadingModel::Pbr) => batch::PipelineId::Pbr,
+            _ => batch::PipelineId::Unlit,
+        };
+        self.draw_batcher.submit(
+            batch::DrawKey {
+                pipeline,
+                material,
+                geometry,
+            },
+            transform,
+            pick_id,
+        );
+    }
+
+    /// culled against the camera's frustum, otherwise drawn with the level of detail
+    /// for its distance to the camera. `lod_state` is the instance's, kept between frames,
+    /// `pick_id` as for `submit_pickable_draw`
+    pub fn submit_lod_draw(
+        &mut self,
+        mesh: geometry::LodMeshId,
+        material: material::MaterialId,
+        transform: ModelMat,
+        lod_state: &mut geometry::LodState,
+        pick_id: Option<u32>,
+    ) {
+        let levels = self.geometry_system.get_lod_mesh(mesh).get_levels();
+        // the finest level bounds the coarser ones closely enough
+        let bounds = self.geometry_system.get_bounds(levels[0].geometry).transformed(&transform);
+        if !Frustum::from_proj_view(&self.camera.calc_proj_view()).intersects_aabb(&bounds.aabb) {
+            self.frame_lod_stats.frustum_culled += 1;
+            return;
+        }
+
+        let distance = (bounds.center - self.camera.translation).norm_sqr().sqrt();
+        let mut switch_distances = [0.0; geometry::MAX_LOD_LEVELS];
+        for (switch_distance, level) in switch_distances.iter_mut().zip(levels) {
+            *switch_distance = level.switch_distance;
+        }
+        lod_state.level = geometry::select_lod_level(
+            &switch_distances[..levels.len()],
+            distance,
+            lod_state.level,
+            geometry::LOD_HYSTERESIS,
+        );
+
+        match lod_state.level {
+            Some(level) => {
+                let geometry = levels[level].geometry;
+                self.frame_lod_stats.drawn_per_level[level] += 1;
+                self.submit_batched_draw(geometry, material, transform, pick_id);
+            }
+            None => self.frame_lod_stats.distance_culled += 1,
+        }
+    }
+
+    /// replaces the current terrain, waits for the device to go idle
+    pub fn set_terrain(&mut self, terrain: crate::terrain::Terrain, material: terrain::TerrainMaterial) {
+        unsafe { self.device.device_wait_idle().unwrap(); }
+        self.terrain_renderer.set_terrain(terrain, material, &self.physical_device_memory_properties);
+    }
+
+    /// `count` default values that live until the next frame starts,
+    /// for transient lists that would otherwise be a new `Vec` every frame
+    pub fn frame_alloc<T: Copy + Default>(&self, count: usize) -> &mut [T] {
+        self.frame_arena.alloc_slice(count)
+    }
+
+    /// shared with earlier loads of the same path, materials refer to it by the handle's index,
+    /// `None` when the file is missing or the textures array is full
+    pub fn load_texture(&mut self, path: &str, ty: texture::TextureType) -> Option<TextureHandle> {
+        self.acquire_texture(path, ty, None)
+    }
+
+    /// `load_texture` for many files at once, the files not loaded yet are decoded as jobs
+    pub fn load_textures(&mut self, requests: &[(&str, texture::TextureType)]) -> Vec<Option<TextureHandle>> {
+        let blit_support = texture::BlitSupport::query(&self.instance, self.physical_device);
+        let mut decodes: Vec<(&str, texture::TextureType, Option<texture::DecodedTexture>)> = vec![];
+        for &(path, ty) in requests {
+            if !self.texture_assets.is_cached(path)
+                && std::path::Path::new(path).exists()
+                && !decodes.iter().any(|&(decode_path, _, _)| decode_path == path)
+            {
+                decodes.push((path, ty, None));
+            }
+        }
+        self.job_system.parallel_for("decode texture", &mut decodes, 1, |_, (path, ty, decoded)| {
+            *decoded = Some(texture::DecodedTexture::decode(path, *ty, blit_support));
+        });
+
+        let mut decoded: HashMap<&str, texture::DecodedTexture> = decodes
+            .into_iter()
+            .map(|(path, _, decoded)| (path, decoded.unwrap()))
+            .collect();
+        requests
+            .iter()
+            .map(|&(path, ty)| self.acquire_texture(path, ty, decoded.remove(path)))
+            .collect()
+    }
+
+    /// decodes the file unless `decoded` already holds it
+    fn acquire_texture(
+        &mut self,
+        path: &str,
+        ty: texture::TextureType,
+        decoded: Option<texture::DecodedTexture>,
+    ) -> Option<TextureHandle> {
+        let transfer = texture::TransferContext {
+            sync: &self.sync,
+            physical_device_memory_properties: &self.physical_device_memory_properties,
+            command_pool: self.transient_command_pool,
+            queue: self.graphics_queue,
+            queue_family_index: self.graphics_family_index,
+        };
+        let handle = self.texture_assets.acquire(path, |path| Some(match decoded {
+            Some(decoded) => texture::Texture::upload(
+                decoded,
+                self.device.clone(),
+                &transfer,
+                &mut self.sampler_cache,
+            ),
+            None => texture::Texture::load(
+                path,
+                &self.instance,
+                self.physical_device,
+                self.device.clone(),
+                &transfer,
+                &mut self.sampler_cache,
+                ty,
+            ),
+        }))?;
+        if handle.index() as u32 >= descriptor::MAX_TEXTURE_COUNT {
+            log::error!("Textures array is full at {} elements, can't load {}", descriptor::MAX_TEXTURE_COUNT, path);
+            self.texture_assets.release(handle);
+            return None;
+        }
+        self.write_texture_descriptor(handle);
+        Some(handle)
+    }
+
+    /// shared with earlier loads of the same path, so scene objects using one mesh share its geometry.
+    /// Only `primitives` paths resolve until there is a mesh loader, `None` for others
+    pub fn load_geometry(&mut self, path: &str) -> Option<AssetHandle<GeometryId>> {
+        if self.geometry_assets.is_cached(path) {
+            return self.geometry_assets.acquire(path, |_| None);
+        }
+        let Some((vertices, indices)) = crate::primitives::from_path(path) else {
+            log::warn!("No mesh loader for {}", path);
+            return None;
+        };
+        let geometry = self.geometry_system.create_geometry(&vertices, &indices);
+        Some(self.geometry_assets.insert(path, geometry))
+    }
+
+    /// uploads a packed atlas as a texture named `name`, which `load_texture` then shares.
+    /// None when the textures array is full
+    pub fn load_atlas(&mut self, name: &str, atlas: atlas::TextureAtlas) -> Option<TextureHandle> {
+        self.load_decoded_texture(name, atlas.into_decoded())
+    }
+
+    /// uploads a texture generated at runtime named `name`, which `load_texture` then shares.
+    /// None when the textures array is full
+    pub fn load_decoded_texture(&mut self, name: &str, decoded: texture::DecodedTexture) -> Option<TextureHandle> {
+        let texture = texture::Texture::upload(
+            decoded,
+            self.device.clone(),
+            &texture::TransferContext {
+                sync: &self.sync,
+                physical_device_memory_properties: &self.physical_device_memory_properties,
+                command_pool: self.transient_command_pool,
+                queue: self.graphics_queue,
+                queue_family_index: self.graphics_family_index,
+            },
+            &mut self.sampler_cache,
+        );
+        let handle = self.texture_assets.insert(name, texture);
+        if handle.index() as u32 >= descriptor::MAX_TEXTURE_COUNT {
+            log::error!("Textures array is full at {} elements, can't load {}", descriptor::MAX_TEXTURE_COUNT, name);
+            self.texture_assets.release(handle);
+            return None;
+        }
+        self.write_texture_descriptor(handle);
+        Some(handle)
+    }
+
+    /// offscreen target of `width` by `height` rendered from `camera` each frame, see `render_target`.
+    /// `None` when the textures array is full
+    pub fn create_render_target(
+        &mut self,
+        name: &str,
+        width: u32,
+        height: u32,
+        camera: Camera,
+    ) -> Option<render_target::RenderTargetId> {
+        let pixels = vec![0; (width * height * 4) as usize];
+        let texture = self.load_decoded_texture(
+            name,
+            texture::DecodedTexture::from_pixels(texture::TextureType::Diffuse, width, height, pixels),
+        )?;
+        Some(self.render_targets.insert(&self.physical_device_memory_properties, texture, width, height, camera))
+    }
+
+    /// the target and its texture are destroyed once no frame in flight uses them
+    pub fn destroy_render_target(&mut self, id: render_target::RenderTargetId) {
+        if let Some(texture) = self.render_targets.remove(id) {
+            self.release_texture(texture);
+        }
+    }
+
+    /// captured around its position during the next frame, see `reflection_probe`.
+    /// `None` when every probe slot is taken
+    pub fn add_reflection_probe(
+        &mut self,
+        probe: reflection_probe::ReflectionProbe,
+    ) -> Option<reflection_probe::ReflectionProbeId> {
+        unsafe { self.device.device_wait_idle().unwrap() };
+        self.reflection_probes.insert(probe, &self.environment, self.transient_command_pool, self.graphics_queue)
+    }
+
+    pub fn remove_reflection_probe(&mut self, id: reflection_probe::ReflectionProbeId) {
+        unsafe { self.device.device_wait_idle().unwrap() };
+        self.reflection_probes.remove(id);
+    }
+
+    /// e.g. point filtering or clamping for one texture, the sampler is shared with
+    /// every texture of the same `desc`. Lasts until the texture is reloaded
+    pub fn set_texture_sampler(&mut self, handle: TextureHandle, desc: &sampler::SamplerDesc) {
+        let sampler = self.sampler_cache.get(desc);
+        let Some(texture) = self.texture_assets.get_mut(handle) else {
+            return;
+        };
+        texture.sampler = sampler;
+        self.write_texture_descriptor(handle);
+    }
+
+    /// a white pixel, for untextured sprites and immediate geometry. Shared, don't release it
+    pub fn get_fallback_texture(&self) -> TextureHandle {
+        self.fallback_texture
+    }
+
+    /// `load_texture` that always gives a texture to draw with, the fallback texture when the file is
+    /// missing or the textures array is full. Release it with `release_texture` all the same.
+    /// The array doesn't grow, its size is compiled into the shaders and pipeline layouts,
+    /// so a full array logs an error, raise `descriptor::MAX_TEXTURE_COUNT` and the shaders' size with it
+    pub fn add_texture(&mut self, path: &str, ty: texture::TextureType) -> TextureHandle {
+        self.load_texture(path, ty).unwrap_or_else(|| {
+            log::warn!("Using the fallback texture for {}", path);
+            self.texture_assets.retain(self.fallback_texture);
+            self.fallback_texture
+        })
+    }
+
+    /// the texture is destroyed once no frame in flight uses it,
+    /// its element in the textures array samples the fallback texture from the next frame
+    pub fn release_texture(&mut self, handle: TextureHandle) {
+        self.texture_assets.release(handle);
+        if self.texture_assets.get(handle).is_none() {
+            // the texture only retires once the frames in flight now are done, frames recorded
+            // from here on must not sample it. The write waits in the batcher until the next frame's
+            // flush, which waits for every frame in flight, and a texture taking the element before
+            // then replaces it, so the element is written once
+            self.write_texture_descriptor_of(handle.index() as u32, self.fallback_texture);
+        }
+    }
+
+    /// reloads textures whose files changed when hot reloading is enabled
+    /// paths of the reloaded assets
+    pub fn reload_changed_assets(&mut self) -> Vec<String> {
+        let reloaded = self.texture_assets.reload_changed(|path, old_texture| Some(texture::Texture::load(
+            path,
+            &self.instance,
+            self.physical_device,
+            self.device.clone(),
+            &texture::TransferContext {
+                sync: &self.sync,
+                physical_device_memory_properties: &self.physical_device_memory_properties,
+                command_pool: self.transient_command_pool,
+                queue: self.graphics_queue,
+                queue_family_index: self.graphics_family_index,
+            },
+            &mut self.sampler_cache,
+            old_texture.get_type(),
+        )));
+        reloaded
+            .into_iter()
+            .map(|handle| {
+                self.write_texture_descriptor(handle);
+                self.texture_assets.get_path(handle).unwrap().to_owned()
+            })
+            .collect()
+    }
+
+    fn write_texture_descriptor(&mut self, handle: TextureHandle) {
+        self.write_texture_descriptor_of(handle.index() as u32, handle);
+    }
+
+    /// `element` of the textures array to sample `handle`'s texture
+    fn write_texture_descriptor_of(&mut self, element: u32, handle: TextureHandle) {
+        let texture = self.texture_assets.get(handle).unwrap();
+        self.descriptor_write_batcher.queue_image_write(
+            self.textures_set,
+            0,
+            element,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            vk::DescriptorImageInfo {
+                sampler: texture.sampler,
+                image_view: texture.image_view,
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            },
+        );
+    }
+
+    pub fn get_vsync(&self) -> bool {
+        self.vsync
+    }
+
+    /// applied when the next frame starts
+    pub fn set_vsync(&mut self, vsync: bool) {
+        if vsync != self.vsync {
+            self.vsync = vsync;
+            self.resize_tracker.mark_out_of_date();
+        }
+    }
+
+    pub fn get_swapchain_format_preference(&self) -> swapchain::SwapchainFormatPreference {
+        self.swapchain_format_preference
+    }
+
+    /// applied when the next frame starts, falls back to sdr when the surface doesn't support it
+    pub fn set_swapchain_format_preference(&mut self, preference: swapchain::SwapchainFormatPreference) {
+        if preference != self.swapchain_format_preference {
+            self.swapchain_format_preference = preference;
+            self.resize_tracker.mark_out_of_date();
+        }
+    }
+
+    /// how colors are encoded for the current swapchain format
+    pub fn output_transfer(&self) -> swapchain::OutputTransfer {
+        swapchain::OutputTransfer::of(vk::SurfaceFormatKHR {
+            format: self.swapchain_image_format,
+            color_space: self.swapchain_color_space,
+        })
+    }
+
+    /// applied when the next frame starts
+    pub fn request_resize(&mut self, extent: vk::Extent2D) {
+        self.resize_tracker.request_resize(extent);
+    }
+
+    /// frees the swapchain and the scene sized attachments while nothing is drawn,
+    /// e.g. when minimized for a while. The next drawn frame renews them, waits for the device to go idle
+    pub fn release_swapchain(&mut self) {
+        if self.swapchain_released {
+            return;
+        }
+        log::debug!("Releasing swapchain");
+        self.cleanup_swapchain();
+        self.swapchain_released = true;
+        self.presented_image_index = None;
+        self.resize_tracker.mark_out_of_date();
+    }
+
+    // TODO: swapchain abstraction
+    pub fn renew_swapchain(&mut self) {
+        if !self.swapchain_released {
+            self.cleanup_swapchain();
+        }
+        self.swapchain_released = false;
+        let preferred_extent = self.resize_tracker.take_renewal_extent(self.swapchain_extent);
+
+        let surface_format;
+        (
+            self.swapchain, 
+            self.swapchain_khr, 
+            self.swapchain_images, 
+            self.swapchain_image_views,
+            surface_format, 
+            self.swapchain_extent,
+            self.swapchain_image_usage,
+        ) = swapchain::new_swapchain_and_images(
+            &swapchain::SwapchainContext {
+                instance: &self.instance,
+                physical_device: self.physical_device,
+                device: &self.device,
+                surface: &self.surface,
+                surface_khr: self.surface_khr,
+                graphics_family_index: self.graphics_family_index,
+                present_family_index: self.present_family_index,
+            },
+            preferred_extent,
+            self.vsync,
+            self.swapchain_format_preference,
+        );
+        // the render pass and the pipelines' output encoding depend on the format
+        if surface_format.format != self.swapchain_image_format || surface_format.color_space != self.swapchain_color_space {
+            log::info!("Swapchain format {:?} -> {:?}", self.swapchain_image_format, surface_format);
+            self.swapchain_image_format = surface_format.format;
+            self.swapchain_color_space = surface_format.color_space;
+            self.renew_render_pass_and_pipelines();
+        }
+        self.fit_camera_projection();
+        let scene_extent = self.get_scene_extent();
+
+        self.scene_target = if self.is_render_scaled() {
+            Some(render_scale::SceneTarget::new(
+                self.device.clone(),
+                &self.physical_device_memory_properties,
+                self.swapchain_image_format,
+                scene_extent,
+            ))
+        } else {
+            None
+        };
+
+        (
+            self.swapchain_depth_image,
+            self.swapchain_depth_image_memory,
+            self.swapchain_depth_image_view,
+            self.swapchain_depth_sampled_view,
+        ) = Self::new_depth_resources(
+            &self.device,
+            &self.sync,
+            &self.physical_device_memory_properties,
+            self.graphics_command_pool,
+            self.graphics_queue,
+            self.graphics_family_index,
+            self.swapchain_depth_format,
+            scene_extent,
+        );
+        self.precipitation_system.set_depth_view(&mut self.descriptor_write_batcher, self.swapchain_depth_sampled_view);
+        self.hiz.resize(&mut self.descriptor_write_batcher, scene_extent, self.swapchain_depth_sampled_view);
+        self.ssr.resize(
+            &mut self.descriptor_write_batcher,
+            scene_extent,
+            self.swapchain_depth_sampled_view,
+            self.transient_command_pool,
+            self.graphics_queue,
+        );
+        self.motion_blur.resize(&mut self.descriptor_write_batcher, scene_extent, self.swapchain_depth_sampled_view);
+
+        self.gbuffer = match self.render_path {
+            RenderPath::Forward => None,
+            RenderPath::Deferred => {
+                let gbuffer = gbuffer::GBuffer::new(
+                    self.device.clone(),
+                    &self.physical_device_memory_properties,
+                    scene_extent,
+                );
+                // TODO: input attachments should be the attached view, which has the stencil aspect too
+                gbuffer::queue_gbuffer_set_writes(
+                    &mut self.descriptor_write_batcher,
+                    self.gbuffer_set,
+                    &gbuffer,
+                    self.swapchain_depth_sampled_view,
+                );
+                Some(gbuffer)
+            }
+        };
+
+        // a scaled scene draws every frame to the same target
+        let color_views = match &self.scene_target {
+            Some(scene_target) => vec![scene_target.image_view; self.swapchain_image_views.len()],
+            None => self.swapchain_image_views.clone(),
+        };
+        self.scene_color_used = vec![false; if self.scene_target.is_some() { 1 } else { color_views.len() }];
+        self.edge_outlines.resize(
+            &mut self.descriptor_write_batcher,
+            &color_views,
+            scene_extent,
+            self.swapchain_depth_sampled_view,
+        );
+        self.swapchain_framebuffers = swapchain::new_swapchain_framebuffers(
+            &self.device, 
+            &color_views,
+            self.swapchain_depth_image_view,
+            match &self.gbuffer {
+                Some(gbuffer) => &gbuffer.image_views,
+                None => &[],
+            },
+            self.render_pass, 
+            scene_extent,
+        );
+    }
+    
+    fn cleanup_swapchain(&mut self) {
+        unsafe {
+            //TODO:  = no good
+            self.device.device_wait_idle().unwrap();
+
+            self.device.destroy_image_view(self.swapchain_depth_sampled_view, None);
+            self.device.destroy_image_view(self.swapchain_depth_image_view, None);
+            self.device.destroy_image(self.swapchain_depth_image, None);
+            self.device.free_memory(self.swapchain_depth_image_memory, None);
+
+            if let Some(gbuffer) = &mut self.gbuffer {
+                gbuffer.destroy();
+            }
+            self.gbuffer = None;
+
+            if let Some(scene_target) = &mut self.scene_target {
+                scene_target.destroy();
+            }
+            self.scene_target = None;
+
+            // empty with dynamic rendering
+            for &framebuffer in &self.swapchain_framebuffers {
+                self.device.destroy_framebuffer(framebuffer, None);
+            }
+            for &image_view in &self.swapchain_image_views {
+                self.device.destroy_image_view(image_view, None);
+            }
+
+            self.swapchain.destroy_swapchain(self.swapchain_khr, None);
+        }
+    }
+
+    fn new_command_buffers(
+        device: &ash::Device, 
+        pool: vk::CommandPool,
+        count: usize,
+    ) -> Vec<vk::CommandBuffer> {
+        //allocate command command_buffers
+        let alloc_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(count as u32);
+
+        unsafe { device.allocate_command_buffers(&alloc_info).unwrap() }
+    }
+
+    fn new_command_pool(
+        create_flags: vk::CommandPoolCreateFlags,
+        queue_family_index: u32,
+        device: &ash::Device,
+    ) -> vk::CommandPool {
+        let info = vk::CommandPoolCreateInfo::builder()
+            .queue_family_index(queue_family_index)
+            .flags(create_flags);
+
+        unsafe { device.create_command_pool(&info, None).expect("Failed to create command pool") }
+    }
+
+    /// `validation` enables the validation layer in debug builds
+    fn new_instance(
+        entry: &ash::Entry,
+        api_version: u32,
+        display_handle: raw_window_handle::RawDisplayHandle,
+        validation: bool,
+    ) -> ash::Instance {
+        let app_name = CString::new("Vulkan Application").unwrap();
+        let engine_name = CString::new("No Engine").unwrap();
+
+        let app_info = vk::ApplicationInfo::builder()
+            .application_name(&app_name)
+            .engine_name(&engine_name)
+            .application_version(vk::make_api_version(0, 0, 0, 1))
+            .engine_version(vk::make_api_version(0, 0, 0, 1))
+            .api_version(api_version);
+
+        // surface extensions for whichever windowing system the display belongs to
+        let mut extension_name_ptrs = ash_window::enumerate_required_extensions(display_handle)
+            .expect("Unsupported windowing system")
+            .to_vec();
+        #[cfg(debug_assertions)] 
+        extension_name_ptrs.push(DebugUtils::name().as_ptr());
+        // MoltenVK is only listed as a portability driver
+        #[cfg(target_os = "macos")]
+        extension_name_ptrs.push(vk::KhrPortabilityEnumerationFn::name().as_ptr());
+        // surfaces only report hdr color spaces with it
+        let extension_properties = entry.enumerate_instance_extension_properties(None).unwrap();
+        let has_swapchain_colorspace = extension_properties.iter().any(|properties| {
+            let name = unsafe { CStr::from_ptr(properties.extension_name.as_ptr()) };
+            name == vk::ExtSwapchainColorspaceFn::name()
+        });
+        if has_swapchain_colorspace {
+            extension_name_ptrs.push(vk::ExtSwapchainColorspaceFn::name().as_ptr());
+        }
+
+        let (_, layer_name_ptrs) = &debug::get_layer_names_and_ptrs();
 
         let mut info = vk::InstanceCreateInfo::builder()
             .application_info(&app_info)
             .enabled_extension_names(&extension_name_ptrs);
+        #[cfg(target_os = "macos")] {
+            info = info.flags(vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR);
+        }
             
-        #[cfg(debug_assertions)] {
+        if cfg!(debug_assertions) && validation {
             debug::check_validation_layer_support(entry);
             info = info.enabled_layer_names(&layer_name_ptrs);
         }
@@ -578,66 +2115,103 @@ impl VkApp {
     }
 
     fn update_uniform_buffer(&mut self) {
+        let (light_direction, light_color) = self.light.to_shader_vectors();
+        let time = self.start_instant.elapsed().as_secs_f32();
+        let wind = self.weather.wind.velocity(time);
+        let camera_position = self.camera.translation;
+        let proj_view = self.camera.calc_proj_view();
+        let (fog_mode, fog_color, fog_params) = self.fog.to_shader_vectors(self.clear_config.clear_color);
         let ubo = descriptor::PerFrameUBO {
-            proj_view: self.camera.calc_proj_view()
+            proj_view,
+            light_direction,
+            light_color,
+            camera_position: [camera_position.x, camera_position.y, camera_position.z, 1.0],
+            wind: [wind.x, wind.y, wind.z, 0.0],
+            time,
+            wetness: self.weather.wetness,
+            environment_intensity: if self.environment_path.is_some() { self.environment_intensity } else { 0.0 },
+            fog_mode,
+            fog_color,
+            fog_params,
+            inverse_proj_view: proj_view.inverse().unwrap_or_default(),
+            previous_proj_view: self.motion_blur.advance(proj_view, time),
         };
 
-        let per_frame_ubo_ptr = (self.per_frame_uniform_buffer.mapped_ptr as usize + 
-            self.current_frame * size_of::<PerFrameUBO>()) as *mut PerFrameUBO;
-        unsafe {
-            *per_frame_ubo_ptr = ubo;
-        }
+        self.uniform_ring.begin_frame(self.current_frame);
+        self.view_ubo_offsets[descriptor::MAIN_VIEW] = self.uniform_ring.push(&ubo);
+        self.view_ubo_offsets[minimap::MINIMAP_VIEW] = self.uniform_ring.push(&self.minimap.view_ubo(&ubo));
+        self.render_targets.push_view_ubos(&ubo, &mut self.uniform_ring);
+        self.reflection_probes.push_view_ubos(&ubo, &mut self.uniform_ring);
+    }
+
+    /// indexed by `batch::PipelineId`, pbr and outlined draws fall back to the unlit pipeline
+    /// when their own doesn't exist
+    fn get_draw_pipelines(&self) -> [vk::Pipeline; batch::PIPELINE_ID_COUNT] {
+        let pbr_pipeline = if self.pbr_pipeline != vk::Pipeline::null() { self.pbr_pipeline } else { self.pipeline };
+        [
+            self.pipeline,
+            pbr_pipeline,
+            self.outline_renderer.get_stencil_pipeline().unwrap_or(self.pipeline),
+        ]
     }
 
     fn record_graphics_command_buffer(
         &mut self, 
         graphics_command_buffer: vk::CommandBuffer,
         image_index: usize,
+        frame_extent: vk::Extent2D,
     ) {
         let begin_info = vk::CommandBufferBeginInfo::default();
+        let scene_extent = render_scale::scale_extent(frame_extent, self.render_scale);
         
         let render_area = vk::Rect2D {
             offset: vk::Offset2D{
                 x: 0, y: 0,
             },
-            extent: self.swapchain_extent,
+            extent: scene_extent,
         };
 
-        let clear_values = [
-            vk::ClearValue {
-                color: vk::ClearColorValue {
-                    float32: [0.0, 0.0, 0.2, 1.0],
-                }
-            },
-            vk::ClearValue {
-                depth_stencil: vk::ClearDepthStencilValue {
-                    depth: 1.0,
-                    stencil: 0,
-                }
-            },
-        ];
+        // draws were keyed by pipeline id, pipelines may have been renewed since they were submitted
+        self.draw_batcher.set_pipelines(self.get_draw_pipelines());
+
+        // a load on the image's first frame would read it in UNDEFINED layout
+        let scene_color_index = self.scene_color_index(image_index);
+        let first_use = !self.scene_color_used[scene_color_index];
+        self.scene_color_used[scene_color_index] = true;
+        let clear_config = if first_use { self.clear_config.first_use() } else { self.clear_config };
+        let scene_render_pass = if first_use && self.first_use_render_pass != vk::RenderPass::null() {
+            self.first_use_render_pass
+        } else {
+            self.render_pass
+        };
+
+        let mut clear_values = clear_config.clear_values().to_vec();
+        if self.render_path == RenderPath::Deferred {
+            for _ in gbuffer::GBUFFER_FORMATS {
+                clear_values.push(vk::ClearValue {
+                    color: vk::ClearColorValue {
+                        float32: [0.0, 0.0, 0.0, 0.0],
+                    }
+                });
+            }
+        }
         
         let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
-            .render_pass(self.render_pass)
-            .framebuffer(self.swapchain_framebuffers[image_index])
+            .render_pass(scene_render_pass)
+            .framebuffer(self.swapchain_framebuffers.get(image_index).copied().unwrap_or_default())
             .render_area(render_area)
             .clear_values(&clear_values);
         
+        // the whole scene extent unless letterboxed
+        let scissor = letterbox::letterbox(scene_extent, self.fixed_aspect_ratio);
         let viewport = vk::Viewport {
-            x: 0.0, 
-            y: 0.0,
-            width: self.swapchain_extent.width as f32, 
-            height: self.swapchain_extent.height as f32,
+            x: scissor.offset.x as f32, 
+            y: scissor.offset.y as f32,
+            width: scissor.extent.width as f32, 
+            height: scissor.extent.height as f32,
             min_depth: 0.0, 
             max_depth: 1.0, 
         };
-        let scissor = vk::Rect2D {
-            offset: vk::Offset2D {
-                x: 0,
-                y: 0,
-            },
-            extent: self.swapchain_extent,
-        };
 
         unsafe {
             self.device.begin_command_buffer(
@@ -645,51 +2219,672 @@ impl VkApp {
                 &begin_info
             ).expect("Failed to begin recording command buffer");
 
-            self.device.cmd_begin_render_pass(
-                graphics_command_buffer, 
-                &render_pass_begin_info, 
-                vk::SubpassContents::INLINE
+            self.gpu_profiler.cmd_begin_frame(graphics_command_buffer, self.current_frame);
+            self.pipeline_statistics_profiler.cmd_begin_frame(graphics_command_buffer, self.current_frame);
+            self.cmd_mark(graphics_command_buffer, "frame start");
+            self.skinning_system.cmd_dispatch(graphics_command_buffer, self.current_frame);
+            self.cmd_mark(graphics_command_buffer, "skinning");
+            self.precipitation_system.cmd_dispatch(
+                graphics_command_buffer,
+                self.swapchain_depth_image,
+                self.swapchain_depth_format,
+            );
+            self.cmd_mark(graphics_command_buffer, "precipitation");
+            self.hiz.cmd_build(
+                graphics_command_buffer,
+                self.swapchain_depth_image,
+                self.swapchain_depth_format,
+                self.reverse_z,
             );
+            self.cmd_mark(graphics_command_buffer, "hi-z");
+            if !self.is_async_compute() {
+                self.gpu_particle_system.cmd_dispatch(graphics_command_buffer, false);
+                self.cmd_mark(graphics_command_buffer, "particles");
+            }
+            self.geometry_system.cmd_upload_geometries(graphics_command_buffer);
+            self.draw_batcher.cmd_upload_indirect_commands(
+                graphics_command_buffer,
+                self.current_frame,
+                &self.geometry_system,
+            );
+            self.cmd_mark(graphics_command_buffer, "uploads");
+            self.gpu_culling.cmd_dispatch(graphics_command_buffer, self.current_frame);
+            self.cmd_mark(graphics_command_buffer, "culling");
+            {
+                // the views drawing the batches again share them, marked through the breadcrumbs
+                // alone since the draws stay borrowed
+                let scene_draws = batch::SceneDraws {
+                    draw_batcher: &self.draw_batcher,
+                    geometry_system: &self.geometry_system,
+                    material_system: &self.material_system,
+                    per_frame_ubo_set: self.per_frame_ubo_set,
+                    textures_set: self.textures_set,
+                };
+                let mut cmd_mark = |name: &'static str| {
+                    if let Some(breadcrumbs) = &mut self.breadcrumbs {
+                        breadcrumbs.cmd_mark(graphics_command_buffer, self.current_frame, name);
+                    }
+                };
+                self.minimap.cmd_render(
+                    graphics_command_buffer,
+                    self.current_frame,
+                    self.view_ubo_offsets[minimap::MINIMAP_VIEW],
+                    &scene_draws,
+                );
+                cmd_mark("minimap");
+                self.render_targets.cmd_render(
+                    &self.sync,
+                    graphics_command_buffer,
+                    self.current_frame,
+                    self.clear_config.clear_color,
+                    &scene_draws,
+                    &self.texture_assets,
+                );
+                cmd_mark("render targets");
+                self.reflection_probes.cmd_capture(
+                    graphics_command_buffer,
+                    self.current_frame,
+                    [self.per_frame_ubo_set, self.textures_set],
+                    &self.draw_batcher,
+                    &self.geometry_system,
+                    &self.material_system,
+                );
+                cmd_mark("reflection probes");
+                self.picking.cmd_render(
+                    graphics_command_buffer,
+                    self.current_frame,
+                    scissor.extent,
+                    self.view_ubo_offsets[descriptor::MAIN_VIEW],
+                    &scene_draws,
+                );
+                cmd_mark("picking");
+                self.motion_blur.cmd_render_velocity(
+                    graphics_command_buffer,
+                    self.current_frame,
+                    scissor,
+                    self.view_ubo_offsets[descriptor::MAIN_VIEW],
+                    &scene_draws,
+                );
+                cmd_mark("motion vectors");
+            }
+
+            // indirect drawing records few draws already
+            let worker_count = if self.draw_batcher.indirect {
+                0
+            } else {
+                self.parallel_recorder.workers_for(self.draw_batcher.batch_count())
+            };
+            let scene_contents = if worker_count > 0 {
+                vk::SubpassContents::SECONDARY_COMMAND_BUFFERS
+            } else {
+                vk::SubpassContents::INLINE
+            };
+
+            if self.uses_dynamic_rendering() {
+                self.cmd_begin_rendering(graphics_command_buffer, image_index, &clear_config, render_area, scene_contents);
+            } else {
+                self.device.cmd_begin_render_pass(
+                    graphics_command_buffer, 
+                    &render_pass_begin_info, 
+                    scene_contents,
+                );
+            }
+
+            // a subpass executing secondary command buffers can't record inline,
+            // what isn't a batch goes to the main thread's secondary command buffer.
+            // Only the first subpass is recorded this way: on the deferred path the lighting and
+            // translucent draws are recorded inline into the next subpass, which is only valid
+            // because `cmd_next_subpass` begins it with `SubpassContents::INLINE`
+            let secondary_target = parallel_record::SecondaryTarget {
+                render_pass: self.render_pass,
+                subpass: 0,
+                framebuffer: render_pass_begin_info.framebuffer,
+                color_format: self.swapchain_image_format,
+                depth_format: self.swapchain_depth_format,
+                pipeline_statistics: self.pipeline_statistics_profiler.get_inherited_statistics(),
+            };
+            let scene_command_buffer = if worker_count > 0 {
+                self.parallel_recorder.reset(self.current_frame);
+                self.parallel_recorder.begin_main(self.current_frame, &secondary_target)
+            } else {
+                graphics_command_buffer
+            };
 
             self.device.cmd_set_viewport(
-                graphics_command_buffer, 
+                scene_command_buffer, 
                 0, 
                 &[viewport]
             );
             self.device.cmd_set_scissor(
-                graphics_command_buffer, 
+                scene_command_buffer, 
                 0, 
                 &[scissor]
             );
 
             self.device.cmd_bind_pipeline(
-                graphics_command_buffer, 
+                scene_command_buffer, 
                 vk::PipelineBindPoint::GRAPHICS, 
                 self.pipeline
             );
 
             self.device.cmd_bind_descriptor_sets(
-                graphics_command_buffer, 
+                scene_command_buffer, 
                 vk::PipelineBindPoint::GRAPHICS, 
                 self.pipeline_layout, 
                 0, 
-                &[self.per_frame_ubo_set],
-                &[(self.current_frame * size_of::<PerFrameUBO>()) as u32] 
+                &[self.per_frame_ubo_set, self.textures_set, self.environment.get_set()],
+                &[self.view_ubo_offsets[descriptor::MAIN_VIEW]],
+            );
+
+            self.geometry_system.cmd_bind_resources(scene_command_buffer);
+            let debug_view_pipeline = self.debug_view_renderer.get_pipeline();
+            let batch_command_buffers = if worker_count > 0 {
+                self.draw_batcher.resolve_batches(&self.geometry_system, &self.material_system, &mut self.batch_draws);
+                if let Some(pipeline) = debug_view_pipeline {
+                    self.batch_draws.iter_mut().for_each(|draw| draw.pipeline = pipeline);
+                }
+                let (vertex_buffer, index_buffer) = self.geometry_system.get_buffers();
+                let (instance_buffer, instance_offset) = self.draw_batcher.get_instance_binding(self.current_frame);
+                let draw_state = parallel_record::DrawState {
+                    viewport,
+                    scissor,
+                    pipeline_layout: self.pipeline_layout,
+                    descriptor_sets: [self.per_frame_ubo_set, self.textures_set, self.environment.get_set()],
+                    dynamic_offsets: [self.view_ubo_offsets[descriptor::MAIN_VIEW]],
+                    vertex_buffer,
+                    index_buffer,
+                    instance_buffer,
+                    instance_offset,
+                };
+                self.parallel_recorder.record_batches(
+                    &self.job_system,
+                    self.current_frame,
+                    worker_count,
+                    &secondary_target,
+                    &draw_state,
+                    &self.batch_draws,
+                )
+            } else if self.gpu_culling.is_active() {
+                self.gpu_culling.cmd_draw(
+                    scene_command_buffer,
+                    self.current_frame,
+                    self.pipeline_layout,
+                    debug_view_pipeline,
+                    &self.draw_batcher,
+                    &self.material_system,
+                );
+                &[]
+            } else {
+                self.draw_batcher.cmd_draw_batches(
+                    scene_command_buffer,
+                    self.current_frame,
+                    self.pipeline_layout,
+                    debug_view_pipeline,
+                    &self.geometry_system,
+                    &self.material_system,
+                );
+                &[]
+            };
+            self.skinning_system.cmd_draw(
+                scene_command_buffer,
+                self.current_frame,
+                self.pipeline_layout,
+                &self.material_system,
+            );
+            self.terrain_renderer.cmd_draw(
+                scene_command_buffer,
+                self.per_frame_ubo_set,
+                self.view_ubo_offsets[descriptor::MAIN_VIEW],
+                self.textures_set,
+            );
+
+            // translucent geometry draws in the opaque subpass on the forward path
+            let translucent_command_buffer = if self.render_path == RenderPath::Deferred {
+                if worker_count > 0 {
+                    self.cmd_execute_scene_commands(graphics_command_buffer, batch_command_buffers, scene_command_buffer);
+                }
+                self.device.cmd_next_subpass(graphics_command_buffer, vk::SubpassContents::INLINE);
+                // dynamic state is undefined after executing secondary command buffers
+                self.device.cmd_set_viewport(graphics_command_buffer, 0, &[viewport]);
+                self.device.cmd_set_scissor(graphics_command_buffer, 0, &[scissor]);
+
+                self.device.cmd_bind_pipeline(
+                    graphics_command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.lighting_pipeline,
+                );
+                self.device.cmd_bind_descriptor_sets(
+                    graphics_command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.lighting_pipeline_layout,
+                    0,
+                    &[self.gbuffer_set, self.per_frame_ubo_set],
+                    &[self.view_ubo_offsets[descriptor::MAIN_VIEW]],
+                );
+                let (light_direction, light_color) = self.light.to_shader_vectors();
+                let push_constants = gbuffer::LightingPushConstants {
+                    clear_color: self.clear_config.clear_color,
+                    light_direction,
+                    light_color,
+                    clear_depth: self.clear_config.clear_depth,
+                };
+                self.device.cmd_push_constants(
+                    graphics_command_buffer,
+                    self.lighting_pipeline_layout,
+                    gbuffer::LightingPushConstants::RANGE.stage_flags,
+                    0,
+                    std::slice::from_raw_parts(
+                        &push_constants as *const gbuffer::LightingPushConstants as *const u8,
+                        size_of::<gbuffer::LightingPushConstants>(),
+                    ),
+                );
+                self.device.cmd_draw(graphics_command_buffer, 3, 1, 0, 0);
+
+                graphics_command_buffer
+            } else {
+                scene_command_buffer
+            };
+
+            // after the outlined draws wrote the stencil, in their subpass or later.
+            // Debug views draw them without writing it
+            if debug_view_pipeline.is_none() {
+                self.outline_renderer.cmd_draw(
+                    translucent_command_buffer,
+                    self.current_frame,
+                    self.per_frame_ubo_set,
+                    self.view_ubo_offsets[descriptor::MAIN_VIEW],
+                    &self.geometry_system,
+                );
+            }
+            self.immediate_renderer.cmd_draw(
+                translucent_command_buffer,
+                self.current_frame,
+                self.per_frame_ubo_set,
+                self.view_ubo_offsets[descriptor::MAIN_VIEW],
+                self.textures_set,
+            );
+            self.precipitation_system.cmd_draw(
+                translucent_command_buffer,
+                self.per_frame_ubo_set,
+                self.view_ubo_offsets[descriptor::MAIN_VIEW],
+            );
+            self.billboard_renderer.cmd_draw(
+                translucent_command_buffer,
+                self.current_frame,
+                self.per_frame_ubo_set,
+                self.view_ubo_offsets[descriptor::MAIN_VIEW],
+            );
+            self.gpu_particle_system.cmd_draw(
+                translucent_command_buffer,
+                &self.billboard_renderer,
+                self.per_frame_ubo_set,
+                self.view_ubo_offsets[descriptor::MAIN_VIEW],
+            );
+            self.sprite_renderer.cmd_draw(
+                translucent_command_buffer,
+                self.current_frame,
+                self.per_frame_ubo_set,
+                self.view_ubo_offsets[descriptor::MAIN_VIEW],
+                self.textures_set,
+            );
+            // TODO: belongs on a ui layer at swapchain resolution, untouched by the render scale
+            self.minimap.cmd_draw(translucent_command_buffer, scissor.extent);
+            self.debug_line_renderer.cmd_draw(
+                translucent_command_buffer,
+                self.current_frame,
+                self.per_frame_ubo_set,
+                self.view_ubo_offsets[descriptor::MAIN_VIEW],
             );
 
-            self.geometry_system.cmd_bind_resources(graphics_command_buffer);
+            if scissor != render_area {
+                self.cmd_clear_bars(translucent_command_buffer, scene_extent, scissor);
+            }
 
-            self.device.cmd_end_render_pass(graphics_command_buffer);
+            if self.render_path == RenderPath::Forward && worker_count > 0 {
+                self.cmd_execute_scene_commands(graphics_command_buffer, batch_command_buffers, scene_command_buffer);
+            }
+
+            if self.uses_dynamic_rendering() {
+                self.cmd_end_rendering(graphics_command_buffer, image_index);
+            } else {
+                self.device.cmd_end_render_pass(graphics_command_buffer);
+            }
+            self.cmd_mark(graphics_command_buffer, "scene");
+
+            let scene_color_image = match &self.scene_target {
+                Some(scene_target) => scene_target.image,
+                None => self.swapchain_images[image_index],
+            };
+            self.ssr.cmd_trace(graphics_command_buffer, &ssr::SceneInputs {
+                color_image: scene_color_image,
+                color_layout: self.scene_color_final_layout(),
+                depth_image: self.swapchain_depth_image,
+                depth_format: self.swapchain_depth_format,
+                viewport: scissor,
+                per_frame_ubo_set: self.per_frame_ubo_set,
+                view_ubo_offset: self.view_ubo_offsets[descriptor::MAIN_VIEW],
+                reverse_z: self.reverse_z,
+                clear_depth: self.clear_config.clear_depth,
+                output_transfer: self.output_transfer(),
+            });
+            self.cmd_mark(graphics_command_buffer, "screen space reflections");
+            self.motion_blur.cmd_blur(graphics_command_buffer, &motion_blur::SceneInputs {
+                color_image: scene_color_image,
+                color_layout: self.scene_color_final_layout(),
+                depth_image: self.swapchain_depth_image,
+                depth_format: self.swapchain_depth_format,
+                viewport: scissor,
+                per_frame_ubo_set: self.per_frame_ubo_set,
+                view_ubo_offset: self.view_ubo_offsets[descriptor::MAIN_VIEW],
+                clear_depth: self.clear_config.clear_depth,
+            });
+            self.cmd_mark(graphics_command_buffer, "motion blur");
+            self.edge_outlines.cmd_draw(graphics_command_buffer, &edge_outline::SceneInputs {
+                color_image: scene_color_image,
+                color_layout: self.scene_color_final_layout(),
+                image_index,
+                depth_image: self.swapchain_depth_image,
+                depth_format: self.swapchain_depth_format,
+                viewport: scissor,
+                per_frame_ubo_set: self.per_frame_ubo_set,
+                view_ubo_offset: self.view_ubo_offsets[descriptor::MAIN_VIEW],
+                clear_depth: self.clear_config.clear_depth,
+            });
+            self.cmd_mark(graphics_command_buffer, "edge outlines");
+
+            // TODO: ui goes after the upscale, at swapchain resolution
+            if let Some(scene_target) = &self.scene_target {
+                scene_target.cmd_blit_to_swapchain(
+                    &self.sync,
+                    graphics_command_buffer,
+                    self.graphics_family_index,
+                    self.swapchain_image_format,
+                    self.swapchain_images[image_index],
+                    frame_extent,
+                );
+            }
+
+            self.cmd_mark(graphics_command_buffer, "frame end");
+            self.pipeline_statistics_profiler.cmd_end_frame(graphics_command_buffer, self.current_frame);
+            self.gpu_profiler.cmd_end_frame(graphics_command_buffer, self.current_frame);
 
             self.device.end_command_buffer(graphics_command_buffer).expect("Could not end recording command buffer");
         }
         
     }
 
-    fn wait_for_and_reset_fences(&mut self, fences: &[vk::Fence]) {
+    /// dynamic rendering counterpart of beginning the forward render pass,
+    /// does the layout transitions the render pass would have done
+    unsafe fn cmd_begin_rendering(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+        clear_config: &render_pass::ClearConfig,
+        render_area: vk::Rect2D,
+        contents: vk::SubpassContents,
+    ) {
+        let (color_image, color_image_view) = match &self.scene_target {
+            Some(scene_target) => (scene_target.image, scene_target.image_view),
+            None => (self.swapchain_images[image_index], self.swapchain_image_views[image_index]),
+        };
+        // `ClearConfig::first_use` on the image's first frame, loads only follow a previous frame
+        let color_old_layout = match clear_config.color_load_op {
+            render_pass::LoadOp::Load => self.scene_color_final_layout(),
+            _ => vk::ImageLayout::UNDEFINED,
+        };
+        if color_old_layout != vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL {
+            image::cmd_transition_image_layout(
+                &self.sync,
+                command_buffer,
+                self.graphics_family_index,
+                &image::ImageTransition {
+                    image: color_image,
+                    format: self.swapchain_image_format,
+                    mip_levels: 1,
+                    old_layout: color_old_layout,
+                    new_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                },
+            );
+        }
+
+        let [color_clear_value, depth_clear_value] = clear_config.clear_values();
+        let color_attachments = [vk::RenderingAttachmentInfo::builder()
+            .image_view(color_image_view)
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(clear_config.color_load_op.to_vk())
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .clear_value(color_clear_value)
+            .build()];
+        // depth is in attachment layout between passes,
+        // stored for the next frame's load and precipitation collisions
+        let depth_attachment = vk::RenderingAttachmentInfo::builder()
+            .image_view(self.swapchain_depth_image_view)
+            .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .load_op(clear_config.depth_load_op.to_vk())
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .clear_value(depth_clear_value);
+        // the same view again, the stencil is cleared every frame
+        let stencil_attachment = vk::RenderingAttachmentInfo::builder()
+            .image_view(self.swapchain_depth_image_view)
+            .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .clear_value(depth_clear_value);
+
+        let mut rendering_info = vk::RenderingInfo::builder()
+            .flags(match contents {
+                vk::SubpassContents::SECONDARY_COMMAND_BUFFERS => vk::RenderingFlags::CONTENTS_SECONDARY_COMMAND_BUFFERS,
+                _ => vk::RenderingFlags::empty(),
+            })
+            .render_area(render_area)
+            .layer_count(1)
+            .color_attachments(&color_attachments)
+            .depth_attachment(&depth_attachment);
+        if image::has_stencil_component(self.swapchain_depth_format) {
+            rendering_info = rendering_info.stencil_attachment(&stencil_attachment);
+        }
+
+        match &self.dynamic_rendering_khr {
+            Some(dynamic_rendering_khr) => dynamic_rendering_khr.cmd_begin_rendering(command_buffer, &rendering_info),
+            None => self.device.cmd_begin_rendering(command_buffer, &rendering_info),
+        }
+    }
+
+    /// blackens what is outside the letterboxed `viewport`, whatever the clear color,
+    /// record in the subpass drawing the scene color
+    unsafe fn cmd_clear_bars(&self, command_buffer: vk::CommandBuffer, scene_extent: vk::Extent2D, viewport: vk::Rect2D) {
+        let attachments = [vk::ClearAttachment {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            color_attachment: 0,
+            clear_value: vk::ClearValue {
+                color: vk::ClearColorValue { float32: [0.0, 0.0, 0.0, 1.0] },
+            },
+        }];
+        let rects = letterbox::bars(scene_extent, viewport).map(|rect| vk::ClearRect {
+            rect,
+            base_array_layer: 0,
+            layer_count: 1,
+        });
+        // clearing nothing is invalid
+        let rects: Vec<_> = rects.into_iter()
+            .filter(|rect| rect.rect.extent.width > 0 && rect.rect.extent.height > 0)
+            .collect();
+        if !rects.is_empty() {
+            self.device.cmd_clear_attachments(command_buffer, &attachments, &rects);
+        }
+    }
+
+    /// ends the scene's main secondary command buffer and executes it after the batches
+    unsafe fn cmd_execute_scene_commands(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        batch_command_buffers: &[vk::CommandBuffer],
+        scene_command_buffer: vk::CommandBuffer,
+    ) {
+        self.device.end_command_buffer(scene_command_buffer).expect("Failed to end recording secondary command buffer");
+        if !batch_command_buffers.is_empty() {
+            self.device.cmd_execute_commands(command_buffer, batch_command_buffers);
+        }
+        self.device.cmd_execute_commands(command_buffer, &[scene_command_buffer]);
+    }
+
+    unsafe fn cmd_end_rendering(&self, command_buffer: vk::CommandBuffer, image_index: usize) {
+        match &self.dynamic_rendering_khr {
+            Some(dynamic_rendering_khr) => dynamic_rendering_khr.cmd_end_rendering(command_buffer),
+            None => self.device.cmd_end_rendering(command_buffer),
+        }
+
+        // the upscaling blit presents scaled scenes
+        if self.scene_target.is_some() {
+            return;
+        }
+        image::cmd_transition_image_layout(
+            &self.sync,
+            command_buffer,
+            self.graphics_family_index,
+            &image::ImageTransition {
+                image: self.swapchain_images[image_index],
+                format: self.swapchain_image_format,
+                mip_levels: 1,
+                old_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                new_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            },
+        );
+    }
+
+    /// picks the draw under the cursor in the next drawn frame, see `take_pick` for the result.
+    /// Only draws submitted with a pick id report one
+    pub fn pick_at_cursor(&mut self) {
+        match self.input_state.cursor_pos {
+            Some(cursor) => self.picking.request(cursor),
+            None => log::debug!("Cursor outside the window, nothing to pick"),
+        }
+    }
+
+    /// The pick requested during the previous frame's update, from the frame after the request on
+    /// until taken, `None` before. Waits for the frame holding the pick if it's still in flight
+    pub fn take_pick(&mut self) -> Option<picking::Pick> {
+        for frame in 0..MAX_FRAMES_IN_FLIGHT {
+            if self.picking.is_in_flight(frame) {
+                self.wait_for_fences(&[self.in_flight_fences[frame]]);
+                self.picking.resolve(frame);
+            }
+        }
+        self.picking.take_result()
+    }
+
+    /// the image screenshots copy from, its layout between frames and its extent.
+    /// The presented image when the surface lets it be a transfer source, else the scene target
+    /// at the scene's resolution if the scene is scaled
+    fn screenshot_source(&self) -> Result<(vk::Image, vk::ImageLayout, vk::Extent2D), String> {
+        let image_index = self.presented_image_index.ok_or("No frame has been presented yet")?;
+        if self.swapchain_image_usage.contains(vk::ImageUsageFlags::TRANSFER_SRC) {
+            return Ok((
+                self.swapchain_images[image_index as usize],
+                vk::ImageLayout::PRESENT_SRC_KHR,
+                self.swapchain_extent,
+            ));
+        }
+        match &self.scene_target {
+            Some(scene_target) => Ok((scene_target.image, self.scene_color_final_layout(), self.get_scene_extent())),
+            None => Err("Swapchain images can't be transfer sources on this surface, \
+                a render scale below 1 screenshots the scene target instead".to_owned()),
+        }
+    }
+
+    /// reads back the last presented frame as tightly packed rgba8, hdr frames tone mapped,
+    /// waits for the device to go idle. Errors when the swapchain format can't be converted or there is no image to copy from
+    pub fn screenshot(&mut self) -> Result<(u32, u32, Vec<u8>), String> {
+        let texel_size = swapchain::screenshot_texel_size(self.swapchain_image_format)
+            .ok_or_else(|| format!("Screenshot of swapchain format {:?} not supported", self.swapchain_image_format))?;
+        let (image, layout, vk::Extent2D { width, height }) = self.screenshot_source()?;
+
+        let size = (width * height * texel_size) as vk::DeviceSize;
+        let mut readback_buffer = buffer::Buffer::new(
+            size,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            self.device.clone(),
+            &self.physical_device_memory_properties,
+        );
+
+        unsafe { self.device.device_wait_idle().unwrap(); }
+
+        Self::execute_transient_commands(
+            &self.device,
+            self.transient_command_pool,
+            self.graphics_queue,
+            |transfer_command_buffer| {
+                image::cmd_transition_image_layout(
+                    &self.sync,
+                    transfer_command_buffer,
+                    self.graphics_family_index,
+                    &image::ImageTransition {
+                        image,
+                        format: self.swapchain_image_format,
+                        mip_levels: 1,
+                        old_layout: layout,
+                        new_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    },
+                );
+
+                image::cmd_copy_image_to_buffer(
+                    &self.device,
+                    transfer_command_buffer,
+                    image,
+                    readback_buffer.handle,
+                    width,
+                    height,
+                    0,
+                );
+
+                image::cmd_transition_image_layout(
+                    &self.sync,
+                    transfer_command_buffer,
+                    self.graphics_family_index,
+                    &image::ImageTransition {
+                        image,
+                        format: self.swapchain_image_format,
+                        mip_levels: 1,
+                        old_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        new_layout: layout,
+                    },
+                );
+            }
+        );
+
+        let texels = readback_buffer.copy_to_vec::<u8>(size as usize, 0);
         unsafe {
-            self.device.wait_for_fences(fences, true, u64::MAX).unwrap();
-            self.device.reset_fences(fences).unwrap();
+            readback_buffer.destroy();
+        }
+
+        let pixels = swapchain::decode_screenshot(self.swapchain_image_format, self.output_transfer(), &texels);
+        Ok((width, height, pixels))
+    }
+
+    fn wait_for_fences(&mut self, fences: &[vk::Fence]) {
+        let result = unsafe { self.device.wait_for_fences(fences, true, u64::MAX) };
+        self.check_device_lost(result);
+    }
+
+    /// breadcrumb after the pass just recorded, when gpu crash diagnostics are on
+    fn cmd_mark(&mut self, command_buffer: vk::CommandBuffer, name: &'static str) {
+        if let Some(breadcrumbs) = &mut self.breadcrumbs {
+            breadcrumbs.cmd_mark(command_buffer, self.current_frame, name);
+        }
+    }
+
+    /// unwraps `result`, writing the crash log first when the device was lost
+    fn check_device_lost<T>(&self, result: ash::prelude::VkResult<T>) -> T {
+        match result {
+            Err(vk::Result::ERROR_DEVICE_LOST) => {
+                match &self.breadcrumbs {
+                    Some(breadcrumbs) => breadcrumbs.write_crash_log(),
+                    None => log::error!("Device lost, enable gpu_crash_diagnostics for a crash log"),
+                }
+                panic!("Device lost");
+            }
+            result => result.unwrap(),
         }
     }
 
@@ -703,16 +2898,43 @@ impl VkApp {
     }
 
     /// returns wether swapchain is dirty
-    pub fn draw_frame(&mut self) -> bool {
+    pub fn draw_frame(&mut self) {
         log::trace!("Drawing frame...");
 
+        if self.resize_tracker.needs_renewal() {
+            if self.resize_tracker.is_minimized() {
+                return;
+            }
+            self.renew_swapchain();
+        }
         let image_available_semaphore = self.image_available_semaphores[self.current_frame];
         let render_finished_semaphore = self.render_finished_semaphores[self.current_frame];
         let in_flight_fence = self.in_flight_fences[self.current_frame];
 
         let graphics_command_buffer = self.graphics_command_buffers[self.current_frame];
 
-        self.wait_for_and_reset_fences(&[in_flight_fence]);
+        self.wait_for_fences(&[in_flight_fence]);
+        self.texture_assets.collect_retired(|mut texture| unsafe { texture.destroy() });
+        self.render_targets.collect_retired();
+        self.geometry_assets.collect_retired(|geometry| self.geometry_system.destroy_geometry(geometry));
+        self.geometry_system.collect_retired();
+        self.picking.resolve(self.current_frame);
+
+        if let Some(gpu_frame_time_ms) = self.gpu_profiler.read_frame_time(self.current_frame) {
+            if self.auto_quality.update(gpu_frame_time_ms) && self.auto_quality.enabled {
+                self.set_render_scale(self.auto_quality.settings.render_scale);
+            }
+        }
+        if let Some(statistics) = self.pipeline_statistics_profiler.read_frame_statistics(self.current_frame) {
+            log::trace!("{:?}", statistics);
+        }
+        // the previous frame's jobs, including those the main thread ran while waiting
+        self.job_profiler.end_frame();
+        for (name, timing) in self.job_profiler.get_frame_timings() {
+            log::trace!("{} jobs {}: {:?} total, {:?} longest", timing.count, name, timing.total, timing.longest);
+        }
+        // resizes arriving from here on wait for the next frame
+        let frame_extent = self.swapchain_extent;
 
         let image_index = unsafe {
             match self.swapchain.acquire_next_image(
@@ -722,27 +2944,93 @@ impl VkApp {
                 vk::Fence::null(),
             ) {
                 Ok((image_index, _)) => image_index,
-                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => return true,
+                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                    self.resize_tracker.mark_out_of_date();
+                    return;
+                }
                 Err(err) => panic!("Error acquiring image: {}", err),
             }
         };
 
+        // only reset once work is guaranteed to be submitted,
+        // bailing out above with an unsignaled fence would deadlock the next frame
+        unsafe { self.device.reset_fences(&[in_flight_fence]).unwrap(); }
         self.reset_command_buffer(graphics_command_buffer);
 
+        self.frame_arena.reset();
+        if !self.descriptor_write_batcher.is_empty() {
+            // sets are shared between frames in flight, the other frames may still have them bound.
+            // Their fences are signaled or about to be by submitted work, ours was reset above
+            // and signals with this frame's submission, so it's left out
+            let other_fences = (0..MAX_FRAMES_IN_FLIGHT)
+                .filter(|&frame| frame != self.current_frame)
+                .map(|frame| self.in_flight_fences[frame])
+                .collect::<Vec<_>>();
+            self.wait_for_fences(&other_fences);
+            self.descriptor_write_batcher.flush(&self.device, &self.frame_arena);
+        }
+        // before anything resolves material push constants
+        self.material_system.build(self.current_frame);
+        self.minimap.build(&self.camera);
+        self.render_targets.build();
+        self.reflection_probes.build(self.clear_config.clear_color);
         self.update_uniform_buffer();
+        self.draw_batcher.build(self.current_frame, &mut self.geometry_system);
+        self.gpu_culling.build(
+            self.current_frame,
+            &self.camera.calc_proj_view(),
+            &self.draw_batcher,
+            &self.geometry_system,
+        );
+        self.picking.build(
+            self.current_frame,
+            &self.draw_batcher,
+            letterbox::letterbox(frame_extent, self.fixed_aspect_ratio),
+            letterbox::letterbox(self.get_scene_extent(), self.fixed_aspect_ratio).extent,
+        );
+        self.motion_blur.build(self.current_frame, &self.draw_batcher);
+        self.lod_stats = std::mem::take(&mut self.frame_lod_stats);
+        log::trace!("Levels of detail: {:?}", self.lod_stats);
+        self.skinning_system.build(self.current_frame);
+        self.billboard_renderer.build(self.current_frame);
+        self.sprite_renderer.build(
+            self.current_frame,
+            letterbox::letterbox(frame_extent, self.fixed_aspect_ratio).extent,
+            self.display.scale_factor as f32,
+        );
+        self.debug_line_renderer.build(self.current_frame);
+        self.immediate_renderer.build(self.current_frame);
+        self.outline_renderer.build(self.current_frame);
+        self.gpu_particle_system.build(self.current_frame, &self.frame_arena);
+        self.terrain_renderer.build(
+            self.camera.translation,
+            &Frustum::from_proj_view(&self.camera.calc_proj_view()),
+        );
+        self.precipitation_system.build(
+            &self.weather,
+            self.start_instant.elapsed().as_secs_f32(),
+            self.camera.translation,
+            self.camera.calc_proj_view(),
+        );
+
+        //compute
+        let mut wait_semaphores = vec![(image_available_semaphore, vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)];
+        if let (Some(async_compute), true) = (&self.async_compute, self.use_async_compute) {
+            let compute_command_buffer = async_compute.begin(self.current_frame);
+            self.gpu_particle_system.cmd_dispatch(compute_command_buffer, true);
+            wait_semaphores.push((async_compute.submit(&self.sync, self.current_frame), async_compute::WAIT_STAGE));
+        }
 
         //render
-        self.record_graphics_command_buffer(graphics_command_buffer, image_index as usize);
+        self.record_graphics_command_buffer(graphics_command_buffer, image_index as usize, frame_extent);
         {
-            let render_info = vk::SubmitInfo::builder()
-                .command_buffers(&[graphics_command_buffer])
-                .wait_dst_stage_mask(&[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT])
-                .wait_semaphores(&[image_available_semaphore])
-                .signal_semaphores(&[render_finished_semaphore])
-                .build();
-            let render_infos = [render_info];
-
-            unsafe { self.device.queue_submit(self.graphics_queue, &render_infos, in_flight_fence).unwrap(); }
+            let render_submit = synchronization::Submit {
+                wait_semaphores: &wait_semaphores,
+                command_buffers: &[graphics_command_buffer],
+                signal_semaphores: &[render_finished_semaphore],
+            };
+            let result = self.sync.queue_submit(self.graphics_queue, &[render_submit], in_flight_fence);
+            self.check_device_lost(result);
         }
 
         //present
@@ -754,15 +3042,16 @@ impl VkApp {
                 .build();
             unsafe {
                 match self.swapchain.queue_present(self.present_queue, &present_info) {
-                    Ok(true) | Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => return true,
+                    Ok(true) | Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => self.resize_tracker.mark_out_of_date(),
+                    Err(vk::Result::ERROR_DEVICE_LOST) => self.check_device_lost(Err(vk::Result::ERROR_DEVICE_LOST)),
                     Err(err) => panic!("Error presenting: {}", err),
                     _ => {},
                 }
             }
         }
 
+        self.presented_image_index = Some(image_index);
         self.current_frame = (self.current_frame + 1) % MAX_FRAMES_IN_FLIGHT;
-        false
     }
 }
 
@@ -770,23 +3059,59 @@ impl Drop for VkApp {
     fn drop(&mut self) {
         log::debug!("Dropping application...");
 
-        self.cleanup_swapchain();
+        if !self.swapchain_released {
+            self.cleanup_swapchain();
+        }
 
         unsafe {
+            // whatever is still referenced, e.g. by an app that didn't release it
+            self.geometry_assets.destroy_all(|geometry| self.geometry_system.destroy_geometry(geometry));
             self.geometry_system.destroy_resources();
+            self.material_system.destroy();
+            self.draw_batcher.destroy();
+            self.gpu_culling.destroy();
+            self.parallel_recorder.destroy();
+            self.skinning_system.destroy();
+            self.precipitation_system.destroy();
+            self.hiz.destroy();
+            self.ssr.destroy();
+            self.motion_blur.destroy();
+            self.edge_outlines.destroy();
+            self.billboard_renderer.destroy();
+            self.sprite_renderer.destroy();
+            self.debug_line_renderer.destroy();
+            self.immediate_renderer.destroy();
+            self.outline_renderer.destroy();
+            self.debug_view_renderer.destroy();
+            self.gpu_particle_system.destroy();
+            if let Some(async_compute) = &mut self.async_compute {
+                async_compute.destroy();
+            }
+            self.minimap.destroy();
+            self.render_targets.destroy();
+            self.reflection_probes.destroy();
+            self.picking.destroy();
+            self.terrain_renderer.destroy();
+            self.gpu_profiler.destroy();
+            if let Some(breadcrumbs) = &mut self.breadcrumbs {
+                breadcrumbs.destroy();
+            }
+            self.pipeline_statistics_profiler.destroy();
 
-            self.per_frame_uniform_buffer.destroy();
+            self.uniform_ring.destroy();
             self.device.destroy_descriptor_set_layout(self.per_frame_ubo_set_layout, None);
 
-            // for texture in self.textures.iter_mut() {
-            //     texture.destroy();
-            // }
+            self.texture_assets.destroy_all(|mut texture| texture.destroy());
+            self.sampler_cache.destroy();
+            self.device.destroy_descriptor_update_template(self.textures_update_template, None);
             self.device.destroy_descriptor_set_layout(self.textures_set_layout, None);
 
             self.device.destroy_descriptor_pool(self.descriptor_pool, None);
 
-            self.device.destroy_pipeline(self.pipeline, None);
-            self.device.destroy_pipeline_layout(self.pipeline_layout, None);
+            self.destroy_render_pass_and_pipelines();
+            self.device.destroy_descriptor_set_layout(self.gbuffer_set_layout, None);
+            self.environment.destroy();
+            self.device.destroy_descriptor_set_layout(self.ibl_set_layout, None);
 
             for frame in 0..MAX_FRAMES_IN_FLIGHT {
                 self.device.destroy_semaphore(self.image_available_semaphores[frame], None);
@@ -797,8 +3122,6 @@ impl Drop for VkApp {
             self.device.destroy_command_pool(self.graphics_command_pool, None);
             self.device.destroy_command_pool(self.transient_command_pool, None);
 
-            self.device.destroy_render_pass(self.render_pass, None);
-
             self.device.destroy_device(None);
 
             self.surface.destroy_surface(self.surface_khr, None);
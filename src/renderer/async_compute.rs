@@ -0,0 +1,112 @@
+// Compute work on a queue of its own family, for devices with one besides the graphics family.
+// Each frame records its compute passes into its own command buffer, submitted before the frame's
+// graphics work so it overlaps the previous frame still drawing. The graphics submission waits on
+// the frame's semaphore where it first reads the results:
+//
+//     let command_buffer = async_compute.begin(frame);
+//     gpu_particle_system.cmd_dispatch(command_buffer, true);
+//     let semaphore = async_compute.submit(&sync, frame);
+//     // wait on `semaphore` at `async_compute::WAIT_STAGE` in the graphics submission
+//
+// Resources both queues use are created with `Buffer::new_shared` over `get_queue_family_indices`
+
+use std::rc::Rc;
+
+use ash::vk;
+
+use super::{
+    synchronization::{Submit, Synchronization},
+    MAX_FRAMES_IN_FLIGHT,
+};
+
+/// where the graphics queue waits for the compute results, particles are drawn indirectly as billboards
+pub const WAIT_STAGE: vk::PipelineStageFlags2 = vk::PipelineStageFlags2::from_raw(
+    vk::PipelineStageFlags2::DRAW_INDIRECT.as_raw() | vk::PipelineStageFlags2::VERTEX_INPUT.as_raw(),
+);
+
+pub struct AsyncCompute {
+    device: Rc<ash::Device>,
+    queue: vk::Queue,
+    family_index: u32,
+    graphics_family_index: u32,
+    command_pool: vk::CommandPool,
+    /// one per frame in flight
+    command_buffers: Vec<vk::CommandBuffer>,
+    /// signaled when the frame's compute work finishes
+    finished_semaphores: Vec<vk::Semaphore>,
+}
+
+impl AsyncCompute {
+    pub fn new(device: Rc<ash::Device>, queue: vk::Queue, family_index: u32, graphics_family_index: u32) -> Self {
+        let command_pool = unsafe {
+            let info = vk::CommandPoolCreateInfo::builder()
+                .queue_family_index(family_index)
+                .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER);
+            device.create_command_pool(&info, None).expect("Failed to create command pool")
+        };
+        let command_buffers = unsafe {
+            let info = vk::CommandBufferAllocateInfo::builder()
+                .command_pool(command_pool)
+                .level(vk::CommandBufferLevel::PRIMARY)
+                .command_buffer_count(MAX_FRAMES_IN_FLIGHT as u32);
+            device.allocate_command_buffers(&info).unwrap()
+        };
+        let finished_semaphores = (0..MAX_FRAMES_IN_FLIGHT)
+            .map(|_| unsafe { device.create_semaphore(&vk::SemaphoreCreateInfo::builder(), None).unwrap() })
+            .collect();
+
+        Self {
+            device,
+            queue,
+            family_index,
+            graphics_family_index,
+            command_pool,
+            command_buffers,
+            finished_semaphores,
+        }
+    }
+
+    /// of the graphics and compute queues, for resources both use
+    pub fn get_queue_family_indices(&self) -> [u32; 2] {
+        [self.graphics_family_index, self.family_index]
+    }
+
+    /// `frame`'s command buffer ready for recording, the frame's fence must have been waited on
+    pub fn begin(&self, frame: usize) -> vk::CommandBuffer {
+        let command_buffer = self.command_buffers[frame];
+        unsafe {
+            self.device
+                .reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())
+                .unwrap();
+            let begin_info = vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            self.device
+                .begin_command_buffer(command_buffer, &begin_info)
+                .expect("Failed to begin recording command buffer");
+        }
+        command_buffer
+    }
+
+    /// ends and submits `frame`'s command buffer, returns the semaphore the graphics submission waits on
+    pub fn submit(&self, sync: &Synchronization, frame: usize) -> vk::Semaphore {
+        let command_buffer = self.command_buffers[frame];
+        let semaphore = self.finished_semaphores[frame];
+        unsafe { self.device.end_command_buffer(command_buffer).unwrap() };
+        let submit = Submit {
+            wait_semaphores: &[],
+            command_buffers: &[command_buffer],
+            signal_semaphores: &[semaphore],
+        };
+        // the graphics submission's fence covers this too, as it waits on the semaphore
+        sync.queue_submit(self.queue, &[submit], vk::Fence::null()).unwrap();
+        semaphore
+    }
+
+    // caller must ensure only called once
+    pub unsafe fn destroy(&mut self) {
+        for &semaphore in &self.finished_semaphores {
+            self.device.destroy_semaphore(semaphore, None);
+        }
+        self.device.destroy_command_pool(self.command_pool, None);
+    }
+}
@@ -0,0 +1,181 @@
+// Packs many small images, like sprites, glyphs and icons, into one texture so they take
+// a single slot of the textures descriptor array. Images are packed on shelves by decreasing height
+// into the smallest power of two sized atlas they fit in:
+//
+//     let mut builder = TextureAtlasBuilder::new(2048);
+//     let coin = builder.add_file("textures/coin.png").unwrap();
+//     let atlas = builder.build().unwrap();
+//     let uv_rect = atlas.uv_rects[coin];
+//     let texture = app.load_atlas("atlas:items", atlas);
+
+use super::texture::{DecodedTexture, TextureType};
+
+/// rgba8
+struct AtlasImage {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+/// top left corners in a `width` by `height` area for rectangles of `sizes`,
+/// `padding` pixels apart, None when they don't fit
+pub fn pack_shelves(sizes: &[[u32; 2]], width: u32, height: u32, padding: u32) -> Option<Vec<[u32; 2]>> {
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&index| std::cmp::Reverse((sizes[index][1], sizes[index][0])));
+
+    let mut positions = vec![[0; 2]; sizes.len()];
+    let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+    for index in order {
+        let [rect_width, rect_height] = sizes[index];
+        if x + rect_width > width {
+            // next shelf
+            y += shelf_height;
+            x = 0;
+            shelf_height = 0;
+        }
+        if x + rect_width > width || y + rect_height > height {
+            return None;
+        }
+
+        positions[index] = [x, y];
+        x += rect_width + padding;
+        shelf_height = shelf_height.max(rect_height + padding);
+    }
+    Some(positions)
+}
+
+/// Collects images and packs them into a `TextureAtlas`
+pub struct TextureAtlasBuilder {
+    images: Vec<AtlasImage>,
+    /// largest width and height the atlas may grow to
+    max_size: u32,
+    /// transparent pixels between images, so filtering doesn't bleed neighbours in
+    pub padding: u32,
+}
+
+impl TextureAtlasBuilder {
+    pub fn new(max_size: u32) -> Self {
+        Self {
+            images: vec![],
+            max_size,
+            padding: 1,
+        }
+    }
+
+    /// `pixels` are rgba8, returns the image's index into the atlas' `uv_rects`
+    pub fn add_pixels(&mut self, width: u32, height: u32, pixels: Vec<u8>) -> usize {
+        assert!(pixels.len() == (width * height * 4) as usize, "Atlas image pixels don't match its size");
+        self.images.push(AtlasImage { width, height, pixels });
+        self.images.len() - 1
+    }
+
+    /// None when the file can't be decoded
+    pub fn add_file(&mut self, path: &str) -> Option<usize> {
+        let image = match image::open(path) {
+            Ok(image) => image.to_rgba(),
+            Err(err) => {
+                log::warn!("Failed to decode atlas image {}: {}", path, err);
+                return None;
+            }
+        };
+        let (width, height) = (image.width(), image.height());
+        Some(self.add_pixels(width, height, image.into_raw()))
+    }
+
+    /// None when the images don't fit into `max_size` by `max_size` pixels
+    pub fn build(&self) -> Option<TextureAtlas> {
+        let sizes: Vec<[u32; 2]> = self.images.iter().map(|image| [image.width, image.height]).collect();
+        let area: u32 = sizes.iter().map(|[width, height]| (width + self.padding) * (height + self.padding)).sum();
+        let widest = sizes.iter().map(|size| size[0]).max().unwrap_or(1);
+        let tallest = sizes.iter().map(|size| size[1]).max().unwrap_or(1);
+
+        // grows width and height in turns from the smallest square that could hold everything
+        let mut width = ((area as f32).sqrt() as u32).max(widest).max(1).next_power_of_two();
+        let mut height = width.max(tallest.next_power_of_two());
+        let positions = loop {
+            if width > self.max_size || height > self.max_size {
+                log::warn!("{} images don't fit into a {}x{} atlas", self.images.len(), self.max_size, self.max_size);
+                return None;
+            }
+            if let Some(positions) = pack_shelves(&sizes, width, height, self.padding) {
+                break positions;
+            }
+            if width <= height {
+                width *= 2;
+            } else {
+                height *= 2;
+            }
+        };
+
+        let mut pixels = vec![0; (width * height * 4) as usize];
+        let mut uv_rects = Vec::with_capacity(self.images.len());
+        for (image, [x, y]) in self.images.iter().zip(positions) {
+            let row_size = (image.width * 4) as usize;
+            for row in 0..image.height {
+                let src = (row * image.width * 4) as usize;
+                let dst = (((y + row) * width + x) * 4) as usize;
+                pixels[dst..dst + row_size].copy_from_slice(&image.pixels[src..src + row_size]);
+            }
+            uv_rects.push([
+                x as f32 / width as f32,
+                y as f32 / height as f32,
+                (x + image.width) as f32 / width as f32,
+                (y + image.height) as f32 / height as f32,
+            ]);
+        }
+
+        Some(TextureAtlas { width, height, pixels, uv_rects })
+    }
+}
+
+pub struct TextureAtlas {
+    pub width: u32,
+    pub height: u32,
+    /// rgba8
+    pixels: Vec<u8>,
+    /// min u, min v, max u, max v of every added image, in adding order, like a sprite's `uv_rect`
+    pub uv_rects: Vec<[f32; 4]>,
+}
+
+impl TextureAtlas {
+    /// without mips, they would blend neighbouring images
+    pub fn into_decoded(self) -> DecodedTexture {
+        DecodedTexture::from_pixels(TextureType::Diffuse, self.width, self.height, self.pixels)
+    }
+}
+
+#[test]
+fn test_texture_atlas() {
+    assert!(pack_shelves(&[[4, 4], [2, 2], [2, 8]], 8, 8, 0) == Some(vec![[2, 0], [6, 0], [0, 0]]));
+    assert!(pack_shelves(&[[4, 4], [4, 4], [4, 4]], 8, 4, 0).is_none());
+    assert!(pack_shelves(&[[9, 1]], 8, 8, 0).is_none());
+
+    let mut builder = TextureAtlasBuilder::new(64);
+    let red = builder.add_pixels(16, 16, [255, 0, 0, 255].repeat(16 * 16));
+    let green = builder.add_pixels(16, 8, [0, 255, 0, 255].repeat(16 * 8));
+    let blue = builder.add_pixels(8, 8, [0, 0, 255, 255].repeat(8 * 8));
+    let atlas = builder.build().unwrap();
+    assert!(atlas.width == 32 && atlas.height == 32);
+
+    // every image's pixels end up under its uv rect, padded apart
+    for (index, color) in [(red, [255, 0, 0, 255]), (green, [0, 255, 0, 255]), (blue, [0, 0, 255, 255])] {
+        let [min_u, min_v, max_u, max_v] = atlas.uv_rects[index];
+        let (x0, y0) = ((min_u * atlas.width as f32) as u32, (min_v * atlas.height as f32) as u32);
+        let (x1, y1) = ((max_u * atlas.width as f32) as u32, (max_v * atlas.height as f32) as u32);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let offset = ((y * atlas.width + x) * 4) as usize;
+                assert!(atlas.pixels[offset..offset + 4] == color);
+            }
+        }
+        if x1 < atlas.width {
+            let offset = ((y0 * atlas.width + x1) * 4) as usize;
+            assert!(atlas.pixels[offset..offset + 4] == [0; 4]);
+        }
+    }
+
+    let mut builder = TextureAtlasBuilder::new(16);
+    builder.add_pixels(16, 16, vec![0; 16 * 16 * 4]);
+    builder.add_pixels(1, 1, vec![0; 4]);
+    assert!(builder.build().is_none());
+}
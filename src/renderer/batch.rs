@@ -0,0 +1,478 @@
+// Draw batching, draws submitted during a frame are sorted and grouped by what they bind
+// and drawn with one instanced draw per group. With indirect drawing, batches sharing
+// a pipeline and material are further drawn with one indirect call.
+// See draw_list.rs for the sort order
+
+use std::{mem::size_of, rc::Rc};
+
+use ash::vk;
+
+use crate::{geometry::{GeometryId, GeometrySystem}, math::ModelMat};
+use super::{
+    buffer::Buffer,
+    draw_list::{DrawList, StateTracker},
+    material::{MaterialId, MaterialSystem},
+    parallel_record::BatchDraw,
+    MAX_FRAMES_IN_FLIGHT,
+};
+
+/// per frame in flight
+pub const MAX_INSTANCE_COUNT: usize = 0x4000;
+
+/// which of the scene's pipelines a draw binds. Pipelines are recreated whenever the render pass
+/// or shaders change, possibly between a draw's submission and recording, so draws keep this
+/// and it's resolved to the current handle when recording, see `DrawBatcher::set_pipelines`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PipelineId {
+    Unlit,
+    Pbr,
+    /// marks the stencil outlines are drawn around
+    OutlineStencil,
+}
+
+pub const PIPELINE_ID_COUNT: usize = 3;
+
+/// what a draw binds, pipeline switches are the rarest in sorted draws
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DrawKey {
+    pub pipeline: PipelineId,
+    pub material: MaterialId,
+    pub geometry: GeometryId,
+}
+
+/// the frame's batched draws and the sets they're drawn with, for passes drawing them again from another view
+#[derive(Clone, Copy)]
+pub struct SceneDraws<'a> {
+    pub draw_batcher: &'a DrawBatcher,
+    pub geometry_system: &'a GeometrySystem,
+    pub material_system: &'a MaterialSystem,
+    pub per_frame_ubo_set: vk::DescriptorSet,
+    pub textures_set: vk::DescriptorSet,
+}
+
+/// one instanced draw, instances are a contiguous range of the frame's instance data
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Batch {
+    pub key: DrawKey,
+    pub first_instance: u32,
+    pub instance_count: u32,
+}
+
+/// consecutive batches sharing a pipeline and material, one indirect call draws them all
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Bucket {
+    pub pipeline: PipelineId,
+    pub material: MaterialId,
+    pub first_batch: u32,
+    pub batch_count: u32,
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct BatchStats {
+    pub submitted_draws: usize,
+    /// instanced draws, each is a draw record when drawing indirectly
+    pub draw_calls: usize,
+    /// 0 when not drawing indirectly
+    pub indirect_draw_calls: usize,
+    pub pipeline_binds: usize,
+    pub material_binds: usize,
+}
+
+impl BatchStats {
+    pub fn saved_draw_calls(&self) -> usize {
+        self.submitted_draws - self.draw_calls
+    }
+
+    /// compared to binding the pipeline and material for every submitted draw
+    pub fn saved_binds(&self) -> usize {
+        2 * self.submitted_draws - self.pipeline_binds - self.material_binds
+    }
+}
+
+/// Sorts `draws` and groups equal keys,
+/// `instances` receives the instance data in batch order
+pub fn build_batches<T: Copy>(
+    draws: &mut DrawList<T>,
+    batches: &mut Vec<Batch>,
+    instances: &mut Vec<T>,
+) {
+    // stable so instances of a batch keep their submission order
+    draws.sort();
+
+    batches.clear();
+    instances.clear();
+    for &(key, instance) in draws.iter() {
+        match batches.last_mut() {
+            Some(batch) if batch.key == key => batch.instance_count += 1,
+            _ => batches.push(Batch {
+                key,
+                first_instance: instances.len() as u32,
+                instance_count: 1,
+            }),
+        }
+        instances.push(instance);
+    }
+}
+
+/// drops the instances from `instance_count` on, the batches past it go and the one across it is cut short
+pub fn truncate_batches(batches: &mut Vec<Batch>, instance_count: u32) {
+    batches.retain(|batch| batch.first_instance < instance_count);
+    if let Some(batch) = batches.last_mut() {
+        batch.instance_count = batch.instance_count.min(instance_count - batch.first_instance);
+    }
+}
+
+/// `batches` as sorted by `build_batches`
+pub fn build_buckets(batches: &[Batch], buckets: &mut Vec<Bucket>) {
+    buckets.clear();
+    for (index, batch) in batches.iter().enumerate() {
+        match buckets.last_mut() {
+            Some(bucket) if bucket.pipeline == batch.key.pipeline && bucket.material == batch.key.material => {
+                bucket.batch_count += 1;
+            }
+            _ => buckets.push(Bucket {
+                pipeline: batch.key.pipeline,
+                material: batch.key.material,
+                first_batch: index as u32,
+                batch_count: 1,
+            }),
+        }
+    }
+}
+
+/// Collects draws for the current frame, call `build` once all are submitted,
+/// `cmd_upload_indirect_commands` before any render pass and `cmd_draw_batches` while recording
+pub struct DrawBatcher {
+    device: Rc<ash::Device>,
+    /// draw batches through indirect draw records in the geometry system,
+    /// needs the drawIndirectFirstInstance feature
+    pub indirect: bool,
+    /// transforms along with their pick ids
+    draws: DrawList<(ModelMat, Option<u32>)>,
+    batches: Vec<Batch>,
+    buckets: Vec<Bucket>,
+    indirect_commands: Vec<vk::DrawIndexedIndirectCommand>,
+    sorted_draws: Vec<(ModelMat, Option<u32>)>,
+    instances: Vec<ModelMat>,
+    /// the pick id of each instance of the last built frame
+    instance_pick_ids: Vec<Option<u32>>,
+    /// host visible, one region per frame in flight
+    instance_buffer: Buffer,
+    /// current handle of each `PipelineId`
+    pipelines: [vk::Pipeline; PIPELINE_ID_COUNT],
+    /// of the last built frame
+    pub stats: BatchStats,
+}
+
+impl DrawBatcher {
+    pub fn new(
+        device: Rc<ash::Device>,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    ) -> Self {
+        Self {
+            instance_buffer: Buffer::new(
+                (MAX_FRAMES_IN_FLIGHT * MAX_INSTANCE_COUNT * size_of::<ModelMat>()) as vk::DeviceSize,
+                // read by gpu culling
+                vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                device.clone(),
+                physical_device_memory_properties,
+            ),
+            device,
+            indirect: false,
+            draws: DrawList::default(),
+            batches: vec![],
+            buckets: vec![],
+            indirect_commands: vec![],
+            sorted_draws: vec![],
+            instances: vec![],
+            instance_pick_ids: vec![],
+            pipelines: [vk::Pipeline::null(); PIPELINE_ID_COUNT],
+            stats: Default::default(),
+        }
+    }
+
+    /// the handles draws' pipeline ids resolve to, indexed by `PipelineId`, set before recording
+    pub fn set_pipelines(&mut self, pipelines: [vk::Pipeline; PIPELINE_ID_COUNT]) {
+        self.pipelines = pipelines;
+    }
+
+    pub fn get_pipeline(&self, id: PipelineId) -> vk::Pipeline {
+        self.pipelines[id as usize]
+    }
+
+    /// `pick_id` is what picking reports for the draw's pixels, `None` if it can't be picked
+    pub fn submit(&mut self, key: DrawKey, transform: ModelMat, pick_id: Option<u32>) {
+        self.draws.submit(key, (transform, pick_id));
+    }
+
+    fn frame_offset(frame: usize) -> vk::DeviceSize {
+        (frame * MAX_INSTANCE_COUNT * size_of::<ModelMat>()) as vk::DeviceSize
+    }
+
+    /// batches the submitted draws into `frame`'s instance region and clears them,
+    /// the frame's previous commands must have finished executing
+    pub fn build(&mut self, frame: usize, geometry_system: &mut GeometrySystem) {
+        build_batches(&mut self.draws, &mut self.batches, &mut self.sorted_draws);
+        self.instances.clear();
+        self.instances.extend(self.sorted_draws.iter().map(|&(transform, _)| transform));
+        self.instance_pick_ids.clear();
+        self.instance_pick_ids.extend(self.sorted_draws.iter().map(|&(_, pick_id)| pick_id));
+        if self.instances.len() > MAX_INSTANCE_COUNT {
+            log::warn!("Dropping {} draws over the instance limit", self.instances.len() - MAX_INSTANCE_COUNT);
+            truncate_batches(&mut self.batches, MAX_INSTANCE_COUNT as u32);
+            self.instances.truncate(MAX_INSTANCE_COUNT);
+            self.instance_pick_ids.truncate(MAX_INSTANCE_COUNT);
+        }
+        self.instance_buffer.copy_from_slice(&self.instances, frame * MAX_INSTANCE_COUNT);
+
+        self.buckets.clear();
+        self.indirect_commands.clear();
+        if self.indirect {
+            build_buckets(&self.batches, &mut self.buckets);
+            self.indirect_commands.extend(self.batches.iter().map(|batch| geometry_system.indirect_command(
+                batch.key.geometry,
+                batch.first_instance,
+                batch.instance_count,
+            )));
+            geometry_system.write_indirect_commands(frame, &self.indirect_commands);
+        }
+
+        // binds as `cmd_draw_batches` records them without a pipeline override
+        let mut tracker = StateTracker::default();
+        let mut track = |pipeline, material| {
+            tracker.bind_pipeline(pipeline);
+            tracker.bind_material(material);
+        };
+        if self.indirect {
+            self.buckets.iter().for_each(|bucket| track(bucket.pipeline, bucket.material));
+        } else {
+            self.batches.iter().for_each(|batch| track(batch.key.pipeline, batch.key.material));
+        }
+        self.stats = BatchStats {
+            submitted_draws: self.draws.len(),
+            draw_calls: self.batches.len(),
+            indirect_draw_calls: self.buckets.len(),
+            pipeline_binds: tracker.counters.pipeline_binds,
+            material_binds: tracker.counters.material_binds,
+        };
+        log::trace!("Batched draws: {:?}", self.stats);
+
+        self.draws.clear();
+    }
+
+    /// batches of the last built frame
+    pub fn batch_count(&self) -> usize {
+        self.batches.len()
+    }
+
+    /// of the last built frame, in instance order
+    pub fn get_batches(&self) -> &[Batch] {
+        &self.batches
+    }
+
+    /// of the last built frame, empty when not drawing indirectly
+    pub fn get_buckets(&self) -> &[Bucket] {
+        &self.buckets
+    }
+
+    /// transforms of the last built frame, as in the instance buffer
+    pub fn get_instances(&self) -> &[ModelMat] {
+        &self.instances
+    }
+
+    /// indexed like the instances in the instance buffer
+    pub fn get_instance_pick_ids(&self) -> &[Option<u32>] {
+        &self.instance_pick_ids
+    }
+
+    /// instance buffer and offset of `frame`'s region, as `cmd_draw_batches` binds them
+    pub fn get_instance_binding(&self, frame: usize) -> (vk::Buffer, vk::DeviceSize) {
+        (self.instance_buffer.handle, Self::frame_offset(frame))
+    }
+
+    /// the last built frame's batches resolved for recording off the main thread
+    pub fn resolve_batches(
+        &self,
+        geometry_system: &GeometrySystem,
+        material_system: &MaterialSystem,
+        draws: &mut Vec<BatchDraw>,
+    ) {
+        draws.clear();
+        draws.extend(self.batches.iter().map(|batch| BatchDraw {
+            pipeline: self.get_pipeline(batch.key.pipeline),
+            material: material_system.push_constants(batch.key.material),
+            command: geometry_system.indirect_command(batch.key.geometry, batch.first_instance, batch.instance_count),
+        }));
+    }
+
+    /// record outside of any render pass, before `cmd_draw_batches`
+    pub fn cmd_upload_indirect_commands(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+        geometry_system: &GeometrySystem,
+    ) {
+        geometry_system.cmd_upload_indirect_commands(command_buffer, frame, self.indirect_commands.len());
+    }
+
+    /// batch pipelines must share `pipeline_layout`, `pipeline_override` draws every batch
+    /// with one pipeline instead, e.g. for a secondary view. Geometry resources must already be bound
+    pub fn cmd_draw_batches(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+        pipeline_layout: vk::PipelineLayout,
+        pipeline_override: Option<vk::Pipeline>,
+        geometry_system: &GeometrySystem,
+        material_system: &MaterialSystem,
+    ) {
+        if self.batches.is_empty() {
+            return;
+        }
+
+        unsafe {
+            self.device.cmd_bind_vertex_buffers(
+                command_buffer,
+                super::pipeline::INSTANCE_BINDING,
+                &[self.instance_buffer.handle],
+                &[Self::frame_offset(frame)],
+            );
+        }
+
+        if self.indirect {
+            self.cmd_draw_buckets(command_buffer, frame, pipeline_layout, pipeline_override, geometry_system, material_system);
+            return;
+        }
+
+        let mut tracker = StateTracker::default();
+        for batch in &self.batches {
+            let pipeline = pipeline_override.unwrap_or(self.get_pipeline(batch.key.pipeline));
+            if tracker.bind_pipeline(pipeline) {
+                unsafe {
+                    self.device.cmd_bind_pipeline(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        pipeline,
+                    );
+                }
+            }
+            if tracker.bind_material(batch.key.material) {
+                material_system.cmd_push_material(&self.device, command_buffer, pipeline_layout, batch.key.material);
+            }
+
+            geometry_system.cmd_draw_geometry(
+                command_buffer,
+                batch.key.geometry,
+                batch.first_instance,
+                batch.instance_count,
+            );
+        }
+    }
+
+    fn cmd_draw_buckets(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+        pipeline_layout: vk::PipelineLayout,
+        pipeline_override: Option<vk::Pipeline>,
+        geometry_system: &GeometrySystem,
+        material_system: &MaterialSystem,
+    ) {
+        let mut tracker = StateTracker::default();
+        for bucket in &self.buckets {
+            let pipeline = pipeline_override.unwrap_or(self.get_pipeline(bucket.pipeline));
+            if tracker.bind_pipeline(pipeline) {
+                unsafe {
+                    self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+                }
+            }
+            if tracker.bind_material(bucket.material) {
+                material_system.cmd_push_material(&self.device, command_buffer, pipeline_layout, bucket.material);
+            }
+
+            geometry_system.cmd_draw_geometry_indirect(command_buffer, frame, bucket.first_batch, bucket.batch_count);
+        }
+    }
+
+    // caller must ensure only called once
+    pub unsafe fn destroy(&mut self) {
+        self.instance_buffer.destroy();
+    }
+}
+
+#[test]
+fn test_build_batches() {
+    use crate::data_structures::handle_map;
+
+    let key = |geometry, material| DrawKey {
+        pipeline: PipelineId::Unlit,
+        material: handle_map::Handle::from_raw_parts(material, 0),
+        geometry: handle_map::Handle::from_raw_parts(geometry, 0),
+    };
+
+    let mut draws = DrawList::default();
+    for (key, instance) in [
+        (key(0, 1), 'a'),
+        (key(1, 0), 'b'),
+        (key(0, 1), 'c'),
+        (key(0, 0), 'd'),
+        (key(0, 1), 'e'),
+    ] {
+        draws.submit(key, instance);
+    }
+    let mut batches = vec![];
+    let mut instances = vec![];
+    build_batches(&mut draws, &mut batches, &mut instances);
+
+    assert!(batches == [
+        Batch { key: key(0, 0), first_instance: 0, instance_count: 1 },
+        Batch { key: key(1, 0), first_instance: 1, instance_count: 1 },
+        Batch { key: key(0, 1), first_instance: 2, instance_count: 3 },
+    ]);
+    assert!(instances == ['d', 'b', 'a', 'c', 'e']);
+
+    // over the limit the last batch is cut short and those past it dropped
+    truncate_batches(&mut batches, 3);
+    assert!(batches == [
+        Batch { key: key(0, 0), first_instance: 0, instance_count: 1 },
+        Batch { key: key(1, 0), first_instance: 1, instance_count: 1 },
+        Batch { key: key(0, 1), first_instance: 2, instance_count: 1 },
+    ]);
+    truncate_batches(&mut batches, 1);
+    assert!(batches == [Batch { key: key(0, 0), first_instance: 0, instance_count: 1 }]);
+}
+
+#[test]
+fn test_build_buckets() {
+    use crate::data_structures::handle_map;
+
+    let (opaque, masked) = (PipelineId::Unlit, PipelineId::Pbr);
+    let batch = |pipeline, material, geometry| Batch {
+        key: DrawKey {
+            pipeline,
+            material: handle_map::Handle::from_raw_parts(material, 0),
+            geometry: handle_map::Handle::from_raw_parts(geometry, 0),
+        },
+        first_instance: 0,
+        instance_count: 1,
+    };
+
+    let batches = [
+        batch(opaque, 0, 0),
+        batch(opaque, 0, 1),
+        batch(opaque, 1, 0),
+        batch(masked, 1, 0),
+        batch(masked, 1, 2),
+    ];
+    let mut buckets = vec![];
+    build_buckets(&batches, &mut buckets);
+
+    let bucket = |pipeline, material, first_batch, batch_count| Bucket {
+        pipeline,
+        material: handle_map::Handle::from_raw_parts(material, 0),
+        first_batch,
+        batch_count,
+    };
+    assert!(buckets == [bucket(opaque, 0, 0, 2), bucket(opaque, 1, 2, 1), bucket(masked, 1, 3, 2)]);
+}
@@ -0,0 +1,205 @@
+// Camera facing quads drawn through the instancing path with additive blending,
+// for glowing particles that don't need sorting
+
+use std::{mem::size_of, rc::Rc};
+
+use ash::vk;
+
+use super::{buffer::Buffer, pipeline, MAX_FRAMES_IN_FLIGHT};
+
+/// per frame in flight
+pub const MAX_BILLBOARD_COUNT: usize = 0x4000;
+
+/// instance data, must match the instance attributes of billboard.vert
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Billboard {
+    pub position: [f32; 3],
+    /// world space width and height
+    pub size: f32,
+    /// straight alpha, scales what gets added
+    pub color: [f32; 4],
+}
+
+pub const BILLBOARD_ATTRIBUTES: [pipeline::Attribute; 2] = [
+    pipeline::Attribute::F32x4,
+    pipeline::Attribute::F32x4,
+];
+
+/// Submit during the frame, `build` once the frame's fence is waited on and `cmd_draw`
+/// in the scene pass. The pipeline depends on the scene render pass, `renew_pipeline` when it changes
+pub struct BillboardRenderer {
+    device: Rc<ash::Device>,
+    submitted: Vec<Billboard>,
+    /// of the last built frame
+    billboard_count: u32,
+    /// host visible, one region per frame in flight
+    instance_buffer: Buffer,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl BillboardRenderer {
+    pub fn new(
+        device: Rc<ash::Device>,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    ) -> Self {
+        Self {
+            instance_buffer: Buffer::new(
+                (MAX_FRAMES_IN_FLIGHT * MAX_BILLBOARD_COUNT * size_of::<Billboard>()) as vk::DeviceSize,
+                vk::BufferUsageFlags::VERTEX_BUFFER,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                device.clone(),
+                physical_device_memory_properties,
+            ),
+            device,
+            submitted: vec![],
+            billboard_count: 0,
+            pipeline_layout: vk::PipelineLayout::null(),
+            pipeline: vk::Pipeline::null(),
+        }
+    }
+
+    /// `render_pass` null for dynamic rendering, draws in the translucent subpass with depth attached
+    pub fn renew_pipeline(
+        &mut self,
+        context: &pipeline::PassContext,
+        render_pass: vk::RenderPass,
+    ) {
+        let pipeline::PassContext { shader_compiler, color_format, depth_format, per_frame_ubo_set_layout, output_transfer, reverse_z, .. } = *context;
+        let subpass = context.translucent_subpass();
+        unsafe { self.destroy_pipeline(); }
+
+        (self.pipeline, self.pipeline_layout) = pipeline::new_pipeline_and_layout(
+            &self.device,
+            shader_compiler,
+            &pipeline::PipelineDesc {
+                render_pass,
+                subpass,
+                color_formats: &[color_format],
+                depth_format,
+                set_layouts: &[per_frame_ubo_set_layout],
+                vertex_shader_path: "shaders/billboard.vert",
+                fragment_shader_path: "shaders/billboard.frag",
+                instance_attributes: &BILLBOARD_ATTRIBUTES,
+                blend_mode: pipeline::BlendMode::Additive,
+                cull_mode: vk::CullModeFlags::NONE,
+                depth_write: false,
+                output_transfer,
+                reverse_z,
+                ..Default::default()
+            },
+        );
+    }
+
+    /// drawn next frame, only for that frame
+    pub fn submit<I: IntoIterator<Item = Billboard>>(&mut self, billboards: I) {
+        self.submitted.extend(billboards);
+    }
+
+    fn frame_offset(frame: usize) -> vk::DeviceSize {
+        (frame * MAX_BILLBOARD_COUNT * size_of::<Billboard>()) as vk::DeviceSize
+    }
+
+    /// writes the submitted billboards into `frame`'s region and clears them,
+    /// the frame's previous commands must have finished executing
+    pub fn build(&mut self, frame: usize) {
+        if self.submitted.len() > MAX_BILLBOARD_COUNT {
+            log::warn!("Dropping {} billboards over the limit", self.submitted.len() - MAX_BILLBOARD_COUNT);
+            self.submitted.truncate(MAX_BILLBOARD_COUNT);
+        }
+        self.instance_buffer.copy_from_slice(&self.submitted, frame * MAX_BILLBOARD_COUNT);
+        self.billboard_count = self.submitted.len() as u32;
+        self.submitted.clear();
+    }
+
+    /// record in the scene pass after the opaque geometry, binds its own pipeline
+    pub fn cmd_draw(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+        per_frame_ubo_set: vk::DescriptorSet,
+        per_frame_ubo_offset: u32,
+    ) {
+        if self.billboard_count == 0 {
+            return;
+        }
+
+        self.cmd_bind(
+            command_buffer,
+            self.instance_buffer.handle,
+            Self::frame_offset(frame),
+            per_frame_ubo_set,
+            per_frame_ubo_offset,
+        );
+        unsafe {
+            // quad corners come from the vertex index
+            self.device.cmd_draw(command_buffer, 6, self.billboard_count, 0, 0);
+        }
+    }
+
+    /// draws billboards written on the device, e.g. by a compute shader. `indirect_buffer`
+    /// holds a vk::DrawIndirectCommand at `indirect_offset` with a vertex count of 6
+    pub fn cmd_draw_indirect(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        instance_buffer: vk::Buffer,
+        instance_offset: vk::DeviceSize,
+        indirect_buffer: vk::Buffer,
+        indirect_offset: vk::DeviceSize,
+        per_frame_ubo_set: vk::DescriptorSet,
+        per_frame_ubo_offset: u32,
+    ) {
+        self.cmd_bind(command_buffer, instance_buffer, instance_offset, per_frame_ubo_set, per_frame_ubo_offset);
+        unsafe {
+            self.device.cmd_draw_indirect(
+                command_buffer,
+                indirect_buffer,
+                indirect_offset,
+                1,
+                size_of::<vk::DrawIndirectCommand>() as u32,
+            );
+        }
+    }
+
+    fn cmd_bind(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        instance_buffer: vk::Buffer,
+        instance_offset: vk::DeviceSize,
+        per_frame_ubo_set: vk::DescriptorSet,
+        per_frame_ubo_offset: u32,
+    ) {
+        unsafe {
+            self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[per_frame_ubo_set],
+                &[per_frame_ubo_offset],
+            );
+            self.device.cmd_bind_vertex_buffers(
+                command_buffer,
+                pipeline::INSTANCE_BINDING,
+                &[instance_buffer],
+                &[instance_offset],
+            );
+        }
+    }
+
+    unsafe fn destroy_pipeline(&mut self) {
+        if self.pipeline != vk::Pipeline::null() {
+            self.device.destroy_pipeline(self.pipeline, None);
+            self.device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+
+    /// # Safety
+    /// call once, after the device finished with the pipeline and the instance buffer
+    pub unsafe fn destroy(&mut self) {
+        self.destroy_pipeline();
+        self.instance_buffer.destroy();
+    }
+}
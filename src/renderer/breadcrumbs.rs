@@ -0,0 +1,179 @@
+// Breadcrumbs for debugging lost devices. A marker is written to a host visible buffer as each pass
+// of the frame finishes, one slot per frame in flight, and the names of recent markers are kept on
+// the cpu. When the device is lost the slots still hold the last marker the gpu reached, so the
+// pass it died in is the one after it:
+//
+//     breadcrumbs.cmd_mark(command_buffer, frame, "skinning");
+//     // on ERROR_DEVICE_LOST
+//     breadcrumbs.write_crash_log();
+//
+// With VK_AMD_buffer_marker markers are written once all earlier work has finished. Without it they
+// are buffer fills, which only show how far the gpu got through the command buffer, and must be
+// recorded outside render passes. VK_NV_device_diagnostic_checkpoints isn't used
+
+use std::{collections::VecDeque, fmt::Write, rc::Rc};
+
+use ash::vk;
+
+use super::{buffer::Buffer, MAX_FRAMES_IN_FLIGHT};
+
+pub const CRASH_LOG_PATH: &str = "gpu_crash.log";
+/// markers of the last few frames
+const HISTORY_LENGTH: usize = 64;
+
+/// a marker recorded into the command buffer of a frame in flight
+#[derive(Clone, Copy, Debug)]
+pub struct Breadcrumb {
+    /// increases with each marker, 0 is never written
+    pub marker: u32,
+    pub frame: usize,
+    /// of the pass finished when the marker is written
+    pub name: &'static str,
+}
+
+pub struct Breadcrumbs {
+    device: Rc<ash::Device>,
+    /// `None` without VK_AMD_buffer_marker
+    buffer_marker_fn: Option<vk::AmdBufferMarkerFn>,
+    /// the last marker each frame in flight reached
+    buffer: Buffer,
+    next_marker: u32,
+    /// oldest first
+    history: VecDeque<Breadcrumb>,
+}
+
+impl Breadcrumbs {
+    /// `buffer_marker` when VK_AMD_buffer_marker was enabled on `device`
+    pub fn new(
+        instance: &ash::Instance,
+        device: Rc<ash::Device>,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        buffer_marker: bool,
+    ) -> Self {
+        let buffer_marker_fn = buffer_marker.then(|| {
+            vk::AmdBufferMarkerFn::load(|name| unsafe {
+                std::mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+            })
+        });
+        let mut buffer = Buffer::new(
+            (MAX_FRAMES_IN_FLIGHT * std::mem::size_of::<u32>()) as vk::DeviceSize,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            device.clone(),
+            physical_device_memory_properties,
+        );
+        buffer.copy_from_slice(&[0u32; MAX_FRAMES_IN_FLIGHT], 0);
+
+        Self {
+            device,
+            buffer_marker_fn,
+            buffer,
+            next_marker: 1,
+            history: VecDeque::with_capacity(HISTORY_LENGTH),
+        }
+    }
+
+    /// marks the end of the pass `name` in `frame`'s command buffer, outside a render pass
+    pub fn cmd_mark(&mut self, command_buffer: vk::CommandBuffer, frame: usize, name: &'static str) {
+        let marker = self.next_marker;
+        self.next_marker = self.next_marker.checked_add(1).unwrap_or(1);
+        let offset = (frame * std::mem::size_of::<u32>()) as vk::DeviceSize;
+        unsafe {
+            match &self.buffer_marker_fn {
+                Some(buffer_marker_fn) => (buffer_marker_fn.cmd_write_buffer_marker_amd)(
+                    command_buffer,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    self.buffer.handle,
+                    offset,
+                    marker,
+                ),
+                None => self.device.cmd_fill_buffer(
+                    command_buffer,
+                    self.buffer.handle,
+                    offset,
+                    std::mem::size_of::<u32>() as vk::DeviceSize,
+                    marker,
+                ),
+            }
+        }
+
+        if self.history.len() == HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+        self.history.push_back(Breadcrumb { marker, frame, name });
+    }
+
+    /// the last marker each frame in flight reached and the recent markers, readable after the device is lost
+    pub fn report(&self) -> String {
+        let reached = self.buffer.copy_to_vec::<u32>(MAX_FRAMES_IN_FLIGHT, 0);
+        format_report(&self.history, &reached)
+    }
+
+    /// logs the report and writes it to `CRASH_LOG_PATH`
+    pub fn write_crash_log(&self) {
+        let report = self.report();
+        log::error!("Device lost\n{}", report);
+        match std::fs::write(CRASH_LOG_PATH, &report) {
+            Ok(()) => log::error!("Wrote gpu crash log to {}", CRASH_LOG_PATH),
+            Err(err) => log::error!("Failed to write gpu crash log to {}: {}", CRASH_LOG_PATH, err),
+        }
+    }
+
+    // caller must ensure only called once
+    pub unsafe fn destroy(&mut self) {
+        self.buffer.destroy();
+    }
+}
+
+/// `reached` holds the last marker written in each frame in flight
+fn format_report(history: &VecDeque<Breadcrumb>, reached: &[u32]) -> String {
+    let name_of = |marker: u32| history
+        .iter()
+        .find(|breadcrumb| breadcrumb.marker == marker)
+        .map_or("?", |breadcrumb| breadcrumb.name);
+
+    let mut report = String::new();
+    for (frame, &marker) in reached.iter().enumerate() {
+        if marker == 0 {
+            writeln!(report, "frame {}: no marker reached", frame).unwrap();
+            continue;
+        }
+        writeln!(report, "frame {}: last finished {} (marker {})", frame, name_of(marker), marker).unwrap();
+        // markers of older submissions of the frame were all reached before its fence signaled
+        let unfinished = history
+            .iter()
+            .filter(|breadcrumb| breadcrumb.frame == frame && breadcrumb.marker > marker)
+            .map(|breadcrumb| breadcrumb.name)
+            .collect::<Vec<_>>();
+        if !unfinished.is_empty() {
+            writeln!(report, "    unfinished: {}", unfinished.join(", ")).unwrap();
+        }
+    }
+
+    writeln!(report, "recent markers, oldest first:").unwrap();
+    for breadcrumb in history {
+        let finished = breadcrumb.marker <= reached[breadcrumb.frame];
+        writeln!(
+            report,
+            "    {} frame {} {}{}",
+            breadcrumb.marker,
+            breadcrumb.frame,
+            breadcrumb.name,
+            if finished { "" } else { " (unfinished)" },
+        ).unwrap();
+    }
+    report
+}
+
+#[test]
+fn test_breadcrumb_report() {
+    let history = [(1, 0, "skinning"), (2, 0, "scene"), (3, 1, "skinning"), (4, 1, "scene"), (5, 1, "frame end")]
+        .into_iter()
+        .map(|(marker, frame, name)| Breadcrumb { marker, frame, name })
+        .collect();
+    let report = format_report(&history, &[2, 3]);
+    assert!(report.contains("frame 0: last finished scene (marker 2)"));
+    assert!(report.contains("frame 1: last finished skinning (marker 3)\n    unfinished: scene, frame end"));
+    assert!(report.contains("    4 frame 1 scene (unfinished)"));
+    assert!(format_report(&history, &[0, 5]).contains("frame 0: no marker reached"));
+}
@@ -1,5 +1,193 @@
+// Buffers with their own memory allocation. Host visible buffers are written and read
+// in elements of the slice's type, `copy_from_slice(&instances, frame * MAX_INSTANCE_COUNT)`,
+// mapping only the range written, widened down to the map alignment
+
 use ash::vk;
-use std::{rc::Rc, ffi::c_void, mem::size_of};
+use std::{rc::Rc, mem::{align_of, size_of, size_of_val}};
+
+/// the smallest `minMemoryMapAlignment` Vulkan allows, the device's is a power of two at least this,
+/// so ranges mapped from multiples of it start this aligned
+pub const MEMORY_MAP_ALIGNMENT: vk::DeviceSize = 64;
+
+/// the range to map for accessing `size` bytes at `offset`, as the mapped offset, mapped size
+/// and offset of the bytes in the mapping
+pub fn aligned_map_range(
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+) -> (vk::DeviceSize, vk::DeviceSize, usize) {
+    let map_offset = crate::utils::align_down(offset as usize, MEMORY_MAP_ALIGNMENT as usize) as vk::DeviceSize;
+    (map_offset, offset - map_offset + size, (offset - map_offset) as usize)
+}
+
+pub struct Buffer {
+    device: Rc<ash::Device>,
+    pub handle: vk::Buffer,
+    memory: vk::DeviceMemory,
+    pub size: vk::DeviceSize,
+    /// of the memory type allocated from, may have more than asked for
+    memory_properties: vk::MemoryPropertyFlags,
+}
+
+impl Buffer {
+    pub fn new(
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        memory_properties: vk::MemoryPropertyFlags,
+        device: Rc<ash::Device>,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    ) -> Self {
+        Self::new_shared(size, usage, memory_properties, &[], device, physical_device_memory_properties)
+    }
+
+    /// used by the queues of all of `queue_family_indices` without ownership transfers,
+    /// exclusive to one family when fewer than two are given
+    pub fn new_shared(
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        memory_properties: vk::MemoryPropertyFlags,
+        queue_family_indices: &[u32],
+        device: Rc<ash::Device>,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    ) -> Self {
+        let handle = {
+            let mut info = vk::BufferCreateInfo::builder()
+                .size(size)
+                .usage(usage)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE);
+            if queue_family_indices.len() > 1 {
+                info = info
+                    .sharing_mode(vk::SharingMode::CONCURRENT)
+                    .queue_family_indices(queue_family_indices);
+            }
+            unsafe { device.create_buffer(&info, None) }.expect("Failed to create buffer handle")
+        };
+
+        let mem_requirements = unsafe { device.get_buffer_memory_requirements(handle) };
+
+        let (memory, memory_properties) = {
+            let mem_type_index = super::device::find_mem_type_index(
+                mem_requirements.memory_type_bits,
+                memory_properties,
+                physical_device_memory_properties,
+            );
+            let alloc_info = vk::MemoryAllocateInfo::builder()
+                .allocation_size(mem_requirements.size)
+                .memory_type_index(mem_type_index);
+
+            let memory = unsafe { device.allocate_memory(&alloc_info, None) }
+                .expect("Failed to allocate device memory");
+            (memory, physical_device_memory_properties.memory_types[mem_type_index as usize].property_flags)
+        };
+
+        unsafe {
+            device
+                .bind_buffer_memory(handle, memory, 0)
+                .expect("Failed to associate memory with buffer");
+        }
+
+        Self {
+            device,
+            handle,
+            memory,
+            size,
+            memory_properties,
+        }
+    }
+
+    /// host writes are only visible to the device without flushing on coherent memory
+    fn debug_assert_host_accessible(&self) {
+        debug_assert!(
+            self.memory_properties.contains(vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT),
+            "Buffer memory {:?} isn't host visible and coherent",
+            self.memory_properties,
+        );
+    }
+
+    /// `size` bytes at `offset` mapped, the mapping must be unmapped before the next
+    unsafe fn map_range(&self, offset: vk::DeviceSize, size: vk::DeviceSize) -> *mut u8 {
+        self.debug_assert_host_accessible();
+        let (map_offset, map_size, offset_in_mapping) = aligned_map_range(offset, size);
+        let mapping = self.device
+            .map_memory(self.memory, map_offset, map_size, vk::MemoryMapFlags::empty())
+            .unwrap() as *mut u8;
+        debug_assert!((mapping as vk::DeviceSize).is_multiple_of(MEMORY_MAP_ALIGNMENT), "Mapping isn't aligned");
+        mapping.add(offset_in_mapping)
+    }
+
+    /// byte offset of element `first`, checking `count` elements from it fit
+    fn element_offset<T>(&self, first: usize, count: usize) -> vk::DeviceSize {
+        // elements are at multiples of their size, aligned as the mapping is aligned more
+        debug_assert!(align_of::<T>() as vk::DeviceSize <= MEMORY_MAP_ALIGNMENT);
+        let offset = (first * size_of::<T>()) as vk::DeviceSize;
+        assert!(
+            offset + (count * size_of::<T>()) as vk::DeviceSize <= self.size,
+            "Elements {}..{} out of buffer of {} bytes", first, first + count, self.size,
+        );
+        offset
+    }
+
+    /// writes `data` from element `first` on, counted in `T`s from the start of the buffer.
+    /// Buffer must be host visible and not mapped
+    pub fn copy_from_slice<T: Copy>(&mut self, data: &[T], first: usize) {
+        let offset = self.element_offset::<T>(first, data.len());
+        let size = size_of_val(data) as vk::DeviceSize;
+        // mapping 0 bytes is invalid
+        if size == 0 {
+            return;
+        }
+
+        unsafe {
+            let data_ptr = self.map_range(offset, size);
+            (data_ptr as *mut T).copy_from_nonoverlapping(data.as_ptr(), data.len());
+            self.device.unmap_memory(self.memory);
+        }
+    }
+
+    /// reads `count` elements from element `first` on, counted as in `copy_from_slice`.
+    /// Buffer must be host visible and not mapped,
+    /// caller must make sure device writes to the buffer have finished
+    pub fn copy_to_vec<T: Copy>(&self, count: usize, first: usize) -> Vec<T> {
+        let offset = self.element_offset::<T>(first, count);
+        let size = (count * size_of::<T>()) as vk::DeviceSize;
+        if size == 0 {
+            return vec![];
+        }
+
+        let mut data: Vec<T> = Vec::with_capacity(count);
+        unsafe {
+            let data_ptr = self.map_range(offset, size);
+            data.as_mut_ptr().copy_from_nonoverlapping(data_ptr as *const T, count);
+            data.set_len(count);
+            self.device.unmap_memory(self.memory);
+        }
+        data
+    }
+
+    /// buffer must be host visible and not mapped,
+    /// the memory stays mapped until `unmap` or `destroy`
+    pub unsafe fn map(&mut self) -> *mut u8 {
+        self.debug_assert_host_accessible();
+        self.device
+            .map_memory(self.memory, 0, self.size, vk::MemoryMapFlags::empty())
+            .unwrap() as *mut u8
+    }
+
+    pub unsafe fn unmap(&mut self) {
+        self.device.unmap_memory(self.memory);
+    }
 
-use super::descriptor::PerFrameUBO;
+    // caller must ensure only called once
+    pub unsafe fn destroy(&mut self) {
+        self.device.destroy_buffer(self.handle, None);
+        self.device.free_memory(self.memory, None);
+    }
+}
 
+#[test]
+fn test_aligned_map_range() {
+    // already aligned, mapped as is
+    assert!(aligned_map_range(128, 16) == (128, 16, 0));
+    // widened down to the alignment, the bytes sit past the start of the mapping
+    assert!(aligned_map_range(200, 16) == (192, 24, 8));
+    assert!(aligned_map_range(63, 1) == (0, 64, 63));
+}
@@ -1,5 +1,7 @@
 use std::ffi::{c_void, CStr, CString};
 
+use serde::Deserialize;
+
 use ash::{
     extensions::ext::DebugUtils,
     vk::{self, DebugUtilsMessengerEXT},
@@ -7,24 +9,168 @@ use ash::{
 
 const LAYER_NAMES: [&str; 1] = ["VK_LAYER_KHRONOS_validation"];
 
+/// least severe validation message that gets through
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Deserialize)]
+pub enum Severity {
+    Verbose,
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    /// "verbose", "info", "warning" or "error", any case
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "verbose" => Some(Self::Verbose),
+            "info" => Some(Self::Info),
+            "warning" => Some(Self::Warning),
+            "error" => Some(Self::Error),
+            _ => None,
+        }
+    }
+
+    /// this severity and the ones above it
+    fn flags(self) -> vk::DebugUtilsMessageSeverityFlagsEXT {
+        type Flag = vk::DebugUtilsMessageSeverityFlagsEXT;
+        [Flag::VERBOSE, Flag::INFO, Flag::WARNING, Flag::ERROR][self as usize..]
+            .iter()
+            .fold(Flag::empty(), |flags, &flag| flags | flag)
+    }
+}
+
+/// Which validation messages are logged, applied at startup only.
+/// `ASH_VALIDATION_SEVERITY`, `ASH_VALIDATION_IGNORE` (comma separated) and `ASH_VALIDATION_PANIC`
+/// override it from the environment, see `with_env_overrides`
+#[derive(Clone, PartialEq, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ValidationConfig {
+    /// enables the validation layer in debug builds, release builds never do
+    pub enabled: bool,
+    pub min_severity: Severity,
+    pub general: bool,
+    pub validation: bool,
+    pub performance: bool,
+    /// message id names like "VUID-vkCmdDraw-None-02859" or id numbers in decimal or 0x prefixed hex
+    pub ignored_messages: Vec<String>,
+    /// so ci catches misuse immediately, the panic can't unwind through the driver and aborts
+    pub panic_on_error: bool,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_severity: Severity::Warning,
+            general: true,
+            validation: true,
+            performance: true,
+            ignored_messages: vec![],
+            panic_on_error: false,
+        }
+    }
+}
+
+impl ValidationConfig {
+    pub fn with_env_overrides(mut self) -> Self {
+        if let Ok(severity) = std::env::var("ASH_VALIDATION_SEVERITY") {
+            match Severity::parse(&severity) {
+                Some(severity) => self.min_severity = severity,
+                None => log::warn!("Unknown validation severity {}", severity),
+            }
+        }
+        if let Ok(ignored) = std::env::var("ASH_VALIDATION_IGNORE") {
+            self.ignored_messages.extend(
+                ignored.split(',').map(str::trim).filter(|id| !id.is_empty()).map(str::to_owned),
+            );
+        }
+        if let Some(panic_on_error) = std::env::var_os("ASH_VALIDATION_PANIC") {
+            self.panic_on_error = panic_on_error != "0";
+        }
+        self
+    }
+
+    fn type_flags(&self) -> vk::DebugUtilsMessageTypeFlagsEXT {
+        type Flag = vk::DebugUtilsMessageTypeFlagsEXT;
+        let mut flags = Flag::empty();
+        if self.general {
+            flags |= Flag::GENERAL;
+        }
+        if self.validation {
+            flags |= Flag::VALIDATION;
+        }
+        if self.performance {
+            flags |= Flag::PERFORMANCE;
+        }
+        flags
+    }
+}
+
+/// What the messenger's callback reads, must outlive the messenger
+pub struct MessageFilter {
+    ignored_names: Vec<String>,
+    ignored_numbers: Vec<i32>,
+    panic_on_error: bool,
+}
+
+impl MessageFilter {
+    fn new(config: &ValidationConfig) -> Self {
+        let mut ignored_names = vec![];
+        let mut ignored_numbers = vec![];
+        for id in &config.ignored_messages {
+            let number = match id.strip_prefix("0x") {
+                Some(hex) => u32::from_str_radix(hex, 16).ok().map(|number| number as i32),
+                None => id.parse::<i32>().ok(),
+            };
+            match number {
+                Some(number) => ignored_numbers.push(number),
+                None => ignored_names.push(id.clone()),
+            }
+        }
+        Self {
+            ignored_names,
+            ignored_numbers,
+            panic_on_error: config.panic_on_error,
+        }
+    }
+
+    fn is_ignored(&self, name: Option<&str>, number: i32) -> bool {
+        self.ignored_numbers.contains(&number)
+            || name.is_some_and(|name| self.ignored_names.iter().any(|ignored| ignored == name))
+    }
+}
+
 unsafe extern "system" fn vulkan_debug_callback(
     flag: vk::DebugUtilsMessageSeverityFlagsEXT,
     typ: vk::DebugUtilsMessageTypeFlagsEXT,
     p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
-    _: *mut c_void,
+    p_user_data: *mut c_void,
 ) -> vk::Bool32 {
     type Flag = vk::DebugUtilsMessageSeverityFlagsEXT;
 
+    let filter = &*(p_user_data as *const MessageFilter);
+    let callback_data = &*p_callback_data;
+    let id_name = (!callback_data.p_message_id_name.is_null())
+        .then(|| CStr::from_ptr(callback_data.p_message_id_name).to_string_lossy());
+    if filter.is_ignored(id_name.as_deref(), callback_data.message_id_number) {
+        return vk::FALSE;
+    }
+
     let msg = format!(
         "(Validation Layer): {:?} - {:?}",
         typ,
-        CStr::from_ptr((*p_callback_data).p_message)
+        CStr::from_ptr(callback_data.p_message)
     );
     match flag {
         Flag::VERBOSE => log::debug!("{msg}"),
         Flag::INFO => log::info!("{msg}"),
         Flag::WARNING => log::warn!("{msg}"),
-        _ => log::error!("{msg}"),
+        _ => {
+            log::error!("{msg}");
+            if filter.panic_on_error {
+                panic!("{msg}");
+            }
+        }
     }
     vk::FALSE
 }
@@ -47,26 +193,21 @@ pub fn check_validation_layer_support(entry: &ash::Entry) {
     }
 }
 
-pub fn new_messenger(debug_entry: &DebugUtils) -> DebugUtilsMessengerEXT {
+/// the filter is read by the messenger's callback, keep it until the messenger is destroyed
+pub fn new_messenger(debug_entry: &DebugUtils, config: &ValidationConfig) -> (DebugUtilsMessengerEXT, Box<MessageFilter>) {
+    let filter = Box::new(MessageFilter::new(config));
     let create_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
-        .message_severity(
-            vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
-                | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
-                | vk::DebugUtilsMessageSeverityFlagsEXT::INFO
-                | vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE,
-        )
-        .message_type(
-            vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
-                | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
-                | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
-        )
-        .pfn_user_callback(Some(vulkan_debug_callback));
-
-    unsafe {
+        .message_severity(config.min_severity.flags())
+        .message_type(config.type_flags())
+        .pfn_user_callback(Some(vulkan_debug_callback))
+        .user_data(&*filter as *const MessageFilter as *mut c_void);
+
+    let messenger = unsafe {
         debug_entry
             .create_debug_utils_messenger(&create_info, None)
             .unwrap()
-    }
+    };
+    (messenger, filter)
 }
 
 //Return CString to avoid dangling ptrs
@@ -81,3 +222,18 @@ pub fn get_layer_names_and_ptrs() -> (Vec<CString>, Vec<*const i8>) {
         .collect::<Vec<_>>();
     (layer_names, layer_names_ptrs)
 }
+
+#[test]
+fn test_validation_filter() {
+    assert!(Severity::Warning.flags() == vk::DebugUtilsMessageSeverityFlagsEXT::WARNING | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR);
+    assert!(Severity::parse("INFO") == Some(Severity::Info) && Severity::parse("loud").is_none());
+
+    let config = ValidationConfig {
+        ignored_messages: vec!["VUID-vkCmdDraw-None-02859".to_owned(), "0x7cd0911d".to_owned(), "-12".to_owned()],
+        ..Default::default()
+    };
+    let filter = MessageFilter::new(&config);
+    assert!(filter.is_ignored(Some("VUID-vkCmdDraw-None-02859"), 1));
+    assert!(filter.is_ignored(None, 0x7cd0911d) && filter.is_ignored(Some("other"), -12));
+    assert!(!filter.is_ignored(Some("VUID-vkCmdDraw-None-02860"), 1));
+}
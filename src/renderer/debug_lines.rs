@@ -0,0 +1,171 @@
+// World space lines for visualizing things while developing, e.g. gizmos and bounds.
+// Lines are drawn over the scene without depth testing so they never hide behind it
+
+use std::{mem::size_of, rc::Rc};
+
+use ash::vk;
+
+use super::{buffer::Buffer, pipeline, MAX_FRAMES_IN_FLIGHT};
+
+/// per frame in flight
+pub const MAX_DEBUG_LINE_COUNT: usize = 0x4000;
+
+/// vertex data, must match the vertex attributes of debug_line.vert
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct DebugLine {
+    pub start: [f32; 3],
+    pub end: [f32; 3],
+    /// straight alpha
+    pub color: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct LineVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+const LINE_ATTRIBUTES: [pipeline::Attribute; 2] = [
+    pipeline::Attribute::F32x3,
+    pipeline::Attribute::F32x4,
+];
+
+/// Submit during the frame, `build` once the frame's fence is waited on and `cmd_draw`
+/// in the scene pass. The pipeline depends on the scene render pass, `renew_pipeline` when it changes
+pub struct DebugLineRenderer {
+    device: Rc<ash::Device>,
+    submitted: Vec<LineVertex>,
+    /// of the last built frame
+    vertex_count: u32,
+    /// host visible, one region per frame in flight
+    vertex_buffer: Buffer,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl DebugLineRenderer {
+    pub fn new(
+        device: Rc<ash::Device>,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    ) -> Self {
+        Self {
+            vertex_buffer: Buffer::new(
+                (MAX_FRAMES_IN_FLIGHT * Self::frame_size()) as vk::DeviceSize,
+                vk::BufferUsageFlags::VERTEX_BUFFER,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                device.clone(),
+                physical_device_memory_properties,
+            ),
+            device,
+            submitted: vec![],
+            vertex_count: 0,
+            pipeline_layout: vk::PipelineLayout::null(),
+            pipeline: vk::Pipeline::null(),
+        }
+    }
+
+    /// `render_pass` null for dynamic rendering, draws in the translucent subpass with depth attached.
+    /// The lines aren't depth tested, so they ignore the context's `reverse_z`
+    pub fn renew_pipeline(
+        &mut self,
+        context: &pipeline::PassContext,
+        render_pass: vk::RenderPass,
+    ) {
+        let pipeline::PassContext { shader_compiler, color_format, depth_format, per_frame_ubo_set_layout, output_transfer, .. } = *context;
+        let subpass = context.translucent_subpass();
+        unsafe { self.destroy_pipeline(); }
+
+        (self.pipeline, self.pipeline_layout) = pipeline::new_pipeline_and_layout(
+            &self.device,
+            shader_compiler,
+            &pipeline::PipelineDesc {
+                render_pass,
+                subpass,
+                color_formats: &[color_format],
+                depth_format,
+                set_layouts: &[per_frame_ubo_set_layout],
+                vertex_shader_path: "shaders/debug_line.vert",
+                fragment_shader_path: "shaders/debug_line.frag",
+                vertex_attributes: &LINE_ATTRIBUTES,
+                topology: vk::PrimitiveTopology::LINE_LIST,
+                blend_mode: pipeline::BlendMode::Alpha,
+                cull_mode: vk::CullModeFlags::NONE,
+                depth_test: false,
+                depth_write: false,
+                output_transfer,
+                ..Default::default()
+            },
+        );
+    }
+
+    /// drawn next frame, only for that frame
+    pub fn submit<I: IntoIterator<Item = DebugLine>>(&mut self, lines: I) {
+        for line in lines {
+            self.submitted.push(LineVertex { position: line.start, color: line.color });
+            self.submitted.push(LineVertex { position: line.end, color: line.color });
+        }
+    }
+
+    const fn frame_size() -> usize {
+        2 * MAX_DEBUG_LINE_COUNT * size_of::<LineVertex>()
+    }
+
+    /// writes the submitted lines into `frame`'s region and clears them,
+    /// the frame's previous commands must have finished executing
+    pub fn build(&mut self, frame: usize) {
+        if self.submitted.len() > 2 * MAX_DEBUG_LINE_COUNT {
+            log::warn!("Dropping {} debug lines over the limit", self.submitted.len() / 2 - MAX_DEBUG_LINE_COUNT);
+            self.submitted.truncate(2 * MAX_DEBUG_LINE_COUNT);
+        }
+        self.vertex_buffer.copy_from_slice(&self.submitted, frame * 2 * MAX_DEBUG_LINE_COUNT);
+        self.vertex_count = self.submitted.len() as u32;
+        self.submitted.clear();
+    }
+
+    /// record in the scene pass last, binds its own pipeline
+    pub fn cmd_draw(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+        per_frame_ubo_set: vk::DescriptorSet,
+        per_frame_ubo_offset: u32,
+    ) {
+        if self.vertex_count == 0 {
+            return;
+        }
+
+        unsafe {
+            self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[per_frame_ubo_set],
+                &[per_frame_ubo_offset],
+            );
+            self.device.cmd_bind_vertex_buffers(
+                command_buffer,
+                pipeline::VERTEX_BINDING,
+                &[self.vertex_buffer.handle],
+                &[(frame * Self::frame_size()) as vk::DeviceSize],
+            );
+            self.device.cmd_draw(command_buffer, self.vertex_count, 1, 0, 0);
+        }
+    }
+
+    unsafe fn destroy_pipeline(&mut self) {
+        if self.pipeline != vk::Pipeline::null() {
+            self.device.destroy_pipeline(self.pipeline, None);
+            self.device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+
+    // caller must ensure only called once
+    pub unsafe fn destroy(&mut self) {
+        self.destroy_pipeline();
+        self.vertex_buffer.destroy();
+    }
+}
@@ -0,0 +1,142 @@
+// Debug views replacing the shading of batched scene draws, for diagnosing rendering issues.
+// Each view draws the batches with its own pipeline, sharing the scene pipeline's layout, so
+// switching views is only picking another pipeline. Skinned meshes, terrain and translucent
+// draws stay lit, and the views need the forward path, the deferred path's scene pipeline
+// writes the g-buffer
+// TODO: shadow cascade boundaries, once there are shadow maps
+
+use std::rc::Rc;
+
+use ash::vk;
+
+use crate::geometry;
+use super::{
+    material,
+    pipeline::{self, BlendMode},
+    RenderPath,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum DebugView {
+    /// shaded as usual
+    #[default]
+    Lit,
+    /// distance to the camera
+    Depth,
+    /// world space normals, after normal mapping
+    Normals,
+    /// how many layers were drawn over each pixel, without depth testing
+    Overdraw,
+    /// mip level of the diffuse texture sampled
+    MipLevel,
+}
+
+impl DebugView {
+    const ALL: [DebugView; 5] = [
+        DebugView::Lit,
+        DebugView::Depth,
+        DebugView::Normals,
+        DebugView::Overdraw,
+        DebugView::MipLevel,
+    ];
+
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+}
+
+/// Holds a pipeline per view, `renew_pipelines` when the scene render pass changes
+pub struct DebugViewRenderer {
+    device: Rc<ash::Device>,
+    pub view: DebugView,
+    /// indexed by view, null for `Lit` and off the forward path
+    pipelines: [(vk::Pipeline, vk::PipelineLayout); DebugView::ALL.len()],
+}
+
+impl DebugViewRenderer {
+    pub fn new(device: Rc<ash::Device>) -> Self {
+        Self {
+            device,
+            view: DebugView::Lit,
+            pipelines: [(vk::Pipeline::null(), vk::PipelineLayout::null()); DebugView::ALL.len()],
+        }
+    }
+
+    /// `render_pass` null for dynamic rendering, layouts as the scene pipeline's
+    pub fn renew_pipelines(
+        &mut self,
+        context: &pipeline::PassContext,
+        render_pass: vk::RenderPass,
+    ) {
+        let pipeline::PassContext { shader_compiler, render_path, color_format, depth_format, per_frame_ubo_set_layout, textures_set_layout, output_transfer, reverse_z, .. } = *context;
+        unsafe { self.destroy(); }
+
+        if render_path != RenderPath::Forward {
+            return;
+        }
+
+        for view in DebugView::ALL {
+            let fragment_shader_path = match view {
+                DebugView::Lit => continue,
+                DebugView::Depth => "shaders/debug_depth.frag",
+                DebugView::Normals => "shaders/debug_normals.frag",
+                DebugView::Overdraw => "shaders/debug_overdraw.frag",
+                DebugView::MipLevel => "shaders/debug_mip_level.frag",
+            };
+            let overdraw = view == DebugView::Overdraw;
+            self.pipelines[view as usize] = pipeline::new_pipeline_and_layout(
+                &self.device,
+                shader_compiler,
+                &pipeline::PipelineDesc {
+                    render_pass,
+                    color_formats: &[color_format],
+                    depth_format,
+                    set_layouts: &[per_frame_ubo_set_layout, textures_set_layout],
+                    push_constant_ranges: &[material::MaterialPushConstants::RANGE],
+                    vertex_shader_path: "shaders/foo.vert",
+                    fragment_shader_path,
+                    vertex_attributes: &geometry::VERTEX_ATTRIBUTES,
+                    instance_attributes: &geometry::INSTANCE_ATTRIBUTES,
+                    blend_mode: if overdraw { BlendMode::Additive } else { BlendMode::Opaque },
+                    depth_test: !overdraw,
+                    depth_write: !overdraw,
+                    output_transfer,
+                    reverse_z,
+                    ..Default::default()
+                },
+            );
+        }
+    }
+
+    /// what batches draw with instead of their own pipeline, `None` for `Lit`
+    /// or when the view can't be drawn on this render path
+    pub fn get_pipeline(&self) -> Option<vk::Pipeline> {
+        let (pipeline, _) = self.pipelines[self.view as usize];
+        (pipeline != vk::Pipeline::null()).then_some(pipeline)
+    }
+
+    /// safe to call again, the pipelines are nulled
+    pub unsafe fn destroy(&mut self) {
+        for (pipeline, pipeline_layout) in &mut self.pipelines {
+            if *pipeline != vk::Pipeline::null() {
+                self.device.destroy_pipeline(*pipeline, None);
+                self.device.destroy_pipeline_layout(*pipeline_layout, None);
+            }
+            *pipeline = vk::Pipeline::null();
+            *pipeline_layout = vk::PipelineLayout::null();
+        }
+    }
+}
+
+#[test]
+fn test_debug_view() {
+    let mut view = DebugView::default();
+    let mut seen = vec![];
+    for _ in DebugView::ALL {
+        seen.push(view);
+        view = view.next();
+    }
+    // every view once, then back to lit
+    assert!(view == DebugView::Lit);
+    assert!(DebugView::ALL.iter().all(|view| seen.contains(view)));
+}
@@ -0,0 +1,85 @@
+// Resources a frame in flight may still use, destroyed once it can't anymore.
+// Whatever is pushed waits for the fences of the MAX_FRAMES_IN_FLIGHT frames that may
+// have recorded it before `collect` hands it to `destroy`:
+//
+//     queue.push(old_buffer);
+//     // every frame, after waiting for its fence
+//     queue.collect(|mut buffer| unsafe { buffer.destroy() });
+//
+// Pipelines are only replaced with the device idle, so they don't go through a queue
+
+use super::MAX_FRAMES_IN_FLIGHT;
+
+pub struct DeletionQueue<T> {
+    /// values and the fence waits left before they can be destroyed
+    pending: Vec<(T, usize)>,
+}
+
+impl<T> Default for DeletionQueue<T> {
+    fn default() -> Self {
+        Self { pending: vec![] }
+    }
+}
+
+impl<T> DeletionQueue<T> {
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// destroyed after the fences of the frames in flight now
+    pub fn push(&mut self, value: T) {
+        self.pending.push((value, MAX_FRAMES_IN_FLIGHT));
+    }
+
+    /// for updating values that wait, e.g. allocations whose heap moved
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.pending.iter_mut().map(|(value, _)| value)
+    }
+
+    /// call once per frame after waiting for its fence,
+    /// destroys the values no frame in flight can still use
+    pub fn collect<F: FnMut(T)>(&mut self, mut destroy: F) {
+        for (_, fence_waits_left) in &mut self.pending {
+            *fence_waits_left -= 1;
+        }
+        let mut i = 0;
+        while i < self.pending.len() {
+            if self.pending[i].1 == 0 {
+                destroy(self.pending.swap_remove(i).0);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    /// destroys everything regardless of frames, the device must be idle
+    pub fn destroy_all<F: FnMut(T)>(&mut self, destroy: F) {
+        self.pending.drain(..).map(|(value, _)| value).for_each(destroy);
+    }
+}
+
+#[test]
+fn test_deletion_queue() {
+    let mut queue = DeletionQueue::default();
+    let mut destroyed = vec![];
+    queue.push(1);
+    queue.collect(|value| destroyed.push(value));
+    queue.push(2);
+
+    // each value waits for every frame in flight
+    for _ in 1..MAX_FRAMES_IN_FLIGHT {
+        assert!(destroyed.is_empty());
+        queue.collect(|value| destroyed.push(value));
+    }
+    assert!(destroyed == [1] && queue.len() == 1);
+    queue.collect(|value| destroyed.push(value));
+    assert!(destroyed == [1, 2] && queue.is_empty());
+
+    queue.push(3);
+    queue.destroy_all(|value| destroyed.push(value));
+    assert!(destroyed == [1, 2, 3]);
+}
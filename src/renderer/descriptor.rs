@@ -1,82 +1,45 @@
-use std::{mem::size_of, rc::Rc};
+use std::mem::size_of;
 
 use ash::vk;
 
+use crate::arena::FrameArena;
+
 //TODO: update descriptor set managing system
+/// std140, must match the uniform block in the shaders
+#[repr(C)]
 #[derive(Clone, Copy, Default)]
 pub struct PerFrameUBO {
     pub proj_view: crate::math::Mat,
+    /// towards the light
+    pub light_direction: [f32; 4],
+    /// rgb scaled by intensity, ambient in w
+    pub light_color: [f32; 4],
+    pub camera_position: [f32; 4],
+    /// velocity, for anything animated by the weather's wind
+    pub wind: [f32; 4],
+    /// seconds since startup
+    pub time: f32,
+    /// 0 dry to 1 soaked
+    pub wetness: f32,
+    /// of the image based lighting, 0 without an environment
+    pub environment_intensity: f32,
+    /// fog from `Fog::to_shader_vectors`
+    pub fog_mode: u32,
+    pub fog_color: [f32; 4],
+    pub fog_params: [f32; 4],
+    /// to world space from the depth buffer
+    pub inverse_proj_view: crate::math::Mat,
+    /// of the previous frame, for motion vectors
+    pub previous_proj_view: crate::math::Mat,
 }
 
-pub struct PerFrameUniformBuffer {
-    device: Rc<ash::Device>,
-    pub handle: vk::Buffer,
-    memory: vk::DeviceMemory,
-    pub mapped_ptr: *mut u8,
-}
-
-impl PerFrameUniformBuffer {
-    const SIZE: vk::DeviceSize = (crate::renderer::MAX_FRAMES_IN_FLIGHT * size_of::<PerFrameUBO>()) as vk::DeviceSize;
-
-    pub fn new(
-        device: Rc<ash::Device>,
-        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
-    ) -> Self {
-        let handle = {
-            let info = vk::BufferCreateInfo::builder()
-                .size(Self::SIZE)
-                .usage(vk::BufferUsageFlags::UNIFORM_BUFFER)
-                .sharing_mode(vk::SharingMode::EXCLUSIVE); // configurable
-            unsafe { device.create_buffer(&info, None) }.expect("Failed to create buffer handle")
-        };
-
-        let mem_requirements = unsafe { device.get_buffer_memory_requirements(handle) };
-
-        let memory = {
-            let mem_type_index = super::device::find_mem_type_index(
-                mem_requirements.memory_type_bits,
-                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-                &physical_device_memory_properties,
-            );
-            let alloc_info = vk::MemoryAllocateInfo::builder()
-                .allocation_size(mem_requirements.size)
-                .memory_type_index(mem_type_index);
-
-            unsafe { device.allocate_memory(&alloc_info, None) }
-                .expect("Failed to allocate device memory")
-        };
-
-        unsafe {
-            device
-                .bind_buffer_memory(handle, memory, 0)
-                .expect("Failed to associate memory with buffer");
-        }
-
-        let mapped_ptr = unsafe { device
-            .map_memory(
-                memory,
-                0,
-                Self::SIZE,
-                vk::MemoryMapFlags::empty(),
-            )
-            .unwrap() as *mut u8
-        };
+/// cameras rendered each frame, each pushes its own uniform buffer object
+pub const MAX_VIEW_COUNT: usize = 2;
+/// the main camera
+pub const MAIN_VIEW: usize = 0;
 
-        Self {
-            device,
-            handle,
-            memory,
-            mapped_ptr,
-        }
-    }
-
-    pub unsafe fn destroy(&mut self) {
-        self.device.unmap_memory(self.memory);
-
-        self.device.destroy_buffer(self.handle, None);
-        self.device.free_memory(self.memory, None);
-    }
-}
+/// bytes of the uniform ring per frame in flight, for the views' and per object uniform data
+pub const UNIFORM_RING_FRAME_CAPACITY: usize = 0x10000;
 
 // Textures, need multiple descriptors for each texture samplers
 // use different descriptor sets for difference frequency resources
@@ -91,11 +54,14 @@ impl PerFrameUniformBuffer {
 //      Diffuse texture
 //      Normal/Height texture
 
+/// size of the textures descriptor array, shaders must match it
+pub const MAX_TEXTURE_COUNT: u32 = 20;
+/// binding of the materials storage buffer in the textures set
+pub const MATERIALS_BINDING: u32 = 1;
+
 pub fn new_descriptor_pool(
     device: &ash::Device,
 ) -> vk::DescriptorPool {
-    const MAX_TEXTURE_COUNT: u32 = 20;
-
     let pool_sizes = [
         vk::DescriptorPoolSize {
             ty: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
@@ -105,10 +71,20 @@ pub fn new_descriptor_pool(
             ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
             descriptor_count: MAX_TEXTURE_COUNT,
         },
+        // materials
+        vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: 1,
+        },
+        // g-buffer attachments and depth
+        vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::INPUT_ATTACHMENT,
+            descriptor_count: super::gbuffer::GBUFFER_FORMATS.len() as u32 + 1,
+        },
     ];
 
     let info = vk::DescriptorPoolCreateInfo::builder()
-        .max_sets(2)
+        .max_sets(4)
         .pool_sizes(&pool_sizes) // TODO: configurable
         .build();
 
@@ -127,21 +103,30 @@ pub fn new_descriptor_set_layouts(
         .binding(0)
         .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
         .descriptor_count(1)
-        .stage_flags(vk::ShaderStageFlags::VERTEX)
+        // compute for screen space reflections
+        .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE)
         .build();
 
-    let textures_set_layout_binding = vk::DescriptorSetLayoutBinding::builder()
-        .binding(0)
-        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-        .descriptor_count(texture_descriptor_count)
-        .stage_flags(vk::ShaderStageFlags::FRAGMENT)
-        .build();
+    let textures_set_layout_bindings = [
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(texture_descriptor_count)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build(),
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(MATERIALS_BINDING)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build(),
+    ];
 
     let ubo_set_layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
         .bindings(&[ubo_set_layout_binding])
         .build();
     let textures_set_layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
-        .bindings(&[textures_set_layout_binding])
+        .bindings(&textures_set_layout_bindings)
         .build();
 
     unsafe {
@@ -160,7 +145,8 @@ pub fn new_per_frame_ubo_set(
     device: &ash::Device,
     pool: vk::DescriptorPool,
     ubo_set_layout: vk::DescriptorSetLayout,
-    per_frame_uniform_buffer: &PerFrameUniformBuffer,
+    uniform_ring: &super::uniform_ring::UniformRing,
+    write_batcher: &mut DescriptorWriteBatcher,
 ) -> vk::DescriptorSet {
     let set = unsafe {
         let alloc_info = vk::DescriptorSetAllocateInfo::builder()
@@ -171,25 +157,47 @@ pub fn new_per_frame_ubo_set(
             .allocate_descriptor_sets(&alloc_info).unwrap()[0]
     };
 
-    let write = {
-        let buffer_info = vk::DescriptorBufferInfo::builder()
-            .buffer(per_frame_uniform_buffer.handle)
-            .offset(0)
-            .range(size_of::<PerFrameUBO>() as vk::DeviceSize)
-            .build();
+    let buffer_info = vk::DescriptorBufferInfo::builder()
+        .buffer(uniform_ring.handle)
+        .offset(0)
+        .range(size_of::<PerFrameUBO>() as vk::DeviceSize)
+        .build();
 
-        vk::WriteDescriptorSet::builder()
-            .dst_set(set)
-            .dst_array_element(0)
-            .dst_binding(0)
-            .buffer_info(&[buffer_info])
-            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
-            .build()
+    write_batcher.queue_buffer_write(
+        set,
+        0,
+        0,
+        vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+        buffer_info,
+    );
+
+    set
+}
+
+/// bindless textures at binding 0, materials storage buffer at `MATERIALS_BINDING`
+pub fn new_textures_set(
+    device: &ash::Device,
+    pool: vk::DescriptorPool,
+    textures_set_layout: vk::DescriptorSetLayout,
+    materials_buffer_info: vk::DescriptorBufferInfo,
+    write_batcher: &mut DescriptorWriteBatcher,
+) -> vk::DescriptorSet {
+    let set = unsafe {
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(pool)
+            .set_layouts(&[textures_set_layout])
+            .build();
+        device
+            .allocate_descriptor_sets(&alloc_info).unwrap()[0]
     };
 
-    unsafe {
-        device.update_descriptor_sets(&[write], &[])
-    }
+    write_batcher.queue_buffer_write(
+        set,
+        MATERIALS_BINDING,
+        0,
+        vk::DescriptorType::STORAGE_BUFFER,
+        materials_buffer_info,
+    );
 
     set
 }
@@ -221,28 +229,302 @@ pub fn new_texture_descriptor_update_template(
     unsafe { device.create_descriptor_update_template(&info, None).unwrap() }
 }
 
-pub fn update_textures_descriptor_set(
-    device: &ash::Device,
+#[derive(Clone, Copy)]
+enum PendingInfo {
+    Image(vk::DescriptorImageInfo),
+    Buffer(vk::DescriptorBufferInfo),
+}
 
-    template: vk::DescriptorUpdateTemplate,
+#[derive(Clone, Copy)]
+struct PendingWrite {
+    set: vk::DescriptorSet,
+    binding: u32,
+    array_element: u32,
+    ty: vk::DescriptorType,
+    info: PendingInfo,
+}
 
+struct PendingTemplateUpdate {
     set: vk::DescriptorSet,
-    samplers: &[vk::Sampler],
-    image_views: &[vk::ImageView],
-) {
-    assert!(samplers.len() == image_views.len());
-
-    let image_infos = (0..samplers.len()).map(|i|
-        vk::DescriptorImageInfo {
-            sampler: samplers[i],
-            image_view: image_views[i],
-            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    /// binding fully covered by the template
+    binding: u32,
+    template: vk::DescriptorUpdateTemplate,
+    image_infos: Vec<vk::DescriptorImageInfo>,
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct DescriptorWriteStats {
+    /// descriptor writes requested since the last flush
+    pub queued_writes: usize,
+    /// writes left after dropping overwritten or template covered writes
+    /// and merging consecutive array elements
+    pub vk_writes: usize,
+    /// calls to vkUpdateDescriptorSets and vkUpdateDescriptorSetWithTemplate
+    pub update_calls: usize,
+
+    pub total_queued_writes: usize,
+    pub total_update_calls: usize,
+}
+
+impl DescriptorWriteStats {
+    /// update calls saved compared to eagerly updating every write
+    pub fn saved_update_calls(&self) -> usize {
+        self.total_queued_writes.saturating_sub(self.total_update_calls)
+    }
+}
+
+/// Collects descriptor writes from all systems during the frame
+/// and submits them all at once with a single vkUpdateDescriptorSets call.
+/// Caller must flush only when the written sets are not in use by the device,
+/// the sets are shared by the frames in flight so none of them may still be executing
+pub struct DescriptorWriteBatcher {
+    pending_writes: Vec<PendingWrite>,
+    pending_template_updates: Vec<PendingTemplateUpdate>,
+    pub stats: DescriptorWriteStats,
+}
+
+impl Default for DescriptorWriteBatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DescriptorWriteBatcher {
+    pub fn new() -> Self {
+        Self {
+            pending_writes: vec![],
+            pending_template_updates: vec![],
+            stats: Default::default(),
         }
-    ).collect::<Vec<_>>();
+    }
+
+    pub fn queue_image_write(
+        &mut self,
+        set: vk::DescriptorSet,
+        binding: u32,
+        array_element: u32,
+        ty: vk::DescriptorType,
+        info: vk::DescriptorImageInfo,
+    ) {
+        self.stats.queued_writes += 1;
+        self.pending_writes.push(PendingWrite {
+            set,
+            binding,
+            array_element,
+            ty,
+            info: PendingInfo::Image(info),
+        });
+    }
+
+    pub fn queue_buffer_write(
+        &mut self,
+        set: vk::DescriptorSet,
+        binding: u32,
+        array_element: u32,
+        ty: vk::DescriptorType,
+        info: vk::DescriptorBufferInfo,
+    ) {
+        self.stats.queued_writes += 1;
+        self.pending_writes.push(PendingWrite {
+            set,
+            binding,
+            array_element,
+            ty,
+            info: PendingInfo::Buffer(info),
+        });
+    }
+
+    /// rewrites the whole texture array of `set` through the update template,
+    /// writes to the same binding queued before it become redundant
+    pub fn queue_textures_update(
+        &mut self,
+        set: vk::DescriptorSet,
+        template: vk::DescriptorUpdateTemplate,
+        samplers: &[vk::Sampler],
+        image_views: &[vk::ImageView],
+    ) {
+        assert!(samplers.len() == image_views.len());
+
+        let image_infos = (0..samplers.len()).map(|i|
+            vk::DescriptorImageInfo {
+                sampler: samplers[i],
+                image_view: image_views[i],
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            }
+        ).collect::<Vec<_>>();
+
+        self.stats.queued_writes += image_infos.len();
+        // flushed before the writes, the ones queued after it still apply
+        self.pending_writes.retain(|write| write.set != set || write.binding != 0);
+        self.pending_template_updates.retain(|update| update.set != set || update.binding != 0);
+        self.pending_template_updates.push(PendingTemplateUpdate {
+            set,
+            binding: 0,
+            template,
+            image_infos,
+        });
+    }
+
+    /// nothing to flush
+    pub fn is_empty(&self) -> bool {
+        self.pending_writes.is_empty() && self.pending_template_updates.is_empty()
+    }
+
+    pub fn flush(&mut self, device: &ash::Device, frame_arena: &FrameArena) {
+        for update in &self.pending_template_updates {
+            unsafe { device.update_descriptor_set_with_template(
+                update.set,
+                update.template,
+                update.image_infos.as_ptr() as *const std::ffi::c_void,
+            )};
+            self.stats.update_calls += 1;
+        }
+        self.stats.vk_writes = self.pending_template_updates.len();
+        self.pending_template_updates.clear();
+
+        let ranges = coalesce_writes(&mut self.pending_writes);
+        if !ranges.is_empty() {
+            // infos must not move once writes point into them
+            let image_infos = frame_arena.alloc_slice::<vk::DescriptorImageInfo>(self.pending_writes.len());
+            let buffer_infos = frame_arena.alloc_slice::<vk::DescriptorBufferInfo>(self.pending_writes.len());
+            let (mut image_info_count, mut buffer_info_count) = (0, 0);
+            for write in &self.pending_writes {
+                match write.info {
+                    PendingInfo::Image(info) => {
+                        image_infos[image_info_count] = info;
+                        image_info_count += 1;
+                    }
+                    PendingInfo::Buffer(info) => {
+                        buffer_infos[buffer_info_count] = info;
+                        buffer_info_count += 1;
+                    }
+                }
+            }
+
+            let writes = frame_arena.alloc_slice::<vk::WriteDescriptorSet>(ranges.len());
+            let mut image_info_index = 0;
+            let mut buffer_info_index = 0;
+            for (vk_write, &(start, count)) in writes.iter_mut().zip(&ranges) {
+                let first = &self.pending_writes[start];
+                let mut write = vk::WriteDescriptorSet::builder()
+                    .dst_set(first.set)
+                    .dst_binding(first.binding)
+                    .dst_array_element(first.array_element)
+                    .descriptor_type(first.ty)
+                    .build();
+                write.descriptor_count = count as u32;
+
+                match first.info {
+                    PendingInfo::Image(_) => {
+                        write.p_image_info = image_infos[image_info_index..].as_ptr();
+                        image_info_index += count;
+                    }
+                    PendingInfo::Buffer(_) => {
+                        write.p_buffer_info = buffer_infos[buffer_info_index..].as_ptr();
+                        buffer_info_index += count;
+                    }
+                }
+                *vk_write = write;
+            }
+
+            unsafe { device.update_descriptor_sets(writes, &[]) };
+            self.stats.update_calls += 1;
+            self.stats.vk_writes += writes.len();
+        }
+        self.pending_writes.clear();
+
+        self.stats.total_queued_writes += self.stats.queued_writes;
+        self.stats.total_update_calls += self.stats.update_calls;
+        log::trace!("Flushed descriptor writes: {:?}", self.stats);
+        self.stats.queued_writes = 0;
+        self.stats.update_calls = 0;
+    }
+}
+
+/// Drops writes overwritten by later writes and sorts the rest so that
+/// consecutive array elements of a binding sit next to each other.
+/// Returns (start, count) ranges of writes that can share one vk::WriteDescriptorSet,
+/// images and buffers keep their relative order so the ranges index into them too
+fn coalesce_writes(writes: &mut Vec<PendingWrite>) -> Vec<(usize, usize)> {
+    // stable sort keeps queue order, so the last write to an element wins
+    writes.sort_by_key(|write| (write.set, write.binding, write.array_element));
+    let mut i = writes.len();
+    while i > 1 {
+        i -= 1;
+        let (a, b) = (&writes[i - 1], &writes[i]);
+        if a.set == b.set && a.binding == b.binding && a.array_element == b.array_element {
+            writes.remove(i - 1);
+        }
+    }
+
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    for (i, write) in writes.iter().enumerate() {
+        if let Some((start, count)) = ranges.last_mut() {
+            let previous = &writes[*start + *count - 1];
+            let is_image = matches!(write.info, PendingInfo::Image(_));
+            let previous_is_image = matches!(previous.info, PendingInfo::Image(_));
+            if previous.set == write.set
+                && previous.binding == write.binding
+                && previous.ty == write.ty
+                && previous_is_image == is_image
+                && previous.array_element + 1 == write.array_element
+            {
+                *count += 1;
+                continue;
+            }
+        }
+        ranges.push((i, 1));
+    }
+    ranges
+}
+
+#[test]
+fn test_coalesce_writes() {
+    use ash::vk::Handle;
+
+    let set = vk::DescriptorSet::from_raw(1);
+    let write = |array_element, image_view| PendingWrite {
+        set,
+        binding: 0,
+        array_element,
+        ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        info: PendingInfo::Image(vk::DescriptorImageInfo {
+            image_view: vk::ImageView::from_raw(image_view),
+            ..Default::default()
+        }),
+    };
+
+    let mut writes = vec![write(2, 1), write(0, 1), write(1, 1), write(0, 2), write(5, 1)];
+    let ranges = coalesce_writes(&mut writes);
+
+    assert!(ranges == [(0, 3), (3, 1)]);
+    // later write to element 0 wins
+    match writes[0].info {
+        PendingInfo::Image(info) => assert!(info.image_view.as_raw() == 2),
+        _ => unreachable!(),
+    }
+}
 
-    unsafe { device.update_descriptor_set_with_template(
-        set, 
-        template, 
-        image_infos.as_ptr() as *const std::ffi::c_void,
-    )};
+#[test]
+fn test_textures_update_order() {
+    use ash::vk::Handle;
+
+    let set = vk::DescriptorSet::from_raw(1);
+    let mut batcher = DescriptorWriteBatcher::default();
+    assert!(batcher.is_empty());
+    let queue_write = |batcher: &mut DescriptorWriteBatcher, array_element| batcher.queue_image_write(
+        set,
+        0,
+        array_element,
+        vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        vk::DescriptorImageInfo::default(),
+    );
+
+    // the template overwrites the write before it, the one after lands on top of the template
+    queue_write(&mut batcher, 3);
+    batcher.queue_textures_update(set, vk::DescriptorUpdateTemplate::null(), &[vk::Sampler::null(); 4], &[vk::ImageView::null(); 4]);
+    queue_write(&mut batcher, 1);
+    assert!(batcher.pending_template_updates.len() == 1);
+    assert!(batcher.pending_writes.len() == 1 && batcher.pending_writes[0].array_element == 1);
+    assert!(!batcher.is_empty());
 }
@@ -1,21 +1,170 @@
 use std::{ffi::CStr, rc::Rc};
 
 use ash::{
-    extensions::khr::{Surface, Swapchain},
+    extensions::khr::{DynamicRendering, Surface, Swapchain, Synchronization2},
     vk,
 };
 
+/// highest api version the engine knows how to use
+pub const MAX_API_VERSION: u32 = vk::API_VERSION_1_3;
+
+pub fn get_instance_api_version(entry: &ash::Entry) -> u32 {
+    // vkEnumerateInstanceVersion doesn't exist on 1.0 loaders
+    match entry.try_enumerate_instance_version().unwrap() {
+        Some(api_version) => api_version.min(MAX_API_VERSION),
+        None => vk::API_VERSION_1_0,
+    }
+}
+
+/// Optional features, enabled at device creation when the device has them
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DeviceFeatures {
+    /// lower of the instance and device api versions
+    pub api_version: u32,
+    pub timeline_semaphore: bool,
+    /// core in 1.3, through VK_KHR_synchronization2 on 1.2
+    pub synchronization2: bool,
+    /// core in 1.3, through VK_KHR_dynamic_rendering on 1.2
+    pub dynamic_rendering: bool,
+    /// more than one draw per indirect call
+    pub multi_draw_indirect: bool,
+    /// indirect draws with a first instance other than 0
+    pub draw_indirect_first_instance: bool,
+    /// indirect draws taking their count from a buffer, core in 1.2
+    pub draw_indirect_count: bool,
+    pub pipeline_statistics_query: bool,
+    /// secondary command buffers executed while a query is active
+    pub inherited_queries: bool,
+    /// resizable BAR, device local memory the host can write all of, so uploads skip staging.
+    /// Nothing to enable, only detected
+    pub rebar: bool,
+    /// VK_AMD_buffer_marker, gpu breadcrumbs written as passes finish rather than as they start
+    pub buffer_marker: bool,
+}
+
+impl DeviceFeatures {
+    pub fn uses_dynamic_rendering_extension(&self) -> bool {
+        self.dynamic_rendering && self.api_version < vk::API_VERSION_1_3
+    }
+
+    pub fn uses_synchronization2_extension(&self) -> bool {
+        self.synchronization2 && self.api_version < vk::API_VERSION_1_3
+    }
+}
+
+pub fn query_device_features(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    instance_api_version: u32,
+) -> DeviceFeatures {
+    let props = unsafe { instance.get_physical_device_properties(physical_device) };
+    let api_version = props.api_version.min(instance_api_version);
+
+    let core_features = unsafe { instance.get_physical_device_features(physical_device) };
+    let memory_properties = unsafe { instance.get_physical_device_memory_properties(physical_device) };
+    let mut features = DeviceFeatures {
+        api_version,
+        multi_draw_indirect: core_features.multi_draw_indirect == vk::TRUE,
+        draw_indirect_first_instance: core_features.draw_indirect_first_instance == vk::TRUE,
+        pipeline_statistics_query: core_features.pipeline_statistics_query == vk::TRUE,
+        inherited_queries: core_features.inherited_queries == vk::TRUE,
+        rebar: has_host_visible_device_memory(&memory_properties),
+        ..Default::default()
+    };
+
+    let extensions = unsafe { instance.enumerate_device_extension_properties(physical_device).unwrap() };
+    let has_extension = |name: &CStr| extensions
+        .iter()
+        .any(|ext| unsafe { CStr::from_ptr(ext.extension_name.as_ptr()) } == name);
+    features.buffer_marker = has_extension(vk::AmdBufferMarkerFn::name());
+
+    // TODO: query the 1.1 promoted extensions on older devices
+    if api_version < vk::API_VERSION_1_2 {
+        return features;
+    }
+
+    let has_dynamic_rendering_extension = has_extension(DynamicRendering::name());
+    let has_synchronization2_extension = has_extension(Synchronization2::name());
+
+    let mut vulkan_12_features = vk::PhysicalDeviceVulkan12Features::default();
+    let mut vulkan_13_features = vk::PhysicalDeviceVulkan13Features::default();
+    let mut dynamic_rendering_features = vk::PhysicalDeviceDynamicRenderingFeatures::default();
+    let mut synchronization2_features = vk::PhysicalDeviceSynchronization2Features::default();
+    {
+        let mut features2 = vk::PhysicalDeviceFeatures2::builder()
+            .push_next(&mut vulkan_12_features);
+        if api_version >= vk::API_VERSION_1_3 {
+            features2 = features2.push_next(&mut vulkan_13_features);
+        } else {
+            if has_dynamic_rendering_extension {
+                features2 = features2.push_next(&mut dynamic_rendering_features);
+            }
+            if has_synchronization2_extension {
+                features2 = features2.push_next(&mut synchronization2_features);
+            }
+        }
+        unsafe { instance.get_physical_device_features2(physical_device, &mut features2) };
+    }
+
+    features.timeline_semaphore = vulkan_12_features.timeline_semaphore == vk::TRUE;
+    features.draw_indirect_count = vulkan_12_features.draw_indirect_count == vk::TRUE;
+    features.synchronization2 = vulkan_13_features.synchronization2 == vk::TRUE
+        || synchronization2_features.synchronization2 == vk::TRUE;
+    features.dynamic_rendering = vulkan_13_features.dynamic_rendering == vk::TRUE
+        || dynamic_rendering_features.dynamic_rendering == vk::TRUE;
+
+    features
+}
+
+/// whether a device local, host visible and coherent memory type spans more than the 256 MiB
+/// window discrete gpus expose without resizable BAR, integrated gpus have one too
+pub fn has_host_visible_device_memory(memory_properties: &vk::PhysicalDeviceMemoryProperties) -> bool {
+    const BAR_WINDOW_SIZE: vk::DeviceSize = 256 << 20;
+    let flags = vk::MemoryPropertyFlags::DEVICE_LOCAL
+        | vk::MemoryPropertyFlags::HOST_VISIBLE
+        | vk::MemoryPropertyFlags::HOST_COHERENT;
+    memory_properties.memory_types[..memory_properties.memory_type_count as usize]
+        .iter()
+        .any(|memory_type| {
+            memory_type.property_flags.contains(flags)
+                && memory_properties.memory_heaps[memory_type.heap_index as usize].size > BAR_WINDOW_SIZE
+        })
+}
+
+/// features every device must have, enabled unconditionally
+fn has_required_features(features: &vk::PhysicalDeviceFeatures) -> bool {
+    features.sampler_anisotropy == vk::TRUE && features.image_cube_array == vk::TRUE
+}
+
+fn get_device_name(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> String {
+    let props = unsafe { instance.get_physical_device_properties(physical_device) };
+    unsafe { CStr::from_ptr(props.device_name.as_ptr()) }.to_string_lossy().into_owned()
+}
+
+/// the first suitable device, trying devices whose name contains `preferred_name` first, in any case
 pub fn get_physical_device_and_queue_family_indices(
     instance: &ash::Instance,
     surface: &Surface,
     surface_khr: vk::SurfaceKHR,
+    preferred_name: Option<&str>,
 ) -> (vk::PhysicalDevice, u32, u32, u32) {
-    let physical_devices = unsafe { instance.enumerate_physical_devices() }.unwrap();
+    let mut physical_devices = unsafe { instance.enumerate_physical_devices() }.unwrap();
+    if let Some(preferred_name) = preferred_name {
+        let preferred_name = preferred_name.to_lowercase();
+        let is_preferred = |&physical_device: &vk::PhysicalDevice| {
+            get_device_name(instance, physical_device).to_lowercase().contains(&preferred_name)
+        };
+        if !physical_devices.iter().any(is_preferred) {
+            log::warn!("No device named like {}, picking another", preferred_name);
+        }
+        // stable, so the preferred ones keep their order
+        physical_devices.sort_by_key(|physical_device| !is_preferred(physical_device));
+    }
 
     let mut physical_device = physical_devices[0];
     let mut extension_support = check_device_extension_support(instance, physical_device);
     let features = unsafe { instance.get_physical_device_features(physical_device) };
-    let mut feature_support = features.sampler_anisotropy == vk::TRUE;
+    let mut feature_support = has_required_features(&features);
     let (mut graphics, mut present, mut transfer) =
         find_queue_family_indices(physical_device, surface, surface_khr, instance);
 
@@ -33,15 +182,12 @@ pub fn get_physical_device_and_queue_family_indices(
         (graphics, present, transfer) =
             find_queue_family_indices(physical_device, surface, surface_khr, &instance);
         let features = unsafe { instance.get_physical_device_features(physical_device) };
-        feature_support = features.sampler_anisotropy == vk::TRUE;
+        feature_support = has_required_features(&features);
 
         i += 1;
     }
 
-    let props = unsafe { instance.get_physical_device_properties(physical_device) };
-    log::debug!("Selected physical device: {:?}", unsafe {
-        CStr::from_ptr(props.device_name.as_ptr())
-    });
+    log::info!("Selected physical device: {}", get_device_name(instance, physical_device));
 
     (
         physical_device,
@@ -93,13 +239,29 @@ fn find_queue_family_indices(
     (graphics, present, transfer)
 }
 
+/// a family with compute but no graphics, whose queues run alongside the graphics queue
+pub fn find_async_compute_family_index(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> Option<u32> {
+    let props = unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+    props
+        .iter()
+        .position(|family_props| {
+            family_props.queue_count > 0
+                && family_props.queue_flags.contains(vk::QueueFlags::COMPUTE)
+                && !family_props.queue_flags.contains(vk::QueueFlags::GRAPHICS)
+        })
+        .map(|index| index as u32)
+}
+
+/// the compute queue only when `compute_family_index` is given
 pub fn new_logical_device_and_queues(
     instance: &ash::Instance,
     physical_device: vk::PhysicalDevice,
     graphics_family_index: u32,
     present_family_index: u32,
     transfer_family_index: u32,
-) -> (Rc<ash::Device>, vk::Queue, vk::Queue, vk::Queue) {
+    compute_family_index: Option<u32>,
+    features: &DeviceFeatures,
+) -> (Rc<ash::Device>, vk::Queue, vk::Queue, vk::Queue, Option<vk::Queue>) {
     let queue_priorities = [1.0];
 
     let queue_infos = {
@@ -108,6 +270,9 @@ pub fn new_logical_device_and_queues(
             present_family_index,
             transfer_family_index,
         ];
+        indices.extend(compute_family_index);
+        // one create info per family
+        indices.sort();
         indices.dedup();
 
         indices
@@ -125,15 +290,67 @@ pub fn new_logical_device_and_queues(
 
     let physical_device_features = vk::PhysicalDeviceFeatures::builder()
         .fill_mode_non_solid(true)
-        .sampler_anisotropy(true);
-
-    let (_, device_extension_name_ptrs) = &get_device_extension_names_and_ptrs();
+        .sampler_anisotropy(true)
+        // reflection probes are sampled from a cubemap array
+        .image_cube_array(true)
+        // materials index the textures array with push constants
+        .shader_sampled_image_array_dynamic_indexing(true)
+        .multi_draw_indirect(features.multi_draw_indirect)
+        .draw_indirect_first_instance(features.draw_indirect_first_instance)
+        .pipeline_statistics_query(features.pipeline_statistics_query)
+        .inherited_queries(features.inherited_queries)
+        .build();
+
+    let (_, mut device_extension_name_ptrs) = get_device_extension_names_and_ptrs();
+    if features.uses_dynamic_rendering_extension() {
+        device_extension_name_ptrs.push(DynamicRendering::name().as_ptr());
+    }
+    if features.uses_synchronization2_extension() {
+        device_extension_name_ptrs.push(Synchronization2::name().as_ptr());
+    }
+    if features.buffer_marker {
+        device_extension_name_ptrs.push(vk::AmdBufferMarkerFn::name().as_ptr());
+    }
+    // must be enabled on portability implementations like MoltenVK
+    #[cfg(target_os = "macos")]
+    device_extension_name_ptrs.push(vk::KhrPortabilitySubsetFn::name().as_ptr());
+
+    let mut features2 = vk::PhysicalDeviceFeatures2::builder()
+        .features(physical_device_features);
+    let mut vulkan_12_features = vk::PhysicalDeviceVulkan12Features::builder()
+        .timeline_semaphore(features.timeline_semaphore)
+        .draw_indirect_count(features.draw_indirect_count);
+    let mut vulkan_13_features = vk::PhysicalDeviceVulkan13Features::builder()
+        .synchronization2(features.synchronization2)
+        .dynamic_rendering(features.dynamic_rendering);
+    let mut dynamic_rendering_features = vk::PhysicalDeviceDynamicRenderingFeatures::builder()
+        .dynamic_rendering(true);
+    let mut synchronization2_features = vk::PhysicalDeviceSynchronization2Features::builder()
+        .synchronization2(true);
 
     let mut info = vk::DeviceCreateInfo::builder()
         .queue_create_infos(&queue_infos)
-        .enabled_features(&physical_device_features)
         .enabled_extension_names(&device_extension_name_ptrs);
 
+    // the 1.2+ feature structs can only be chained through features2
+    if features.api_version >= vk::API_VERSION_1_2 {
+        info = info
+            .push_next(&mut features2)
+            .push_next(&mut vulkan_12_features);
+        if features.api_version >= vk::API_VERSION_1_3 {
+            info = info.push_next(&mut vulkan_13_features);
+        } else {
+            if features.dynamic_rendering {
+                info = info.push_next(&mut dynamic_rendering_features);
+            }
+            if features.synchronization2 {
+                info = info.push_next(&mut synchronization2_features);
+            }
+        }
+    } else {
+        info = info.enabled_features(&physical_device_features);
+    }
+
     #[cfg(debug_assertions)]
     {
         info = info.enabled_layer_names(&layer_name_ptrs);
@@ -146,12 +363,14 @@ pub fn new_logical_device_and_queues(
         let graphics_queue = device.get_device_queue(graphics_family_index, 0);
         let present_queue = device.get_device_queue(present_family_index, 0);
         let transfer_queue = device.get_device_queue(transfer_family_index, 0);
+        let compute_queue = compute_family_index.map(|index| device.get_device_queue(index, 0));
 
         (
             Rc::from(device),
             graphics_queue,
             present_queue,
             transfer_queue,
+            compute_queue,
         )
     }
 }
@@ -208,11 +427,13 @@ pub fn find_mem_type_index(
     panic!("Could not find suitable memory type");
 }
 
+/// float formats first, reverse-Z relies on them
 pub fn find_depth_format(instance: &ash::Instance, device: vk::PhysicalDevice) -> vk::Format {
+    // stencil for outlines, depth only formats leave them off
     const CANDIDATES: [vk::Format; 3] = [
-        vk::Format::D32_SFLOAT,
         vk::Format::D32_SFLOAT_S8_UINT,
         vk::Format::D24_UNORM_S8_UINT,
+        vk::Format::D32_SFLOAT,
     ];
 
     find_supported_format(
@@ -225,6 +446,11 @@ pub fn find_depth_format(instance: &ash::Instance, device: vk::PhysicalDevice) -
     .expect("Failed to find a supported depth format")
 }
 
+/// float depth keeps its precision with reverse-Z, where most of the range is close to 0
+pub fn is_float_depth_format(format: vk::Format) -> bool {
+    matches!(format, vk::Format::D32_SFLOAT | vk::Format::D32_SFLOAT_S8_UINT)
+}
+
 /// Find the first compatible format from `candidates`.
 pub fn find_supported_format(
     instance: &ash::Instance,
@@ -249,3 +475,30 @@ pub fn find_supported_format(
         _ => None,
     }
 }
+
+#[test]
+fn test_host_visible_device_memory() {
+    let mut memory_properties = vk::PhysicalDeviceMemoryProperties {
+        memory_type_count: 2,
+        memory_heap_count: 2,
+        ..Default::default()
+    };
+    memory_properties.memory_heaps[0].size = 8 << 30;
+    memory_properties.memory_heaps[1].size = 256 << 20;
+    memory_properties.memory_types[0].property_flags = vk::MemoryPropertyFlags::DEVICE_LOCAL;
+    memory_properties.memory_types[1] = vk::MemoryType {
+        property_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL
+            | vk::MemoryPropertyFlags::HOST_VISIBLE
+            | vk::MemoryPropertyFlags::HOST_COHERENT,
+        heap_index: 1,
+    };
+    // only the 256 MiB window is host visible
+    assert!(!has_host_visible_device_memory(&memory_properties));
+
+    memory_properties.memory_types[1].heap_index = 0;
+    assert!(has_host_visible_device_memory(&memory_properties));
+
+    // types past the count don't exist
+    memory_properties.memory_type_count = 1;
+    assert!(!has_host_visible_device_memory(&memory_properties));
+}
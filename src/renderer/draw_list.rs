@@ -0,0 +1,198 @@
+// Draw submissions sorted by a packed 64 bit key, so draws sharing state end up next to each other.
+// From the most to the least significant bits the key holds the pipeline's slot in the frame,
+// the material's and the geometry's handle indices:
+//
+//     | pipeline slot: 16 | material index: 16 | geometry index: 16 | unused: 16 |
+//
+// Keys are radix sorted, which keeps submission order between equal keys.
+// `StateTracker` then skips binding what is already bound while recording
+
+use ash::vk;
+
+use crate::geometry::GeometryId;
+use super::{batch::{DrawKey, PipelineId}, material::MaterialId};
+
+/// bits per radix sort pass
+const RADIX_BITS: u32 = 8;
+const RADIX_SIZE: usize = 1 << RADIX_BITS;
+
+pub fn sort_key(pipeline_slot: u16, material: MaterialId, geometry: GeometryId) -> u64 {
+    (pipeline_slot as u64) << 48 | (material.index() as u64) << 32 | (geometry.index() as u64) << 16
+}
+
+/// stable least significant digit first sort of `items` by their keys,
+/// digits every key shares are skipped
+pub fn radix_sort<T: Copy>(items: &mut [(u64, T)], scratch: &mut Vec<(u64, T)>) {
+    for shift in (0..u64::BITS).step_by(RADIX_BITS as usize) {
+        let digit = |key: u64| ((key >> shift) as usize) & (RADIX_SIZE - 1);
+
+        let mut counts = [0; RADIX_SIZE];
+        for &(key, _) in items.iter() {
+            counts[digit(key)] += 1;
+        }
+        if counts.contains(&items.len()) {
+            continue;
+        }
+
+        let mut offsets = [0; RADIX_SIZE];
+        for i in 1..RADIX_SIZE {
+            offsets[i] = offsets[i - 1] + counts[i - 1];
+        }
+        scratch.clear();
+        scratch.extend_from_slice(items);
+        for &item in scratch.iter() {
+            let offset = &mut offsets[digit(item.0)];
+            items[*offset] = item;
+            *offset += 1;
+        }
+    }
+}
+
+/// Collects a frame's draws, `sort` then visits them grouped by pipeline, material and geometry
+pub struct DrawList<T> {
+    /// index is the pipeline's slot, in order of first submission
+    pipelines: Vec<PipelineId>,
+    draws: Vec<(DrawKey, T)>,
+    /// sort key and index into `draws`, sorted by `sort`
+    order: Vec<(u64, u32)>,
+    scratch: Vec<(u64, u32)>,
+}
+
+impl<T> Default for DrawList<T> {
+    fn default() -> Self {
+        Self {
+            pipelines: vec![],
+            draws: vec![],
+            order: vec![],
+            scratch: vec![],
+        }
+    }
+}
+
+impl<T> DrawList<T> {
+    pub fn submit(&mut self, key: DrawKey, instance: T) {
+        let pipeline_slot = match self.pipelines.iter().position(|&pipeline| pipeline == key.pipeline) {
+            Some(slot) => slot,
+            None => {
+                self.pipelines.push(key.pipeline);
+                self.pipelines.len() - 1
+            }
+        };
+        assert!(pipeline_slot <= u16::MAX as usize, "Out of draw list pipeline slots");
+
+        self.order.push((sort_key(pipeline_slot as u16, key.material, key.geometry), self.draws.len() as u32));
+        self.draws.push((key, instance));
+    }
+
+    pub fn len(&self) -> usize {
+        self.draws.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.draws.is_empty()
+    }
+
+    pub fn sort(&mut self) {
+        radix_sort(&mut self.order, &mut self.scratch);
+    }
+
+    /// in sorted order once `sort` ran since the last submission, otherwise in submission order
+    pub fn iter(&self) -> impl Iterator<Item = &(DrawKey, T)> {
+        self.order.iter().map(|&(_, index)| &self.draws[index as usize])
+    }
+
+    pub fn clear(&mut self) {
+        self.pipelines.clear();
+        self.draws.clear();
+        self.order.clear();
+    }
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct BindCounters {
+    pub pipeline_binds: usize,
+    pub material_binds: usize,
+    /// binds skipped as the state was already bound
+    pub skipped_binds: usize,
+}
+
+/// What is bound while recording, `M` identifies materials, e.g. by id or push constants,
+/// `P` pipelines, by handle or by `PipelineId` before they're resolved
+pub struct StateTracker<M, P = vk::Pipeline> {
+    pipeline: Option<P>,
+    material: Option<M>,
+    pub counters: BindCounters,
+}
+
+impl<M: Copy + PartialEq, P: Copy + PartialEq> Default for StateTracker<M, P> {
+    fn default() -> Self {
+        Self {
+            pipeline: None,
+            material: None,
+            counters: Default::default(),
+        }
+    }
+}
+
+impl<M: Copy + PartialEq, P: Copy + PartialEq> StateTracker<M, P> {
+    /// true when `pipeline` has to be bound
+    pub fn bind_pipeline(&mut self, pipeline: P) -> bool {
+        if self.pipeline == Some(pipeline) {
+            self.counters.skipped_binds += 1;
+            return false;
+        }
+        self.pipeline = Some(pipeline);
+        self.counters.pipeline_binds += 1;
+        true
+    }
+
+    /// true when `material` has to be pushed
+    pub fn bind_material(&mut self, material: M) -> bool {
+        if self.material == Some(material) {
+            self.counters.skipped_binds += 1;
+            return false;
+        }
+        self.material = Some(material);
+        self.counters.material_binds += 1;
+        true
+    }
+}
+
+#[test]
+fn test_draw_list() {
+    use crate::data_structures::handle_map;
+
+    let mut keys: Vec<(u64, char)> = [0x0300, 0x0102, 0x0300, 0x0001, 0x0102, 0xff00_0000_0000_0000, 0]
+        .into_iter()
+        .zip("abcdefg".chars())
+        .collect();
+    radix_sort(&mut keys, &mut vec![]);
+    assert!(keys.iter().map(|&(_, value)| value).collect::<String>() == "gdbeacf");
+
+    let (opaque, masked) = (PipelineId::Pbr, PipelineId::Unlit);
+    let key = |pipeline, material, geometry| DrawKey {
+        pipeline,
+        material: handle_map::Handle::from_raw_parts(material, 0),
+        geometry: handle_map::Handle::from_raw_parts(geometry, 0),
+    };
+    let mut draw_list = DrawList::default();
+    draw_list.submit(key(opaque, 1, 0), 'a');
+    draw_list.submit(key(masked, 0, 0), 'b');
+    draw_list.submit(key(opaque, 0, 2), 'c');
+    draw_list.submit(key(opaque, 1, 0), 'd');
+    draw_list.submit(key(opaque, 0, 1), 'e');
+    draw_list.sort();
+    // pipelines in order of first submission
+    assert!(draw_list.iter().map(|&(_, value)| value).collect::<String>() == "ecadb");
+
+    let mut tracker = StateTracker::default();
+    for (key, _) in draw_list.iter() {
+        tracker.bind_pipeline(key.pipeline);
+        tracker.bind_material(key.material);
+    }
+    let counters = tracker.counters;
+    assert!(counters.pipeline_binds == 2 && counters.material_binds == 3 && counters.skipped_binds == 5);
+
+    draw_list.clear();
+    assert!(draw_list.is_empty() && draw_list.iter().next().is_none());
+}
@@ -0,0 +1,435 @@
+// Screen space outlines for a stylized look, an alternative to outlining selected draws with the
+// stencil buffer in outline.rs. After the scene pass a fullscreen triangle runs a Sobel filter over
+// the distances to the camera and the normals around each pixel, both reconstructed from the depth
+// buffer, and blends the outline color over the scene where either changes sharply, which outlines
+// silhouettes as well as creases of every draw:
+//
+//     app.edge_outlines.enabled = true;
+//     app.edge_outlines.color = [0.0, 0.0, 0.0, 1.0];
+//     app.edge_outlines.thickness = 2.0;
+//
+// Only what's in the depth buffer is outlined, translucent draws, particles and sprites aren't.
+// Draws with alpha blending, so the scene color keeps its encoding
+
+use std::{mem::size_of, rc::Rc};
+
+use ash::vk;
+
+use super::{descriptor::DescriptorWriteBatcher, image, pipeline, shader, swapchain::OutputTransfer};
+
+/// must match the push constant block in edge_outline.frag
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct EdgePushConstants {
+    color: [f32; 4],
+    viewport_offset: [i32; 2],
+    viewport_size: [i32; 2],
+    spacing: i32,
+    depth_threshold: f32,
+    normal_threshold: f32,
+    clear_depth: f32,
+}
+
+impl EdgePushConstants {
+    const RANGE: vk::PushConstantRange = vk::PushConstantRange {
+        stage_flags: vk::ShaderStageFlags::FRAGMENT,
+        offset: 0,
+        size: size_of::<Self>() as u32,
+    };
+}
+
+/// pixels between the filter's taps for outlines about `thickness` pixels wide
+pub fn tap_spacing(thickness: f32) -> i32 {
+    (thickness.round() as i32).max(1)
+}
+
+/// magnitude of the Sobel gradient of a 3x3 neighbourhood, rows from top to bottom,
+/// as edge_outline.frag takes it of the distances and normals
+pub fn sobel(samples: [[f32; 3]; 3]) -> f32 {
+    let (mut x, mut y) = (0.0, 0.0);
+    for (row, samples) in samples.iter().enumerate() {
+        for (column, &sample) in samples.iter().enumerate() {
+            let (dx, dy) = (column as f32 - 1.0, row as f32 - 1.0);
+            x += dx * if dy == 0.0 { 2.0 } else { 1.0 } * sample;
+            y += dy * if dx == 0.0 { 2.0 } else { 1.0 } * sample;
+        }
+    }
+    (x * x + y * y).sqrt()
+}
+
+/// what the outlines read of the frame's scene pass
+pub struct SceneInputs {
+    /// the swapchain image or the render scaled target
+    pub color_image: vk::Image,
+    /// the scene pass left it in
+    pub color_layout: vk::ImageLayout,
+    /// index of the framebuffer over the color image, as in the views given to `resize`
+    pub image_index: usize,
+    pub depth_image: vk::Image,
+    pub depth_format: vk::Format,
+    /// the letterboxed part of the scene
+    pub viewport: vk::Rect2D,
+    pub per_frame_ubo_set: vk::DescriptorSet,
+    pub view_ubo_offset: u32,
+    pub clear_depth: f32,
+}
+
+/// `renew_pipeline` when the scene's color format or output transfer changes, `resize` along with
+/// the depth buffer and `cmd_draw` after the scene pass, outside of any render pass
+pub struct EdgeOutlines {
+    device: Rc<ash::Device>,
+    /// off by default, while off nothing is drawn
+    pub enabled: bool,
+    /// straight alpha
+    pub color: [f32; 4],
+    /// in pixels, rounded
+    pub thickness: f32,
+    /// relative change in distance to the camera outlined, lower outlines smaller steps
+    pub depth_threshold: f32,
+    /// change in normal outlined, lower outlines shallower creases
+    pub normal_threshold: f32,
+
+    /// loads the scene color and keeps it in COLOR_ATTACHMENT_OPTIMAL
+    render_pass: vk::RenderPass,
+    /// one per scene color view
+    framebuffers: Vec<vk::Framebuffer>,
+    /// depth sampled with texelFetch
+    sampler: vk::Sampler,
+    /// reset on resize
+    descriptor_pool: vk::DescriptorPool,
+    set_layout: vk::DescriptorSetLayout,
+    set: vk::DescriptorSet,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl EdgeOutlines {
+    pub fn new(device: Rc<ash::Device>) -> Self {
+        let sampler = unsafe {
+            let info = vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::NEAREST)
+                .min_filter(vk::Filter::NEAREST)
+                .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE);
+            device.create_sampler(&info, None).unwrap()
+        };
+        let set_layout = unsafe {
+            let bindings = [vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build()];
+            let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+            device.create_descriptor_set_layout(&info, None).unwrap()
+        };
+        let descriptor_pool = unsafe {
+            let pool_sizes = [vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 1,
+            }];
+            let info = vk::DescriptorPoolCreateInfo::builder()
+                .max_sets(1)
+                .pool_sizes(&pool_sizes);
+            device.create_descriptor_pool(&info, None).expect("Failed to create descriptor pool")
+        };
+
+        Self {
+            device,
+            enabled: false,
+            color: [0.0, 0.0, 0.0, 1.0],
+            thickness: 1.0,
+            depth_threshold: 0.5,
+            normal_threshold: 0.8,
+
+            render_pass: vk::RenderPass::null(),
+            framebuffers: vec![],
+            sampler,
+            descriptor_pool,
+            set_layout,
+            set: vk::DescriptorSet::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            pipeline: vk::Pipeline::null(),
+        }
+    }
+
+    /// the framebuffers must be `resize`d after a change of `color_format`
+    pub fn renew_pipeline(
+        &mut self,
+        shader_compiler: &shader::ShaderCompiler,
+        color_format: vk::Format,
+        per_frame_ubo_set_layout: vk::DescriptorSetLayout,
+        output_transfer: OutputTransfer,
+    ) {
+        unsafe { self.destroy_pipeline(); }
+
+        self.render_pass = unsafe {
+            let attachments = [vk::AttachmentDescription::builder()
+                .format(color_format)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .load_op(vk::AttachmentLoadOp::LOAD)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .initial_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .build()];
+            let color_attachment_refs = [vk::AttachmentReference {
+                attachment: 0,
+                layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            }];
+            let subpasses = [vk::SubpassDescription::builder()
+                .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+                .color_attachments(&color_attachment_refs)
+                .build()];
+            let info = vk::RenderPassCreateInfo::builder()
+                .attachments(&attachments)
+                .subpasses(&subpasses);
+            self.device.create_render_pass(&info, None).unwrap()
+        };
+
+        (self.pipeline, self.pipeline_layout) = pipeline::new_pipeline_and_layout(
+            &self.device,
+            shader_compiler,
+            &pipeline::PipelineDesc {
+                render_pass: self.render_pass,
+                color_formats: &[color_format],
+                set_layouts: &[self.set_layout, per_frame_ubo_set_layout],
+                push_constant_ranges: &[EdgePushConstants::RANGE],
+                vertex_shader_path: "shaders/fullscreen.vert",
+                fragment_shader_path: "shaders/edge_outline.frag",
+                blend_mode: pipeline::BlendMode::Alpha,
+                cull_mode: vk::CullModeFlags::NONE,
+                depth_test: false,
+                depth_write: false,
+                output_transfer,
+                ..Default::default()
+            },
+        );
+    }
+
+    /// replaces the framebuffers over `color_views` of a scene of `extent`, `scene_depth_view`
+    /// samples the depth buffer's depth aspect. The previous ones must no longer be in use
+    pub fn resize(
+        &mut self,
+        write_batcher: &mut DescriptorWriteBatcher,
+        color_views: &[vk::ImageView],
+        extent: vk::Extent2D,
+        scene_depth_view: vk::ImageView,
+    ) {
+        unsafe { self.destroy_targets(); }
+
+        self.framebuffers = color_views
+            .iter()
+            .map(|&color_view| unsafe {
+                let attachments = [color_view];
+                let info = vk::FramebufferCreateInfo::builder()
+                    .render_pass(self.render_pass)
+                    .attachments(&attachments)
+                    .width(extent.width)
+                    .height(extent.height)
+                    .layers(1);
+                self.device.create_framebuffer(&info, None).unwrap()
+            })
+            .collect();
+
+        self.set = unsafe {
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(self.descriptor_pool)
+                .set_layouts(&[self.set_layout])
+                .build();
+            self.device.allocate_descriptor_sets(&alloc_info).unwrap()[0]
+        };
+        write_batcher.queue_image_write(
+            self.set,
+            0,
+            0,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            vk::DescriptorImageInfo {
+                sampler: self.sampler,
+                image_view: scene_depth_view,
+                image_layout: vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            },
+        );
+    }
+
+    /// outlines the scene the frame's scene pass drew, record after it.
+    /// Leaves the scene's images in the layouts they were in
+    pub fn cmd_draw(&self, command_buffer: vk::CommandBuffer, scene: &SceneInputs) {
+        if !self.enabled || self.framebuffers.is_empty() {
+            return;
+        }
+
+        let barrier = |image, aspect_mask, old_layout, new_layout, src_access_mask, dst_access_mask| {
+            vk::ImageMemoryBarrier::builder()
+                .old_layout(old_layout)
+                .new_layout(new_layout)
+                .src_access_mask(src_access_mask)
+                .dst_access_mask(dst_access_mask)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(image)
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask,
+                    base_mip_level: 0,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .build()
+        };
+        let depth_aspect_mask = image::get_depth_aspect_mask(scene.depth_format);
+        let viewport = scene.viewport;
+        let push_constants = EdgePushConstants {
+            color: self.color,
+            viewport_offset: [viewport.offset.x, viewport.offset.y],
+            viewport_size: [viewport.extent.width as i32, viewport.extent.height as i32],
+            spacing: tap_spacing(self.thickness),
+            depth_threshold: self.depth_threshold,
+            normal_threshold: self.normal_threshold,
+            clear_depth: scene.clear_depth,
+        };
+        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.render_pass)
+            .framebuffer(self.framebuffers[scene.image_index])
+            .render_area(viewport);
+
+        unsafe {
+            // after the scene pass and whatever post passes blitted over its color since
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[
+                    barrier(
+                        scene.color_image,
+                        vk::ImageAspectFlags::COLOR,
+                        scene.color_layout,
+                        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                        vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::TRANSFER_WRITE,
+                        vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                    ),
+                    barrier(
+                        scene.depth_image,
+                        depth_aspect_mask,
+                        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                        vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+                        vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                        vk::AccessFlags::SHADER_READ,
+                    ),
+                ],
+            );
+
+            self.device.cmd_begin_render_pass(command_buffer, &render_pass_begin_info, vk::SubpassContents::INLINE);
+            self.device.cmd_set_viewport(command_buffer, 0, &[vk::Viewport {
+                x: viewport.offset.x as f32,
+                y: viewport.offset.y as f32,
+                width: viewport.extent.width as f32,
+                height: viewport.extent.height as f32,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            }]);
+            self.device.cmd_set_scissor(command_buffer, 0, &[viewport]);
+            self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[self.set, scene.per_frame_ubo_set],
+                &[scene.view_ubo_offset],
+            );
+            self.device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                EdgePushConstants::RANGE.stage_flags,
+                0,
+                std::slice::from_raw_parts(
+                    &push_constants as *const EdgePushConstants as *const u8,
+                    size_of::<EdgePushConstants>(),
+                ),
+            );
+            self.device.cmd_draw(command_buffer, 3, 1, 0, 0);
+            self.device.cmd_end_render_pass(command_buffer);
+
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::PipelineStageFlags::TRANSFER
+                    | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[
+                    barrier(
+                        scene.color_image,
+                        vk::ImageAspectFlags::COLOR,
+                        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                        scene.color_layout,
+                        vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                        vk::AccessFlags::TRANSFER_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                    ),
+                    barrier(
+                        scene.depth_image,
+                        depth_aspect_mask,
+                        vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+                        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                        vk::AccessFlags::empty(),
+                        vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                            | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                    ),
+                ],
+            );
+        }
+    }
+
+    unsafe fn destroy_pipeline(&mut self) {
+        if self.pipeline != vk::Pipeline::null() {
+            self.device.destroy_pipeline(self.pipeline, None);
+            self.device.destroy_pipeline_layout(self.pipeline_layout, None);
+            self.device.destroy_render_pass(self.render_pass, None);
+        }
+        self.pipeline = vk::Pipeline::null();
+    }
+
+    unsafe fn destroy_targets(&mut self) {
+        for framebuffer in self.framebuffers.drain(..) {
+            self.device.destroy_framebuffer(framebuffer, None);
+        }
+        self.device.reset_descriptor_pool(self.descriptor_pool, vk::DescriptorPoolResetFlags::empty()).unwrap();
+    }
+
+    // caller must ensure only called once
+    pub unsafe fn destroy(&mut self) {
+        self.destroy_targets();
+        self.destroy_pipeline();
+        self.device.destroy_descriptor_pool(self.descriptor_pool, None);
+        self.device.destroy_descriptor_set_layout(self.set_layout, None);
+        self.device.destroy_sampler(self.sampler, None);
+    }
+}
+
+#[test]
+fn test_edge_outline() {
+    // at least one pixel apart
+    assert!(tap_spacing(0.0) == 1 && tap_spacing(1.0) == 1 && tap_spacing(2.6) == 3);
+
+    // flat neighbourhoods have no edge, steps do whichever way they go
+    assert!(sobel([[3.0; 3]; 3]) == 0.0);
+    let vertical_step = [[0.0, 1.0, 1.0]; 3];
+    let horizontal_step = [[0.0; 3], [1.0; 3], [1.0; 3]];
+    assert!(sobel(vertical_step) == 4.0 && sobel(horizontal_step) == 4.0);
+    assert!(sobel([[1.0, 1.0, 0.0]; 3]) == 4.0);
+    // a single differing pixel next to the center weighs twice as much as one in a corner
+    let mut side = [[0.0; 3]; 3];
+    side[1][2] = 1.0;
+    let mut corner = [[0.0; 3]; 3];
+    corner[0][2] = 1.0;
+    assert!(sobel(side) == 2.0 && (sobel(corner) - 2.0f32.sqrt()).abs() < 1e-6);
+}
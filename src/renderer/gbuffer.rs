@@ -0,0 +1,153 @@
+use std::rc::Rc;
+
+use ash::vk;
+
+/// albedo, view independent normal
+/// must match the push constant block in deferred_lighting.frag
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct LightingPushConstants {
+    pub clear_color: [f32; 4],
+    pub light_direction: [f32; 4],
+    pub light_color: [f32; 4],
+    /// to find pixels nothing was drawn to
+    pub clear_depth: f32,
+}
+
+impl LightingPushConstants {
+    pub const RANGE: vk::PushConstantRange = vk::PushConstantRange {
+        stage_flags: vk::ShaderStageFlags::FRAGMENT,
+        offset: 0,
+        size: std::mem::size_of::<Self>() as u32,
+    };
+}
+
+pub const GBUFFER_FORMATS: [vk::Format; 2] = [
+    vk::Format::R8G8B8A8_UNORM,
+    vk::Format::R16G16B16A16_SFLOAT,
+];
+
+/// Render targets written by the geometry subpass of the deferred path
+/// and read by the lighting subpass as input attachments
+pub struct GBuffer {
+    device: Rc<ash::Device>,
+    images: Vec<vk::Image>,
+    memories: Vec<vk::DeviceMemory>,
+    pub image_views: Vec<vk::ImageView>,
+}
+
+impl GBuffer {
+    pub fn new(
+        device: Rc<ash::Device>,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        extent: vk::Extent2D,
+    ) -> Self {
+        let mut images = Vec::with_capacity(GBUFFER_FORMATS.len());
+        let mut memories = Vec::with_capacity(GBUFFER_FORMATS.len());
+        let mut image_views = Vec::with_capacity(GBUFFER_FORMATS.len());
+
+        for format in GBUFFER_FORMATS {
+            let (image, memory) = super::image::new_image_and_memory(
+                &device,
+                physical_device_memory_properties,
+                &super::image::ImageDesc {
+                    width: extent.width,
+                    height: extent.height,
+                    usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::INPUT_ATTACHMENT,
+                    format,
+                    ..Default::default()
+                },
+            );
+            images.push(image);
+            memories.push(memory);
+            image_views.push(super::image::new_image_view(
+                &device,
+                image,
+                format,
+                vk::ImageAspectFlags::COLOR,
+                1,
+            ));
+        }
+
+        Self {
+            device,
+            images,
+            memories,
+            image_views,
+        }
+    }
+
+    // caller must ensure only called once
+    pub unsafe fn destroy(&mut self) {
+        for i in 0..self.images.len() {
+            self.device.destroy_image_view(self.image_views[i], None);
+            self.device.destroy_image(self.images[i], None);
+            self.device.free_memory(self.memories[i], None);
+        }
+    }
+}
+
+/// g-buffer attachments followed by depth, all as input attachments of the lighting subpass
+pub fn new_gbuffer_set_layout(device: &ash::Device) -> vk::DescriptorSetLayout {
+    let bindings = (0..GBUFFER_FORMATS.len() as u32 + 1)
+        .map(|binding| vk::DescriptorSetLayoutBinding::builder()
+            .binding(binding)
+            .descriptor_type(vk::DescriptorType::INPUT_ATTACHMENT)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build())
+        .collect::<Vec<_>>();
+
+    let info = vk::DescriptorSetLayoutCreateInfo::builder()
+        .bindings(&bindings)
+        .build();
+
+    unsafe { device.create_descriptor_set_layout(&info, None).unwrap() }
+}
+
+pub fn new_gbuffer_set(
+    device: &ash::Device,
+    pool: vk::DescriptorPool,
+    gbuffer_set_layout: vk::DescriptorSetLayout,
+) -> vk::DescriptorSet {
+    unsafe {
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(pool)
+            .set_layouts(&[gbuffer_set_layout])
+            .build();
+        device.allocate_descriptor_sets(&alloc_info).unwrap()[0]
+    }
+}
+
+/// must be requeued whenever the g-buffer or depth image is recreated
+pub fn queue_gbuffer_set_writes(
+    write_batcher: &mut super::descriptor::DescriptorWriteBatcher,
+    set: vk::DescriptorSet,
+    gbuffer: &GBuffer,
+    depth_image_view: vk::ImageView,
+) {
+    for (binding, &image_view) in gbuffer.image_views.iter().enumerate() {
+        write_batcher.queue_image_write(
+            set,
+            binding as u32,
+            0,
+            vk::DescriptorType::INPUT_ATTACHMENT,
+            vk::DescriptorImageInfo {
+                sampler: vk::Sampler::null(),
+                image_view,
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            },
+        );
+    }
+    write_batcher.queue_image_write(
+        set,
+        gbuffer.image_views.len() as u32,
+        0,
+        vk::DescriptorType::INPUT_ATTACHMENT,
+        vk::DescriptorImageInfo {
+            sampler: vk::Sampler::null(),
+            image_view: depth_image_view,
+            image_layout: vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+        },
+    );
+}
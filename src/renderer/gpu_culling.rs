@@ -0,0 +1,602 @@
+// Frustum culling of the main view's batched draws. Each batch's bounding sphere and the instance
+// transforms go to storage buffers, a compute pass tests every instance against the camera's frustum,
+// copies the visible ones to a culled instance buffer and packs each bucket's non empty batches into
+// draw records, drawn with vkCmdDrawIndexedIndirectCount. Without drawIndirectCount the same culling
+// runs on the cpu in `build` and the packed records are drawn with counts known up front:
+//
+//     gpu_culling.build(frame, &proj_view, &draw_batcher, &geometry_system);
+//     gpu_culling.cmd_dispatch(command_buffer, frame);
+//     // in the scene pass, instead of draw_batcher.cmd_draw_batches
+//     gpu_culling.cmd_draw(command_buffer, frame, pipeline_layout, None, &draw_batcher, &material_system);
+//
+// Other views keep drawing the unculled batches. Needs indirect drawing,
+// no occlusion culling against a depth pyramid yet
+
+use std::{mem::size_of, rc::Rc};
+
+use ash::vk;
+
+use crate::{
+    geometry::{GeometrySystem, MAX_INDIRECT_COMMAND_COUNT},
+    math::{Frustum, Mat, ModelMat},
+};
+use super::{
+    batch::{DrawBatcher, MAX_INSTANCE_COUNT},
+    buffer::Buffer,
+    descriptor::DescriptorWriteBatcher,
+    draw_list::StateTracker,
+    material::MaterialSystem,
+    pipeline::{self, Constant},
+    shader,
+    MAX_FRAMES_IN_FLIGHT,
+};
+
+const WORKGROUP_SIZE: u32 = 64;
+const PHASE_CONSTANT_ID: u32 = pipeline::FIRST_CUSTOM_CONSTANT_ID;
+const MAX_BATCH_COUNT_CONSTANT_ID: u32 = pipeline::FIRST_CUSTOM_CONSTANT_ID + 1;
+const PHASE_INSTANCES: u32 = 0;
+const PHASE_PACK: u32 = 1;
+const BINDING_COUNT: u32 = 6;
+
+/// must match CullBatch in cull.comp
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct CullBatch {
+    /// local bounding sphere of the batch's geometry, radius in w
+    sphere: [f32; 4],
+    /// draws all of the batch's instances
+    command: vk::DrawIndexedIndirectCommand,
+    bucket: u32,
+    /// of the bucket, its draw records are packed from there on
+    first_command: u32,
+    _pad: u32,
+}
+
+/// must match the push constant block in cull.comp
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CullPushConstants {
+    planes: [[f32; 4]; 6],
+    instance_count: u32,
+    batch_count: u32,
+}
+
+/// Culls the draw batcher's last built frame for the main view.
+/// `build` after the batcher, `cmd_dispatch` outside of any render pass
+/// and `cmd_draw` in place of the batcher's `cmd_draw_batches` while `is_active`
+pub struct GpuCulling {
+    device: Rc<ash::Device>,
+    /// only takes effect while the draw batcher draws indirectly
+    pub enabled: bool,
+    /// whether the last built frame is culled
+    active: bool,
+    /// culls in a compute pass, otherwise on the cpu
+    gpu: bool,
+    multi_draw_indirect: bool,
+
+    /// host visible, one region per frame in flight
+    batch_buffer: Buffer,
+    /// host visible, the batch of each instance, one region per frame in flight
+    instance_batch_buffer: Buffer,
+    /// one region per frame in flight, host visible when culling on the cpu
+    culled_instance_buffer: Buffer,
+    /// per batch visible instance counts, then per bucket draw counts, one region per frame in flight
+    count_buffer: Buffer,
+    /// packed draw records, one region per frame in flight, host visible when culling on the cpu
+    draw_buffer: Buffer,
+
+    batches: Vec<CullBatch>,
+    instance_batches: Vec<u32>,
+    culled_instances: Vec<ModelMat>,
+    draws: Vec<vk::DrawIndexedIndirectCommand>,
+    /// per bucket, when culling on the cpu
+    draw_counts: Vec<u32>,
+    planes: [[f32; 4]; 6],
+    instance_count: u32,
+
+    descriptor_pool: vk::DescriptorPool,
+    set_layout: vk::DescriptorSetLayout,
+    sets: Vec<vk::DescriptorSet>,
+    pipeline_layout: vk::PipelineLayout,
+    instance_pipeline: vk::Pipeline,
+    pack_pipeline: vk::Pipeline,
+}
+
+impl GpuCulling {
+    /// culls on the gpu when the device has drawIndirectCount and multiDrawIndirect
+    pub fn new(
+        device: Rc<ash::Device>,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        device_features: &super::device::DeviceFeatures,
+        shader_compiler: &shader::ShaderCompiler,
+        write_batcher: &mut DescriptorWriteBatcher,
+        draw_batcher: &DrawBatcher,
+    ) -> Self {
+        let gpu = device_features.draw_indirect_count && device_features.multi_draw_indirect;
+        log::info!("Culling batched draws on the {}", if gpu { "gpu" } else { "cpu" });
+
+        let host_visible = vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+        let output_memory = if gpu { vk::MemoryPropertyFlags::DEVICE_LOCAL } else { host_visible };
+        let new_buffer = |size: vk::DeviceSize, usage, memory_properties| Buffer::new(
+            MAX_FRAMES_IN_FLIGHT as vk::DeviceSize * size,
+            usage,
+            memory_properties,
+            device.clone(),
+            physical_device_memory_properties,
+        );
+
+        let batch_buffer = new_buffer(Self::batch_region_size(), vk::BufferUsageFlags::STORAGE_BUFFER, host_visible);
+        let instance_batch_buffer = new_buffer(
+            Self::instance_batch_region_size(),
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            host_visible,
+        );
+        let culled_instance_buffer = new_buffer(
+            Self::instance_region_size(),
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::VERTEX_BUFFER,
+            output_memory,
+        );
+        let count_buffer = new_buffer(
+            Self::count_region_size(),
+            vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::INDIRECT_BUFFER
+                | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        );
+        let draw_buffer = new_buffer(
+            Self::draw_region_size(),
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::INDIRECT_BUFFER,
+            output_memory,
+        );
+
+        let set_layout_bindings = (0..BINDING_COUNT)
+            .map(|binding| vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build())
+            .collect::<Vec<_>>();
+        let set_layout = unsafe {
+            let info = vk::DescriptorSetLayoutCreateInfo::builder()
+                .bindings(&set_layout_bindings);
+            device.create_descriptor_set_layout(&info, None).unwrap()
+        };
+
+        let descriptor_pool = unsafe {
+            let pool_sizes = [vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: BINDING_COUNT * MAX_FRAMES_IN_FLIGHT as u32,
+            }];
+            let info = vk::DescriptorPoolCreateInfo::builder()
+                .max_sets(MAX_FRAMES_IN_FLIGHT as u32)
+                .pool_sizes(&pool_sizes);
+            device.create_descriptor_pool(&info, None).expect("Failed to create descriptor pool")
+        };
+
+        let sets = unsafe {
+            let set_layouts = [set_layout; MAX_FRAMES_IN_FLIGHT];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(descriptor_pool)
+                .set_layouts(&set_layouts);
+            device.allocate_descriptor_sets(&alloc_info).unwrap()
+        };
+
+        for (frame, &set) in sets.iter().enumerate() {
+            let region = |buffer: &Buffer, size: vk::DeviceSize| (buffer.handle, frame as vk::DeviceSize * size, size);
+            let (instance_buffer, instance_offset) = draw_batcher.get_instance_binding(frame);
+            let buffer_infos = [
+                region(&batch_buffer, Self::batch_region_size()),
+                region(&instance_batch_buffer, Self::instance_batch_region_size()),
+                (instance_buffer, instance_offset, Self::instance_region_size()),
+                region(&culled_instance_buffer, Self::instance_region_size()),
+                region(&count_buffer, Self::count_region_size()),
+                region(&draw_buffer, Self::draw_region_size()),
+            ];
+            for (binding, (buffer, offset, range)) in buffer_infos.into_iter().enumerate() {
+                write_batcher.queue_buffer_write(
+                    set,
+                    binding as u32,
+                    0,
+                    vk::DescriptorType::STORAGE_BUFFER,
+                    vk::DescriptorBufferInfo { buffer, offset, range },
+                );
+            }
+        }
+
+        let push_constant_ranges = [vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            offset: 0,
+            size: size_of::<CullPushConstants>() as u32,
+        }];
+        let new_pipeline = |phase| pipeline::new_compute_pipeline_and_layout(
+            &device,
+            shader_compiler,
+            "shaders/cull.comp",
+            &[set_layout],
+            &push_constant_ranges,
+            &[
+                (PHASE_CONSTANT_ID, Constant::U32(phase)),
+                (MAX_BATCH_COUNT_CONSTANT_ID, Constant::U32(MAX_INDIRECT_COMMAND_COUNT as u32)),
+            ],
+        );
+        let (instance_pipeline, pipeline_layout) = new_pipeline(PHASE_INSTANCES);
+        let (pack_pipeline, pack_pipeline_layout) = new_pipeline(PHASE_PACK);
+        // both layouts are the same
+        unsafe { device.destroy_pipeline_layout(pack_pipeline_layout, None); }
+
+        Self {
+            device,
+            enabled: true,
+            active: false,
+            gpu,
+            multi_draw_indirect: device_features.multi_draw_indirect,
+
+            batch_buffer,
+            instance_batch_buffer,
+            culled_instance_buffer,
+            count_buffer,
+            draw_buffer,
+
+            batches: vec![],
+            instance_batches: vec![],
+            culled_instances: vec![],
+            draws: vec![],
+            draw_counts: vec![],
+            planes: [[0.0; 4]; 6],
+            instance_count: 0,
+
+            descriptor_pool,
+            set_layout,
+            sets,
+            pipeline_layout,
+            instance_pipeline,
+            pack_pipeline,
+        }
+    }
+
+    // per frame region sizes are multiples of 256 bytes,
+    // the largest storage buffer offset alignment devices may require
+    fn batch_region_size() -> vk::DeviceSize {
+        (MAX_INDIRECT_COMMAND_COUNT * size_of::<CullBatch>()) as vk::DeviceSize
+    }
+
+    fn instance_batch_region_size() -> vk::DeviceSize {
+        (MAX_INSTANCE_COUNT * size_of::<u32>()) as vk::DeviceSize
+    }
+
+    fn instance_region_size() -> vk::DeviceSize {
+        (MAX_INSTANCE_COUNT * size_of::<ModelMat>()) as vk::DeviceSize
+    }
+
+    fn count_region_size() -> vk::DeviceSize {
+        (2 * MAX_INDIRECT_COMMAND_COUNT * size_of::<u32>()) as vk::DeviceSize
+    }
+
+    fn draw_region_size() -> vk::DeviceSize {
+        (MAX_INDIRECT_COMMAND_COUNT * size_of::<vk::DrawIndexedIndirectCommand>()) as vk::DeviceSize
+    }
+
+    /// whether the last built frame is culled, draw it with `cmd_draw` then
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// whether culling runs in a compute pass rather than on the cpu
+    pub fn is_gpu(&self) -> bool {
+        self.gpu
+    }
+
+    /// prepares culling `draw_batcher`'s last built frame against `proj_view`'s frustum,
+    /// the frame's previous commands must have finished executing
+    pub fn build(
+        &mut self,
+        frame: usize,
+        proj_view: &Mat,
+        draw_batcher: &DrawBatcher,
+        geometry_system: &GeometrySystem,
+    ) {
+        self.active = self.enabled && draw_batcher.indirect;
+        if !self.active {
+            return;
+        }
+
+        let frustum = Frustum::from_proj_view(proj_view);
+        self.planes = *frustum.get_planes();
+
+        // buckets cover the batches in order
+        let buckets = draw_batcher.get_buckets();
+        let batches = draw_batcher.get_batches();
+        self.batches.clear();
+        for (bucket_index, bucket) in buckets.iter().enumerate() {
+            let first_batch = bucket.first_batch as usize;
+            for batch in &batches[first_batch..first_batch + bucket.batch_count as usize] {
+                let bounds = geometry_system.get_bounds(batch.key.geometry);
+                self.batches.push(CullBatch {
+                    sphere: [bounds.center.x, bounds.center.y, bounds.center.z, bounds.radius],
+                    command: geometry_system.indirect_command(batch.key.geometry, batch.first_instance, batch.instance_count),
+                    bucket: bucket_index as u32,
+                    first_command: bucket.first_batch,
+                    _pad: 0,
+                });
+            }
+        }
+
+        let instances = draw_batcher.get_instances();
+        self.instance_count = instances.len() as u32;
+        if self.gpu {
+            self.instance_batches.clear();
+            for (index, batch) in self.batches.iter().enumerate() {
+                self.instance_batches.extend(std::iter::repeat_n(index as u32, batch.command.instance_count as usize));
+            }
+            self.batch_buffer.copy_from_slice(&self.batches, frame * MAX_INDIRECT_COMMAND_COUNT);
+            self.instance_batch_buffer.copy_from_slice(&self.instance_batches, frame * MAX_INSTANCE_COUNT);
+            return;
+        }
+
+        self.culled_instances.resize(instances.len(), ModelMat::identity());
+        self.draws.resize(self.batches.len(), vk::DrawIndexedIndirectCommand::default());
+        self.draw_counts.resize(buckets.len(), 0);
+        cull_batches(
+            &frustum,
+            &self.batches,
+            instances,
+            &mut self.culled_instances,
+            &mut self.draws,
+            &mut self.draw_counts,
+        );
+        self.culled_instance_buffer.copy_from_slice(&self.culled_instances, frame * MAX_INSTANCE_COUNT);
+        self.draw_buffer.copy_from_slice(&self.draws, frame * MAX_INDIRECT_COMMAND_COUNT);
+        log::trace!(
+            "Culled batches: {} of {} draw records left",
+            self.draw_counts.iter().sum::<u32>(),
+            self.batches.len(),
+        );
+    }
+
+    /// culls on the gpu, record outside of any render pass before `cmd_draw`
+    pub fn cmd_dispatch(&self, command_buffer: vk::CommandBuffer, frame: usize) {
+        if !self.active || !self.gpu || self.batches.is_empty() {
+            return;
+        }
+
+        let push_constants = CullPushConstants {
+            planes: self.planes,
+            instance_count: self.instance_count,
+            batch_count: self.batches.len() as u32,
+        };
+        let memory_barrier = |src_access_mask, dst_access_mask| vk::MemoryBarrier::builder()
+            .src_access_mask(src_access_mask)
+            .dst_access_mask(dst_access_mask)
+            .build();
+        let shader_read_write = vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE;
+
+        unsafe {
+            self.device.cmd_fill_buffer(
+                command_buffer,
+                self.count_buffer.handle,
+                frame as vk::DeviceSize * Self::count_region_size(),
+                Self::count_region_size(),
+                0,
+            );
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[memory_barrier(vk::AccessFlags::TRANSFER_WRITE, shader_read_write)],
+                &[],
+                &[],
+            );
+
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[self.sets[frame]],
+                &[],
+            );
+            self.device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                std::slice::from_raw_parts(
+                    &push_constants as *const CullPushConstants as *const u8,
+                    size_of::<CullPushConstants>(),
+                ),
+            );
+
+            self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, self.instance_pipeline);
+            self.device.cmd_dispatch(command_buffer, self.instance_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+            // packing reads the visible instance counts
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[memory_barrier(vk::AccessFlags::SHADER_WRITE, shader_read_write)],
+                &[],
+                &[],
+            );
+
+            self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, self.pack_pipeline);
+            self.device.cmd_dispatch(command_buffer, (self.batches.len() as u32).div_ceil(WORKGROUP_SIZE), 1, 1);
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::DRAW_INDIRECT | vk::PipelineStageFlags::VERTEX_INPUT,
+                vk::DependencyFlags::empty(),
+                &[memory_barrier(
+                    vk::AccessFlags::SHADER_WRITE,
+                    vk::AccessFlags::INDIRECT_COMMAND_READ | vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+                )],
+                &[],
+                &[],
+            );
+        }
+    }
+
+    /// draws the culled batches like `DrawBatcher::cmd_draw_batches`,
+    /// geometry resources must already be bound
+    pub fn cmd_draw(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+        pipeline_layout: vk::PipelineLayout,
+        pipeline_override: Option<vk::Pipeline>,
+        draw_batcher: &DrawBatcher,
+        material_system: &MaterialSystem,
+    ) {
+        let buckets = draw_batcher.get_buckets();
+        if buckets.is_empty() {
+            return;
+        }
+
+        let stride = size_of::<vk::DrawIndexedIndirectCommand>() as u32;
+        let draw_region = frame as vk::DeviceSize * Self::draw_region_size();
+        let count_region = frame as vk::DeviceSize * Self::count_region_size();
+        unsafe {
+            self.device.cmd_bind_vertex_buffers(
+                command_buffer,
+                pipeline::INSTANCE_BINDING,
+                &[self.culled_instance_buffer.handle],
+                &[frame as vk::DeviceSize * Self::instance_region_size()],
+            );
+        }
+
+        let mut tracker = StateTracker::default();
+        for (index, bucket) in buckets.iter().enumerate() {
+            // cpu culling knows which buckets are empty
+            if !self.gpu && self.draw_counts[index] == 0 {
+                continue;
+            }
+
+            let pipeline = pipeline_override.unwrap_or(draw_batcher.get_pipeline(bucket.pipeline));
+            if tracker.bind_pipeline(pipeline) {
+                unsafe {
+                    self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+                }
+            }
+            if tracker.bind_material(bucket.material) {
+                material_system.cmd_push_material(&self.device, command_buffer, pipeline_layout, bucket.material);
+            }
+
+            let offset = draw_region + (bucket.first_batch * stride) as vk::DeviceSize;
+            unsafe {
+                if self.gpu {
+                    self.device.cmd_draw_indexed_indirect_count(
+                        command_buffer,
+                        self.draw_buffer.handle,
+                        offset,
+                        self.count_buffer.handle,
+                        count_region + ((MAX_INDIRECT_COMMAND_COUNT + index) * size_of::<u32>()) as vk::DeviceSize,
+                        bucket.batch_count,
+                        stride,
+                    );
+                } else if self.multi_draw_indirect {
+                    self.device.cmd_draw_indexed_indirect(
+                        command_buffer,
+                        self.draw_buffer.handle,
+                        offset,
+                        self.draw_counts[index],
+                        stride,
+                    );
+                } else {
+                    for command in 0..self.draw_counts[index] {
+                        self.device.cmd_draw_indexed_indirect(
+                            command_buffer,
+                            self.draw_buffer.handle,
+                            offset + (command * stride) as vk::DeviceSize,
+                            1,
+                            stride,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    // caller must ensure only called once
+    pub unsafe fn destroy(&mut self) {
+        self.device.destroy_pipeline(self.instance_pipeline, None);
+        self.device.destroy_pipeline(self.pack_pipeline, None);
+        self.device.destroy_pipeline_layout(self.pipeline_layout, None);
+        self.device.destroy_descriptor_pool(self.descriptor_pool, None);
+        self.device.destroy_descriptor_set_layout(self.set_layout, None);
+        self.batch_buffer.destroy();
+        self.instance_batch_buffer.destroy();
+        self.culled_instance_buffer.destroy();
+        self.count_buffer.destroy();
+        self.draw_buffer.destroy();
+    }
+}
+
+/// what cull.comp computes, the visible instances of each batch are copied to the start of its range
+/// of `culled_instances` and each bucket's non empty batches are packed into `draws` from its first
+/// command on, `draw_counts` gets the number of records of each bucket
+fn cull_batches(
+    frustum: &Frustum,
+    batches: &[CullBatch],
+    instances: &[ModelMat],
+    culled_instances: &mut [ModelMat],
+    draws: &mut [vk::DrawIndexedIndirectCommand],
+    draw_counts: &mut [u32],
+) {
+    draw_counts.fill(0);
+    for batch in batches {
+        let [x, y, z, radius] = batch.sphere;
+        let first_instance = batch.command.first_instance as usize;
+        let mut visible = 0;
+        for model in &instances[first_instance..first_instance + batch.command.instance_count as usize] {
+            let center = model.translation() + model.axis(0) * x + model.axis(1) * y + model.axis(2) * z;
+            let max_scale_sqr = (0..3).map(|axis| model.axis(axis).norm_sqr()).fold(0.0, f32::max);
+            if frustum.intersects_sphere(center, radius * max_scale_sqr.sqrt()) {
+                culled_instances[first_instance + visible] = *model;
+                visible += 1;
+            }
+        }
+
+        if visible > 0 {
+            let draw_count = &mut draw_counts[batch.bucket as usize];
+            draws[(batch.first_command + *draw_count) as usize] = vk::DrawIndexedIndirectCommand {
+                instance_count: visible as u32,
+                ..batch.command
+            };
+            *draw_count += 1;
+        }
+    }
+}
+
+#[test]
+fn test_cull_batches() {
+    let frustum = Frustum::from_proj_view(&ModelMat::identity().project(1.0, 1.0, 100.0, false));
+    let batch = |bucket, first_command, first_instance, instance_count| CullBatch {
+        sphere: [0.0, 0.0, 0.0, 1.0],
+        command: vk::DrawIndexedIndirectCommand { index_count: 3, instance_count, first_instance, ..Default::default() },
+        bucket,
+        first_command,
+        _pad: 0,
+    };
+    let at = |x, z| *ModelMat::identity().translate(x, 0.0, z);
+    let batches = [batch(0, 0, 0, 2), batch(0, 0, 2, 1), batch(1, 2, 3, 2)];
+    let instances = [at(0.0, 10.0), at(0.0, -10.0), at(50.0, 10.0), at(0.0, -10.0), at(1.0, 20.0)];
+
+    let mut culled_instances = [ModelMat::identity(); 5];
+    let mut draws = [vk::DrawIndexedIndirectCommand::default(); 3];
+    let mut draw_counts = [0; 2];
+    cull_batches(&frustum, &batches, &instances, &mut culled_instances, &mut draws, &mut draw_counts);
+
+    // the second batch is culled whole, the third keeps its second instance at the start of its range
+    assert!(draw_counts == [1, 1]);
+    assert!(draws[0].instance_count == 1 && draws[0].first_instance == 0);
+    assert!(draws[2].instance_count == 1 && draws[2].first_instance == 3 && draws[2].index_count == 3);
+    let translation = culled_instances[3].translation();
+    assert!(translation.x == 1.0 && translation.z == 20.0);
+
+    // a sphere scaled up with its instance reaches into the frustum
+    let scaled = *ModelMat::identity().translate(0.0, 0.0, -10.0).scale(20.0, 20.0, 20.0);
+    cull_batches(&frustum, &[batch(0, 0, 0, 1)], &[scaled], &mut culled_instances, &mut draws, &mut draw_counts);
+    assert!(draw_counts[0] == 1);
+}
@@ -0,0 +1,475 @@
+// GPU particles, for emitters spawning far more particles than the CPU path handles.
+// Particle state is double buffered, each frame a compute pass reads last frame's particles,
+// ages and moves the live ones and spawns new ones into the other buffer, compacting them.
+// It also writes their billboards and the instance count of an indirect draw, so the CPU
+// never learns how many particles are alive
+
+use std::{mem::size_of, rc::Rc};
+
+use ash::vk;
+
+use crate::{arena::FrameArena, math::Vector, particles::{Emitter, EmitterDesc, EmitterId}};
+use super::{
+    billboard::{Billboard, BillboardRenderer},
+    buffer::Buffer,
+    descriptor::DescriptorWriteBatcher,
+    pipeline,
+    shader,
+    MAX_FRAMES_IN_FLIGHT,
+};
+
+/// per particle buffer, spawns beyond it are dropped
+pub const MAX_GPU_PARTICLE_COUNT: u32 = 0x10000;
+pub const MAX_GPU_EMITTER_COUNT: usize = 16;
+
+const WORKGROUP_SIZE: u32 = 64;
+/// longer frames are simulated as this long so hitches don't teleport particles
+const MAX_TIME_STEP: f32 = 0.1;
+
+/// must match Particle in gpu_particles.comp
+#[repr(C)]
+struct Particle {
+    /// w is the age
+    _position: [f32; 4],
+    /// w is the lifetime
+    _velocity: [f32; 4],
+    _emitter: [u32; 4],
+}
+
+/// must match Emitter in gpu_particles.comp
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct GpuEmitter {
+    position: [f32; 4],
+    /// normalized, w is the spread
+    direction: [f32; 4],
+    /// min and max speed, min and max lifetime
+    speed_lifetime: [f32; 4],
+    /// start and end size, gravity
+    size_gravity: [f32; 4],
+    start_color: [f32; 4],
+    end_color: [f32; 4],
+    /// first spawn invocation and spawn count of this frame
+    spawns: [u32; 4],
+}
+
+impl GpuEmitter {
+    fn new(desc: &EmitterDesc, position: Vector, first_spawn: u32, spawn_count: u32) -> Self {
+        let (x, y, z) = desc.direction;
+        let norm = (x * x + y * y + z * z).sqrt();
+        let (r0, g0, b0, a0) = desc.start_color;
+        let (r1, g1, b1, a1) = desc.end_color;
+        Self {
+            position: [position.x, position.y, position.z, 1.0],
+            direction: [x / norm, y / norm, z / norm, desc.spread],
+            speed_lifetime: [desc.speed.0, desc.speed.1, desc.lifetime.0, desc.lifetime.1],
+            size_gravity: [desc.size.0, desc.size.1, desc.gravity, 0.0],
+            start_color: [r0, g0, b0, a0],
+            end_color: [r1, g1, b1, a1],
+            spawns: [first_spawn, spawn_count, 0, 0],
+        }
+    }
+}
+
+/// must match the push constant block in gpu_particles.comp
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct SimulatePushConstants {
+    dt: f32,
+    /// the side read from, the other is written
+    src_side: u32,
+    spawn_count: u32,
+    emitter_count: u32,
+    /// varies spawns between frames
+    seed: u32,
+}
+
+/// Add emitters, `update` along with the game, `build` once the frame's fence is waited on,
+/// `cmd_dispatch` before the scene pass or on the async compute queue and `cmd_draw` inside it.
+/// `queue_family_indices` are of the queues the buffers are used on, see `AsyncCompute`
+pub struct GpuParticleSystem {
+    device: Rc<ash::Device>,
+
+    /// two sides of `MAX_GPU_PARTICLE_COUNT` particles, read from one and written to the other
+    particle_buffer: Buffer,
+    /// same sides as the particles
+    billboard_buffer: Buffer,
+    /// one vk::DrawIndirectCommand per side, the instance count is the side's live particle count
+    indirect_buffer: Buffer,
+    /// host visible, one region per frame in flight
+    emitter_buffer: Buffer,
+
+    descriptor_pool: vk::DescriptorPool,
+    set_layout: vk::DescriptorSetLayout,
+    /// one per side read from
+    sets: [vk::DescriptorSet; 2],
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+
+    emitters: Vec<Emitter>,
+    /// since the last build
+    pending_spawns: Vec<u32>,
+    pending_dt: f32,
+    push_constants: SimulatePushConstants,
+    frame: usize,
+    /// neither side's instance count has been written yet
+    first_dispatch: bool,
+}
+
+impl GpuParticleSystem {
+    pub fn new(
+        device: Rc<ash::Device>,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        shader_compiler: &shader::ShaderCompiler,
+        write_batcher: &mut DescriptorWriteBatcher,
+        queue_family_indices: &[u32],
+    ) -> Self {
+        let particle_buffer = Buffer::new_shared(
+            (2 * MAX_GPU_PARTICLE_COUNT as usize * size_of::<Particle>()) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            queue_family_indices,
+            device.clone(),
+            physical_device_memory_properties,
+        );
+        let billboard_buffer = Buffer::new_shared(
+            (2 * MAX_GPU_PARTICLE_COUNT as usize * size_of::<Billboard>()) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::VERTEX_BUFFER,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            queue_family_indices,
+            device.clone(),
+            physical_device_memory_properties,
+        );
+        let indirect_buffer = Buffer::new_shared(
+            (2 * size_of::<vk::DrawIndirectCommand>()) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::INDIRECT_BUFFER
+                | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            queue_family_indices,
+            device.clone(),
+            physical_device_memory_properties,
+        );
+        let emitter_buffer = Buffer::new_shared(
+            (MAX_FRAMES_IN_FLIGHT * MAX_GPU_EMITTER_COUNT * size_of::<GpuEmitter>()) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            queue_family_indices,
+            device.clone(),
+            physical_device_memory_properties,
+        );
+
+        // src particles, dst particles, dst billboards, draw commands, emitters
+        let set_layout_bindings = [
+            (0, vk::DescriptorType::STORAGE_BUFFER),
+            (1, vk::DescriptorType::STORAGE_BUFFER),
+            (2, vk::DescriptorType::STORAGE_BUFFER),
+            (3, vk::DescriptorType::STORAGE_BUFFER),
+            (4, vk::DescriptorType::STORAGE_BUFFER_DYNAMIC),
+        ].map(|(binding, ty)| vk::DescriptorSetLayoutBinding::builder()
+            .binding(binding)
+            .descriptor_type(ty)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .build()
+        );
+        let set_layout = unsafe {
+            let info = vk::DescriptorSetLayoutCreateInfo::builder()
+                .bindings(&set_layout_bindings);
+            device.create_descriptor_set_layout(&info, None).unwrap()
+        };
+
+        let descriptor_pool = unsafe {
+            let pool_sizes = [
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_BUFFER,
+                    descriptor_count: 2 * 4,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_BUFFER_DYNAMIC,
+                    descriptor_count: 2,
+                },
+            ];
+            let info = vk::DescriptorPoolCreateInfo::builder()
+                .max_sets(2)
+                .pool_sizes(&pool_sizes);
+            device.create_descriptor_pool(&info, None).expect("Failed to create descriptor pool")
+        };
+
+        let sets = unsafe {
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(descriptor_pool)
+                .set_layouts(&[set_layout, set_layout])
+                .build();
+            let sets = device.allocate_descriptor_sets(&alloc_info).unwrap();
+            [sets[0], sets[1]]
+        };
+        let particle_side_size = (MAX_GPU_PARTICLE_COUNT as usize * size_of::<Particle>()) as vk::DeviceSize;
+        let billboard_side_size = (MAX_GPU_PARTICLE_COUNT as usize * size_of::<Billboard>()) as vk::DeviceSize;
+        for (src_side, &set) in sets.iter().enumerate() {
+            let dst_side = 1 - src_side as vk::DeviceSize;
+            let writes = [
+                (0, vk::DescriptorType::STORAGE_BUFFER, particle_buffer.handle, src_side as vk::DeviceSize * particle_side_size, particle_side_size),
+                (1, vk::DescriptorType::STORAGE_BUFFER, particle_buffer.handle, dst_side * particle_side_size, particle_side_size),
+                (2, vk::DescriptorType::STORAGE_BUFFER, billboard_buffer.handle, dst_side * billboard_side_size, billboard_side_size),
+                (3, vk::DescriptorType::STORAGE_BUFFER, indirect_buffer.handle, 0, indirect_buffer.size),
+                (
+                    4,
+                    vk::DescriptorType::STORAGE_BUFFER_DYNAMIC,
+                    emitter_buffer.handle,
+                    0,
+                    (MAX_GPU_EMITTER_COUNT * size_of::<GpuEmitter>()) as vk::DeviceSize,
+                ),
+            ];
+            for (binding, ty, buffer, offset, range) in writes {
+                write_batcher.queue_buffer_write(set, binding, 0, ty, vk::DescriptorBufferInfo { buffer, offset, range });
+            }
+        }
+
+        let (pipeline, pipeline_layout) = pipeline::new_compute_pipeline_and_layout(
+            &device,
+            shader_compiler,
+            "shaders/gpu_particles.comp",
+            &[set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                offset: 0,
+                size: size_of::<SimulatePushConstants>() as u32,
+            }],
+            &[],
+        );
+
+        Self {
+            device,
+
+            particle_buffer,
+            billboard_buffer,
+            indirect_buffer,
+            emitter_buffer,
+
+            descriptor_pool,
+            set_layout,
+            sets,
+            pipeline_layout,
+            pipeline,
+
+            emitters: vec![],
+            pending_spawns: vec![],
+            pending_dt: 0.0,
+            // the first build reads side 0
+            push_constants: SimulatePushConstants {
+                src_side: 1,
+                ..Default::default()
+            },
+            frame: 0,
+            first_dispatch: true,
+        }
+    }
+
+    // TODO: removing emitters, disable them for now
+    pub fn add_emitter(&mut self, desc: EmitterDesc, position: Vector) -> EmitterId {
+        assert!(self.emitters.len() < MAX_GPU_EMITTER_COUNT, "Out of gpu emitter slots");
+        self.emitters.push(Emitter::new(desc, position));
+        self.pending_spawns.push(0);
+        (self.emitters.len() - 1) as EmitterId
+    }
+
+    pub fn get_emitter_mut(&mut self, id: EmitterId) -> &mut Emitter {
+        &mut self.emitters[id as usize]
+    }
+
+    /// accumulates spawns and time until the next build
+    pub fn update(&mut self, dt: f32) {
+        self.pending_dt += dt;
+        for (emitter, spawns) in self.emitters.iter_mut().zip(&mut self.pending_spawns) {
+            *spawns += emitter.take_spawns(dt);
+        }
+    }
+
+    fn emitter_offset(frame: usize) -> vk::DeviceSize {
+        (frame * MAX_GPU_EMITTER_COUNT * size_of::<GpuEmitter>()) as vk::DeviceSize
+    }
+
+    /// writes the emitters and their spawns into `frame`'s region,
+    /// the frame's previous commands must have finished executing
+    pub fn build(&mut self, frame: usize, frame_arena: &FrameArena) {
+        let gpu_emitters = frame_arena.alloc_slice::<GpuEmitter>(self.emitters.len());
+        let mut spawn_count = 0;
+        for ((emitter, spawns), gpu_emitter) in self.emitters.iter().zip(&mut self.pending_spawns).zip(gpu_emitters.iter_mut()) {
+            let emitter_spawns = (*spawns).min(MAX_GPU_PARTICLE_COUNT - spawn_count);
+            *gpu_emitter = GpuEmitter::new(&emitter.desc, emitter.position, spawn_count, emitter_spawns);
+            spawn_count += emitter_spawns;
+            *spawns = 0;
+        }
+        self.emitter_buffer.copy_from_slice(gpu_emitters, frame * MAX_GPU_EMITTER_COUNT);
+
+        self.push_constants = SimulatePushConstants {
+            dt: self.pending_dt.min(MAX_TIME_STEP),
+            // last frame's destination
+            src_side: 1 - self.push_constants.src_side,
+            spawn_count,
+            emitter_count: self.emitters.len() as u32,
+            seed: self.push_constants.seed.wrapping_add(1),
+        };
+        self.pending_dt = 0.0;
+        self.frame = frame;
+    }
+
+    fn dst_side(&self) -> vk::DeviceSize {
+        1 - self.push_constants.src_side as vk::DeviceSize
+    }
+
+    /// record outside of any render pass, before the scene pass, or into the frame's async compute
+    /// command buffer when `async_compute`. The frame's fence covers the draw two frames ago writing the
+    /// same side, the graphics queue waiting on the compute semaphore covers this frame's draw
+    pub fn cmd_dispatch(&mut self, command_buffer: vk::CommandBuffer, async_compute: bool) {
+        if self.emitters.is_empty() {
+            return;
+        }
+
+        let draw_command = vk::DrawIndirectCommand {
+            vertex_count: 6,
+            instance_count: 0,
+            first_vertex: 0,
+            first_instance: 0,
+        };
+        let draw_command_bytes = unsafe {
+            std::slice::from_raw_parts(
+                &draw_command as *const vk::DrawIndirectCommand as *const u8,
+                size_of::<vk::DrawIndirectCommand>(),
+            )
+        };
+
+        unsafe {
+            // last frame's simulation must finish writing before it's read,
+            // and its draw must finish reading before the destination is overwritten
+            let before_barrier = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE | vk::AccessFlags::TRANSFER_WRITE)
+                .build();
+            // compute queues have no draw stages, draws are waited on through fences there
+            let draw_stages = if async_compute {
+                vk::PipelineStageFlags::empty()
+            } else {
+                vk::PipelineStageFlags::DRAW_INDIRECT | vk::PipelineStageFlags::VERTEX_INPUT
+            };
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER | draw_stages,
+                vk::PipelineStageFlags::TRANSFER | vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[before_barrier],
+                &[],
+                &[],
+            );
+
+            // the destination starts out empty, as does the source before anything was simulated
+            let sides = if self.first_dispatch { 0..2 } else { self.dst_side()..self.dst_side() + 1 };
+            for side in sides {
+                self.device.cmd_update_buffer(
+                    command_buffer,
+                    self.indirect_buffer.handle,
+                    side * size_of::<vk::DrawIndirectCommand>() as vk::DeviceSize,
+                    draw_command_bytes,
+                );
+            }
+            self.first_dispatch = false;
+
+            let reset_barrier = vk::BufferMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .buffer(self.indirect_buffer.handle)
+                .offset(0)
+                .size(vk::WHOLE_SIZE)
+                .build();
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[reset_barrier],
+                &[],
+            );
+
+            self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, self.pipeline);
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[self.sets[self.push_constants.src_side as usize]],
+                &[Self::emitter_offset(self.frame) as u32],
+            );
+            self.device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                std::slice::from_raw_parts(
+                    &self.push_constants as *const SimulatePushConstants as *const u8,
+                    size_of::<SimulatePushConstants>(),
+                ),
+            );
+            // live particles are only known on the device, every slot gets an invocation
+            self.device.cmd_dispatch(command_buffer, MAX_GPU_PARTICLE_COUNT.div_ceil(WORKGROUP_SIZE), 1, 1);
+
+            // the graphics queue's semaphore wait makes the writes visible to the draw
+            if async_compute {
+                return;
+            }
+            let draw_barrier = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::INDIRECT_COMMAND_READ | vk::AccessFlags::VERTEX_ATTRIBUTE_READ)
+                .build();
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::DRAW_INDIRECT | vk::PipelineStageFlags::VERTEX_INPUT,
+                vk::DependencyFlags::empty(),
+                &[draw_barrier],
+                &[],
+                &[],
+            );
+        }
+    }
+
+    /// record in the scene pass after the opaque geometry, draws through the billboard pipeline
+    pub fn cmd_draw(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        billboard_renderer: &BillboardRenderer,
+        per_frame_ubo_set: vk::DescriptorSet,
+        per_frame_ubo_offset: u32,
+    ) {
+        if self.emitters.is_empty() {
+            return;
+        }
+
+        let dst_side = self.dst_side();
+        billboard_renderer.cmd_draw_indirect(
+            command_buffer,
+            self.billboard_buffer.handle,
+            dst_side * (MAX_GPU_PARTICLE_COUNT as usize * size_of::<Billboard>()) as vk::DeviceSize,
+            self.indirect_buffer.handle,
+            dst_side * size_of::<vk::DrawIndirectCommand>() as vk::DeviceSize,
+            per_frame_ubo_set,
+            per_frame_ubo_offset,
+        );
+    }
+
+    // caller must ensure only called once
+    pub unsafe fn destroy(&mut self) {
+        self.device.destroy_pipeline(self.pipeline, None);
+        self.device.destroy_pipeline_layout(self.pipeline_layout, None);
+        self.device.destroy_descriptor_pool(self.descriptor_pool, None);
+        self.device.destroy_descriptor_set_layout(self.set_layout, None);
+
+        self.particle_buffer.destroy();
+        self.billboard_buffer.destroy();
+        self.indirect_buffer.destroy();
+        self.emitter_buffer.destroy();
+    }
+}
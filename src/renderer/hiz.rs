@@ -0,0 +1,457 @@
+// Hierarchical depth (Hi-Z) pyramid. Mip 0 copies the depth buffer and every further texel holds
+// the farthest depth of the texels it covers, so testing bounds against one texel of a coarse mip
+// is conservative, for gpu occlusion culling and screen space ray marching. A compute dispatch per
+// mip builds it. There's no depth prepass, it's built from the previous frame's depth at the start
+// of the frame, like precipitation collisions:
+//
+//     hiz.cmd_build(command_buffer, depth_image, depth_format, reverse_z);
+//     // sample with texelFetch or textureLod, in GENERAL layout
+//     let (view, sampler) = (hiz.get_view(), hiz.get_sampler());
+//
+// Farthest is the largest depth, or the smallest with reverse z
+
+use std::{mem::size_of, rc::Rc};
+
+use ash::vk;
+
+use super::{descriptor::DescriptorWriteBatcher, image, pipeline, shader};
+
+pub const HIZ_FORMAT: vk::Format = vk::Format::R32_SFLOAT;
+/// enough for 64k wide depth buffers
+pub const MAX_HIZ_LEVELS: u32 = 17;
+
+const WORKGROUP_SIZE: u32 = 8;
+
+/// must match the push constant block in hiz.comp
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct LevelPushConstants {
+    source_size: [i32; 2],
+    destination_size: [i32; 2],
+    reverse_z: u32,
+}
+
+/// size dependent resources, replaced on resize
+struct Pyramid {
+    image: vk::Image,
+    memory: vk::DeviceMemory,
+    /// every mip, for sampling
+    view: vk::ImageView,
+    /// one per mip, for writing
+    level_views: Vec<vk::ImageView>,
+    /// one per mip, reading the level before it or the depth buffer
+    sets: Vec<vk::DescriptorSet>,
+    extents: Vec<vk::Extent2D>,
+    /// in GENERAL layout once built
+    initialized: bool,
+}
+
+/// Builds the pyramid of the scene's depth buffer, `resize` along with the depth buffer
+/// and `cmd_build` outside of any render pass while `enabled`
+pub struct HiZBuilder {
+    device: Rc<ash::Device>,
+    physical_device_memory_properties: vk::PhysicalDeviceMemoryProperties,
+    /// nothing samples the pyramid by default, it's only built while enabled
+    pub enabled: bool,
+    pyramid: Option<Pyramid>,
+
+    /// nearest texel and mip, clamped to the edges
+    sampler: vk::Sampler,
+    descriptor_pool: vk::DescriptorPool,
+    set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl HiZBuilder {
+    pub fn new(
+        device: Rc<ash::Device>,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        shader_compiler: &shader::ShaderCompiler,
+    ) -> Self {
+        let sampler = unsafe {
+            let info = vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::NEAREST)
+                .min_filter(vk::Filter::NEAREST)
+                .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .max_lod(vk::LOD_CLAMP_NONE);
+            device.create_sampler(&info, None).unwrap()
+        };
+
+        let set_layout_bindings = [
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(1)
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build(),
+        ];
+        let set_layout = unsafe {
+            let info = vk::DescriptorSetLayoutCreateInfo::builder()
+                .bindings(&set_layout_bindings);
+            device.create_descriptor_set_layout(&info, None).unwrap()
+        };
+
+        // reset on resize
+        let descriptor_pool = unsafe {
+            let pool_sizes = [
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    descriptor_count: MAX_HIZ_LEVELS,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_IMAGE,
+                    descriptor_count: MAX_HIZ_LEVELS,
+                },
+            ];
+            let info = vk::DescriptorPoolCreateInfo::builder()
+                .max_sets(MAX_HIZ_LEVELS)
+                .pool_sizes(&pool_sizes);
+            device.create_descriptor_pool(&info, None).expect("Failed to create descriptor pool")
+        };
+
+        let (pipeline, pipeline_layout) = pipeline::new_compute_pipeline_and_layout(
+            &device,
+            shader_compiler,
+            "shaders/hiz.comp",
+            &[set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                offset: 0,
+                size: size_of::<LevelPushConstants>() as u32,
+            }],
+            &[],
+        );
+
+        Self {
+            device,
+            physical_device_memory_properties: *physical_device_memory_properties,
+            enabled: false,
+            pyramid: None,
+
+            sampler,
+            descriptor_pool,
+            set_layout,
+            pipeline_layout,
+            pipeline,
+        }
+    }
+
+    /// replaces the pyramid with one for a depth buffer of `extent`,
+    /// `depth_view` samples its depth aspect. The previous pyramid must no longer be in use,
+    /// the first build after reads a depth buffer nothing was drawn to yet
+    pub fn resize(&mut self, write_batcher: &mut DescriptorWriteBatcher, extent: vk::Extent2D, depth_view: vk::ImageView) {
+        unsafe { self.destroy_pyramid(); }
+
+        let extents = level_extents(extent);
+        let level_count = extents.len() as u32;
+        let (image, memory) = image::new_image_and_memory(
+            &self.device,
+            &self.physical_device_memory_properties,
+            &image::ImageDesc {
+                width: extent.width,
+                height: extent.height,
+                mip_levels: level_count,
+                usage: vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+                format: HIZ_FORMAT,
+                ..Default::default()
+            },
+        );
+        let view = image::new_image_view(&self.device, image, HIZ_FORMAT, vk::ImageAspectFlags::COLOR, level_count);
+        let level_views = (0..level_count)
+            .map(|level| unsafe {
+                let info = vk::ImageViewCreateInfo::builder()
+                    .image(image)
+                    .view_type(vk::ImageViewType::TYPE_2D)
+                    .format(HIZ_FORMAT)
+                    .subresource_range(vk::ImageSubresourceRange {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        base_mip_level: level,
+                        level_count: 1,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    });
+                self.device.create_image_view(&info, None).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let sets = unsafe {
+            let set_layouts = vec![self.set_layout; level_count as usize];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(self.descriptor_pool)
+                .set_layouts(&set_layouts);
+            self.device.allocate_descriptor_sets(&alloc_info).unwrap()
+        };
+        for (level, &set) in sets.iter().enumerate() {
+            let source = if level == 0 {
+                (depth_view, vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
+            } else {
+                (level_views[level - 1], vk::ImageLayout::GENERAL)
+            };
+            write_batcher.queue_image_write(
+                set,
+                0,
+                0,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                vk::DescriptorImageInfo { sampler: self.sampler, image_view: source.0, image_layout: source.1 },
+            );
+            write_batcher.queue_image_write(
+                set,
+                1,
+                0,
+                vk::DescriptorType::STORAGE_IMAGE,
+                vk::DescriptorImageInfo {
+                    sampler: vk::Sampler::null(),
+                    image_view: level_views[level],
+                    image_layout: vk::ImageLayout::GENERAL,
+                },
+            );
+        }
+
+        self.pyramid = Some(Pyramid { image, memory, view, level_views, sets, extents, initialized: false });
+    }
+
+    /// every mip, in GENERAL layout once built
+    pub fn get_view(&self) -> vk::ImageView {
+        self.pyramid.as_ref().map_or(vk::ImageView::null(), |pyramid| pyramid.view)
+    }
+
+    pub fn get_sampler(&self) -> vk::Sampler {
+        self.sampler
+    }
+
+    /// size of each mip, empty before the first resize
+    pub fn get_extents(&self) -> &[vk::Extent2D] {
+        self.pyramid.as_ref().map_or(&[], |pyramid| &pyramid.extents)
+    }
+
+    /// builds the pyramid from the depth in `depth_image`, record outside of any render pass.
+    /// Leaves it readable from compute, vertex and fragment shaders
+    pub fn cmd_build(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        depth_image: vk::Image,
+        depth_format: vk::Format,
+        reverse_z: bool,
+    ) {
+        let Some(pyramid) = self.pyramid.as_mut().filter(|_| self.enabled) else {
+            return;
+        };
+
+        let depth_subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: image::get_depth_aspect_mask(depth_format),
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let pyramid_subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: pyramid.extents.len() as u32,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let readers = vk::PipelineStageFlags::COMPUTE_SHADER
+            | vk::PipelineStageFlags::VERTEX_SHADER
+            | vk::PipelineStageFlags::FRAGMENT_SHADER;
+
+        unsafe {
+            // the previous frame's depth writes and pyramid reads must finish first
+            let depth_barrier = vk::ImageMemoryBarrier::builder()
+                .old_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                .new_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
+                .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(depth_image)
+                .subresource_range(depth_subresource_range)
+                .build();
+            let pyramid_barrier = vk::ImageMemoryBarrier::builder()
+                .old_layout(if pyramid.initialized { vk::ImageLayout::GENERAL } else { vk::ImageLayout::UNDEFINED })
+                .new_layout(vk::ImageLayout::GENERAL)
+                .src_access_mask(vk::AccessFlags::empty())
+                .dst_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(pyramid.image)
+                .subresource_range(pyramid_subresource_range)
+                .build();
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::LATE_FRAGMENT_TESTS | readers,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[depth_barrier, pyramid_barrier],
+            );
+            pyramid.initialized = true;
+
+            self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, self.pipeline);
+            for (level, &extent) in pyramid.extents.iter().enumerate() {
+                let source_extent = pyramid.extents[level.saturating_sub(1)];
+                let push_constants = LevelPushConstants {
+                    source_size: [source_extent.width as i32, source_extent.height as i32],
+                    destination_size: [extent.width as i32, extent.height as i32],
+                    reverse_z: reverse_z as u32,
+                };
+                self.device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::COMPUTE,
+                    self.pipeline_layout,
+                    0,
+                    &[pyramid.sets[level]],
+                    &[],
+                );
+                self.device.cmd_push_constants(
+                    command_buffer,
+                    self.pipeline_layout,
+                    vk::ShaderStageFlags::COMPUTE,
+                    0,
+                    std::slice::from_raw_parts(
+                        &push_constants as *const LevelPushConstants as *const u8,
+                        size_of::<LevelPushConstants>(),
+                    ),
+                );
+                self.device.cmd_dispatch(
+                    command_buffer,
+                    extent.width.div_ceil(WORKGROUP_SIZE),
+                    extent.height.div_ceil(WORKGROUP_SIZE),
+                    1,
+                );
+
+                // the next level reads this one, readers of the pyramid read them all
+                let memory_barrier = vk::MemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                    .build();
+                let last = level + 1 == pyramid.extents.len();
+                self.device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    if last { readers } else { vk::PipelineStageFlags::COMPUTE_SHADER },
+                    vk::DependencyFlags::empty(),
+                    &[memory_barrier],
+                    &[],
+                    &[],
+                );
+            }
+
+            let depth_barrier = vk::ImageMemoryBarrier::builder()
+                .old_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
+                .new_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                .src_access_mask(vk::AccessFlags::empty())
+                .dst_access_mask(
+                    vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+                )
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(depth_image)
+                .subresource_range(depth_subresource_range)
+                .build();
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[depth_barrier],
+            );
+        }
+    }
+
+    unsafe fn destroy_pyramid(&mut self) {
+        let Some(pyramid) = self.pyramid.take() else {
+            return;
+        };
+        for &level_view in &pyramid.level_views {
+            self.device.destroy_image_view(level_view, None);
+        }
+        self.device.destroy_image_view(pyramid.view, None);
+        self.device.destroy_image(pyramid.image, None);
+        self.device.free_memory(pyramid.memory, None);
+        self.device.reset_descriptor_pool(self.descriptor_pool, vk::DescriptorPoolResetFlags::empty()).unwrap();
+    }
+
+    // caller must ensure only called once
+    pub unsafe fn destroy(&mut self) {
+        self.destroy_pyramid();
+        self.device.destroy_pipeline(self.pipeline, None);
+        self.device.destroy_pipeline_layout(self.pipeline_layout, None);
+        self.device.destroy_descriptor_pool(self.descriptor_pool, None);
+        self.device.destroy_descriptor_set_layout(self.set_layout, None);
+        self.device.destroy_sampler(self.sampler, None);
+    }
+}
+
+/// mip 0 is `extent`, each further mip halves it rounding down, down to 1x1
+pub fn level_extents(extent: vk::Extent2D) -> Vec<vk::Extent2D> {
+    let mut extents = vec![extent];
+    while let Some(&last) = extents.last().filter(|last| last.width > 1 || last.height > 1) {
+        extents.push(vk::Extent2D { width: (last.width / 2).max(1), height: (last.height / 2).max(1) });
+    }
+    assert!(extents.len() as u32 <= MAX_HIZ_LEVELS, "Depth buffer too large for the depth pyramid");
+    extents
+}
+
+/// what hiz.comp computes for a mip after the first, `source` is the row major mip before it
+pub fn reduce_level(source: &[f32], source_extent: vk::Extent2D, reverse_z: bool) -> Vec<f32> {
+    let farthest = |a: f32, b: f32| if reverse_z { a.min(b) } else { a.max(b) };
+    let width = (source_extent.width / 2).max(1);
+    let height = (source_extent.height / 2).max(1);
+
+    // the last texel of each axis also covers an odd source's last row or column
+    let range = |texel: u32, size: u32, source_size: u32| {
+        let first = (2 * texel).min(source_size - 1);
+        let extra = (texel == size - 1) as u32 * (source_size % 2);
+        first..=(first + 1 + extra).min(source_size - 1)
+    };
+
+    let mut destination = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let mut depth = source[(range(y, height, source_extent.height).start() * source_extent.width
+                + range(x, width, source_extent.width).start()) as usize];
+            for source_y in range(y, height, source_extent.height) {
+                for source_x in range(x, width, source_extent.width) {
+                    depth = farthest(depth, source[(source_y * source_extent.width + source_x) as usize]);
+                }
+            }
+            destination.push(depth);
+        }
+    }
+    destination
+}
+
+#[test]
+fn test_hiz_pyramid() {
+    let extent = |width, height| vk::Extent2D { width, height };
+    let extents = level_extents(extent(5, 2));
+    assert!(extents == [extent(5, 2), extent(2, 1), extent(1, 1)]);
+    assert!(level_extents(extent(1, 1)).len() == 1);
+
+    // the last column of the odd width folds into the last texel
+    let depths = [
+        0.1, 0.2, 0.3, 0.4, 0.9,
+        0.5, 0.1, 0.2, 0.1, 0.1,
+    ];
+    assert!(reduce_level(&depths, extent(5, 2), false) == [0.5, 0.9]);
+    assert!(reduce_level(&depths, extent(5, 2), true) == [0.1, 0.1]);
+
+    // a 3x3 source reduces to one texel covering all of it
+    let depths = [0.1, 0.1, 0.1, 0.1, 0.1, 0.1, 0.1, 0.1, 0.7];
+    assert!(reduce_level(&depths, extent(3, 3), false) == [0.7]);
+    assert!(reduce_level(&[0.3, 0.6], extent(1, 2), false) == [0.6]);
+}
@@ -0,0 +1,614 @@
+// Image based lighting from an hdr environment map. The equirectangular map is projected onto a
+// cubemap which compute shaders convolve into an irradiance cubemap for diffuse light and a
+// prefiltered cubemap for specular light, roughening with each mip, next to a BRDF lookup table
+// indexed by view angle and roughness. The three are bound as set 2 of the scene pipelines
+// and light the materials with pbr shading:
+//
+//     app.set_environment("images/sky.hdr");
+//
+// The convolution runs once when the environment is set, waiting for the gpu
+// TODO: mips of the source cubemap for the prefilter, bright spots sparkle at high roughness
+
+use std::{fs::File, io::{BufRead, BufReader}, mem::size_of, rc::Rc};
+
+use ash::vk;
+
+use super::{buffer::Buffer, image, pipeline, reflection_probe, shader, ssr};
+
+/// side of the cubemap the equirectangular map is projected onto
+pub const ENVIRONMENT_SIZE: u32 = 512;
+pub const IRRADIANCE_SIZE: u32 = 32;
+/// side of the prefiltered cubemap's first mip
+pub const PREFILTERED_SIZE: u32 = 128;
+/// roughness goes from 0 at the first mip to 1 at the last, must match shaders/pbr.frag
+pub const PREFILTERED_MIP_LEVELS: u32 = 5;
+pub const BRDF_LUT_SIZE: u32 = 256;
+/// importance samples per texel of the prefiltered cubemap and the lut, fewer convolve faster but noisier
+pub const PREFILTER_SAMPLE_COUNT: u32 = 512;
+pub const BRDF_SAMPLE_COUNT: u32 = 1024;
+/// of `SAMPLE_COUNT` in ibl_prefilter.comp and ibl_brdf.comp
+pub const SAMPLE_COUNT_CONSTANT_ID: u32 = pipeline::FIRST_CUSTOM_CONSTANT_ID;
+
+/// of the cubemaps and lut, writable as storage images on every device
+pub const FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+/// of the uploaded equirectangular map, only ever sampled with nearest filtering
+const EQUIRECT_FORMAT: vk::Format = vk::Format::R32G32B32A32_SFLOAT;
+/// must match the local size of the ibl compute shaders
+pub const WORKGROUP_SIZE: u32 = 8;
+
+/// roughness the prefiltered cubemap's mip is convolved with
+pub fn prefilter_roughness(mip: u32) -> f32 {
+    mip as f32 / (PREFILTERED_MIP_LEVELS - 1) as f32
+}
+
+/// must match the push constant block in shaders/ibl_prefilter.comp
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PrefilterPushConstants {
+    pub roughness: f32,
+}
+
+/// irradiance cubemap, prefiltered cubemap and BRDF lut for the fragment shaders,
+/// then the reflection probes, written by `reflection_probe`, and the screen space reflections by `ssr`
+pub fn new_ibl_set_layout(device: &ash::Device) -> vk::DescriptorSetLayout {
+    let bindings = [
+        (0, vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+        (1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+        (2, vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+        (reflection_probe::PROBES_BINDING, vk::DescriptorType::UNIFORM_BUFFER),
+        (reflection_probe::PROBE_CUBES_BINDING, vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+        (ssr::SSR_BINDING, vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+    ].map(|(binding, ty)| vk::DescriptorSetLayoutBinding::builder()
+        .binding(binding)
+        .descriptor_type(ty)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+        .build()
+    );
+    unsafe {
+        let info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings);
+        device.create_descriptor_set_layout(&info, None).unwrap()
+    }
+}
+
+/// equirectangular, rows from the top down
+pub struct DecodedEnvironment {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[f32; 4]>,
+}
+
+impl DecodedEnvironment {
+    /// panics when `path` isn't a radiance hdr file
+    pub fn decode(path: &str) -> Self {
+        let file = File::open(path).unwrap_or_else(|err| panic!("Failed to open environment {}: {}", path, err));
+        Self::from_reader(BufReader::new(file))
+            .unwrap_or_else(|err| panic!("Failed to decode environment {}: {}", path, err))
+    }
+
+    pub fn from_reader<R: BufRead>(reader: R) -> ::image::ImageResult<Self> {
+        let decoder = ::image::hdr::HDRDecoder::new(reader)?;
+        let metadata = decoder.metadata();
+        let pixels = decoder
+            .read_image_hdr()?
+            .into_iter()
+            .map(|::image::Rgb { data: [r, g, b] }| [r, g, b, 1.0])
+            .collect();
+        Ok(Self {
+            width: metadata.width,
+            height: metadata.height,
+            pixels,
+        })
+    }
+}
+
+/// transitions every mip and layer of `image`
+fn cmd_barrier(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    (old_layout, src_access_mask, src_stage): (vk::ImageLayout, vk::AccessFlags, vk::PipelineStageFlags),
+    (new_layout, dst_access_mask, dst_stage): (vk::ImageLayout, vk::AccessFlags, vk::PipelineStageFlags),
+) {
+    let barrier = vk::ImageMemoryBarrier::builder()
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: vk::REMAINING_MIP_LEVELS,
+            base_array_layer: 0,
+            layer_count: vk::REMAINING_ARRAY_LAYERS,
+        })
+        .src_access_mask(src_access_mask)
+        .dst_access_mask(dst_access_mask)
+        .build();
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage,
+            dst_stage,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[barrier],
+        );
+    }
+}
+
+/// Holds the lighting precomputed from an environment and the set binding it,
+/// black until `convolve`d
+pub struct Environment {
+    device: Rc<ash::Device>,
+    /// irradiance cubemap, prefiltered cubemap and BRDF lut, in binding order
+    images: [(vk::Image, vk::DeviceMemory, vk::ImageView); 3],
+    /// trilinear, for the prefiltered mips
+    sampler: vk::Sampler,
+    descriptor_pool: vk::DescriptorPool,
+    set: vk::DescriptorSet,
+}
+
+impl Environment {
+    pub fn new(
+        device: Rc<ash::Device>,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        set_layout: vk::DescriptorSetLayout,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+    ) -> Self {
+        // the prefiltered cubemap is copied into reflection probes until they are captured
+        let usage = vk::ImageUsageFlags::STORAGE
+            | vk::ImageUsageFlags::SAMPLED
+            | vk::ImageUsageFlags::TRANSFER_SRC
+            | vk::ImageUsageFlags::TRANSFER_DST;
+        let new_cube = |size, mip_levels| {
+            let (image, memory) = image::new_cube_image_and_memory(
+                &device,
+                physical_device_memory_properties,
+                size,
+                mip_levels,
+                usage,
+                FORMAT,
+            );
+            let view = image::new_cube_image_view(&device, image, vk::ImageViewType::CUBE, FORMAT, 0, mip_levels);
+            (image, memory, view)
+        };
+        let irradiance = new_cube(IRRADIANCE_SIZE, 1);
+        let prefiltered = new_cube(PREFILTERED_SIZE, PREFILTERED_MIP_LEVELS);
+        let brdf_lut = {
+            let (image, memory) = image::new_image_and_memory(
+                &device,
+                physical_device_memory_properties,
+                &image::ImageDesc {
+                    width: BRDF_LUT_SIZE,
+                    height: BRDF_LUT_SIZE,
+                    usage,
+                    format: FORMAT,
+                    ..Default::default()
+                },
+            );
+            let view = image::new_image_view(&device, image, FORMAT, vk::ImageAspectFlags::COLOR, 1);
+            (image, memory, view)
+        };
+        let images = [irradiance, prefiltered, brdf_lut];
+
+        let sampler = unsafe {
+            let info = vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::LINEAR)
+                .min_filter(vk::Filter::LINEAR)
+                .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .max_lod(PREFILTERED_MIP_LEVELS as f32);
+            device.create_sampler(&info, None).unwrap()
+        };
+
+        let descriptor_pool = unsafe {
+            // with the reflection probes' buffer and cubemap array and the screen space reflections
+            let pool_sizes = [
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    descriptor_count: images.len() as u32 + 2,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::UNIFORM_BUFFER,
+                    descriptor_count: 1,
+                },
+            ];
+            let info = vk::DescriptorPoolCreateInfo::builder()
+                .max_sets(1)
+                .pool_sizes(&pool_sizes);
+            device.create_descriptor_pool(&info, None).expect("Failed to create descriptor pool")
+        };
+
+        let set = unsafe {
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(descriptor_pool)
+                .set_layouts(&[set_layout])
+                .build();
+            device.allocate_descriptor_sets(&alloc_info).unwrap()[0]
+        };
+        let image_infos = images.map(|(_, _, image_view)| [vk::DescriptorImageInfo {
+            sampler,
+            image_view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        }]);
+        let writes: Vec<vk::WriteDescriptorSet> = image_infos
+            .iter()
+            .enumerate()
+            .map(|(binding, image_info)| vk::WriteDescriptorSet::builder()
+                .dst_set(set)
+                .dst_binding(binding as u32)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(image_info)
+                .build()
+            )
+            .collect();
+        unsafe { device.update_descriptor_sets(&writes, &[]) };
+
+        // black, so the set can be bound before there is an environment
+        super::VkApp::execute_transient_commands(&device, command_pool, queue, |command_buffer| {
+            for (image, _, _) in images {
+                cmd_barrier(
+                    &device,
+                    command_buffer,
+                    image,
+                    (vk::ImageLayout::UNDEFINED, vk::AccessFlags::empty(), vk::PipelineStageFlags::TOP_OF_PIPE),
+                    (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::AccessFlags::TRANSFER_WRITE, vk::PipelineStageFlags::TRANSFER),
+                );
+                unsafe {
+                    device.cmd_clear_color_image(
+                        command_buffer,
+                        image,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        &vk::ClearColorValue { float32: [0.0, 0.0, 0.0, 1.0] },
+                        &[vk::ImageSubresourceRange {
+                            aspect_mask: vk::ImageAspectFlags::COLOR,
+                            base_mip_level: 0,
+                            level_count: vk::REMAINING_MIP_LEVELS,
+                            base_array_layer: 0,
+                            layer_count: vk::REMAINING_ARRAY_LAYERS,
+                        }],
+                    );
+                }
+                cmd_barrier(
+                    &device,
+                    command_buffer,
+                    image,
+                    (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::AccessFlags::TRANSFER_WRITE, vk::PipelineStageFlags::TRANSFER),
+                    (vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::AccessFlags::SHADER_READ, vk::PipelineStageFlags::FRAGMENT_SHADER),
+                );
+            }
+        });
+
+        Self {
+            device,
+            images,
+            sampler,
+            descriptor_pool,
+            set,
+        }
+    }
+
+    pub fn get_set(&self) -> vk::DescriptorSet {
+        self.set
+    }
+
+    /// in SHADER_READ_ONLY_OPTIMAL layout, `PREFILTERED_SIZE` with `PREFILTERED_MIP_LEVELS` mips
+    pub fn get_prefiltered_image(&self) -> vk::Image {
+        self.images[1].0
+    }
+
+    /// recomputes the lighting from `decoded`, no frame may be in flight
+    pub fn convolve(
+        &mut self,
+        decoded: &DecodedEnvironment,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        shader_compiler: &shader::ShaderCompiler,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+    ) {
+        let device = self.device.clone();
+
+        let mut staging_buffer = Buffer::new(
+            (decoded.pixels.len() * size_of::<[f32; 4]>()) as vk::DeviceSize,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            device.clone(),
+            physical_device_memory_properties,
+        );
+        staging_buffer.copy_from_slice(&decoded.pixels, 0);
+        let (equirect_image, equirect_memory) = image::new_image_and_memory(
+            &device,
+            physical_device_memory_properties,
+            &image::ImageDesc {
+                width: decoded.width,
+                height: decoded.height,
+                usage: vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+                format: EQUIRECT_FORMAT,
+                ..Default::default()
+            },
+        );
+        let equirect_view = image::new_image_view(&device, equirect_image, EQUIRECT_FORMAT, vk::ImageAspectFlags::COLOR, 1);
+        let (environment_image, environment_memory) = image::new_cube_image_and_memory(
+            &device,
+            physical_device_memory_properties,
+            ENVIRONMENT_SIZE,
+            1,
+            vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+            FORMAT,
+        );
+        let environment_view = image::new_cube_image_view(&device, environment_image, vk::ImageViewType::CUBE, FORMAT, 0, 1);
+
+        // written through 2d array views, a mip each
+        let [irradiance, prefiltered, brdf_lut] = self.images.map(|(image, _, _)| image);
+        let mut storage_views = vec![
+            image::new_cube_image_view(&device, environment_image, vk::ImageViewType::TYPE_2D_ARRAY, FORMAT, 0, 1),
+            image::new_cube_image_view(&device, irradiance, vk::ImageViewType::TYPE_2D_ARRAY, FORMAT, 0, 1),
+        ];
+        storage_views.extend((0..PREFILTERED_MIP_LEVELS).map(|mip| {
+            image::new_cube_image_view(&device, prefiltered, vk::ImageViewType::TYPE_2D_ARRAY, FORMAT, mip, 1)
+        }));
+        storage_views.push(image::new_image_view(&device, brdf_lut, FORMAT, vk::ImageAspectFlags::COLOR, 1));
+
+        let equirect_sampler = unsafe {
+            let info = vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::NEAREST)
+                .min_filter(vk::Filter::NEAREST)
+                .address_mode_u(vk::SamplerAddressMode::REPEAT)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE);
+            device.create_sampler(&info, None).unwrap()
+        };
+
+        // source to sample at binding 0, storage image to write at binding 1
+        let set_layout = unsafe {
+            let bindings = [
+                (0, vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+                (1, vk::DescriptorType::STORAGE_IMAGE),
+            ].map(|(binding, ty)| vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_type(ty)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build()
+            );
+            let info = vk::DescriptorSetLayoutCreateInfo::builder()
+                .bindings(&bindings);
+            device.create_descriptor_set_layout(&info, None).unwrap()
+        };
+        let descriptor_pool = unsafe {
+            let pool_sizes = [
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    descriptor_count: storage_views.len() as u32,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_IMAGE,
+                    descriptor_count: storage_views.len() as u32,
+                },
+            ];
+            let info = vk::DescriptorPoolCreateInfo::builder()
+                .max_sets(storage_views.len() as u32)
+                .pool_sizes(&pool_sizes);
+            device.create_descriptor_pool(&info, None).expect("Failed to create descriptor pool")
+        };
+        let sets = unsafe {
+            let set_layouts = vec![set_layout; storage_views.len()];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(descriptor_pool)
+                .set_layouts(&set_layouts)
+                .build();
+            device.allocate_descriptor_sets(&alloc_info).unwrap()
+        };
+
+        // the lut's source is left unwritten, its shader samples nothing
+        let brdf_set = sets.len() - 1;
+        let source_infos: Vec<[vk::DescriptorImageInfo; 1]> = (0..brdf_set)
+            .map(|i| [if i == 0 {
+                vk::DescriptorImageInfo {
+                    sampler: equirect_sampler,
+                    image_view: equirect_view,
+                    image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                }
+            } else {
+                vk::DescriptorImageInfo {
+                    sampler: self.sampler,
+                    image_view: environment_view,
+                    image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                }
+            }])
+            .collect();
+        let storage_infos: Vec<[vk::DescriptorImageInfo; 1]> = storage_views
+            .iter()
+            .map(|&image_view| [vk::DescriptorImageInfo {
+                sampler: vk::Sampler::null(),
+                image_view,
+                image_layout: vk::ImageLayout::GENERAL,
+            }])
+            .collect();
+        let mut writes = vec![];
+        for (i, &set) in sets.iter().enumerate() {
+            if let Some(source_info) = source_infos.get(i) {
+                writes.push(vk::WriteDescriptorSet::builder()
+                    .dst_set(set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(source_info)
+                    .build());
+            }
+            writes.push(vk::WriteDescriptorSet::builder()
+                .dst_set(set)
+                .dst_binding(1)
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .image_info(&storage_infos[i])
+                .build());
+        }
+        unsafe { device.update_descriptor_sets(&writes, &[]) };
+
+        let prefilter_range = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            offset: 0,
+            size: size_of::<PrefilterPushConstants>() as u32,
+        };
+        let pipelines = [
+            ("shaders/ibl_equirect.comp", None),
+            ("shaders/ibl_irradiance.comp", None),
+            ("shaders/ibl_prefilter.comp", Some(PREFILTER_SAMPLE_COUNT)),
+            ("shaders/ibl_brdf.comp", Some(BRDF_SAMPLE_COUNT)),
+        ].map(|(shader_path, sample_count)| pipeline::new_compute_pipeline_and_layout(
+            &device,
+            shader_compiler,
+            shader_path,
+            &[set_layout],
+            &[prefilter_range],
+            &sample_count.map_or(vec![], |sample_count| vec![(SAMPLE_COUNT_CONSTANT_ID, pipeline::Constant::U32(sample_count))]),
+        ));
+        let [equirect_pipeline, irradiance_pipeline, prefilter_pipeline, brdf_pipeline] = pipelines;
+
+        let cmd_dispatch = |command_buffer, (pipeline, pipeline_layout): (vk::Pipeline, vk::PipelineLayout), set, size: u32, layers| unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, pipeline);
+            device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::COMPUTE, pipeline_layout, 0, &[set], &[]);
+            let group_count = size.div_ceil(WORKGROUP_SIZE);
+            device.cmd_dispatch(command_buffer, group_count, group_count, layers);
+        };
+        let compute_write = (vk::AccessFlags::SHADER_WRITE, vk::PipelineStageFlags::COMPUTE_SHADER);
+
+        super::VkApp::execute_transient_commands(&device, command_pool, queue, |command_buffer| unsafe {
+            cmd_barrier(
+                &device,
+                command_buffer,
+                equirect_image,
+                (vk::ImageLayout::UNDEFINED, vk::AccessFlags::empty(), vk::PipelineStageFlags::TOP_OF_PIPE),
+                (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::AccessFlags::TRANSFER_WRITE, vk::PipelineStageFlags::TRANSFER),
+            );
+            let region = vk::BufferImageCopy::builder()
+                .image_subresource(vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: 0,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .image_extent(vk::Extent3D {
+                    width: decoded.width,
+                    height: decoded.height,
+                    depth: 1,
+                })
+                .build();
+            device.cmd_copy_buffer_to_image(
+                command_buffer,
+                staging_buffer.handle,
+                equirect_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[region],
+            );
+            cmd_barrier(
+                &device,
+                command_buffer,
+                equirect_image,
+                (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::AccessFlags::TRANSFER_WRITE, vk::PipelineStageFlags::TRANSFER),
+                (vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::AccessFlags::SHADER_READ, vk::PipelineStageFlags::COMPUTE_SHADER),
+            );
+
+            cmd_barrier(
+                &device,
+                command_buffer,
+                environment_image,
+                (vk::ImageLayout::UNDEFINED, vk::AccessFlags::empty(), vk::PipelineStageFlags::TOP_OF_PIPE),
+                (vk::ImageLayout::GENERAL, compute_write.0, compute_write.1),
+            );
+            cmd_dispatch(command_buffer, equirect_pipeline, sets[0], ENVIRONMENT_SIZE, 6);
+            cmd_barrier(
+                &device,
+                command_buffer,
+                environment_image,
+                (vk::ImageLayout::GENERAL, compute_write.0, compute_write.1),
+                (vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::AccessFlags::SHADER_READ, vk::PipelineStageFlags::COMPUTE_SHADER),
+            );
+
+            // the previous contents are discarded
+            for image in [irradiance, prefiltered, brdf_lut] {
+                cmd_barrier(
+                    &device,
+                    command_buffer,
+                    image,
+                    (vk::ImageLayout::UNDEFINED, vk::AccessFlags::empty(), vk::PipelineStageFlags::TOP_OF_PIPE),
+                    (vk::ImageLayout::GENERAL, compute_write.0, compute_write.1),
+                );
+            }
+            cmd_dispatch(command_buffer, irradiance_pipeline, sets[1], IRRADIANCE_SIZE, 6);
+            for mip in 0..PREFILTERED_MIP_LEVELS {
+                let push_constants = PrefilterPushConstants { roughness: prefilter_roughness(mip) };
+                device.cmd_push_constants(
+                    command_buffer,
+                    prefilter_pipeline.1,
+                    prefilter_range.stage_flags,
+                    0,
+                    std::slice::from_raw_parts(
+                        &push_constants as *const PrefilterPushConstants as *const u8,
+                        size_of::<PrefilterPushConstants>(),
+                    ),
+                );
+                cmd_dispatch(command_buffer, prefilter_pipeline, sets[2 + mip as usize], PREFILTERED_SIZE >> mip, 6);
+            }
+            cmd_dispatch(command_buffer, brdf_pipeline, sets[brdf_set], BRDF_LUT_SIZE, 1);
+            for image in [irradiance, prefiltered, brdf_lut] {
+                cmd_barrier(
+                    &device,
+                    command_buffer,
+                    image,
+                    (vk::ImageLayout::GENERAL, compute_write.0, compute_write.1),
+                    (vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::AccessFlags::SHADER_READ, vk::PipelineStageFlags::FRAGMENT_SHADER),
+                );
+            }
+        });
+
+        unsafe {
+            for (pipeline, pipeline_layout) in pipelines {
+                device.destroy_pipeline(pipeline, None);
+                device.destroy_pipeline_layout(pipeline_layout, None);
+            }
+            device.destroy_descriptor_pool(descriptor_pool, None);
+            device.destroy_descriptor_set_layout(set_layout, None);
+            device.destroy_sampler(equirect_sampler, None);
+            for view in storage_views {
+                device.destroy_image_view(view, None);
+            }
+            device.destroy_image_view(environment_view, None);
+            device.destroy_image(environment_image, None);
+            device.free_memory(environment_memory, None);
+            device.destroy_image_view(equirect_view, None);
+            device.destroy_image(equirect_image, None);
+            device.free_memory(equirect_memory, None);
+            staging_buffer.destroy();
+        }
+    }
+
+    // caller must ensure only called once
+    pub unsafe fn destroy(&mut self) {
+        self.device.destroy_descriptor_pool(self.descriptor_pool, None);
+        self.device.destroy_sampler(self.sampler, None);
+        for (image, memory, view) in self.images {
+            self.device.destroy_image_view(view, None);
+            self.device.destroy_image(image, None);
+            self.device.free_memory(memory, None);
+        }
+    }
+}
+
+#[test]
+fn test_ibl() {
+    assert!(prefilter_roughness(0) == 0.0 && prefilter_roughness(PREFILTERED_MIP_LEVELS - 1) == 1.0);
+
+    let pixels = vec![::image::Rgb { data: [4.0, 0.5, 0.0] }, ::image::Rgb { data: [0.0, 0.0, 1.0] }];
+    let mut encoded = vec![];
+    ::image::hdr::HDREncoder::new(&mut encoded).encode(&pixels, 2, 1).unwrap();
+    let decoded = DecodedEnvironment::from_reader(encoded.as_slice()).unwrap();
+    assert!(decoded.width == 2 && decoded.height == 1);
+    assert!(decoded.pixels == [[4.0, 0.5, 0.0, 1.0], [0.0, 0.0, 1.0, 1.0]]);
+}
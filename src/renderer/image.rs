@@ -1,52 +1,133 @@
 use ash::vk;
 
+use super::synchronization::Synchronization;
+
+/// a 2d image of a single layer, by default of one mip level, optimally tiled and device local
+#[derive(Clone, Copy, Debug)]
+pub struct ImageDesc {
+    pub width: u32,
+    pub height: u32,
+    pub mip_levels: u32,
+    pub usage: vk::ImageUsageFlags,
+    pub format: vk::Format,
+    pub tiling: vk::ImageTiling,
+    pub memory_properties: vk::MemoryPropertyFlags,
+}
+
+impl Default for ImageDesc {
+    fn default() -> Self {
+        Self {
+            width: 0,
+            height: 0,
+            mip_levels: 1,
+            usage: vk::ImageUsageFlags::empty(),
+            format: vk::Format::UNDEFINED,
+            tiling: vk::ImageTiling::OPTIMAL,
+            memory_properties: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        }
+    }
+}
+
 pub fn new_image_and_memory(
     device: &ash::Device,
     physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
-    width: u32,
-    height: u32,
+    desc: &ImageDesc,
+) -> (vk::Image, vk::DeviceMemory) {
+    let info = vk::ImageCreateInfo::builder()
+        .image_type(vk::ImageType::TYPE_2D)
+        .extent(vk::Extent3D {
+            width: desc.width,
+            height: desc.height,
+            depth: 1,
+        })
+        .mip_levels(desc.mip_levels)
+        .array_layers(1)
+        .format(desc.format)
+        .tiling(desc.tiling)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .usage(desc.usage)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .flags(vk::ImageCreateFlags::empty());
+
+    let image = unsafe { device.create_image(&info, None).unwrap() };
+    let memory = allocate_and_bind_image_memory(device, physical_device_memory_properties, image, desc.memory_properties);
+
+    (image, memory)
+}
+
+/// six layers viewed through `new_cube_image_view`, optimal tiling and device local
+pub fn new_cube_image_and_memory(
+    device: &ash::Device,
+    physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    size: u32,
+    mip_levels: u32,
+    usage: vk::ImageUsageFlags,
+    format: vk::Format,
+) -> (vk::Image, vk::DeviceMemory) {
+    new_cube_array_image_and_memory(device, physical_device_memory_properties, size, mip_levels, 1, usage, format)
+}
+
+/// `cube_count` cubemaps of 6 layers each, viewable as a cube array
+pub fn new_cube_array_image_and_memory(
+    device: &ash::Device,
+    physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    size: u32,
+    mip_levels: u32,
+    cube_count: u32,
     usage: vk::ImageUsageFlags,
     format: vk::Format,
-    tiling: vk::ImageTiling,
-    memory_properties: vk::MemoryPropertyFlags,
 ) -> (vk::Image, vk::DeviceMemory) {
     let info = vk::ImageCreateInfo::builder()
         .image_type(vk::ImageType::TYPE_2D)
         .extent(vk::Extent3D {
-            width,
-            height,
+            width: size,
+            height: size,
             depth: 1,
         })
-        .mip_levels(1)
-        .array_layers(1)
+        .mip_levels(mip_levels)
+        .array_layers(6 * cube_count)
         .format(format)
-        .tiling(tiling)
+        .tiling(vk::ImageTiling::OPTIMAL)
         .initial_layout(vk::ImageLayout::UNDEFINED)
         .usage(usage)
         .sharing_mode(vk::SharingMode::EXCLUSIVE)
         .samples(vk::SampleCountFlags::TYPE_1)
-        .flags(vk::ImageCreateFlags::empty());
+        .flags(vk::ImageCreateFlags::CUBE_COMPATIBLE);
 
     let image = unsafe { device.create_image(&info, None).unwrap() };
+    let memory = allocate_and_bind_image_memory(
+        device,
+        physical_device_memory_properties,
+        image,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    );
+
+    (image, memory)
+}
 
+fn allocate_and_bind_image_memory(
+    device: &ash::Device,
+    physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    image: vk::Image,
+    memory_properties: vk::MemoryPropertyFlags,
+) -> vk::DeviceMemory {
     let mem_requirements = unsafe { device.get_image_memory_requirements(image) };
     let mem_type_index = super::device::find_mem_type_index(
         mem_requirements.memory_type_bits,
         memory_properties,
-        &physical_device_memory_properties,
+        physical_device_memory_properties,
     );
 
     let alloc_info = vk::MemoryAllocateInfo::builder()
         .allocation_size(mem_requirements.size)
         .memory_type_index(mem_type_index)
         .build();
-    let memory = unsafe {
+    unsafe {
         let mem = device.allocate_memory(&alloc_info, None).unwrap();
         device.bind_image_memory(image, mem, 0).unwrap();
         mem
-    };
-
-    (image, memory)
+    }
 }
 
 pub fn new_image_view(
@@ -54,6 +135,7 @@ pub fn new_image_view(
     image: vk::Image,
     format: vk::Format,
     aspect_mask: vk::ImageAspectFlags,
+    mip_levels: u32,
 ) -> vk::ImageView {
     let create_info = vk::ImageViewCreateInfo::builder()
         .image(image)
@@ -62,7 +144,7 @@ pub fn new_image_view(
         .subresource_range(vk::ImageSubresourceRange {
             aspect_mask,
             base_mip_level: 0,
-            level_count: 1,
+            level_count: mip_levels,
             base_array_layer: 0,
             layer_count: 1,
         });
@@ -70,52 +152,111 @@ pub fn new_image_view(
     unsafe { device.create_image_view(&create_info, None).unwrap() }
 }
 
-pub fn cmd_transition_image_layout(
+/// `view_type` CUBE for sampling or TYPE_2D_ARRAY for storage, over every face of `mip_levels` mips
+pub fn new_cube_image_view(
     device: &ash::Device,
     image: vk::Image,
-    command_buffer: vk::CommandBuffer,
-    queue_family_index: u32,
+    view_type: vk::ImageViewType,
     format: vk::Format,
-    old_layout: vk::ImageLayout,
-    new_layout: vk::ImageLayout,
-) {
-    let (src_access_mask, dst_access_mask, src_stage, dst_stage) = match (old_layout, new_layout) {
-        (vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL) => (
-            vk::AccessFlags::empty(),
-            vk::AccessFlags::TRANSFER_WRITE,
-            vk::PipelineStageFlags::TOP_OF_PIPE,
-            vk::PipelineStageFlags::TRANSFER,
+    base_mip_level: u32,
+    mip_levels: u32,
+) -> vk::ImageView {
+    new_image_view_of_range(device, image, view_type, format, vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level,
+        level_count: mip_levels,
+        base_array_layer: 0,
+        layer_count: 6,
+    })
+}
+
+/// e.g. some layers of a cube array
+pub fn new_image_view_of_range(
+    device: &ash::Device,
+    image: vk::Image,
+    view_type: vk::ImageViewType,
+    format: vk::Format,
+    subresource_range: vk::ImageSubresourceRange,
+) -> vk::ImageView {
+    let create_info = vk::ImageViewCreateInfo::builder()
+        .image(image)
+        .view_type(view_type)
+        .format(format)
+        .subresource_range(subresource_range);
+
+    unsafe { device.create_image_view(&create_info, None).unwrap() }
+}
+
+/// stages and accesses of an image in `layout`, on either side of a transition
+fn layout_scope(layout: vk::ImageLayout) -> (vk::PipelineStageFlags2, vk::AccessFlags2) {
+    match layout {
+        vk::ImageLayout::UNDEFINED => (vk::PipelineStageFlags2::NONE, vk::AccessFlags2::NONE),
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL => (vk::PipelineStageFlags2::ALL_TRANSFER, vk::AccessFlags2::TRANSFER_WRITE),
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL => (vk::PipelineStageFlags2::ALL_TRANSFER, vk::AccessFlags2::TRANSFER_READ),
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => (
+            vk::PipelineStageFlags2::FRAGMENT_SHADER,
+            vk::AccessFlags2::SHADER_SAMPLED_READ,
         ),
-        (vk::ImageLayout::UNDEFINED, vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL) => (
-            vk::AccessFlags::empty(),
-            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
-                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
-            vk::PipelineStageFlags::TOP_OF_PIPE,
-            vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL => (
+            vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+            vk::AccessFlags2::COLOR_ATTACHMENT_READ | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
         ),
-        (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL) => (
-            vk::AccessFlags::TRANSFER_WRITE,
-            vk::AccessFlags::SHADER_READ,
-            vk::PipelineStageFlags::TRANSFER,
-            vk::PipelineStageFlags::FRAGMENT_SHADER,
-        ),
-        _ => panic!(
-            "Unsupported layout transition({:?} => {:?}).",
-            old_layout, new_layout
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL => (
+            vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS,
+            vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
         ),
+        // the stage the frame waits for the swapchain image at, presentation waits on a semaphore
+        vk::ImageLayout::PRESENT_SRC_KHR => (vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT, vk::AccessFlags2::NONE),
+        _ => panic!("Unsupported layout transition from or to {:?}.", layout),
+    }
+}
+
+/// the source and destination stages and accesses of a transition from `old_layout` to `new_layout`
+pub fn transition_scopes(
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+) -> ((vk::PipelineStageFlags2, vk::AccessFlags2), (vk::PipelineStageFlags2, vk::AccessFlags2)) {
+    let (dst_stage, dst_access) = layout_scope(new_layout);
+    let (src_stage, src_access) = match old_layout {
+        // nothing to wait for, but waiting at the destination's stages chains the transition
+        // after semaphore waits there, like the swapchain image's acquire
+        vk::ImageLayout::UNDEFINED => (dst_stage, vk::AccessFlags2::NONE),
+        _ => layout_scope(old_layout),
     };
+    ((src_stage, src_access), (dst_stage, dst_access))
+}
+
+/// a layout transition of every mip level of an image's first layer
+#[derive(Clone, Copy, Debug)]
+pub struct ImageTransition {
+    pub image: vk::Image,
+    /// picks the aspect of transitions into a depth layout
+    pub format: vk::Format,
+    pub mip_levels: u32,
+    pub old_layout: vk::ImageLayout,
+    pub new_layout: vk::ImageLayout,
+}
+
+pub fn cmd_transition_image_layout(
+    sync: &Synchronization,
+    command_buffer: vk::CommandBuffer,
+    queue_family_index: u32,
+    transition: &ImageTransition,
+) {
+    let &ImageTransition { image, format, mip_levels, old_layout, new_layout } = transition;
+    let ((src_stage, src_access_mask), (dst_stage, dst_access_mask)) = transition_scopes(old_layout, new_layout);
 
     let aspect_mask = if new_layout == vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL {
-        let mut mask = vk::ImageAspectFlags::DEPTH;
-        if has_stencil_component(format) {
-            mask |= vk::ImageAspectFlags::STENCIL;
-        }
-        mask
+        get_depth_aspect_mask(format)
     } else {
         vk::ImageAspectFlags::COLOR
     };
 
-    let barrier = vk::ImageMemoryBarrier::builder()
+    let barrier = vk::ImageMemoryBarrier2::builder()
+        .src_stage_mask(src_stage)
+        .src_access_mask(src_access_mask)
+        .dst_stage_mask(dst_stage)
+        .dst_access_mask(dst_access_mask)
         .old_layout(old_layout)
         .new_layout(new_layout)
         .src_queue_family_index(queue_family_index)
@@ -124,19 +265,183 @@ pub fn cmd_transition_image_layout(
         .subresource_range(vk::ImageSubresourceRange {
             aspect_mask,
             base_mip_level: 0,
+            level_count: mip_levels,
+            base_array_layer: 0,
+            layer_count: 1,
+        })
+        .build();
+
+    sync.cmd_pipeline_barrier(command_buffer, &[], &[barrier]);
+}
+
+pub fn cmd_copy_image_to_buffer(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    buffer: vk::Buffer,
+    width: u32,
+    height: u32,
+    mip_level: u32,
+) {
+    let region = vk::BufferImageCopy::builder()
+        .buffer_offset(0)
+        .buffer_row_length(0)
+        .buffer_image_height(0)
+        .image_subresource(vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level,
+            base_array_layer: 0,
+            layer_count: 1,
+        })
+        .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+        .image_extent(vk::Extent3D {
+            width,
+            height,
+            depth: 1,
+        })
+        .build();
+
+    unsafe {
+        device.cmd_copy_image_to_buffer(
+            command_buffer,
+            image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            buffer,
+            &[region],
+        )
+    }
+}
+
+/// checks if mips of `format` can be generated by blitting on the device
+pub fn supports_blit_mips(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    format: vk::Format,
+) -> bool {
+    let props = unsafe { instance.get_physical_device_format_properties(physical_device, format) };
+
+    props.optimal_tiling_features.contains(
+        vk::FormatFeatureFlags::BLIT_SRC
+            | vk::FormatFeatureFlags::BLIT_DST
+            | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR
+    )
+}
+
+/// expects all mip levels of the image to be in TRANSFER_DST_OPTIMAL with level 0 filled,
+/// leaves all mip levels in SHADER_READ_ONLY_OPTIMAL
+pub fn cmd_generate_mips(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    width: u32,
+    height: u32,
+    mip_levels: u32,
+) {
+    let mut barrier = vk::ImageMemoryBarrier::builder()
+        .image(image)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .subresource_range(vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
             level_count: 1,
             base_array_layer: 0,
             layer_count: 1,
         })
-        .src_access_mask(src_access_mask)
-        .dst_access_mask(dst_access_mask)
         .build();
 
+    let mut mip_width = width as i32;
+    let mut mip_height = height as i32;
+
+    for level in 1..mip_levels {
+        barrier.subresource_range.base_mip_level = level - 1;
+        barrier.old_layout = vk::ImageLayout::TRANSFER_DST_OPTIMAL;
+        barrier.new_layout = vk::ImageLayout::TRANSFER_SRC_OPTIMAL;
+        barrier.src_access_mask = vk::AccessFlags::TRANSFER_WRITE;
+        barrier.dst_access_mask = vk::AccessFlags::TRANSFER_READ;
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier],
+            )
+        };
+
+        let next_mip_width = (mip_width / 2).max(1);
+        let next_mip_height = (mip_height / 2).max(1);
+
+        let blit = vk::ImageBlit::builder()
+            .src_offsets([
+                vk::Offset3D { x: 0, y: 0, z: 0 },
+                vk::Offset3D { x: mip_width, y: mip_height, z: 1 },
+            ])
+            .src_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: level - 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .dst_offsets([
+                vk::Offset3D { x: 0, y: 0, z: 0 },
+                vk::Offset3D { x: next_mip_width, y: next_mip_height, z: 1 },
+            ])
+            .dst_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: level,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .build();
+
+        unsafe {
+            device.cmd_blit_image(
+                command_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[blit],
+                vk::Filter::LINEAR,
+            )
+        };
+
+        barrier.old_layout = vk::ImageLayout::TRANSFER_SRC_OPTIMAL;
+        barrier.new_layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+        barrier.src_access_mask = vk::AccessFlags::TRANSFER_READ;
+        barrier.dst_access_mask = vk::AccessFlags::SHADER_READ;
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier],
+            )
+        };
+
+        mip_width = next_mip_width;
+        mip_height = next_mip_height;
+    }
+
+    barrier.subresource_range.base_mip_level = mip_levels - 1;
+    barrier.old_layout = vk::ImageLayout::TRANSFER_DST_OPTIMAL;
+    barrier.new_layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+    barrier.src_access_mask = vk::AccessFlags::TRANSFER_WRITE;
+    barrier.dst_access_mask = vk::AccessFlags::SHADER_READ;
+
     unsafe {
         device.cmd_pipeline_barrier(
             command_buffer,
-            src_stage,
-            dst_stage,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
             vk::DependencyFlags::empty(),
             &[],
             &[],
@@ -145,6 +450,15 @@ pub fn cmd_transition_image_layout(
     };
 }
 
-fn has_stencil_component(format: vk::Format) -> bool {
+pub fn has_stencil_component(format: vk::Format) -> bool {
     format == vk::Format::D32_SFLOAT_S8_UINT || format == vk::Format::D24_UNORM_S8_UINT
 }
+
+/// layout transitions of depth images must include the stencil aspect if there is one
+pub fn get_depth_aspect_mask(format: vk::Format) -> vk::ImageAspectFlags {
+    let mut mask = vk::ImageAspectFlags::DEPTH;
+    if has_stencil_component(format) {
+        mask |= vk::ImageAspectFlags::STENCIL;
+    }
+    mask
+}
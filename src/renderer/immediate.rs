@@ -0,0 +1,253 @@
+// Immediate mode geometry, triangles and quads given again every frame, for ui, gizmos and prototyping
+// without creating geometry. Their vertices go into one host visible buffer per frame in flight and
+// are drawn in submission order after the scene's geometry, depth tested against it and alpha blended.
+// Untextured geometry samples the fallback texture, a white pixel:
+//
+//     let white = app.get_fallback_texture().index() as u32;
+//     app.immediate_renderer.draw_quads(white, &[[a, b, c, d]]);
+//     app.immediate_renderer.draw_triangles(terrain_texture, &fan);
+
+use std::{mem::size_of, rc::Rc};
+
+use ash::vk;
+
+use super::{buffer::Buffer, pipeline, MAX_FRAMES_IN_FLIGHT};
+
+/// per frame in flight
+pub const MAX_IMMEDIATE_VERTEX_COUNT: usize = 0x10000;
+
+/// must match the vertex attributes of immediate.vert
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct ImmediateVertex {
+    /// world space
+    pub position: [f32; 3],
+    pub uv: [f32; 2],
+    /// straight alpha, multiplies the texture
+    pub color: [f32; 4],
+}
+
+const IMMEDIATE_ATTRIBUTES: [pipeline::Attribute; 3] = [
+    pipeline::Attribute::F32x3,
+    pipeline::Attribute::F32x2,
+    pipeline::Attribute::F32x4,
+];
+
+/// consecutive vertices sampling the same texture
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct ImmediateRun {
+    texture: u32,
+    first_vertex: u32,
+    vertex_count: u32,
+}
+
+/// must match the push constant block in immediate.frag
+const PUSH_CONSTANT_RANGE: vk::PushConstantRange = vk::PushConstantRange {
+    stage_flags: vk::ShaderStageFlags::FRAGMENT,
+    offset: 0,
+    size: size_of::<u32>() as u32,
+};
+
+/// two triangles sharing the first and third corners of `quad`
+fn quad_triangles(quad: &[ImmediateVertex; 4]) -> [ImmediateVertex; 6] {
+    [0, 1, 2, 0, 2, 3].map(|corner| quad[corner])
+}
+
+/// appends `vertices` sampling `texture`, continuing the last run when it samples the same one
+fn push_vertices(
+    texture: u32,
+    vertices: impl Iterator<Item = ImmediateVertex>,
+    submitted: &mut Vec<ImmediateVertex>,
+    runs: &mut Vec<ImmediateRun>,
+) {
+    let first_vertex = submitted.len() as u32;
+    submitted.extend(vertices);
+    let vertex_count = submitted.len() as u32 - first_vertex;
+    if vertex_count == 0 {
+        return;
+    }
+    match runs.last_mut() {
+        Some(run) if run.texture == texture => run.vertex_count += vertex_count,
+        _ => runs.push(ImmediateRun { texture, first_vertex, vertex_count }),
+    }
+}
+
+/// Draw during the frame, `build` once the frame's fence is waited on and `cmd_draw`
+/// in the scene pass. The pipeline depends on the scene render pass, `renew_pipeline` when it changes
+pub struct ImmediateRenderer {
+    device: Rc<ash::Device>,
+    submitted: Vec<ImmediateVertex>,
+    submitted_runs: Vec<ImmediateRun>,
+    /// of the last built frame
+    runs: Vec<ImmediateRun>,
+    /// host visible, one region per frame in flight
+    vertex_buffer: Buffer,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl ImmediateRenderer {
+    pub fn new(
+        device: Rc<ash::Device>,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    ) -> Self {
+        Self {
+            vertex_buffer: Buffer::new(
+                (MAX_FRAMES_IN_FLIGHT * Self::frame_size()) as vk::DeviceSize,
+                vk::BufferUsageFlags::VERTEX_BUFFER,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                device.clone(),
+                physical_device_memory_properties,
+            ),
+            device,
+            submitted: vec![],
+            submitted_runs: vec![],
+            runs: vec![],
+            pipeline_layout: vk::PipelineLayout::null(),
+            pipeline: vk::Pipeline::null(),
+        }
+    }
+
+    /// `render_pass` null for dynamic rendering, draws in the translucent subpass with depth attached
+    pub fn renew_pipeline(
+        &mut self,
+        context: &pipeline::PassContext,
+        render_pass: vk::RenderPass,
+    ) {
+        let pipeline::PassContext { shader_compiler, color_format, depth_format, per_frame_ubo_set_layout, textures_set_layout, output_transfer, reverse_z, .. } = *context;
+        let subpass = context.translucent_subpass();
+        unsafe { self.destroy_pipeline(); }
+
+        (self.pipeline, self.pipeline_layout) = pipeline::new_pipeline_and_layout(
+            &self.device,
+            shader_compiler,
+            &pipeline::PipelineDesc {
+                render_pass,
+                subpass,
+                color_formats: &[color_format],
+                depth_format,
+                set_layouts: &[per_frame_ubo_set_layout, textures_set_layout],
+                push_constant_ranges: &[PUSH_CONSTANT_RANGE],
+                vertex_shader_path: "shaders/immediate.vert",
+                fragment_shader_path: "shaders/immediate.frag",
+                vertex_attributes: &IMMEDIATE_ATTRIBUTES,
+                blend_mode: pipeline::BlendMode::Alpha,
+                cull_mode: vk::CullModeFlags::NONE,
+                depth_write: false,
+                output_transfer,
+                reverse_z,
+                ..Default::default()
+            },
+        );
+    }
+
+    /// a triangle per three of `vertices`, drawn next frame only. `texture` is a `TextureHandle`'s index
+    pub fn draw_triangles(&mut self, texture: u32, vertices: &[ImmediateVertex]) {
+        assert!(vertices.len().is_multiple_of(3), "{} vertices don't make whole triangles", vertices.len());
+        push_vertices(texture, vertices.iter().copied(), &mut self.submitted, &mut self.submitted_runs);
+    }
+
+    /// two triangles per quad, its corners in order around it, drawn next frame only
+    pub fn draw_quads(&mut self, texture: u32, quads: &[[ImmediateVertex; 4]]) {
+        push_vertices(texture, quads.iter().flat_map(quad_triangles), &mut self.submitted, &mut self.submitted_runs);
+    }
+
+    const fn frame_size() -> usize {
+        MAX_IMMEDIATE_VERTEX_COUNT * size_of::<ImmediateVertex>()
+    }
+
+    /// writes the drawn vertices into `frame`'s region and clears them,
+    /// the frame's previous commands must have finished executing
+    pub fn build(&mut self, frame: usize) {
+        if self.submitted.len() > MAX_IMMEDIATE_VERTEX_COUNT {
+            log::warn!("Dropping {} immediate vertices over the limit", self.submitted.len() - MAX_IMMEDIATE_VERTEX_COUNT);
+            // whole triangles, the runs past the limit go and the one across it is cut short
+            let limit = (MAX_IMMEDIATE_VERTEX_COUNT - MAX_IMMEDIATE_VERTEX_COUNT % 3) as u32;
+            self.submitted.truncate(limit as usize);
+            self.submitted_runs.retain(|run| run.first_vertex < limit);
+            if let Some(run) = self.submitted_runs.last_mut() {
+                run.vertex_count = run.vertex_count.min(limit - run.first_vertex);
+            }
+        }
+        self.vertex_buffer.copy_from_slice(&self.submitted, frame * MAX_IMMEDIATE_VERTEX_COUNT);
+        self.submitted.clear();
+        std::mem::swap(&mut self.runs, &mut self.submitted_runs);
+        self.submitted_runs.clear();
+    }
+
+    /// record in the scene pass after the opaque geometry, binds its own pipeline
+    pub fn cmd_draw(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+        per_frame_ubo_set: vk::DescriptorSet,
+        per_frame_ubo_offset: u32,
+        textures_set: vk::DescriptorSet,
+    ) {
+        if self.runs.is_empty() {
+            return;
+        }
+
+        unsafe {
+            self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[per_frame_ubo_set, textures_set],
+                &[per_frame_ubo_offset],
+            );
+            self.device.cmd_bind_vertex_buffers(
+                command_buffer,
+                pipeline::VERTEX_BINDING,
+                &[self.vertex_buffer.handle],
+                &[(frame * Self::frame_size()) as vk::DeviceSize],
+            );
+            for run in &self.runs {
+                self.device.cmd_push_constants(
+                    command_buffer,
+                    self.pipeline_layout,
+                    PUSH_CONSTANT_RANGE.stage_flags,
+                    0,
+                    &run.texture.to_ne_bytes(),
+                );
+                self.device.cmd_draw(command_buffer, run.vertex_count, 1, run.first_vertex, 0);
+            }
+        }
+    }
+
+    unsafe fn destroy_pipeline(&mut self) {
+        if self.pipeline != vk::Pipeline::null() {
+            self.device.destroy_pipeline(self.pipeline, None);
+            self.device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+
+    // caller must ensure only called once
+    pub unsafe fn destroy(&mut self) {
+        self.destroy_pipeline();
+        self.vertex_buffer.destroy();
+    }
+}
+
+#[test]
+fn test_immediate_runs() {
+    let vertex = |x: f32| ImmediateVertex { position: [x, 0.0, 0.0], uv: [0.0; 2], color: [1.0; 4] };
+    let (mut submitted, mut runs) = (vec![], vec![]);
+
+    // same texture in a row is one run, another texture starts the next, in submission order
+    push_vertices(2, [vertex(0.0), vertex(1.0), vertex(2.0)].into_iter(), &mut submitted, &mut runs);
+    push_vertices(2, [vertex(3.0), vertex(4.0), vertex(5.0)].into_iter(), &mut submitted, &mut runs);
+    push_vertices(7, std::iter::empty(), &mut submitted, &mut runs);
+    push_vertices(1, [vertex(6.0), vertex(7.0), vertex(8.0)].into_iter(), &mut submitted, &mut runs);
+    push_vertices(2, [vertex(9.0), vertex(10.0), vertex(11.0)].into_iter(), &mut submitted, &mut runs);
+    assert!(runs == [
+        ImmediateRun { texture: 2, first_vertex: 0, vertex_count: 6 },
+        ImmediateRun { texture: 1, first_vertex: 6, vertex_count: 3 },
+        ImmediateRun { texture: 2, first_vertex: 9, vertex_count: 3 },
+    ]);
+
+    let triangles = quad_triangles(&[vertex(0.0), vertex(1.0), vertex(2.0), vertex(3.0)]);
+    assert!(triangles.map(|vertex| vertex.position[0]) == [0.0, 1.0, 2.0, 0.0, 2.0, 3.0]);
+}
@@ -0,0 +1,98 @@
+// Fixed aspect ratio output. With an aspect ratio set the scene is drawn into the largest
+// centered rectangle of that ratio, the rest of the frame becomes black bars:
+// above and below for letterboxing, left and right for pillarboxing
+
+use ash::vk;
+
+/// the centered viewport of `aspect_ratio`, width over height, in `extent`,
+/// all of `extent` for `None`
+pub fn letterbox(extent: vk::Extent2D, aspect_ratio: Option<f32>) -> vk::Rect2D {
+    let full = vk::Rect2D {
+        offset: vk::Offset2D { x: 0, y: 0 },
+        extent,
+    };
+    let Some(aspect_ratio) = aspect_ratio else {
+        return full;
+    };
+
+    let (width, height) = (extent.width as f32, extent.height as f32);
+    let viewport_extent = if width > height * aspect_ratio {
+        vk::Extent2D {
+            width: ((height * aspect_ratio).round() as u32).clamp(1, extent.width.max(1)),
+            height: extent.height,
+        }
+    } else {
+        vk::Extent2D {
+            width: extent.width,
+            height: ((width / aspect_ratio).round() as u32).clamp(1, extent.height.max(1)),
+        }
+    };
+    vk::Rect2D {
+        offset: vk::Offset2D {
+            x: ((extent.width - viewport_extent.width) / 2) as i32,
+            y: ((extent.height - viewport_extent.height) / 2) as i32,
+        },
+        extent: viewport_extent,
+    }
+}
+
+/// the parts of `extent` outside `viewport`, empty ones included
+pub fn bars(extent: vk::Extent2D, viewport: vk::Rect2D) -> [vk::Rect2D; 2] {
+    let vk::Offset2D { x, y } = viewport.offset;
+    if x > 0 {
+        let width = extent.width - viewport.extent.width - x as u32;
+        [
+            vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: vk::Extent2D { width: x as u32, height: extent.height },
+            },
+            vk::Rect2D {
+                offset: vk::Offset2D { x: x + viewport.extent.width as i32, y: 0 },
+                extent: vk::Extent2D { width, height: extent.height },
+            },
+        ]
+    } else {
+        let height = extent.height - viewport.extent.height - y as u32;
+        [
+            vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: vk::Extent2D { width: extent.width, height: y as u32 },
+            },
+            vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: y + viewport.extent.height as i32 },
+                extent: vk::Extent2D { width: extent.width, height },
+            },
+        ]
+    }
+}
+
+/// `cursor` in window pixels relative to `viewport`'s top left, `None` on the bars
+pub fn to_viewport(cursor: [f32; 2], viewport: vk::Rect2D) -> Option<[f32; 2]> {
+    let x = cursor[0] - viewport.offset.x as f32;
+    let y = cursor[1] - viewport.offset.y as f32;
+    let inside = x >= 0.0 && y >= 0.0 && x < viewport.extent.width as f32 && y < viewport.extent.height as f32;
+    inside.then_some([x, y])
+}
+
+#[test]
+fn test_letterbox() {
+    let rect = |x, y, width, height| vk::Rect2D {
+        offset: vk::Offset2D { x, y },
+        extent: vk::Extent2D { width, height },
+    };
+    let extent = |width, height| vk::Extent2D { width, height };
+
+    assert!(letterbox(extent(800, 600), None) == rect(0, 0, 800, 600));
+    // 4:3 window, 16:9 output
+    let wide = letterbox(extent(800, 600), Some(16.0 / 9.0));
+    assert!(wide == rect(0, 75, 800, 450));
+    assert!(bars(extent(800, 600), wide) == [rect(0, 0, 800, 75), rect(0, 525, 800, 75)]);
+    // ultrawide window, odd leftovers go to the right
+    let narrow = letterbox(extent(2561, 1080), Some(16.0 / 9.0));
+    assert!(narrow == rect(320, 0, 1920, 1080));
+    assert!(bars(extent(2561, 1080), narrow) == [rect(0, 0, 320, 1080), rect(2240, 0, 321, 1080)]);
+    assert!(letterbox(extent(1920, 1080), Some(16.0 / 9.0)) == rect(0, 0, 1920, 1080));
+
+    assert!(to_viewport([400.0, 80.0], wide) == Some([400.0, 5.0]));
+    assert!(to_viewport([400.0, 70.0], wide).is_none() && to_viewport([400.0, 525.0], wide).is_none());
+}
@@ -0,0 +1,268 @@
+use std::{mem::size_of, rc::Rc};
+
+use ash::vk;
+use serde::{Deserialize, Serialize};
+
+use crate::data_structures::handle_map::{Handle, HandleMap};
+use super::{buffer::Buffer, MAX_FRAMES_IN_FLIGHT};
+
+/// its index is the material's slot in the materials storage buffer
+pub type MaterialId = Handle<Material>;
+
+/// material slots, the materials storage buffer holds this many per frame in flight
+pub const MAX_MATERIAL_COUNT: usize = 1024;
+
+pub type MaterialFlags = u32;
+/// perturb the interpolated normal with the material's normal texture
+pub const MATERIAL_FLAG_NORMAL_MAP: MaterialFlags = 1 << 0;
+/// scale metallic and roughness by the material's metallic roughness texture, pbr only
+pub const MATERIAL_FLAG_METALLIC_ROUGHNESS_MAP: MaterialFlags = 1 << 1;
+/// darken ambient light by the material's occlusion texture, pbr only
+pub const MATERIAL_FLAG_OCCLUSION_MAP: MaterialFlags = 1 << 2;
+
+/// which scene pipeline draws the material, scenes may mix both
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum ShadingModel {
+    /// lambert with wet specular, shaders/foo.frag
+    #[default]
+    Simple,
+    /// metallic roughness cook torrance lit by the environment, shaders/pbr.frag.
+    /// Drawn simply on the deferred path
+    Pbr,
+}
+
+/// texture fields index into the textures descriptor array, the fields after `flags` are pbr only
+#[derive(Clone, Copy)]
+pub struct Material {
+    pub shading: ShadingModel,
+    pub diffuse_texture: u32,
+    pub normal_texture: u32,
+    pub flags: MaterialFlags,
+    /// linear, multiplies the diffuse texture, alpha is unused
+    pub albedo: [f32; 4],
+    /// roughness in g and metallic in b, as in gltf
+    pub metallic_roughness_texture: u32,
+    /// ambient occlusion in r
+    pub occlusion_texture: u32,
+    /// 0 dielectric to 1 metal
+    pub metallic: f32,
+    /// 0 mirror to 1 rough
+    pub roughness: f32,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            shading: ShadingModel::Simple,
+            diffuse_texture: 0,
+            normal_texture: 0,
+            flags: 0,
+            albedo: [1.0; 4],
+            metallic_roughness_texture: 0,
+            occlusion_texture: 0,
+            metallic: 0.0,
+            roughness: 1.0,
+        }
+    }
+}
+
+/// std430 layout, must match the Material struct in the fragment shaders
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct GpuMaterial {
+    albedo: [f32; 4],
+    diffuse_texture: u32,
+    normal_texture: u32,
+    metallic_roughness_texture: u32,
+    occlusion_texture: u32,
+    flags: MaterialFlags,
+    metallic: f32,
+    roughness: f32,
+    /// to the struct's 16 byte alignment
+    _padding: u32,
+}
+
+impl From<&Material> for GpuMaterial {
+    fn from(material: &Material) -> Self {
+        Self {
+            albedo: material.albedo,
+            diffuse_texture: material.diffuse_texture,
+            normal_texture: material.normal_texture,
+            metallic_roughness_texture: material.metallic_roughness_texture,
+            occlusion_texture: material.occlusion_texture,
+            flags: material.flags,
+            metallic: material.metallic,
+            roughness: material.roughness,
+            _padding: 0,
+        }
+    }
+}
+
+/// draws only push which material to fetch from the materials storage buffer,
+/// must match the push constant block in the fragment shaders
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MaterialPushConstants {
+    material_index: u32,
+}
+
+impl MaterialPushConstants {
+    pub const RANGE: vk::PushConstantRange = vk::PushConstantRange {
+        stage_flags: vk::ShaderStageFlags::FRAGMENT,
+        offset: 0,
+        size: size_of::<Self>() as u32,
+    };
+
+    pub fn cmd_push(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, pipeline_layout: vk::PipelineLayout) {
+        unsafe {
+            let bytes = std::slice::from_raw_parts(
+                self as *const MaterialPushConstants as *const u8,
+                size_of::<MaterialPushConstants>(),
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                pipeline_layout,
+                Self::RANGE.stage_flags,
+                Self::RANGE.offset,
+                bytes,
+            );
+        }
+    }
+}
+
+/// of `slot` in `frame`'s region of the materials storage buffer
+fn material_index(frame: usize, slot: usize) -> u32 {
+    (frame * MAX_MATERIAL_COUNT + slot) as u32
+}
+
+/// All materials live in one storage buffer indexed per draw,
+/// so changing a material never needs a descriptor rebind.
+/// Each frame in flight reads its own region, changes reach a region when its frame is built
+pub struct MaterialSystem {
+    materials: HandleMap<Material>,
+    /// what the regions are brought up to, by slot
+    gpu_materials: Vec<GpuMaterial>,
+    /// slots changed since each frame's region was last written
+    stale_slots: [Vec<usize>; MAX_FRAMES_IN_FLIGHT],
+    /// the last built frame, whose region draws push indices into
+    frame: usize,
+    /// host visible, one region per frame in flight
+    buffer: Buffer,
+}
+
+impl MaterialSystem {
+    pub fn new(
+        device: Rc<ash::Device>,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    ) -> Self {
+        Self {
+            materials: HandleMap::default(),
+            gpu_materials: vec![GpuMaterial::from(&Material::default()); MAX_MATERIAL_COUNT],
+            stale_slots: Default::default(),
+            frame: 0,
+            buffer: Buffer::new(
+                (MAX_FRAMES_IN_FLIGHT * MAX_MATERIAL_COUNT * size_of::<GpuMaterial>()) as vk::DeviceSize,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                device,
+                physical_device_memory_properties,
+            ),
+        }
+    }
+
+    /// all regions, draws index into the one of their frame
+    pub fn get_buffer_info(&self) -> vk::DescriptorBufferInfo {
+        vk::DescriptorBufferInfo {
+            buffer: self.buffer.handle,
+            offset: 0,
+            range: self.buffer.size,
+        }
+    }
+
+    fn write_material(&mut self, id: MaterialId) {
+        let slot = id.index() as usize;
+        self.gpu_materials[slot] = GpuMaterial::from(self.materials.get(id).expect("Writing a stale material id"));
+        for stale_slots in &mut self.stale_slots {
+            stale_slots.push(slot);
+        }
+    }
+
+    /// brings `frame`'s region up to date and has draws read it,
+    /// the frame's previous commands must have finished executing
+    pub fn build(&mut self, frame: usize) {
+        self.frame = frame;
+        let mut stale_slots = std::mem::take(&mut self.stale_slots[frame]);
+        stale_slots.sort_unstable();
+        stale_slots.dedup();
+        for &slot in &stale_slots {
+            self.buffer.copy_from_slice(&self.gpu_materials[slot..slot + 1], frame * MAX_MATERIAL_COUNT + slot);
+        }
+        stale_slots.clear();
+        self.stale_slots[frame] = stale_slots;
+    }
+
+    pub fn create_material(&mut self, material: Material) -> MaterialId {
+        assert!(self.materials.len() < MAX_MATERIAL_COUNT, "Out of material slots");
+        // freed slots are reused first, so indices stay below the live count's peak
+        let id = self.materials.insert(material);
+        self.write_material(id);
+        id
+    }
+
+    /// frees the material's slot, `None` when the id is stale.
+    /// Frames in flight keep drawing with it, a reused slot only reaches their regions once they're built again
+    pub fn destroy_material(&mut self, id: MaterialId) -> Option<Material> {
+        self.materials.remove(id)
+    }
+
+    pub fn set_material(&mut self, id: MaterialId, material: Material) {
+        *self.materials.get_mut(id).expect("Setting a stale material id") = material;
+        self.write_material(id);
+    }
+
+    /// `None` when the id is stale
+    pub fn get_material(&self, id: MaterialId) -> Option<&Material> {
+        self.materials.get(id)
+    }
+
+    pub fn set_normal_mapping(&mut self, id: MaterialId, enabled: bool) {
+        let material = self.materials.get_mut(id).expect("Setting a stale material id");
+        if enabled {
+            material.flags |= MATERIAL_FLAG_NORMAL_MAP;
+        } else {
+            material.flags &= !MATERIAL_FLAG_NORMAL_MAP;
+        }
+        self.write_material(id);
+    }
+
+    /// what draws with the material push, indexes into the last built frame's region
+    pub fn push_constants(&self, id: MaterialId) -> MaterialPushConstants {
+        assert!(self.materials.contains(id), "Drawing with a stale material id");
+        MaterialPushConstants {
+            material_index: material_index(self.frame, id.index() as usize),
+        }
+    }
+
+    pub fn cmd_push_material(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        pipeline_layout: vk::PipelineLayout,
+        id: MaterialId,
+    ) {
+        self.push_constants(id).cmd_push(device, command_buffer, pipeline_layout);
+    }
+
+    // caller must ensure only called once
+    pub unsafe fn destroy(&mut self) {
+        self.buffer.destroy();
+    }
+}
+
+#[test]
+fn test_material_index() {
+    // each frame in flight reads its own region
+    assert!(material_index(0, 5) == 5);
+    assert!(material_index(1, 5) as usize == MAX_MATERIAL_COUNT + 5);
+    assert!(material_index(MAX_FRAMES_IN_FLIGHT - 1, MAX_MATERIAL_COUNT - 1) as usize == MAX_FRAMES_IN_FLIGHT * MAX_MATERIAL_COUNT - 1);
+}
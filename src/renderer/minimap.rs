@@ -0,0 +1,498 @@
+// Minimap, a top-down orthographic view centered on the camera rendered to an offscreen
+// target every few frames, then drawn as a sprite in a corner of the scene with a marker
+// for where the player is and faces
+
+use std::{f32::consts::FRAC_PI_2, mem::size_of, rc::Rc};
+
+use ash::vk;
+
+use crate::{camera::Camera, geometry, math::{Mat, ModelMat, Vector}};
+use super::{
+    batch::SceneDraws,
+    descriptor::{DescriptorWriteBatcher, PerFrameUBO},
+    material,
+    pipeline,
+    render_pass,
+};
+
+/// index of the top-down camera's uniform buffer object among the views
+pub const MINIMAP_VIEW: usize = 1;
+
+const EXTENT: vk::Extent2D = vk::Extent2D { width: 256, height: 256 };
+
+/// must match the push constant blocks in minimap.vert and minimap.frag
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct SpritePushConstants {
+    /// min and max corner in normalized device coordinates
+    rect: [f32; 4],
+    /// player position in texture coordinates, then the heading
+    marker: [f32; 4],
+}
+
+const SPRITE_PUSH_CONSTANT_RANGE: vk::PushConstantRange = vk::PushConstantRange {
+    stage_flags: vk::ShaderStageFlags::from_raw(
+        vk::ShaderStageFlags::VERTEX.as_raw() | vk::ShaderStageFlags::FRAGMENT.as_raw()
+    ),
+    offset: 0,
+    size: size_of::<SpritePushConstants>() as u32,
+};
+
+/// looks down +y, world y points down, with +z at the top of the map
+pub fn top_down_proj_view(center: Vector, half_extent: f32, height: f32) -> Mat {
+    ModelMat::identity()
+        .translate(-center.x, -(center.y - height), -center.z)
+        .rotate(FRAC_PI_2, 0.0, 1.0, 0.0)
+        // sees as far below the center as above it
+        .project_orthographic(half_extent, half_extent, 0.0, 2.0 * height)
+}
+
+/// `center` in texture coordinates of the map rendered around `rendered_center`,
+/// followed by the direction `z_x_angle` faces in them
+fn marker(center: Vector, z_x_angle: f32, rendered_center: Vector, half_extent: f32) -> [f32; 4] {
+    let scale = 0.5 / half_extent;
+    [
+        0.5 + (center.x - rendered_center.x) * scale,
+        0.5 - (center.z - rendered_center.z) * scale,
+        z_x_angle.sin(),
+        -z_x_angle.cos(),
+    ]
+}
+
+/// Call `build` once per frame before the uniform buffer is written, `cmd_render` before
+/// the scene pass and `cmd_draw` inside it. The sprite pipeline depends on the scene render pass,
+/// `renew_pipeline` when it changes
+pub struct Minimap {
+    device: Rc<ash::Device>,
+    pub enabled: bool,
+    /// frames between rerenders of the map
+    pub interval: u32,
+    /// world space distance from the center to the map's edges
+    pub half_extent: f32,
+    /// of the top-down camera above the player
+    pub height: f32,
+    /// fraction of the screen height
+    pub screen_size: f32,
+
+    color_image: vk::Image,
+    color_image_memory: vk::DeviceMemory,
+    color_image_view: vk::ImageView,
+    depth_image: vk::Image,
+    depth_image_memory: vk::DeviceMemory,
+    depth_image_view: vk::ImageView,
+    render_pass: vk::RenderPass,
+    framebuffer: vk::Framebuffer,
+    geometry_pipeline_layout: vk::PipelineLayout,
+    geometry_pipeline: vk::Pipeline,
+
+    sampler: vk::Sampler,
+    descriptor_pool: vk::DescriptorPool,
+    set_layout: vk::DescriptorSetLayout,
+    set: vk::DescriptorSet,
+    sprite_pipeline_layout: vk::PipelineLayout,
+    sprite_pipeline: vk::Pipeline,
+
+    frames_since_render: u32,
+    /// the target holds a rendered map
+    rendered: bool,
+    render_this_frame: bool,
+    rendered_center: Vector,
+    proj_view: Mat,
+    marker: [f32; 4],
+}
+
+impl Minimap {
+    pub fn new(
+        device: Rc<ash::Device>,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        write_batcher: &mut DescriptorWriteBatcher,
+        context: &pipeline::PassContext,
+    ) -> Self {
+        let pipeline::PassContext {
+            shader_compiler, color_format, depth_format, per_frame_ubo_set_layout, textures_set_layout, ..
+        } = *context;
+        let (color_image, color_image_memory) = super::image::new_image_and_memory(
+            &device,
+            physical_device_memory_properties,
+            &super::image::ImageDesc {
+                width: EXTENT.width,
+                height: EXTENT.height,
+                usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                format: color_format,
+                ..Default::default()
+            },
+        );
+        let color_image_view = super::image::new_image_view(
+            &device,
+            color_image,
+            color_format,
+            vk::ImageAspectFlags::COLOR,
+            1,
+        );
+        let (depth_image, depth_image_memory) = super::image::new_image_and_memory(
+            &device,
+            physical_device_memory_properties,
+            &super::image::ImageDesc {
+                width: EXTENT.width,
+                height: EXTENT.height,
+                usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+                format: depth_format,
+                ..Default::default()
+            },
+        );
+        let depth_image_view = super::image::new_image_view(
+            &device,
+            depth_image,
+            depth_format,
+            super::image::get_depth_aspect_mask(depth_format),
+            1,
+        );
+
+        // left ready to be sampled by the sprite
+        let render_pass = render_pass::new_render_pass(
+            &device,
+            color_format,
+            depth_format,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            &render_pass::ClearConfig::default(),
+        );
+        let framebuffer = unsafe {
+            let attachments = [color_image_view, depth_image_view];
+            let info = vk::FramebufferCreateInfo::builder()
+                .render_pass(render_pass)
+                .attachments(&attachments)
+                .width(EXTENT.width)
+                .height(EXTENT.height)
+                .layers(1);
+            device.create_framebuffer(&info, None).unwrap()
+        };
+
+        // forward shaded whatever the scene's render path
+        let (geometry_pipeline, geometry_pipeline_layout) = pipeline::new_pipeline_and_layout(
+            &device,
+            shader_compiler,
+            &pipeline::PipelineDesc {
+                render_pass,
+                color_formats: &[color_format],
+                depth_format,
+                set_layouts: &[per_frame_ubo_set_layout, textures_set_layout],
+                push_constant_ranges: &[material::MaterialPushConstants::RANGE],
+                vertex_shader_path: "shaders/foo.vert",
+                fragment_shader_path: "shaders/foo.frag",
+                vertex_attributes: &geometry::VERTEX_ATTRIBUTES,
+                instance_attributes: &geometry::INSTANCE_ATTRIBUTES,
+                ..Default::default()
+            },
+        );
+
+        let sampler = unsafe {
+            let info = vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::LINEAR)
+                .min_filter(vk::Filter::LINEAR)
+                .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .max_lod(0.0);
+            device.create_sampler(&info, None).unwrap()
+        };
+
+        let set_layout = unsafe {
+            let bindings = [vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build()];
+            let info = vk::DescriptorSetLayoutCreateInfo::builder()
+                .bindings(&bindings);
+            device.create_descriptor_set_layout(&info, None).unwrap()
+        };
+
+        let descriptor_pool = unsafe {
+            let pool_sizes = [vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 1,
+            }];
+            let info = vk::DescriptorPoolCreateInfo::builder()
+                .max_sets(1)
+                .pool_sizes(&pool_sizes);
+            device.create_descriptor_pool(&info, None).expect("Failed to create descriptor pool")
+        };
+
+        let set = unsafe {
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(descriptor_pool)
+                .set_layouts(&[set_layout])
+                .build();
+            device.allocate_descriptor_sets(&alloc_info).unwrap()[0]
+        };
+        write_batcher.queue_image_write(
+            set,
+            0,
+            0,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            vk::DescriptorImageInfo {
+                sampler,
+                image_view: color_image_view,
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            },
+        );
+
+        Self {
+            device,
+            enabled: true,
+            interval: 10,
+            half_extent: 30.0,
+            height: 50.0,
+            screen_size: 0.3,
+
+            color_image,
+            color_image_memory,
+            color_image_view,
+            depth_image,
+            depth_image_memory,
+            depth_image_view,
+            render_pass,
+            framebuffer,
+            geometry_pipeline_layout,
+            geometry_pipeline,
+
+            sampler,
+            descriptor_pool,
+            set_layout,
+            set,
+            sprite_pipeline_layout: vk::PipelineLayout::null(),
+            sprite_pipeline: vk::Pipeline::null(),
+
+            frames_since_render: 0,
+            rendered: false,
+            render_this_frame: false,
+            rendered_center: Vector::new(0.0, 0.0, 0.0),
+            proj_view: Mat::default(),
+            marker: [0.5, 0.5, 0.0, -1.0],
+        }
+    }
+
+    /// `render_pass` null for dynamic rendering, draws in the translucent subpass
+    pub fn renew_pipeline(
+        &mut self,
+        context: &pipeline::PassContext,
+        render_pass: vk::RenderPass,
+    ) {
+        let pipeline::PassContext { shader_compiler, color_format, depth_format, output_transfer, .. } = *context;
+        let subpass = context.translucent_subpass();
+        unsafe { self.destroy_sprite_pipeline(); }
+
+        (self.sprite_pipeline, self.sprite_pipeline_layout) = pipeline::new_pipeline_and_layout(
+            &self.device,
+            shader_compiler,
+            &pipeline::PipelineDesc {
+                render_pass,
+                subpass,
+                color_formats: &[color_format],
+                depth_format,
+                set_layouts: &[self.set_layout],
+                push_constant_ranges: &[SPRITE_PUSH_CONSTANT_RANGE],
+                vertex_shader_path: "shaders/minimap.vert",
+                fragment_shader_path: "shaders/minimap.frag",
+                blend_mode: pipeline::BlendMode::Alpha,
+                cull_mode: vk::CullModeFlags::NONE,
+                depth_test: false,
+                depth_write: false,
+                output_transfer,
+                ..Default::default()
+            },
+        );
+    }
+
+    /// decides wether the map is rerendered this frame and moves the marker
+    pub fn build(&mut self, camera: &Camera) {
+        self.render_this_frame = self.enabled && (!self.rendered || self.frames_since_render + 1 >= self.interval);
+        if self.render_this_frame {
+            self.frames_since_render = 0;
+            self.rendered_center = camera.translation;
+            self.proj_view = top_down_proj_view(camera.translation, self.half_extent, self.height);
+        } else {
+            self.frames_since_render += 1;
+        }
+        self.marker = marker(camera.translation, camera.z_x_angle, self.rendered_center, self.half_extent);
+    }
+
+    /// the main view's uniform buffer object seen from the top-down camera
+    pub fn view_ubo(&self, main_view_ubo: &PerFrameUBO) -> PerFrameUBO {
+        let center = self.rendered_center;
+        PerFrameUBO {
+            proj_view: self.proj_view,
+            camera_position: [center.x, center.y - self.height, center.z, 1.0],
+            // fogging from above would hide the ground
+            fog_mode: crate::fog::FogMode::None as u32,
+            inverse_proj_view: self.proj_view.inverse().unwrap_or_default(),
+            previous_proj_view: self.proj_view,
+            ..*main_view_ubo
+        }
+    }
+
+    /// renders the batched draws from the top-down camera when due, record before the scene pass.
+    /// Skinned meshes are left out
+    pub fn cmd_render(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+        per_frame_ubo_offset: u32,
+        scene: &SceneDraws,
+    ) {
+        let SceneDraws { draw_batcher, geometry_system, material_system, per_frame_ubo_set, textures_set } = *scene;
+        if !self.render_this_frame {
+            return;
+        }
+        self.rendered = true;
+
+        let render_area = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: EXTENT,
+        };
+        let clear_values = render_pass::ClearConfig::default().clear_values();
+        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.render_pass)
+            .framebuffer(self.framebuffer)
+            .render_area(render_area)
+            .clear_values(&clear_values);
+
+        unsafe {
+            // the previous frame's sprite may still be sampling the target
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[],
+            );
+
+            self.device.cmd_begin_render_pass(command_buffer, &render_pass_begin_info, vk::SubpassContents::INLINE);
+            self.device.cmd_set_viewport(command_buffer, 0, &[vk::Viewport {
+                x: 0.0,
+                y: 0.0,
+                width: EXTENT.width as f32,
+                height: EXTENT.height as f32,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            }]);
+            self.device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.geometry_pipeline_layout,
+                0,
+                &[per_frame_ubo_set, textures_set],
+                &[per_frame_ubo_offset],
+            );
+
+            geometry_system.cmd_bind_resources(command_buffer);
+            draw_batcher.cmd_draw_batches(
+                command_buffer,
+                frame,
+                self.geometry_pipeline_layout,
+                Some(self.geometry_pipeline),
+                geometry_system,
+                material_system,
+            );
+
+            self.device.cmd_end_render_pass(command_buffer);
+
+            let memory_barrier = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .build();
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[memory_barrier],
+                &[],
+                &[],
+            );
+        }
+    }
+
+    /// record in the scene pass after everything else, binds its own pipeline.
+    /// `scene_extent` keeps the sprite square
+    pub fn cmd_draw(&self, command_buffer: vk::CommandBuffer, scene_extent: vk::Extent2D) {
+        if !self.enabled || !self.rendered {
+            return;
+        }
+
+        // top right corner
+        let margin = 0.05;
+        let height = 2.0 * self.screen_size;
+        let width = height * scene_extent.height as f32 / scene_extent.width as f32;
+        let push_constants = SpritePushConstants {
+            rect: [1.0 - margin - width, -1.0 + margin, 1.0 - margin, -1.0 + margin + height],
+            marker: self.marker,
+        };
+
+        unsafe {
+            self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.sprite_pipeline);
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.sprite_pipeline_layout,
+                0,
+                &[self.set],
+                &[],
+            );
+            self.device.cmd_push_constants(
+                command_buffer,
+                self.sprite_pipeline_layout,
+                SPRITE_PUSH_CONSTANT_RANGE.stage_flags,
+                0,
+                std::slice::from_raw_parts(
+                    &push_constants as *const SpritePushConstants as *const u8,
+                    size_of::<SpritePushConstants>(),
+                ),
+            );
+            self.device.cmd_draw(command_buffer, 6, 1, 0, 0);
+        }
+    }
+
+    unsafe fn destroy_sprite_pipeline(&mut self) {
+        if self.sprite_pipeline != vk::Pipeline::null() {
+            self.device.destroy_pipeline(self.sprite_pipeline, None);
+            self.device.destroy_pipeline_layout(self.sprite_pipeline_layout, None);
+        }
+    }
+
+    /// # Safety
+    /// call once, after the last frame drawing the minimap finished
+    pub unsafe fn destroy(&mut self) {
+        self.destroy_sprite_pipeline();
+        self.device.destroy_descriptor_pool(self.descriptor_pool, None);
+        self.device.destroy_descriptor_set_layout(self.set_layout, None);
+        self.device.destroy_sampler(self.sampler, None);
+
+        self.device.destroy_pipeline(self.geometry_pipeline, None);
+        self.device.destroy_pipeline_layout(self.geometry_pipeline_layout, None);
+        self.device.destroy_framebuffer(self.framebuffer, None);
+        self.device.destroy_render_pass(self.render_pass, None);
+
+        self.device.destroy_image_view(self.depth_image_view, None);
+        self.device.destroy_image(self.depth_image, None);
+        self.device.free_memory(self.depth_image_memory, None);
+        self.device.destroy_image_view(self.color_image_view, None);
+        self.device.destroy_image(self.color_image, None);
+        self.device.free_memory(self.color_image_memory, None);
+    }
+}
+
+#[test]
+fn test_minimap_marker() {
+    let center = Vector::new(2.0, -1.0, 3.0);
+    assert!(marker(center, 0.0, center, 10.0) == [0.5, 0.5, 0.0, -1.0]);
+
+    // 5 along +x and +z from where the map was rendered, +z is up on the map
+    let [u, v, ..] = marker(Vector::new(7.0, -1.0, 8.0), 0.0, center, 10.0);
+    assert!(u == 0.75 && v == 0.25);
+}
@@ -0,0 +1,1022 @@
+// Per object motion vectors and a motion blur pass over the scene. Before the scene pass the
+// frame's batched draws are rendered once more into a velocity attachment, positioned with this
+// frame's and the previous frame's model matrix and camera. After the scene pass a compute pass
+// resolves every pixel's velocity, taking the object's own where the velocity pass drew what the
+// scene shows and reprojecting the depth buffer with the previous camera elsewhere, keeps the
+// longest of each tile, and blurs the scene color along the longest velocity around each pixel:
+//
+//     app.motion_blur.enabled = true;
+//     app.motion_blur.strength = 0.5;
+//
+// Previous model matrices are matched to instances by pick id, draws without one as well as
+// skinned meshes and terrain only blur with the camera. Blurs the scene's colors as they were
+// encoded, writes the result back with a blit, so it needs transfer destination usage on the
+// swapchain or the render scaled target
+
+use std::{collections::HashMap, mem::size_of, rc::Rc};
+
+use ash::vk;
+
+use crate::{geometry, math::{Mat, ModelMat}};
+use super::{
+    batch::{DrawBatcher, SceneDraws, MAX_INSTANCE_COUNT},
+    buffer::Buffer,
+    descriptor::DescriptorWriteBatcher,
+    image,
+    material,
+    pipeline,
+    render_pass,
+    shader,
+    MAX_FRAMES_IN_FLIGHT,
+};
+
+/// of the velocity attachment and the resolved velocities, in uv units and pixels
+pub const VELOCITY_FORMAT: vk::Format = vk::Format::R16G16_SFLOAT;
+/// of the scene color copy and the blurred scene
+pub const COLOR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+/// pixels along a side of the tiles the longest velocity is kept for, see motion_blur_tiles.comp
+pub const TILE_SIZE: u32 = 16;
+/// the frame time `strength` is meant for
+pub const REFERENCE_FRAME_TIME: f32 = 1.0 / 60.0;
+/// shorter frames blur as if they took this long, so the first frames don't blow up velocities
+const MIN_FRAME_TIME: f32 = 1.0 / 1000.0;
+
+const WORKGROUP_SIZE: u32 = 8;
+
+/// must match the push constant blocks in motion_blur_tiles.comp and motion_blur.comp
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct BlurPushConstants {
+    viewport_offset: [i32; 2],
+    viewport_size: [i32; 2],
+    velocity_scale: f32,
+    clear_depth: f32,
+}
+
+/// how much of a frame's motion to blur over for a frame of `frame_time` seconds, so the blur's
+/// length depends on how fast things move rather than on the frame rate
+pub fn velocity_scale(strength: f32, frame_time: f32) -> f32 {
+    strength * REFERENCE_FRAME_TIME / frame_time.max(MIN_FRAME_TIME)
+}
+
+/// Model matrices of the previous frame's instances by pick id. Draws sharing a pick id are told
+/// apart by their order among the frame's instances, which holds while the same draws are submitted
+#[derive(Default)]
+pub struct TransformHistory {
+    previous: HashMap<(u32, u32), ModelMat>,
+    current: HashMap<(u32, u32), ModelMat>,
+}
+
+impl TransformHistory {
+    /// `previous_instances` gets the previous transform of each of `instances`, its current one
+    /// for draws without a pick id or new this frame. Call once per frame
+    pub fn match_instances(
+        &mut self,
+        instances: &[ModelMat],
+        pick_ids: &[Option<u32>],
+        previous_instances: &mut Vec<ModelMat>,
+    ) {
+        previous_instances.clear();
+        for (&transform, &pick_id) in instances.iter().zip(pick_ids) {
+            let Some(pick_id) = pick_id else {
+                previous_instances.push(transform);
+                continue;
+            };
+            let mut key = (pick_id, 0);
+            while self.current.contains_key(&key) {
+                key.1 += 1;
+            }
+            previous_instances.push(self.previous.get(&key).copied().unwrap_or(transform));
+            self.current.insert(key, transform);
+        }
+        std::mem::swap(&mut self.previous, &mut self.current);
+        self.current.clear();
+    }
+
+    /// forgets every transform, the next frame has no motion of its own
+    pub fn clear(&mut self) {
+        self.previous.clear();
+    }
+}
+
+/// what the blur reads of the frame's scene pass
+pub struct SceneInputs {
+    /// the swapchain image or the render scaled target
+    pub color_image: vk::Image,
+    /// the scene pass left it in
+    pub color_layout: vk::ImageLayout,
+    pub depth_image: vk::Image,
+    pub depth_format: vk::Format,
+    /// the letterboxed part of the scene
+    pub viewport: vk::Rect2D,
+    pub per_frame_ubo_set: vk::DescriptorSet,
+    pub view_ubo_offset: u32,
+    pub clear_depth: f32,
+}
+
+/// size dependent resources, replaced on resize
+struct Targets {
+    extent: vk::Extent2D,
+    velocity_image: vk::Image,
+    velocity_memory: vk::DeviceMemory,
+    velocity_view: vk::ImageView,
+    depth_image: vk::Image,
+    depth_memory: vk::DeviceMemory,
+    depth_view: vk::ImageView,
+    /// the depth aspect alone
+    depth_sampled_view: vk::ImageView,
+    framebuffer: vk::Framebuffer,
+    copy_image: vk::Image,
+    copy_memory: vk::DeviceMemory,
+    copy_view: vk::ImageView,
+    /// in pixels, of both the velocity pass and the camera
+    resolved_image: vk::Image,
+    resolved_memory: vk::DeviceMemory,
+    resolved_view: vk::ImageView,
+    tiles_image: vk::Image,
+    tiles_memory: vk::DeviceMemory,
+    tiles_view: vk::ImageView,
+    output_image: vk::Image,
+    output_memory: vk::DeviceMemory,
+    output_view: vk::ImageView,
+    set: vk::DescriptorSet,
+}
+
+/// Call `advance` while filling the frame's uniform buffer, `build` once the draws are batched,
+/// `cmd_render_velocity` before the scene pass and `cmd_blur` after it, outside of any render pass.
+/// `resize` along with the depth buffer
+pub struct MotionBlur {
+    device: Rc<ash::Device>,
+    physical_device_memory_properties: vk::PhysicalDeviceMemoryProperties,
+    /// off by default, while off nothing is rendered
+    pub enabled: bool,
+    /// fraction of a `REFERENCE_FRAME_TIME` long frame's motion blurred over, like a camera's shutter
+    pub strength: f32,
+    depth_format: vk::Format,
+    reverse_z: bool,
+    targets: Option<Targets>,
+
+    /// of the previous frame's main view, `None` before the first
+    previous_proj_view: Option<Mat>,
+    /// seconds since startup of the previous frame
+    previous_time: Option<f32>,
+    frame_time: f32,
+    history: TransformHistory,
+    previous_instances: Vec<ModelMat>,
+    /// host visible, one region per frame in flight indexed like the instance buffer's
+    previous_instance_buffer: Buffer,
+
+    render_pass: vk::RenderPass,
+    velocity_set_layout: vk::DescriptorSetLayout,
+    velocity_descriptor_pool: vk::DescriptorPool,
+    /// the previous instances, at a dynamic offset per frame
+    velocity_set: vk::DescriptorSet,
+    velocity_pipeline_layout: vk::PipelineLayout,
+    velocity_pipeline: vk::Pipeline,
+
+    /// for every sampled image, read with texelFetch
+    sampler: vk::Sampler,
+    /// reset on resize
+    descriptor_pool: vk::DescriptorPool,
+    set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    tiles_pipeline: vk::Pipeline,
+    blur_pipeline: vk::Pipeline,
+}
+
+impl MotionBlur {
+    pub fn new(
+        device: Rc<ash::Device>,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        shader_compiler: &shader::ShaderCompiler,
+        write_batcher: &mut DescriptorWriteBatcher,
+        per_frame_ubo_set_layout: vk::DescriptorSetLayout,
+        depth_format: vk::Format,
+    ) -> Self {
+        let region_size = (MAX_INSTANCE_COUNT * size_of::<ModelMat>()) as vk::DeviceSize;
+        let previous_instance_buffer = Buffer::new(
+            MAX_FRAMES_IN_FLIGHT as vk::DeviceSize * region_size,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            device.clone(),
+            physical_device_memory_properties,
+        );
+
+        // leaves the velocities ready for the blur
+        let render_pass = render_pass::new_render_pass(
+            &device,
+            VELOCITY_FORMAT,
+            depth_format,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            &render_pass::ClearConfig::default(),
+        );
+
+        let velocity_set_layout = unsafe {
+            let bindings = [vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER_DYNAMIC)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::VERTEX)
+                .build()];
+            let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+            device.create_descriptor_set_layout(&info, None).unwrap()
+        };
+        let velocity_descriptor_pool = unsafe {
+            let pool_sizes = [vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER_DYNAMIC,
+                descriptor_count: 1,
+            }];
+            let info = vk::DescriptorPoolCreateInfo::builder()
+                .max_sets(1)
+                .pool_sizes(&pool_sizes);
+            device.create_descriptor_pool(&info, None).expect("Failed to create descriptor pool")
+        };
+        let velocity_set = unsafe {
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(velocity_descriptor_pool)
+                .set_layouts(&[velocity_set_layout])
+                .build();
+            device.allocate_descriptor_sets(&alloc_info).unwrap()[0]
+        };
+        write_batcher.queue_buffer_write(
+            velocity_set,
+            0,
+            0,
+            vk::DescriptorType::STORAGE_BUFFER_DYNAMIC,
+            vk::DescriptorBufferInfo {
+                buffer: previous_instance_buffer.handle,
+                offset: 0,
+                range: region_size,
+            },
+        );
+
+        let sampler = unsafe {
+            let info = vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::NEAREST)
+                .min_filter(vk::Filter::NEAREST)
+                .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE);
+            device.create_sampler(&info, None).unwrap()
+        };
+
+        let set_layout_bindings = [
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            vk::DescriptorType::STORAGE_IMAGE,
+            vk::DescriptorType::STORAGE_IMAGE,
+            vk::DescriptorType::STORAGE_IMAGE,
+        ];
+        let set_layout_bindings: Vec<_> = set_layout_bindings
+            .iter()
+            .enumerate()
+            .map(|(binding, &ty)| vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding as u32)
+                .descriptor_type(ty)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build()
+            )
+            .collect();
+        let set_layout = unsafe {
+            let info = vk::DescriptorSetLayoutCreateInfo::builder()
+                .bindings(&set_layout_bindings);
+            device.create_descriptor_set_layout(&info, None).unwrap()
+        };
+        let descriptor_pool = unsafe {
+            let pool_sizes = [
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    descriptor_count: 4,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_IMAGE,
+                    descriptor_count: 3,
+                },
+            ];
+            let info = vk::DescriptorPoolCreateInfo::builder()
+                .max_sets(1)
+                .pool_sizes(&pool_sizes);
+            device.create_descriptor_pool(&info, None).expect("Failed to create descriptor pool")
+        };
+
+        let push_constant_ranges = [vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            offset: 0,
+            size: size_of::<BlurPushConstants>() as u32,
+        }];
+        let (tiles_pipeline, pipeline_layout) = pipeline::new_compute_pipeline_and_layout(
+            &device,
+            shader_compiler,
+            "shaders/motion_blur_tiles.comp",
+            &[set_layout, per_frame_ubo_set_layout],
+            &push_constant_ranges,
+            &[],
+        );
+        let (blur_pipeline, blur_pipeline_layout) = pipeline::new_compute_pipeline_and_layout(
+            &device,
+            shader_compiler,
+            "shaders/motion_blur.comp",
+            &[set_layout, per_frame_ubo_set_layout],
+            &push_constant_ranges,
+            &[],
+        );
+        // both were made from the same layouts
+        unsafe { device.destroy_pipeline_layout(blur_pipeline_layout, None); }
+
+        Self {
+            device,
+            physical_device_memory_properties: *physical_device_memory_properties,
+            enabled: false,
+            strength: 0.5,
+            depth_format,
+            reverse_z: false,
+            targets: None,
+
+            previous_proj_view: None,
+            previous_time: None,
+            frame_time: REFERENCE_FRAME_TIME,
+            history: TransformHistory::default(),
+            previous_instances: vec![],
+            previous_instance_buffer,
+
+            render_pass,
+            velocity_set_layout,
+            velocity_descriptor_pool,
+            velocity_set,
+            velocity_pipeline_layout: vk::PipelineLayout::null(),
+            velocity_pipeline: vk::Pipeline::null(),
+
+            sampler,
+            descriptor_pool,
+            set_layout,
+            pipeline_layout,
+            tiles_pipeline,
+            blur_pipeline,
+        }
+    }
+
+    pub fn renew_pipeline(
+        &mut self,
+        shader_compiler: &shader::ShaderCompiler,
+        per_frame_ubo_set_layout: vk::DescriptorSetLayout,
+        reverse_z: bool,
+    ) {
+        unsafe { self.destroy_velocity_pipeline(); }
+
+        self.reverse_z = reverse_z;
+        // material push constants are pushed by the batches even though they aren't read
+        (self.velocity_pipeline, self.velocity_pipeline_layout) = pipeline::new_pipeline_and_layout(
+            &self.device,
+            shader_compiler,
+            &pipeline::PipelineDesc {
+                render_pass: self.render_pass,
+                color_formats: &[VELOCITY_FORMAT],
+                depth_format: self.depth_format,
+                set_layouts: &[per_frame_ubo_set_layout, self.velocity_set_layout],
+                push_constant_ranges: &[material::MaterialPushConstants::RANGE],
+                vertex_shader_path: "shaders/motion.vert",
+                fragment_shader_path: "shaders/motion.frag",
+                vertex_attributes: &geometry::VERTEX_ATTRIBUTES,
+                instance_attributes: &geometry::INSTANCE_ATTRIBUTES,
+                reverse_z,
+                ..Default::default()
+            },
+        );
+    }
+
+    /// replaces the size dependent images for a scene of `extent`. `scene_depth_view` samples
+    /// the depth buffer's depth aspect, the previous images must no longer be in use
+    pub fn resize(
+        &mut self,
+        write_batcher: &mut DescriptorWriteBatcher,
+        extent: vk::Extent2D,
+        scene_depth_view: vk::ImageView,
+    ) {
+        unsafe { self.destroy_targets(); }
+
+        let new_image = |width, height, usage, format| {
+            let (image, memory) = image::new_image_and_memory(
+                &self.device,
+                &self.physical_device_memory_properties,
+                &image::ImageDesc {
+                    width,
+                    height,
+                    usage,
+                    format,
+                    ..Default::default()
+                },
+            );
+            let aspect_mask = if format == self.depth_format {
+                image::get_depth_aspect_mask(format)
+            } else {
+                vk::ImageAspectFlags::COLOR
+            };
+            (image, memory, image::new_image_view(&self.device, image, format, aspect_mask, 1))
+        };
+        let (velocity_image, velocity_memory, velocity_view) = new_image(
+            extent.width,
+            extent.height,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            VELOCITY_FORMAT,
+        );
+        let (depth_image, depth_memory, depth_view) = new_image(
+            extent.width,
+            extent.height,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            self.depth_format,
+        );
+        let depth_sampled_view = image::new_image_view(
+            &self.device,
+            depth_image,
+            self.depth_format,
+            vk::ImageAspectFlags::DEPTH,
+            1,
+        );
+        let (copy_image, copy_memory, copy_view) = new_image(
+            extent.width,
+            extent.height,
+            vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+            COLOR_FORMAT,
+        );
+        let (resolved_image, resolved_memory, resolved_view) = new_image(
+            extent.width,
+            extent.height,
+            vk::ImageUsageFlags::STORAGE,
+            VELOCITY_FORMAT,
+        );
+        let (tiles_image, tiles_memory, tiles_view) = new_image(
+            extent.width.div_ceil(TILE_SIZE),
+            extent.height.div_ceil(TILE_SIZE),
+            vk::ImageUsageFlags::STORAGE,
+            VELOCITY_FORMAT,
+        );
+        let (output_image, output_memory, output_view) = new_image(
+            extent.width,
+            extent.height,
+            vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_SRC,
+            COLOR_FORMAT,
+        );
+
+        let framebuffer = unsafe {
+            let attachments = [velocity_view, depth_view];
+            let info = vk::FramebufferCreateInfo::builder()
+                .render_pass(self.render_pass)
+                .attachments(&attachments)
+                .width(extent.width)
+                .height(extent.height)
+                .layers(1);
+            self.device.create_framebuffer(&info, None).unwrap()
+        };
+
+        let set = unsafe {
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(self.descriptor_pool)
+                .set_layouts(&[self.set_layout])
+                .build();
+            self.device.allocate_descriptor_sets(&alloc_info).unwrap()[0]
+        };
+        let image_writes = [
+            (0, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, copy_view, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+            (1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, scene_depth_view, vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL),
+            (2, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, depth_sampled_view, vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL),
+            (3, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, velocity_view, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+            (4, vk::DescriptorType::STORAGE_IMAGE, resolved_view, vk::ImageLayout::GENERAL),
+            (5, vk::DescriptorType::STORAGE_IMAGE, tiles_view, vk::ImageLayout::GENERAL),
+            (6, vk::DescriptorType::STORAGE_IMAGE, output_view, vk::ImageLayout::GENERAL),
+        ];
+        for (binding, ty, image_view, image_layout) in image_writes {
+            let sampler = match ty {
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER => self.sampler,
+                _ => vk::Sampler::null(),
+            };
+            write_batcher.queue_image_write(
+                set,
+                binding,
+                0,
+                ty,
+                vk::DescriptorImageInfo { sampler, image_view, image_layout },
+            );
+        }
+
+        self.targets = Some(Targets {
+            extent,
+            velocity_image,
+            velocity_memory,
+            velocity_view,
+            depth_image,
+            depth_memory,
+            depth_view,
+            depth_sampled_view,
+            framebuffer,
+            copy_image,
+            copy_memory,
+            copy_view,
+            resolved_image,
+            resolved_memory,
+            resolved_view,
+            tiles_image,
+            tiles_memory,
+            tiles_view,
+            output_image,
+            output_memory,
+            output_view,
+            set,
+        });
+    }
+
+    /// the previous frame's `proj_view`, this frame's on the first. Call once per frame with
+    /// the main view's `proj_view` and the seconds since startup
+    pub fn advance(&mut self, proj_view: Mat, time: f32) -> Mat {
+        if let Some(previous_time) = self.previous_time.replace(time) {
+            self.frame_time = time - previous_time;
+        }
+        self.previous_proj_view.replace(proj_view).unwrap_or(proj_view)
+    }
+
+    /// seconds between the last two `advance`s
+    pub fn get_frame_time(&self) -> f32 {
+        self.frame_time
+    }
+
+    /// matches the previous transforms to `frame`'s instances, call once its draws are batched
+    /// and its previous commands have finished
+    pub fn build(&mut self, frame: usize, draw_batcher: &DrawBatcher) {
+        if !self.enabled {
+            // stale transforms would blur whatever moved while it was off
+            self.history.clear();
+            return;
+        }
+        self.history.match_instances(
+            draw_batcher.get_instances(),
+            draw_batcher.get_instance_pick_ids(),
+            &mut self.previous_instances,
+        );
+        self.previous_instance_buffer.copy_from_slice(&self.previous_instances, frame * MAX_INSTANCE_COUNT);
+    }
+
+    /// renders the batched draws' velocities, record before the scene pass. `viewport` is
+    /// the letterboxed part of the scene, as the scene pass draws it
+    pub fn cmd_render_velocity(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+        viewport: vk::Rect2D,
+        per_frame_ubo_offset: u32,
+        scene: &SceneDraws,
+    ) {
+        let SceneDraws { draw_batcher, geometry_system, material_system, per_frame_ubo_set, .. } = *scene;
+        if !self.enabled {
+            return;
+        }
+        let Some(targets) = self.targets.as_ref() else {
+            return;
+        };
+
+        let clear_values = [
+            vk::ClearValue {
+                color: vk::ClearColorValue { float32: [0.0; 4] },
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: render_pass::far_depth(self.reverse_z),
+                    stencil: 0,
+                },
+            },
+        ];
+        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.render_pass)
+            .framebuffer(targets.framebuffer)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: targets.extent,
+            })
+            .clear_values(&clear_values);
+        let instance_offset = (frame * MAX_INSTANCE_COUNT * size_of::<ModelMat>()) as u32;
+
+        unsafe {
+            // the previous frame's blur may still be reading the attachments
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[],
+            );
+
+            self.device.cmd_begin_render_pass(command_buffer, &render_pass_begin_info, vk::SubpassContents::INLINE);
+            self.device.cmd_set_viewport(command_buffer, 0, &[vk::Viewport {
+                x: viewport.offset.x as f32,
+                y: viewport.offset.y as f32,
+                width: viewport.extent.width as f32,
+                height: viewport.extent.height as f32,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            }]);
+            self.device.cmd_set_scissor(command_buffer, 0, &[viewport]);
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.velocity_pipeline_layout,
+                0,
+                &[per_frame_ubo_set, self.velocity_set],
+                &[per_frame_ubo_offset, instance_offset],
+            );
+
+            geometry_system.cmd_bind_resources(command_buffer);
+            draw_batcher.cmd_draw_batches(
+                command_buffer,
+                frame,
+                self.velocity_pipeline_layout,
+                Some(self.velocity_pipeline),
+                geometry_system,
+                material_system,
+            );
+
+            self.device.cmd_end_render_pass(command_buffer);
+        }
+    }
+
+    /// blurs the scene the frame's scene pass drew, record after it and after `cmd_render_velocity`.
+    /// Leaves the scene's images in the layouts they were in
+    pub fn cmd_blur(&self, command_buffer: vk::CommandBuffer, scene: &SceneInputs) {
+        if !self.enabled {
+            return;
+        }
+        let Some(targets) = self.targets.as_ref() else {
+            return;
+        };
+
+        let viewport = scene.viewport;
+        let depth_barrier = |image, old_layout, new_layout, src_access_mask, dst_access_mask| {
+            vk::ImageMemoryBarrier::builder()
+                .old_layout(old_layout)
+                .new_layout(new_layout)
+                .src_access_mask(src_access_mask)
+                .dst_access_mask(dst_access_mask)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(image)
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask: image::get_depth_aspect_mask(scene.depth_format),
+                    base_mip_level: 0,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .build()
+        };
+        let subresource = vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let viewport_offsets = [
+            vk::Offset3D { x: viewport.offset.x, y: viewport.offset.y, z: 0 },
+            vk::Offset3D {
+                x: viewport.offset.x + viewport.extent.width as i32,
+                y: viewport.offset.y + viewport.extent.height as i32,
+                z: 1,
+            },
+        ];
+
+        unsafe {
+            // the scene pass and the velocity pass must be done, as must the previous frame's blur
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::COMPUTE_SHADER
+                    | vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::TRANSFER | vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[
+                    color_barrier(
+                        scene.color_image,
+                        scene.color_layout,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                        vk::AccessFlags::TRANSFER_READ,
+                    ),
+                    color_barrier(
+                        targets.copy_image,
+                        vk::ImageLayout::UNDEFINED,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        vk::AccessFlags::empty(),
+                        vk::AccessFlags::TRANSFER_WRITE,
+                    ),
+                    depth_barrier(
+                        scene.depth_image,
+                        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                        vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+                        vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                        vk::AccessFlags::SHADER_READ,
+                    ),
+                    depth_barrier(
+                        targets.depth_image,
+                        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                        vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+                        vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                        vk::AccessFlags::SHADER_READ,
+                    ),
+                    color_barrier(
+                        targets.velocity_image,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                        vk::AccessFlags::SHADER_READ,
+                    ),
+                    color_barrier(
+                        targets.resolved_image,
+                        vk::ImageLayout::UNDEFINED,
+                        vk::ImageLayout::GENERAL,
+                        vk::AccessFlags::empty(),
+                        vk::AccessFlags::SHADER_WRITE,
+                    ),
+                    color_barrier(
+                        targets.tiles_image,
+                        vk::ImageLayout::UNDEFINED,
+                        vk::ImageLayout::GENERAL,
+                        vk::AccessFlags::empty(),
+                        vk::AccessFlags::SHADER_WRITE,
+                    ),
+                    color_barrier(
+                        targets.output_image,
+                        vk::ImageLayout::UNDEFINED,
+                        vk::ImageLayout::GENERAL,
+                        vk::AccessFlags::empty(),
+                        vk::AccessFlags::SHADER_WRITE,
+                    ),
+                ],
+            );
+
+            // converts to float, and decodes srgb formats
+            self.device.cmd_blit_image(
+                command_buffer,
+                scene.color_image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                targets.copy_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[vk::ImageBlit {
+                    src_subresource: subresource,
+                    src_offsets: viewport_offsets,
+                    dst_subresource: subresource,
+                    dst_offsets: viewport_offsets,
+                }],
+                vk::Filter::NEAREST,
+            );
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[color_barrier(
+                    targets.copy_image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::AccessFlags::SHADER_READ,
+                )],
+            );
+
+            let push_constants = BlurPushConstants {
+                viewport_offset: [viewport.offset.x, viewport.offset.y],
+                viewport_size: [viewport.extent.width as i32, viewport.extent.height as i32],
+                velocity_scale: velocity_scale(self.strength, self.frame_time),
+                clear_depth: scene.clear_depth,
+            };
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[targets.set, scene.per_frame_ubo_set],
+                &[scene.view_ubo_offset],
+            );
+            self.device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                std::slice::from_raw_parts(
+                    &push_constants as *const BlurPushConstants as *const u8,
+                    size_of::<BlurPushConstants>(),
+                ),
+            );
+            self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, self.tiles_pipeline);
+            self.device.cmd_dispatch(
+                command_buffer,
+                viewport.extent.width.div_ceil(TILE_SIZE),
+                viewport.extent.height.div_ceil(TILE_SIZE),
+                1,
+            );
+
+            let memory_barrier = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .build();
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[memory_barrier],
+                &[],
+                &[],
+            );
+            self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, self.blur_pipeline);
+            self.device.cmd_dispatch(
+                command_buffer,
+                viewport.extent.width.div_ceil(WORKGROUP_SIZE),
+                viewport.extent.height.div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[
+                    color_barrier(
+                        targets.output_image,
+                        vk::ImageLayout::GENERAL,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        vk::AccessFlags::SHADER_WRITE,
+                        vk::AccessFlags::TRANSFER_READ,
+                    ),
+                    color_barrier(
+                        scene.color_image,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        vk::AccessFlags::TRANSFER_READ,
+                        vk::AccessFlags::TRANSFER_WRITE,
+                    ),
+                ],
+            );
+            // encodes srgb formats again
+            self.device.cmd_blit_image(
+                command_buffer,
+                targets.output_image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                scene.color_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[vk::ImageBlit {
+                    src_subresource: subresource,
+                    src_offsets: viewport_offsets,
+                    dst_subresource: subresource,
+                    dst_offsets: viewport_offsets,
+                }],
+                vk::Filter::NEAREST,
+            );
+
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER | vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::TRANSFER
+                    | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[
+                    color_barrier(
+                        scene.color_image,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        scene.color_layout,
+                        vk::AccessFlags::TRANSFER_WRITE,
+                        vk::AccessFlags::TRANSFER_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                    ),
+                    depth_barrier(
+                        scene.depth_image,
+                        vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+                        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                        vk::AccessFlags::empty(),
+                        vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                            | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                    ),
+                ],
+            );
+        }
+    }
+
+    unsafe fn destroy_velocity_pipeline(&mut self) {
+        if self.velocity_pipeline != vk::Pipeline::null() {
+            self.device.destroy_pipeline(self.velocity_pipeline, None);
+            self.device.destroy_pipeline_layout(self.velocity_pipeline_layout, None);
+        }
+    }
+
+    unsafe fn destroy_targets(&mut self) {
+        let Some(targets) = self.targets.take() else {
+            return;
+        };
+        self.device.destroy_framebuffer(targets.framebuffer, None);
+        let images = [
+            (targets.velocity_view, targets.velocity_image, targets.velocity_memory),
+            (targets.depth_view, targets.depth_image, targets.depth_memory),
+            (targets.copy_view, targets.copy_image, targets.copy_memory),
+            (targets.resolved_view, targets.resolved_image, targets.resolved_memory),
+            (targets.tiles_view, targets.tiles_image, targets.tiles_memory),
+            (targets.output_view, targets.output_image, targets.output_memory),
+        ];
+        self.device.destroy_image_view(targets.depth_sampled_view, None);
+        for (view, image, memory) in images {
+            self.device.destroy_image_view(view, None);
+            self.device.destroy_image(image, None);
+            self.device.free_memory(memory, None);
+        }
+        self.device.reset_descriptor_pool(self.descriptor_pool, vk::DescriptorPoolResetFlags::empty()).unwrap();
+    }
+
+    // caller must ensure only called once
+    pub unsafe fn destroy(&mut self) {
+        self.destroy_targets();
+        self.destroy_velocity_pipeline();
+        self.previous_instance_buffer.destroy();
+        self.device.destroy_render_pass(self.render_pass, None);
+        self.device.destroy_descriptor_pool(self.velocity_descriptor_pool, None);
+        self.device.destroy_descriptor_set_layout(self.velocity_set_layout, None);
+
+        self.device.destroy_pipeline(self.blur_pipeline, None);
+        self.device.destroy_pipeline(self.tiles_pipeline, None);
+        self.device.destroy_pipeline_layout(self.pipeline_layout, None);
+        self.device.destroy_descriptor_pool(self.descriptor_pool, None);
+        self.device.destroy_descriptor_set_layout(self.set_layout, None);
+        self.device.destroy_sampler(self.sampler, None);
+    }
+}
+
+/// a single mip and layer color image
+fn color_barrier(
+    image: vk::Image,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    src_access_mask: vk::AccessFlags,
+    dst_access_mask: vk::AccessFlags,
+) -> vk::ImageMemoryBarrier {
+    vk::ImageMemoryBarrier::builder()
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_access_mask(src_access_mask)
+        .dst_access_mask(dst_access_mask)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        })
+        .build()
+}
+
+#[test]
+fn test_motion_blur() {
+    use crate::math::{Rotor, Vector};
+
+    // the same blur for the same motion per second at any frame rate, none without strength
+    assert!((velocity_scale(0.5, REFERENCE_FRAME_TIME) - 0.5).abs() < 1e-6);
+    assert!((velocity_scale(0.5, REFERENCE_FRAME_TIME / 2.0) - 1.0).abs() < 1e-6);
+    assert!((velocity_scale(1.0, REFERENCE_FRAME_TIME * 4.0) - 0.25).abs() < 1e-6);
+    assert!(velocity_scale(0.0, REFERENCE_FRAME_TIME) == 0.0);
+    assert!(velocity_scale(1.0, 0.0).is_finite());
+
+    let at = |x| ModelMat::from(Vector::new(1.0, 1.0, 1.0), Rotor::identity(), Vector::new(x, 0.0, 0.0));
+    let x = |transforms: &[ModelMat]| transforms.iter().map(|transform| transform.translation().x).collect::<Vec<_>>();
+    let mut history = TransformHistory::default();
+    let mut previous = vec![];
+
+    // new draws haven't moved
+    history.match_instances(&[at(1.0), at(2.0)], &[Some(7), None], &mut previous);
+    assert!(x(&previous) == [1.0, 2.0]);
+    // by pick id, wherever the draw lands among the instances. Draws without one never move
+    history.match_instances(&[at(5.0), at(3.0), at(4.0)], &[None, Some(8), Some(7)], &mut previous);
+    assert!(x(&previous) == [5.0, 3.0, 1.0]);
+    // draws sharing a pick id in order of their instances, one more is new
+    history.match_instances(&[at(10.0), at(11.0), at(12.0)], &[Some(7), Some(7), Some(7)], &mut previous);
+    assert!(x(&previous) == [4.0, 11.0, 12.0]);
+    history.match_instances(&[at(20.0), at(21.0)], &[Some(7), Some(7)], &mut previous);
+    assert!(x(&previous) == [10.0, 11.0]);
+    // a vanished draw is forgotten, as is everything on clear
+    history.match_instances(&[at(0.0)], &[Some(8)], &mut previous);
+    assert!(x(&previous) == [0.0]);
+    history.match_instances(&[at(30.0)], &[Some(7)], &mut previous);
+    history.clear();
+    history.match_instances(&[at(31.0)], &[Some(7)], &mut previous);
+    assert!(x(&previous) == [31.0]);
+}
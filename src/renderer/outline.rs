@@ -0,0 +1,291 @@
+// Outlines around selected draws, made with the stencil buffer in two steps:
+// 1. outlined draws are batched with a variant of the scene pipeline that also writes
+//    `STENCIL_REFERENCE` wherever the draw ends up visible
+// 2. after the scene they're drawn again in a flat color, scaled up about their origin,
+//    only where the stencil isn't `STENCIL_REFERENCE`, which leaves a rim around the visible part
+// The rim isn't depth tested so it shows through whatever is in front. Depth formats
+// without a stencil component draw outlined draws like any other, without a rim
+
+use std::{mem::size_of, rc::Rc};
+
+use ash::vk;
+
+use crate::{geometry::{self, GeometryId, GeometrySystem}, math::ModelMat};
+use super::{
+    buffer::Buffer,
+    gbuffer,
+    image,
+    material,
+    pipeline::{self, StencilState},
+    swapchain::OutputTransfer,
+    RenderPath,
+    MAX_FRAMES_IN_FLIGHT,
+};
+
+/// per frame in flight
+pub const MAX_OUTLINED_COUNT: usize = 0x100;
+/// written by outlined draws, the scene's other draws leave the cleared 0
+pub const STENCIL_REFERENCE: u32 = 1;
+
+/// must match the push constant block of outline.frag
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct OutlinePushConstants {
+    color: [f32; 4],
+}
+
+impl OutlinePushConstants {
+    const RANGE: vk::PushConstantRange = vk::PushConstantRange {
+        stage_flags: vk::ShaderStageFlags::FRAGMENT,
+        offset: 0,
+        size: size_of::<Self>() as u32,
+    };
+}
+
+/// `transform` uniformly scaled by `scale` about its translation
+pub fn scaled_about_origin(transform: &ModelMat, scale: f32) -> ModelMat {
+    let mut scaled = *transform;
+    scaled.scale(scale, scale, scale);
+    scaled
+}
+
+/// Batch outlined draws with `PipelineId::OutlineStencil` and `submit` them here too, `build` once
+/// the frame's fence is waited on and `cmd_draw` in the scene pass after the outlined draws.
+/// The pipelines depend on the scene render pass, `renew_pipelines` when it changes
+pub struct OutlineRenderer {
+    device: Rc<ash::Device>,
+    /// straight alpha
+    pub color: [f32; 4],
+    /// of the rim's redraw, relative to the outlined draw
+    pub scale: f32,
+
+    submitted: Vec<(GeometryId, ModelMat)>,
+    /// geometries of the last built frame, their scaled transforms are in the same order
+    geometries: Vec<GeometryId>,
+    instances: Vec<ModelMat>,
+    /// host visible, one region per frame in flight
+    instance_buffer: Buffer,
+
+    /// the scene pipeline also writing the stencil, null without a stencil component
+    stencil_pipeline_layout: vk::PipelineLayout,
+    stencil_pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl OutlineRenderer {
+    pub fn new(
+        device: Rc<ash::Device>,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    ) -> Self {
+        Self {
+            instance_buffer: Buffer::new(
+                (MAX_FRAMES_IN_FLIGHT * Self::frame_size()) as vk::DeviceSize,
+                vk::BufferUsageFlags::VERTEX_BUFFER,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                device.clone(),
+                physical_device_memory_properties,
+            ),
+            device,
+            color: [1.0, 0.6, 0.1, 1.0],
+            scale: 1.05,
+
+            submitted: vec![],
+            geometries: vec![],
+            instances: vec![],
+
+            stencil_pipeline_layout: vk::PipelineLayout::null(),
+            stencil_pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            pipeline: vk::Pipeline::null(),
+        }
+    }
+
+    /// `render_pass` null for dynamic rendering, the rim is drawn in `render_path`'s translucent subpass
+    pub fn renew_pipelines(
+        &mut self,
+        context: &pipeline::PassContext,
+        render_pass: vk::RenderPass,
+    ) {
+        let pipeline::PassContext { shader_compiler, render_path, color_format, depth_format, per_frame_ubo_set_layout, textures_set_layout, output_transfer, reverse_z, .. } = *context;
+        unsafe { self.destroy_pipelines(); }
+
+        if !image::has_stencil_component(depth_format) {
+            log::warn!("Depth format {:?} has no stencil, outlines are off", depth_format);
+            return;
+        }
+
+        // as the scene pipeline, so its layout is compatible with the one batches are drawn with
+        (self.stencil_pipeline, self.stencil_pipeline_layout) = pipeline::new_pipeline_and_layout(
+            &self.device,
+            shader_compiler,
+            &pipeline::PipelineDesc {
+                render_pass,
+                color_formats: &[color_format],
+                depth_format,
+                set_layouts: &[per_frame_ubo_set_layout, textures_set_layout],
+                push_constant_ranges: &[material::MaterialPushConstants::RANGE],
+                vertex_shader_path: "shaders/foo.vert",
+                fragment_shader_path: match render_path {
+                    RenderPath::Forward => "shaders/foo.frag",
+                    RenderPath::Deferred => "shaders/gbuffer.frag",
+                },
+                vertex_attributes: &geometry::VERTEX_ATTRIBUTES,
+                instance_attributes: &geometry::INSTANCE_ATTRIBUTES,
+                color_attachment_count: match render_path {
+                    RenderPath::Forward => 1,
+                    RenderPath::Deferred => gbuffer::GBUFFER_FORMATS.len() as u32,
+                },
+                output_transfer: match render_path {
+                    RenderPath::Forward => output_transfer,
+                    RenderPath::Deferred => OutputTransfer::None,
+                },
+                reverse_z,
+                stencil: Some(StencilState::write(STENCIL_REFERENCE)),
+                ..Default::default()
+            },
+        );
+
+        (self.pipeline, self.pipeline_layout) = pipeline::new_pipeline_and_layout(
+            &self.device,
+            shader_compiler,
+            &pipeline::PipelineDesc {
+                render_pass,
+                subpass: render_path.translucent_subpass(),
+                color_formats: &[color_format],
+                depth_format,
+                set_layouts: &[per_frame_ubo_set_layout],
+                push_constant_ranges: &[OutlinePushConstants::RANGE],
+                vertex_shader_path: "shaders/outline.vert",
+                fragment_shader_path: "shaders/outline.frag",
+                vertex_attributes: &geometry::VERTEX_ATTRIBUTES,
+                instance_attributes: &geometry::INSTANCE_ATTRIBUTES,
+                blend_mode: pipeline::BlendMode::Alpha,
+                depth_test: false,
+                depth_write: false,
+                stencil: Some(StencilState::not_equal(STENCIL_REFERENCE)),
+                output_transfer,
+                ..Default::default()
+            },
+        );
+    }
+
+    /// what `PipelineId::OutlineStencil` resolves to, `None` when outlines are off
+    pub fn get_stencil_pipeline(&self) -> Option<vk::Pipeline> {
+        (self.stencil_pipeline != vk::Pipeline::null()).then_some(self.stencil_pipeline)
+    }
+
+    /// outlined next frame, only for that frame
+    pub fn submit(&mut self, geometry: GeometryId, transform: ModelMat) {
+        self.submitted.push((geometry, transform));
+    }
+
+    const fn frame_size() -> usize {
+        MAX_OUTLINED_COUNT * size_of::<ModelMat>()
+    }
+
+    /// writes the submitted draws' scaled transforms into `frame`'s region and clears them,
+    /// the frame's previous commands must have finished executing
+    pub fn build(&mut self, frame: usize) {
+        if self.submitted.len() > MAX_OUTLINED_COUNT {
+            log::warn!("Dropping {} outlines over the limit", self.submitted.len() - MAX_OUTLINED_COUNT);
+            self.submitted.truncate(MAX_OUTLINED_COUNT);
+        }
+        if self.get_stencil_pipeline().is_none() {
+            self.submitted.clear();
+        }
+
+        self.geometries.clear();
+        self.instances.clear();
+        for (geometry, transform) in self.submitted.drain(..) {
+            self.geometries.push(geometry);
+            self.instances.push(scaled_about_origin(&transform, self.scale));
+        }
+        self.instance_buffer.copy_from_slice(&self.instances, frame * MAX_OUTLINED_COUNT);
+    }
+
+    /// record in the scene pass after the outlined draws, binds its own pipeline and geometry resources
+    pub fn cmd_draw(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+        per_frame_ubo_set: vk::DescriptorSet,
+        per_frame_ubo_offset: u32,
+        geometry_system: &GeometrySystem,
+    ) {
+        if self.geometries.is_empty() {
+            return;
+        }
+
+        let push_constants = OutlinePushConstants { color: self.color };
+        unsafe {
+            self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[per_frame_ubo_set],
+                &[per_frame_ubo_offset],
+            );
+            self.device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                OutlinePushConstants::RANGE.stage_flags,
+                0,
+                std::slice::from_raw_parts(
+                    &push_constants as *const OutlinePushConstants as *const u8,
+                    size_of::<OutlinePushConstants>(),
+                ),
+            );
+            geometry_system.cmd_bind_resources(command_buffer);
+            self.device.cmd_bind_vertex_buffers(
+                command_buffer,
+                pipeline::INSTANCE_BINDING,
+                &[self.instance_buffer.handle],
+                &[(frame * Self::frame_size()) as vk::DeviceSize],
+            );
+        }
+
+        for (instance, &geometry) in self.geometries.iter().enumerate() {
+            geometry_system.cmd_draw_geometry(command_buffer, geometry, instance as u32, 1);
+        }
+    }
+
+    unsafe fn destroy_pipelines(&mut self) {
+        if self.stencil_pipeline != vk::Pipeline::null() {
+            self.device.destroy_pipeline(self.stencil_pipeline, None);
+            self.device.destroy_pipeline_layout(self.stencil_pipeline_layout, None);
+            self.device.destroy_pipeline(self.pipeline, None);
+            self.device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        self.stencil_pipeline = vk::Pipeline::null();
+        self.pipeline = vk::Pipeline::null();
+    }
+
+    // caller must ensure only called once
+    pub unsafe fn destroy(&mut self) {
+        self.destroy_pipelines();
+        self.instance_buffer.destroy();
+    }
+}
+
+#[test]
+fn test_outline() {
+    use crate::math::Vector;
+
+    let mut transform = ModelMat::identity();
+    transform.scale(2.0, 2.0, 2.0).translate(1.0, 2.0, 3.0);
+    let scaled = scaled_about_origin(&transform, 1.5);
+    let near = |a: Vector, b: Vector| (a - b).norm_sqr() < 1e-6;
+    // the origin stays, the axes grow
+    assert!(near(scaled.translation(), Vector::new(1.0, 2.0, 3.0)));
+    assert!(near(scaled.axis(0), Vector::new(3.0, 0.0, 0.0)) && near(scaled.axis(2), Vector::new(0.0, 0.0, 3.0)));
+
+    // outlined draws mark their pixels, the rim only tests them
+    let write = StencilState::write(STENCIL_REFERENCE).to_vk();
+    assert!(write.compare_op == vk::CompareOp::ALWAYS && write.pass_op == vk::StencilOp::REPLACE);
+    assert!(write.write_mask == 0xff && write.depth_fail_op == vk::StencilOp::KEEP);
+    let rim = StencilState::not_equal(STENCIL_REFERENCE).to_vk();
+    assert!(rim.compare_op == vk::CompareOp::NOT_EQUAL && rim.reference == STENCIL_REFERENCE && rim.write_mask == 0);
+}
@@ -0,0 +1,261 @@
+// Records the frame's draw batches as jobs into secondary command buffers,
+// which the primary command buffer then executes. Every job records with its own
+// command pool per frame in flight, so pools are only reset once their frame finished.
+// Workers never touch the systems, batches are resolved to plain draws on the main thread first
+
+use std::rc::Rc;
+
+use ash::vk;
+
+use crate::{geometry, jobs::JobSystem};
+use super::{draw_list::StateTracker, material::MaterialPushConstants, pipeline, MAX_FRAMES_IN_FLIGHT};
+
+/// at most this many jobs record a frame
+const MAX_WORKER_COUNT: usize = 8;
+
+/// a batch with everything needed to record it
+#[derive(Clone, Copy, Debug)]
+pub struct BatchDraw {
+    pub pipeline: vk::Pipeline,
+    pub material: MaterialPushConstants,
+    pub command: vk::DrawIndexedIndirectCommand,
+}
+
+/// what the secondary command buffers draw into, `render_pass` null for dynamic rendering
+#[derive(Clone, Copy)]
+pub struct SecondaryTarget {
+    pub render_pass: vk::RenderPass,
+    pub subpass: u32,
+    pub framebuffer: vk::Framebuffer,
+    pub color_format: vk::Format,
+    pub depth_format: vk::Format,
+    /// of the pipeline statistics query active in the primary command buffer
+    pub pipeline_statistics: vk::QueryPipelineStatisticFlags,
+}
+
+/// secondary command buffers inherit no state, every worker binds this before drawing
+#[derive(Clone, Copy)]
+pub struct DrawState {
+    pub viewport: vk::Viewport,
+    pub scissor: vk::Rect2D,
+    /// shared by the batches' pipelines
+    pub pipeline_layout: vk::PipelineLayout,
+    pub descriptor_sets: [vk::DescriptorSet; 3],
+    pub dynamic_offsets: [u32; 1],
+    pub vertex_buffer: vk::Buffer,
+    pub index_buffer: vk::Buffer,
+    pub instance_buffer: vk::Buffer,
+    pub instance_offset: vk::DeviceSize,
+}
+
+/// splits `draw_count` draws into at most `worker_count` contiguous chunks, in draw order
+pub fn chunk_size(draw_count: usize, worker_count: usize) -> usize {
+    draw_count.div_ceil(worker_count.max(1)).max(1)
+}
+
+/// workers recording `batch_count` batches when each should get at least `min_batches_per_worker`,
+/// 0 when they are recorded inline
+pub fn workers_for(batch_count: usize, min_batches_per_worker: usize, worker_count: usize) -> usize {
+    let worker_count = (batch_count / min_batches_per_worker.max(1)).min(worker_count);
+    // one worker only adds overhead
+    if worker_count < 2 { 0 } else { worker_count }
+}
+
+unsafe fn begin_secondary(device: &ash::Device, command_buffer: vk::CommandBuffer, target: &SecondaryTarget) {
+    let color_formats = [target.color_format];
+    let mut rendering_info = vk::CommandBufferInheritanceRenderingInfo::builder()
+        .color_attachment_formats(&color_formats)
+        .depth_attachment_format(target.depth_format)
+        .stencil_attachment_format(pipeline::stencil_attachment_format(target.depth_format))
+        .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+    let mut inheritance_info = vk::CommandBufferInheritanceInfo::builder()
+        .render_pass(target.render_pass)
+        .subpass(target.subpass)
+        .framebuffer(target.framebuffer)
+        .pipeline_statistics(target.pipeline_statistics);
+    if target.render_pass == vk::RenderPass::null() {
+        inheritance_info = inheritance_info.push_next(&mut rendering_info);
+    }
+
+    let begin_info = vk::CommandBufferBeginInfo::builder()
+        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT | vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE)
+        .inheritance_info(&inheritance_info);
+    device.begin_command_buffer(command_buffer, &begin_info).expect("Failed to begin recording secondary command buffer");
+}
+
+unsafe fn cmd_draw_batches(device: &ash::Device, command_buffer: vk::CommandBuffer, state: &DrawState, draws: &[BatchDraw]) {
+    device.cmd_set_viewport(command_buffer, 0, &[state.viewport]);
+    device.cmd_set_scissor(command_buffer, 0, &[state.scissor]);
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        state.pipeline_layout,
+        0,
+        &state.descriptor_sets,
+        &state.dynamic_offsets,
+    );
+    device.cmd_bind_vertex_buffers(
+        command_buffer,
+        pipeline::VERTEX_BINDING,
+        &[state.vertex_buffer, state.instance_buffer],
+        &[0, state.instance_offset],
+    );
+    device.cmd_bind_index_buffer(command_buffer, state.index_buffer, 0, geometry::VK_INDEX_TYPE);
+
+    let mut tracker = StateTracker::default();
+    for draw in draws {
+        if tracker.bind_pipeline(draw.pipeline) {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, draw.pipeline);
+        }
+        if tracker.bind_material(draw.material) {
+            draw.material.cmd_push(device, command_buffer, state.pipeline_layout);
+        }
+
+        let command = &draw.command;
+        device.cmd_draw_indexed(
+            command_buffer,
+            command.index_count,
+            command.instance_count,
+            command.first_index,
+            command.vertex_offset,
+            command.first_instance,
+        );
+    }
+}
+
+/// Records batches on the job system when there are enough of them,
+/// `reset` each frame before recording and execute what `record_batches` returns
+pub struct ParallelRecorder {
+    device: Rc<ash::Device>,
+    /// otherwise every batch is recorded inline on the main thread
+    pub enabled: bool,
+    /// fewer batches than this per job aren't worth one
+    pub min_batches_per_worker: usize,
+    /// recording jobs per frame
+    worker_count: usize,
+    /// per frame in flight, the main thread's pool then one per job
+    command_pools: Vec<Vec<vk::CommandPool>>,
+    /// one secondary command buffer per pool
+    command_buffers: Vec<Vec<vk::CommandBuffer>>,
+}
+
+impl ParallelRecorder {
+    pub fn new(device: Rc<ash::Device>, graphics_family_index: u32, job_system: &JobSystem) -> Self {
+        // the main thread records too while waiting on the jobs
+        let worker_count = (job_system.get_worker_count() + 1).min(MAX_WORKER_COUNT);
+        log::info!("Recording draws on up to {} threads", worker_count);
+
+        let mut command_pools = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT);
+        let mut command_buffers = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT);
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            let mut frame_pools = Vec::with_capacity(worker_count + 1);
+            let mut frame_command_buffers = Vec::with_capacity(worker_count + 1);
+            for _ in 0..worker_count + 1 {
+                let pool_info = vk::CommandPoolCreateInfo::builder()
+                    .queue_family_index(graphics_family_index)
+                    .flags(vk::CommandPoolCreateFlags::TRANSIENT);
+                let pool = unsafe { device.create_command_pool(&pool_info, None).expect("Failed to create command pool") };
+
+                let allocate_info = vk::CommandBufferAllocateInfo::builder()
+                    .command_pool(pool)
+                    .level(vk::CommandBufferLevel::SECONDARY)
+                    .command_buffer_count(1);
+                let command_buffer = unsafe { device.allocate_command_buffers(&allocate_info).unwrap()[0] };
+
+                frame_pools.push(pool);
+                frame_command_buffers.push(command_buffer);
+            }
+            command_pools.push(frame_pools);
+            command_buffers.push(frame_command_buffers);
+        }
+
+        Self {
+            device,
+            enabled: true,
+            min_batches_per_worker: 64,
+            worker_count,
+            command_pools,
+            command_buffers,
+        }
+    }
+
+    pub fn get_worker_count(&self) -> usize {
+        self.worker_count
+    }
+
+    /// workers that would record `batch_count` batches, 0 when they are recorded inline
+    pub fn workers_for(&self, batch_count: usize) -> usize {
+        if !self.enabled {
+            return 0;
+        }
+        workers_for(batch_count, self.min_batches_per_worker, self.worker_count)
+    }
+
+    /// resets `frame`'s pools, the frame's previous commands must have finished executing
+    pub fn reset(&self, frame: usize) {
+        for &pool in &self.command_pools[frame] {
+            unsafe {
+                self.device.reset_command_pool(pool, vk::CommandPoolResetFlags::empty()).unwrap();
+            }
+        }
+    }
+
+    /// the main thread's secondary command buffer for `frame`, begun inside `target`,
+    /// for whatever else draws in the subpass
+    pub fn begin_main(&self, frame: usize, target: &SecondaryTarget) -> vk::CommandBuffer {
+        let command_buffer = self.command_buffers[frame][0];
+        unsafe { begin_secondary(&self.device, command_buffer, target); }
+        command_buffer
+    }
+
+    /// records `draws` split across `worker_count` jobs and waits for them,
+    /// the returned command buffers are ended and draw in order
+    pub fn record_batches(
+        &self,
+        job_system: &JobSystem,
+        frame: usize,
+        worker_count: usize,
+        target: &SecondaryTarget,
+        state: &DrawState,
+        draws: &[BatchDraw],
+    ) -> &[vk::CommandBuffer] {
+        assert!(worker_count <= self.worker_count);
+        let chunks = draws.chunks(chunk_size(draws.len(), worker_count));
+        let command_buffers = &self.command_buffers[frame][1..chunks.len() + 1];
+
+        let device: &ash::Device = &self.device;
+        job_system.scope(|scope| {
+            for (&command_buffer, chunk) in command_buffers.iter().zip(chunks) {
+                scope.spawn("record batches", move || unsafe {
+                    begin_secondary(device, command_buffer, target);
+                    cmd_draw_batches(device, command_buffer, state, chunk);
+                    device.end_command_buffer(command_buffer).expect("Failed to end recording secondary command buffer");
+                });
+            }
+        });
+        command_buffers
+    }
+
+    // caller must ensure only called once
+    pub unsafe fn destroy(&mut self) {
+        for &pool in self.command_pools.iter().flatten() {
+            self.device.destroy_command_pool(pool, None);
+        }
+    }
+}
+
+#[test]
+fn test_chunk_size() {
+    assert!(chunk_size(100, 4) == 25);
+    // the last worker gets the remainder
+    assert!(chunk_size(10, 3) == 4 && 10usize.div_ceil(chunk_size(10, 3)) == 3);
+    assert!(chunk_size(0, 4) == 1 && chunk_size(5, 0) == 5);
+}
+
+#[test]
+fn test_workers_for() {
+    // below the threshold of two workers' worth of batches they're recorded inline
+    assert!(workers_for(0, 64, 8) == 0 && workers_for(64, 64, 8) == 0 && workers_for(127, 64, 8) == 0);
+    assert!(workers_for(128, 64, 8) == 2 && workers_for(10_000, 64, 8) == 8);
+    assert!(workers_for(1000, 64, 1) == 0 && workers_for(3, 0, 8) == 3);
+}
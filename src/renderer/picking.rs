@@ -0,0 +1,438 @@
+// Picking by object id, exact to the pixel where ray casts only know bounds. When a pick is
+// requested the frame's batched draws are rendered once more, writing which instance covers a
+// pixel into an R32_UINT attachment. Only the pixel under the cursor is rendered: the attachments
+// are a single pixel and the viewport is shifted so the cursor's pixel lands on it. That pixel is
+// copied into a host visible buffer, read once the frame's commands have finished, so a pick
+// requested during one frame's update is available from the next frame's update on.
+// Skinned meshes and terrain aren't drawn, alpha masks are ignored
+
+use std::{mem::size_of, rc::Rc};
+
+use ash::vk;
+
+use crate::geometry;
+use super::{
+    batch::{DrawBatcher, SceneDraws},
+    buffer::Buffer,
+    letterbox,
+    material,
+    pipeline,
+    shader,
+    render_pass,
+    MAX_FRAMES_IN_FLIGHT,
+};
+
+const ID_FORMAT: vk::Format = vk::Format::R32_UINT;
+const EXTENT: vk::Extent2D = vk::Extent2D { width: 1, height: 1 };
+
+/// What was under the cursor when the pick was requested
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Pick {
+    /// window pixels from the top left
+    pub cursor: [f32; 2],
+    /// of the draw covering the cursor, `None` for nothing or a draw without one
+    pub pick_id: Option<u32>,
+}
+
+/// pixel of a `scene_extent` sized scene viewport under `cursor` in window pixels,
+/// `None` outside the window's `viewport`
+pub fn cursor_pixel(cursor: [f32; 2], viewport: vk::Rect2D, scene_extent: vk::Extent2D) -> Option<[u32; 2]> {
+    let [x, y] = letterbox::to_viewport(cursor, viewport)?;
+    // the scene may be rendered at a lower resolution and upscaled
+    let x = x * scene_extent.width as f32 / viewport.extent.width as f32;
+    let y = y * scene_extent.height as f32 / viewport.extent.height as f32;
+    Some([
+        (x as u32).min(scene_extent.width - 1),
+        (y as u32).min(scene_extent.height - 1),
+    ])
+}
+
+/// the pick id for the value in the id attachment, 0 where nothing was drawn,
+/// otherwise one more than the index of the instance drawn
+pub fn resolve_pick_id(value: u32, instance_pick_ids: &[Option<u32>]) -> Option<u32> {
+    let index = value.checked_sub(1)?;
+    instance_pick_ids.get(index as usize).copied().flatten()
+}
+
+/// Call `request` during the update, `build` once per frame after the draws are batched
+/// and `cmd_render` before the scene pass. Once the frame's fence is waited `resolve` reads its pick
+pub struct Picking {
+    device: Rc<ash::Device>,
+
+    id_image: vk::Image,
+    id_image_memory: vk::DeviceMemory,
+    id_image_view: vk::ImageView,
+    depth_image: vk::Image,
+    depth_image_memory: vk::DeviceMemory,
+    depth_image_view: vk::ImageView,
+    render_pass: vk::RenderPass,
+    framebuffer: vk::Framebuffer,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    reverse_z: bool,
+
+    /// host visible, one id per frame in flight
+    readback_buffer: Buffer,
+
+    /// cursor of the pick for the next built frame
+    requested: Option<[f32; 2]>,
+    /// cursor and pixel of each frame in flight's pick
+    in_flight: [Option<([f32; 2], [u32; 2])>; MAX_FRAMES_IN_FLIGHT],
+    /// the instances' pick ids of each frame in flight with a pick
+    pick_ids: [Vec<Option<u32>>; MAX_FRAMES_IN_FLIGHT],
+    /// of the latest resolved pick, until taken
+    result: Option<Pick>,
+}
+
+impl Picking {
+    pub fn new(
+        device: Rc<ash::Device>,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        depth_format: vk::Format,
+    ) -> Self {
+        let (id_image, id_image_memory) = super::image::new_image_and_memory(
+            &device,
+            physical_device_memory_properties,
+            &super::image::ImageDesc {
+                width: EXTENT.width,
+                height: EXTENT.height,
+                usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+                format: ID_FORMAT,
+                ..Default::default()
+            },
+        );
+        let id_image_view = super::image::new_image_view(
+            &device,
+            id_image,
+            ID_FORMAT,
+            vk::ImageAspectFlags::COLOR,
+            1,
+        );
+        let (depth_image, depth_image_memory) = super::image::new_image_and_memory(
+            &device,
+            physical_device_memory_properties,
+            &super::image::ImageDesc {
+                width: EXTENT.width,
+                height: EXTENT.height,
+                usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+                format: depth_format,
+                ..Default::default()
+            },
+        );
+        let depth_image_view = super::image::new_image_view(
+            &device,
+            depth_image,
+            depth_format,
+            super::image::get_depth_aspect_mask(depth_format),
+            1,
+        );
+
+        // left ready to be copied from
+        let render_pass = render_pass::new_render_pass(
+            &device,
+            ID_FORMAT,
+            depth_format,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            &render_pass::ClearConfig::default(),
+        );
+        let framebuffer = unsafe {
+            let attachments = [id_image_view, depth_image_view];
+            let info = vk::FramebufferCreateInfo::builder()
+                .render_pass(render_pass)
+                .attachments(&attachments)
+                .width(EXTENT.width)
+                .height(EXTENT.height)
+                .layers(1);
+            device.create_framebuffer(&info, None).unwrap()
+        };
+
+        Self {
+            readback_buffer: Buffer::new(
+                (MAX_FRAMES_IN_FLIGHT * size_of::<u32>()) as vk::DeviceSize,
+                vk::BufferUsageFlags::TRANSFER_DST,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                device.clone(),
+                physical_device_memory_properties,
+            ),
+            device,
+
+            id_image,
+            id_image_memory,
+            id_image_view,
+            depth_image,
+            depth_image_memory,
+            depth_image_view,
+            render_pass,
+            framebuffer,
+            pipeline_layout: vk::PipelineLayout::null(),
+            pipeline: vk::Pipeline::null(),
+            reverse_z: false,
+
+            requested: None,
+            in_flight: Default::default(),
+            pick_ids: Default::default(),
+            result: None,
+        }
+    }
+
+    pub fn renew_pipeline(
+        &mut self,
+        shader_compiler: &shader::ShaderCompiler,
+        depth_format: vk::Format,
+        per_frame_ubo_set_layout: vk::DescriptorSetLayout,
+        reverse_z: bool,
+    ) {
+        unsafe { self.destroy_pipeline(); }
+
+        self.reverse_z = reverse_z;
+        // material push constants are pushed by the batches even though they aren't read
+        (self.pipeline, self.pipeline_layout) = pipeline::new_pipeline_and_layout(
+            &self.device,
+            shader_compiler,
+            &pipeline::PipelineDesc {
+                render_pass: self.render_pass,
+                color_formats: &[ID_FORMAT],
+                depth_format,
+                set_layouts: &[per_frame_ubo_set_layout],
+                push_constant_ranges: &[material::MaterialPushConstants::RANGE],
+                vertex_shader_path: "shaders/pick.vert",
+                fragment_shader_path: "shaders/pick.frag",
+                vertex_attributes: &geometry::VERTEX_ATTRIBUTES,
+                instance_attributes: &geometry::INSTANCE_ATTRIBUTES,
+                reverse_z,
+                ..Default::default()
+            },
+        );
+    }
+
+    /// picks what is under `cursor` in the next built frame, replaces an earlier request
+    pub fn request(&mut self, cursor: [f32; 2]) {
+        self.requested = Some(cursor);
+    }
+
+    /// wether `frame` has a pick to `resolve`
+    pub fn is_in_flight(&self, frame: usize) -> bool {
+        self.in_flight[frame].is_some()
+    }
+
+    /// takes the request into `frame`, call once its draws are batched and any pick it had
+    /// is resolved. `viewport` is in window pixels, `scene_extent` the scene viewport's
+    pub fn build(
+        &mut self,
+        frame: usize,
+        draw_batcher: &DrawBatcher,
+        viewport: vk::Rect2D,
+        scene_extent: vk::Extent2D,
+    ) {
+        assert!(self.in_flight[frame].is_none(), "Pick of frame {} not resolved", frame);
+        let Some(cursor) = self.requested.take() else {
+            return;
+        };
+        match cursor_pixel(cursor, viewport, scene_extent) {
+            Some(pixel) => {
+                self.in_flight[frame] = Some((cursor, pixel));
+                self.pick_ids[frame].clear();
+                self.pick_ids[frame].extend_from_slice(draw_batcher.get_instance_pick_ids());
+            }
+            // nothing to render
+            None => self.result = Some(Pick { cursor, pick_id: None }),
+        }
+    }
+
+    /// reads `frame`'s pick, the frame's commands must have finished executing
+    pub fn resolve(&mut self, frame: usize) {
+        let Some((cursor, _)) = self.in_flight[frame].take() else {
+            return;
+        };
+        let value = self.readback_buffer.copy_to_vec::<u32>(1, frame)[0];
+        let pick_id = resolve_pick_id(value, &self.pick_ids[frame]);
+        log::debug!("Picked {:?} at {:?}", pick_id, cursor);
+        self.result = Some(Pick { cursor, pick_id });
+    }
+
+    /// the latest resolved pick, once
+    pub fn take_result(&mut self) -> Option<Pick> {
+        self.result.take()
+    }
+
+    fn readback_offset(frame: usize) -> vk::DeviceSize {
+        (frame * size_of::<u32>()) as vk::DeviceSize
+    }
+
+    /// renders the batched draws under the pick's pixel if `frame` has one, record before the scene pass.
+    /// `scene_extent` is the scene viewport's
+    pub fn cmd_render(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+        scene_extent: vk::Extent2D,
+        per_frame_ubo_offset: u32,
+        scene: &SceneDraws,
+    ) {
+        let SceneDraws { draw_batcher, geometry_system, material_system, per_frame_ubo_set, .. } = *scene;
+        let Some((_, [x, y])) = self.in_flight[frame] else {
+            return;
+        };
+
+        let render_area = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: EXTENT,
+        };
+        let clear_values = [
+            vk::ClearValue {
+                color: vk::ClearColorValue { uint32: [0; 4] },
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: render_pass::far_depth(self.reverse_z),
+                    stencil: 0,
+                },
+            },
+        ];
+        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.render_pass)
+            .framebuffer(self.framebuffer)
+            .render_area(render_area)
+            .clear_values(&clear_values);
+
+        unsafe {
+            // the other frame in flight may still be copying from the id attachment
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[],
+            );
+
+            self.device.cmd_begin_render_pass(command_buffer, &render_pass_begin_info, vk::SubpassContents::INLINE);
+            // the scene's viewport, shifted so the picked pixel is the attachments' only one
+            self.device.cmd_set_viewport(command_buffer, 0, &[vk::Viewport {
+                x: -(x as f32),
+                y: -(y as f32),
+                width: scene_extent.width as f32,
+                height: scene_extent.height as f32,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            }]);
+            self.device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[per_frame_ubo_set],
+                &[per_frame_ubo_offset],
+            );
+
+            geometry_system.cmd_bind_resources(command_buffer);
+            draw_batcher.cmd_draw_batches(
+                command_buffer,
+                frame,
+                self.pipeline_layout,
+                Some(self.pipeline),
+                geometry_system,
+                material_system,
+            );
+
+            self.device.cmd_end_render_pass(command_buffer);
+
+            let memory_barrier = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+                .build();
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[memory_barrier],
+                &[],
+                &[],
+            );
+
+            let region = vk::BufferImageCopy::builder()
+                .buffer_offset(Self::readback_offset(frame))
+                .image_subresource(vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: 0,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .image_extent(vk::Extent3D {
+                    width: EXTENT.width,
+                    height: EXTENT.height,
+                    depth: 1,
+                })
+                .build();
+            self.device.cmd_copy_image_to_buffer(
+                command_buffer,
+                self.id_image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                self.readback_buffer.handle,
+                &[region],
+            );
+
+            let memory_barrier = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::HOST_READ)
+                .build();
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &[memory_barrier],
+                &[],
+                &[],
+            );
+        }
+    }
+
+    unsafe fn destroy_pipeline(&mut self) {
+        if self.pipeline != vk::Pipeline::null() {
+            self.device.destroy_pipeline(self.pipeline, None);
+            self.device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+
+    // caller must ensure only called once
+    pub unsafe fn destroy(&mut self) {
+        self.destroy_pipeline();
+        self.readback_buffer.destroy();
+
+        self.device.destroy_framebuffer(self.framebuffer, None);
+        self.device.destroy_render_pass(self.render_pass, None);
+
+        self.device.destroy_image_view(self.depth_image_view, None);
+        self.device.destroy_image(self.depth_image, None);
+        self.device.free_memory(self.depth_image_memory, None);
+        self.device.destroy_image_view(self.id_image_view, None);
+        self.device.destroy_image(self.id_image, None);
+        self.device.free_memory(self.id_image_memory, None);
+    }
+}
+
+#[test]
+fn test_picking() {
+    let window = vk::Extent2D { width: 800, height: 600 };
+    let viewport = letterbox::letterbox(window, None);
+    assert!(cursor_pixel([0.0, 0.0], viewport, window) == Some([0, 0]));
+    assert!(cursor_pixel([799.9, 599.5], viewport, window) == Some([799, 599]));
+    assert!(cursor_pixel([800.0, 10.0], viewport, window).is_none() && cursor_pixel([10.0, -1.0], viewport, window).is_none());
+    // a half resolution scene
+    let scene = vk::Extent2D { width: 400, height: 300 };
+    assert!(cursor_pixel([401.0, 599.0], viewport, scene) == Some([200, 299]));
+    // letterboxed to 16:9, the bars are 75 pixels high
+    let viewport = letterbox::letterbox(window, Some(16.0 / 9.0));
+    assert!(cursor_pixel([400.0, 74.0], viewport, viewport.extent).is_none());
+    assert!(cursor_pixel([400.0, 75.0], viewport, viewport.extent) == Some([400, 0]));
+
+    let instance_pick_ids = [Some(7), None, Some(3)];
+    assert!(resolve_pick_id(0, &instance_pick_ids).is_none());
+    assert!(resolve_pick_id(1, &instance_pick_ids) == Some(7) && resolve_pick_id(3, &instance_pick_ids) == Some(3));
+    // a draw that isn't pickable, and one past the frame's instances
+    assert!(resolve_pick_id(2, &instance_pick_ids).is_none() && resolve_pick_id(4, &instance_pick_ids).is_none());
+}
@@ -1,16 +1,28 @@
-use std::{ffi::CString, io::Read};
+use std::{ffi::CString, mem::size_of};
 
 use ash::vk;
 
+use super::{shader::{ShaderCompiler, ShaderStage}, swapchain::OutputTransfer, RenderPath};
+
 #[derive(Copy, Clone)]
 pub enum Attribute {
     F32x2,
     F32x3,
+    F32x4,
 
     // Uses graphics programmer's convention
     // width x height
     F32x4x3,
     F32x3x2,
+
+    // Quantized, half the size of their f32 counterparts,
+    // read as floats by shaders. Three components are padded to four,
+    // as three component 16 bit formats are rarely supported for vertices
+    F16x2,
+    F16x4,
+    /// -1 to 1, e.g. octahedral encoded normals, see `crate::quantize`
+    Snorm16x2,
+    Snorm16x4,
 }
 
 impl Attribute {
@@ -22,30 +34,49 @@ impl Attribute {
         use Attribute::*;
 
         match self {
-            F32x2 | F32x3 => 1,
+            F32x2 | F32x3 | F32x4 | F16x2 | F16x4 | Snorm16x2 | Snorm16x4 => 1,
             F32x4x3 => 4,
             F32x3x2 => 3,
         }
     }
 
     const fn get_size_of_single_location(self) -> u32 {
-        4 * self.get_rgb_components()
+        self.get_component_size() * self.get_rgb_components()
+    }
+
+    const fn get_component_size(self) -> u32 {
+        use Attribute::*;
+
+        match self {
+            F32x2 | F32x3 | F32x4 | F32x4x3 | F32x3x2 => 4,
+            F16x2 | F16x4 | Snorm16x2 | Snorm16x4 => 2,
+        }
     }
 
     const fn get_rgb_components(self) -> u32 {
         use Attribute::*;
 
         match self {
-            F32x2 | F32x3x2 => 2,
+            F32x2 | F32x3x2 | F16x2 | Snorm16x2 => 2,
             F32x3 | F32x4x3 => 3,
+            F32x4 | F16x4 | Snorm16x4 => 4,
         }
     }
 
     const fn get_vk_format_of_single_location(self) -> vk::Format {
-        match self.get_rgb_components() {
-            2 => vk::Format::R32G32_SFLOAT,
-            3 => vk::Format::R32G32B32_SFLOAT,
-            _ => panic!(),
+        use Attribute::*;
+
+        match self {
+            F16x2 => vk::Format::R16G16_SFLOAT,
+            F16x4 => vk::Format::R16G16B16A16_SFLOAT,
+            Snorm16x2 => vk::Format::R16G16_SNORM,
+            Snorm16x4 => vk::Format::R16G16B16A16_SNORM,
+            F32x2 | F32x3 | F32x4 | F32x4x3 | F32x3x2 => match self.get_rgb_components() {
+                2 => vk::Format::R32G32_SFLOAT,
+                3 => vk::Format::R32G32B32_SFLOAT,
+                4 => vk::Format::R32G32B32A32_SFLOAT,
+                _ => panic!(),
+            },
         }
     }
 }
@@ -98,19 +129,24 @@ pub const INSTANCE_BINDING: u32 = 1;
 pub fn get_binding_descs(
     vertex_attributes: &[Attribute],
     instance_attributes: &[Attribute],
-) -> [vk::VertexInputBindingDescription; 1] {
-    [
-        vk::VertexInputBindingDescription::builder()
+) -> Vec<vk::VertexInputBindingDescription> {
+    let mut binding_descs = Vec::with_capacity(2);
+    // pipelines generating their own vertices (fullscreen passes) have no attributes
+    if !vertex_attributes.is_empty() {
+        binding_descs.push(vk::VertexInputBindingDescription::builder()
             .binding(VERTEX_BINDING)
             .stride(calc_total_stride(vertex_attributes))
             .input_rate(vk::VertexInputRate::VERTEX)
-            .build(),
-        // vk::VertexInputBindingDescription::builder()
-        //     .binding(INSTANCE_BINDING)
-        //     .stride(calc_total_stride(instance_attributes))
-        //     .input_rate(vk::VertexInputRate::INSTANCE)
-        //     .build(),
-    ]
+            .build());
+    }
+    if !instance_attributes.is_empty() {
+        binding_descs.push(vk::VertexInputBindingDescription::builder()
+            .binding(INSTANCE_BINDING)
+            .stride(calc_total_stride(instance_attributes))
+            .input_rate(vk::VertexInputRate::INSTANCE)
+            .build());
+    }
+    binding_descs
 }
 
 pub fn get_attrib_descs(
@@ -127,32 +163,22 @@ pub fn get_attrib_descs(
         0, 
         vertex_attributes,
     );
-    // push_attrib_descs(
-    //     &mut attrib_descs, 
-    //     INSTANCE_BINDING, 
-    //     instance_location_offset,
-    //     instance_attributes
-    // );
+    push_attrib_descs(
+        &mut attrib_descs, 
+        INSTANCE_BINDING, 
+        instance_location_offset,
+        instance_attributes
+    );
     attrib_descs
 }
 
 fn new_shader_module(
     device: &ash::Device, 
-    shader_compiler: &shaderc::Compiler, 
+    shader_compiler: &ShaderCompiler, 
     file_path: &str,
-    shader_kind: shaderc::ShaderKind,
+    stage: ShaderStage,
 ) -> vk::ShaderModule {
-    let mut file = std::fs::File::open(file_path).unwrap();
-    let mut source = String::new();
-    file.read_to_string(&mut source).unwrap();
-
-    let code = shader_compiler.compile_into_spirv(
-        &source, 
-        shader_kind, 
-        file_path, 
-        "main",
-        None,
-    ).unwrap().as_binary().to_vec();
+    let code = shader_compiler.get_spirv(file_path, stage);
 
     let info = vk::ShaderModuleCreateInfo::builder()
         .code(&code);
@@ -163,20 +189,299 @@ fn new_shader_module(
     }
 }
 
-pub fn new_pipeline_and_layout(
-    device: &ash::Device,
-    shader_compiler: &shaderc::Compiler,
-    render_pass: vk::RenderPass,
+/// of `OUTPUT_TRANSFER` in output.glsl
+const OUTPUT_TRANSFER_CONSTANT_ID: u32 = 0;
+/// of `REVERSE_Z` in vertex shaders placing vertices on the near plane themselves
+const REVERSE_Z_CONSTANT_ID: u32 = 1;
+/// the first id of the constants pipelines specialize their shaders with,
+/// the ones before are set from `PipelineDesc`'s other fields
+pub const FIRST_CUSTOM_CONSTANT_ID: u32 = 2;
+
+/// Value of a specialization constant, its type must match the constant's declaration,
+/// e.g. `layout(constant_id = 2) const uint LIGHT_COUNT = 4;` for a `U32`
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Constant {
+    Bool(bool),
+    U32(u32),
+    I32(i32),
+    F32(f32),
+}
+
+impl Constant {
+    fn to_ne_bytes(self) -> [u8; 4] {
+        match self {
+            Constant::Bool(value) => (value as vk::Bool32).to_ne_bytes(),
+            Constant::U32(value) => value.to_ne_bytes(),
+            Constant::I32(value) => value.to_ne_bytes(),
+            Constant::F32(value) => value.to_ne_bytes(),
+        }
+    }
+}
 
-    ubo_set_layout: vk::DescriptorSetLayout,
-    textures_set_layout: vk::DescriptorSetLayout,
+/// map entries and data of one shader stage's constants, alive while its pipeline is created
+struct Specialization {
+    entries: Vec<vk::SpecializationMapEntry>,
+    data: Vec<u8>,
+}
 
-    vertex_shader_path: &str,
-    fragment_shader_path: &str,
-    
-    vertex_attributes: &[Attribute],
-    instance_attributes: &[Attribute],
+impl Specialization {
+    /// `constants` are `(constant_id, value)`, constants the shader doesn't declare are ignored
+    fn new(constants: &[(u32, Constant)]) -> Self {
+        let mut entries = Vec::with_capacity(constants.len());
+        let mut data = Vec::with_capacity(4 * constants.len());
+        for &(constant_id, value) in constants {
+            assert!(
+                !entries.iter().any(|entry: &vk::SpecializationMapEntry| entry.constant_id == constant_id),
+                "Constant {} specialized twice",
+                constant_id,
+            );
+            entries.push(vk::SpecializationMapEntry {
+                constant_id,
+                offset: data.len() as u32,
+                size: size_of::<u32>(),
+            });
+            data.extend(value.to_ne_bytes());
+        }
+        Self { entries, data }
+    }
+
+    /// `custom` after the constants set from the pipeline's other fields
+    fn with_builtins(output_transfer: OutputTransfer, reverse_z: bool, custom: &[(u32, Constant)]) -> Self {
+        assert!(
+            custom.iter().all(|&(constant_id, _)| constant_id >= FIRST_CUSTOM_CONSTANT_ID),
+            "Constant ids below {} are reserved",
+            FIRST_CUSTOM_CONSTANT_ID,
+        );
+        let builtins = [
+            (OUTPUT_TRANSFER_CONSTANT_ID, Constant::U32(output_transfer as u32)),
+            (REVERSE_Z_CONSTANT_ID, Constant::Bool(reverse_z)),
+        ];
+        Self::new(&[&builtins[..], custom].concat())
+    }
+
+    fn info(&self) -> vk::SpecializationInfo {
+        vk::SpecializationInfo::builder()
+            .map_entries(&self.entries)
+            .data(&self.data)
+            .build()
+    }
+}
+
+/// How a pipeline relates to similar ones, which drivers may create and switch between faster
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Derivative {
+    #[default]
+    None,
+    /// other pipelines may derive from this one
+    Parent,
+    /// derives from the pipeline, which must have been created as a `Parent`
+    Of(vk::Pipeline),
+}
+
+impl Derivative {
+    fn flags(self) -> vk::PipelineCreateFlags {
+        match self {
+            Derivative::None => vk::PipelineCreateFlags::empty(),
+            Derivative::Parent => vk::PipelineCreateFlags::ALLOW_DERIVATIVES,
+            Derivative::Of(_) => vk::PipelineCreateFlags::DERIVATIVE,
+        }
+    }
+
+    fn base_pipeline(self) -> vk::Pipeline {
+        match self {
+            Derivative::Of(base) => base,
+            _ => vk::Pipeline::null(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BlendMode {
+    Opaque,
+    /// straight alpha, for translucent surfaces and particles
+    Alpha,
+    /// adds to what's already drawn, for glowing particles
+    Additive,
+}
+
+/// Stencil test and write of both faces. Fragments write `reference` where they pass
+/// the stencil and depth tests, the stencil stays where they fail either
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct StencilState {
+    pub compare_op: vk::CompareOp,
+    pub pass_op: vk::StencilOp,
+    pub reference: u32,
+    /// bits compared and written
+    pub mask: u32,
+}
+
+impl StencilState {
+    /// marks every drawn fragment with `reference`
+    pub const fn write(reference: u32) -> Self {
+        Self {
+            compare_op: vk::CompareOp::ALWAYS,
+            pass_op: vk::StencilOp::REPLACE,
+            reference,
+            mask: 0xff,
+        }
+    }
+
+    /// draws only where the stencil isn't `reference`, leaving it as is
+    pub const fn not_equal(reference: u32) -> Self {
+        Self {
+            compare_op: vk::CompareOp::NOT_EQUAL,
+            pass_op: vk::StencilOp::KEEP,
+            reference,
+            mask: 0xff,
+        }
+    }
+
+    pub fn to_vk(self) -> vk::StencilOpState {
+        vk::StencilOpState {
+            fail_op: vk::StencilOp::KEEP,
+            pass_op: self.pass_op,
+            depth_fail_op: vk::StencilOp::KEEP,
+            compare_op: self.compare_op,
+            compare_mask: self.mask,
+            write_mask: if self.pass_op == vk::StencilOp::KEEP { 0 } else { self.mask },
+            reference: self.reference,
+        }
+    }
+}
+
+/// What the passes drawing into the scene's color and depth create their pipelines against,
+/// the scene render pass aside since it's created from this too
+#[derive(Clone, Copy)]
+pub struct PassContext<'a> {
+    pub shader_compiler: &'a ShaderCompiler,
+    pub render_path: RenderPath,
+    pub color_format: vk::Format,
+    pub depth_format: vk::Format,
+    pub per_frame_ubo_set_layout: vk::DescriptorSetLayout,
+    pub textures_set_layout: vk::DescriptorSetLayout,
+    pub output_transfer: OutputTransfer,
+    pub reverse_z: bool,
+}
+
+impl PassContext<'_> {
+    /// see `RenderPath::translucent_subpass`
+    pub fn translucent_subpass(&self) -> u32 {
+        self.render_path.translucent_subpass()
+    }
+}
+
+/// Fixed function state and resources that differ between the engine's pipelines,
+/// everything else is shared
+#[derive(Clone, Copy)]
+pub struct PipelineDesc<'a> {
+    /// null for dynamic rendering, the attachment formats are then given below
+    pub render_pass: vk::RenderPass,
+    pub subpass: u32,
+    pub color_formats: &'a [vk::Format],
+    pub depth_format: vk::Format,
+
+    pub set_layouts: &'a [vk::DescriptorSetLayout],
+    pub push_constant_ranges: &'a [vk::PushConstantRange],
+
+    pub vertex_shader_path: &'a str,
+    pub fragment_shader_path: &'a str,
+
+    pub vertex_attributes: &'a [Attribute],
+    pub instance_attributes: &'a [Attribute],
+    pub topology: vk::PrimitiveTopology,
+
+    /// one blend attachment state per color attachment of the subpass
+    pub color_attachment_count: u32,
+    pub blend_mode: BlendMode,
+    pub cull_mode: vk::CullModeFlags,
+    pub depth_test: bool,
+    pub depth_write: bool,
+    /// nearer fragments have greater depth, for projections made with `reverse_z`
+    pub reverse_z: bool,
+    /// `None` disables the stencil test, `depth_format` must have a stencil component otherwise
+    pub stencil: Option<StencilState>,
+
+    /// for fragment shaders including output.glsl, set when drawing to the swapchain
+    pub output_transfer: OutputTransfer,
+    /// `(constant_id, value)` specializing each stage's shader, ids from `FIRST_CUSTOM_CONSTANT_ID`,
+    /// e.g. light counts or shadow filter taps without compiling a shader per variant
+    pub vertex_constants: &'a [(u32, Constant)],
+    pub fragment_constants: &'a [(u32, Constant)],
+    pub derivative: Derivative,
+}
+
+impl Default for PipelineDesc<'_> {
+    fn default() -> Self {
+        Self {
+            render_pass: vk::RenderPass::null(),
+            subpass: 0,
+            color_formats: &[],
+            depth_format: vk::Format::UNDEFINED,
+
+            set_layouts: &[],
+            push_constant_ranges: &[],
+
+            vertex_shader_path: "",
+            fragment_shader_path: "",
+
+            vertex_attributes: &[],
+            instance_attributes: &[],
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+
+            color_attachment_count: 1,
+            blend_mode: BlendMode::Opaque,
+            cull_mode: vk::CullModeFlags::BACK,
+            depth_test: true,
+            depth_write: true,
+            reverse_z: false,
+            stencil: None,
+
+            output_transfer: OutputTransfer::None,
+            vertex_constants: &[],
+            fragment_constants: &[],
+            derivative: Derivative::None,
+        }
+    }
+}
+
+/// what dynamic rendering attaches as stencil for `depth_format`, UNDEFINED without a stencil component
+pub fn stencil_attachment_format(depth_format: vk::Format) -> vk::Format {
+    if super::image::has_stencil_component(depth_format) {
+        depth_format
+    } else {
+        vk::Format::UNDEFINED
+    }
+}
+
+pub fn new_pipeline_and_layout(
+    device: &ash::Device,
+    shader_compiler: &ShaderCompiler,
+    desc: &PipelineDesc,
 ) -> (vk::Pipeline, vk::PipelineLayout) {
+    let PipelineDesc {
+        render_pass,
+        subpass,
+        color_formats,
+        depth_format,
+        set_layouts,
+        push_constant_ranges,
+        vertex_shader_path,
+        fragment_shader_path,
+        vertex_attributes,
+        instance_attributes,
+        topology,
+        color_attachment_count,
+        blend_mode,
+        cull_mode,
+        depth_test,
+        depth_write,
+        reverse_z,
+        stencil,
+        output_transfer,
+        vertex_constants,
+        fragment_constants,
+        derivative,
+    } = *desc;
 
     let dynamic_state_info = vk::PipelineDynamicStateCreateInfo::builder()
         .dynamic_states(&[
@@ -189,25 +494,33 @@ pub fn new_pipeline_and_layout(
         device, 
         &shader_compiler, 
         vertex_shader_path,
-        shaderc::ShaderKind::Vertex,
+        ShaderStage::Vertex,
     );
     let frag_module = new_shader_module(
         device, 
         &shader_compiler, 
         fragment_shader_path,
-        shaderc::ShaderKind::Fragment,
+        ShaderStage::Fragment,
     );
 
+    // shaders without the constants ignore them
+    let vert_specialization = Specialization::with_builtins(output_transfer, reverse_z, vertex_constants);
+    let frag_specialization = Specialization::with_builtins(output_transfer, reverse_z, fragment_constants);
+    let vert_specialization_info = vert_specialization.info();
+    let frag_specialization_info = frag_specialization.info();
+
     let entry_name = CString::new("main").unwrap();
     let vert_stage_info = vk::PipelineShaderStageCreateInfo::builder()
         .stage(vk::ShaderStageFlags::VERTEX)
         .module(vert_module)
         .name(&entry_name)
+        .specialization_info(&vert_specialization_info)
         .build();
     let frag_stage_info = vk::PipelineShaderStageCreateInfo::builder()
         .stage(vk::ShaderStageFlags::FRAGMENT)
         .module(frag_module)
         .name(&entry_name)
+        .specialization_info(&frag_specialization_info)
         .build();
 
     let binding_descs = get_binding_descs(vertex_attributes, instance_attributes);
@@ -218,7 +531,7 @@ pub fn new_pipeline_and_layout(
         .build();
 
     let input_assembly_create_info = vk::PipelineInputAssemblyStateCreateInfo::builder()
-        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .topology(topology)
         .primitive_restart_enable(false)
         .build();
 
@@ -230,7 +543,7 @@ pub fn new_pipeline_and_layout(
         .rasterizer_discard_enable(false)
         .polygon_mode(vk::PolygonMode::FILL)
         .line_width(1.0)
-        .cull_mode(vk::CullModeFlags::BACK)
+        .cull_mode(cull_mode)
         .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
         .depth_bias_enable(false)
         .depth_bias_constant_factor(0.0)
@@ -246,17 +559,27 @@ pub fn new_pipeline_and_layout(
         .alpha_to_one_enable(false)
         .build();
 
+    let (src_color_blend_factor, dst_color_blend_factor) = match blend_mode {
+        BlendMode::Opaque => (vk::BlendFactor::ONE, vk::BlendFactor::ZERO),
+        BlendMode::Alpha => (vk::BlendFactor::SRC_ALPHA, vk::BlendFactor::ONE_MINUS_SRC_ALPHA),
+        BlendMode::Additive => (vk::BlendFactor::SRC_ALPHA, vk::BlendFactor::ONE),
+    };
+    // destination alpha is kept as is when blending
+    let (src_alpha_blend_factor, dst_alpha_blend_factor) = match blend_mode {
+        BlendMode::Opaque => (vk::BlendFactor::ONE, vk::BlendFactor::ZERO),
+        BlendMode::Alpha | BlendMode::Additive => (vk::BlendFactor::ZERO, vk::BlendFactor::ONE),
+    };
     let color_blend_attachment = vk::PipelineColorBlendAttachmentState::builder()
         .color_write_mask(vk::ColorComponentFlags::RGBA)
-        .blend_enable(false)
-        .src_color_blend_factor(vk::BlendFactor::ONE)
-        .dst_color_blend_factor(vk::BlendFactor::ZERO)
+        .blend_enable(blend_mode != BlendMode::Opaque)
+        .src_color_blend_factor(src_color_blend_factor)
+        .dst_color_blend_factor(dst_color_blend_factor)
         .color_blend_op(vk::BlendOp::ADD)
-        .src_alpha_blend_factor(vk::BlendFactor::ONE)
-        .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
+        .src_alpha_blend_factor(src_alpha_blend_factor)
+        .dst_alpha_blend_factor(dst_alpha_blend_factor)
         .alpha_blend_op(vk::BlendOp::ADD)
         .build();
-    let color_blend_attachments = [color_blend_attachment];
+    let color_blend_attachments = vec![color_blend_attachment; color_attachment_count as usize];
 
     let color_blending_info = vk::PipelineColorBlendStateCreateInfo::builder()
         .logic_op_enable(false)
@@ -265,32 +588,40 @@ pub fn new_pipeline_and_layout(
         .blend_constants([0.0, 0.0, 0.0, 0.0])
         .build();
 
+    let stencil_op_state = stencil.map(StencilState::to_vk).unwrap_or_default();
     let depth_stencil_info = vk::PipelineDepthStencilStateCreateInfo::builder()
-        .depth_test_enable(true)
-        .depth_write_enable(true)
-        .depth_compare_op(vk::CompareOp::LESS)
+        .depth_test_enable(depth_test)
+        .depth_write_enable(depth_write)
+        .depth_compare_op(if reverse_z { vk::CompareOp::GREATER } else { vk::CompareOp::LESS })
         .depth_bounds_test_enable(false)
         .min_depth_bounds(0.0)
         .max_depth_bounds(1.0)
-        .stencil_test_enable(false)
-        .front(Default::default())
-        .back(Default::default())
+        .stencil_test_enable(stencil.is_some())
+        .front(stencil_op_state)
+        .back(stencil_op_state)
         .build();
 
     let layout = {
         let layout_info = vk::PipelineLayoutCreateInfo::builder()
-            .set_layouts(&[
-                ubo_set_layout,
-                // textures_set_layout,
-            ])
+            .set_layouts(set_layouts)
+            .push_constant_ranges(push_constant_ranges)
             .build();
 
         unsafe { device.create_pipeline_layout(&layout_info, None).unwrap() }
     };
 
-    let info = vk::GraphicsPipelineCreateInfo::builder()
+    let mut rendering_info = vk::PipelineRenderingCreateInfo::builder()
+        .color_attachment_formats(color_formats)
+        .depth_attachment_format(depth_format)
+        .stencil_attachment_format(stencil_attachment_format(depth_format));
+
+    let stages = [vert_stage_info, frag_stage_info];
+    let mut info = vk::GraphicsPipelineCreateInfo::builder()
+        .flags(derivative.flags())
+        .base_pipeline_handle(derivative.base_pipeline())
+        .base_pipeline_index(-1)
         .dynamic_state(&dynamic_state_info)
-        .stages(&[vert_stage_info, frag_stage_info])
+        .stages(&stages)
         .vertex_input_state(&vertex_input_create_info)
         .input_assembly_state(&input_assembly_create_info)
         .viewport_state(&viewport_create_info)
@@ -300,8 +631,11 @@ pub fn new_pipeline_and_layout(
         .color_blend_state(&color_blending_info)
         .layout(layout)
         .render_pass(render_pass)
-        .subpass(0) // what does this do?!
-        .build();
+        .subpass(subpass);
+    if render_pass == vk::RenderPass::null() {
+        info = info.push_next(&mut rendering_info);
+    }
+    let info = info.build();
     let pipeline = unsafe {
         device
             .create_graphics_pipelines(vk::PipelineCache::null(), &[info], None)
@@ -315,3 +649,69 @@ pub fn new_pipeline_and_layout(
 
     (pipeline, layout)
 }
+
+pub fn new_compute_pipeline_and_layout(
+    device: &ash::Device,
+    shader_compiler: &ShaderCompiler,
+    shader_path: &str,
+    set_layouts: &[vk::DescriptorSetLayout],
+    push_constant_ranges: &[vk::PushConstantRange],
+    constants: &[(u32, Constant)],
+) -> (vk::Pipeline, vk::PipelineLayout) {
+    let module = new_shader_module(
+        device,
+        shader_compiler,
+        shader_path,
+        ShaderStage::Compute,
+    );
+
+    let specialization = Specialization::new(constants);
+    let specialization_info = specialization.info();
+    let entry_name = CString::new("main").unwrap();
+    let stage_info = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .module(module)
+        .name(&entry_name)
+        .specialization_info(&specialization_info)
+        .build();
+
+    let layout = {
+        let layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(set_layouts)
+            .push_constant_ranges(push_constant_ranges)
+            .build();
+
+        unsafe { device.create_pipeline_layout(&layout_info, None).unwrap() }
+    };
+
+    let info = vk::ComputePipelineCreateInfo::builder()
+        .stage(stage_info)
+        .layout(layout)
+        .build();
+    let pipeline = unsafe {
+        device
+            .create_compute_pipelines(vk::PipelineCache::null(), &[info], None)
+            .unwrap()[0]
+    };
+
+    unsafe {
+        device.destroy_shader_module(module, None);
+    }
+
+    (pipeline, layout)
+}
+
+#[test]
+fn test_specialization() {
+    let specialization = Specialization::with_builtins(
+        OutputTransfer::None,
+        true,
+        &[(FIRST_CUSTOM_CONSTANT_ID, Constant::U32(8)), (5, Constant::F32(0.5))],
+    );
+    let ids: Vec<u32> = specialization.entries.iter().map(|entry| entry.constant_id).collect();
+    let offsets: Vec<u32> = specialization.entries.iter().map(|entry| entry.offset).collect();
+    assert!(ids == [OUTPUT_TRANSFER_CONSTANT_ID, REVERSE_Z_CONSTANT_ID, FIRST_CUSTOM_CONSTANT_ID, 5]);
+    assert!(offsets == [0, 4, 8, 12] && specialization.entries.iter().all(|entry| entry.size == 4));
+    // booleans are 32 bit
+    assert!(specialization.data[4..8] == 1u32.to_ne_bytes() && specialization.data[12..] == 0.5f32.to_ne_bytes());
+}
@@ -0,0 +1,480 @@
+// GPU rain and snow. Particles live in a box following the camera, a compute pre-pass
+// moves them with gravity and wind and fades out the ones that fell behind the previous
+// frame's depth buffer, i.e. hit something. They're then drawn as camera facing streaks
+// after the opaque geometry of the scene pass
+
+use std::{mem::size_of, rc::Rc, time::Instant};
+
+use ash::vk;
+
+use crate::{math::{Mat, Vector}, weather::{Precipitation, Weather}};
+use super::{buffer::Buffer, descriptor::DescriptorWriteBatcher, pipeline, shader};
+
+pub const MAX_PRECIPITATION_PARTICLES: u32 = 0x8000;
+
+const WORKGROUP_SIZE: u32 = 64;
+/// longer frames are simulated as this long so hitches don't teleport particles
+const MAX_TIME_STEP: f32 = 0.1;
+
+/// must match Particle in precipitation.comp
+#[repr(C)]
+struct Particle {
+    /// w is 1 while falling, fades to 0 after a hit
+    _position: [f32; 4],
+    /// w is a per particle random value
+    _velocity: [f32; 4],
+}
+
+const FLAG_RESET: u32 = 1;
+const FLAG_DEPTH_VALID: u32 = 2;
+const FLAG_REVERSE_Z: u32 = 4;
+
+/// must match the push constant block in precipitation.comp
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct SimulatePushConstants {
+    /// the depth buffer read for collisions was drawn with it
+    prev_proj_view: Mat,
+    camera_position: [f32; 4],
+    /// xyz wind velocity, w the time step
+    wind: [f32; 4],
+    fall_speed: f32,
+    particle_count: u32,
+    /// varies respawn positions between frames
+    seed: u32,
+    flags: u32,
+}
+
+/// must match the push constant blocks in precipitation.vert and precipitation.frag
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct DrawPushConstants {
+    color: [f32; 4],
+    half_width: f32,
+    /// streaks are as long as the distance fallen in this time
+    streak_secs: f32,
+}
+
+const DRAW_PUSH_CONSTANT_RANGE: vk::PushConstantRange = vk::PushConstantRange {
+    stage_flags: vk::ShaderStageFlags::from_raw(
+        vk::ShaderStageFlags::VERTEX.as_raw() | vk::ShaderStageFlags::FRAGMENT.as_raw()
+    ),
+    offset: 0,
+    size: size_of::<DrawPushConstants>() as u32,
+};
+
+/// fall speed and look of each kind of precipitation
+fn precipitation_style(precipitation: Precipitation) -> (f32, DrawPushConstants) {
+    match precipitation {
+        Precipitation::None => (0.0, DrawPushConstants::default()),
+        Precipitation::Rain => (9.0, DrawPushConstants {
+            color: [0.7, 0.75, 0.8, 0.35],
+            half_width: 0.008,
+            streak_secs: 0.04,
+        }),
+        Precipitation::Snow => (1.0, DrawPushConstants {
+            color: [1.0, 1.0, 1.0, 0.8],
+            half_width: 0.03,
+            streak_secs: 0.0,
+        }),
+    }
+}
+
+/// Call `build` once per frame, `cmd_dispatch` before the scene pass and `cmd_draw` inside it.
+/// The draw pipeline depends on the scene render pass, `renew_pipeline` when it changes
+pub struct PrecipitationSystem {
+    device: Rc<ash::Device>,
+
+    /// simulated in place, shared by the frames in flight
+    particle_buffer: Buffer,
+    depth_sampler: vk::Sampler,
+
+    descriptor_pool: vk::DescriptorPool,
+    set_layout: vk::DescriptorSetLayout,
+    set: vk::DescriptorSet,
+    simulate_pipeline_layout: vk::PipelineLayout,
+    simulate_pipeline: vk::Pipeline,
+    draw_pipeline_layout: vk::PipelineLayout,
+    draw_pipeline: vk::Pipeline,
+
+    precipitation: Precipitation,
+    simulate_push_constants: SimulatePushConstants,
+    draw_push_constants: DrawPushConstants,
+    prev_proj_view: Mat,
+    /// the depth buffer holds a frame drawn since it was created
+    depth_valid: bool,
+    reverse_z: bool,
+    frame_count: u32,
+    last_build: Option<Instant>,
+}
+
+impl PrecipitationSystem {
+    pub fn new(
+        device: Rc<ash::Device>,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        shader_compiler: &shader::ShaderCompiler,
+        write_batcher: &mut DescriptorWriteBatcher,
+    ) -> Self {
+        let particle_buffer = Buffer::new(
+            (MAX_PRECIPITATION_PARTICLES as usize * size_of::<Particle>()) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            device.clone(),
+            physical_device_memory_properties,
+        );
+
+        let depth_sampler = unsafe {
+            let info = vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::NEAREST)
+                .min_filter(vk::Filter::NEAREST)
+                .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .max_lod(0.0);
+            device.create_sampler(&info, None).unwrap()
+        };
+
+        let set_layout_bindings = [
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE | vk::ShaderStageFlags::VERTEX)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(1)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build(),
+        ];
+        let set_layout = unsafe {
+            let info = vk::DescriptorSetLayoutCreateInfo::builder()
+                .bindings(&set_layout_bindings);
+            device.create_descriptor_set_layout(&info, None).unwrap()
+        };
+
+        let descriptor_pool = unsafe {
+            let pool_sizes = [
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_BUFFER,
+                    descriptor_count: 1,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    descriptor_count: 1,
+                },
+            ];
+            let info = vk::DescriptorPoolCreateInfo::builder()
+                .max_sets(1)
+                .pool_sizes(&pool_sizes);
+            device.create_descriptor_pool(&info, None).expect("Failed to create descriptor pool")
+        };
+
+        let set = unsafe {
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(descriptor_pool)
+                .set_layouts(&[set_layout])
+                .build();
+            device.allocate_descriptor_sets(&alloc_info).unwrap()[0]
+        };
+        write_batcher.queue_buffer_write(
+            set,
+            0,
+            0,
+            vk::DescriptorType::STORAGE_BUFFER,
+            vk::DescriptorBufferInfo {
+                buffer: particle_buffer.handle,
+                offset: 0,
+                range: particle_buffer.size,
+            },
+        );
+
+        let (simulate_pipeline, simulate_pipeline_layout) = pipeline::new_compute_pipeline_and_layout(
+            &device,
+            shader_compiler,
+            "shaders/precipitation.comp",
+            &[set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                offset: 0,
+                size: size_of::<SimulatePushConstants>() as u32,
+            }],
+            &[],
+        );
+
+        Self {
+            device,
+
+            particle_buffer,
+            depth_sampler,
+
+            descriptor_pool,
+            set_layout,
+            set,
+            simulate_pipeline_layout,
+            simulate_pipeline,
+            draw_pipeline_layout: vk::PipelineLayout::null(),
+            draw_pipeline: vk::Pipeline::null(),
+
+            precipitation: Precipitation::None,
+            simulate_push_constants: SimulatePushConstants::default(),
+            draw_push_constants: DrawPushConstants::default(),
+            prev_proj_view: Mat::default(),
+            depth_valid: false,
+            reverse_z: false,
+            frame_count: 0,
+            last_build: None,
+        }
+    }
+
+    /// `render_pass` null for dynamic rendering, draws in the translucent subpass with depth attached
+    pub fn renew_pipeline(
+        &mut self,
+        context: &pipeline::PassContext,
+        render_pass: vk::RenderPass,
+    ) {
+        let pipeline::PassContext { shader_compiler, color_format, depth_format, per_frame_ubo_set_layout, output_transfer, reverse_z, .. } = *context;
+        let subpass = context.translucent_subpass();
+        unsafe { self.destroy_draw_pipeline(); }
+        // the depth buffer was drawn the other way round
+        if reverse_z != self.reverse_z {
+            self.reverse_z = reverse_z;
+            self.depth_valid = false;
+        }
+
+        (self.draw_pipeline, self.draw_pipeline_layout) = pipeline::new_pipeline_and_layout(
+            &self.device,
+            shader_compiler,
+            &pipeline::PipelineDesc {
+                render_pass,
+                subpass,
+                color_formats: &[color_format],
+                depth_format,
+                set_layouts: &[per_frame_ubo_set_layout, self.set_layout],
+                push_constant_ranges: &[DRAW_PUSH_CONSTANT_RANGE],
+                vertex_shader_path: "shaders/precipitation.vert",
+                fragment_shader_path: "shaders/precipitation.frag",
+                blend_mode: pipeline::BlendMode::Alpha,
+                cull_mode: vk::CullModeFlags::NONE,
+                depth_write: false,
+                output_transfer,
+                reverse_z,
+                ..Default::default()
+            },
+        );
+    }
+
+    /// the depth view changes with the swapchain, its contents are only read after a frame is drawn
+    pub fn set_depth_view(&mut self, write_batcher: &mut DescriptorWriteBatcher, depth_view: vk::ImageView) {
+        write_batcher.queue_image_write(
+            self.set,
+            1,
+            0,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            vk::DescriptorImageInfo {
+                sampler: self.depth_sampler,
+                image_view: depth_view,
+                image_layout: vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            },
+        );
+        self.depth_valid = false;
+    }
+
+    fn particle_count(&self) -> u32 {
+        self.simulate_push_constants.particle_count
+    }
+
+    /// prepares this frame's simulation step, `proj_view` is the one the frame is drawn with
+    pub fn build(&mut self, weather: &Weather, time: f32, camera_position: Vector, proj_view: Mat) {
+        let now = Instant::now();
+        let dt = match self.last_build {
+            Some(last_build) => (now - last_build).as_secs_f32().min(MAX_TIME_STEP),
+            None => 0.0,
+        };
+        self.last_build = Some(now);
+
+        let (fall_speed, draw_push_constants) = precipitation_style(weather.precipitation);
+        let particle_count = match weather.precipitation {
+            Precipitation::None => 0,
+            _ => (weather.intensity.clamp(0.0, 1.0) * MAX_PRECIPITATION_PARTICLES as f32) as u32,
+        };
+
+        let mut flags = 0;
+        // particles of the old kind would keep falling at the wrong speed
+        if weather.precipitation != self.precipitation {
+            flags |= FLAG_RESET;
+        }
+        if self.depth_valid {
+            flags |= FLAG_DEPTH_VALID;
+        }
+        if self.reverse_z {
+            flags |= FLAG_REVERSE_Z;
+        }
+        self.precipitation = weather.precipitation;
+
+        let wind = weather.wind.velocity(time);
+        self.simulate_push_constants = SimulatePushConstants {
+            prev_proj_view: self.prev_proj_view,
+            camera_position: [camera_position.x, camera_position.y, camera_position.z, 1.0],
+            wind: [wind.x, wind.y, wind.z, dt],
+            fall_speed,
+            particle_count,
+            seed: self.frame_count,
+            flags,
+        };
+        self.draw_push_constants = draw_push_constants;
+
+        self.prev_proj_view = proj_view;
+        self.depth_valid = true;
+        self.frame_count = self.frame_count.wrapping_add(1);
+    }
+
+    /// Record outside of any render pass, before the scene pass.
+    /// `depth_image` holds the previous frame's depth and is left in attachment layout
+    pub fn cmd_dispatch(&self, command_buffer: vk::CommandBuffer, depth_image: vk::Image, depth_format: vk::Format) {
+        if self.particle_count() == 0 {
+            return;
+        }
+
+        let depth_subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: super::image::get_depth_aspect_mask(depth_format),
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+
+        unsafe {
+            // the previous frame's depth writes and particle reads must finish first
+            let depth_barrier = vk::ImageMemoryBarrier::builder()
+                .old_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                .new_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
+                .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(depth_image)
+                .subresource_range(depth_subresource_range)
+                .build();
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::LATE_FRAGMENT_TESTS | vk::PipelineStageFlags::VERTEX_SHADER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[depth_barrier],
+            );
+
+            self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, self.simulate_pipeline);
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.simulate_pipeline_layout,
+                0,
+                &[self.set],
+                &[],
+            );
+            self.device.cmd_push_constants(
+                command_buffer,
+                self.simulate_pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                std::slice::from_raw_parts(
+                    &self.simulate_push_constants as *const SimulatePushConstants as *const u8,
+                    size_of::<SimulatePushConstants>(),
+                ),
+            );
+            self.device.cmd_dispatch(command_buffer, self.particle_count().div_ceil(WORKGROUP_SIZE), 1, 1);
+
+            let particle_barrier = vk::BufferMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .buffer(self.particle_buffer.handle)
+                .offset(0)
+                .size(vk::WHOLE_SIZE)
+                .build();
+            let depth_barrier = vk::ImageMemoryBarrier::builder()
+                .old_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
+                .new_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                .src_access_mask(vk::AccessFlags::empty())
+                .dst_access_mask(
+                    vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+                )
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(depth_image)
+                .subresource_range(depth_subresource_range)
+                .build();
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::VERTEX_SHADER | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[particle_barrier],
+                &[depth_barrier],
+            );
+        }
+    }
+
+    /// record in the scene pass after the opaque geometry, binds its own pipeline
+    pub fn cmd_draw(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        per_frame_ubo_set: vk::DescriptorSet,
+        per_frame_ubo_offset: u32,
+    ) {
+        if self.particle_count() == 0 {
+            return;
+        }
+
+        unsafe {
+            self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.draw_pipeline);
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.draw_pipeline_layout,
+                0,
+                &[per_frame_ubo_set, self.set],
+                &[per_frame_ubo_offset],
+            );
+            self.device.cmd_push_constants(
+                command_buffer,
+                self.draw_pipeline_layout,
+                DRAW_PUSH_CONSTANT_RANGE.stage_flags,
+                0,
+                std::slice::from_raw_parts(
+                    &self.draw_push_constants as *const DrawPushConstants as *const u8,
+                    size_of::<DrawPushConstants>(),
+                ),
+            );
+            // two triangles per particle, positions come from the particle buffer
+            self.device.cmd_draw(command_buffer, 6, self.particle_count(), 0, 0);
+        }
+    }
+
+    unsafe fn destroy_draw_pipeline(&mut self) {
+        if self.draw_pipeline != vk::Pipeline::null() {
+            self.device.destroy_pipeline(self.draw_pipeline, None);
+            self.device.destroy_pipeline_layout(self.draw_pipeline_layout, None);
+        }
+    }
+
+    /// # Safety
+    /// call once, after the device finished simulating and drawing the particles
+    pub unsafe fn destroy(&mut self) {
+        self.destroy_draw_pipeline();
+        self.device.destroy_pipeline(self.simulate_pipeline, None);
+        self.device.destroy_pipeline_layout(self.simulate_pipeline_layout, None);
+        self.device.destroy_descriptor_pool(self.descriptor_pool, None);
+        self.device.destroy_descriptor_set_layout(self.set_layout, None);
+        self.device.destroy_sampler(self.depth_sampler, None);
+
+        self.particle_buffer.destroy();
+    }
+}
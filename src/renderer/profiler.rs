@@ -0,0 +1,351 @@
+use std::{collections::HashMap, rc::Rc, sync::Mutex, time::Duration};
+
+use ash::vk;
+
+use crate::jobs::JobHooks;
+use super::{device::DeviceFeatures, MAX_FRAMES_IN_FLIGHT};
+
+const QUERIES_PER_FRAME: u32 = 2;
+
+/// whether the graphics queue's family can write timestamps, and the mask of their valid bits
+pub fn timestamp_mask(limits: &vk::PhysicalDeviceLimits, graphics_queue_family: &vk::QueueFamilyProperties) -> Option<u64> {
+    // the valid bits are what counts, timestamp_compute_and_graphics only guarantees they're nonzero
+    let valid_bits = graphics_queue_family.timestamp_valid_bits;
+    if valid_bits == 0 {
+        if limits.timestamp_compute_and_graphics == vk::TRUE {
+            log::warn!("Graphics queue reports no timestamp bits despite timestamp_compute_and_graphics");
+        }
+        return None;
+    }
+    Some(if valid_bits >= 64 { u64::MAX } else { (1 << valid_bits) - 1 })
+}
+
+/// Measures gpu time of each frame's graphics command buffer with timestamp queries.
+/// Results of a frame are read back once its fence has signaled.
+/// Records nothing when the graphics queue doesn't support timestamps, see `is_supported`
+pub struct GpuProfiler {
+    device: Rc<ash::Device>,
+    /// null when unsupported
+    query_pool: vk::QueryPool,
+    /// nanoseconds per timestamp tick
+    timestamp_period: f32,
+    /// of the timestamps' valid bits, the rest are undefined
+    timestamp_mask: u64,
+    frame_written: [bool; MAX_FRAMES_IN_FLIGHT],
+
+    /// most recent resolved gpu frame time
+    pub gpu_frame_time_ms: f32,
+}
+
+impl GpuProfiler {
+    pub fn new(
+        device: Rc<ash::Device>,
+        physical_device_properties: &vk::PhysicalDeviceProperties,
+        graphics_queue_family: &vk::QueueFamilyProperties,
+    ) -> Self {
+        let timestamp_mask = timestamp_mask(&physical_device_properties.limits, graphics_queue_family);
+        let query_pool = if timestamp_mask.is_some() {
+            let info = vk::QueryPoolCreateInfo::builder()
+                .query_type(vk::QueryType::TIMESTAMP)
+                .query_count(QUERIES_PER_FRAME * MAX_FRAMES_IN_FLIGHT as u32);
+            unsafe { device.create_query_pool(&info, None) }
+                .expect("Failed to create timestamp query pool")
+        } else {
+            log::info!("Timestamps are unsupported on the graphics queue, gpu frame times and auto quality are disabled");
+            vk::QueryPool::null()
+        };
+
+        Self {
+            device,
+            query_pool,
+            timestamp_period: physical_device_properties.limits.timestamp_period,
+            timestamp_mask: timestamp_mask.unwrap_or(0),
+            frame_written: [false; MAX_FRAMES_IN_FLIGHT],
+            gpu_frame_time_ms: 0.0,
+        }
+    }
+
+    pub fn is_supported(&self) -> bool {
+        self.query_pool != vk::QueryPool::null()
+    }
+
+    /// must be called after the frame's fence has signaled,
+    /// returns None if the frame was never recorded
+    pub fn read_frame_time(&mut self, frame: usize) -> Option<f32> {
+        if !self.frame_written[frame] {
+            return None;
+        }
+
+        let mut timestamps = [0_u64; QUERIES_PER_FRAME as usize];
+        let result = unsafe { self.device.get_query_pool_results(
+            self.query_pool,
+            frame as u32 * QUERIES_PER_FRAME,
+            QUERIES_PER_FRAME,
+            &mut timestamps,
+            vk::QueryResultFlags::TYPE_64,
+        ) };
+
+        match result {
+            Ok(()) => {
+                let ticks = timestamps[1].wrapping_sub(timestamps[0]) & self.timestamp_mask;
+                self.gpu_frame_time_ms = ticks as f32 * self.timestamp_period / 1_000_000.0;
+                Some(self.gpu_frame_time_ms)
+            }
+            Err(vk::Result::NOT_READY) => None,
+            Err(err) => panic!("Failed to read timestamps: {}", err),
+        }
+    }
+
+    /// record at the start of the frame's command buffer, outside of a render pass
+    pub fn cmd_begin_frame(&mut self, command_buffer: vk::CommandBuffer, frame: usize) {
+        if !self.is_supported() {
+            return;
+        }
+        let first_query = frame as u32 * QUERIES_PER_FRAME;
+        unsafe {
+            self.device.cmd_reset_query_pool(command_buffer, self.query_pool, first_query, QUERIES_PER_FRAME);
+            self.device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                self.query_pool,
+                first_query,
+            );
+        }
+    }
+
+    /// record at the end of the frame's command buffer, outside of a render pass
+    pub fn cmd_end_frame(&mut self, command_buffer: vk::CommandBuffer, frame: usize) {
+        if !self.is_supported() {
+            return;
+        }
+        unsafe {
+            self.device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                self.query_pool,
+                frame as u32 * QUERIES_PER_FRAME + 1,
+            );
+        }
+        self.frame_written[frame] = true;
+    }
+
+    // caller must ensure only called once
+    pub unsafe fn destroy(&mut self) {
+        if self.is_supported() {
+            self.device.destroy_query_pool(self.query_pool, None);
+        }
+    }
+}
+
+/// counters of one frame's graphics work, in the order of `PIPELINE_STATISTICS`
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct PipelineStatistics {
+    pub input_assembly_vertices: u64,
+    pub input_assembly_primitives: u64,
+    pub vertex_shader_invocations: u64,
+    pub clipping_invocations: u64,
+    /// primitives left after clipping
+    pub clipping_primitives: u64,
+    pub fragment_shader_invocations: u64,
+}
+
+/// queried counters, vulkan writes the results in flag bit order
+pub const PIPELINE_STATISTICS: vk::QueryPipelineStatisticFlags = vk::QueryPipelineStatisticFlags::from_raw(
+    vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_VERTICES.as_raw()
+        | vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_PRIMITIVES.as_raw()
+        | vk::QueryPipelineStatisticFlags::VERTEX_SHADER_INVOCATIONS.as_raw()
+        | vk::QueryPipelineStatisticFlags::CLIPPING_INVOCATIONS.as_raw()
+        | vk::QueryPipelineStatisticFlags::CLIPPING_PRIMITIVES.as_raw()
+        | vk::QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS.as_raw(),
+);
+
+impl PipelineStatistics {
+    /// shaded fragments per pixel, 1 when every pixel is shaded exactly once.
+    /// Early depth testing culls fragments before they count, so this estimates overdraw from below
+    pub fn overdraw(&self, pixel_count: u32) -> f32 {
+        self.fragment_shader_invocations as f32 / pixel_count.max(1) as f32
+    }
+}
+
+/// Counts each frame's graphics work with a pipeline statistics query over the whole frame.
+/// Only available when the device supports the queries and inheriting them into the
+/// secondary command buffers, enabled by default in debug builds
+pub struct PipelineStatisticsProfiler {
+    device: Rc<ash::Device>,
+    /// null when unsupported
+    query_pool: vk::QueryPool,
+    pub enabled: bool,
+    /// whether the frame's query was recorded, so there is something to read back
+    frame_written: [bool; MAX_FRAMES_IN_FLIGHT],
+
+    /// of the most recent frame read back
+    pub frame_statistics: Option<PipelineStatistics>,
+}
+
+impl PipelineStatisticsProfiler {
+    pub fn new(device: Rc<ash::Device>, features: &DeviceFeatures) -> Self {
+        let query_pool = if features.pipeline_statistics_query && features.inherited_queries {
+            let info = vk::QueryPoolCreateInfo::builder()
+                .query_type(vk::QueryType::PIPELINE_STATISTICS)
+                .pipeline_statistics(PIPELINE_STATISTICS)
+                .query_count(MAX_FRAMES_IN_FLIGHT as u32);
+            unsafe { device.create_query_pool(&info, None) }
+                .expect("Failed to create pipeline statistics query pool")
+        } else {
+            log::info!("Pipeline statistics are unsupported");
+            vk::QueryPool::null()
+        };
+
+        Self {
+            device,
+            enabled: cfg!(debug_assertions) && query_pool != vk::QueryPool::null(),
+            query_pool,
+            frame_written: [false; MAX_FRAMES_IN_FLIGHT],
+            frame_statistics: None,
+        }
+    }
+
+    pub fn is_supported(&self) -> bool {
+        self.query_pool != vk::QueryPool::null()
+    }
+
+    /// what secondary command buffers executed during the frame must inherit
+    pub fn get_inherited_statistics(&self) -> vk::QueryPipelineStatisticFlags {
+        if self.enabled { PIPELINE_STATISTICS } else { vk::QueryPipelineStatisticFlags::empty() }
+    }
+
+    /// must be called after the frame's fence has signaled
+    pub fn read_frame_statistics(&mut self, frame: usize) -> Option<PipelineStatistics> {
+        if !self.frame_written[frame] {
+            return None;
+        }
+        self.frame_written[frame] = false;
+
+        let mut statistics = [PipelineStatistics::default()];
+        let result = unsafe { self.device.get_query_pool_results(
+            self.query_pool,
+            frame as u32,
+            1,
+            &mut statistics,
+            vk::QueryResultFlags::TYPE_64,
+        ) };
+
+        match result {
+            Ok(()) => {
+                self.frame_statistics = Some(statistics[0]);
+                self.frame_statistics
+            }
+            Err(vk::Result::NOT_READY) => None,
+            Err(err) => panic!("Failed to read pipeline statistics: {}", err),
+        }
+    }
+
+    /// record before the frame's first draw, outside of a render pass
+    pub fn cmd_begin_frame(&mut self, command_buffer: vk::CommandBuffer, frame: usize) {
+        if !self.enabled {
+            return;
+        }
+        unsafe {
+            self.device.cmd_reset_query_pool(command_buffer, self.query_pool, frame as u32, 1);
+            self.device.cmd_begin_query(command_buffer, self.query_pool, frame as u32, vk::QueryControlFlags::empty());
+        }
+        self.frame_written[frame] = true;
+    }
+
+    /// record after the frame's last draw, outside of a render pass
+    pub fn cmd_end_frame(&mut self, command_buffer: vk::CommandBuffer, frame: usize) {
+        if !self.frame_written[frame] {
+            return;
+        }
+        unsafe {
+            self.device.cmd_end_query(command_buffer, self.query_pool, frame as u32);
+        }
+    }
+
+    // caller must ensure only called once
+    pub unsafe fn destroy(&mut self) {
+        if self.is_supported() {
+            self.device.destroy_query_pool(self.query_pool, None);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct JobTiming {
+    pub count: usize,
+    /// summed over threads, can exceed the frame's time
+    pub total: Duration,
+    pub longest: Duration,
+}
+
+/// Cpu time of the job system's jobs by name, hooked into the job system.
+/// Collects until `end_frame`, which makes them the last frame's timings
+#[derive(Default)]
+pub struct JobProfiler {
+    collecting: Mutex<HashMap<&'static str, JobTiming>>,
+    frame_timings: Mutex<Vec<(&'static str, JobTiming)>>,
+}
+
+impl JobHooks for JobProfiler {
+    fn job_finished(&self, name: &'static str, _: Option<usize>, elapsed: Duration) {
+        let mut collecting = self.collecting.lock().unwrap();
+        let timing = collecting.entry(name).or_default();
+        timing.count += 1;
+        timing.total += elapsed;
+        timing.longest = timing.longest.max(elapsed);
+    }
+}
+
+impl JobProfiler {
+    pub fn end_frame(&self) {
+        let mut frame_timings = self.frame_timings.lock().unwrap();
+        frame_timings.clear();
+        frame_timings.extend(self.collecting.lock().unwrap().drain());
+        frame_timings.sort_by_key(|&(name, _)| name);
+    }
+
+    /// by name
+    pub fn get_frame_timings(&self) -> Vec<(&'static str, JobTiming)> {
+        self.frame_timings.lock().unwrap().clone()
+    }
+}
+
+#[test]
+fn test_job_profiler() {
+    let profiler = JobProfiler::default();
+    profiler.job_finished("record", Some(0), Duration::from_millis(2));
+    profiler.job_finished("record", None, Duration::from_millis(3));
+    profiler.job_finished("decode", Some(1), Duration::from_millis(1));
+    profiler.end_frame();
+
+    let timings = profiler.get_frame_timings();
+    assert!(timings.len() == 2 && timings[0].0 == "decode");
+    let (_, record) = timings[1];
+    assert!(record.count == 2 && record.total == Duration::from_millis(5) && record.longest == Duration::from_millis(3));
+
+    profiler.end_frame();
+    assert!(profiler.get_frame_timings().is_empty());
+}
+
+#[test]
+fn test_pipeline_statistics_layout() {
+    // one u64 per queried counter, as vulkan writes them
+    use std::mem::size_of;
+    assert!(size_of::<PipelineStatistics>() == PIPELINE_STATISTICS.as_raw().count_ones() as usize * size_of::<u64>());
+    let statistics = PipelineStatistics { fragment_shader_invocations: 3 * 640 * 480, ..Default::default() };
+    assert!(statistics.overdraw(640 * 480) == 3.0);
+}
+
+#[test]
+fn test_timestamp_mask() {
+    let limits = vk::PhysicalDeviceLimits::default();
+    let family = |timestamp_valid_bits| vk::QueueFamilyProperties { timestamp_valid_bits, ..Default::default() };
+    assert!(timestamp_mask(&limits, &family(0)).is_none());
+    assert!(timestamp_mask(&limits, &family(36)) == Some(0xf_ffff_ffff));
+    assert!(timestamp_mask(&limits, &family(64)) == Some(u64::MAX));
+
+    // a wrapped counter still gives the ticks in between
+    let mask = timestamp_mask(&limits, &family(36)).unwrap();
+    assert!(2_u64.wrapping_sub(0xf_ffff_fffe) & mask == 4);
+}
@@ -0,0 +1,139 @@
+// Auto quality, steps the render scale down when the gpu can't hold the target frame time
+// and back up when there is headroom. Needs gpu frame times, so it stays off on devices
+// whose graphics queue can't write timestamps
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QualitySettings {
+    /// fraction of the swapchain resolution the scene is rendered at
+    pub render_scale: f32,
+}
+
+impl Default for QualitySettings {
+    fn default() -> Self {
+        Self {
+            render_scale: 1.0,
+        }
+    }
+}
+
+/// user defined range each knob is allowed to move in
+#[derive(Clone, Copy, Debug)]
+pub struct QualityBounds {
+    pub min: QualitySettings,
+    pub max: QualitySettings,
+}
+
+impl Default for QualityBounds {
+    fn default() -> Self {
+        Self {
+            min: QualitySettings {
+                render_scale: 0.5,
+            },
+            max: QualitySettings::default(),
+        }
+    }
+}
+
+const RENDER_SCALE_STEP: f32 = 0.1;
+
+pub struct AutoQuality {
+    pub enabled: bool,
+    pub target_frame_time_ms: f32,
+    pub bounds: QualityBounds,
+    pub settings: QualitySettings,
+
+    /// exponentially smoothed gpu frame time
+    pub average_frame_time_ms: f32,
+    /// frames to wait after a step so the average reflects the new settings
+    pub cooldown_frames: u32,
+    frames_since_step: u32,
+}
+
+impl AutoQuality {
+    pub fn new(target_fps: f32, bounds: QualityBounds) -> Self {
+        Self {
+            enabled: false,
+            target_frame_time_ms: 1000.0 / target_fps,
+            bounds,
+            settings: bounds.max,
+            average_frame_time_ms: 0.0,
+            cooldown_frames: 30,
+            frames_since_step: 0,
+        }
+    }
+
+    /// feeds a gpu frame time, returns wether the settings changed
+    pub fn update(&mut self, gpu_frame_time_ms: f32) -> bool {
+        const SMOOTHING: f32 = 0.1;
+
+        if self.average_frame_time_ms == 0.0 {
+            self.average_frame_time_ms = gpu_frame_time_ms;
+        } else {
+            self.average_frame_time_ms += (gpu_frame_time_ms - self.average_frame_time_ms) * SMOOTHING;
+        }
+
+        self.frames_since_step += 1;
+        if !self.enabled || self.frames_since_step < self.cooldown_frames {
+            return false;
+        }
+
+        // hysteresis band so the settings don't oscillate around the target
+        let changed = if self.average_frame_time_ms > self.target_frame_time_ms * 1.05 {
+            self.step_down()
+        } else if self.average_frame_time_ms < self.target_frame_time_ms * 0.8 {
+            self.step_up()
+        } else {
+            false
+        };
+
+        if changed {
+            self.frames_since_step = 0;
+            log::debug!("Auto quality: {:.2}ms -> {:?}", self.average_frame_time_ms, self.settings);
+        }
+        changed
+    }
+
+    fn step_down(&mut self) -> bool {
+        let min = &self.bounds.min;
+        let settings = &mut self.settings;
+
+        if settings.render_scale > min.render_scale + 0.01 {
+            settings.render_scale = (settings.render_scale - RENDER_SCALE_STEP).max(min.render_scale);
+        } else {
+            return false;
+        }
+        true
+    }
+
+    fn step_up(&mut self) -> bool {
+        let max = &self.bounds.max;
+        let settings = &mut self.settings;
+
+        if settings.render_scale < max.render_scale - 0.01 {
+            settings.render_scale = (settings.render_scale + RENDER_SCALE_STEP).min(max.render_scale);
+        } else {
+            return false;
+        }
+        true
+    }
+}
+
+#[test]
+fn test_auto_quality_stays_in_bounds() {
+    let bounds = QualityBounds::default();
+    let mut auto_quality = AutoQuality::new(60.0, bounds);
+    auto_quality.enabled = true;
+    auto_quality.cooldown_frames = 1;
+
+    // way over budget, the render scale should bottom out at the minimum
+    for _ in 0..100 {
+        auto_quality.update(100.0);
+    }
+    assert!(auto_quality.settings.render_scale == bounds.min.render_scale);
+
+    // way under budget, it should climb back to the maximum
+    for _ in 0..200 {
+        auto_quality.update(1.0);
+    }
+    assert!(auto_quality.settings.render_scale == bounds.max.render_scale);
+}
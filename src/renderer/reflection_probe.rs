@@ -0,0 +1,885 @@
+// Reflection probes, local stand ins for the environment's prefiltered cubemap. A probe renders the
+// batched draws around its position into a cubemap, which is prefiltered like the environment's
+// (see ibl.rs) into the probe's slot of a cubemap array. Pbr materials reflect the probe nearest to
+// the object's origin among those whose box holds it, with the reflection ray projected onto the box
+// so nearby reflections line up with the scene, and the environment outside every box:
+//
+//     let probe = app.add_reflection_probe(ReflectionProbe {
+//         position: Vector::new(0.0, -2.0, 0.0),
+//         box_min: Vector::new(-10.0, -6.0, -10.0),
+//         box_max: Vector::new(10.0, 0.0, 10.0),
+//     }).unwrap();
+//     // after moving things around the probe
+//     app.reflection_probes.recapture(probe);
+//
+// A probe shows the environment until it is captured, during the frame after it was added,
+// one capture per frame. Captures are forward shaded like render targets, so they hold low dynamic
+// range colors without skinned meshes or terrain. Screen space reflections draw over them, see ssr.rs
+
+use std::{collections::VecDeque, mem::size_of, rc::Rc};
+
+use ash::vk;
+
+use crate::{
+    data_structures::handle_map::{Handle, HandleMap},
+    geometry::{self, GeometrySystem},
+    math::{ModelMat, Vector},
+};
+use super::{
+    batch::DrawBatcher,
+    buffer::Buffer,
+    descriptor::{DescriptorWriteBatcher, PerFrameUBO},
+    ibl::{self, PrefilterPushConstants, PREFILTERED_MIP_LEVELS},
+    image,
+    material::{self, MaterialSystem},
+    pipeline,
+    render_pass,
+    shader,
+    swapchain::OutputTransfer,
+    uniform_ring::UniformRing,
+};
+
+/// must match shaders/pbr.frag
+pub const MAX_REFLECTION_PROBES: usize = 8;
+/// of the probes' uniform buffer in the environment set, see `ibl::new_ibl_set_layout`
+pub const PROBES_BINDING: u32 = 3;
+/// of the probes' cubemap array in the environment set
+pub const PROBE_CUBES_BINDING: u32 = 4;
+/// side of a capture and of the first mip of a probe's cubemap,
+/// like the prefiltered cubemap probes start out as a copy of
+pub const PROBE_SIZE: u32 = ibl::PREFILTERED_SIZE;
+/// gives the 90 degree field of view of a cube face at aspect ratio 1, see `ModelMat::project`
+const CAPTURE_NEAR_Z: f32 = 0.5;
+pub const CAPTURE_FAR_Z: f32 = 1000.0;
+
+/// view x, y and z axes of each cube face in +x -x +y -y +z -z order, must match cubeDirection in ibl.glsl
+const FACE_AXES: [[[f32; 3]; 3]; 6] = [
+    [[0.0, 0.0, -1.0], [0.0, -1.0, 0.0], [1.0, 0.0, 0.0]],
+    [[0.0, 0.0, 1.0], [0.0, -1.0, 0.0], [-1.0, 0.0, 0.0]],
+    [[1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]],
+    [[1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, -1.0, 0.0]],
+    [[1.0, 0.0, 0.0], [0.0, -1.0, 0.0], [0.0, 0.0, 1.0]],
+    [[-1.0, 0.0, 0.0], [0.0, -1.0, 0.0], [0.0, 0.0, -1.0]],
+];
+
+/// world to view space of the cube face `face` seen from `eye`
+pub fn face_view(face: usize, eye: Vector) -> ModelMat {
+    let [right, down, forward] = FACE_AXES[face].map(|[x, y, z]| Vector::new(x, y, z));
+    ModelMat::look_along(eye, right, down, forward)
+}
+
+pub type ReflectionProbeId = Handle<ReflectionProbe>;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ReflectionProbe {
+    /// captured from
+    pub position: Vector,
+    /// objects whose origin lies in the box reflect the probe, reflections are projected onto it
+    pub box_min: Vector,
+    pub box_max: Vector,
+}
+
+impl ReflectionProbe {
+    pub fn contains(&self, point: Vector) -> bool {
+        point.x >= self.box_min.x && point.x <= self.box_max.x
+            && point.y >= self.box_min.y && point.y <= self.box_max.y
+            && point.z >= self.box_min.z && point.z <= self.box_max.z
+    }
+
+    /// direction from the probe to where the ray from `position` along `direction` leaves the box,
+    /// mirrors boxProject in shaders/pbr.frag
+    pub fn box_project(&self, direction: Vector, position: Vector) -> Vector {
+        let exit = |min: f32, max: f32, position: f32, direction: f32| {
+            ((max - position) / direction).max((min - position) / direction)
+        };
+        let distance = exit(self.box_min.x, self.box_max.x, position.x, direction.x)
+            .min(exit(self.box_min.y, self.box_max.y, position.y, direction.y))
+            .min(exit(self.box_min.z, self.box_max.z, position.z, direction.z))
+            .max(0.0);
+        Vector::new(
+            position.x + direction.x * distance - self.position.x,
+            position.y + direction.y * distance - self.position.y,
+            position.z + direction.z * distance - self.position.z,
+        )
+    }
+}
+
+/// index into `probes` of the probe nearest to `origin` among those whose box holds it,
+/// mirrors nearestProbe in shaders/pbr.frag
+pub fn nearest_probe(probes: &[ReflectionProbe], origin: Vector) -> Option<usize> {
+    probes
+        .iter()
+        .enumerate()
+        .filter(|(_, probe)| probe.contains(origin))
+        .min_by(|(_, a), (_, b)| {
+            let distance_sqr = |probe: &ReflectionProbe| (origin - probe.position).norm_sqr();
+            distance_sqr(a).total_cmp(&distance_sqr(b))
+        })
+        .map(|(index, _)| index)
+}
+
+/// must match ReflectionProbe in shaders/pbr.frag
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct ShaderProbe {
+    /// cube index into the cubemap array in w
+    position: [f32; 4],
+    box_min: [f32; 4],
+    box_max: [f32; 4],
+}
+
+/// must match the ReflectionProbes block in shaders/pbr.frag
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct ProbesUBO {
+    count: u32,
+    _pad: [u32; 3],
+    probes: [ShaderProbe; MAX_REFLECTION_PROBES],
+}
+
+/// Call `init_environment_set` once before the environment set is bound, `build` once per frame,
+/// `push_view_ubos` while the uniform buffer is written and `cmd_capture` before the scene pass.
+/// Adding and removing probes needs the device idle
+pub struct ReflectionProbeSystem {
+    device: Rc<ash::Device>,
+    probes: HandleMap<ReflectionProbe>,
+    /// captured one per frame, front first
+    pending: VecDeque<ReflectionProbeId>,
+    /// captured this frame
+    capturing: Option<ReflectionProbeId>,
+    clear_color: [f32; 4],
+    face_ubo_offsets: [u32; 6],
+    probes_buffer: Buffer,
+
+    /// a cube of `MAX_REFLECTION_PROBES` in slot order, with every prefiltered mip
+    cube_array: (vk::Image, vk::DeviceMemory, vk::ImageView),
+    /// trilinear, like the environment's
+    sampler: vk::Sampler,
+    /// a 2d array view of each probe's mips, slot major
+    storage_views: Vec<vk::ImageView>,
+
+    capture_color: (vk::Image, vk::DeviceMemory, vk::ImageView),
+    capture_depth: (vk::Image, vk::DeviceMemory, vk::ImageView),
+    /// one per face
+    capture_face_views: Vec<vk::ImageView>,
+    framebuffers: Vec<vk::Framebuffer>,
+    render_pass: vk::RenderPass,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+
+    descriptor_pool: vk::DescriptorPool,
+    prefilter_set_layout: vk::DescriptorSetLayout,
+    /// sample the capture, write a mip of a probe, in the order of `storage_views`
+    prefilter_sets: Vec<vk::DescriptorSet>,
+    prefilter_pipeline_layout: vk::PipelineLayout,
+    prefilter_pipeline: vk::Pipeline,
+}
+
+impl ReflectionProbeSystem {
+    pub fn new(
+        device: Rc<ash::Device>,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        shader_compiler: &shader::ShaderCompiler,
+        depth_format: vk::Format,
+        per_frame_ubo_set_layout: vk::DescriptorSetLayout,
+        textures_set_layout: vk::DescriptorSetLayout,
+    ) -> Self {
+        let probes_buffer = Buffer::new(
+            size_of::<ProbesUBO>() as vk::DeviceSize,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            device.clone(),
+            physical_device_memory_properties,
+        );
+
+        let cube_array = {
+            let (cube_image, memory) = image::new_cube_array_image_and_memory(
+                &device,
+                physical_device_memory_properties,
+                PROBE_SIZE,
+                PREFILTERED_MIP_LEVELS,
+                MAX_REFLECTION_PROBES as u32,
+                vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+                ibl::FORMAT,
+            );
+            let view = image::new_image_view_of_range(
+                &device,
+                cube_image,
+                vk::ImageViewType::CUBE_ARRAY,
+                ibl::FORMAT,
+                slot_range(0, MAX_REFLECTION_PROBES as u32),
+            );
+            (cube_image, memory, view)
+        };
+        let storage_views = (0..MAX_REFLECTION_PROBES as u32)
+            .flat_map(|slot| (0..PREFILTERED_MIP_LEVELS).map(move |mip| (slot, mip)))
+            .map(|(slot, mip)| image::new_image_view_of_range(
+                &device,
+                cube_array.0,
+                vk::ImageViewType::TYPE_2D_ARRAY,
+                ibl::FORMAT,
+                vk::ImageSubresourceRange {
+                    base_mip_level: mip,
+                    level_count: 1,
+                    ..slot_range(slot, 1)
+                },
+            ))
+            .collect();
+        let sampler = unsafe {
+            let info = vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::LINEAR)
+                .min_filter(vk::Filter::LINEAR)
+                .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .max_lod(PREFILTERED_MIP_LEVELS as f32);
+            device.create_sampler(&info, None).unwrap()
+        };
+
+        let capture_color = {
+            let (cube_image, memory) = image::new_cube_image_and_memory(
+                &device,
+                physical_device_memory_properties,
+                PROBE_SIZE,
+                1,
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                ibl::FORMAT,
+            );
+            let view = image::new_cube_image_view(&device, cube_image, vk::ImageViewType::CUBE, ibl::FORMAT, 0, 1);
+            (cube_image, memory, view)
+        };
+        let capture_face_views: Vec<vk::ImageView> = (0..6)
+            .map(|face| image::new_image_view_of_range(
+                &device,
+                capture_color.0,
+                vk::ImageViewType::TYPE_2D,
+                ibl::FORMAT,
+                vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: 1,
+                    base_array_layer: face,
+                    layer_count: 1,
+                },
+            ))
+            .collect();
+        let capture_depth = {
+            let (depth_image, memory) = image::new_image_and_memory(
+                &device,
+                physical_device_memory_properties,
+                &image::ImageDesc {
+                    width: PROBE_SIZE,
+                    height: PROBE_SIZE,
+                    usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+                    format: depth_format,
+                    ..Default::default()
+                },
+            );
+            let view = image::new_image_view(
+                &device,
+                depth_image,
+                depth_format,
+                image::get_depth_aspect_mask(depth_format),
+                1,
+            );
+            (depth_image, memory, view)
+        };
+
+        // sampled by the prefilter right after
+        let render_pass = render_pass::new_render_pass(
+            &device,
+            ibl::FORMAT,
+            depth_format,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            &render_pass::ClearConfig::default(),
+        );
+        let framebuffers = capture_face_views
+            .iter()
+            .map(|&face_view| unsafe {
+                let attachments = [face_view, capture_depth.2];
+                let info = vk::FramebufferCreateInfo::builder()
+                    .render_pass(render_pass)
+                    .attachments(&attachments)
+                    .width(PROBE_SIZE)
+                    .height(PROBE_SIZE)
+                    .layers(1);
+                device.create_framebuffer(&info, None).unwrap()
+            })
+            .collect();
+
+        // forward shaded whatever the scene's render path, decoded to linear for the prefilter.
+        // Cube faces are mirrored views, which flips the winding
+        let (pipeline, pipeline_layout) = pipeline::new_pipeline_and_layout(
+            &device,
+            shader_compiler,
+            &pipeline::PipelineDesc {
+                render_pass,
+                color_formats: &[ibl::FORMAT],
+                depth_format,
+                set_layouts: &[per_frame_ubo_set_layout, textures_set_layout],
+                push_constant_ranges: &[material::MaterialPushConstants::RANGE],
+                vertex_shader_path: "shaders/foo.vert",
+                fragment_shader_path: "shaders/foo.frag",
+                vertex_attributes: &geometry::VERTEX_ATTRIBUTES,
+                instance_attributes: &geometry::INSTANCE_ATTRIBUTES,
+                cull_mode: vk::CullModeFlags::NONE,
+                output_transfer: OutputTransfer::Srgb,
+                ..Default::default()
+            },
+        );
+
+        // like the prefilter's in `ibl::Environment::convolve`
+        let prefilter_set_layout = unsafe {
+            let bindings = [
+                (0, vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+                (1, vk::DescriptorType::STORAGE_IMAGE),
+            ].map(|(binding, ty)| vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_type(ty)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build()
+            );
+            let info = vk::DescriptorSetLayoutCreateInfo::builder()
+                .bindings(&bindings);
+            device.create_descriptor_set_layout(&info, None).unwrap()
+        };
+        let set_count = MAX_REFLECTION_PROBES as u32 * PREFILTERED_MIP_LEVELS;
+        let descriptor_pool = unsafe {
+            let pool_sizes = [
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    descriptor_count: set_count,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_IMAGE,
+                    descriptor_count: set_count,
+                },
+            ];
+            let info = vk::DescriptorPoolCreateInfo::builder()
+                .max_sets(set_count)
+                .pool_sizes(&pool_sizes);
+            device.create_descriptor_pool(&info, None).expect("Failed to create descriptor pool")
+        };
+        let prefilter_sets = unsafe {
+            let set_layouts = vec![prefilter_set_layout; set_count as usize];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(descriptor_pool)
+                .set_layouts(&set_layouts)
+                .build();
+            device.allocate_descriptor_sets(&alloc_info).unwrap()
+        };
+        let (prefilter_pipeline, prefilter_pipeline_layout) = pipeline::new_compute_pipeline_and_layout(
+            &device,
+            shader_compiler,
+            "shaders/ibl_prefilter.comp",
+            &[prefilter_set_layout],
+            &[Self::PREFILTER_RANGE],
+            &[(ibl::SAMPLE_COUNT_CONSTANT_ID, pipeline::Constant::U32(ibl::PREFILTER_SAMPLE_COUNT))],
+        );
+
+        Self {
+            device,
+            probes: HandleMap::default(),
+            pending: VecDeque::new(),
+            capturing: None,
+            clear_color: [0.0; 4],
+            face_ubo_offsets: [0; 6],
+            probes_buffer,
+
+            cube_array,
+            sampler,
+            storage_views,
+
+            capture_color,
+            capture_depth,
+            capture_face_views,
+            framebuffers,
+            render_pass,
+            pipeline_layout,
+            pipeline,
+
+            descriptor_pool,
+            prefilter_set_layout,
+            prefilter_sets,
+            prefilter_pipeline_layout,
+            prefilter_pipeline,
+        }
+    }
+
+    const PREFILTER_RANGE: vk::PushConstantRange = vk::PushConstantRange {
+        stage_flags: vk::ShaderStageFlags::COMPUTE,
+        offset: 0,
+        size: size_of::<PrefilterPushConstants>() as u32,
+    };
+
+    /// writes the probes into the environment set and clears their cubemaps to black
+    pub fn init_environment_set(
+        &mut self,
+        write_batcher: &mut DescriptorWriteBatcher,
+        environment_set: vk::DescriptorSet,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+    ) {
+        self.write_probes_buffer();
+        write_batcher.queue_buffer_write(
+            environment_set,
+            PROBES_BINDING,
+            0,
+            vk::DescriptorType::UNIFORM_BUFFER,
+            vk::DescriptorBufferInfo {
+                buffer: self.probes_buffer.handle,
+                offset: 0,
+                range: size_of::<ProbesUBO>() as vk::DeviceSize,
+            },
+        );
+        write_batcher.queue_image_write(
+            environment_set,
+            PROBE_CUBES_BINDING,
+            0,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            vk::DescriptorImageInfo {
+                sampler: self.sampler,
+                image_view: self.cube_array.2,
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            },
+        );
+        // every probe is prefiltered from the same capture
+        for (&set, &storage_view) in self.prefilter_sets.iter().zip(&self.storage_views) {
+            write_batcher.queue_image_write(set, 0, 0, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::DescriptorImageInfo {
+                sampler: self.sampler,
+                image_view: self.capture_color.2,
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            });
+            write_batcher.queue_image_write(set, 1, 0, vk::DescriptorType::STORAGE_IMAGE, vk::DescriptorImageInfo {
+                sampler: vk::Sampler::null(),
+                image_view: storage_view,
+                image_layout: vk::ImageLayout::GENERAL,
+            });
+        }
+
+        let device = &self.device;
+        let cube_array_image = self.cube_array.0;
+        super::VkApp::execute_transient_commands(device, command_pool, queue, |command_buffer| unsafe {
+            let range = slot_range(0, MAX_REFLECTION_PROBES as u32);
+            cmd_barrier(
+                device,
+                command_buffer,
+                cube_array_image,
+                range,
+                (vk::ImageLayout::UNDEFINED, vk::AccessFlags::empty(), vk::PipelineStageFlags::TOP_OF_PIPE),
+                (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::AccessFlags::TRANSFER_WRITE, vk::PipelineStageFlags::TRANSFER),
+            );
+            device.cmd_clear_color_image(
+                command_buffer,
+                cube_array_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &vk::ClearColorValue { float32: [0.0, 0.0, 0.0, 1.0] },
+                &[range],
+            );
+            cmd_barrier(
+                device,
+                command_buffer,
+                cube_array_image,
+                range,
+                (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::AccessFlags::TRANSFER_WRITE, vk::PipelineStageFlags::TRANSFER),
+                (vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::AccessFlags::SHADER_READ, vk::PipelineStageFlags::FRAGMENT_SHADER),
+            );
+        });
+    }
+
+    /// `None` when all `MAX_REFLECTION_PROBES` are placed. No frame may be in flight,
+    /// the probe starts out as a copy of `environment`'s prefiltered cubemap and is captured next frame
+    pub fn insert(
+        &mut self,
+        probe: ReflectionProbe,
+        environment: &ibl::Environment,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+    ) -> Option<ReflectionProbeId> {
+        if self.probes.len() >= MAX_REFLECTION_PROBES {
+            log::warn!("Out of reflection probe slots");
+            return None;
+        }
+        let id = self.probes.insert(probe);
+        let range = slot_range(id.index() as u32, 1);
+
+        let device = &self.device;
+        let cube_array_image = self.cube_array.0;
+        let prefiltered_image = environment.get_prefiltered_image();
+        super::VkApp::execute_transient_commands(device, command_pool, queue, |command_buffer| unsafe {
+            let prefiltered_range = slot_range(0, 1);
+            cmd_barrier(
+                device,
+                command_buffer,
+                prefiltered_image,
+                prefiltered_range,
+                (vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::AccessFlags::SHADER_READ, vk::PipelineStageFlags::FRAGMENT_SHADER),
+                (vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::AccessFlags::TRANSFER_READ, vk::PipelineStageFlags::TRANSFER),
+            );
+            cmd_barrier(
+                device,
+                command_buffer,
+                cube_array_image,
+                range,
+                (vk::ImageLayout::UNDEFINED, vk::AccessFlags::empty(), vk::PipelineStageFlags::TOP_OF_PIPE),
+                (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::AccessFlags::TRANSFER_WRITE, vk::PipelineStageFlags::TRANSFER),
+            );
+            let regions: Vec<vk::ImageCopy> = (0..PREFILTERED_MIP_LEVELS)
+                .map(|mip| {
+                    let layers = |base_array_layer| vk::ImageSubresourceLayers {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        mip_level: mip,
+                        base_array_layer,
+                        layer_count: 6,
+                    };
+                    let size = PROBE_SIZE >> mip;
+                    vk::ImageCopy::builder()
+                        .src_subresource(layers(0))
+                        .dst_subresource(layers(range.base_array_layer))
+                        .extent(vk::Extent3D { width: size, height: size, depth: 1 })
+                        .build()
+                })
+                .collect();
+            device.cmd_copy_image(
+                command_buffer,
+                prefiltered_image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                cube_array_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &regions,
+            );
+            cmd_barrier(
+                device,
+                command_buffer,
+                prefiltered_image,
+                prefiltered_range,
+                (vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::AccessFlags::empty(), vk::PipelineStageFlags::TRANSFER),
+                (vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::AccessFlags::SHADER_READ, vk::PipelineStageFlags::FRAGMENT_SHADER),
+            );
+            cmd_barrier(
+                device,
+                command_buffer,
+                cube_array_image,
+                range,
+                (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::AccessFlags::TRANSFER_WRITE, vk::PipelineStageFlags::TRANSFER),
+                (vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::AccessFlags::SHADER_READ, vk::PipelineStageFlags::FRAGMENT_SHADER),
+            );
+        });
+
+        self.write_probes_buffer();
+        self.pending.push_back(id);
+        Some(id)
+    }
+
+    /// no frame may be in flight
+    pub fn remove(&mut self, id: ReflectionProbeId) -> Option<ReflectionProbe> {
+        let probe = self.probes.remove(id)?;
+        self.pending.retain(|&pending| pending != id);
+        if self.capturing == Some(id) {
+            self.capturing = None;
+        }
+        self.write_probes_buffer();
+        Some(probe)
+    }
+
+    pub fn get(&self, id: ReflectionProbeId) -> Option<&ReflectionProbe> {
+        self.probes.get(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.probes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.probes.is_empty()
+    }
+
+    /// captures the probe again in a coming frame, e.g. after the scene around it changed
+    pub fn recapture(&mut self, id: ReflectionProbeId) {
+        if self.probes.contains(id) && !self.pending.contains(&id) {
+            self.pending.push_back(id);
+        }
+    }
+
+    pub fn recapture_all(&mut self) {
+        let ids: Vec<ReflectionProbeId> = self.probes.iter().map(|(id, _)| id).collect();
+        for id in ids {
+            self.recapture(id);
+        }
+    }
+
+    fn write_probes_buffer(&mut self) {
+        let mut ubo = ProbesUBO::default();
+        for (id, probe) in self.probes.iter() {
+            let vec4 = |vector: Vector, w: f32| [vector.x, vector.y, vector.z, w];
+            ubo.probes[ubo.count as usize] = ShaderProbe {
+                position: vec4(probe.position, id.index() as f32),
+                box_min: vec4(probe.box_min, 0.0),
+                box_max: vec4(probe.box_max, 0.0),
+            };
+            ubo.count += 1;
+        }
+        self.probes_buffer.copy_from_slice(&[ubo], 0);
+    }
+
+    /// picks the probe captured this frame, if any
+    pub fn build(&mut self, clear_color: [f32; 4]) {
+        self.clear_color = clear_color;
+        self.capturing = self.pending.pop_front();
+    }
+
+    /// the main view's uniform buffer object seen from each face of the probe captured this frame
+    pub fn push_view_ubos(&mut self, main_view_ubo: &PerFrameUBO, uniform_ring: &mut UniformRing) {
+        let Some(probe) = self.capturing.and_then(|id| self.probes.get(id)) else {
+            return;
+        };
+        let position = probe.position;
+        for (face, offset) in self.face_ubo_offsets.iter_mut().enumerate() {
+            let proj_view = face_view(face, position).project(1.0, CAPTURE_NEAR_Z, CAPTURE_FAR_Z, false);
+            *offset = uniform_ring.push(&PerFrameUBO {
+                proj_view,
+                camera_position: [position.x, position.y, position.z, 1.0],
+                inverse_proj_view: proj_view.inverse().unwrap_or_default(),
+                previous_proj_view: proj_view,
+                ..*main_view_ubo
+            });
+        }
+    }
+
+    /// renders the batched draws around the probe due this frame and prefilters them into its slot,
+    /// record before the scene pass. `scene_sets` are the per frame uniform buffer and textures sets
+    pub fn cmd_capture(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+        scene_sets: [vk::DescriptorSet; 2],
+        draw_batcher: &DrawBatcher,
+        geometry_system: &GeometrySystem,
+        material_system: &MaterialSystem,
+    ) {
+        let Some(id) = self.capturing.take().filter(|&id| self.probes.contains(id)) else {
+            return;
+        };
+        let device = &self.device;
+        let clear_values = render_pass::ClearConfig { clear_color: self.clear_color, ..Default::default() }.clear_values();
+        let render_area = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: vk::Extent2D { width: PROBE_SIZE, height: PROBE_SIZE },
+        };
+
+        unsafe {
+            // the previous capture may still be being prefiltered
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[],
+            );
+
+            for (&framebuffer, &ubo_offset) in self.framebuffers.iter().zip(&self.face_ubo_offsets) {
+                let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+                    .render_pass(self.render_pass)
+                    .framebuffer(framebuffer)
+                    .render_area(render_area)
+                    .clear_values(&clear_values);
+                device.cmd_begin_render_pass(command_buffer, &render_pass_begin_info, vk::SubpassContents::INLINE);
+                device.cmd_set_viewport(command_buffer, 0, &[vk::Viewport {
+                    x: 0.0,
+                    y: 0.0,
+                    width: PROBE_SIZE as f32,
+                    height: PROBE_SIZE as f32,
+                    min_depth: 0.0,
+                    max_depth: 1.0,
+                }]);
+                device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.pipeline_layout,
+                    0,
+                    &scene_sets,
+                    &[ubo_offset],
+                );
+                geometry_system.cmd_bind_resources(command_buffer);
+                draw_batcher.cmd_draw_batches(
+                    command_buffer,
+                    frame,
+                    self.pipeline_layout,
+                    Some(self.pipeline),
+                    geometry_system,
+                    material_system,
+                );
+                device.cmd_end_render_pass(command_buffer);
+            }
+
+            let range = slot_range(id.index() as u32, 1);
+            let capture_written = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .build();
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[capture_written],
+                &[],
+                &[],
+            );
+            // the previous contents are discarded, frames before may still sample them
+            cmd_barrier(
+                device,
+                command_buffer,
+                self.cube_array.0,
+                range,
+                (vk::ImageLayout::UNDEFINED, vk::AccessFlags::empty(), vk::PipelineStageFlags::FRAGMENT_SHADER),
+                (vk::ImageLayout::GENERAL, vk::AccessFlags::SHADER_WRITE, vk::PipelineStageFlags::COMPUTE_SHADER),
+            );
+
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, self.prefilter_pipeline);
+            for mip in 0..PREFILTERED_MIP_LEVELS {
+                let set = self.prefilter_sets[(id.index() as u32 * PREFILTERED_MIP_LEVELS + mip) as usize];
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::COMPUTE,
+                    self.prefilter_pipeline_layout,
+                    0,
+                    &[set],
+                    &[],
+                );
+                let push_constants = PrefilterPushConstants { roughness: ibl::prefilter_roughness(mip) };
+                device.cmd_push_constants(
+                    command_buffer,
+                    self.prefilter_pipeline_layout,
+                    Self::PREFILTER_RANGE.stage_flags,
+                    0,
+                    std::slice::from_raw_parts(
+                        &push_constants as *const PrefilterPushConstants as *const u8,
+                        size_of::<PrefilterPushConstants>(),
+                    ),
+                );
+                let group_count = (PROBE_SIZE >> mip).div_ceil(ibl::WORKGROUP_SIZE);
+                device.cmd_dispatch(command_buffer, group_count, group_count, 6);
+            }
+
+            cmd_barrier(
+                device,
+                command_buffer,
+                self.cube_array.0,
+                range,
+                (vk::ImageLayout::GENERAL, vk::AccessFlags::SHADER_WRITE, vk::PipelineStageFlags::COMPUTE_SHADER),
+                (vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::AccessFlags::SHADER_READ, vk::PipelineStageFlags::FRAGMENT_SHADER),
+            );
+        }
+    }
+
+    // caller must ensure only called once
+    pub unsafe fn destroy(&mut self) {
+        let device = &self.device;
+        device.destroy_pipeline(self.prefilter_pipeline, None);
+        device.destroy_pipeline_layout(self.prefilter_pipeline_layout, None);
+        device.destroy_descriptor_pool(self.descriptor_pool, None);
+        device.destroy_descriptor_set_layout(self.prefilter_set_layout, None);
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline_layout(self.pipeline_layout, None);
+        for &framebuffer in &self.framebuffers {
+            device.destroy_framebuffer(framebuffer, None);
+        }
+        device.destroy_render_pass(self.render_pass, None);
+        for &view in self.capture_face_views.iter().chain(&self.storage_views) {
+            device.destroy_image_view(view, None);
+        }
+        for (image, memory, view) in [self.capture_depth, self.capture_color, self.cube_array] {
+            device.destroy_image_view(view, None);
+            device.destroy_image(image, None);
+            device.free_memory(memory, None);
+        }
+        device.destroy_sampler(self.sampler, None);
+        self.probes_buffer.destroy();
+    }
+}
+
+/// every mip of the cubes of `slot_count` slots from `first_slot` on
+fn slot_range(first_slot: u32, slot_count: u32) -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level: 0,
+        level_count: vk::REMAINING_MIP_LEVELS,
+        base_array_layer: 6 * first_slot,
+        layer_count: 6 * slot_count,
+    }
+}
+
+fn cmd_barrier(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    subresource_range: vk::ImageSubresourceRange,
+    (old_layout, src_access_mask, src_stage): (vk::ImageLayout, vk::AccessFlags, vk::PipelineStageFlags),
+    (new_layout, dst_access_mask, dst_stage): (vk::ImageLayout, vk::AccessFlags, vk::PipelineStageFlags),
+) {
+    let barrier = vk::ImageMemoryBarrier::builder()
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(subresource_range)
+        .src_access_mask(src_access_mask)
+        .dst_access_mask(dst_access_mask)
+        .build();
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage,
+            dst_stage,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[barrier],
+        );
+    }
+}
+
+#[test]
+fn test_reflection_probes() {
+    // each face looks along its major axis with right and down matching cubeDirection
+    let eye = Vector::new(1.0, 2.0, 3.0);
+    for (face, [right, down, forward]) in FACE_AXES.iter().enumerate() {
+        let proj = face_view(face, eye).project(1.0, CAPTURE_NEAR_Z, CAPTURE_FAR_Z, false);
+        let point = |[x, y, z]: [f32; 3]| Vector::new(eye.x + x, eye.y + y, eye.z + z);
+        let ndc = |point: Vector| {
+            let [x, y, _, w] = proj.transform_point(point);
+            (x / w, y / w)
+        };
+        assert!(ndc(point(*forward)) == (0.0, 0.0));
+        let [fx, fy, fz] = *forward;
+        // the edge of the face is at 45 degrees
+        let (x, _) = ndc(point([fx + right[0], fy + right[1], fz + right[2]]));
+        let (_, y) = ndc(point([fx + down[0], fy + down[1], fz + down[2]]));
+        assert!((x - 1.0).abs() < 1e-5 && (y - 1.0).abs() < 1e-5);
+    }
+
+    let probe = |x: f32| ReflectionProbe {
+        position: Vector::new(x, 0.0, 0.0),
+        box_min: Vector::new(x - 2.0, -2.0, -2.0),
+        box_max: Vector::new(x + 2.0, 2.0, 2.0),
+    };
+    let probes = [probe(0.0), probe(3.0)];
+    assert!(nearest_probe(&probes, Vector::new(-1.0, 0.0, 0.0)) == Some(0));
+    assert!(nearest_probe(&probes, Vector::new(1.9, 0.0, 0.0)) == Some(1));
+    assert!(nearest_probe(&probes, Vector::new(0.0, 3.0, 0.0)).is_none());
+
+    let close = |a: Vector, b: Vector| (a - b).norm_sqr() < 1e-10;
+    // from off center, the ray hits the +x wall at (2, 0, 1)
+    let projected = probes[0].box_project(Vector::new(1.0, 0.0, 0.0), Vector::new(0.5, 0.0, 1.0));
+    assert!(close(projected, Vector::new(2.0, 0.0, 1.0)));
+    // diagonal rays leave through the nearer wall
+    let projected = probes[0].box_project(Vector::new(1.0, 1.0, 0.0), Vector::new(1.0, 0.0, 0.0));
+    assert!(close(projected, Vector::new(2.0, 1.0, 0.0)));
+}
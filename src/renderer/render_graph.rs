@@ -0,0 +1,323 @@
+// Render graph, for now only tracks which passes use which transient images
+// so images that are never alive at the same time can share memory,
+// see validation.rs for checking passes' barriers in debug builds
+
+pub mod validation;
+
+use std::rc::Rc;
+
+use ash::vk;
+
+pub type PassId = u16;
+pub type TransientImageId = u16;
+
+/// images which only live for part of a frame: bloom mips, ssao buffers, shadow scratch
+#[derive(Clone, Copy)]
+pub struct TransientImageDesc {
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    pub usage: vk::ImageUsageFlags,
+    pub aspect_mask: vk::ImageAspectFlags,
+}
+
+pub struct Pass {
+    pub name: &'static str,
+    pub transient_images: Vec<TransientImageId>,
+}
+
+/// inclusive range of passes an image is alive for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Lifetime {
+    pub first_pass: PassId,
+    pub last_pass: PassId,
+}
+
+impl Lifetime {
+    pub fn overlaps(self, other: Lifetime) -> bool {
+        self.first_pass <= other.last_pass && other.first_pass <= self.last_pass
+    }
+}
+
+pub struct AliasingPlan {
+    pub offsets: Vec<vk::DeviceSize>,
+    /// size of the shared block
+    pub block_size: vk::DeviceSize,
+    /// size if every image had its own allocation
+    pub naive_size: vk::DeviceSize,
+}
+
+impl AliasingPlan {
+    pub fn saved_bytes(&self) -> vk::DeviceSize {
+        self.naive_size - self.block_size
+    }
+}
+
+#[derive(Default)]
+pub struct RenderGraph {
+    pub passes: Vec<Pass>,
+    pub transient_image_descs: Vec<TransientImageDesc>,
+}
+
+impl RenderGraph {
+    pub fn new() -> Self {
+        Self {
+            passes: Vec::new(),
+            transient_image_descs: Vec::new(),
+        }
+    }
+
+    pub fn add_transient_image(&mut self, desc: TransientImageDesc) -> TransientImageId {
+        self.transient_image_descs.push(desc);
+        (self.transient_image_descs.len() - 1) as TransientImageId
+    }
+
+    /// passes execute in the order they are added
+    pub fn add_pass(&mut self, name: &'static str, transient_images: &[TransientImageId]) -> PassId {
+        self.passes.push(Pass {
+            name,
+            transient_images: transient_images.to_vec(),
+        });
+        (self.passes.len() - 1) as PassId
+    }
+
+    /// images no pass uses get `None`
+    pub fn compute_lifetimes(&self) -> Vec<Option<Lifetime>> {
+        let mut lifetimes: Vec<Option<Lifetime>> = vec![None; self.transient_image_descs.len()];
+        for (pass_id, pass) in self.passes.iter().enumerate() {
+            let pass_id = pass_id as PassId;
+            for &image_id in &pass.transient_images {
+                let lifetime = &mut lifetimes[image_id as usize];
+                match lifetime {
+                    Some(lifetime) => lifetime.last_pass = pass_id,
+                    None => *lifetime = Some(Lifetime {
+                        first_pass: pass_id,
+                        last_pass: pass_id,
+                    }),
+                }
+            }
+        }
+        lifetimes
+    }
+
+    /// creates every used transient image, aliased into one allocation
+    pub fn create_transient_images(
+        &self,
+        device: Rc<ash::Device>,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    ) -> TransientImages {
+        TransientImages::new(
+            device,
+            physical_device_memory_properties,
+            &self.transient_image_descs,
+            &self.compute_lifetimes(),
+        )
+    }
+}
+
+/// Places the largest images first, each at the lowest offset that doesn't
+/// overlap an already placed image with an overlapping lifetime
+pub fn plan_aliasing(
+    requirements: &[vk::MemoryRequirements],
+    lifetimes: &[Lifetime],
+) -> AliasingPlan {
+    assert!(requirements.len() == lifetimes.len());
+
+    let mut order: Vec<usize> = (0..requirements.len()).collect();
+    order.sort_by(|&a, &b| requirements[b].size.cmp(&requirements[a].size));
+
+    let mut offsets = vec![0; requirements.len()];
+    let mut placed: Vec<usize> = Vec::with_capacity(requirements.len());
+    let mut block_size = 0;
+    let mut naive_size = 0;
+
+    for i in order {
+        let size = requirements[i].size;
+        let alignment = requirements[i].alignment.max(1);
+        naive_size += size;
+
+        // memory ranges taken while this image is alive, sorted by offset
+        let mut taken: Vec<(vk::DeviceSize, vk::DeviceSize)> = placed
+            .iter()
+            .filter(|&&j| lifetimes[i].overlaps(lifetimes[j]))
+            .map(|&j| (offsets[j], offsets[j] + requirements[j].size))
+            .collect();
+        taken.sort();
+
+        let mut offset = 0;
+        for (start, end) in taken {
+            if offset + size <= start {
+                break;
+            }
+            offset = crate::utils::align_up(offset.max(end) as usize, alignment as usize) as vk::DeviceSize;
+        }
+
+        offsets[i] = offset;
+        block_size = block_size.max(offset + size);
+        placed.push(i);
+    }
+
+    AliasingPlan {
+        offsets,
+        block_size,
+        naive_size,
+    }
+}
+
+pub struct TransientImages {
+    device: Rc<ash::Device>,
+    /// null for images no pass uses
+    pub images: Vec<vk::Image>,
+    pub image_views: Vec<vk::ImageView>,
+    memory: vk::DeviceMemory,
+    pub plan: AliasingPlan,
+}
+
+impl TransientImages {
+    pub fn new(
+        device: Rc<ash::Device>,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        descs: &[TransientImageDesc],
+        lifetimes: &[Option<Lifetime>],
+    ) -> Self {
+        let used: Vec<usize> = (0..descs.len()).filter(|&i| lifetimes[i].is_some()).collect();
+
+        let mut images = vec![vk::Image::null(); descs.len()];
+        let mut requirements = Vec::with_capacity(used.len());
+        let mut memory_type_bits = !0;
+        for &i in &used {
+            let desc = &descs[i];
+            let info = vk::ImageCreateInfo::builder()
+                .image_type(vk::ImageType::TYPE_2D)
+                .extent(vk::Extent3D {
+                    width: desc.extent.width,
+                    height: desc.extent.height,
+                    depth: 1,
+                })
+                .mip_levels(1)
+                .array_layers(1)
+                .format(desc.format)
+                .tiling(vk::ImageTiling::OPTIMAL)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .usage(desc.usage)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .samples(vk::SampleCountFlags::TYPE_1);
+
+            images[i] = unsafe { device.create_image(&info, None).unwrap() };
+            let image_requirements = unsafe { device.get_image_memory_requirements(images[i]) };
+            memory_type_bits &= image_requirements.memory_type_bits;
+            requirements.push(image_requirements);
+        }
+        assert!(used.is_empty() || memory_type_bits != 0, "Transient images have no memory type in common");
+
+        let used_lifetimes: Vec<Lifetime> = used.iter().map(|&i| lifetimes[i].unwrap()).collect();
+        let plan = plan_aliasing(&requirements, &used_lifetimes);
+        log::debug!(
+            "Transient images: {} KiB aliased into {} KiB, saved {} KiB",
+            plan.naive_size / 1024,
+            plan.block_size / 1024,
+            plan.saved_bytes() / 1024,
+        );
+
+        let memory = if used.is_empty() {
+            vk::DeviceMemory::null()
+        } else {
+            let alloc_info = vk::MemoryAllocateInfo::builder()
+                .allocation_size(plan.block_size)
+                .memory_type_index(super::device::find_mem_type_index(
+                    memory_type_bits,
+                    vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                    physical_device_memory_properties,
+                ));
+            unsafe { device.allocate_memory(&alloc_info, None).unwrap() }
+        };
+
+        let mut image_views = vec![vk::ImageView::null(); descs.len()];
+        for (plan_index, &i) in used.iter().enumerate() {
+            unsafe {
+                device.bind_image_memory(images[i], memory, plan.offsets[plan_index]).unwrap();
+            }
+            image_views[i] = super::image::new_image_view(
+                &device,
+                images[i],
+                descs[i].format,
+                descs[i].aspect_mask,
+                1,
+            );
+        }
+
+        Self {
+            device,
+            images,
+            image_views,
+            memory,
+            plan,
+        }
+    }
+
+    /// # Safety
+    /// call once, after the device has finished with the images
+    pub unsafe fn destroy(&mut self) {
+        for i in 0..self.images.len() {
+            if self.images[i] != vk::Image::null() {
+                self.device.destroy_image_view(self.image_views[i], None);
+                self.device.destroy_image(self.images[i], None);
+            }
+        }
+        if self.memory != vk::DeviceMemory::null() {
+            self.device.free_memory(self.memory, None);
+        }
+    }
+}
+
+#[test]
+fn test_plan_aliasing() {
+    let requirements = |size| vk::MemoryRequirements {
+        size,
+        alignment: 256,
+        memory_type_bits: !0,
+    };
+
+    let mut graph = RenderGraph::new();
+    let desc = TransientImageDesc {
+        format: vk::Format::R8G8B8A8_UNORM,
+        extent: vk::Extent2D { width: 1, height: 1 },
+        usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+    };
+    let ssao = graph.add_transient_image(desc);
+    let bloom = graph.add_transient_image(desc);
+    let shadow_scratch = graph.add_transient_image(desc);
+    let unused = graph.add_transient_image(desc);
+    graph.add_pass("ssao", &[ssao]);
+    graph.add_pass("ssao_blur", &[ssao, shadow_scratch]);
+    graph.add_pass("bloom", &[bloom]);
+
+    let lifetimes = graph.compute_lifetimes();
+    assert!(lifetimes[ssao as usize] == Some(Lifetime { first_pass: 0, last_pass: 1 }));
+    assert!(lifetimes[shadow_scratch as usize] == Some(Lifetime { first_pass: 1, last_pass: 1 }));
+    assert!(lifetimes[bloom as usize] == Some(Lifetime { first_pass: 2, last_pass: 2 }));
+    assert!(lifetimes[unused as usize].is_none());
+
+    // ssao and bloom never overlap so bloom reuses ssao's memory,
+    // shadow scratch overlaps ssao so goes after it
+    let lifetimes: Vec<Lifetime> = lifetimes[..3].iter().map(|l| l.unwrap()).collect();
+    let plan = plan_aliasing(
+        &[requirements(1000), requirements(800), requirements(300)],
+        &lifetimes,
+    );
+    assert!(plan.offsets[ssao as usize] == 0);
+    assert!(plan.offsets[bloom as usize] == 0);
+    assert!(plan.offsets[shadow_scratch as usize] == 1024);
+    assert!(plan.block_size == 1324);
+    assert!(plan.naive_size == 2100);
+    assert!(plan.saved_bytes() == 776);
+
+    // passes on either side of a long lived image share the memory after it
+    let lifetime = |first_pass, last_pass| Lifetime { first_pass, last_pass };
+    let plan = plan_aliasing(
+        &[requirements(1000), requirements(500), requirements(400)],
+        &[lifetime(0, 2), lifetime(0, 0), lifetime(2, 2)],
+    );
+    assert!(plan.offsets == [0, 1024, 1024]);
+    assert!(plan.block_size == 1524 && plan.saved_bytes() == 376);
+}
@@ -0,0 +1,300 @@
+// Validation of the render graph's barriers on the cpu, for people adding passes. Passes record
+// the layouts they expect images in and the accesses they make, barriers record the transitions
+// between them. Reading an image in the wrong layout, reading something written by an earlier pass
+// without a barrier in between, writing over something an earlier pass is still using, or using an
+// image the pass didn't declare panics naming the pass and the resource, before the validation
+// layers report it as a hazard somewhere in the frame's command buffer:
+//
+//     let mut validator = BarrierValidator::new(&graph);
+//     validator.name_image(ssao, "ssao");
+//     validator.begin_pass(ssao_pass);
+//     validator.image_barrier(ssao, vk::ImageLayout::UNDEFINED, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+//     validator.write_image(ssao, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+//     validator.begin_pass(ssao_blur_pass);
+//     // panics, ssao is still a color attachment and its writes aren't visible yet
+//     validator.read_image(ssao, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+//
+// Render passes' initial and final layouts are transitions too, record them with `image_barrier`.
+// Transient images start every frame UNDEFINED, `end_frame` resets them
+
+use ash::vk;
+
+use super::{PassId, RenderGraph, TransientImageId};
+
+/// index of a buffer tracked by the validator, in order of adding
+pub type TrackedBufferId = u16;
+
+#[derive(Clone, Copy)]
+struct Access {
+    pass: PassId,
+    write: bool,
+    /// a barrier came after it
+    synchronized: bool,
+}
+
+struct TrackedImage {
+    name: &'static str,
+    layout: vk::ImageLayout,
+    last_access: Option<Access>,
+}
+
+struct TrackedBuffer {
+    name: &'static str,
+    last_access: Option<Access>,
+}
+
+pub struct BarrierValidator {
+    pass_names: Vec<&'static str>,
+    pass_images: Vec<Vec<TransientImageId>>,
+    pass: Option<PassId>,
+    images: Vec<TrackedImage>,
+    buffers: Vec<TrackedBuffer>,
+}
+
+impl BarrierValidator {
+    /// tracks every transient image of `graph`
+    pub fn new(graph: &RenderGraph) -> Self {
+        Self {
+            pass_names: graph.passes.iter().map(|pass| pass.name).collect(),
+            pass_images: graph.passes.iter().map(|pass| pass.transient_images.clone()).collect(),
+            pass: None,
+            images: (0..graph.transient_image_descs.len())
+                .map(|_| TrackedImage {
+                    name: "unnamed",
+                    layout: vk::ImageLayout::UNDEFINED,
+                    last_access: None,
+                })
+                .collect(),
+            buffers: Vec::new(),
+        }
+    }
+
+    /// for the error messages
+    pub fn name_image(&mut self, image: TransientImageId, name: &'static str) {
+        self.images[image as usize].name = name;
+    }
+
+    pub fn add_buffer(&mut self, name: &'static str) -> TrackedBufferId {
+        self.buffers.push(TrackedBuffer { name, last_access: None });
+        (self.buffers.len() - 1) as TrackedBufferId
+    }
+
+    /// accesses and barriers from here on belong to `pass`
+    pub fn begin_pass(&mut self, pass: PassId) {
+        assert!((pass as usize) < self.pass_names.len(), "Render graph has no pass {}", pass);
+        self.pass = Some(pass);
+    }
+
+    /// transient images are UNDEFINED again and buffers have no accesses left to wait on
+    pub fn end_frame(&mut self) {
+        self.pass = None;
+        for image in &mut self.images {
+            image.layout = vk::ImageLayout::UNDEFINED;
+            image.last_access = None;
+        }
+        for buffer in &mut self.buffers {
+            buffer.last_access = None;
+        }
+    }
+
+    /// `old_layout` UNDEFINED discards the contents, so it matches any layout
+    pub fn image_barrier(&mut self, image: TransientImageId, old_layout: vk::ImageLayout, new_layout: vk::ImageLayout) {
+        let pass = self.check_declared(image);
+        let tracked = &self.images[image as usize];
+        if old_layout != vk::ImageLayout::UNDEFINED && old_layout != tracked.layout {
+            self.fail(pass, format!(
+                "transitions image \"{}\" from {:?} but it is in {:?}",
+                tracked.name, old_layout, tracked.layout,
+            ));
+        }
+        let tracked = &mut self.images[image as usize];
+        tracked.layout = new_layout;
+        tracked.last_access = match old_layout {
+            vk::ImageLayout::UNDEFINED => None,
+            _ => tracked.last_access.map(|access| Access { synchronized: true, ..access }),
+        };
+    }
+
+    pub fn buffer_barrier(&mut self, buffer: TrackedBufferId) {
+        self.current_pass();
+        let tracked = &mut self.buffers[buffer as usize];
+        tracked.last_access = tracked.last_access.map(|access| Access { synchronized: true, ..access });
+    }
+
+    /// sampled, read as an input attachment or copied from
+    pub fn read_image(&mut self, image: TransientImageId, layout: vk::ImageLayout) {
+        let pass = self.check_declared(image);
+        let tracked = &self.images[image as usize];
+        if tracked.layout == vk::ImageLayout::UNDEFINED {
+            self.fail(pass, format!("reads image \"{}\" before anything wrote it this frame", tracked.name));
+        }
+        if tracked.layout != layout {
+            self.fail(pass, format!(
+                "reads image \"{}\" in {:?} but it is in {:?}, missing a transition",
+                tracked.name, layout, tracked.layout,
+            ));
+        }
+        if let Err(error) = self.check_read(tracked.last_access) {
+            self.fail(pass, format!("reads image \"{}\" {}", tracked.name, error));
+        }
+        self.images[image as usize].last_access = Some(Access { pass, write: false, synchronized: false });
+    }
+
+    /// rendered to, stored to or copied to
+    pub fn write_image(&mut self, image: TransientImageId, layout: vk::ImageLayout) {
+        let pass = self.check_declared(image);
+        let tracked = &self.images[image as usize];
+        if tracked.layout != layout {
+            self.fail(pass, format!(
+                "writes image \"{}\" in {:?} but it is in {:?}, missing a transition",
+                tracked.name, layout, tracked.layout,
+            ));
+        }
+        if let Err(error) = self.check_write(pass, tracked.last_access) {
+            self.fail(pass, format!("writes image \"{}\" {}", tracked.name, error));
+        }
+        self.images[image as usize].last_access = Some(Access { pass, write: true, synchronized: false });
+    }
+
+    pub fn read_buffer(&mut self, buffer: TrackedBufferId) {
+        let pass = self.current_pass();
+        let tracked = &self.buffers[buffer as usize];
+        if let Err(error) = self.check_read(tracked.last_access) {
+            self.fail(pass, format!("reads buffer \"{}\" {}", tracked.name, error));
+        }
+        self.buffers[buffer as usize].last_access = Some(Access { pass, write: false, synchronized: false });
+    }
+
+    pub fn write_buffer(&mut self, buffer: TrackedBufferId) {
+        let pass = self.current_pass();
+        let tracked = &self.buffers[buffer as usize];
+        if let Err(error) = self.check_write(pass, tracked.last_access) {
+            self.fail(pass, format!("writes buffer \"{}\" {}", tracked.name, error));
+        }
+        self.buffers[buffer as usize].last_access = Some(Access { pass, write: true, synchronized: false });
+    }
+
+    fn current_pass(&self) -> PassId {
+        self.pass.expect("Render graph validation: resource used outside of a pass, call begin_pass first")
+    }
+
+    /// images a pass doesn't declare may share memory with the ones it uses
+    fn check_declared(&self, image: TransientImageId) -> PassId {
+        let pass = self.current_pass();
+        if !self.pass_images[pass as usize].contains(&image) {
+            self.fail(pass, format!(
+                "uses image \"{}\" without declaring it, its memory may be aliased by another image",
+                self.images[image as usize].name,
+            ));
+        }
+        pass
+    }
+
+    fn check_read(&self, last_access: Option<Access>) -> Result<(), String> {
+        match last_access {
+            Some(Access { pass, write: true, synchronized: false }) => Err(format!(
+                "after pass \"{}\" wrote it without a barrier in between",
+                self.pass_names[pass as usize],
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// writes within the same pass, like draws to the same attachment, don't need barriers
+    fn check_write(&self, current_pass: PassId, last_access: Option<Access>) -> Result<(), String> {
+        match last_access {
+            Some(Access { pass, write, synchronized: false }) if pass != current_pass => Err(format!(
+                "while pass \"{}\" may still be {} it, missing a barrier",
+                self.pass_names[pass as usize],
+                if write { "writing" } else { "reading" },
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn fail(&self, pass: PassId, error: String) -> ! {
+        panic!("Render graph validation: pass \"{}\" {}", self.pass_names[pass as usize], error);
+    }
+}
+
+#[test]
+fn test_barrier_validation() {
+    use super::TransientImageDesc;
+
+    let mut graph = RenderGraph::new();
+    let desc = TransientImageDesc {
+        format: vk::Format::R8G8B8A8_UNORM,
+        extent: vk::Extent2D { width: 1, height: 1 },
+        usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+    };
+    let ssao = graph.add_transient_image(desc);
+    let bloom = graph.add_transient_image(desc);
+    let ssao_pass = graph.add_pass("ssao", &[ssao]);
+    let blur_pass = graph.add_pass("ssao_blur", &[ssao]);
+    let bloom_pass = graph.add_pass("bloom", &[bloom]);
+
+    let new_validator = || {
+        let mut validator = BarrierValidator::new(&graph);
+        validator.name_image(ssao, "ssao");
+        validator.name_image(bloom, "bloom");
+        validator.begin_pass(ssao_pass);
+        validator.image_barrier(ssao, vk::ImageLayout::UNDEFINED, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+        validator.write_image(ssao, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+        validator.write_image(ssao, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+        validator.begin_pass(blur_pass);
+        validator
+    };
+    let fails = |f: &dyn Fn(&mut BarrierValidator)| {
+        let mut validator = new_validator();
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| f(&mut validator))).is_err()
+    };
+
+    // a transition makes the writes visible and puts the image in the layout read in
+    let mut validator = new_validator();
+    validator.image_barrier(
+        ssao,
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    );
+    validator.read_image(ssao, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+    validator.read_image(ssao, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+    validator.end_frame();
+    validator.begin_pass(ssao_pass);
+    validator.image_barrier(ssao, vk::ImageLayout::UNDEFINED, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+    validator.write_image(ssao, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+    // wrong layout, missing barrier, transition from the wrong layout
+    assert!(fails(&|v| v.read_image(ssao, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)));
+    assert!(fails(&|v| v.read_image(ssao, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)));
+    assert!(fails(&|v| v.image_barrier(
+        ssao,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    )));
+    // undeclared and never written images
+    assert!(fails(&|v| v.read_image(bloom, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)));
+    assert!(fails(&|v| {
+        v.begin_pass(bloom_pass);
+        v.read_image(bloom, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+    }));
+
+    // buffers only need the barrier, writes after reads need one too
+    let mut validator = new_validator();
+    let particles = validator.add_buffer("particles");
+    validator.write_buffer(particles);
+    validator.buffer_barrier(particles);
+    validator.begin_pass(bloom_pass);
+    validator.read_buffer(particles);
+    let write_after_read = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        validator.begin_pass(ssao_pass);
+        validator.write_buffer(particles);
+    }));
+    assert!(write_after_read.is_err());
+    assert!(fails(&|v| {
+        let particles = v.add_buffer("particles");
+        v.write_buffer(particles);
+        v.begin_pass(bloom_pass);
+        v.read_buffer(particles);
+    }));
+}
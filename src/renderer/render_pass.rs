@@ -1,28 +1,130 @@
 use ash::vk;
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LoadOp {
+    Clear,
+    /// keeps what the previous frame left in the attachment
+    Load,
+    /// for passes that overwrite every pixel anyway
+    DontCare,
+}
+
+impl LoadOp {
+    pub const fn to_vk(self) -> vk::AttachmentLoadOp {
+        match self {
+            LoadOp::Clear => vk::AttachmentLoadOp::CLEAR,
+            LoadOp::Load => vk::AttachmentLoadOp::LOAD,
+            LoadOp::DontCare => vk::AttachmentLoadOp::DONT_CARE,
+        }
+    }
+}
+
+/// depth of the far plane, what the depth attachment is cleared to
+pub const fn far_depth(reverse_z: bool) -> f32 {
+    if reverse_z { 0.0 } else { 1.0 }
+}
+
+/// How a pass starts off its color and depth attachments
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ClearConfig {
+    pub color_load_op: LoadOp,
+    pub clear_color: [f32; 4],
+    pub depth_load_op: LoadOp,
+    pub clear_depth: f32,
+}
+
+impl Default for ClearConfig {
+    fn default() -> Self {
+        Self {
+            color_load_op: LoadOp::Clear,
+            clear_color: [0.0, 0.0, 0.2, 1.0],
+            depth_load_op: LoadOp::Clear,
+            clear_depth: 1.0,
+        }
+    }
+}
+
+impl ClearConfig {
+    /// color then depth, matching the attachment order of the render passes
+    pub fn clear_values(&self) -> [vk::ClearValue; 2] {
+        [
+            vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: self.clear_color,
+                }
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: self.clear_depth,
+                    stencil: 0,
+                }
+            },
+        ]
+    }
+
+    /// for an image's first frame, its layout is still UNDEFINED and there is nothing to load,
+    /// a color load clears instead
+    pub fn first_use(&self) -> ClearConfig {
+        ClearConfig {
+            color_load_op: match self.color_load_op {
+                LoadOp::Load => LoadOp::Clear,
+                load_op => load_op,
+            },
+            ..*self
+        }
+    }
+
+    /// wether the render pass has to be rebuilt going from `self` to `other`
+    pub fn load_ops_differ(&self, other: &ClearConfig) -> bool {
+        self.color_load_op != other.color_load_op || self.depth_load_op != other.depth_load_op
+    }
+}
+
+fn new_depth_attachment_desc(
+    swapchain_depth_format: vk::Format,
+    depth_load_op: LoadOp,
+) -> vk::AttachmentDescription {
+    let initial_layout = match depth_load_op {
+        LoadOp::Load => vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        _ => vk::ImageLayout::UNDEFINED,
+    };
+
+    vk::AttachmentDescription::builder()
+        .format(swapchain_depth_format)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .load_op(depth_load_op.to_vk())
+        // read back by the next frame's load and by precipitation collisions
+        .store_op(vk::AttachmentStoreOp::STORE)
+        // stencil is written anew every frame, ignored by formats without it
+        .stencil_load_op(vk::AttachmentLoadOp::CLEAR)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(initial_layout)
+        .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+        .build()
+}
+
 pub fn new_render_pass(
     device: &ash::Device,
     color_format: vk::Format,
     swapchain_depth_format: vk::Format,
+    // PRESENT_SRC_KHR for swapchain images, COLOR_ATTACHMENT_OPTIMAL for offscreen targets
+    color_final_layout: vk::ImageLayout,
+    clear_config: &ClearConfig,
 ) -> vk::RenderPass {
+    // a loading pass expects the layout the previous frame left, the first frame uses `ClearConfig::first_use`
+    let color_initial_layout = match clear_config.color_load_op {
+        LoadOp::Load => color_final_layout,
+        _ => vk::ImageLayout::UNDEFINED,
+    };
     let color_attachment_desc = vk::AttachmentDescription::builder()
         .format(color_format)
         .samples(vk::SampleCountFlags::TYPE_1)
-        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .load_op(clear_config.color_load_op.to_vk())
         .store_op(vk::AttachmentStoreOp::STORE)
-        .initial_layout(vk::ImageLayout::UNDEFINED)
-        .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)
-        .build();
-    let depth_attachement_desc = vk::AttachmentDescription::builder()
-        .format(swapchain_depth_format)
-        .samples(vk::SampleCountFlags::TYPE_1)
-        .load_op(vk::AttachmentLoadOp::CLEAR)
-        .store_op(vk::AttachmentStoreOp::DONT_CARE)
-        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
-        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-        .initial_layout(vk::ImageLayout::UNDEFINED)
-        .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+        .initial_layout(color_initial_layout)
+        .final_layout(color_final_layout)
         .build();
+    let depth_attachement_desc = new_depth_attachment_desc(swapchain_depth_format, clear_config.depth_load_op);
 
     let color_attachment_ref = vk::AttachmentReference::builder()
         .attachment(0)
@@ -59,3 +161,125 @@ pub fn new_render_pass(
             .expect("Failed to create render procedure(renderpass), setup color attachments and sub procedure(subpass) dependencies")
     }
 }
+
+/// Two subpasses, the first fills the g-buffer attachments (multiple render targets) and depth,
+/// the second reads them back as input attachments and shades the swapchain image.
+/// Attachment order: swapchain color, depth, g-buffer attachments.
+/// The lighting subpass writes every pixel so the color load op is always DONT_CARE
+pub fn new_deferred_render_pass(
+    device: &ash::Device,
+    color_format: vk::Format,
+    swapchain_depth_format: vk::Format,
+    gbuffer_formats: &[vk::Format],
+    color_final_layout: vk::ImageLayout,
+    clear_config: &ClearConfig,
+) -> vk::RenderPass {
+    let mut attachment_descs = vec![
+        vk::AttachmentDescription::builder()
+            .format(color_format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(color_final_layout)
+            .build(),
+        new_depth_attachment_desc(swapchain_depth_format, clear_config.depth_load_op),
+    ];
+    for &format in gbuffer_formats {
+        // g-buffer contents never leave the render pass
+        attachment_descs.push(vk::AttachmentDescription::builder()
+            .format(format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .build());
+    }
+
+    let gbuffer_color_refs = (0..gbuffer_formats.len())
+        .map(|i| vk::AttachmentReference {
+            attachment: 2 + i as u32,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        })
+        .collect::<Vec<_>>();
+    let depth_attachment_ref = vk::AttachmentReference {
+        attachment: 1,
+        layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+    };
+
+    let mut lighting_input_refs = (0..gbuffer_formats.len())
+        .map(|i| vk::AttachmentReference {
+            attachment: 2 + i as u32,
+            layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        })
+        .collect::<Vec<_>>();
+    lighting_input_refs.push(vk::AttachmentReference {
+        attachment: 1,
+        layout: vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+    });
+    let lighting_color_refs = [vk::AttachmentReference {
+        attachment: 0,
+        layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+    }];
+    // depth tested translucent draws after lighting, read only so it can stay an input attachment
+    let lighting_depth_ref = vk::AttachmentReference {
+        attachment: 1,
+        layout: vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+    };
+
+    let subpass_descs = [
+        vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&gbuffer_color_refs)
+            .depth_stencil_attachment(&depth_attachment_ref)
+            .build(),
+        vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .input_attachments(&lighting_input_refs)
+            .color_attachments(&lighting_color_refs)
+            .depth_stencil_attachment(&lighting_depth_ref)
+            .build(),
+    ];
+
+    let subpass_deps = [
+        vk::SubpassDependency::builder()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS)
+            .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .build(),
+        vk::SubpassDependency::builder()
+            .src_subpass(0)
+            .dst_subpass(1)
+            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS)
+            .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::INPUT_ATTACHMENT_READ | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ)
+            .dependency_flags(vk::DependencyFlags::BY_REGION)
+            .build(),
+    ];
+
+    let info = vk::RenderPassCreateInfo::builder()
+        .subpasses(&subpass_descs)
+        .dependencies(&subpass_deps)
+        .attachments(&attachment_descs)
+        .build();
+
+    unsafe {
+        device.create_render_pass(&info, None)
+            .expect("Failed to create deferred render pass")
+    }
+}
+
+#[test]
+fn test_first_use() {
+    let loading = ClearConfig { color_load_op: LoadOp::Load, depth_load_op: LoadOp::Load, ..Default::default() };
+    let first_use = loading.first_use();
+    assert!(first_use.color_load_op == LoadOp::Clear && first_use.depth_load_op == LoadOp::Load);
+    assert!(first_use.clear_color == loading.clear_color);
+    let discarding = ClearConfig { color_load_op: LoadOp::DontCare, ..Default::default() };
+    assert!(discarding.first_use() == discarding);
+}
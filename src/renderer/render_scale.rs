@@ -0,0 +1,178 @@
+// Render scale, the scene is drawn to an offscreen target smaller than the swapchain
+// and blitted up to it afterwards
+
+use std::rc::Rc;
+
+use ash::vk;
+
+pub const MIN_RENDER_SCALE: f32 = 0.25;
+pub const MAX_RENDER_SCALE: f32 = 1.0;
+
+pub fn clamp_render_scale(render_scale: f32) -> f32 {
+    render_scale.clamp(MIN_RENDER_SCALE, MAX_RENDER_SCALE)
+}
+
+/// never smaller than 1x1
+pub fn scale_extent(extent: vk::Extent2D, render_scale: f32) -> vk::Extent2D {
+    vk::Extent2D {
+        width: ((extent.width as f32 * render_scale).round() as u32).max(1),
+        height: ((extent.height as f32 * render_scale).round() as u32).max(1),
+    }
+}
+
+/// Offscreen color target the scene renders to when the render scale is below 1
+pub struct SceneTarget {
+    device: Rc<ash::Device>,
+    pub image: vk::Image,
+    memory: vk::DeviceMemory,
+    pub image_view: vk::ImageView,
+    pub extent: vk::Extent2D,
+}
+
+impl SceneTarget {
+    pub fn new(
+        device: Rc<ash::Device>,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        format: vk::Format,
+        extent: vk::Extent2D,
+    ) -> Self {
+        let (image, memory) = super::image::new_image_and_memory(
+            &device,
+            physical_device_memory_properties,
+            &super::image::ImageDesc {
+                width: extent.width,
+                height: extent.height,
+                // blitted from and, by motion blur, into
+                usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST,
+                format,
+                ..Default::default()
+            },
+        );
+        let image_view = super::image::new_image_view(
+            &device,
+            image,
+            format,
+            vk::ImageAspectFlags::COLOR,
+            1,
+        );
+
+        Self {
+            device,
+            image,
+            memory,
+            image_view,
+            extent,
+        }
+    }
+
+    /// Upscales into the swapchain image with a linear filter.
+    /// Expects the target in COLOR_ATTACHMENT_OPTIMAL and leaves it there,
+    /// the swapchain image ends up in PRESENT_SRC_KHR
+    pub fn cmd_blit_to_swapchain(
+        &self,
+        sync: &super::synchronization::Synchronization,
+        command_buffer: vk::CommandBuffer,
+        queue_family_index: u32,
+        format: vk::Format,
+        swapchain_image: vk::Image,
+        swapchain_extent: vk::Extent2D,
+    ) {
+        super::image::cmd_transition_image_layout(
+            sync,
+            command_buffer,
+            queue_family_index,
+            &super::image::ImageTransition {
+                image: self.image,
+                format,
+                mip_levels: 1,
+                old_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                new_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            },
+        );
+        super::image::cmd_transition_image_layout(
+            sync,
+            command_buffer,
+            queue_family_index,
+            &super::image::ImageTransition {
+                image: swapchain_image,
+                format,
+                mip_levels: 1,
+                old_layout: vk::ImageLayout::UNDEFINED,
+                new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            },
+        );
+
+        let subresource = vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let blit = vk::ImageBlit {
+            src_subresource: subresource,
+            src_offsets: [
+                vk::Offset3D { x: 0, y: 0, z: 0 },
+                vk::Offset3D { x: self.extent.width as i32, y: self.extent.height as i32, z: 1 },
+            ],
+            dst_subresource: subresource,
+            dst_offsets: [
+                vk::Offset3D { x: 0, y: 0, z: 0 },
+                vk::Offset3D { x: swapchain_extent.width as i32, y: swapchain_extent.height as i32, z: 1 },
+            ],
+        };
+        unsafe {
+            self.device.cmd_blit_image(
+                command_buffer,
+                self.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                swapchain_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[blit],
+                vk::Filter::LINEAR,
+            );
+        }
+
+        super::image::cmd_transition_image_layout(
+            sync,
+            command_buffer,
+            queue_family_index,
+            &super::image::ImageTransition {
+                image: swapchain_image,
+                format,
+                mip_levels: 1,
+                old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                new_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            },
+        );
+        // also keeps the next frame from drawing over the target before the blit read it
+        super::image::cmd_transition_image_layout(
+            sync,
+            command_buffer,
+            queue_family_index,
+            &super::image::ImageTransition {
+                image: self.image,
+                format,
+                mip_levels: 1,
+                old_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                new_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            },
+        );
+    }
+
+    // caller must ensure only called once
+    pub unsafe fn destroy(&mut self) {
+        self.device.destroy_image_view(self.image_view, None);
+        self.device.destroy_image(self.image, None);
+        self.device.free_memory(self.memory, None);
+    }
+}
+
+#[test]
+fn test_scale_extent() {
+    let extent = vk::Extent2D { width: 1280, height: 720 };
+    assert!(scale_extent(extent, 1.0) == extent);
+    assert!(scale_extent(extent, 0.5) == vk::Extent2D { width: 640, height: 360 });
+    assert!(scale_extent(vk::Extent2D { width: 1, height: 1 }, 0.25) == vk::Extent2D { width: 1, height: 1 });
+    assert!(clamp_render_scale(0.1) == MIN_RENDER_SCALE);
+    assert!(clamp_render_scale(2.0) == MAX_RENDER_SCALE);
+}
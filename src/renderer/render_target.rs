@@ -0,0 +1,486 @@
+// Offscreen targets the scene is rendered into from cameras of their own before the main pass,
+// then sampled like any other texture, for mirrors, security camera screens or portals:
+//
+//     let target = app.create_render_target("security_camera", 256, 256, camera).unwrap();
+//     let texture = app.render_targets.get(target).unwrap().texture;
+//     // a material with `diffuse_texture: texture.index() as u32` shows the camera's view
+//     app.render_targets.get_mut(target).unwrap().camera.translation = Vector::new(0.0, -3.0, 0.0);
+//
+// The batched draws are rendered into an image of the target's own then copied into the texture,
+// so objects showing a target can be in its view, with the previous render on them.
+// Skinned meshes and terrain are left out, lod meshes are culled against the main camera
+
+use std::rc::Rc;
+
+use ash::vk;
+
+use crate::{
+    camera::Camera,
+    data_structures::handle_map::{Handle, HandleMap},
+    geometry,
+};
+use super::{
+    batch::SceneDraws,
+    deletion_queue::DeletionQueue,
+    descriptor::PerFrameUBO,
+    material,
+    pipeline,
+    render_pass,
+    shader,
+    synchronization::Synchronization,
+    texture::{Texture, TEXTURE_FORMAT},
+    uniform_ring::UniformRing,
+    TextureHandle,
+};
+
+pub type RenderTargetId = Handle<RenderTarget>;
+
+pub struct RenderTarget {
+    /// its aspect ratio and `reverse_z` are ignored, the target's extent decides the aspect ratio
+    pub camera: Camera,
+    pub enabled: bool,
+    /// frames between rerenders, 1 renders every frame
+    pub interval: u32,
+    /// sampled by materials through its index in the textures array
+    pub texture: TextureHandle,
+    extent: vk::Extent2D,
+
+    color_image: vk::Image,
+    color_image_memory: vk::DeviceMemory,
+    color_image_view: vk::ImageView,
+    depth_image: vk::Image,
+    depth_image_memory: vk::DeviceMemory,
+    depth_image_view: vk::ImageView,
+    framebuffer: vk::Framebuffer,
+
+    frames_since_render: u32,
+    /// the texture holds a render
+    rendered: bool,
+    render_this_frame: bool,
+    ubo_offset: u32,
+}
+
+impl RenderTarget {
+    pub fn get_extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    /// the main view's uniform buffer object seen from the target's camera
+    fn view_ubo(&self, main_view_ubo: &PerFrameUBO) -> PerFrameUBO {
+        let camera = Camera {
+            aspect_ratio: self.extent.width as f32 / self.extent.height as f32,
+            reverse_z: false,
+            ..self.camera
+        };
+        let proj_view = camera.calc_proj_view();
+        let translation = camera.translation;
+        PerFrameUBO {
+            proj_view,
+            camera_position: [translation.x, translation.y, translation.z, 1.0],
+            inverse_proj_view: proj_view.inverse().unwrap_or_default(),
+            previous_proj_view: proj_view,
+            ..*main_view_ubo
+        }
+    }
+
+    unsafe fn destroy(&mut self, device: &ash::Device) {
+        device.destroy_framebuffer(self.framebuffer, None);
+        device.destroy_image_view(self.depth_image_view, None);
+        device.destroy_image(self.depth_image, None);
+        device.free_memory(self.depth_image_memory, None);
+        device.destroy_image_view(self.color_image_view, None);
+        device.destroy_image(self.color_image, None);
+        device.free_memory(self.color_image_memory, None);
+    }
+}
+
+/// Call `build` once per frame before the uniform buffer is written, `push_view_ubos` while it is
+/// and `cmd_render` before the scene pass. Textures are created and released by the `VkApp`
+pub struct RenderTargetSystem {
+    device: Rc<ash::Device>,
+    depth_format: vk::Format,
+    render_pass: vk::RenderPass,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    targets: HandleMap<RenderTarget>,
+    retired: DeletionQueue<RenderTarget>,
+}
+
+impl RenderTargetSystem {
+    pub fn new(
+        device: Rc<ash::Device>,
+        shader_compiler: &shader::ShaderCompiler,
+        depth_format: vk::Format,
+        per_frame_ubo_set_layout: vk::DescriptorSetLayout,
+        textures_set_layout: vk::DescriptorSetLayout,
+    ) -> Self {
+        // transitioned for the copy into the texture by `cmd_render`
+        let render_pass = render_pass::new_render_pass(
+            &device,
+            TEXTURE_FORMAT,
+            depth_format,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            &render_pass::ClearConfig::default(),
+        );
+
+        // forward shaded whatever the scene's render path
+        let (pipeline, pipeline_layout) = pipeline::new_pipeline_and_layout(
+            &device,
+            shader_compiler,
+            &pipeline::PipelineDesc {
+                render_pass,
+                color_formats: &[TEXTURE_FORMAT],
+                depth_format,
+                set_layouts: &[per_frame_ubo_set_layout, textures_set_layout],
+                push_constant_ranges: &[material::MaterialPushConstants::RANGE],
+                vertex_shader_path: "shaders/foo.vert",
+                fragment_shader_path: "shaders/foo.frag",
+                vertex_attributes: &geometry::VERTEX_ATTRIBUTES,
+                instance_attributes: &geometry::INSTANCE_ATTRIBUTES,
+                ..Default::default()
+            },
+        );
+
+        Self {
+            device,
+            depth_format,
+            render_pass,
+            pipeline_layout,
+            pipeline,
+            targets: HandleMap::default(),
+            retired: DeletionQueue::default(),
+        }
+    }
+
+    /// rendered into `texture`, which must be `width` by `height` in `TEXTURE_FORMAT`
+    /// with transfer destination usage, like textures made `from_pixels`
+    pub fn insert(
+        &mut self,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        texture: TextureHandle,
+        width: u32,
+        height: u32,
+        camera: Camera,
+    ) -> RenderTargetId {
+        let device = &self.device;
+        let (color_image, color_image_memory) = super::image::new_image_and_memory(
+            device,
+            physical_device_memory_properties,
+            &super::image::ImageDesc {
+                width,
+                height,
+                usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+                format: TEXTURE_FORMAT,
+                ..Default::default()
+            },
+        );
+        let color_image_view = super::image::new_image_view(
+            device,
+            color_image,
+            TEXTURE_FORMAT,
+            vk::ImageAspectFlags::COLOR,
+            1,
+        );
+        let (depth_image, depth_image_memory) = super::image::new_image_and_memory(
+            device,
+            physical_device_memory_properties,
+            &super::image::ImageDesc {
+                width,
+                height,
+                usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+                format: self.depth_format,
+                ..Default::default()
+            },
+        );
+        let depth_image_view = super::image::new_image_view(
+            device,
+            depth_image,
+            self.depth_format,
+            super::image::get_depth_aspect_mask(self.depth_format),
+            1,
+        );
+        let framebuffer = unsafe {
+            let attachments = [color_image_view, depth_image_view];
+            let info = vk::FramebufferCreateInfo::builder()
+                .render_pass(self.render_pass)
+                .attachments(&attachments)
+                .width(width)
+                .height(height)
+                .layers(1);
+            device.create_framebuffer(&info, None).unwrap()
+        };
+
+        self.targets.insert(RenderTarget {
+            camera,
+            enabled: true,
+            interval: 1,
+            texture,
+            extent: vk::Extent2D { width, height },
+
+            color_image,
+            color_image_memory,
+            color_image_view,
+            depth_image,
+            depth_image_memory,
+            depth_image_view,
+            framebuffer,
+
+            frames_since_render: 0,
+            rendered: false,
+            render_this_frame: false,
+            ubo_offset: 0,
+        })
+    }
+
+    /// the removed target's texture, for the caller to release.
+    /// The target is destroyed once no frame in flight renders it
+    pub fn remove(&mut self, id: RenderTargetId) -> Option<TextureHandle> {
+        let target = self.targets.remove(id)?;
+        let texture = target.texture;
+        self.retired.push(target);
+        Some(texture)
+    }
+
+    pub fn get(&self, id: RenderTargetId) -> Option<&RenderTarget> {
+        self.targets.get(id)
+    }
+
+    pub fn get_mut(&mut self, id: RenderTargetId) -> Option<&mut RenderTarget> {
+        self.targets.get_mut(id)
+    }
+
+    /// decides which targets are rerendered this frame
+    pub fn build(&mut self) {
+        for (_, target) in self.targets.iter_mut() {
+            target.render_this_frame = target.enabled && (!target.rendered || target.frames_since_render + 1 >= target.interval);
+            if target.render_this_frame {
+                target.frames_since_render = 0;
+            } else {
+                target.frames_since_render += 1;
+            }
+        }
+    }
+
+    /// a view for each target rendered this frame
+    pub fn push_view_ubos(&mut self, main_view_ubo: &PerFrameUBO, uniform_ring: &mut UniformRing) {
+        for (_, target) in self.targets.iter_mut() {
+            if target.render_this_frame {
+                target.ubo_offset = uniform_ring.push(&target.view_ubo(main_view_ubo));
+            }
+        }
+    }
+
+    /// renders the batched draws into the targets due and copies them into their textures,
+    /// record before the scene pass
+    pub fn cmd_render(
+        &mut self,
+        sync: &Synchronization,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+        clear_color: [f32; 4],
+        scene: &SceneDraws,
+        textures: &crate::assets::AssetCache<Texture>,
+    ) {
+        let SceneDraws { draw_batcher, geometry_system, material_system, per_frame_ubo_set, textures_set } = *scene;
+        let clear_values = render_pass::ClearConfig { clear_color, ..Default::default() }.clear_values();
+        for (_, target) in self.targets.iter_mut() {
+            if !target.render_this_frame {
+                continue;
+            }
+            let Some(texture) = textures.get(target.texture) else {
+                continue;
+            };
+            target.rendered = true;
+
+            let render_area = vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: target.extent,
+            };
+            let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+                .render_pass(self.render_pass)
+                .framebuffer(target.framebuffer)
+                .render_area(render_area)
+                .clear_values(&clear_values);
+
+            unsafe {
+                // the previous render may still be being copied out
+                self.device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[],
+                );
+
+                self.device.cmd_begin_render_pass(command_buffer, &render_pass_begin_info, vk::SubpassContents::INLINE);
+                self.device.cmd_set_viewport(command_buffer, 0, &[vk::Viewport {
+                    x: 0.0,
+                    y: 0.0,
+                    width: target.extent.width as f32,
+                    height: target.extent.height as f32,
+                    min_depth: 0.0,
+                    max_depth: 1.0,
+                }]);
+                self.device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+                self.device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.pipeline_layout,
+                    0,
+                    &[per_frame_ubo_set, textures_set],
+                    &[target.ubo_offset],
+                );
+
+                geometry_system.cmd_bind_resources(command_buffer);
+                draw_batcher.cmd_draw_batches(
+                    command_buffer,
+                    frame,
+                    self.pipeline_layout,
+                    Some(self.pipeline),
+                    geometry_system,
+                    material_system,
+                );
+
+                self.device.cmd_end_render_pass(command_buffer);
+
+                let subresource_range = vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                };
+                let image_barriers = [
+                    vk::ImageMemoryBarrier::builder()
+                        .image(target.color_image)
+                        .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                        .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                        .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+                        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                        .subresource_range(subresource_range)
+                        .build(),
+                    // earlier passes of this frame may sample the texture, e.g. the minimap
+                    vk::ImageMemoryBarrier::builder()
+                        .image(texture.get_image())
+                        .old_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                        .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                        .src_access_mask(vk::AccessFlags::SHADER_READ)
+                        .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                        .subresource_range(subresource_range)
+                        .build(),
+                ];
+                self.device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &image_barriers,
+                );
+
+                let layers = vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: 0,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                };
+                let region = vk::ImageCopy::builder()
+                    .src_subresource(layers)
+                    .dst_subresource(layers)
+                    .extent(vk::Extent3D { width: target.extent.width, height: target.extent.height, depth: 1 })
+                    .build();
+                self.device.cmd_copy_image(
+                    command_buffer,
+                    target.color_image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    texture.get_image(),
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &[region],
+                );
+
+                super::image::cmd_transition_image_layout(
+                    sync,
+                    command_buffer,
+                    vk::QUEUE_FAMILY_IGNORED,
+                    &super::image::ImageTransition {
+                        image: texture.get_image(),
+                        format: TEXTURE_FORMAT,
+                        mip_levels: 1,
+                        old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    },
+                );
+            }
+        }
+    }
+
+    /// call once per frame after waiting for its fence
+    pub fn collect_retired(&mut self) {
+        let device = &self.device;
+        self.retired.collect(|mut target| unsafe { target.destroy(device) });
+    }
+
+    // caller must ensure only called once
+    pub unsafe fn destroy(&mut self) {
+        let device = &self.device;
+        self.retired.destroy_all(|mut target| target.destroy(device));
+        for (_, target) in self.targets.iter_mut() {
+            target.destroy(device);
+        }
+        self.device.destroy_pipeline(self.pipeline, None);
+        self.device.destroy_pipeline_layout(self.pipeline_layout, None);
+        self.device.destroy_render_pass(self.render_pass, None);
+    }
+}
+
+#[test]
+fn test_render_target_view() {
+    use crate::math::Vector;
+
+    let camera = Camera {
+        translation: Vector::new(1.0, -2.0, 3.0),
+        z_x_angle: 0.5,
+        y_xz_angle: 0.2,
+        roll: 0.0,
+        aspect_ratio: 1.0,
+        near_z: 0.1,
+        far_z: 100.0,
+        reverse_z: true,
+        projection: crate::camera::Projection::Perspective,
+        translation_speed: 1.0,
+    };
+    let target = RenderTarget {
+        camera,
+        enabled: true,
+        interval: 1,
+        texture: TextureHandle::from_raw_parts(0, 0),
+        extent: vk::Extent2D { width: 200, height: 100 },
+        color_image: vk::Image::null(),
+        color_image_memory: vk::DeviceMemory::null(),
+        color_image_view: vk::ImageView::null(),
+        depth_image: vk::Image::null(),
+        depth_image_memory: vk::DeviceMemory::null(),
+        depth_image_view: vk::ImageView::null(),
+        framebuffer: vk::Framebuffer::null(),
+        frames_since_render: 0,
+        rendered: false,
+        render_this_frame: false,
+        ubo_offset: 0,
+    };
+    let main_view_ubo = PerFrameUBO { time: 4.0, ..Default::default() };
+    let ubo = target.view_ubo(&main_view_ubo);
+    assert!(ubo.time == 4.0 && ubo.camera_position == [1.0, -2.0, 3.0, 1.0]);
+
+    // the target's own aspect ratio, regular depth, whatever the camera says
+    let expected = Camera { aspect_ratio: 2.0, reverse_z: false, ..target.camera }.calc_proj_view();
+    let forward = target.camera.forward();
+    let ahead = Vector::new(1.0 + forward.x * 10.0, -2.0 + forward.y * 10.0, 3.0 + forward.z * 10.0);
+    assert!(ubo.proj_view.transform_point(ahead) == expected.transform_point(ahead));
+}
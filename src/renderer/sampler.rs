@@ -0,0 +1,203 @@
+// Samplers shared between textures. Textures ask the `SamplerCache` for the sampler matching
+// their `SamplerDesc`, so a thousand repeating linear textures still use one `vk::Sampler`.
+// Samplers live as long as the cache, textures don't destroy them
+
+use std::{collections::HashMap, hash::{Hash, Hasher}, rc::Rc};
+
+use ash::vk;
+
+/// Filtering, addressing and comparison of a sampler, every mip level is accessible
+#[derive(Clone, Copy, Debug)]
+pub struct SamplerDesc {
+    pub mag_filter: vk::Filter,
+    pub min_filter: vk::Filter,
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    pub address_mode_u: vk::SamplerAddressMode,
+    pub address_mode_v: vk::SamplerAddressMode,
+    pub address_mode_w: vk::SamplerAddressMode,
+    /// 1 or less disables anisotropic filtering, clamped to the device's limit
+    pub max_anisotropy: f32,
+    /// added to the computed mip level, negative sharpens
+    pub mip_lod_bias: f32,
+    /// `Some` for comparison samplers, e.g. for shadow maps
+    pub compare_op: Option<vk::CompareOp>,
+    /// for `CLAMP_TO_BORDER`
+    pub border_color: vk::BorderColor,
+}
+
+impl Default for SamplerDesc {
+    /// what textures use unless their meta file says otherwise
+    fn default() -> Self {
+        Self {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_mode_u: vk::SamplerAddressMode::REPEAT,
+            address_mode_v: vk::SamplerAddressMode::REPEAT,
+            address_mode_w: vk::SamplerAddressMode::REPEAT,
+            max_anisotropy: 16.0,
+            mip_lod_bias: 0.0,
+            compare_op: None,
+            border_color: vk::BorderColor::INT_OPAQUE_BLACK,
+        }
+    }
+}
+
+impl SamplerDesc {
+    /// nearest texel and mip, e.g. for pixel art and lookup tables
+    pub fn point() -> Self {
+        Self {
+            mag_filter: vk::Filter::NEAREST,
+            min_filter: vk::Filter::NEAREST,
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            max_anisotropy: 1.0,
+            ..Default::default()
+        }
+    }
+
+    /// linear without anisotropy, clamped to the edges so ui quads don't bleed
+    pub fn ui() -> Self {
+        Self {
+            max_anisotropy: 1.0,
+            ..Default::default()
+        }
+        .with_address_mode(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+    }
+
+    /// hardware filtered depth comparison, outside the map counts as lit
+    pub fn shadow(compare_op: vk::CompareOp) -> Self {
+        Self {
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            max_anisotropy: 1.0,
+            compare_op: Some(compare_op),
+            border_color: vk::BorderColor::FLOAT_OPAQUE_WHITE,
+            ..Default::default()
+        }
+        .with_address_mode(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+    }
+
+    /// same mode on every axis
+    pub fn with_address_mode(self, address_mode: vk::SamplerAddressMode) -> Self {
+        Self {
+            address_mode_u: address_mode,
+            address_mode_v: address_mode,
+            address_mode_w: address_mode,
+            ..self
+        }
+    }
+
+    fn key(&self) -> impl PartialEq + Hash {
+        (
+            self.mag_filter,
+            self.min_filter,
+            self.mipmap_mode,
+            [self.address_mode_u, self.address_mode_v, self.address_mode_w],
+            // normalized, all values disabling anisotropy are the same sampler
+            self.max_anisotropy.max(1.0).to_bits(),
+            self.mip_lod_bias.to_bits(),
+            self.compare_op,
+            self.border_color,
+        )
+    }
+}
+
+// floats compare by their bits, so descs can key the cache
+
+impl PartialEq for SamplerDesc {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for SamplerDesc {}
+
+impl Hash for SamplerDesc {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+pub struct SamplerCache {
+    device: Rc<ash::Device>,
+    max_supported_anisotropy: f32,
+    samplers: HashMap<SamplerDesc, vk::Sampler>,
+}
+
+impl SamplerCache {
+    pub fn new(device: Rc<ash::Device>, limits: &vk::PhysicalDeviceLimits) -> Self {
+        Self {
+            device,
+            max_supported_anisotropy: limits.max_sampler_anisotropy,
+            samplers: HashMap::new(),
+        }
+    }
+
+    /// creates the sampler on first use
+    pub fn get(&mut self, desc: &SamplerDesc) -> vk::Sampler {
+        if let Some(&sampler) = self.samplers.get(desc) {
+            return sampler;
+        }
+
+        let max_anisotropy = desc.max_anisotropy.min(self.max_supported_anisotropy);
+        let info = vk::SamplerCreateInfo::builder()
+            .mag_filter(desc.mag_filter)
+            .min_filter(desc.min_filter)
+            .mipmap_mode(desc.mipmap_mode)
+            .address_mode_u(desc.address_mode_u)
+            .address_mode_v(desc.address_mode_v)
+            .address_mode_w(desc.address_mode_w)
+            .anisotropy_enable(max_anisotropy > 1.0)
+            .max_anisotropy(max_anisotropy.max(1.0))
+            .border_color(desc.border_color)
+            .unnormalized_coordinates(false)
+            .compare_enable(desc.compare_op.is_some())
+            .compare_op(desc.compare_op.unwrap_or(vk::CompareOp::ALWAYS))
+            .mip_lod_bias(desc.mip_lod_bias)
+            .min_lod(0.0)
+            .max_lod(vk::LOD_CLAMP_NONE);
+        let sampler = unsafe { self.device.create_sampler(&info, None).unwrap() };
+        log::debug!("Created sampler {:?}, {} cached", desc, self.samplers.len() + 1);
+
+        self.samplers.insert(*desc, sampler);
+        sampler
+    }
+
+    pub fn len(&self) -> usize {
+        self.samplers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samplers.is_empty()
+    }
+
+    /// # Safety
+    /// call once, after every use of the samplers finished
+    pub unsafe fn destroy(&mut self) {
+        for (_, sampler) in self.samplers.drain() {
+            self.device.destroy_sampler(sampler, None);
+        }
+    }
+}
+
+#[test]
+fn test_sampler_desc_key() {
+    use std::collections::hash_map::DefaultHasher;
+
+    let hash = |desc: &SamplerDesc| {
+        let mut hasher = DefaultHasher::new();
+        desc.hash(&mut hasher);
+        hasher.finish()
+    };
+
+    let repeat = SamplerDesc::default();
+    let also_repeat = SamplerDesc::default().with_address_mode(vk::SamplerAddressMode::REPEAT);
+    assert!(repeat == also_repeat && hash(&repeat) == hash(&also_repeat));
+
+    // anisotropy at or below 1 is off either way
+    let no_anisotropy = SamplerDesc { max_anisotropy: 0.0, ..SamplerDesc::point() };
+    assert!(no_anisotropy == SamplerDesc::point() && hash(&no_anisotropy) == hash(&SamplerDesc::point()));
+
+    assert!(SamplerDesc::ui() != repeat && SamplerDesc::point() != repeat);
+    assert!(SamplerDesc::shadow(vk::CompareOp::LESS) != SamplerDesc::shadow(vk::CompareOp::GREATER));
+    assert!(SamplerDesc { mip_lod_bias: -0.5, ..repeat } != repeat);
+}
@@ -0,0 +1,142 @@
+// Shader modules' SPIR-V, compiled from the glsl in shaders/ at runtime with the `runtime-shaders`
+// feature, for development, loaded precompiled otherwise. Precompiled shaders sit next to their
+// source in a spirv directory, shaders/foo.frag as shaders/spirv/foo.frag.spv, written by
+//
+//     cargo run --bin compile_shaders
+//
+// The `embedded-shaders` feature includes them in the executable, taking precedence over both,
+// so release builds need neither shaderc nor the shaders directory:
+//
+//     cargo run --bin compile_shaders && cargo build --release --no-default-features --features embedded-shaders
+
+use std::{collections::HashMap, io::Cursor, path::PathBuf};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ShaderStage {
+    Vertex,
+    Fragment,
+    Compute,
+}
+
+impl ShaderStage {
+    /// by the file's extension, `None` for includes
+    pub fn from_path(path: &str) -> Option<Self> {
+        match path.rsplit('.').next()? {
+            "vert" => Some(ShaderStage::Vertex),
+            "frag" => Some(ShaderStage::Fragment),
+            "comp" => Some(ShaderStage::Compute),
+            _ => None,
+        }
+    }
+}
+
+/// where the precompiled SPIR-V of the shader at `path` is
+pub fn spirv_path(path: &str) -> PathBuf {
+    let path = std::path::Path::new(path);
+    let mut file_name = path.file_name().expect("Shader path without a file name").to_os_string();
+    file_name.push(".spv");
+    path.with_file_name("spirv").join(file_name)
+}
+
+/// `(glsl path, spir-v)` of every shader in shaders/spirv, listed by build.rs
+#[cfg(feature = "embedded-shaders")]
+const EMBEDDED_SHADERS: &[(&str, &[u8])] = include!(concat!(env!("OUT_DIR"), "/embedded_shaders.rs"));
+
+/// compiles `path` including files relative to it, panics on errors
+#[cfg(feature = "runtime-shaders")]
+pub fn compile_glsl(compiler: &shaderc::Compiler, path: &str, stage: ShaderStage) -> Vec<u32> {
+    let source = std::fs::read_to_string(path).unwrap_or_else(|err| panic!("{}: {}", path, err));
+
+    // includes resolve relative to the including file
+    let mut options = shaderc::CompileOptions::new().unwrap();
+    options.set_include_callback(|requested, _, requesting, _| {
+        let path = std::path::Path::new(requesting).with_file_name(requested);
+        let content = std::fs::read_to_string(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        Ok(shaderc::ResolvedInclude {
+            resolved_name: path.to_string_lossy().into_owned(),
+            content,
+        })
+    });
+
+    let kind = match stage {
+        ShaderStage::Vertex => shaderc::ShaderKind::Vertex,
+        ShaderStage::Fragment => shaderc::ShaderKind::Fragment,
+        ShaderStage::Compute => shaderc::ShaderKind::Compute,
+    };
+    compiler
+        .compile_into_spirv(&source, kind, path, "main", Some(&options))
+        .unwrap_or_else(|err| panic!("{}", err))
+        .as_binary()
+        .to_vec()
+}
+
+/// Hands out the SPIR-V of shaders by their glsl path
+pub struct ShaderCompiler {
+    /// `None` when loading precompiled shaders
+    #[cfg(feature = "runtime-shaders")]
+    compiler: Option<shaderc::Compiler>,
+    /// by glsl path, used instead of compiling or loading them
+    embedded: HashMap<&'static str, &'static [u8]>,
+}
+
+impl Default for ShaderCompiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShaderCompiler {
+    /// compiles at runtime with the `runtime-shaders` feature, loads precompiled shaders otherwise
+    pub fn new() -> Self {
+        Self {
+            #[cfg(feature = "runtime-shaders")]
+            compiler: Some(shaderc::Compiler::new().expect("Failed to create shader compiler")),
+            ..Self::precompiled()
+        }
+    }
+
+    /// loads precompiled shaders even with the `runtime-shaders` feature
+    pub fn precompiled() -> Self {
+        Self {
+            #[cfg(feature = "runtime-shaders")]
+            compiler: None,
+            #[cfg(feature = "embedded-shaders")]
+            embedded: EMBEDDED_SHADERS.iter().copied().collect(),
+            #[cfg(not(feature = "embedded-shaders"))]
+            embedded: HashMap::new(),
+        }
+    }
+
+    /// the SPIR-V of the glsl shader at `path`, panics when it can't be compiled or found
+    #[cfg_attr(not(feature = "runtime-shaders"), allow(unused_variables))]
+    pub fn get_spirv(&self, path: &str, stage: ShaderStage) -> Vec<u32> {
+        if let Some(&bytes) = self.embedded.get(path) {
+            return ash::util::read_spv(&mut Cursor::new(bytes))
+                .unwrap_or_else(|err| panic!("Invalid embedded SPIR-V of {}: {}", path, err));
+        }
+
+        #[cfg(feature = "runtime-shaders")]
+        if let Some(compiler) = &self.compiler {
+            return compile_glsl(compiler, path, stage);
+        }
+
+        let spirv_path = spirv_path(path);
+        let mut file = std::fs::File::open(&spirv_path).unwrap_or_else(|err| {
+            panic!("No precompiled {}, run compile_shaders: {}", spirv_path.display(), err)
+        });
+        ash::util::read_spv(&mut file)
+            .unwrap_or_else(|err| panic!("Invalid SPIR-V in {}: {}", spirv_path.display(), err))
+    }
+}
+
+#[test]
+fn test_shader() {
+    assert!(ShaderStage::from_path("shaders/foo.frag") == Some(ShaderStage::Fragment));
+    assert!(ShaderStage::from_path("shaders/ibl.glsl").is_none());
+    assert!(spirv_path("shaders/foo.frag") == std::path::Path::new("shaders/spirv/foo.frag.spv"));
+
+    // the SPIR-V magic number and nothing else
+    let mut shader_compiler = ShaderCompiler::precompiled();
+    shader_compiler.embedded.insert("shaders/foo.frag", &[0x03, 0x02, 0x23, 0x07]);
+    assert!(shader_compiler.get_spirv("shaders/foo.frag", ShaderStage::Fragment) == [0x07230203]);
+}
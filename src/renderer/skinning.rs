@@ -0,0 +1,433 @@
+// Compute skinning, skinned meshes are deformed once per frame by a compute pre-pass
+// into a per frame vertex buffer that every pass drawing them then reads
+
+use std::{mem::size_of, rc::Rc};
+
+use ash::vk;
+
+use crate::{geometry::{self, Index, Vertex}, math::ModelMat};
+use super::{
+    buffer::Buffer,
+    descriptor::DescriptorWriteBatcher,
+    material::{MaterialId, MaterialSystem},
+    shader,
+    MAX_FRAMES_IN_FLIGHT,
+};
+
+pub type SkinnedMeshId = u16;
+
+// per frame region sizes are multiples of 256 bytes,
+// the largest storage buffer offset alignment devices may require
+pub const MAX_SKINNED_VERTEX_COUNT: usize = 0x10000;
+pub const MAX_SKINNED_INDEX_COUNT: usize = 0x30000;
+/// per frame, over all submitted skinned draws
+pub const MAX_JOINT_COUNT: usize = 0x400;
+/// per frame
+pub const MAX_SKINNED_DRAW_COUNT: usize = 0x100;
+
+const WORKGROUP_SIZE: u32 = 64;
+
+/// up to four joint influences per vertex, must match SkinWeights in skinning.comp
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct SkinWeights {
+    pub joints: [u32; 4],
+    /// should sum to 1
+    pub weights: [f32; 4],
+}
+
+/// must match the push constant block in skinning.comp
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SkinningPushConstants {
+    first_vertex: u32,
+    vertex_count: u32,
+    first_joint: u32,
+}
+
+struct SkinnedMesh {
+    first_vertex: u32,
+    vertex_count: u32,
+    first_index: u32,
+    index_count: u32,
+    joint_count: u32,
+}
+
+struct SkinnedDraw {
+    mesh: SkinnedMeshId,
+    material: MaterialId,
+    first_joint: u32,
+}
+
+/// Holds bind pose meshes and skins the submitted ones once per frame.
+/// Submit during the frame, `build` once the frame's fence is waited on,
+/// `cmd_dispatch` before any pass and `cmd_draw` from every pass that draws them
+pub struct SkinningSystem {
+    device: Rc<ash::Device>,
+
+    meshes: Vec<SkinnedMesh>,
+    vertex_count: usize,
+    index_count: usize,
+
+    // TODO: device local with staging once meshes are loaded from disk
+    bind_pose_buffer: Buffer,
+    weights_buffer: Buffer,
+    index_buffer: Buffer,
+    /// one region per frame in flight
+    joint_buffer: Buffer,
+    /// one region per frame in flight, transform of each skinned draw
+    instance_buffer: Buffer,
+    /// one region per frame in flight, written by the compute pre-pass
+    skinned_buffer: Buffer,
+
+    submitted_draws: Vec<SkinnedDraw>,
+    submitted_joints: Vec<ModelMat>,
+    submitted_transforms: Vec<ModelMat>,
+    /// of the last built frame
+    draws: Vec<SkinnedDraw>,
+
+    descriptor_pool: vk::DescriptorPool,
+    set_layout: vk::DescriptorSetLayout,
+    sets: Vec<vk::DescriptorSet>,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl SkinningSystem {
+    pub fn new(
+        device: Rc<ash::Device>,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        shader_compiler: &shader::ShaderCompiler,
+        write_batcher: &mut DescriptorWriteBatcher,
+    ) -> Self {
+        let host_visible = vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+        let new_buffer = |size: usize, usage, memory_properties| Buffer::new(
+            size as vk::DeviceSize,
+            usage,
+            memory_properties,
+            device.clone(),
+            physical_device_memory_properties,
+        );
+
+        let bind_pose_buffer = new_buffer(
+            MAX_SKINNED_VERTEX_COUNT * size_of::<Vertex>(),
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            host_visible,
+        );
+        let weights_buffer = new_buffer(
+            MAX_SKINNED_VERTEX_COUNT * size_of::<SkinWeights>(),
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            host_visible,
+        );
+        let index_buffer = new_buffer(
+            MAX_SKINNED_INDEX_COUNT * size_of::<Index>(),
+            vk::BufferUsageFlags::INDEX_BUFFER,
+            host_visible,
+        );
+        let joint_buffer = new_buffer(
+            MAX_FRAMES_IN_FLIGHT * Self::joint_region_size() as usize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            host_visible,
+        );
+        let instance_buffer = new_buffer(
+            MAX_FRAMES_IN_FLIGHT * Self::instance_region_size() as usize,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            host_visible,
+        );
+        let skinned_buffer = new_buffer(
+            MAX_FRAMES_IN_FLIGHT * Self::skinned_region_size() as usize,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::VERTEX_BUFFER,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        );
+
+        let set_layout_bindings = (0..4)
+            .map(|binding| vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build())
+            .collect::<Vec<_>>();
+        let set_layout = unsafe {
+            let info = vk::DescriptorSetLayoutCreateInfo::builder()
+                .bindings(&set_layout_bindings);
+            device.create_descriptor_set_layout(&info, None).unwrap()
+        };
+
+        let descriptor_pool = unsafe {
+            let pool_sizes = [vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: 4 * MAX_FRAMES_IN_FLIGHT as u32,
+            }];
+            let info = vk::DescriptorPoolCreateInfo::builder()
+                .max_sets(MAX_FRAMES_IN_FLIGHT as u32)
+                .pool_sizes(&pool_sizes);
+            device.create_descriptor_pool(&info, None).expect("Failed to create descriptor pool")
+        };
+
+        let sets = unsafe {
+            let set_layouts = [set_layout; MAX_FRAMES_IN_FLIGHT];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(descriptor_pool)
+                .set_layouts(&set_layouts);
+            device.allocate_descriptor_sets(&alloc_info).unwrap()
+        };
+
+        for (frame, &set) in sets.iter().enumerate() {
+            let buffer_infos = [
+                (bind_pose_buffer.handle, 0, bind_pose_buffer.size),
+                (weights_buffer.handle, 0, weights_buffer.size),
+                (joint_buffer.handle, frame as vk::DeviceSize * Self::joint_region_size(), Self::joint_region_size()),
+                (skinned_buffer.handle, frame as vk::DeviceSize * Self::skinned_region_size(), Self::skinned_region_size()),
+            ];
+            for (binding, (buffer, offset, range)) in buffer_infos.into_iter().enumerate() {
+                write_batcher.queue_buffer_write(
+                    set,
+                    binding as u32,
+                    0,
+                    vk::DescriptorType::STORAGE_BUFFER,
+                    vk::DescriptorBufferInfo { buffer, offset, range },
+                );
+            }
+        }
+
+        let (pipeline, pipeline_layout) = super::pipeline::new_compute_pipeline_and_layout(
+            &device,
+            shader_compiler,
+            "shaders/skinning.comp",
+            &[set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                offset: 0,
+                size: size_of::<SkinningPushConstants>() as u32,
+            }],
+            &[],
+        );
+
+        Self {
+            device,
+
+            meshes: vec![],
+            vertex_count: 0,
+            index_count: 0,
+
+            bind_pose_buffer,
+            weights_buffer,
+            index_buffer,
+            joint_buffer,
+            instance_buffer,
+            skinned_buffer,
+
+            submitted_draws: vec![],
+            submitted_joints: vec![],
+            submitted_transforms: vec![],
+            draws: vec![],
+
+            descriptor_pool,
+            set_layout,
+            sets,
+            pipeline_layout,
+            pipeline,
+        }
+    }
+
+    fn joint_region_size() -> vk::DeviceSize {
+        (MAX_JOINT_COUNT * size_of::<ModelMat>()) as vk::DeviceSize
+    }
+
+    fn instance_region_size() -> vk::DeviceSize {
+        (MAX_SKINNED_DRAW_COUNT * size_of::<ModelMat>()) as vk::DeviceSize
+    }
+
+    fn skinned_region_size() -> vk::DeviceSize {
+        (MAX_SKINNED_VERTEX_COUNT * size_of::<Vertex>()) as vk::DeviceSize
+    }
+
+    /// `weights` has one entry per vertex, joints index the matrices given to `submit`
+    // TODO: freeing meshes, storage is only bump allocated
+    pub fn create_skinned_mesh(
+        &mut self,
+        vertices: &[Vertex],
+        weights: &[SkinWeights],
+        indices: &[Index],
+        joint_count: u32,
+    ) -> SkinnedMeshId {
+        assert!(vertices.len() == weights.len());
+        assert!(self.vertex_count + vertices.len() <= MAX_SKINNED_VERTEX_COUNT, "Out of skinned vertex space");
+        assert!(self.index_count + indices.len() <= MAX_SKINNED_INDEX_COUNT, "Out of skinned index space");
+
+        let mut vertices = vertices.to_vec();
+        if vertices.iter().all(|vertex| vertex.tw == 0.0) {
+            geometry::generate_tangents(&mut vertices, indices);
+        }
+
+        self.bind_pose_buffer.copy_from_slice(&vertices, self.vertex_count);
+        self.weights_buffer.copy_from_slice(weights, self.vertex_count);
+        self.index_buffer.copy_from_slice(indices, self.index_count);
+
+        self.meshes.push(SkinnedMesh {
+            first_vertex: self.vertex_count as u32,
+            vertex_count: vertices.len() as u32,
+            first_index: self.index_count as u32,
+            index_count: indices.len() as u32,
+            joint_count,
+        });
+        self.vertex_count += vertices.len();
+        self.index_count += indices.len();
+
+        (self.meshes.len() - 1) as SkinnedMeshId
+    }
+
+    /// skins and draws the mesh next frame, `joint_matrices` are in model space.
+    /// A mesh is skinned once per frame, only its first submission counts
+    pub fn submit(
+        &mut self,
+        mesh: SkinnedMeshId,
+        material: MaterialId,
+        transform: ModelMat,
+        joint_matrices: &[ModelMat],
+    ) {
+        assert!(joint_matrices.len() == self.meshes[mesh as usize].joint_count as usize);
+        if self.submitted_draws.iter().any(|draw| draw.mesh == mesh) {
+            return;
+        }
+
+        self.submitted_draws.push(SkinnedDraw {
+            mesh,
+            material,
+            first_joint: self.submitted_joints.len() as u32,
+        });
+        self.submitted_joints.extend_from_slice(joint_matrices);
+        self.submitted_transforms.push(transform);
+    }
+
+    /// writes the submitted draws into `frame`'s regions and clears them,
+    /// the frame's previous commands must have finished executing
+    pub fn build(&mut self, frame: usize) {
+        assert!(self.submitted_joints.len() <= MAX_JOINT_COUNT, "Out of joint slots");
+        assert!(self.submitted_draws.len() <= MAX_SKINNED_DRAW_COUNT, "Out of skinned draw slots");
+
+        self.joint_buffer.copy_from_slice(&self.submitted_joints, frame * MAX_JOINT_COUNT);
+        self.instance_buffer.copy_from_slice(&self.submitted_transforms, frame * MAX_SKINNED_DRAW_COUNT);
+
+        self.draws = std::mem::take(&mut self.submitted_draws);
+        self.submitted_joints.clear();
+        self.submitted_transforms.clear();
+    }
+
+    /// record outside of any render pass, before the passes drawing skinned meshes
+    pub fn cmd_dispatch(&self, command_buffer: vk::CommandBuffer, frame: usize) {
+        if self.draws.is_empty() {
+            return;
+        }
+
+        unsafe {
+            self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, self.pipeline);
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[self.sets[frame]],
+                &[],
+            );
+
+            for draw in &self.draws {
+                let mesh = &self.meshes[draw.mesh as usize];
+                let push_constants = SkinningPushConstants {
+                    first_vertex: mesh.first_vertex,
+                    vertex_count: mesh.vertex_count,
+                    first_joint: draw.first_joint,
+                };
+                self.device.cmd_push_constants(
+                    command_buffer,
+                    self.pipeline_layout,
+                    vk::ShaderStageFlags::COMPUTE,
+                    0,
+                    std::slice::from_raw_parts(
+                        &push_constants as *const SkinningPushConstants as *const u8,
+                        size_of::<SkinningPushConstants>(),
+                    ),
+                );
+                self.device.cmd_dispatch(command_buffer, mesh.vertex_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+            }
+
+            // every pass of the frame reads the same skinned vertices
+            let barrier = vk::BufferMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::VERTEX_ATTRIBUTE_READ)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .buffer(self.skinned_buffer.handle)
+                .offset(frame as vk::DeviceSize * Self::skinned_region_size())
+                .size(Self::skinned_region_size())
+                .build();
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::VERTEX_INPUT,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[barrier],
+                &[],
+            );
+        }
+    }
+
+    /// draws every skinned mesh of the frame with the bound pipeline,
+    /// rebinds the vertex, instance and index buffers
+    pub fn cmd_draw(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+        pipeline_layout: vk::PipelineLayout,
+        material_system: &MaterialSystem,
+    ) {
+        if self.draws.is_empty() {
+            return;
+        }
+
+        unsafe {
+            self.device.cmd_bind_vertex_buffers(
+                command_buffer,
+                super::pipeline::VERTEX_BINDING,
+                &[self.skinned_buffer.handle, self.instance_buffer.handle],
+                &[
+                    frame as vk::DeviceSize * Self::skinned_region_size(),
+                    frame as vk::DeviceSize * Self::instance_region_size(),
+                ],
+            );
+            self.device.cmd_bind_index_buffer(command_buffer, self.index_buffer.handle, 0, vk::IndexType::UINT32);
+        }
+
+        for (instance, draw) in self.draws.iter().enumerate() {
+            let mesh = &self.meshes[draw.mesh as usize];
+            material_system.cmd_push_material(&self.device, command_buffer, pipeline_layout, draw.material);
+            unsafe {
+                self.device.cmd_draw_indexed(
+                    command_buffer,
+                    mesh.index_count,
+                    1,
+                    mesh.first_index,
+                    mesh.first_vertex as i32,
+                    instance as u32,
+                );
+            }
+        }
+    }
+
+    // caller must ensure only called once
+    pub unsafe fn destroy(&mut self) {
+        self.device.destroy_pipeline(self.pipeline, None);
+        self.device.destroy_pipeline_layout(self.pipeline_layout, None);
+        self.device.destroy_descriptor_pool(self.descriptor_pool, None);
+        self.device.destroy_descriptor_set_layout(self.set_layout, None);
+
+        self.bind_pose_buffer.destroy();
+        self.weights_buffer.destroy();
+        self.index_buffer.destroy();
+        self.joint_buffer.destroy();
+        self.instance_buffer.destroy();
+        self.skinned_buffer.destroy();
+    }
+}
@@ -0,0 +1,340 @@
+// Textured quads for health bars, markers and 2D games. Sprites are sorted by layer then texture
+// and written as vertices into one host visible buffer, each run of a texture is one draw.
+// Camera facing sprites are depth tested against the scene, screen sprites are drawn over it
+
+use std::{mem::size_of, rc::Rc};
+
+use ash::vk;
+
+use super::{buffer::Buffer, pipeline, MAX_FRAMES_IN_FLIGHT};
+
+/// per frame in flight
+pub const MAX_SPRITE_COUNT: usize = 0x2000;
+
+const VERTICES_PER_SPRITE: usize = 6;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum SpriteFacing {
+    /// `position` and `size` in world units, the sprite's top stays up on screen
+    Camera,
+    /// axis aligned on screen, `position` and `size` in logical pixels from the top left,
+    /// scaled by the display's scale factor, drawn after the camera facing sprites
+    Screen,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Sprite {
+    pub facing: SpriteFacing,
+    /// of the center, z is ignored by screen sprites
+    pub position: [f32; 3],
+    /// width and height
+    pub size: [f32; 2],
+    /// texture coordinates of the top left then the bottom right corner,
+    /// see `atlas_cell` for atlases laid out in a grid
+    pub uv_rect: [f32; 4],
+    /// straight alpha, multiplies the texture
+    pub color: [f32; 4],
+    /// into the textures descriptor array, a `TextureHandle`'s index
+    pub texture: u32,
+    /// higher layers are drawn over lower ones of the same facing,
+    /// textures are batched within a layer
+    pub layer: i32,
+}
+
+/// `uv_rect` of cell `index` in an atlas of `columns` by `rows` equally sized cells, row major
+pub fn atlas_cell(columns: u32, rows: u32, index: u32) -> [f32; 4] {
+    let (width, height) = (1.0 / columns as f32, 1.0 / rows as f32);
+    let (column, row) = ((index % columns) as f32, (index / columns) as f32);
+    [column * width, row * height, (column + 1.0) * width, (row + 1.0) * height]
+}
+
+/// must match the vertex attributes of sprite.vert
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+struct SpriteVertex {
+    /// center in world space with w 0, otherwise the corner in normalized device coordinates with w 1
+    position: [f32; 4],
+    /// of camera facing sprites, world space offset of the corner along the camera's right and up
+    corner: [f32; 2],
+    uv: [f32; 2],
+    color: [f32; 4],
+}
+
+const SPRITE_ATTRIBUTES: [pipeline::Attribute; 4] = [
+    pipeline::Attribute::F32x4,
+    pipeline::Attribute::F32x2,
+    pipeline::Attribute::F32x2,
+    pipeline::Attribute::F32x4,
+];
+
+/// consecutive vertices sampling the same texture
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct SpriteRun {
+    texture: u32,
+    first_vertex: u32,
+    vertex_count: u32,
+}
+
+/// must match the push constant block in sprite.frag
+const PUSH_CONSTANT_RANGE: vk::PushConstantRange = vk::PushConstantRange {
+    stage_flags: vk::ShaderStageFlags::FRAGMENT,
+    offset: 0,
+    size: size_of::<u32>() as u32,
+};
+
+/// Sorts `sprites` by facing, layer then texture, keeping the submission order within a texture,
+/// and writes two triangles per sprite. `extent` is what screen sprites are placed in,
+/// in physical pixels, `scale_factor` physical pixels to a logical one
+fn build_vertices(
+    sprites: &mut [Sprite],
+    extent: vk::Extent2D,
+    scale_factor: f32,
+    vertices: &mut Vec<SpriteVertex>,
+    runs: &mut Vec<SpriteRun>,
+) {
+    sprites.sort_by_key(|sprite| (sprite.facing, sprite.layer, sprite.texture));
+
+    for sprite in sprites.iter() {
+        match runs.last_mut() {
+            Some(run) if run.texture == sprite.texture => run.vertex_count += VERTICES_PER_SPRITE as u32,
+            _ => runs.push(SpriteRun {
+                texture: sprite.texture,
+                first_vertex: vertices.len() as u32,
+                vertex_count: VERTICES_PER_SPRITE as u32,
+            }),
+        }
+
+        let [min_u, min_v, max_u, max_v] = sprite.uv_rect;
+        // top left, top right, bottom right, bottom left
+        let corners = [(-1.0, -1.0, min_u, min_v), (1.0, -1.0, max_u, min_v), (1.0, 1.0, max_u, max_v), (-1.0, 1.0, min_u, max_v)];
+        let half_size = [0.5 * sprite.size[0], 0.5 * sprite.size[1]];
+        for index in [0, 1, 2, 0, 2, 3] {
+            let (x, y, u, v) = corners[index];
+            let (position, corner) = match sprite.facing {
+                // up is -y in the camera's basis
+                SpriteFacing::Camera => (
+                    [sprite.position[0], sprite.position[1], sprite.position[2], 0.0],
+                    [x * half_size[0], -y * half_size[1]],
+                ),
+                SpriteFacing::Screen => (
+                    [
+                        2.0 * scale_factor * (sprite.position[0] + x * half_size[0]) / extent.width as f32 - 1.0,
+                        2.0 * scale_factor * (sprite.position[1] + y * half_size[1]) / extent.height as f32 - 1.0,
+                        0.0,
+                        1.0,
+                    ],
+                    [0.0, 0.0],
+                ),
+            };
+            vertices.push(SpriteVertex {
+                position,
+                corner,
+                uv: [u, v],
+                color: sprite.color,
+            });
+        }
+    }
+}
+
+/// Submit during the frame, `build` once the frame's fence is waited on and `cmd_draw`
+/// in the scene pass. The pipeline depends on the scene render pass, `renew_pipeline` when it changes
+pub struct SpriteRenderer {
+    device: Rc<ash::Device>,
+    submitted: Vec<Sprite>,
+    vertices: Vec<SpriteVertex>,
+    /// of the last built frame
+    runs: Vec<SpriteRun>,
+    /// host visible, one region per frame in flight
+    vertex_buffer: Buffer,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl SpriteRenderer {
+    pub fn new(
+        device: Rc<ash::Device>,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    ) -> Self {
+        Self {
+            vertex_buffer: Buffer::new(
+                (MAX_FRAMES_IN_FLIGHT * Self::frame_size()) as vk::DeviceSize,
+                vk::BufferUsageFlags::VERTEX_BUFFER,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                device.clone(),
+                physical_device_memory_properties,
+            ),
+            device,
+            submitted: vec![],
+            vertices: vec![],
+            runs: vec![],
+            pipeline_layout: vk::PipelineLayout::null(),
+            pipeline: vk::Pipeline::null(),
+        }
+    }
+
+    /// `render_pass` null for dynamic rendering, draws in the translucent subpass with depth attached
+    pub fn renew_pipeline(
+        &mut self,
+        context: &pipeline::PassContext,
+        render_pass: vk::RenderPass,
+    ) {
+        let pipeline::PassContext { shader_compiler, color_format, depth_format, per_frame_ubo_set_layout, textures_set_layout, output_transfer, reverse_z, .. } = *context;
+        let subpass = context.translucent_subpass();
+        unsafe { self.destroy_pipeline(); }
+
+        (self.pipeline, self.pipeline_layout) = pipeline::new_pipeline_and_layout(
+            &self.device,
+            shader_compiler,
+            &pipeline::PipelineDesc {
+                render_pass,
+                subpass,
+                color_formats: &[color_format],
+                depth_format,
+                set_layouts: &[per_frame_ubo_set_layout, textures_set_layout],
+                push_constant_ranges: &[PUSH_CONSTANT_RANGE],
+                vertex_shader_path: "shaders/sprite.vert",
+                fragment_shader_path: "shaders/sprite.frag",
+                vertex_attributes: &SPRITE_ATTRIBUTES,
+                blend_mode: pipeline::BlendMode::Alpha,
+                cull_mode: vk::CullModeFlags::NONE,
+                // screen sprites sit on the near plane and pass the test
+                depth_write: false,
+                output_transfer,
+                reverse_z,
+                ..Default::default()
+            },
+        );
+    }
+
+    /// drawn next frame, only for that frame
+    pub fn submit<I: IntoIterator<Item = Sprite>>(&mut self, sprites: I) {
+        self.submitted.extend(sprites);
+    }
+
+    const fn frame_size() -> usize {
+        MAX_SPRITE_COUNT * VERTICES_PER_SPRITE * size_of::<SpriteVertex>()
+    }
+
+    /// writes the submitted sprites into `frame`'s region and clears them,
+    /// the frame's previous commands must have finished executing.
+    /// `screen_extent` is the swapchain's, screen sprites keep their size under the render scale
+    /// and scale with `scale_factor`, the display's
+    pub fn build(&mut self, frame: usize, screen_extent: vk::Extent2D, scale_factor: f32) {
+        if self.submitted.len() > MAX_SPRITE_COUNT {
+            log::warn!("Dropping {} sprites over the limit", self.submitted.len() - MAX_SPRITE_COUNT);
+            self.submitted.truncate(MAX_SPRITE_COUNT);
+        }
+        self.vertices.clear();
+        self.runs.clear();
+        build_vertices(&mut self.submitted, screen_extent, scale_factor, &mut self.vertices, &mut self.runs);
+        self.vertex_buffer.copy_from_slice(&self.vertices, frame * MAX_SPRITE_COUNT * VERTICES_PER_SPRITE);
+        self.submitted.clear();
+    }
+
+    /// record in the scene pass after the translucent geometry, binds its own pipeline
+    pub fn cmd_draw(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+        per_frame_ubo_set: vk::DescriptorSet,
+        per_frame_ubo_offset: u32,
+        textures_set: vk::DescriptorSet,
+    ) {
+        if self.runs.is_empty() {
+            return;
+        }
+
+        unsafe {
+            self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[per_frame_ubo_set, textures_set],
+                &[per_frame_ubo_offset],
+            );
+            self.device.cmd_bind_vertex_buffers(
+                command_buffer,
+                pipeline::VERTEX_BINDING,
+                &[self.vertex_buffer.handle],
+                &[(frame * Self::frame_size()) as vk::DeviceSize],
+            );
+            for run in &self.runs {
+                self.device.cmd_push_constants(
+                    command_buffer,
+                    self.pipeline_layout,
+                    PUSH_CONSTANT_RANGE.stage_flags,
+                    0,
+                    &run.texture.to_ne_bytes(),
+                );
+                self.device.cmd_draw(command_buffer, run.vertex_count, 1, run.first_vertex, 0);
+            }
+        }
+    }
+
+    unsafe fn destroy_pipeline(&mut self) {
+        if self.pipeline != vk::Pipeline::null() {
+            self.device.destroy_pipeline(self.pipeline, None);
+            self.device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+
+    // caller must ensure only called once
+    pub unsafe fn destroy(&mut self) {
+        self.destroy_pipeline();
+        self.vertex_buffer.destroy();
+    }
+}
+
+#[test]
+fn test_build_sprite_vertices() {
+    assert!(atlas_cell(4, 2, 5) == [0.25, 0.5, 0.5, 1.0]);
+
+    let sprite = |facing, texture| Sprite {
+        facing,
+        position: [100.0, 50.0, 3.0],
+        size: [20.0, 10.0],
+        uv_rect: atlas_cell(2, 2, 1),
+        color: [1.0; 4],
+        texture,
+        layer: 0,
+    };
+    let mut sprites = [
+        sprite(SpriteFacing::Screen, 0),
+        sprite(SpriteFacing::Camera, 2),
+        sprite(SpriteFacing::Camera, 1),
+        sprite(SpriteFacing::Camera, 2),
+    ];
+    let (mut vertices, mut runs) = (vec![], vec![]);
+    build_vertices(&mut sprites, vk::Extent2D { width: 200, height: 100 }, 1.0, &mut vertices, &mut runs);
+
+    // screen sprites last, textures drawn once each
+    assert!(runs == [
+        SpriteRun { texture: 1, first_vertex: 0, vertex_count: 6 },
+        SpriteRun { texture: 2, first_vertex: 6, vertex_count: 12 },
+        SpriteRun { texture: 0, first_vertex: 18, vertex_count: 6 },
+    ]);
+    // the top left corner is up and left of the center
+    assert!(vertices[0] == SpriteVertex {
+        position: [100.0, 50.0, 3.0, 0.0],
+        corner: [-10.0, 5.0],
+        uv: [0.5, 0.0],
+        color: [1.0; 4],
+    });
+    // screen pixels to normalized device coordinates, y down
+    let near = |a: [f32; 4], b: [f32; 4]| a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-6);
+    assert!(near(vertices[18].position, [-0.1, -0.1, 0.0, 1.0]) && vertices[18].uv == [0.5, 0.0]);
+    assert!(near(vertices[20].position, [0.1, 0.1, 0.0, 1.0]) && vertices[20].uv == [1.0, 0.5]);
+
+    // twice the pixels at twice the scale, the same place on screen
+    let (mut scaled_vertices, mut scaled_runs) = (vec![], vec![]);
+    build_vertices(&mut sprites, vk::Extent2D { width: 400, height: 200 }, 2.0, &mut scaled_vertices, &mut scaled_runs);
+    assert!(near(scaled_vertices[18].position, vertices[18].position));
+
+    // a higher layer over a lower one, even with a texture sorting before it
+    let mut layered = [Sprite { layer: 1, ..sprite(SpriteFacing::Screen, 0) }, sprite(SpriteFacing::Screen, 1)];
+    let (mut layered_vertices, mut layered_runs) = (vec![], vec![]);
+    build_vertices(&mut layered, vk::Extent2D { width: 200, height: 100 }, 1.0, &mut layered_vertices, &mut layered_runs);
+    assert!(layered_runs.iter().map(|run| run.texture).eq([1, 0]));
+}
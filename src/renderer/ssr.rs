@@ -0,0 +1,676 @@
+// Screen space reflections, a compute pass after the scene pass. Each pixel's position is
+// reconstructed from the depth buffer and its normal from the positions next to it, the reflected
+// view ray is marched against the depth buffer and takes the scene's color where it went behind a
+// surface. Mips of the result blur it for rough materials, which pbr.frag blends over the reflection
+// probe's or the environment's reflection during the next frame, those fill in where rays missed:
+//
+//     app.ssr.enabled = true;
+//     app.ssr.max_distance = 40.0;
+//
+// Only forward shaded materials reflect it. The scene's colors are tone mapped and encoded by then,
+// ssr.comp decodes them again except on hdr10 swapchains. Reads the swapchain image like screenshots
+// do, so it needs transfer source usage on it.
+// TODO: the scene color has translucent geometry, sprites and debug lines in it too
+
+use std::{mem::size_of, rc::Rc};
+
+use ash::vk;
+
+use super::{descriptor::DescriptorWriteBatcher, hiz, image, pipeline, shader, swapchain::OutputTransfer};
+
+/// of the scene color copy and the reflections
+pub const SSR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+/// of the reflections in the environment set, see `ibl::new_ibl_set_layout`
+pub const SSR_BINDING: u32 = 5;
+/// the last one is sampled by the roughest materials
+pub const MAX_SSR_MIP_LEVELS: u32 = 5;
+
+const WORKGROUP_SIZE: u32 = 8;
+
+/// must match the push constant block in ssr.comp
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct TracePushConstants {
+    viewport_offset: [i32; 2],
+    viewport_size: [i32; 2],
+    max_distance: f32,
+    thickness: f32,
+    step_count: u32,
+    reverse_z: u32,
+    clear_depth: f32,
+    output_transfer: u32,
+}
+
+/// what the trace reads of the frame's scene pass
+pub struct SceneInputs {
+    /// the swapchain image or the render scaled target
+    pub color_image: vk::Image,
+    /// the scene pass left it in
+    pub color_layout: vk::ImageLayout,
+    pub depth_image: vk::Image,
+    pub depth_format: vk::Format,
+    /// the letterboxed part of the scene
+    pub viewport: vk::Rect2D,
+    pub per_frame_ubo_set: vk::DescriptorSet,
+    pub view_ubo_offset: u32,
+    pub reverse_z: bool,
+    pub clear_depth: f32,
+    pub output_transfer: OutputTransfer,
+}
+
+/// size dependent resources, replaced on resize
+struct Targets {
+    copy_image: vk::Image,
+    copy_memory: vk::DeviceMemory,
+    copy_view: vk::ImageView,
+    image: vk::Image,
+    memory: vk::DeviceMemory,
+    /// every mip, sampled by pbr.frag
+    view: vk::ImageView,
+    /// the first mip, written by ssr.comp
+    level_view: vk::ImageView,
+    set: vk::DescriptorSet,
+    extents: Vec<vk::Extent2D>,
+}
+
+/// Traces reflections of the scene, `resize` along with the depth buffer and `cmd_trace`
+/// after the scene pass every frame, outside of any render pass
+pub struct ScreenSpaceReflections {
+    device: Rc<ash::Device>,
+    physical_device_memory_properties: vk::PhysicalDeviceMemoryProperties,
+    /// off by default, while off the reflections are cleared once and not traced
+    pub enabled: bool,
+    /// world units a ray marches before giving up
+    pub max_distance: f32,
+    /// how far behind the depth buffer a ray still hits it
+    pub thickness: f32,
+    /// per ray, before refining the hit
+    pub step_count: u32,
+    targets: Option<Targets>,
+    /// the reflections were traced since they were last cleared
+    stale: bool,
+    environment_set: vk::DescriptorSet,
+
+    /// for the scene color and depth, read with texelFetch
+    nearest_sampler: vk::Sampler,
+    /// for the reflections, across mips
+    sampler: vk::Sampler,
+    descriptor_pool: vk::DescriptorPool,
+    set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl ScreenSpaceReflections {
+    /// writes its reflections into `environment_set` on resize
+    pub fn new(
+        device: Rc<ash::Device>,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        shader_compiler: &shader::ShaderCompiler,
+        per_frame_ubo_set_layout: vk::DescriptorSetLayout,
+        environment_set: vk::DescriptorSet,
+    ) -> Self {
+        let new_sampler = |filter, mipmap_mode| unsafe {
+            let info = vk::SamplerCreateInfo::builder()
+                .mag_filter(filter)
+                .min_filter(filter)
+                .mipmap_mode(mipmap_mode)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .max_lod(vk::LOD_CLAMP_NONE);
+            device.create_sampler(&info, None).unwrap()
+        };
+        let nearest_sampler = new_sampler(vk::Filter::NEAREST, vk::SamplerMipmapMode::NEAREST);
+        let sampler = new_sampler(vk::Filter::LINEAR, vk::SamplerMipmapMode::LINEAR);
+
+        let set_layout_bindings = [
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            vk::DescriptorType::STORAGE_IMAGE,
+        ];
+        let set_layout_bindings: Vec<_> = set_layout_bindings
+            .iter()
+            .enumerate()
+            .map(|(binding, &ty)| vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding as u32)
+                .descriptor_type(ty)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build()
+            )
+            .collect();
+        let set_layout = unsafe {
+            let info = vk::DescriptorSetLayoutCreateInfo::builder()
+                .bindings(&set_layout_bindings);
+            device.create_descriptor_set_layout(&info, None).unwrap()
+        };
+
+        // reset on resize
+        let descriptor_pool = unsafe {
+            let pool_sizes = [
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    descriptor_count: 2,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_IMAGE,
+                    descriptor_count: 1,
+                },
+            ];
+            let info = vk::DescriptorPoolCreateInfo::builder()
+                .max_sets(1)
+                .pool_sizes(&pool_sizes);
+            device.create_descriptor_pool(&info, None).expect("Failed to create descriptor pool")
+        };
+
+        let (pipeline, pipeline_layout) = pipeline::new_compute_pipeline_and_layout(
+            &device,
+            shader_compiler,
+            "shaders/ssr.comp",
+            &[set_layout, per_frame_ubo_set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                offset: 0,
+                size: size_of::<TracePushConstants>() as u32,
+            }],
+            &[],
+        );
+
+        Self {
+            device,
+            physical_device_memory_properties: *physical_device_memory_properties,
+            enabled: false,
+            max_distance: 20.0,
+            thickness: 0.5,
+            step_count: 32,
+            targets: None,
+            stale: false,
+            environment_set,
+
+            nearest_sampler,
+            sampler,
+            descriptor_pool,
+            set_layout,
+            pipeline_layout,
+            pipeline,
+        }
+    }
+
+    /// replaces the reflections with cleared ones for a scene of `extent`, waiting for the gpu.
+    /// `depth_view` samples the depth buffer's depth aspect, the previous reflections must no longer be in use
+    pub fn resize(
+        &mut self,
+        write_batcher: &mut DescriptorWriteBatcher,
+        extent: vk::Extent2D,
+        depth_view: vk::ImageView,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+    ) {
+        unsafe { self.destroy_targets(); }
+
+        let (copy_image, copy_memory) = image::new_image_and_memory(
+            &self.device,
+            &self.physical_device_memory_properties,
+            &image::ImageDesc {
+                width: extent.width,
+                height: extent.height,
+                usage: vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+                format: SSR_FORMAT,
+                ..Default::default()
+            },
+        );
+        let copy_view = image::new_image_view(&self.device, copy_image, SSR_FORMAT, vk::ImageAspectFlags::COLOR, 1);
+
+        let extents = mip_extents(extent);
+        let mip_levels = extents.len() as u32;
+        let (image, memory) = image::new_image_and_memory(
+            &self.device,
+            &self.physical_device_memory_properties,
+            &image::ImageDesc {
+                width: extent.width,
+                height: extent.height,
+                mip_levels,
+                usage: vk::ImageUsageFlags::STORAGE
+                    | vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::TRANSFER_SRC
+                    | vk::ImageUsageFlags::TRANSFER_DST,
+                format: SSR_FORMAT,
+                ..Default::default()
+            },
+        );
+        let view = image::new_image_view(&self.device, image, SSR_FORMAT, vk::ImageAspectFlags::COLOR, mip_levels);
+        let level_view = image::new_image_view(&self.device, image, SSR_FORMAT, vk::ImageAspectFlags::COLOR, 1);
+
+        let set = unsafe {
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(self.descriptor_pool)
+                .set_layouts(&[self.set_layout])
+                .build();
+            self.device.allocate_descriptor_sets(&alloc_info).unwrap()[0]
+        };
+        let image_writes = [
+            (0, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, self.nearest_sampler, copy_view, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+            (1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, self.nearest_sampler, depth_view, vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL),
+            (2, vk::DescriptorType::STORAGE_IMAGE, vk::Sampler::null(), level_view, vk::ImageLayout::GENERAL),
+        ];
+        for (binding, ty, sampler, image_view, image_layout) in image_writes {
+            write_batcher.queue_image_write(
+                set,
+                binding,
+                0,
+                ty,
+                vk::DescriptorImageInfo { sampler, image_view, image_layout },
+            );
+        }
+        write_batcher.queue_image_write(
+            self.environment_set,
+            SSR_BINDING,
+            0,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            vk::DescriptorImageInfo {
+                sampler: self.sampler,
+                image_view: view,
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            },
+        );
+
+        let targets = Targets { copy_image, copy_memory, copy_view, image, memory, view, level_view, set, extents };
+        super::VkApp::execute_transient_commands(&self.device, command_pool, queue, |command_buffer| {
+            self.cmd_clear(command_buffer, &targets, vk::ImageLayout::UNDEFINED);
+        });
+        self.targets = Some(targets);
+        self.stale = false;
+    }
+
+    /// traces the reflections of the scene the frame's scene pass drew, record right after it.
+    /// Leaves the scene's images as they were and the reflections readable from fragment shaders
+    pub fn cmd_trace(&mut self, command_buffer: vk::CommandBuffer, scene: &SceneInputs) {
+        let Some(targets) = self.targets.as_ref() else {
+            return;
+        };
+        if !self.enabled {
+            if self.stale {
+                self.cmd_clear(command_buffer, targets, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+                self.stale = false;
+            }
+            return;
+        }
+
+        let extent = targets.extents[0];
+        let mip_levels = targets.extents.len() as u32;
+        let depth_subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: image::get_depth_aspect_mask(scene.depth_format),
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+
+        unsafe {
+            // the scene pass must be done with the color, the previous trace with the copy
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[
+                    color_barrier(
+                        scene.color_image,
+                        1,
+                        scene.color_layout,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                        vk::AccessFlags::TRANSFER_READ,
+                    ),
+                    color_barrier(
+                        targets.copy_image,
+                        1,
+                        vk::ImageLayout::UNDEFINED,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        vk::AccessFlags::empty(),
+                        vk::AccessFlags::TRANSFER_WRITE,
+                    ),
+                ],
+            );
+
+            // converts to float, and decodes srgb formats
+            let subresource = vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            };
+            let offsets = [
+                vk::Offset3D { x: 0, y: 0, z: 0 },
+                vk::Offset3D { x: extent.width as i32, y: extent.height as i32, z: 1 },
+            ];
+            self.device.cmd_blit_image(
+                command_buffer,
+                scene.color_image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                targets.copy_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[vk::ImageBlit {
+                    src_subresource: subresource,
+                    src_offsets: offsets,
+                    dst_subresource: subresource,
+                    dst_offsets: offsets,
+                }],
+                vk::Filter::NEAREST,
+            );
+
+            // this frame's fragment shaders are done reading the reflections
+            let depth_barrier = vk::ImageMemoryBarrier::builder()
+                .old_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                .new_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
+                .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(scene.depth_image)
+                .subresource_range(depth_subresource_range)
+                .build();
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::PipelineStageFlags::COMPUTE_SHADER
+                    | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[
+                    color_barrier(
+                        scene.color_image,
+                        1,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        scene.color_layout,
+                        vk::AccessFlags::TRANSFER_READ,
+                        vk::AccessFlags::empty(),
+                    ),
+                    color_barrier(
+                        targets.copy_image,
+                        1,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        vk::AccessFlags::TRANSFER_WRITE,
+                        vk::AccessFlags::SHADER_READ,
+                    ),
+                    depth_barrier,
+                    color_barrier(
+                        targets.image,
+                        mip_levels,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        vk::ImageLayout::GENERAL,
+                        vk::AccessFlags::empty(),
+                        vk::AccessFlags::SHADER_WRITE,
+                    ),
+                ],
+            );
+
+            let push_constants = TracePushConstants {
+                viewport_offset: [scene.viewport.offset.x, scene.viewport.offset.y],
+                viewport_size: [scene.viewport.extent.width as i32, scene.viewport.extent.height as i32],
+                max_distance: self.max_distance,
+                thickness: self.thickness,
+                step_count: self.step_count,
+                reverse_z: scene.reverse_z as u32,
+                clear_depth: scene.clear_depth,
+                output_transfer: scene.output_transfer as u32,
+            };
+            self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, self.pipeline);
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[targets.set, scene.per_frame_ubo_set],
+                &[scene.view_ubo_offset],
+            );
+            self.device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                std::slice::from_raw_parts(
+                    &push_constants as *const TracePushConstants as *const u8,
+                    size_of::<TracePushConstants>(),
+                ),
+            );
+            self.device.cmd_dispatch(
+                command_buffer,
+                extent.width.div_ceil(WORKGROUP_SIZE),
+                extent.height.div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+
+            // each mip halves the one before, blurring it for rougher materials.
+            // Blits read and write the mips in GENERAL layout
+            for level in 1..targets.extents.len() {
+                let memory_barrier = vk::MemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::SHADER_WRITE | vk::AccessFlags::TRANSFER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::TRANSFER_READ | vk::AccessFlags::TRANSFER_WRITE)
+                    .build();
+                self.device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::DependencyFlags::empty(),
+                    &[memory_barrier],
+                    &[],
+                    &[],
+                );
+
+                let (source, destination) = (targets.extents[level - 1], targets.extents[level]);
+                let subresource = |mip_level| vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                };
+                let blit = vk::ImageBlit {
+                    src_subresource: subresource(level as u32 - 1),
+                    src_offsets: [
+                        vk::Offset3D { x: 0, y: 0, z: 0 },
+                        vk::Offset3D { x: source.width as i32, y: source.height as i32, z: 1 },
+                    ],
+                    dst_subresource: subresource(level as u32),
+                    dst_offsets: [
+                        vk::Offset3D { x: 0, y: 0, z: 0 },
+                        vk::Offset3D { x: destination.width as i32, y: destination.height as i32, z: 1 },
+                    ],
+                };
+                self.device.cmd_blit_image(
+                    command_buffer,
+                    targets.image,
+                    vk::ImageLayout::GENERAL,
+                    targets.image,
+                    vk::ImageLayout::GENERAL,
+                    &[blit],
+                    vk::Filter::LINEAR,
+                );
+            }
+
+            let depth_barrier = vk::ImageMemoryBarrier::builder()
+                .old_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
+                .new_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                .src_access_mask(vk::AccessFlags::empty())
+                .dst_access_mask(
+                    vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+                )
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(scene.depth_image)
+                .subresource_range(depth_subresource_range)
+                .build();
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[
+                    depth_barrier,
+                    color_barrier(
+                        targets.image,
+                        mip_levels,
+                        vk::ImageLayout::GENERAL,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        vk::AccessFlags::SHADER_WRITE | vk::AccessFlags::TRANSFER_WRITE,
+                        vk::AccessFlags::SHADER_READ,
+                    ),
+                ],
+            );
+        }
+        self.stale = true;
+    }
+
+    /// every mip to transparent black, readable from fragment shaders after
+    fn cmd_clear(&self, command_buffer: vk::CommandBuffer, targets: &Targets, old_layout: vk::ImageLayout) {
+        let mip_levels = targets.extents.len() as u32;
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[color_barrier(
+                    targets.image,
+                    mip_levels,
+                    old_layout,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::AccessFlags::empty(),
+                    vk::AccessFlags::TRANSFER_WRITE,
+                )],
+            );
+            self.device.cmd_clear_color_image(
+                command_buffer,
+                targets.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &vk::ClearColorValue { float32: [0.0; 4] },
+                &[vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: mip_levels,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                }],
+            );
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[color_barrier(
+                    targets.image,
+                    mip_levels,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::AccessFlags::SHADER_READ,
+                )],
+            );
+        }
+    }
+
+    unsafe fn destroy_targets(&mut self) {
+        let Some(targets) = self.targets.take() else {
+            return;
+        };
+        self.device.destroy_image_view(targets.copy_view, None);
+        self.device.destroy_image(targets.copy_image, None);
+        self.device.free_memory(targets.copy_memory, None);
+        self.device.destroy_image_view(targets.level_view, None);
+        self.device.destroy_image_view(targets.view, None);
+        self.device.destroy_image(targets.image, None);
+        self.device.free_memory(targets.memory, None);
+        self.device.reset_descriptor_pool(self.descriptor_pool, vk::DescriptorPoolResetFlags::empty()).unwrap();
+    }
+
+    // caller must ensure only called once
+    pub unsafe fn destroy(&mut self) {
+        self.destroy_targets();
+        self.device.destroy_pipeline(self.pipeline, None);
+        self.device.destroy_pipeline_layout(self.pipeline_layout, None);
+        self.device.destroy_descriptor_pool(self.descriptor_pool, None);
+        self.device.destroy_descriptor_set_layout(self.set_layout, None);
+        self.device.destroy_sampler(self.sampler, None);
+        self.device.destroy_sampler(self.nearest_sampler, None);
+    }
+}
+
+/// the first `mip_levels` mips of a single layer color image
+fn color_barrier(
+    image: vk::Image,
+    mip_levels: u32,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    src_access_mask: vk::AccessFlags,
+    dst_access_mask: vk::AccessFlags,
+) -> vk::ImageMemoryBarrier {
+    vk::ImageMemoryBarrier::builder()
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_access_mask(src_access_mask)
+        .dst_access_mask(dst_access_mask)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: mip_levels,
+            base_array_layer: 0,
+            layer_count: 1,
+        })
+        .build()
+}
+
+/// of the reflections' mips, halving like the depth pyramid's up to `MAX_SSR_MIP_LEVELS`
+pub fn mip_extents(extent: vk::Extent2D) -> Vec<vk::Extent2D> {
+    let mut extents = hiz::level_extents(extent);
+    extents.truncate(MAX_SSR_MIP_LEVELS as usize);
+    extents
+}
+
+/// how much ssr.comp trusts a hit at `hit_ndc`, `hit_distance` along the ray.
+/// `facing` is the cosine between the ray and the direction back to the camera
+pub fn hit_weight(hit_ndc: [f32; 2], hit_distance: f32, max_distance: f32, facing: f32) -> f32 {
+    let smoothstep = |edge0: f32, edge1: f32, x: f32| {
+        let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    };
+    let edge = |ndc: f32| 1.0 - smoothstep(0.8, 1.0, ndc.abs());
+    edge(hit_ndc[0]) * edge(hit_ndc[1])
+        * (1.0 - hit_distance / max_distance)
+        * (1.0 - (facing * 2.0).clamp(0.0, 1.0))
+}
+
+#[test]
+fn test_ssr_weights() {
+    let extent = |width, height| vk::Extent2D { width, height };
+    assert!(mip_extents(extent(1920, 1080)).len() == MAX_SSR_MIP_LEVELS as usize);
+    assert!(mip_extents(extent(3, 2)) == [extent(3, 2), extent(1, 1)]);
+
+    // a hit in the middle of the screen right at the surface, with a ray leaving the camera
+    assert!(hit_weight([0.0, 0.0], 0.0, 20.0, -1.0) == 1.0);
+    assert!((hit_weight([0.0, 0.0], 10.0, 20.0, 0.0) - 0.5).abs() < 1e-6);
+    // nothing at the screen's edge, the ray's end or from rays heading back at the camera
+    assert!(hit_weight([1.0, 0.0], 0.0, 20.0, 0.0) == 0.0);
+    assert!(hit_weight([0.0, -1.0], 0.0, 20.0, 0.0) == 0.0);
+    assert!(hit_weight([0.0, 0.0], 20.0, 20.0, 0.0) == 0.0);
+    assert!(hit_weight([0.0, 0.0], 0.0, 20.0, 0.5) == 0.0);
+    // fades in between
+    let faded = hit_weight([0.9, 0.0], 0.0, 20.0, 0.0);
+    assert!(faded > 0.0 && faded < 1.0);
+}
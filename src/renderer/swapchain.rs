@@ -2,24 +2,188 @@ use ash::{
     extensions::khr::{Surface, Swapchain},
     vk,
 };
+use serde::Deserialize;
 
+/// Swapchain formats looked for first, falls back to 8 bit sdr when the surface offers none of them
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Deserialize)]
+pub enum SwapchainFormatPreference {
+    /// 8 bit unorm, shaded colors are presented as they are
+    #[default]
+    Unorm,
+    /// 8 bit srgb, blending happens on linear colors
+    Srgb,
+    /// 10 bit bt.2020 with the st 2084 curve
+    Hdr10,
+    /// 16 bit float scRGB, values above 1 are brighter than sdr white
+    ExtendedLinear,
+}
+
+impl SwapchainFormatPreference {
+    /// most preferred first
+    fn candidates(self) -> &'static [(vk::Format, vk::ColorSpaceKHR)] {
+        const UNORM: [(vk::Format, vk::ColorSpaceKHR); 2] = [
+            (vk::Format::B8G8R8A8_UNORM, vk::ColorSpaceKHR::SRGB_NONLINEAR),
+            (vk::Format::R8G8B8A8_UNORM, vk::ColorSpaceKHR::SRGB_NONLINEAR),
+        ];
+        const SRGB: [(vk::Format, vk::ColorSpaceKHR); 4] = [
+            (vk::Format::B8G8R8A8_SRGB, vk::ColorSpaceKHR::SRGB_NONLINEAR),
+            (vk::Format::R8G8B8A8_SRGB, vk::ColorSpaceKHR::SRGB_NONLINEAR),
+            UNORM[0],
+            UNORM[1],
+        ];
+        const HDR10: [(vk::Format, vk::ColorSpaceKHR); 6] = [
+            (vk::Format::A2B10G10R10_UNORM_PACK32, vk::ColorSpaceKHR::HDR10_ST2084_EXT),
+            (vk::Format::A2R10G10B10_UNORM_PACK32, vk::ColorSpaceKHR::HDR10_ST2084_EXT),
+            SRGB[0],
+            SRGB[1],
+            UNORM[0],
+            UNORM[1],
+        ];
+        const EXTENDED_LINEAR: [(vk::Format, vk::ColorSpaceKHR); 5] = [
+            (vk::Format::R16G16B16A16_SFLOAT, vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT),
+            SRGB[0],
+            SRGB[1],
+            UNORM[0],
+            UNORM[1],
+        ];
+
+        match self {
+            SwapchainFormatPreference::Unorm => &UNORM,
+            SwapchainFormatPreference::Srgb => &SRGB,
+            SwapchainFormatPreference::Hdr10 => &HDR10,
+            SwapchainFormatPreference::ExtendedLinear => &EXTENDED_LINEAR,
+        }
+    }
+}
+
+/// How shaders drawing to the swapchain encode their colors, must match output.glsl.
+/// Colors are shaded as if srgb encoded like the textures they come from
+#[repr(u32)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OutputTransfer {
+    /// written as shaded
+    None = 0,
+    /// decoded to linear, the swapchain encodes them again on write
+    Srgb = 1,
+    /// hdr10
+    Pq = 2,
+    /// scRGB
+    Linear = 3,
+}
+
+impl OutputTransfer {
+    pub fn of(surface_format: vk::SurfaceFormatKHR) -> Self {
+        match surface_format.color_space {
+            vk::ColorSpaceKHR::HDR10_ST2084_EXT => OutputTransfer::Pq,
+            vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT => OutputTransfer::Linear,
+            _ => match surface_format.format {
+                vk::Format::B8G8R8A8_SRGB | vk::Format::R8G8B8A8_SRGB | vk::Format::A8B8G8R8_SRGB_PACK32 => {
+                    OutputTransfer::Srgb
+                }
+                _ => OutputTransfer::None,
+            },
+        }
+    }
+}
+
+/// brightness of sdr white on hdr displays, must match output.glsl
+pub const PAPER_WHITE_NITS: f32 = 200.0;
+
+/// bytes per texel of swapchain formats screenshots can decode
+pub fn screenshot_texel_size(format: vk::Format) -> Option<u32> {
+    match format {
+        vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB
+        | vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::A2B10G10R10_UNORM_PACK32 | vk::Format::A2R10G10B10_UNORM_PACK32 => Some(4),
+        vk::Format::R16G16B16A16_SFLOAT => Some(8),
+        _ => None,
+    }
+}
+
+/// Tightly packed swapchain texels to rgba8 as the shaders shaded them, undoing `OutputTransfer`.
+/// Hdr outputs are tone mapped, highlights brighter than sdr white don't fit in 8 bits
+pub fn decode_screenshot(format: vk::Format, output_transfer: OutputTransfer, texels: &[u8]) -> Vec<u8> {
+    use crate::pixels::{bt2020_to_bt709, half_to_f32, linear_to_srgb, pq_to_nits, tonemap};
+
+    let texel_size = screenshot_texel_size(format).expect("Unsupported screenshot format") as usize;
+    // rgba, 0..=1 encoded by the output transfer
+    let decode_texel = |texel: &[u8]| -> [f32; 4] {
+        match format {
+            vk::Format::R16G16B16A16_SFLOAT => {
+                [0, 1, 2, 3].map(|c| half_to_f32(u16::from_le_bytes([texel[2 * c], texel[2 * c + 1]])))
+            }
+            vk::Format::A2B10G10R10_UNORM_PACK32 | vk::Format::A2R10G10B10_UNORM_PACK32 => {
+                let packed = u32::from_le_bytes([texel[0], texel[1], texel[2], texel[3]]);
+                let [low, middle, high] = [0, 10, 20].map(|shift| ((packed >> shift) & 0x3ff) as f32 / 1023.0);
+                let alpha = (packed >> 30) as f32 / 3.0;
+                match format {
+                    vk::Format::A2B10G10R10_UNORM_PACK32 => [low, middle, high, alpha],
+                    _ => [high, middle, low, alpha],
+                }
+            }
+            vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => [2, 1, 0, 3].map(|c| texel[c] as f32 / 255.0),
+            _ => [0, 1, 2, 3].map(|c| texel[c] as f32 / 255.0),
+        }
+    };
+    // linear with sdr white at 1, `None` when the value is already what was shaded
+    let to_linear = |[r, g, b, _]: [f32; 4]| -> Option<[f32; 3]> {
+        match output_transfer {
+            OutputTransfer::None | OutputTransfer::Srgb => None,
+            OutputTransfer::Linear => Some([r, g, b].map(|c| c * 80.0 / PAPER_WHITE_NITS)),
+            OutputTransfer::Pq => Some(bt2020_to_bt709([r, g, b].map(pq_to_nits)).map(|c| c / PAPER_WHITE_NITS)),
+        }
+    };
+
+    let mut pixels = Vec::with_capacity(texels.len() / texel_size * 4);
+    for texel in texels.chunks_exact(texel_size) {
+        let rgba = decode_texel(texel);
+        let rgb = match to_linear(rgba) {
+            Some(linear) => linear.map(|c| linear_to_srgb(tonemap(c))),
+            None => [rgba[0], rgba[1], rgba[2]],
+        };
+        pixels.extend([rgb[0], rgb[1], rgb[2], rgba[3]].map(|c| (c.clamp(0.0, 1.0) * 255.0 + 0.5) as u8));
+    }
+    pixels
+}
+
+/// What swapchains are created for, the same across renewals
+#[derive(Clone, Copy)]
+pub struct SwapchainContext<'a> {
+    pub instance: &'a ash::Instance,
+    pub physical_device: vk::PhysicalDevice,
+    pub device: &'a ash::Device,
+    pub surface: &'a Surface,
+    pub surface_khr: vk::SurfaceKHR,
+    pub graphics_family_index: u32,
+    pub present_family_index: u32,
+}
+
+/// the usage flags returned are those the images were created with,
+/// transfer source and destination only when the surface supports them
 pub fn new_swapchain_and_images(
-    instance: &ash::Instance,
-    physical_device: vk::PhysicalDevice,
-    device: &ash::Device,
-    surface: &Surface,
-    surface_khr: vk::SurfaceKHR,
+    context: &SwapchainContext,
     preferred_swapchain_extent: vk::Extent2D,
-    graphics_family_index: u32,
-    present_family_index: u32,
+    vsync: bool,
+    format_preference: SwapchainFormatPreference,
 ) -> (
     Swapchain,
     vk::SwapchainKHR,
     Vec<vk::Image>,
     Vec<vk::ImageView>,
-    vk::Format,
+    vk::SurfaceFormatKHR,
     vk::Extent2D,
+    vk::ImageUsageFlags,
 ) {
+    let &SwapchainContext {
+        instance,
+        physical_device,
+        device,
+        surface,
+        surface_khr,
+        graphics_family_index,
+        present_family_index,
+    } = context;
+
     let (capabilities, formats, present_modes) = unsafe {
         (
             surface
@@ -34,11 +198,15 @@ pub fn new_swapchain_and_images(
         )
     };
 
-    let format = choose_swapchain_format(&formats);
-    let present_mode = choose_swapchain_present_mode(&present_modes);
+    let format = choose_swapchain_format(&formats, format_preference);
+    let present_mode = choose_swapchain_present_mode(&present_modes, vsync);
     let extent = choose_swapchain_extent(&capabilities, preferred_swapchain_extent);
     let image_count = (capabilities.min_image_count + 1).min(capabilities.max_image_count);
 
+    // allows reading back presented images for screenshots and upscaling into them with a blit
+    let image_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT
+        | (capabilities.supported_usage_flags & (vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST));
+
     log::debug!(
         "Creating swapchain.\n\tFormat: {:?}\n\tColorSpace: {:?}\n\tPresentMode: {:?}\n\tExtent: {:?}\n\tImageCount: {:?}",
         format.format,
@@ -56,7 +224,7 @@ pub fn new_swapchain_and_images(
             .image_color_space(format.color_space)
             .image_extent(extent)
             .image_array_layers(1)
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT);
+            .image_usage(image_usage);
 
         let indices = [graphics_family_index, present_family_index];
         builder = if graphics_family_index != present_family_index {
@@ -85,7 +253,8 @@ pub fn new_swapchain_and_images(
                 device, 
                 image, 
                 format.format, 
-                vk::ImageAspectFlags::COLOR
+                vk::ImageAspectFlags::COLOR,
+                1,
             )
         })
         .collect();
@@ -95,8 +264,9 @@ pub fn new_swapchain_and_images(
         swapchain_khr,
         swapchain_images,
         swapchain_image_views,
-        format.format,
+        format,
         extent,
+        image_usage,
     )
 }
 
@@ -104,14 +274,24 @@ pub fn new_swapchain_framebuffers(
     device: &ash::Device,
     image_views: &[vk::ImageView],
     swapchain_depth_image_view: vk::ImageView,
+    // attached after color and depth, e.g. g-buffer render targets
+    extra_attachments: &[vk::ImageView],
     render_pass: vk::RenderPass,
     extent: vk::Extent2D,
 ) -> Vec<vk::Framebuffer> {
+    // dynamic rendering has no render pass to make framebuffers for
+    if render_pass == vk::RenderPass::null() {
+        return Vec::new();
+    }
+
     image_views
         .iter()
         .map(|&image_view| {
+            let mut attachments = vec![image_view, swapchain_depth_image_view];
+            attachments.extend_from_slice(extra_attachments);
+
             let info = vk::FramebufferCreateInfo::builder()
-                .attachments(&[image_view, swapchain_depth_image_view])
+                .attachments(&attachments)
                 .render_pass(render_pass)
                 .width(extent.width)
                 .height(extent.height)
@@ -123,30 +303,37 @@ pub fn new_swapchain_framebuffers(
         .collect()
 }
 
-fn choose_swapchain_format(formats: &[vk::SurfaceFormatKHR]) -> vk::SurfaceFormatKHR {
+/// the preference's first candidate the surface supports, otherwise whatever it lists first
+fn choose_swapchain_format(
+    formats: &[vk::SurfaceFormatKHR],
+    preference: SwapchainFormatPreference,
+) -> vk::SurfaceFormatKHR {
+    let candidates = preference.candidates();
+    // any format goes
     if formats.len() == 1 && formats[0].format == vk::Format::UNDEFINED {
-        return vk::SurfaceFormatKHR {
-            format: vk::Format::B8G8R8A8_UNORM,
-            color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
-        };
+        let (format, color_space) = candidates[0];
+        return vk::SurfaceFormatKHR { format, color_space };
     }
 
-    *formats
-        .iter()
-        .find(|f| {
-            f.format == vk::Format::B8G8R8A8_UNORM
-                && f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
-        })
-        .unwrap_or(&formats[0])
+    for &(format, color_space) in candidates {
+        if formats.iter().any(|f| f.format == format && f.color_space == color_space) {
+            return vk::SurfaceFormatKHR { format, color_space };
+        }
+    }
+    log::warn!("Surface has none of the {:?} formats, using {:?}", preference, formats[0]);
+    formats[0]
 }
 
-fn choose_swapchain_present_mode(present_modes: &[vk::PresentModeKHR]) -> vk::PresentModeKHR {
-    if present_modes.contains(&vk::PresentModeKHR::MAILBOX) {
-        vk::PresentModeKHR::MAILBOX
-    } else if present_modes.contains(&vk::PresentModeKHR::FIFO) {
+/// fifo is always supported
+fn choose_swapchain_present_mode(present_modes: &[vk::PresentModeKHR], vsync: bool) -> vk::PresentModeKHR {
+    if vsync {
         vk::PresentModeKHR::FIFO
-    } else {
+    } else if present_modes.contains(&vk::PresentModeKHR::MAILBOX) {
+        vk::PresentModeKHR::MAILBOX
+    } else if present_modes.contains(&vk::PresentModeKHR::IMMEDIATE) {
         vk::PresentModeKHR::IMMEDIATE
+    } else {
+        vk::PresentModeKHR::FIFO
     }
 }
 
@@ -170,3 +357,116 @@ fn choose_swapchain_extent(
         .max(min.height);
     vk::Extent2D { width, height }
 }
+
+/// Window resizes only take effect when the swapchain is renewed at the start of a frame,
+/// so a frame never sees the extent change while it is being recorded
+#[derive(Default)]
+pub struct ResizeTracker {
+    /// latest extent the window reported, older ones are dropped
+    pending_extent: Option<vk::Extent2D>,
+    /// swapchain was reported out of date or suboptimal
+    out_of_date: bool,
+}
+
+impl ResizeTracker {
+    pub fn request_resize(&mut self, extent: vk::Extent2D) {
+        self.pending_extent = Some(extent);
+    }
+
+    pub fn mark_out_of_date(&mut self) {
+        self.out_of_date = true;
+    }
+
+    pub fn needs_renewal(&self) -> bool {
+        self.out_of_date || self.pending_extent.is_some()
+    }
+
+    /// window has no area so nothing can be presented
+    pub fn is_minimized(&self) -> bool {
+        matches!(self.pending_extent, Some(extent) if extent.width == 0 || extent.height == 0)
+    }
+
+    /// extent to renew the swapchain with, a minimized extent stays pending
+    pub fn take_renewal_extent(&mut self, current_extent: vk::Extent2D) -> vk::Extent2D {
+        if self.is_minimized() {
+            return current_extent;
+        }
+        self.out_of_date = false;
+        self.pending_extent.take().unwrap_or(current_extent)
+    }
+}
+
+#[test]
+fn test_resize_event_storm() {
+    let start = vk::Extent2D { width: 800, height: 600 };
+    let mut tracker = ResizeTracker::default();
+    assert!(!tracker.needs_renewal());
+
+    // a storm of resizes between two frames only renews once, with the last extent
+    for i in 0..1000 {
+        tracker.request_resize(vk::Extent2D { width: 800 + i, height: 600 + i });
+    }
+    assert!(tracker.needs_renewal());
+    let extent = tracker.take_renewal_extent(start);
+    assert!(extent == vk::Extent2D { width: 1799, height: 1599 });
+    assert!(!tracker.needs_renewal());
+
+    // minimizing mid storm keeps the renewal pending until the window has area again
+    tracker.request_resize(vk::Extent2D { width: 1024, height: 768 });
+    tracker.request_resize(vk::Extent2D { width: 0, height: 0 });
+    assert!(tracker.is_minimized());
+    assert!(tracker.take_renewal_extent(extent) == extent);
+    assert!(tracker.needs_renewal());
+    tracker.request_resize(vk::Extent2D { width: 640, height: 480 });
+    assert!(!tracker.is_minimized());
+    assert!(tracker.take_renewal_extent(extent) == vk::Extent2D { width: 640, height: 480 });
+
+    // out of date without a resize renews at the current extent
+    tracker.mark_out_of_date();
+    assert!(tracker.needs_renewal());
+    assert!(tracker.take_renewal_extent(start) == start);
+    assert!(!tracker.needs_renewal());
+}
+
+#[test]
+fn test_choose_swapchain_format() {
+    let surface_format = |format, color_space| vk::SurfaceFormatKHR { format, color_space };
+    let sdr = surface_format(vk::Format::B8G8R8A8_UNORM, vk::ColorSpaceKHR::SRGB_NONLINEAR);
+    let srgb = surface_format(vk::Format::B8G8R8A8_SRGB, vk::ColorSpaceKHR::SRGB_NONLINEAR);
+    let hdr10 = surface_format(vk::Format::A2B10G10R10_UNORM_PACK32, vk::ColorSpaceKHR::HDR10_ST2084_EXT);
+    // same format but not hdr
+    let wide = surface_format(vk::Format::A2B10G10R10_UNORM_PACK32, vk::ColorSpaceKHR::SRGB_NONLINEAR);
+
+    let formats = [wide, sdr, srgb, hdr10];
+    assert!(choose_swapchain_format(&formats, SwapchainFormatPreference::Unorm) == sdr);
+    assert!(choose_swapchain_format(&formats, SwapchainFormatPreference::Srgb) == srgb);
+    assert!(choose_swapchain_format(&formats, SwapchainFormatPreference::Hdr10) == hdr10);
+    // no scRGB, the next best
+    assert!(choose_swapchain_format(&formats, SwapchainFormatPreference::ExtendedLinear) == srgb);
+    assert!(choose_swapchain_format(&[wide, sdr], SwapchainFormatPreference::Hdr10) == sdr);
+    assert!(choose_swapchain_format(&[wide], SwapchainFormatPreference::Srgb) == wide);
+
+    assert!(OutputTransfer::of(sdr) == OutputTransfer::None && OutputTransfer::of(wide) == OutputTransfer::None);
+    assert!(OutputTransfer::of(srgb) == OutputTransfer::Srgb && OutputTransfer::of(hdr10) == OutputTransfer::Pq);
+}
+
+#[test]
+fn test_decode_screenshot() {
+    let bgra = [10, 20, 30, 255];
+    assert!(decode_screenshot(vk::Format::B8G8R8A8_UNORM, OutputTransfer::None, &bgra) == [30, 20, 10, 255]);
+    assert!(decode_screenshot(vk::Format::R8G8B8A8_SRGB, OutputTransfer::Srgb, &bgra) == bgra);
+
+    // scRGB black, then 2.5 which is sdr white at 200 nits, a little darker after tone mapping
+    let scrgb = |half: u16| [half, half, half, 0x3c00].iter().flat_map(|half| half.to_le_bytes()).collect::<Vec<_>>();
+    assert!(decode_screenshot(vk::Format::R16G16B16A16_SFLOAT, OutputTransfer::Linear, &scrgb(0x0000)) == [0, 0, 0, 255]);
+    let white = decode_screenshot(vk::Format::R16G16B16A16_SFLOAT, OutputTransfer::Linear, &scrgb(0x4100));
+    assert!(white[0] > 230 && white[0] < 255 && white[0] == white[2] && white[3] == 255);
+
+    // hdr10 black with 2 bit alpha, red and blue swapped between the two packings
+    let packed = |r: u32, g: u32, b: u32| (r | (g << 10) | (b << 20) | (3 << 30)).to_le_bytes();
+    assert!(decode_screenshot(vk::Format::A2B10G10R10_UNORM_PACK32, OutputTransfer::Pq, &packed(0, 0, 0)) == [0, 0, 0, 255]);
+    let abgr = decode_screenshot(vk::Format::A2B10G10R10_UNORM_PACK32, OutputTransfer::None, &packed(1023, 0, 0));
+    let argb = decode_screenshot(vk::Format::A2R10G10B10_UNORM_PACK32, OutputTransfer::None, &packed(1023, 0, 0));
+    assert!(abgr == [255, 0, 0, 255] && argb == [0, 0, 255, 255]);
+    assert!(screenshot_texel_size(vk::Format::R16G16B16A16_SFLOAT) == Some(8) && screenshot_texel_size(vk::Format::D32_SFLOAT).is_none());
+}
@@ -0,0 +1,266 @@
+// Barriers and queue submissions through synchronization2, core in 1.3 and VK_KHR_synchronization2
+// on 1.2, with a fallback to the 1.0 commands on devices without it. Both take synchronization2's
+// 64 bit stage and access flags, the fallback narrows them to the 1.0 ones covering them:
+//
+//     sync.cmd_pipeline_barrier(command_buffer, &[], &[image_barrier]);
+//     sync.queue_submit(queue, &[Submit {
+//         wait_semaphores: &[(image_available, vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)],
+//         command_buffers: &[command_buffer],
+//         signal_semaphores: &[render_finished],
+//     }], fence)?;
+//
+// Image layout transitions and the frame's submissions go through it, passes recording their own
+// 1.0 barriers mix with these freely
+
+use std::rc::Rc;
+
+use ash::{extensions::khr::Synchronization2, prelude::VkResult, vk};
+
+use super::device::DeviceFeatures;
+
+enum Api {
+    Core,
+    Khr(Synchronization2),
+    /// 1.0 commands
+    Fallback,
+}
+
+/// a batch of command buffers for `Synchronization::queue_submit`
+pub struct Submit<'a> {
+    /// each with the stages that wait on it
+    pub wait_semaphores: &'a [(vk::Semaphore, vk::PipelineStageFlags2)],
+    pub command_buffers: &'a [vk::CommandBuffer],
+    /// signalled once every command has finished
+    pub signal_semaphores: &'a [vk::Semaphore],
+}
+
+pub struct Synchronization {
+    device: Rc<ash::Device>,
+    api: Api,
+}
+
+impl Synchronization {
+    /// `features` the device was created with
+    pub fn new(instance: &ash::Instance, device: Rc<ash::Device>, features: &DeviceFeatures) -> Self {
+        let api = if !features.synchronization2 {
+            Api::Fallback
+        } else if features.uses_synchronization2_extension() {
+            Api::Khr(Synchronization2::new(instance, &device))
+        } else {
+            Api::Core
+        };
+        Self { device, api }
+    }
+
+    /// whether barriers and submissions go through synchronization2 rather than the fallback
+    pub fn is_synchronization2(&self) -> bool {
+        !matches!(self.api, Api::Fallback)
+    }
+
+    /// the fallback records one barrier over every stage of the given ones
+    pub fn cmd_pipeline_barrier(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        memory_barriers: &[vk::MemoryBarrier2],
+        image_barriers: &[vk::ImageMemoryBarrier2],
+    ) {
+        let dependency_info = vk::DependencyInfo::builder()
+            .memory_barriers(memory_barriers)
+            .image_memory_barriers(image_barriers);
+        match &self.api {
+            Api::Core => unsafe { self.device.cmd_pipeline_barrier2(command_buffer, &dependency_info) },
+            Api::Khr(khr) => unsafe { khr.cmd_pipeline_barrier2(command_buffer, &dependency_info) },
+            Api::Fallback => {
+                let mut src_stages = vk::PipelineStageFlags2::NONE;
+                let mut dst_stages = vk::PipelineStageFlags2::NONE;
+                let memory_barriers: Vec<_> = memory_barriers
+                    .iter()
+                    .map(|barrier| {
+                        src_stages |= barrier.src_stage_mask;
+                        dst_stages |= barrier.dst_stage_mask;
+                        vk::MemoryBarrier::builder()
+                            .src_access_mask(fallback_access(barrier.src_access_mask))
+                            .dst_access_mask(fallback_access(barrier.dst_access_mask))
+                            .build()
+                    })
+                    .collect();
+                let image_barriers: Vec<_> = image_barriers
+                    .iter()
+                    .map(|barrier| {
+                        src_stages |= barrier.src_stage_mask;
+                        dst_stages |= barrier.dst_stage_mask;
+                        vk::ImageMemoryBarrier::builder()
+                            .src_access_mask(fallback_access(barrier.src_access_mask))
+                            .dst_access_mask(fallback_access(barrier.dst_access_mask))
+                            .old_layout(barrier.old_layout)
+                            .new_layout(barrier.new_layout)
+                            .src_queue_family_index(barrier.src_queue_family_index)
+                            .dst_queue_family_index(barrier.dst_queue_family_index)
+                            .image(barrier.image)
+                            .subresource_range(barrier.subresource_range)
+                            .build()
+                    })
+                    .collect();
+                unsafe {
+                    self.device.cmd_pipeline_barrier(
+                        command_buffer,
+                        fallback_stages(src_stages, vk::PipelineStageFlags::TOP_OF_PIPE),
+                        fallback_stages(dst_stages, vk::PipelineStageFlags::BOTTOM_OF_PIPE),
+                        vk::DependencyFlags::empty(),
+                        &memory_barriers,
+                        &[],
+                        &image_barriers,
+                    );
+                }
+            }
+        }
+    }
+
+    /// `fence` is signalled once every submission has finished
+    pub fn queue_submit(&self, queue: vk::Queue, submits: &[Submit], fence: vk::Fence) -> VkResult<()> {
+        if let Api::Fallback = self.api {
+            let wait_stages: Vec<Vec<_>> = submits
+                .iter()
+                .map(|submit| submit.wait_semaphores
+                    .iter()
+                    .map(|&(_, stages)| fallback_stages(stages, vk::PipelineStageFlags::BOTTOM_OF_PIPE))
+                    .collect()
+                )
+                .collect();
+            let wait_semaphores: Vec<Vec<_>> = submits
+                .iter()
+                .map(|submit| submit.wait_semaphores.iter().map(|&(semaphore, _)| semaphore).collect())
+                .collect();
+            let submit_infos: Vec<_> = submits
+                .iter()
+                .enumerate()
+                .map(|(i, submit)| vk::SubmitInfo::builder()
+                    .wait_semaphores(&wait_semaphores[i])
+                    .wait_dst_stage_mask(&wait_stages[i])
+                    .command_buffers(submit.command_buffers)
+                    .signal_semaphores(submit.signal_semaphores)
+                    .build()
+                )
+                .collect();
+            return unsafe { self.device.queue_submit(queue, &submit_infos, fence) };
+        }
+
+        let semaphore_info = |semaphore, stage_mask| vk::SemaphoreSubmitInfo::builder()
+            .semaphore(semaphore)
+            .stage_mask(stage_mask)
+            .build();
+        let wait_infos: Vec<Vec<_>> = submits
+            .iter()
+            .map(|submit| submit.wait_semaphores
+                .iter()
+                .map(|&(semaphore, stages)| semaphore_info(semaphore, stages))
+                .collect()
+            )
+            .collect();
+        let command_buffer_infos: Vec<Vec<_>> = submits
+            .iter()
+            .map(|submit| submit.command_buffers
+                .iter()
+                .map(|&command_buffer| vk::CommandBufferSubmitInfo::builder().command_buffer(command_buffer).build())
+                .collect()
+            )
+            .collect();
+        let signal_infos: Vec<Vec<_>> = submits
+            .iter()
+            .map(|submit| submit.signal_semaphores
+                .iter()
+                .map(|&semaphore| semaphore_info(semaphore, vk::PipelineStageFlags2::ALL_COMMANDS))
+                .collect()
+            )
+            .collect();
+        let submit_infos: Vec<_> = (0..submits.len())
+            .map(|i| vk::SubmitInfo2::builder()
+                .wait_semaphore_infos(&wait_infos[i])
+                .command_buffer_infos(&command_buffer_infos[i])
+                .signal_semaphore_infos(&signal_infos[i])
+                .build()
+            )
+            .collect();
+        match &self.api {
+            Api::Khr(khr) => unsafe { khr.queue_submit2(queue, &submit_infos, fence) },
+            _ => unsafe { self.device.queue_submit2(queue, &submit_infos, fence) },
+        }
+    }
+}
+
+/// the 1.0 stages covering `stages`, `none` where there are none, which 1.0 doesn't allow
+pub fn fallback_stages(stages: vk::PipelineStageFlags2, none: vk::PipelineStageFlags) -> vk::PipelineStageFlags {
+    use vk::PipelineStageFlags2 as Stage2;
+    use vk::PipelineStageFlags as Stage;
+
+    // the 1.0 stages have the same bits, the split up ones are above them
+    let mut fallback = Stage::from_raw(stages.as_raw() as u32);
+    let split = [
+        (Stage2::COPY | Stage2::RESOLVE | Stage2::BLIT | Stage2::CLEAR, Stage::TRANSFER),
+        (Stage2::INDEX_INPUT | Stage2::VERTEX_ATTRIBUTE_INPUT, Stage::VERTEX_INPUT),
+        (
+            Stage2::PRE_RASTERIZATION_SHADERS,
+            Stage::VERTEX_SHADER
+                | Stage::TESSELLATION_CONTROL_SHADER
+                | Stage::TESSELLATION_EVALUATION_SHADER
+                | Stage::GEOMETRY_SHADER,
+        ),
+    ];
+    for (split_stages, stage) in split {
+        if stages.intersects(split_stages) {
+            fallback |= stage;
+        }
+    }
+    if fallback.is_empty() { none } else { fallback }
+}
+
+/// the 1.0 accesses covering `access`
+pub fn fallback_access(access: vk::AccessFlags2) -> vk::AccessFlags {
+    use vk::AccessFlags2 as Access2;
+    use vk::AccessFlags as Access;
+
+    let mut fallback = Access::from_raw(access.as_raw() as u32);
+    if access.intersects(Access2::SHADER_SAMPLED_READ | Access2::SHADER_STORAGE_READ) {
+        fallback |= Access::SHADER_READ;
+    }
+    if access.contains(Access2::SHADER_STORAGE_WRITE) {
+        fallback |= Access::SHADER_WRITE;
+    }
+    fallback
+}
+
+#[test]
+fn test_synchronization() {
+    use vk::{AccessFlags, AccessFlags2, ImageLayout, PipelineStageFlags, PipelineStageFlags2};
+
+    // 1.0 stages keep their bits, split up ones widen to the stage they were part of
+    let stages = PipelineStageFlags2::FRAGMENT_SHADER | PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT;
+    assert!(fallback_stages(stages, PipelineStageFlags::TOP_OF_PIPE)
+        == PipelineStageFlags::FRAGMENT_SHADER | PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT);
+    assert!(fallback_stages(PipelineStageFlags2::BLIT, PipelineStageFlags::TOP_OF_PIPE) == PipelineStageFlags::TRANSFER);
+    assert!(fallback_stages(PipelineStageFlags2::ALL_TRANSFER, PipelineStageFlags::TOP_OF_PIPE) == PipelineStageFlags::TRANSFER);
+    assert!(fallback_stages(PipelineStageFlags2::INDEX_INPUT, PipelineStageFlags::TOP_OF_PIPE) == PipelineStageFlags::VERTEX_INPUT);
+    assert!(fallback_stages(PipelineStageFlags2::PRE_RASTERIZATION_SHADERS, PipelineStageFlags::TOP_OF_PIPE)
+        .contains(PipelineStageFlags::VERTEX_SHADER | PipelineStageFlags::GEOMETRY_SHADER));
+    // 1.0 needs a stage on either side
+    assert!(fallback_stages(PipelineStageFlags2::NONE, PipelineStageFlags::TOP_OF_PIPE) == PipelineStageFlags::TOP_OF_PIPE);
+    assert!(fallback_stages(PipelineStageFlags2::NONE, PipelineStageFlags::BOTTOM_OF_PIPE) == PipelineStageFlags::BOTTOM_OF_PIPE);
+
+    assert!(fallback_access(AccessFlags2::TRANSFER_WRITE | AccessFlags2::COLOR_ATTACHMENT_READ)
+        == AccessFlags::TRANSFER_WRITE | AccessFlags::COLOR_ATTACHMENT_READ);
+    assert!(fallback_access(AccessFlags2::SHADER_SAMPLED_READ) == AccessFlags::SHADER_READ);
+    assert!(fallback_access(AccessFlags2::SHADER_STORAGE_READ | AccessFlags2::SHADER_STORAGE_WRITE)
+        == AccessFlags::SHADER_READ | AccessFlags::SHADER_WRITE);
+    assert!(fallback_access(AccessFlags2::NONE).is_empty());
+
+    // transitions out of UNDEFINED wait at the destination's stages, for the acquire semaphore
+    let ((src_stage, src_access), (dst_stage, dst_access)) =
+        super::image::transition_scopes(ImageLayout::UNDEFINED, ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+    assert!(src_stage == PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT && src_access.is_empty());
+    assert!(dst_stage == src_stage && dst_access.contains(AccessFlags2::COLOR_ATTACHMENT_WRITE));
+    let ((src_stage, src_access), (dst_stage, dst_access)) =
+        super::image::transition_scopes(ImageLayout::TRANSFER_DST_OPTIMAL, ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+    assert!(fallback_stages(src_stage, PipelineStageFlags::TOP_OF_PIPE) == PipelineStageFlags::TRANSFER);
+    assert!(fallback_access(src_access) == AccessFlags::TRANSFER_WRITE);
+    assert!(dst_stage == PipelineStageFlags2::FRAGMENT_SHADER && fallback_access(dst_access) == AccessFlags::SHADER_READ);
+}
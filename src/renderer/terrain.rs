@@ -0,0 +1,260 @@
+// Draws the chunks of a `terrain::Terrain` in the opaque scene subpass. Every chunk is meshed at every
+// level of detail up front, each frame only picks which of them to draw.
+// The splat map's channels weigh four tiled layer textures, all from the textures array
+
+use std::{mem::size_of, rc::Rc};
+
+use ash::vk;
+
+use crate::{geometry::{self, Index}, math::{Frustum, Vector}, terrain::Terrain};
+use super::{buffer::Buffer, gbuffer, pipeline, swapchain::OutputTransfer, RenderPath};
+
+/// indices into the textures descriptor array, must match the push constants of terrain.frag
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct TerrainMaterial {
+    /// rgba weighs the layers
+    pub splat_texture: u32,
+    pub layer_textures: [u32; 4],
+    /// layer repeats across the whole terrain
+    pub layer_tiling: f32,
+}
+
+impl TerrainMaterial {
+    pub const RANGE: vk::PushConstantRange = vk::PushConstantRange {
+        stage_flags: vk::ShaderStageFlags::FRAGMENT,
+        offset: 0,
+        size: size_of::<Self>() as u32,
+    };
+}
+
+/// where a chunk's mesh at one level of detail lives in the buffers
+#[derive(Clone, Copy)]
+struct ChunkMesh {
+    vertex_offset: i32,
+    first_index: u32,
+    index_count: u32,
+}
+
+/// Set a terrain, `build` each frame and `cmd_draw` in the opaque scene subpass.
+/// The pipeline depends on the scene render pass, `renew_pipeline` when it changes
+pub struct TerrainRenderer {
+    device: Rc<ash::Device>,
+    terrain: Option<Terrain>,
+    material: TerrainMaterial,
+    /// indexed by chunk * lod count + lod
+    chunk_meshes: Vec<ChunkMesh>,
+    /// host visible, only written when the terrain is set
+    // TODO: device local, static after upload
+    vertex_buffer: Option<Buffer>,
+    index_buffer: Option<Buffer>,
+    /// chunks drawn this frame
+    draws: Vec<ChunkMesh>,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl TerrainRenderer {
+    pub fn new(device: Rc<ash::Device>) -> Self {
+        Self {
+            device,
+            terrain: None,
+            material: TerrainMaterial::default(),
+            chunk_meshes: vec![],
+            vertex_buffer: None,
+            index_buffer: None,
+            draws: vec![],
+            pipeline_layout: vk::PipelineLayout::null(),
+            pipeline: vk::Pipeline::null(),
+        }
+    }
+
+    /// `render_pass` null for dynamic rendering, draws in the subpass filling the g-buffer on the deferred path
+    pub fn renew_pipeline(
+        &mut self,
+        context: &pipeline::PassContext,
+        render_pass: vk::RenderPass,
+    ) {
+        let pipeline::PassContext { shader_compiler, render_path, color_format, depth_format, per_frame_ubo_set_layout, textures_set_layout, output_transfer, reverse_z, .. } = *context;
+        unsafe { self.destroy_pipeline(); }
+
+        (self.pipeline, self.pipeline_layout) = pipeline::new_pipeline_and_layout(
+            &self.device,
+            shader_compiler,
+            &pipeline::PipelineDesc {
+                render_pass,
+                color_formats: &[color_format],
+                depth_format,
+                set_layouts: &[per_frame_ubo_set_layout, textures_set_layout],
+                push_constant_ranges: &[TerrainMaterial::RANGE],
+                vertex_shader_path: "shaders/terrain.vert",
+                fragment_shader_path: match render_path {
+                    RenderPath::Forward => "shaders/terrain.frag",
+                    RenderPath::Deferred => "shaders/terrain_gbuffer.frag",
+                },
+                vertex_attributes: &geometry::VERTEX_ATTRIBUTES,
+                color_attachment_count: match render_path {
+                    RenderPath::Forward => 1,
+                    RenderPath::Deferred => gbuffer::GBUFFER_FORMATS.len() as u32,
+                },
+                output_transfer: match render_path {
+                    RenderPath::Forward => output_transfer,
+                    // the lighting subpass writes the swapchain
+                    RenderPath::Deferred => OutputTransfer::None,
+                },
+                reverse_z,
+                ..Default::default()
+            },
+        );
+    }
+
+    pub fn get_terrain(&self) -> Option<&Terrain> {
+        self.terrain.as_ref()
+    }
+
+    /// meshes every chunk at every level of detail, replacing the previous terrain,
+    /// the device must not be using the previous terrain's buffers
+    pub fn set_terrain(
+        &mut self,
+        terrain: Terrain,
+        material: TerrainMaterial,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    ) {
+        let lod_count = terrain.get_desc().lod_count;
+        let mut vertices = vec![];
+        let mut indices: Vec<Index> = vec![];
+        self.chunk_meshes.clear();
+        for chunk in 0..terrain.chunk_count() {
+            for lod in 0..lod_count {
+                let (chunk_vertices, chunk_indices) = terrain.chunk_mesh(chunk, lod);
+                self.chunk_meshes.push(ChunkMesh {
+                    vertex_offset: vertices.len() as i32,
+                    first_index: indices.len() as u32,
+                    index_count: chunk_indices.len() as u32,
+                });
+                vertices.extend(chunk_vertices);
+                indices.extend(chunk_indices);
+            }
+        }
+        log::info!(
+            "Meshed {} terrain chunks at {} levels of detail, {} vertices and {} indices",
+            terrain.chunk_count(), lod_count, vertices.len(), indices.len(),
+        );
+
+        unsafe { self.destroy_buffers(); }
+        let new_buffer = |size, usage| Buffer::new(
+            size as vk::DeviceSize,
+            usage,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            self.device.clone(),
+            physical_device_memory_properties,
+        );
+        let mut vertex_buffer = new_buffer(vertices.len() * size_of::<geometry::Vertex>(), vk::BufferUsageFlags::VERTEX_BUFFER);
+        let mut index_buffer = new_buffer(indices.len() * size_of::<Index>(), vk::BufferUsageFlags::INDEX_BUFFER);
+        vertex_buffer.copy_from_slice(&vertices, 0);
+        index_buffer.copy_from_slice(&indices, 0);
+
+        self.vertex_buffer = Some(vertex_buffer);
+        self.index_buffer = Some(index_buffer);
+        self.terrain = Some(terrain);
+        self.material = material;
+        self.draws.clear();
+    }
+
+    pub fn set_material(&mut self, material: TerrainMaterial) {
+        self.material = material;
+    }
+
+    /// culls the chunks against the view and picks their level of detail
+    pub fn build(&mut self, eye: Vector, frustum: &Frustum) {
+        self.draws.clear();
+        let Some(terrain) = &self.terrain else {
+            return;
+        };
+        let lod_count = terrain.get_desc().lod_count as usize;
+        for (chunk, lod) in terrain.visible_chunks(eye, frustum) {
+            self.draws.push(self.chunk_meshes[chunk * lod_count + lod as usize]);
+        }
+    }
+
+    /// record in the subpass drawing opaque geometry, binds its own pipeline
+    pub fn cmd_draw(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        per_frame_ubo_set: vk::DescriptorSet,
+        per_frame_ubo_offset: u32,
+        textures_set: vk::DescriptorSet,
+    ) {
+        let (Some(vertex_buffer), Some(index_buffer)) = (&self.vertex_buffer, &self.index_buffer) else {
+            return;
+        };
+        if self.draws.is_empty() {
+            return;
+        }
+
+        unsafe {
+            self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[per_frame_ubo_set, textures_set],
+                &[per_frame_ubo_offset],
+            );
+            self.device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                TerrainMaterial::RANGE.stage_flags,
+                0,
+                std::slice::from_raw_parts(
+                    &self.material as *const TerrainMaterial as *const u8,
+                    size_of::<TerrainMaterial>(),
+                ),
+            );
+            self.device.cmd_bind_vertex_buffers(
+                command_buffer,
+                pipeline::VERTEX_BINDING,
+                &[vertex_buffer.handle],
+                &[0],
+            );
+            self.device.cmd_bind_index_buffer(command_buffer, index_buffer.handle, 0, vk::IndexType::UINT32);
+
+            for draw in &self.draws {
+                self.device.cmd_draw_indexed(
+                    command_buffer,
+                    draw.index_count,
+                    1,
+                    draw.first_index,
+                    draw.vertex_offset,
+                    0,
+                );
+            }
+        }
+    }
+
+    unsafe fn destroy_buffers(&mut self) {
+        if let Some(vertex_buffer) = &mut self.vertex_buffer {
+            vertex_buffer.destroy();
+        }
+        if let Some(index_buffer) = &mut self.index_buffer {
+            index_buffer.destroy();
+        }
+        self.vertex_buffer = None;
+        self.index_buffer = None;
+    }
+
+    unsafe fn destroy_pipeline(&mut self) {
+        if self.pipeline != vk::Pipeline::null() {
+            self.device.destroy_pipeline(self.pipeline, None);
+            self.device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+
+    /// # Safety
+    /// call once, after the last frame drawing the terrain finished
+    pub unsafe fn destroy(&mut self) {
+        self.destroy_pipeline();
+        self.destroy_buffers();
+    }
+}
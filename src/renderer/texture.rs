@@ -2,6 +2,13 @@ use std::rc::Rc;
 
 use ash::vk;
 
+use super::{
+    image::ImageDesc,
+    sampler::{SamplerCache, SamplerDesc},
+    synchronization::Synchronization,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum TextureType {
     Diffuse,
     Specular,
@@ -14,78 +21,248 @@ pub struct Texture {
 
     width: u32,
     height: u32,
+    mip_levels: u32,
+    format: vk::Format,
     ty: TextureType,
 
     image: vk::Image,
     pub image_view: vk::ImageView,
+    /// owned by the `SamplerCache`
     pub sampler: vk::Sampler,
     memory: vk::DeviceMemory,
 }
 
+/// what texture files are decoded to
+pub const TEXTURE_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+/// of textures whose `.meta` sets `srgb = true`, they sample as linear colors.
+/// Others keep their encoded values, which the shaders treat as srgb
+pub const SRGB_TEXTURE_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+/// which texture formats the device can generate mips of with blits
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct BlitSupport {
+    pub unorm: bool,
+    pub srgb: bool,
+}
+
+impl BlitSupport {
+    pub fn query(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> Self {
+        Self {
+            unorm: super::image::supports_blit_mips(instance, physical_device, TEXTURE_FORMAT),
+            srgb: super::image::supports_blit_mips(instance, physical_device, SRGB_TEXTURE_FORMAT),
+        }
+    }
+
+    fn supports(self, format: vk::Format) -> bool {
+        if format == SRGB_TEXTURE_FORMAT { self.srgb } else { self.unorm }
+    }
+}
+
+/// A texture file decoded on the cpu, ready for upload.
+/// Decoding doesn't touch the device, so files can be decoded as jobs
+pub struct DecodedTexture {
+    ty: TextureType,
+    /// `TEXTURE_FORMAT` or `SRGB_TEXTURE_FORMAT`
+    format: vk::Format,
+    width: u32,
+    height: u32,
+    mip_levels: u32,
+    pixels: Vec<u8>,
+    /// below the base level, only generated when the format can't be blitted
+    cpu_mips: Vec<crate::pixels::Mip>,
+    pub sampler_desc: SamplerDesc,
+}
+
+impl DecodedTexture {
+    /// mips are left to the device when `blit_support` has the texture's format
+    pub fn decode(path: &str, ty: TextureType, blit_support: BlitSupport) -> Self {
+        let image = image::open(path).unwrap(); //TODO: implement own image reader
+        let image_as_rgb = image.to_rgba();
+        let image_width = image_as_rgb.width();
+        let image_height = image_as_rgb.height();
+        let pixels = image_as_rgb.into_raw();
+        let import_settings = crate::meta::TextureImportSettings::load(path);
+
+        let mip_levels = import_settings.mip_policy.level_count(image_width, image_height);
+        let format = match import_settings.srgb {
+            Some(true) => SRGB_TEXTURE_FORMAT,
+            _ => TEXTURE_FORMAT,
+        };
+
+        // formats that can't be blitted get their mips generated on the cpu
+        // and uploaded along with the base level
+        let cpu_mips = if blit_support.supports(format) || mip_levels == 1 {
+            vec![]
+        } else {
+            let srgb = import_settings.srgb
+                .unwrap_or(matches!(ty, TextureType::Diffuse | TextureType::Specular));
+            let mut mips = crate::pixels::generate_mips_rgba8(image_width, image_height, &pixels, srgb);
+            mips.truncate(mip_levels as usize - 1);
+            mips
+        };
+
+        Self {
+            ty,
+            format,
+            width: image_width,
+            height: image_height,
+            mip_levels,
+            pixels,
+            cpu_mips,
+            sampler_desc: import_settings.sampler_desc(),
+        }
+    }
+
+    /// rgba8 `pixels` generated at runtime, without mips and clamped at the edges
+    pub fn from_pixels(ty: TextureType, width: u32, height: u32, pixels: Vec<u8>) -> Self {
+        assert!(pixels.len() == (width * height * 4) as usize);
+        Self {
+            ty,
+            format: TEXTURE_FORMAT,
+            width,
+            height,
+            mip_levels: 1,
+            pixels,
+            cpu_mips: vec![],
+            sampler_desc: SamplerDesc::default().with_address_mode(vk::SamplerAddressMode::CLAMP_TO_EDGE),
+        }
+    }
+}
+
+/// what uploads and read backs record their transient commands with
+#[derive(Clone, Copy)]
+pub struct TransferContext<'a> {
+    pub sync: &'a Synchronization,
+    pub physical_device_memory_properties: &'a vk::PhysicalDeviceMemoryProperties,
+    pub command_pool: vk::CommandPool,
+    pub queue: vk::Queue,
+    pub queue_family_index: u32,
+}
+
 impl Texture {
     pub fn load(
         path: &str,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
         device: Rc<ash::Device>,
-        physical_device_memory_properties: vk::PhysicalDeviceMemoryProperties,
+        transfer: &TransferContext,
+        sampler_cache: &mut SamplerCache,
         ty: TextureType,
-        transition_command_pool: vk::CommandPool,
-        transition_queue: vk::Queue,
-        transition_family_index: u32,
     ) -> Texture {
-        let image = image::open(path).unwrap(); //TODO: implement own image reader
-        let image_as_rgb = image.to_rgba();
-        let image_width = (&image_as_rgb).width();
-        let image_height = (&image_as_rgb).height();
-        let pixels = image_as_rgb.into_raw();
+        Self::upload(
+            DecodedTexture::decode(path, ty, BlitSupport::query(instance, physical_device)),
+            device,
+            transfer,
+            sampler_cache,
+        )
+    }
 
+    /// mips are blitted unless `decoded` came with them
+    pub fn upload(
+        decoded: DecodedTexture,
+        device: Rc<ash::Device>,
+        transfer: &TransferContext,
+        sampler_cache: &mut SamplerCache,
+    ) -> Texture {
+        let DecodedTexture {
+            ty,
+            format,
+            width: image_width,
+            height: image_height,
+            mip_levels,
+            pixels,
+            cpu_mips,
+            sampler_desc,
+        } = decoded;
+        let blit_mips = cpu_mips.len() + 1 < mip_levels as usize;
+
+        // staged even with resizable BAR, the host can't write optimally tiled images
+        let staging_size = pixels.len() + cpu_mips.iter().map(|mip| mip.pixels.len()).sum::<usize>();
         let mut staging_buffer = super::buffer::Buffer::new(
-            pixels.len() as vk::DeviceSize,
+            staging_size as vk::DeviceSize,
             vk::BufferUsageFlags::TRANSFER_SRC,
             vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
             device.clone(),
-            &physical_device_memory_properties,
+            transfer.physical_device_memory_properties,
         );
 
         staging_buffer.copy_from_slice(&pixels, 0);
+        let mut mip_offsets = Vec::with_capacity(cpu_mips.len());
+        let mut offset = pixels.len() as vk::DeviceSize;
+        for mip in &cpu_mips {
+            // bytes, so the element index is the offset
+            staging_buffer.copy_from_slice(&mip.pixels, offset as usize);
+            mip_offsets.push(offset);
+            offset += mip.pixels.len() as vk::DeviceSize;
+        }
 
         let mut texture = Self::new(
             device.clone(),
-            &physical_device_memory_properties,
-            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            transfer.physical_device_memory_properties,
             ty,
-            image_width,
-            image_height,
-            vk::Format::R8G8B8A8_UNORM,
-            vk::ImageTiling::OPTIMAL,
-            vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+            &ImageDesc {
+                width: image_width,
+                height: image_height,
+                mip_levels,
+                usage: vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+                format,
+                ..Default::default()
+            },
+            sampler_cache.get(&sampler_desc),
         );
 
-        crate::VkApp::execute_transient_commands(
+        crate::renderer::VkApp::execute_transient_commands(
             &device, 
-            transition_command_pool, 
-            transition_queue, 
+            transfer.command_pool, 
+            transfer.queue, 
             |transition_command_buffer| {
                 super::image::cmd_transition_image_layout(
-                    &device,
-                    texture.image,
+                    transfer.sync,
                     transition_command_buffer,
-                    transition_family_index,
-                    vk::Format::R8G8B8A8_UNORM,
-                    vk::ImageLayout::UNDEFINED,
-                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    transfer.queue_family_index,
+                    &super::image::ImageTransition {
+                        image: texture.image,
+                        format,
+                        mip_levels,
+                        old_layout: vk::ImageLayout::UNDEFINED,
+                        new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    },
                 );
     
-                texture.cmd_copy_from_buffer(transition_command_buffer, &staging_buffer);
-    
-                super::image::cmd_transition_image_layout(
-                    &device,
-                    texture.image,
-                    transition_command_buffer,
-                    transition_family_index,
-                    vk::Format::R8G8B8A8_UNORM,
-                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-                );
+                texture.cmd_copy_from_buffer(transition_command_buffer, &staging_buffer, 0, 0);
+
+                if blit_mips && mip_levels > 1 {
+                    super::image::cmd_generate_mips(
+                        &device,
+                        transition_command_buffer,
+                        texture.image,
+                        image_width,
+                        image_height,
+                        mip_levels,
+                    );
+                } else {
+                    for (i, &mip_offset) in mip_offsets.iter().enumerate() {
+                        texture.cmd_copy_from_buffer(
+                            transition_command_buffer,
+                            &staging_buffer,
+                            mip_offset,
+                            i as u32 + 1,
+                        );
+                    }
+
+                    super::image::cmd_transition_image_layout(
+                        transfer.sync,
+                        transition_command_buffer,
+                        transfer.queue_family_index,
+                        &super::image::ImageTransition {
+                            image: texture.image,
+                            format,
+                            mip_levels,
+                            old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                            new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        },
+                    );
+                }
             }
         );
 
@@ -96,57 +273,34 @@ impl Texture {
         texture
     }
 
+    pub fn get_type(&self) -> TextureType {
+        self.ty
+    }
+
+    pub fn get_image(&self) -> vk::Image {
+        self.image
+    }
+
+    /// a color image of `desc` viewed with every mip level
     pub fn new(
         device: Rc<ash::Device>,
         physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
-        memory_properties: vk::MemoryPropertyFlags,
         ty: TextureType,
-        width: u32,
-        height: u32,
-        format: vk::Format,
-        tiling: vk::ImageTiling,
-        usage: vk::ImageUsageFlags,
+        desc: &ImageDesc,
+        sampler: vk::Sampler,
     ) -> Self {
-        let (image, memory) = super::image::new_image_and_memory(
-            &device,
-            physical_device_memory_properties,
-            width,
-            height,
-            usage,
-            format,
-            tiling,
-            memory_properties,
-        );
+        let (image, memory) = super::image::new_image_and_memory(&device, physical_device_memory_properties, desc);
 
         let image_view =
-            super::image::new_image_view(&device, image, format, vk::ImageAspectFlags::COLOR);
-
-        let sampler = {
-            let info = vk::SamplerCreateInfo::builder()
-                .mag_filter(vk::Filter::LINEAR)
-                .min_filter(vk::Filter::LINEAR)
-                .address_mode_u(vk::SamplerAddressMode::REPEAT)
-                .address_mode_v(vk::SamplerAddressMode::REPEAT)
-                .address_mode_w(vk::SamplerAddressMode::REPEAT)
-                .anisotropy_enable(true)
-                .max_anisotropy(16.0)
-                .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
-                .unnormalized_coordinates(false)
-                .compare_enable(false)
-                .compare_op(vk::CompareOp::ALWAYS)
-                .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
-                .mip_lod_bias(0.0)
-                .min_lod(0.0)
-                .max_lod(0.0);
-
-            unsafe { device.create_sampler(&info, None).unwrap() }
-        };
+            super::image::new_image_view(&device, image, desc.format, vk::ImageAspectFlags::COLOR, desc.mip_levels);
 
         Self {
             device,
 
-            width,
-            height,
+            width: desc.width,
+            height: desc.height,
+            mip_levels: desc.mip_levels,
+            format: desc.format,
             ty,
 
             image,
@@ -160,21 +314,23 @@ impl Texture {
         &mut self,
         command_buffer: vk::CommandBuffer,
         buffer: &super::buffer::Buffer,
+        buffer_offset: vk::DeviceSize,
+        mip_level: u32,
     ) {
         let region = vk::BufferImageCopy::builder()
-            .buffer_offset(0)
+            .buffer_offset(buffer_offset)
             .buffer_row_length(0)
             .buffer_image_height(0)
             .image_subresource(vk::ImageSubresourceLayers {
                 aspect_mask: vk::ImageAspectFlags::COLOR,
-                mip_level: 0,
+                mip_level,
                 base_array_layer: 0,
                 layer_count: 1,
             })
             .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
             .image_extent(vk::Extent3D {
-                width: self.width,
-                height: self.height,
+                width: (self.width >> mip_level).max(1),
+                height: (self.height >> mip_level).max(1),
                 depth: 1,
             })
             .build();
@@ -190,9 +346,76 @@ impl Texture {
             )
         }
     }
-    // caller must ensure only called once
+    /// reads the base level back to the cpu as tightly packed rgba8,
+    /// srgb textures as they are stored, still encoded. Waits for the device to go idle
+    pub fn read_back(&self, transfer: &TransferContext) -> Vec<u8> {
+        assert!(
+            matches!(self.format, vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB),
+            "read back of {:?} not supported", self.format,
+        );
+
+        let size = (self.width * self.height * 4) as vk::DeviceSize;
+        let mut readback_buffer = super::buffer::Buffer::new(
+            size,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            self.device.clone(),
+            transfer.physical_device_memory_properties,
+        );
+
+        crate::renderer::VkApp::execute_transient_commands(
+            &self.device,
+            transfer.command_pool,
+            transfer.queue,
+            |transition_command_buffer| {
+                super::image::cmd_transition_image_layout(
+                    transfer.sync,
+                    transition_command_buffer,
+                    transfer.queue_family_index,
+                    &super::image::ImageTransition {
+                        image: self.image,
+                        format: self.format,
+                        mip_levels: self.mip_levels,
+                        old_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        new_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    },
+                );
+
+                super::image::cmd_copy_image_to_buffer(
+                    &self.device,
+                    transition_command_buffer,
+                    self.image,
+                    readback_buffer.handle,
+                    self.width,
+                    self.height,
+                    0,
+                );
+
+                super::image::cmd_transition_image_layout(
+                    transfer.sync,
+                    transition_command_buffer,
+                    transfer.queue_family_index,
+                    &super::image::ImageTransition {
+                        image: self.image,
+                        format: self.format,
+                        mip_levels: self.mip_levels,
+                        old_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    },
+                );
+            }
+        );
+
+        let pixels = readback_buffer.copy_to_vec(size as usize, 0);
+        unsafe {
+            readback_buffer.destroy();
+        }
+        pixels
+    }
+
+    /// # Safety
+    /// call once, after the device finished with the texture
     pub unsafe fn destroy(&mut self) {
-        self.device.destroy_sampler(self.sampler, None);
         self.device.destroy_image_view(self.image_view, None);
         self.device.destroy_image(self.image, None);
         self.device.free_memory(self.memory, None);
@@ -0,0 +1,174 @@
+// Host visible uniform buffer split into one region per frame in flight.
+// Every frame suballocates its region from the start again, each piece of uniform data
+// gets its own aligned offset, bound as the dynamic offset of a UNIFORM_BUFFER_DYNAMIC descriptor.
+// The descriptor's range must cover the largest block pushed through it
+//
+//     ring.begin_frame(frame);
+//     let offset = ring.push(&ubo);
+//     device.cmd_bind_descriptor_sets(command_buffer, ..., &[set], &[offset]);
+
+use std::{mem::size_of, rc::Rc};
+
+use ash::vk;
+
+use super::MAX_FRAMES_IN_FLIGHT;
+
+/// Hands out aligned offsets in one frame's region of the ring
+#[derive(Clone, Copy, Debug)]
+pub struct FrameRegions {
+    alignment: usize,
+    frame_capacity: usize,
+    frame: usize,
+    /// bytes of the current frame's region
+    used: usize,
+}
+
+impl FrameRegions {
+    /// `alignment` must be a power of two
+    pub fn new(alignment: usize, frame_capacity: usize) -> Self {
+        assert!(alignment.is_power_of_two());
+        Self {
+            alignment,
+            // so every region starts aligned
+            frame_capacity: frame_capacity.next_multiple_of(alignment),
+            frame: 0,
+            used: 0,
+        }
+    }
+
+    pub fn get_frame_capacity(&self) -> usize {
+        self.frame_capacity
+    }
+
+    pub fn begin_frame(&mut self, frame: usize) {
+        assert!(frame < MAX_FRAMES_IN_FLIGHT);
+        self.frame = frame;
+        self.used = 0;
+    }
+
+    /// offset into the whole ring, panics when the frame's region is full
+    /// instead of overwriting what a frame in flight still reads
+    pub fn allocate(&mut self, size: usize) -> usize {
+        let start = self.used.next_multiple_of(self.alignment);
+        let end = start + size;
+        assert!(
+            end <= self.frame_capacity,
+            "Uniform ring frame region of {} bytes is full, {} bytes were used before requesting {}",
+            self.frame_capacity,
+            self.used,
+            size,
+        );
+        self.used = end;
+        self.frame * self.frame_capacity + start
+    }
+}
+
+pub struct UniformRing {
+    device: Rc<ash::Device>,
+    pub handle: vk::Buffer,
+    memory: vk::DeviceMemory,
+    mapped_ptr: *mut u8,
+    regions: FrameRegions,
+}
+
+impl UniformRing {
+    /// `frame_capacity` bytes per frame in flight, rounded up to the device's offset alignment
+    pub fn new(
+        device: Rc<ash::Device>,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        limits: &vk::PhysicalDeviceLimits,
+        frame_capacity: usize,
+    ) -> Self {
+        let regions = FrameRegions::new(limits.min_uniform_buffer_offset_alignment as usize, frame_capacity);
+        let size = (MAX_FRAMES_IN_FLIGHT * regions.get_frame_capacity()) as vk::DeviceSize;
+
+        let handle = {
+            let info = vk::BufferCreateInfo::builder()
+                .size(size)
+                .usage(vk::BufferUsageFlags::UNIFORM_BUFFER)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE);
+            unsafe { device.create_buffer(&info, None) }.expect("Failed to create buffer handle")
+        };
+
+        let mem_requirements = unsafe { device.get_buffer_memory_requirements(handle) };
+
+        let memory = {
+            let mem_type_index = super::device::find_mem_type_index(
+                mem_requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                physical_device_memory_properties,
+            );
+            let alloc_info = vk::MemoryAllocateInfo::builder()
+                .allocation_size(mem_requirements.size)
+                .memory_type_index(mem_type_index);
+
+            unsafe { device.allocate_memory(&alloc_info, None) }
+                .expect("Failed to allocate device memory")
+        };
+
+        let mapped_ptr = unsafe {
+            device
+                .bind_buffer_memory(handle, memory, 0)
+                .expect("Failed to associate memory with buffer");
+            device
+                .map_memory(memory, 0, size, vk::MemoryMapFlags::empty())
+                .unwrap() as *mut u8
+        };
+
+        Self {
+            device,
+            handle,
+            memory,
+            mapped_ptr,
+            regions,
+        }
+    }
+
+    /// starts suballocating `frame`'s region over, the frame's previous commands must have finished executing
+    pub fn begin_frame(&mut self, frame: usize) {
+        self.regions.begin_frame(frame);
+    }
+
+    /// copies `data` into the current frame's region, returns its dynamic offset
+    pub fn push<T: Copy>(&mut self, data: &T) -> u32 {
+        let offset = self.regions.allocate(size_of::<T>());
+        unsafe {
+            (self.mapped_ptr.add(offset) as *mut T).write_unaligned(*data);
+        }
+        offset as u32
+    }
+
+    // caller must ensure only called once
+    pub unsafe fn destroy(&mut self) {
+        self.device.unmap_memory(self.memory);
+
+        self.device.destroy_buffer(self.handle, None);
+        self.device.free_memory(self.memory, None);
+    }
+}
+
+#[test]
+fn test_frame_regions() {
+    let mut regions = FrameRegions::new(64, 1000);
+    assert!(regions.get_frame_capacity() == 1024);
+
+    regions.begin_frame(0);
+    assert!(regions.allocate(136) == 0);
+    assert!(regions.allocate(136) == 192);
+    assert!(regions.allocate(4) == 384);
+
+    // the next frame's offsets start in its own region
+    regions.begin_frame(1);
+    assert!(regions.allocate(136) == 1024);
+    assert!(regions.allocate(1024 - 192) == 1024 + 192);
+
+    let overflow = std::panic::catch_unwind(move || regions.allocate(1));
+    assert!(overflow.is_err());
+
+    // a frame reuses its region once begun again
+    let mut regions = FrameRegions::new(256, 512);
+    regions.begin_frame(0);
+    regions.allocate(512);
+    regions.begin_frame(0);
+    assert!(regions.allocate(256) == 0);
+}
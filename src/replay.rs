@@ -0,0 +1,213 @@
+// Input recording and playback for reproducible runs. A recording is the input state transitions
+// of each frame along with the frame's duration, saved as ron:
+//
+//     (frames: [
+//         (time: 0.0, dt: 0.016, events: [Key(key: W, pressed: true)]),
+//         (time: 0.016, dt: 0.017, events: [MouseMotion((3.0, -1.0))]),
+//     ])
+//
+// Played back, each frame gets the recorded events and dt instead of the live ones,
+// so the same scene and recording give the same updates
+
+use serde::{Deserialize, Serialize};
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent};
+
+use crate::input::InputState;
+
+/// one change to the `InputState`
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum InputEvent {
+    Key { key: VirtualKeyCode, pressed: bool },
+    MouseButton { button: MouseButton, pressed: bool },
+    /// raw device motion, not tied to the cursor
+    MouseMotion([f32; 2]),
+    /// `None` when the cursor left the window
+    CursorMoved(Option<[f32; 2]>),
+    /// lines, positive away from the user
+    Scroll(f32),
+}
+
+impl InputEvent {
+    /// `None` for events not affecting the input state
+    pub fn from_window_event(event: &WindowEvent) -> Option<Self> {
+        Some(match *event {
+            WindowEvent::KeyboardInput { input, .. } => InputEvent::Key {
+                key: input.virtual_keycode?,
+                pressed: input.state == ElementState::Pressed,
+            },
+            WindowEvent::MouseInput { state, button, .. } => InputEvent::MouseButton {
+                button,
+                pressed: state == ElementState::Pressed,
+            },
+            WindowEvent::CursorMoved { position, .. } => {
+                InputEvent::CursorMoved(Some([position.x as f32, position.y as f32]))
+            }
+            WindowEvent::CursorLeft { .. } => InputEvent::CursorMoved(None),
+            WindowEvent::MouseWheel { delta, .. } => InputEvent::Scroll(match delta {
+                MouseScrollDelta::LineDelta(_, lines) => lines,
+                // roughly a line's worth of pixels
+                MouseScrollDelta::PixelDelta(position) => position.y as f32 / 20.0,
+            }),
+            _ => return None,
+        })
+    }
+
+    pub fn apply(&self, input_state: &mut InputState) {
+        match *self {
+            InputEvent::Key { key, pressed } => input_state.set_key_pressed(key, pressed),
+            InputEvent::MouseButton { button, pressed } => input_state.set_mouse_button_pressed(button, pressed),
+            // several motion events can arrive between frames
+            InputEvent::MouseMotion([x, y]) => {
+                input_state.delta_mouse_pos[0] += x;
+                input_state.delta_mouse_pos[1] += y;
+            }
+            InputEvent::CursorMoved(cursor_pos) => input_state.cursor_pos = cursor_pos,
+            InputEvent::Scroll(lines) => input_state.scroll_delta += lines,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    /// seconds since the recording started, when the frame started
+    pub time: f32,
+    pub dt: f32,
+    /// what happened since the previous frame, in order
+    pub events: Vec<InputEvent>,
+}
+
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct InputRecording {
+    pub frames: Vec<RecordedFrame>,
+}
+
+impl InputRecording {
+    pub fn load(path: &str) -> Self {
+        let source = std::fs::read_to_string(path)
+            .unwrap_or_else(|err| panic!("Failed to read input recording {}: {}", path, err));
+        let recording: Self = ron::from_str(&source)
+            .unwrap_or_else(|err| panic!("Failed to parse input recording {}: {}", path, err));
+        log::info!("Loaded input recording {} with {} frames", path, recording.frames.len());
+        recording
+    }
+
+    pub fn save(&self, path: &str) {
+        let source = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()).unwrap();
+        if let Some(dir) = std::path::Path::new(path).parent() {
+            std::fs::create_dir_all(dir).unwrap();
+        }
+        std::fs::write(path, source)
+            .unwrap_or_else(|err| panic!("Failed to write input recording {}: {}", path, err));
+        log::info!("Saved input recording {} with {} frames", path, self.frames.len());
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.frames.iter().map(|frame| frame.dt).sum()
+    }
+}
+
+/// keys held when recording starts are not recorded, start with nothing pressed
+#[derive(Default)]
+pub struct InputRecorder {
+    recording: InputRecording,
+    /// since the last frame
+    pending_events: Vec<InputEvent>,
+    time: f32,
+}
+
+impl InputRecorder {
+    pub fn record(&mut self, event: InputEvent) {
+        self.pending_events.push(event);
+    }
+
+    /// at the start of each frame, the events recorded since belong to it
+    pub fn record_frame(&mut self, dt: f32) {
+        self.recording.frames.push(RecordedFrame {
+            time: self.time,
+            dt,
+            events: std::mem::take(&mut self.pending_events),
+        });
+        self.time += dt;
+    }
+
+    /// events after the last frame are dropped, no frame handled them
+    pub fn finish(self) -> InputRecording {
+        self.recording
+    }
+}
+
+pub struct InputPlayer {
+    recording: InputRecording,
+    next_frame: usize,
+}
+
+impl InputPlayer {
+    pub fn new(recording: InputRecording) -> Self {
+        Self { recording, next_frame: 0 }
+    }
+
+    /// `None` once every frame was played
+    pub fn next_frame(&mut self) -> Option<&RecordedFrame> {
+        let frame = self.recording.frames.get(self.next_frame)?;
+        self.next_frame += 1;
+        Some(frame)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next_frame >= self.recording.frames.len()
+    }
+}
+
+/// where a frame's input comes from
+#[derive(Default)]
+pub enum Replay {
+    #[default]
+    Live,
+    /// live input, also recorded
+    Recording(InputRecorder),
+    /// recorded input, live input is ignored
+    Playing(InputPlayer),
+}
+
+#[test]
+fn test_input_replay() {
+    let mut recorder = InputRecorder::default();
+    recorder.record(InputEvent::Key { key: VirtualKeyCode::W, pressed: true });
+    recorder.record(InputEvent::MouseMotion([3.0, -1.0]));
+    recorder.record(InputEvent::MouseMotion([1.0, 1.0]));
+    recorder.record_frame(0.25);
+    recorder.record_frame(0.5);
+    recorder.record(InputEvent::Key { key: VirtualKeyCode::W, pressed: false });
+    recorder.record(InputEvent::CursorMoved(Some([10.0, 20.0])));
+    recorder.record(InputEvent::Scroll(-2.0));
+    recorder.record_frame(0.125);
+    // after the last frame
+    recorder.record(InputEvent::Key { key: VirtualKeyCode::S, pressed: true });
+    let recording = recorder.finish();
+    assert!(recording.frames.len() == 3 && recording.frames[2].time == 0.75 && recording.duration() == 0.875);
+
+    let source = ron::to_string(&recording).unwrap();
+    let recording: InputRecording = ron::from_str(&source).unwrap();
+
+    let mut input_state = InputState::new();
+    let mut player = InputPlayer::new(recording);
+    let mut play_frame = |input_state: &mut InputState| {
+        let frame = player.next_frame()?;
+        for event in &frame.events {
+            event.apply(input_state);
+        }
+        Some(frame.dt)
+    };
+    assert!(play_frame(&mut input_state) == Some(0.25));
+    assert!(input_state.just_pressed(VirtualKeyCode::W) && input_state.delta_mouse_pos == [4.0, 0.0]);
+    input_state.end_frame();
+
+    assert!(play_frame(&mut input_state) == Some(0.5));
+    assert!(input_state.is_key_pressed(VirtualKeyCode::W) && !input_state.just_pressed(VirtualKeyCode::W));
+    input_state.end_frame();
+
+    assert!(play_frame(&mut input_state) == Some(0.125));
+    assert!(input_state.just_released(VirtualKeyCode::W) && !input_state.is_key_pressed(VirtualKeyCode::S));
+    assert!(input_state.cursor_pos == Some([10.0, 20.0]) && input_state.scroll_delta == -2.0);
+    assert!(play_frame(&mut input_state).is_none() && player.is_finished());
+}
@@ -0,0 +1,519 @@
+// Scene files, the object hierarchy, materials and camera saved as RON
+// so scenes can be authored and iterated on without recompiling.
+//
+//     (
+//         camera: (translation: (0.0, 0.0, -4.0), z_x_angle: 0.0, ...),
+//         materials: [
+//             (name: "brick", diffuse_texture: 0, normal_texture: 1, normal_mapping: true),
+//             (name: "steel", diffuse_texture: 0, normal_texture: 1, shading: Pbr, metallic: 1.0, roughness: 0.3),
+//         ],
+//         objects: [
+//             (name: "wall", parent: None, transform: (...), geometry: Some("primitives/cube"), material: Some("brick")),
+//         ],
+//         terrain: Some((heightmap: "terrain/heightmap.png", splat_texture: 2, layer_textures: (3, 4, 5, 6))),
+//         scripts: ["scripts/door.rhai"],
+//         environment: Some("images/sky.hdr"),
+//     )
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    animation::{AnimationPlayer, Clip},
+    assets::AssetHandle,
+    camera::Camera,
+    geometry::GeometryId,
+    math::{ModelMat, Rotor, Vector},
+    particles::{EmitterDesc, EmitterId, ParticleSystem},
+    renderer::{material::{self, MaterialId, ShadingModel}, terrain::TerrainMaterial, VkApp},
+    terrain::{Heightmap, Terrain, TerrainDesc},
+};
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Transform {
+    pub translation: (f32, f32, f32),
+    /// rotor components (1, yx, zy, xz)
+    pub rotation: (f32, f32, f32, f32),
+    pub scale: (f32, f32, f32),
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: (0.0, 0.0, 0.0),
+            rotation: (1.0, 0.0, 0.0, 0.0),
+            scale: (1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl Transform {
+    pub fn to_model_mat(&self) -> ModelMat {
+        let (x, y, z) = self.translation;
+        let (scalar, yx, zy, xz) = self.rotation;
+        let (sx, sy, sz) = self.scale;
+        ModelMat::from(Vector::new(sx, sy, sz), Rotor::new(scalar, yx, zy, xz), Vector::new(x, y, z))
+    }
+}
+
+/// camera placement, the aspect ratio follows the window and speeds come from the engine config
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct CameraState {
+    pub translation: (f32, f32, f32),
+    pub z_x_angle: f32,
+    pub y_xz_angle: f32,
+    pub near_z: f32,
+    pub far_z: f32,
+}
+
+impl CameraState {
+    pub fn from_camera(camera: &Camera) -> Self {
+        Self {
+            translation: (camera.translation.x, camera.translation.y, camera.translation.z),
+            z_x_angle: camera.z_x_angle,
+            y_xz_angle: camera.y_xz_angle,
+            near_z: camera.near_z,
+            far_z: camera.far_z,
+        }
+    }
+
+    pub fn apply(&self, camera: &mut Camera) {
+        let (x, y, z) = self.translation;
+        camera.translation = Vector::new(x, y, z);
+        camera.z_x_angle = self.z_x_angle;
+        camera.y_xz_angle = self.y_xz_angle;
+        camera.near_z = self.near_z;
+        camera.far_z = self.far_z;
+    }
+}
+
+/// texture fields index into the textures descriptor array, like `material::Material`
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct SceneMaterial {
+    pub name: String,
+    pub diffuse_texture: u32,
+    pub normal_texture: u32,
+    #[serde(default)]
+    pub normal_mapping: bool,
+    #[serde(default)]
+    pub shading: ShadingModel,
+    /// the fields below only affect pbr shading
+    #[serde(default = "default_albedo")]
+    pub albedo: [f32; 4],
+    #[serde(default)]
+    pub metallic_roughness_texture: Option<u32>,
+    #[serde(default)]
+    pub occlusion_texture: Option<u32>,
+    #[serde(default)]
+    pub metallic: f32,
+    #[serde(default = "default_roughness")]
+    pub roughness: f32,
+}
+
+fn default_albedo() -> [f32; 4] {
+    [1.0; 4]
+}
+
+fn default_roughness() -> f32 {
+    1.0
+}
+
+/// texture fields index into the textures descriptor array, like `SceneMaterial`
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct SceneTerrain {
+    /// image path, brightness is height
+    pub heightmap: String,
+    #[serde(default)]
+    pub desc: TerrainDesc,
+    /// rgba weighs the layers
+    pub splat_texture: u32,
+    pub layer_textures: (u32, u32, u32, u32),
+    /// layer repeats across the whole terrain
+    #[serde(default = "default_layer_tiling")]
+    pub layer_tiling: f32,
+}
+
+fn default_layer_tiling() -> f32 {
+    32.0
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct SceneObject {
+    pub name: String,
+    /// index of the parent object, parents come before their children
+    #[serde(default)]
+    pub parent: Option<usize>,
+    /// relative to the parent
+    #[serde(default)]
+    pub transform: Transform,
+    /// asset path of the mesh
+    #[serde(default)]
+    pub geometry: Option<String>,
+    /// name of a scene material
+    #[serde(default)]
+    pub material: Option<String>,
+    /// particles spawned at the object's position, on the gpu if the emitter says so
+    #[serde(default)]
+    pub emitter: Option<EmitterDesc>,
+    /// keyframed transform, replaces the animated parts of `transform` while playing
+    #[serde(default)]
+    pub animation: Option<Clip>,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Scene {
+    pub camera: CameraState,
+    #[serde(default)]
+    pub materials: Vec<SceneMaterial>,
+    #[serde(default)]
+    pub objects: Vec<SceneObject>,
+    #[serde(default)]
+    pub terrain: Option<SceneTerrain>,
+    /// paths of the gameplay scripts, see `scripting::ScriptSystem`
+    #[serde(default)]
+    pub scripts: Vec<String>,
+    /// hdr environment map lighting the scene, see `VkApp::set_environment`
+    #[serde(default)]
+    pub environment: Option<String>,
+}
+
+impl Scene {
+    pub fn load(path: &str) -> Self {
+        let source = std::fs::read_to_string(path)
+            .unwrap_or_else(|err| panic!("Failed to read scene {}: {}", path, err));
+        let scene = Self::parse(&source)
+            .unwrap_or_else(|err| panic!("Failed to parse scene {}: {}", path, err));
+        log::info!("Loaded scene {} with {} objects", path, scene.objects.len());
+        scene
+    }
+
+    pub fn parse(source: &str) -> Result<Self, ron::error::SpannedError> {
+        let scene: Self = ron::from_str(source)?;
+        for (index, object) in scene.objects.iter().enumerate() {
+            if let Some(parent) = object.parent {
+                assert!(parent < index, "Scene object {} must come after its parent", object.name);
+            }
+        }
+        Ok(scene)
+    }
+
+    pub fn save(&self, path: &str) {
+        let source = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()).unwrap();
+        if let Some(dir) = std::path::Path::new(path).parent() {
+            std::fs::create_dir_all(dir).unwrap();
+        }
+        std::fs::write(path, source)
+            .unwrap_or_else(|err| panic!("Failed to write scene {}: {}", path, err));
+        log::info!("Saved scene {}", path);
+    }
+
+    /// object transforms with their parents' applied, in object order
+    pub fn world_transforms(&self) -> Vec<ModelMat> {
+        self.world_transforms_with(|index| self.objects[index].transform)
+    }
+
+    /// like `world_transforms` with `local` giving each object's transform relative to its parent
+    fn world_transforms_with<F: Fn(usize) -> Transform>(&self, local: F) -> Vec<ModelMat> {
+        let mut world_transforms: Vec<ModelMat> = Vec::with_capacity(self.objects.len());
+        for (index, object) in self.objects.iter().enumerate() {
+            let local = local(index).to_model_mat();
+            world_transforms.push(match object.parent {
+                Some(parent) => world_transforms[parent] * local,
+                None => local,
+            });
+        }
+        world_transforms
+    }
+
+    /// creates the scene's materials, geometry, emitters, environment and terrain and applies the camera,
+    /// `load_geometry` is called once per distinct asset path, the instance holds what it returns until `release`
+    pub fn instantiate<F: FnMut(&mut VkApp, &str) -> Option<AssetHandle<GeometryId>>>(
+        &self,
+        app: &mut VkApp,
+        particles: &mut ParticleSystem,
+        mut load_geometry: F,
+    ) -> SceneInstance {
+        self.camera.apply(&mut app.camera);
+
+        let mut material_ids = HashMap::new();
+        for scene_material in &self.materials {
+            let mut material = material::Material {
+                shading: scene_material.shading,
+                diffuse_texture: scene_material.diffuse_texture,
+                normal_texture: scene_material.normal_texture,
+                albedo: scene_material.albedo,
+                metallic: scene_material.metallic,
+                roughness: scene_material.roughness,
+                ..Default::default()
+            };
+            if scene_material.normal_mapping {
+                material.flags |= material::MATERIAL_FLAG_NORMAL_MAP;
+            }
+            if let Some(texture) = scene_material.metallic_roughness_texture {
+                material.metallic_roughness_texture = texture;
+                material.flags |= material::MATERIAL_FLAG_METALLIC_ROUGHNESS_MAP;
+            }
+            if let Some(texture) = scene_material.occlusion_texture {
+                material.occlusion_texture = texture;
+                material.flags |= material::MATERIAL_FLAG_OCCLUSION_MAP;
+            }
+            material_ids.insert(scene_material.name.as_str(), app.material_system.create_material(material));
+        }
+
+        if let Some(environment) = &self.environment {
+            app.set_environment(environment);
+        }
+
+        if let Some(scene_terrain) = &self.terrain {
+            let (layer0, layer1, layer2, layer3) = scene_terrain.layer_textures;
+            app.set_terrain(
+                Terrain::new(Heightmap::load(&scene_terrain.heightmap), scene_terrain.desc),
+                TerrainMaterial {
+                    splat_texture: scene_terrain.splat_texture,
+                    layer_textures: [layer0, layer1, layer2, layer3],
+                    layer_tiling: scene_terrain.layer_tiling,
+                },
+            );
+        }
+
+        let world_transforms = self.world_transforms();
+        let mut emitters = Vec::new();
+        let mut gpu_emitters = Vec::new();
+        for (index, object) in self.objects.iter().enumerate() {
+            let Some(desc) = &object.emitter else {
+                continue;
+            };
+            let position = world_transforms[index].translation();
+            if desc.gpu {
+                gpu_emitters.push((index, app.gpu_particle_system.add_emitter(desc.clone(), position)));
+            } else {
+                emitters.push((index, particles.add_emitter(desc.clone(), position)));
+            }
+        }
+
+        let mut geometry_ids: HashMap<&str, Option<GeometryId>> = HashMap::new();
+        let mut geometries = Vec::new();
+        let mut draws = Vec::new();
+        for (index, object) in self.objects.iter().enumerate() {
+            let Some(geometry_path) = &object.geometry else {
+                continue;
+            };
+            let geometry = *geometry_ids.entry(geometry_path).or_insert_with(|| {
+                let handle = load_geometry(app, geometry_path)?;
+                geometries.push(handle);
+                app.geometry_assets.get(handle).copied()
+            });
+            let Some(geometry) = geometry else {
+                continue;
+            };
+
+            let material = match &object.material {
+                Some(name) => match material_ids.get(name.as_str()) {
+                    Some(&material) => material,
+                    None => {
+                        log::warn!("Scene object {} uses unknown material {}", object.name, name);
+                        continue;
+                    }
+                },
+                None => {
+                    log::warn!("Scene object {} has geometry but no material", object.name);
+                    continue;
+                }
+            };
+            draws.push((index, geometry, material));
+        }
+
+        SceneInstance { draws, geometries, emitters, gpu_emitters, animations: self.new_animation_players() }
+    }
+
+    /// only the animations, for simulating the scene without a renderer, e.g. on a headless server
+    pub fn instantiate_headless(&self) -> SceneInstance {
+        SceneInstance { animations: self.new_animation_players(), ..Default::default() }
+    }
+
+    fn new_animation_players(&self) -> Vec<(usize, AnimationPlayer)> {
+        self.objects.iter()
+            .enumerate()
+            .filter_map(|(index, object)| Some((index, AnimationPlayer::new(object.animation.clone()?))))
+            .collect()
+    }
+}
+
+/// the scene's objects resolved to engine resources
+#[derive(Default)]
+pub struct SceneInstance {
+    /// object index, geometry and material of each drawable object
+    draws: Vec<(usize, GeometryId, MaterialId)>,
+    /// one reference to each loaded geometry, dropped by `release`
+    geometries: Vec<AssetHandle<GeometryId>>,
+    /// object index and emitter of each emitting object
+    emitters: Vec<(usize, EmitterId)>,
+    /// same for the emitters simulated on the gpu
+    gpu_emitters: Vec<(usize, EmitterId)>,
+    /// object index and player of each animated object
+    animations: Vec<(usize, AnimationPlayer)>,
+}
+
+impl SceneInstance {
+    /// the player animating the object, `None` if it has no animation
+    pub fn get_animation_mut(&mut self, object_index: usize) -> Option<&mut AnimationPlayer> {
+        self.animations.iter_mut()
+            .find(|(index, _)| *index == object_index)
+            .map(|(_, player)| player)
+    }
+
+    /// draws `copy` like `object`, for objects copied after instantiating
+    pub fn copy_draws(&mut self, object: usize, copy: usize) {
+        let copies: Vec<_> = self.draws.iter()
+            .filter(|&&(index, _, _)| index == object)
+            .map(|&(_, geometry, material)| (copy, geometry, material))
+            .collect();
+        self.draws.extend(copies);
+    }
+
+    /// call every frame before `world_transforms`
+    pub fn update_animations(&mut self, dt: f32) {
+        for (_, player) in &mut self.animations {
+            player.update(dt);
+        }
+    }
+
+    /// like `Scene::world_transforms` with the animations applied
+    pub fn world_transforms(&self, scene: &Scene) -> Vec<ModelMat> {
+        scene.world_transforms_with(|index| self.local_transform(scene, index))
+    }
+
+    /// each object's transform relative to its parent with the animations applied, in object order
+    pub fn local_transforms(&self, scene: &Scene) -> Vec<Transform> {
+        (0..scene.objects.len()).map(|index| self.local_transform(scene, index)).collect()
+    }
+
+    fn local_transform(&self, scene: &Scene, index: usize) -> Transform {
+        let transform = &scene.objects[index].transform;
+        match self.animations.iter().find(|(animated, _)| *animated == index) {
+            Some((_, player)) => player.sample(transform),
+            None => *transform,
+        }
+    }
+
+    /// call every frame, `world_transforms` as returned by `world_transforms`.
+    /// Picks report the object's index, the `outlined` object's draws get an outline
+    pub fn submit_draws(&self, app: &mut VkApp, world_transforms: &[ModelMat], outlined: Option<usize>) {
+        for &(index, geometry, material) in &self.draws {
+            if outlined == Some(index) {
+                app.submit_outlined_draw(geometry, material, world_transforms[index], Some(index as u32));
+            } else {
+                app.submit_pickable_draw(geometry, material, world_transforms[index], index as u32);
+            }
+        }
+    }
+
+    /// drops the instance's references to its geometry, whose last reference destroys it.
+    /// Call before the renderer is dropped, the instance draws nothing afterwards
+    pub fn release(&mut self, app: &mut VkApp) {
+        self.draws.clear();
+        for handle in self.geometries.drain(..) {
+            app.geometry_assets.release(handle);
+        }
+    }
+
+    /// moves emitters along with their objects
+    pub fn update_emitters(&self, app: &mut VkApp, particles: &mut ParticleSystem, world_transforms: &[ModelMat]) {
+        for &(index, emitter) in &self.emitters {
+            particles.get_emitter_mut(emitter).position = world_transforms[index].translation();
+        }
+        for &(index, emitter) in &self.gpu_emitters {
+            app.gpu_particle_system.get_emitter_mut(emitter).position = world_transforms[index].translation();
+        }
+    }
+}
+
+#[test]
+fn test_scene_round_trip() {
+    use crate::animation::{Interpolation, Keyframe, Track};
+
+    let scene = Scene {
+        camera: CameraState {
+            translation: (0.0, 1.0, -4.0),
+            z_x_angle: 0.5,
+            y_xz_angle: 0.0,
+            near_z: 1.0,
+            far_z: 100.0,
+        },
+        materials: vec![SceneMaterial {
+            name: "brick".to_owned(),
+            diffuse_texture: 0,
+            normal_texture: 1,
+            normal_mapping: true,
+            shading: ShadingModel::Simple,
+            albedo: [1.0; 4],
+            metallic_roughness_texture: None,
+            occlusion_texture: None,
+            metallic: 0.0,
+            roughness: 1.0,
+        }, SceneMaterial {
+            name: "steel".to_owned(),
+            diffuse_texture: 0,
+            normal_texture: 1,
+            normal_mapping: false,
+            shading: ShadingModel::Pbr,
+            albedo: [0.6, 0.6, 0.65, 1.0],
+            metallic_roughness_texture: Some(7),
+            occlusion_texture: None,
+            metallic: 1.0,
+            roughness: 0.3,
+        }],
+        objects: vec![
+            SceneObject {
+                name: "root".to_owned(),
+                parent: None,
+                transform: Transform { translation: (1.0, 0.0, 0.0), ..Default::default() },
+                geometry: None,
+                material: None,
+                emitter: None,
+                animation: None,
+            },
+            SceneObject {
+                name: "child".to_owned(),
+                parent: Some(0),
+                transform: Transform { translation: (0.0, 2.0, 0.0), ..Default::default() },
+                geometry: Some("meshes/cube.obj".to_owned()),
+                material: Some("brick".to_owned()),
+                emitter: Some(EmitterDesc::default()),
+                animation: Some(Clip {
+                    looping: true,
+                    rotation: Track {
+                        interpolation: Interpolation::Cubic,
+                        keyframes: vec![Keyframe { time: 1.0, value: (0.0, 1.0, 0.0, 0.0) }],
+                    },
+                    ..Default::default()
+                }),
+            },
+        ],
+        terrain: Some(SceneTerrain {
+            heightmap: "terrain/heightmap.png".to_owned(),
+            desc: TerrainDesc { chunk_quads: 16, ..Default::default() },
+            splat_texture: 2,
+            layer_textures: (3, 4, 5, 6),
+            layer_tiling: 16.0,
+        }),
+        scripts: vec!["scripts/door.rhai".to_owned()],
+        environment: Some("images/sky.hdr".to_owned()),
+    };
+
+    let source = ron::to_string(&scene).unwrap();
+    assert!(Scene::parse(&source).unwrap() == scene);
+
+    let child = scene.world_transforms()[1].translation();
+    assert!(child.x == 1.0 && child.y == 2.0 && child.z == 0.0);
+
+    // omitted fields take their defaults
+    let minimal = Scene::parse("(
+        camera: (translation: (0.0, 0.0, 0.0), z_x_angle: 0.0, y_xz_angle: 0.0,
+            near_z: 1.0, far_z: 100.0),
+        objects: [(name: \"empty\")],
+    )").unwrap();
+    assert!(minimal.objects[0].transform == Transform::default());
+    assert!(minimal.terrain.is_none());
+}
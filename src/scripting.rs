@@ -0,0 +1,338 @@
+// Gameplay scripts in Rhai, so behaviour can change without recompiling. A script's top level
+// runs when it is loaded and registers the callbacks run every frame:
+//
+//     let door = find("door");
+//     on_update(|dt| {
+//         if is_action_pressed("forward") {
+//             translate(door, 0.0, 0.0, 2.0 * dt);
+//         }
+//     });
+//
+// Entities are the scene's objects, by index. Transforms are relative to the parent and take
+// floats. Actions are the key bindings by name, see `KeyBindings::actions`.
+// Scripts are assets, with hot reload a changed script drops its callbacks and runs its top level again,
+// what it spawned stays
+
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use rhai::{Array, Dynamic, EvalAltResult, FnPtr, AST, FLOAT, INT};
+
+use crate::{
+    assets::{AssetCache, AssetHandle},
+    config::KeyBindings,
+    input::InputState,
+    math::{Rotor, Vector},
+    scene::{Scene, SceneInstance, SceneObject},
+};
+
+pub struct Script {
+    ast: AST,
+}
+
+pub type ScriptHandle = AssetHandle<Script>;
+
+/// what the functions scripts call work on, shared with the engine's registered functions
+#[derive(Default)]
+struct ScriptContext {
+    /// the scene's objects, lent while scripts run
+    objects: Vec<SceneObject>,
+    /// copied and spawned object indices, the copies' draws are added once the objects are returned
+    copies: Vec<(usize, usize)>,
+    /// pressed, just pressed and just released of each action this frame
+    actions: HashMap<&'static str, [bool; 3]>,
+    /// the script whose top level is running, `on_update` registers its callbacks for it
+    running: Option<ScriptHandle>,
+    callbacks: Vec<(ScriptHandle, FnPtr)>,
+}
+
+fn entity_index(objects: &[SceneObject], entity: INT) -> Result<usize, Box<EvalAltResult>> {
+    match usize::try_from(entity) {
+        Ok(index) if index < objects.len() => Ok(index),
+        _ => Err(format!("No entity {}", entity).into()),
+    }
+}
+
+fn action(context: &ScriptContext, name: &str, state: usize) -> Result<bool, Box<EvalAltResult>> {
+    match context.actions.get(name) {
+        Some(states) => Ok(states[state]),
+        None => Err(format!("No action {}", name).into()),
+    }
+}
+
+/// `load` scripts, then `update` and `reload_changed` once per frame.
+/// Retired scripts are dropped a few frames late, as the asset cache keeps them for frames in flight
+pub struct ScriptSystem {
+    engine: rhai::Engine,
+    context: Rc<RefCell<ScriptContext>>,
+    pub scripts: AssetCache<Script>,
+}
+
+impl Default for ScriptSystem {
+    fn default() -> Self {
+        let context = Rc::new(RefCell::new(ScriptContext::default()));
+        let mut engine = rhai::Engine::new();
+        engine.on_print(|text| log::info!("Script: {}", text));
+        engine.on_debug(|text, source, position| log::debug!("Script {}{:?}: {}", source.unwrap_or(""), position, text));
+
+        let c = context.clone();
+        engine.register_fn("find", move |name: &str| -> INT {
+            let context = c.borrow();
+            context.objects.iter().position(|object| object.name == name).map_or(-1, |index| index as INT)
+        });
+        let c = context.clone();
+        engine.register_fn("spawn", move |name: &str| -> INT {
+            let mut context = c.borrow_mut();
+            context.objects.push(SceneObject {
+                name: name.to_owned(),
+                parent: None,
+                transform: Default::default(),
+                geometry: None,
+                material: None,
+                emitter: None,
+                animation: None,
+            });
+            (context.objects.len() - 1) as INT
+        });
+        // with the entity's parent, transform and looks, emitters and animations aren't copied
+        let c = context.clone();
+        engine.register_fn("spawn_copy", move |entity: INT, name: &str| -> Result<INT, Box<EvalAltResult>> {
+            let mut context = c.borrow_mut();
+            let index = entity_index(&context.objects, entity)?;
+            let copy = SceneObject {
+                name: name.to_owned(),
+                emitter: None,
+                animation: None,
+                ..context.objects[index].clone()
+            };
+            context.objects.push(copy);
+            let copy_index = context.objects.len() - 1;
+            context.copies.push((index, copy_index));
+            Ok(copy_index as INT)
+        });
+        let c = context.clone();
+        engine.register_fn("get_translation", move |entity: INT| -> Result<Array, Box<EvalAltResult>> {
+            let context = c.borrow();
+            let (x, y, z) = context.objects[entity_index(&context.objects, entity)?].transform.translation;
+            Ok([x, y, z].map(|value| Dynamic::from_float(value as FLOAT)).to_vec())
+        });
+        let c = context.clone();
+        engine.register_fn("set_translation", move |entity: INT, x: FLOAT, y: FLOAT, z: FLOAT| -> Result<(), Box<EvalAltResult>> {
+            let mut context = c.borrow_mut();
+            let index = entity_index(&context.objects, entity)?;
+            context.objects[index].transform.translation = (x as f32, y as f32, z as f32);
+            Ok(())
+        });
+        let c = context.clone();
+        engine.register_fn("translate", move |entity: INT, x: FLOAT, y: FLOAT, z: FLOAT| -> Result<(), Box<EvalAltResult>> {
+            let mut context = c.borrow_mut();
+            let index = entity_index(&context.objects, entity)?;
+            let translation = &mut context.objects[index].transform.translation;
+            translation.0 += x as f32;
+            translation.1 += y as f32;
+            translation.2 += z as f32;
+            Ok(())
+        });
+        // about the entity's local `axis`, in radians
+        let c = context.clone();
+        engine.register_fn("rotate", move |entity: INT, x: FLOAT, y: FLOAT, z: FLOAT, angle: FLOAT| -> Result<(), Box<EvalAltResult>> {
+            let mut context = c.borrow_mut();
+            let index = entity_index(&context.objects, entity)?;
+            let rotation = &mut context.objects[index].transform.rotation;
+            let (scalar, yx, zy, xz) = *rotation;
+            let rotated = Rotor::from_axis_angle(Vector::new(x as f32, y as f32, z as f32), angle as f32)
+                * Rotor::new(scalar, yx, zy, xz);
+            *rotation = rotated.components();
+            Ok(())
+        });
+        let c = context.clone();
+        engine.register_fn("set_scale", move |entity: INT, x: FLOAT, y: FLOAT, z: FLOAT| -> Result<(), Box<EvalAltResult>> {
+            let mut context = c.borrow_mut();
+            let index = entity_index(&context.objects, entity)?;
+            context.objects[index].transform.scale = (x as f32, y as f32, z as f32);
+            Ok(())
+        });
+        for (function, state) in [("is_action_pressed", 0), ("is_action_just_pressed", 1), ("is_action_just_released", 2)] {
+            let c = context.clone();
+            engine.register_fn(function, move |name: &str| action(&c.borrow(), name, state));
+        }
+        let c = context.clone();
+        engine.register_fn("on_update", move |callback: FnPtr| -> Result<(), Box<EvalAltResult>> {
+            let mut context = c.borrow_mut();
+            let Some(script) = context.running else {
+                return Err("on_update outside of a script's top level".into());
+            };
+            context.callbacks.push((script, callback));
+            Ok(())
+        });
+
+        Self {
+            engine,
+            context,
+            scripts: AssetCache::default(),
+        }
+    }
+}
+
+impl ScriptSystem {
+    /// loads and runs the script at `path` unless it's loaded already,
+    /// `None` when the file is missing or doesn't compile
+    pub fn load(&mut self, path: &str, scene: &mut Scene, scene_instance: &mut SceneInstance) -> Option<ScriptHandle> {
+        let loaded = self.scripts.is_cached(path);
+        let engine = &self.engine;
+        let handle = self.scripts.acquire(path, |path| Self::compile(engine, path))?;
+        if !loaded {
+            self.run(handle, scene, scene_instance);
+        }
+        Some(handle)
+    }
+
+    /// its callbacks stop once the last reference is released
+    pub fn release(&mut self, handle: ScriptHandle) {
+        self.scripts.release(handle);
+        if self.scripts.get(handle).is_none() {
+            self.context.borrow_mut().callbacks.retain(|&(script, _)| script != handle);
+        }
+    }
+
+    fn compile(engine: &rhai::Engine, path: &str) -> Option<Script> {
+        let source = std::fs::read_to_string(path).ok()?;
+        match engine.compile(source) {
+            Ok(ast) => Some(Script { ast }),
+            Err(err) => {
+                log::warn!("Failed to compile script {}: {}", path, err);
+                None
+            }
+        }
+    }
+
+    /// lends the scene's objects to the scripts for `f`, copies spawned meanwhile get their draws
+    fn with_scene<F: FnOnce(&Self)>(&self, scene: &mut Scene, scene_instance: &mut SceneInstance, f: F) {
+        self.context.borrow_mut().objects = std::mem::take(&mut scene.objects);
+        f(self);
+        let mut context = self.context.borrow_mut();
+        scene.objects = std::mem::take(&mut context.objects);
+        for (object, copy) in context.copies.drain(..) {
+            scene_instance.copy_draws(object, copy);
+        }
+    }
+
+    /// runs `handle`'s top level, replacing its callbacks
+    fn run(&mut self, handle: ScriptHandle, scene: &mut Scene, scene_instance: &mut SceneInstance) {
+        {
+            let mut context = self.context.borrow_mut();
+            context.callbacks.retain(|&(script, _)| script != handle);
+            context.running = Some(handle);
+        }
+        self.with_scene(scene, scene_instance, |this| {
+            let script = this.scripts.get(handle).unwrap();
+            if let Err(err) = this.engine.run_ast(&script.ast) {
+                log::warn!("Script {}: {}", this.scripts.get_path(handle).unwrap_or(""), err);
+            }
+        });
+        self.context.borrow_mut().running = None;
+    }
+
+    /// runs every callback once, `dt` in seconds
+    pub fn update(
+        &mut self,
+        scene: &mut Scene,
+        scene_instance: &mut SceneInstance,
+        input_state: &InputState,
+        key_bindings: &KeyBindings,
+        dt: f32,
+    ) {
+        // callbacks may register more, which run from the next frame on
+        let callbacks = {
+            let mut context = self.context.borrow_mut();
+            context.actions = key_bindings.actions()
+                .into_iter()
+                .map(|(name, key)| (name, [
+                    input_state.is_key_pressed(key),
+                    input_state.just_pressed(key),
+                    input_state.just_released(key),
+                ]))
+                .collect();
+            context.callbacks.clone()
+        };
+
+        self.with_scene(scene, scene_instance, |this| {
+            for (handle, callback) in &callbacks {
+                let script = this.scripts.get(*handle).unwrap();
+                if let Err(err) = callback.call::<Dynamic>(&this.engine, &script.ast, (dt as FLOAT,)) {
+                    log::warn!("Script {}: {}", this.scripts.get_path(*handle).unwrap_or(""), err);
+                }
+            }
+        });
+    }
+
+    /// with hot reload on, reruns scripts whose files changed, returns their paths
+    pub fn reload_changed(&mut self, scene: &mut Scene, scene_instance: &mut SceneInstance) -> Vec<String> {
+        let engine = &self.engine;
+        let reloaded = self.scripts.reload_changed(|path, _| Self::compile(engine, path));
+        self.scripts.collect_retired(drop);
+
+        reloaded.into_iter().map(|handle| {
+            self.run(handle, scene, scene_instance);
+            self.scripts.get_path(handle).unwrap().to_owned()
+        }).collect()
+    }
+}
+
+#[test]
+fn test_scripting() {
+    use winit::event::VirtualKeyCode;
+
+    let dir = std::env::temp_dir().join(format!("ash_engine_scripts_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("crate.rhai");
+    let path = path.to_str().unwrap();
+    std::fs::write(path, "
+        let crate_entity = find(\"crate\");
+        spawn_copy(crate_entity, \"crate copy\");
+        on_update(|dt| {
+            if is_action_pressed(\"forward\") {
+                translate(crate_entity, 0.0, 0.0, dt);
+            }
+        });
+    ").unwrap();
+
+    let mut scene = Scene::parse("(
+        camera: (translation: (0.0, 0.0, 0.0), z_x_angle: 0.0, y_xz_angle: 0.0, near_z: 0.1, far_z: 100.0),
+        objects: [(name: \"crate\", transform: (translation: (1.0, 0.0, 0.0), rotation: (1.0, 0.0, 0.0, 0.0), scale: (1.0, 1.0, 1.0)))],
+    )").unwrap();
+    let mut scene_instance = SceneInstance::default();
+    let mut scripts = ScriptSystem::default();
+    let key_bindings = KeyBindings::default();
+    let mut input_state = InputState::new();
+
+    assert!(scripts.load(&format!("{}.missing", path), &mut scene, &mut scene_instance).is_none());
+    let handle = scripts.load(path, &mut scene, &mut scene_instance).unwrap();
+    assert!(scene.objects.len() == 2 && scene.objects[1].name == "crate copy");
+    assert!(scene.objects[1].transform == scene.objects[0].transform);
+
+    scripts.update(&mut scene, &mut scene_instance, &input_state, &key_bindings, 0.5);
+    assert!(scene.objects[0].transform.translation == (1.0, 0.0, 0.0));
+    input_state.set_key_pressed(VirtualKeyCode::W, true);
+    scripts.update(&mut scene, &mut scene_instance, &input_state, &key_bindings, 0.5);
+    assert!(scene.objects[0].transform.translation == (1.0, 0.0, 0.5));
+
+    // the reloaded script's callbacks replace the old ones, a broken one keeps them
+    scripts.scripts.hot_reload = true;
+    let touch = |source: &str, seconds| {
+        std::fs::write(path, source).unwrap();
+        std::fs::File::options().write(true).open(path).unwrap()
+            .set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(seconds)).unwrap();
+    };
+    touch("on_update(|dt| set_scale(find(\"crate copy\"), 2.0, 2.0, 2.0));", 10);
+    assert!(scripts.reload_changed(&mut scene, &mut scene_instance) == [path]);
+    scripts.update(&mut scene, &mut scene_instance, &input_state, &key_bindings, 0.5);
+    assert!(scene.objects[0].transform.translation == (1.0, 0.0, 0.5));
+    assert!(scene.objects[1].transform.scale == (2.0, 2.0, 2.0));
+    touch("on_update(|dt| {", 20);
+    assert!(scripts.reload_changed(&mut scene, &mut scene_instance).is_empty());
+    assert!(scripts.context.borrow().callbacks.len() == 1);
+
+    scripts.release(handle);
+    assert!(scripts.context.borrow().callbacks.is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,229 @@
+// World streaming, the world is split into square chunks on the xz plane which are loaded
+// as jobs once the camera comes within the load radius and unloaded past the unload radius.
+// Loading only reads and builds the chunk's data, its geometries are created on the main thread
+// when it arrives, unloaded geometries go through the geometry system's deletion queue
+//
+//     let mut streamer = WorldStreamer::new(engine.config.streaming, Arc::new(|chunk| load_chunk(chunk)));
+//     // every frame
+//     streamer.update(app.camera.translation, &app.job_system, &mut app.geometry_system);
+//     streamer.submit_draws(app);
+
+use std::{
+    collections::HashMap,
+    sync::{mpsc, Arc},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    geometry::{GeometryId, GeometrySystem, Index, Vertex},
+    jobs::JobSystem,
+    math::{ModelMat, Vector},
+    renderer::{material::MaterialId, VkApp},
+};
+
+/// chunk index along x and z
+pub type ChunkCoord = [i32; 2];
+
+#[derive(Clone, Copy, PartialEq, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StreamingSettings {
+    /// side of a chunk in world units
+    pub chunk_size: f32,
+    /// chunks closer than this to the camera are loaded
+    pub load_radius: f32,
+    /// chunks further than this are unloaded, above `load_radius` so chunks on the edge don't reload
+    pub unload_radius: f32,
+    /// chunks loading at once, the nearest go first
+    pub max_pending_loads: usize,
+}
+
+impl Default for StreamingSettings {
+    fn default() -> Self {
+        Self {
+            chunk_size: 64.0,
+            load_radius: 256.0,
+            unload_radius: 320.0,
+            max_pending_loads: 4,
+        }
+    }
+}
+
+/// entity placed in a chunk
+#[derive(Clone, Copy)]
+pub struct ChunkObject {
+    /// index into the chunk's meshes
+    pub mesh: usize,
+    pub material: MaterialId,
+    /// in world space
+    pub transform: ModelMat,
+}
+
+/// what a chunk loader builds off the main thread
+#[derive(Default)]
+pub struct ChunkContents {
+    pub meshes: Vec<(Vec<Vertex>, Vec<Index>)>,
+    pub objects: Vec<ChunkObject>,
+}
+
+/// runs as a job, `None` when the chunk has nothing in it
+pub type ChunkLoader = Arc<dyn Fn(ChunkCoord) -> Option<ChunkContents> + Send + Sync>;
+
+enum Chunk {
+    Loading,
+    Loaded {
+        geometries: Vec<GeometryId>,
+        objects: Vec<ChunkObject>,
+    },
+}
+
+/// the chunk containing `position`
+pub fn chunk_of(position: Vector, chunk_size: f32) -> ChunkCoord {
+    [(position.x / chunk_size).floor() as i32, (position.z / chunk_size).floor() as i32]
+}
+
+/// from `position` to the closest point of the chunk, on the xz plane
+pub fn chunk_distance(chunk: ChunkCoord, position: Vector, chunk_size: f32) -> f32 {
+    let axis_distance = |index: i32, coordinate: f32| {
+        let min = index as f32 * chunk_size;
+        (min - coordinate).max(coordinate - (min + chunk_size)).max(0.0)
+    };
+    let dx = axis_distance(chunk[0], position.x);
+    let dz = axis_distance(chunk[1], position.z);
+    (dx * dx + dz * dz).sqrt()
+}
+
+/// chunks within `radius` of `position`, nearest first
+pub fn chunks_in_radius(position: Vector, radius: f32, chunk_size: f32) -> Vec<ChunkCoord> {
+    let [center_x, center_z] = chunk_of(position, chunk_size);
+    let reach = (radius / chunk_size).ceil() as i32;
+    let mut chunks: Vec<(ChunkCoord, f32)> = (center_x - reach..=center_x + reach)
+        .flat_map(|x| (center_z - reach..=center_z + reach).map(move |z| [x, z]))
+        .map(|chunk| (chunk, chunk_distance(chunk, position, chunk_size)))
+        .filter(|&(_, distance)| distance <= radius)
+        .collect();
+    chunks.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+    chunks.into_iter().map(|(chunk, _)| chunk).collect()
+}
+
+/// `update` once per frame, `unload_all` before the geometry system is destroyed
+pub struct WorldStreamer {
+    pub settings: StreamingSettings,
+    loader: ChunkLoader,
+    chunks: HashMap<ChunkCoord, Chunk>,
+    /// loads in flight
+    pending_loads: usize,
+    sender: mpsc::Sender<(ChunkCoord, Option<ChunkContents>)>,
+    receiver: mpsc::Receiver<(ChunkCoord, Option<ChunkContents>)>,
+}
+
+impl WorldStreamer {
+    pub fn new(settings: StreamingSettings, loader: ChunkLoader) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            settings,
+            loader,
+            chunks: HashMap::new(),
+            pending_loads: 0,
+            sender,
+            receiver,
+        }
+    }
+
+    pub fn is_loaded(&self, chunk: ChunkCoord) -> bool {
+        matches!(self.chunks.get(&chunk), Some(Chunk::Loaded { .. }))
+    }
+
+    /// loaded chunks
+    pub fn loaded_count(&self) -> usize {
+        self.chunks.values().filter(|chunk| matches!(chunk, Chunk::Loaded { .. })).count()
+    }
+
+    /// creates the geometries of chunks that finished loading, unloads chunks out of range
+    /// and starts loading the nearest missing ones
+    pub fn update(&mut self, camera_position: Vector, job_system: &JobSystem, geometry_system: &mut GeometrySystem) {
+        let StreamingSettings { chunk_size, load_radius, unload_radius, max_pending_loads } = self.settings;
+
+        for (coord, contents) in self.receiver.try_iter() {
+            self.pending_loads -= 1;
+            // unloaded while loading, or loaded again by a later job
+            let Some(chunk @ Chunk::Loading) = self.chunks.get_mut(&coord) else {
+                continue;
+            };
+            let ChunkContents { meshes, objects } = contents.unwrap_or_default();
+            *chunk = Chunk::Loaded {
+                geometries: meshes
+                    .iter()
+                    .map(|(vertices, indices)| geometry_system.create_geometry(vertices, indices))
+                    .collect(),
+                objects,
+            };
+        }
+
+        self.chunks.retain(|&coord, chunk| {
+            if chunk_distance(coord, camera_position, chunk_size) <= unload_radius {
+                return true;
+            }
+            if let Chunk::Loaded { geometries, .. } = chunk {
+                geometries.iter().for_each(|&geometry| geometry_system.destroy_geometry(geometry));
+            }
+            false
+        });
+
+        for coord in chunks_in_radius(camera_position, load_radius, chunk_size) {
+            if self.pending_loads >= max_pending_loads {
+                break;
+            }
+            if self.chunks.contains_key(&coord) {
+                continue;
+            }
+            self.chunks.insert(coord, Chunk::Loading);
+            self.pending_loads += 1;
+
+            let loader = self.loader.clone();
+            let sender = self.sender.clone();
+            job_system.spawn("load chunk", move || {
+                // the streamer may be gone by now
+                let _ = sender.send((coord, loader(coord)));
+            });
+        }
+    }
+
+    /// draws every object of the loaded chunks this frame
+    pub fn submit_draws(&self, app: &mut VkApp) {
+        for chunk in self.chunks.values() {
+            if let Chunk::Loaded { geometries, objects } = chunk {
+                for object in objects {
+                    app.submit_draw(geometries[object.mesh], object.material, object.transform);
+                }
+            }
+        }
+    }
+
+    /// destroys the loaded chunks' geometries, loads in flight are dropped when they arrive
+    pub fn unload_all(&mut self, geometry_system: &mut GeometrySystem) {
+        for (_, chunk) in self.chunks.drain() {
+            if let Chunk::Loaded { geometries, .. } = chunk {
+                geometries.into_iter().for_each(|geometry| geometry_system.destroy_geometry(geometry));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_streaming() {
+    assert!(chunk_of(Vector::new(-0.5, 10.0, 130.0), 64.0) == [-1, 2]);
+
+    // inside the chunk is no distance, the y axis doesn't count
+    assert!(chunk_distance([0, 0], Vector::new(10.0, 100.0, 10.0), 64.0) == 0.0);
+    assert!(chunk_distance([1, 0], Vector::new(60.0, 0.0, 10.0), 64.0) == 4.0);
+    assert!(chunk_distance([1, 1], Vector::new(61.0, 0.0, 60.0), 64.0) == 5.0);
+
+    let chunks = chunks_in_radius(Vector::new(32.0, 0.0, 32.0), 64.0, 64.0);
+    // the chunk itself, its 4 neighbours and the 4 diagonal ones, whose corners are 45 away
+    assert!(chunks.len() == 9 && chunks[0] == [0, 0]);
+    assert!(chunks.contains(&[-1, -1]) && !chunks.contains(&[2, 0]));
+
+    let chunks = chunks_in_radius(Vector::new(32.0, 0.0, 32.0), 40.0, 64.0);
+    assert!(chunks.len() == 5);
+}
@@ -0,0 +1,91 @@
+// Suspending while the window can't be seen. A minimized or occluded window suspends the engine:
+// frames are no longer drawn, the event loop wakes up at a slow interval instead of spinning,
+// and after a while the swapchain is released. Showing the window again resumes,
+// the swapchain is renewed by the next drawn frame
+
+/// what changed with an `update`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SuspendTransition {
+    Suspended,
+    /// suspended long enough that the swapchain should go
+    Release,
+    Resumed,
+}
+
+#[derive(Default)]
+pub struct SuspendTracker {
+    minimized: bool,
+    occluded: bool,
+    /// when the current suspension started
+    suspended_at: Option<f32>,
+    released: bool,
+}
+
+impl SuspendTracker {
+    /// by the window's size, no area is minimized
+    pub fn set_minimized(&mut self, minimized: bool) {
+        self.minimized = minimized;
+    }
+
+    /// e.g. fully covered by other windows, not every platform reports it
+    pub fn set_occluded(&mut self, occluded: bool) {
+        self.occluded = occluded;
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        self.suspended_at.is_some()
+    }
+
+    /// seconds since the suspension started, 0 when not suspended
+    pub fn suspended_for(&self, time: f32) -> f32 {
+        self.suspended_at.map_or(0.0, |suspended_at| time - suspended_at)
+    }
+
+    /// call once per loop iteration with the seconds since startup,
+    /// `release_after` seconds of suspension release, negative never does
+    pub fn update(&mut self, time: f32, release_after: f32) -> Option<SuspendTransition> {
+        let hidden = self.minimized || self.occluded;
+        match self.suspended_at {
+            None if hidden => {
+                self.suspended_at = Some(time);
+                self.released = false;
+                Some(SuspendTransition::Suspended)
+            }
+            Some(_) if !hidden => {
+                self.suspended_at = None;
+                Some(SuspendTransition::Resumed)
+            }
+            Some(suspended_at) if !self.released && release_after >= 0.0 && time - suspended_at >= release_after => {
+                self.released = true;
+                Some(SuspendTransition::Release)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn test_suspend_tracker() {
+    let mut tracker = SuspendTracker::default();
+    assert!(tracker.update(0.0, 5.0).is_none() && !tracker.is_suspended());
+
+    tracker.set_minimized(true);
+    assert!(tracker.update(1.0, 5.0) == Some(SuspendTransition::Suspended));
+    assert!(tracker.is_suspended() && tracker.update(2.0, 5.0).is_none());
+    // occluded while minimized stays one suspension
+    tracker.set_occluded(true);
+    assert!(tracker.update(5.0, 5.0).is_none());
+    assert!(tracker.update(6.0, 5.0) == Some(SuspendTransition::Release));
+    assert!(tracker.update(60.0, 5.0).is_none() && tracker.suspended_for(60.0) == 59.0);
+
+    tracker.set_minimized(false);
+    assert!(tracker.update(61.0, 5.0).is_none());
+    tracker.set_occluded(false);
+    assert!(tracker.update(62.0, 5.0) == Some(SuspendTransition::Resumed));
+    assert!(!tracker.is_suspended() && tracker.suspended_for(63.0) == 0.0);
+
+    // a negative delay never releases
+    tracker.set_occluded(true);
+    assert!(tracker.update(70.0, -1.0) == Some(SuspendTransition::Suspended));
+    assert!(tracker.update(1000.0, -1.0).is_none());
+}
@@ -0,0 +1,341 @@
+// Terrain from a heightmap, split into square chunks that are culled against the view
+// and drawn at a level of detail picked by their distance from the camera.
+// Skirts hang below the chunk edges to hide the cracks between neighbouring levels
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    geometry::{Index, Vertex},
+    math::{simd::{self, Aabbs}, Aabb, Frustum, Vector},
+    utils,
+};
+
+/// Heights sampled on a grid, rows along x
+pub struct Heightmap {
+    width: u32,
+    depth: u32,
+    heights: Vec<f32>,
+}
+
+impl Heightmap {
+    pub fn new(width: u32, depth: u32, heights: Vec<f32>) -> Self {
+        assert!(heights.len() == (width * depth) as usize, "Heightmap has the wrong number of heights");
+        Self { width, depth, heights }
+    }
+
+    /// the image's brightness from 0 to 1
+    pub fn load(path: &str) -> Self {
+        let image = image::open(path)
+            .unwrap_or_else(|err| panic!("Failed to load heightmap {}: {}", path, err))
+            .to_luma();
+        let (width, depth) = image.dimensions();
+        let heights = image.into_raw().into_iter().map(|height| height as f32 / 255.0).collect();
+        Self::new(width, depth, heights)
+    }
+
+    pub fn get_width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn get_depth(&self) -> u32 {
+        self.depth
+    }
+
+    /// clamped to the edges
+    pub fn height(&self, x: i32, z: i32) -> f32 {
+        let x = x.clamp(0, self.width as i32 - 1) as usize;
+        let z = z.clamp(0, self.depth as i32 - 1) as usize;
+        self.heights[z * self.width as usize + x]
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TerrainDesc {
+    /// quads along a chunk's side at the finest level, a power of two dividing the heightmap's size minus 1
+    pub chunk_quads: u32,
+    /// each coarser level halves the quads along a chunk's side
+    pub lod_count: u32,
+    /// world units between heightmap samples
+    pub cell_size: f32,
+    /// world units of height for a heightmap value of 1
+    pub height_scale: f32,
+    /// chunks closer to the camera use the finest level, each coarser level starts twice as far
+    pub lod_distance: f32,
+    /// how far skirts hang below the chunk edges
+    pub skirt_depth: f32,
+    /// world position of the first heightmap sample at height 0
+    pub origin: (f32, f32, f32),
+}
+
+impl Default for TerrainDesc {
+    fn default() -> Self {
+        Self {
+            chunk_quads: 32,
+            lod_count: 4,
+            cell_size: 1.0,
+            height_scale: 32.0,
+            lod_distance: 64.0,
+            skirt_depth: 4.0,
+            origin: (0.0, 0.0, 0.0),
+        }
+    }
+}
+
+pub struct Terrain {
+    heightmap: Heightmap,
+    desc: TerrainDesc,
+    /// chunks along x and z
+    chunk_counts: (u32, u32),
+    /// world bounds of each chunk including its skirts, rows along x
+    chunk_aabbs: Aabbs,
+}
+
+impl Terrain {
+    pub fn new(heightmap: Heightmap, desc: TerrainDesc) -> Self {
+        assert!(desc.chunk_quads.is_power_of_two(), "Terrain chunk quads must be a power of two");
+        assert!(desc.lod_count >= 1 && desc.chunk_quads >> (desc.lod_count - 1) >= 1, "Too many terrain levels of detail");
+        for size in [heightmap.width, heightmap.depth] {
+            assert!(
+                size > 1 && (size - 1) % desc.chunk_quads == 0,
+                "Heightmap size minus 1 must be a multiple of {}", desc.chunk_quads,
+            );
+        }
+
+        let chunk_counts = ((heightmap.width - 1) / desc.chunk_quads, (heightmap.depth - 1) / desc.chunk_quads);
+        let mut terrain = Self {
+            heightmap,
+            desc,
+            chunk_counts,
+            chunk_aabbs: Aabbs::default(),
+        };
+        terrain.chunk_aabbs = (0..terrain.chunk_count()).map(|chunk| terrain.calc_chunk_aabb(chunk)).collect();
+        terrain
+    }
+
+    pub fn get_desc(&self) -> &TerrainDesc {
+        &self.desc
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        (self.chunk_counts.0 * self.chunk_counts.1) as usize
+    }
+
+    pub fn get_chunk_aabb(&self, chunk: usize) -> Aabb {
+        self.chunk_aabbs.get(chunk)
+    }
+
+    /// heightmap sample of the chunk's first corner
+    fn chunk_start(&self, chunk: usize) -> (i32, i32) {
+        let chunk = chunk as u32;
+        let quads = self.desc.chunk_quads;
+        (((chunk % self.chunk_counts.0) * quads) as i32, ((chunk / self.chunk_counts.0) * quads) as i32)
+    }
+
+    /// world position of a heightmap sample, up is -y
+    fn position(&self, x: i32, z: i32) -> Vector {
+        let (origin_x, origin_y, origin_z) = self.desc.origin;
+        Vector::new(
+            origin_x + x as f32 * self.desc.cell_size,
+            origin_y - self.heightmap.height(x, z) * self.desc.height_scale,
+            origin_z + z as f32 * self.desc.cell_size,
+        )
+    }
+
+    /// from the finest heights whatever the level of detail, so lighting doesn't pop between levels
+    fn normal(&self, x: i32, z: i32) -> [f32; 3] {
+        let slope = self.desc.height_scale / (2.0 * self.desc.cell_size);
+        let dx = (self.heightmap.height(x + 1, z) - self.heightmap.height(x - 1, z)) * slope;
+        let dz = (self.heightmap.height(x, z + 1) - self.heightmap.height(x, z - 1)) * slope;
+        let norm = (dx * dx + 1.0 + dz * dz).sqrt();
+        [-dx / norm, -1.0 / norm, -dz / norm]
+    }
+
+    fn calc_chunk_aabb(&self, chunk: usize) -> Aabb {
+        let (start_x, start_z) = self.chunk_start(chunk);
+        let quads = self.desc.chunk_quads as i32;
+
+        let mut min = self.position(start_x, start_z);
+        let mut max = min;
+        for z in start_z..=start_z + quads {
+            for x in start_x..=start_x + quads {
+                let position = self.position(x, z);
+                min.y = min.y.min(position.y);
+                max.y = max.y.max(position.y);
+            }
+        }
+        let corner = self.position(start_x + quads, start_z + quads);
+        max.x = corner.x;
+        max.z = corner.z;
+        max.y += self.desc.skirt_depth;
+        Aabb { min, max }
+    }
+
+    /// finest level for chunks within `lod_distance` of `eye`, one coarser each time the distance doubles
+    pub fn select_lod(&self, chunk: usize, eye: Vector) -> u32 {
+        let Aabb { min, max } = self.chunk_aabbs.get(chunk);
+        let closest = Vector::new(eye.x.clamp(min.x, max.x), eye.y.clamp(min.y, max.y), eye.z.clamp(min.z, max.z));
+        let distance = (eye - closest).norm_sqr().sqrt();
+        if distance < self.desc.lod_distance {
+            return 0;
+        }
+        ((distance / self.desc.lod_distance).log2() as u32 + 1).min(self.desc.lod_count - 1)
+    }
+
+    /// chunks in view with their level of detail
+    pub fn visible_chunks(&self, eye: Vector, frustum: &Frustum) -> Vec<(usize, u32)> {
+        let mut visible = vec![];
+        simd::cull_aabbs(frustum, &self.chunk_aabbs, &mut visible);
+        (0..self.chunk_count())
+            .filter(|&chunk| utils::get_bit(&visible, chunk))
+            .map(|chunk| (chunk, self.select_lod(chunk, eye)))
+            .collect()
+    }
+
+    /// world space vertices and triangles of a chunk at a level of detail, the grid comes first then the skirts.
+    /// Texture coordinates span the whole terrain from 0 to 1 for the splat map
+    pub fn chunk_mesh(&self, chunk: usize, lod: u32) -> (Vec<Vertex>, Vec<Index>) {
+        assert!(lod < self.desc.lod_count);
+        let (start_x, start_z) = self.chunk_start(chunk);
+        let step = 1 << lod;
+        let quads = (self.desc.chunk_quads >> lod) as i32;
+        let side = quads as Index + 1;
+
+        let uv_scale = (1.0 / (self.heightmap.width - 1) as f32, 1.0 / (self.heightmap.depth - 1) as f32);
+        let vertex = |x: i32, z: i32| {
+            let position = self.position(x, z);
+            let [nx, ny, nz] = self.normal(x, z);
+            Vertex {
+                x: position.x, y: position.y, z: position.z,
+                u: x as f32 * uv_scale.0, v: z as f32 * uv_scale.1,
+                nx, ny, nz,
+                tx: 1.0, ty: 0.0, tz: 0.0, tw: 1.0,
+            }
+        };
+
+        let mut vertices = Vec::with_capacity((side * side + 4 * side) as usize);
+        for j in 0..=quads {
+            for i in 0..=quads {
+                vertices.push(vertex(start_x + i * step, start_z + j * step));
+            }
+        }
+
+        let mut indices = Vec::with_capacity((6 * quads * quads + 4 * 6 * quads) as usize);
+        for j in 0..side - 1 {
+            for i in 0..side - 1 {
+                let a = j * side + i;
+                let (b, c, d) = (a + 1, a + side, a + side + 1);
+                // counter clockwise seen from above
+                indices.extend_from_slice(&[a, b, c, b, d, c]);
+            }
+        }
+
+        // edge grid vertices in order and the direction facing out of the chunk
+        let edges: [(Vec<Index>, [f32; 3]); 4] = [
+            ((0..side).collect(), [0.0, 0.0, -1.0]),
+            ((0..side).map(|i| (side - 1) * side + i).collect(), [0.0, 0.0, 1.0]),
+            ((0..side).map(|j| j * side).collect(), [-1.0, 0.0, 0.0]),
+            ((0..side).map(|j| j * side + side - 1).collect(), [1.0, 0.0, 0.0]),
+        ];
+        for (edge, outward) in edges {
+            let first_skirt = vertices.len() as Index;
+            for &top in &edge {
+                let top = vertices[top as usize];
+                vertices.push(Vertex { y: top.y + self.desc.skirt_depth, ..top });
+            }
+            for k in 0..edge.len() - 1 {
+                let (p, q) = (edge[k], edge[k + 1]);
+                let (lower_p, lower_q) = (first_skirt + k as Index, first_skirt + k as Index + 1);
+                push_facing_triangle(&vertices, &mut indices, [p, q, lower_p], outward);
+                push_facing_triangle(&vertices, &mut indices, [q, lower_q, lower_p], outward);
+            }
+        }
+
+        (vertices, indices)
+    }
+}
+
+/// (b - a) x (c - a) of the triangle's positions, front faces point it at the viewer
+fn face_normal(vertices: &[Vertex], [a, b, c]: [Index; 3]) -> [f32; 3] {
+    let (a, b, c) = (&vertices[a as usize], &vertices[b as usize], &vertices[c as usize]);
+    let e1 = [b.x - a.x, b.y - a.y, b.z - a.z];
+    let e2 = [c.x - a.x, c.y - a.y, c.z - a.z];
+    [
+        e1[1] * e2[2] - e1[2] * e2[1],
+        e1[2] * e2[0] - e1[0] * e2[2],
+        e1[0] * e2[1] - e1[1] * e2[0],
+    ]
+}
+
+/// wound so the front face looks along `outward`
+fn push_facing_triangle(vertices: &[Vertex], indices: &mut Vec<Index>, triangle: [Index; 3], outward: [f32; 3]) {
+    let normal = face_normal(vertices, triangle);
+    let [a, b, c] = triangle;
+    if normal[0] * outward[0] + normal[1] * outward[1] + normal[2] * outward[2] >= 0.0 {
+        indices.extend_from_slice(&[a, b, c]);
+    } else {
+        indices.extend_from_slice(&[a, c, b]);
+    }
+}
+
+#[test]
+fn test_terrain_chunks() {
+    // a ramp rising along x
+    let heights = (0..25).map(|i| (i % 5) as f32 / 4.0).collect();
+    let terrain = Terrain::new(Heightmap::new(5, 5, heights), TerrainDesc {
+        chunk_quads: 2,
+        lod_count: 2,
+        height_scale: 4.0,
+        lod_distance: 10.0,
+        skirt_depth: 1.0,
+        ..Default::default()
+    });
+    assert!(terrain.chunk_count() == 4);
+    let aabb = terrain.get_chunk_aabb(3);
+    assert!(aabb.min.x == 2.0 && aabb.max.z == 4.0 && aabb.min.y == -4.0 && aabb.max.y == -2.0 + 1.0);
+
+    let (vertices, indices) = terrain.chunk_mesh(0, 0);
+    assert!(vertices.len() == 9 + 4 * 3 && indices.len() == 3 * (8 + 4 * 4));
+    let (coarse_vertices, coarse_indices) = terrain.chunk_mesh(0, 1);
+    assert!(coarse_vertices.len() == 4 + 4 * 2 && coarse_indices.len() == 3 * (2 + 4 * 2));
+
+    // the grid faces up, skirts face out of the chunk
+    for triangle in indices.chunks_exact(3) {
+        let triangle = [triangle[0], triangle[1], triangle[2]];
+        let normal = face_normal(&vertices, triangle);
+        if triangle.iter().all(|&index| index < 9) {
+            assert!(normal[1] < 0.0);
+        } else {
+            // from the chunk's center at (1, 1)
+            let [a, b, c] = triangle.map(|index| vertices[index as usize]);
+            let out = [(a.x + b.x + c.x) / 3.0 - 1.0, (a.z + b.z + c.z) / 3.0 - 1.0];
+            assert!(normal[0] * out[0] + normal[2] * out[1] > 0.0);
+        }
+    }
+    // rising along x tilts the normal towards -x
+    assert!(vertices[4].nx < 0.0 && vertices[4].ny < 0.0 && vertices[4].nz == 0.0);
+
+    let eye = Vector::new(1.0, -10.0, 1.0);
+    assert!(terrain.select_lod(0, eye) == 0);
+    assert!(terrain.select_lod(0, Vector::new(1.0, -100.0, 1.0)) == 1);
+
+    // looking down at the terrain, then up away from it
+    let mut camera = crate::camera::Camera {
+        translation: Vector::new(2.0, -5.0, 2.0),
+        z_x_angle: 0.0,
+        y_xz_angle: 1.4,
+        roll: 0.0,
+        aspect_ratio: 1.0,
+        near_z: 1.0,
+        far_z: 100.0,
+        reverse_z: false,
+        projection: crate::camera::Projection::Perspective,
+        translation_speed: 1.0,
+    };
+    let visible = |camera: &crate::camera::Camera| {
+        terrain.visible_chunks(camera.translation, &Frustum::from_proj_view(&camera.calc_proj_view()))
+    };
+    assert!(visible(&camera).len() == 4);
+    camera.y_xz_angle = -1.4;
+    assert!(visible(&camera).is_empty());
+}
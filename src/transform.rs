@@ -0,0 +1,262 @@
+// Transforms of objects in a hierarchy, with world matrices cached until something above them
+// changes. Changing a local transform only marks it dirty, `update` recomputes the dirty ones
+// and everything below them, walking the hierarchy breadth first so parents come before their
+// children and the matrices are visited front to back:
+//
+//     let mut transforms = TransformHierarchy::default();
+//     let body = transforms.add(Transform::default(), None);
+//     let wheel = transforms.add(Transform { translation: Vector::new(1.0, 0.0, 0.0), ..Default::default() }, Some(body));
+//     transforms.get_local_mut(body).translation.z += speed * dt;
+//     transforms.update();
+//     let wheel_world = transforms.get_world(wheel);
+//
+// `TransformHierarchy::from_scene` mirrors a scene's objects, ids are object indices
+
+use crate::{
+    math::{simd::{self, Vectors}, ModelMat, Rotor, Vector},
+    scene::{self, Scene},
+    utils,
+};
+
+/// relative to the parent
+#[derive(Clone, Copy, Debug)]
+pub struct Transform {
+    pub translation: Vector,
+    pub rotation: Rotor,
+    pub scale: Vector,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: Vector::new(0.0, 0.0, 0.0),
+            rotation: Rotor::identity(),
+            scale: Vector::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl Transform {
+    pub fn to_model_mat(&self) -> ModelMat {
+        ModelMat::from(self.scale, self.rotation, self.translation)
+    }
+}
+
+impl From<scene::Transform> for Transform {
+    fn from(transform: scene::Transform) -> Self {
+        let (x, y, z) = transform.translation;
+        let (scalar, yx, zy, xz) = transform.rotation;
+        let (sx, sy, sz) = transform.scale;
+        Self {
+            translation: Vector::new(x, y, z),
+            rotation: Rotor::new(scalar, yx, zy, xz),
+            scale: Vector::new(sx, sy, sz),
+        }
+    }
+}
+
+/// index of a transform, in order of adding
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct TransformId(u32);
+
+impl TransformId {
+    pub const fn index(&self) -> usize {
+        self.0 as usize
+    }
+}
+
+#[derive(Default)]
+pub struct TransformHierarchy {
+    locals: Vec<Transform>,
+    parents: Vec<Option<TransformId>>,
+    /// valid after `update`
+    worlds: Vec<ModelMat>,
+    /// a bit per transform, set while its world matrix is out of date
+    dirty: Vec<usize>,
+    /// breadth first, rebuilt by `update` when parents changed
+    order: Vec<u32>,
+    order_outdated: bool,
+}
+
+impl TransformHierarchy {
+    /// every object of `scene` with its parent, updated
+    pub fn from_scene(scene: &Scene) -> Self {
+        let mut transforms = Self::default();
+        for object in &scene.objects {
+            let parent = object.parent.map(|parent| TransformId(parent as u32));
+            transforms.add(object.transform.into(), parent);
+        }
+        transforms.update();
+        transforms
+    }
+
+    pub fn len(&self) -> usize {
+        self.locals.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locals.is_empty()
+    }
+
+    /// dirty until the next `update`
+    pub fn add(&mut self, local: Transform, parent: Option<TransformId>) -> TransformId {
+        let id = TransformId(self.locals.len() as u32);
+        self.locals.push(local);
+        self.parents.push(parent);
+        self.worlds.push(ModelMat::identity());
+        if self.dirty.len() * usize::BITS as usize <= id.index() {
+            self.dirty.push(0);
+        }
+        utils::set_bit_true(&mut self.dirty, id.index());
+        self.order_outdated = true;
+        id
+    }
+
+    pub fn get_parent(&self, id: TransformId) -> Option<TransformId> {
+        self.parents[id.index()]
+    }
+
+    /// keeps the local transform, so the world transform moves along with the new parent
+    pub fn set_parent(&mut self, id: TransformId, parent: Option<TransformId>) {
+        let mut ancestor = parent;
+        while let Some(above) = ancestor {
+            assert!(above != id, "Parenting transform {} to itself or its descendant", id.index());
+            ancestor = self.parents[above.index()];
+        }
+        self.parents[id.index()] = parent;
+        utils::set_bit_true(&mut self.dirty, id.index());
+        self.order_outdated = true;
+    }
+
+    pub fn get_local(&self, id: TransformId) -> &Transform {
+        &self.locals[id.index()]
+    }
+
+    /// marks the transform dirty
+    pub fn get_local_mut(&mut self, id: TransformId) -> &mut Transform {
+        utils::set_bit_true(&mut self.dirty, id.index());
+        &mut self.locals[id.index()]
+    }
+
+    pub fn set_local(&mut self, id: TransformId, local: Transform) {
+        *self.get_local_mut(id) = local;
+    }
+
+    pub fn is_dirty(&self, id: TransformId) -> bool {
+        utils::get_bit(&self.dirty, id.index())
+    }
+
+    /// as of the last `update`
+    pub fn get_world(&self, id: TransformId) -> &ModelMat {
+        &self.worlds[id.index()]
+    }
+
+    /// every world matrix by id, as of the last `update`
+    pub fn get_worlds(&self) -> &[ModelMat] {
+        &self.worlds
+    }
+
+    /// `out` gets `points` relative to `id` in world space as of the last `update`,
+    /// for carrying attached points such as sockets or emitters along in bulk
+    pub fn transform_points(&self, id: TransformId, points: &Vectors, out: &mut Vectors) {
+        simd::transform_points(&self.worlds[id.index()], points, out);
+    }
+
+    /// recomputes the world matrices of dirty transforms and of everything below them,
+    /// returns how many were recomputed
+    pub fn update(&mut self) -> usize {
+        if self.order_outdated {
+            self.order = breadth_first_order(&self.parents);
+            self.order_outdated = false;
+        }
+
+        let mut recomputed = 0;
+        for &index in &self.order {
+            let index = index as usize;
+            let parent = self.parents[index];
+            // parents come first, a recomputed parent is still marked dirty
+            let parent_dirty = parent.is_some_and(|parent| utils::get_bit(&self.dirty, parent.index()));
+            if !parent_dirty && !utils::get_bit(&self.dirty, index) {
+                continue;
+            }
+            utils::set_bit_true(&mut self.dirty, index);
+            let local = self.locals[index].to_model_mat();
+            self.worlds[index] = match parent {
+                Some(parent) => self.worlds[parent.index()] * local,
+                None => local,
+            };
+            recomputed += 1;
+        }
+        self.dirty.fill(0);
+        recomputed
+    }
+}
+
+/// roots in order of their ids, then their children, then their grandchildren...
+pub fn breadth_first_order(parents: &[Option<TransformId>]) -> Vec<u32> {
+    let mut children: Vec<Vec<u32>> = vec![vec![]; parents.len()];
+    let mut order = Vec::with_capacity(parents.len());
+    for (index, parent) in parents.iter().enumerate() {
+        match parent {
+            Some(parent) => children[parent.index()].push(index as u32),
+            None => order.push(index as u32),
+        }
+    }
+    let mut next = 0;
+    while next < order.len() {
+        order.extend_from_slice(&children[order[next] as usize]);
+        next += 1;
+    }
+    order
+}
+
+#[test]
+fn test_transform_hierarchy() {
+    let close = |a: Vector, b: Vector| (a - b).norm_sqr() < 1e-10;
+    let at = |x, y, z| Transform { translation: Vector::new(x, y, z), ..Default::default() };
+
+    let mut transforms = TransformHierarchy::default();
+    let root = transforms.add(at(1.0, 0.0, 0.0), None);
+    let child = transforms.add(Transform { scale: Vector::new(2.0, 2.0, 2.0), ..at(0.0, 1.0, 0.0) }, Some(root));
+    let grandchild = transforms.add(at(0.0, 0.0, 1.0), Some(child));
+    let other = transforms.add(at(5.0, 0.0, 0.0), None);
+    assert!(transforms.len() == 4 && transforms.update() == 4 && !transforms.is_dirty(root));
+    assert!(close(transforms.get_world(grandchild).translation(), Vector::new(1.0, 1.0, 2.0)));
+
+    // nothing changed, nothing recomputed, then only the changed subtree
+    assert!(transforms.update() == 0);
+    transforms.get_local_mut(child).translation.y = 3.0;
+    assert!(transforms.is_dirty(child) && transforms.update() == 2);
+    assert!(close(transforms.get_world(grandchild).translation(), Vector::new(1.0, 3.0, 2.0)));
+    assert!(close(transforms.get_world(other).translation(), Vector::new(5.0, 0.0, 0.0)));
+    let (mut points, mut world_points) = (Vectors::default(), Vectors::default());
+    points.push(Vector::new(0.0, 0.0, 0.0));
+    transforms.transform_points(grandchild, &points, &mut world_points);
+    assert!(close(world_points.get(0), Vector::new(1.0, 3.0, 2.0)));
+
+    // reparenting keeps the local transform
+    transforms.set_parent(child, Some(other));
+    assert!(transforms.update() == 2);
+    assert!(close(transforms.get_world(grandchild).translation(), Vector::new(5.0, 3.0, 2.0)));
+    let cycle = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| transforms.set_parent(other, Some(grandchild))));
+    assert!(cycle.is_err() && transforms.get_parent(other).is_none());
+
+    // breadth first, ids added later can be parents of earlier ones
+    let parents = [Some(TransformId(2)), None, Some(TransformId(1)), Some(TransformId(1)), None];
+    assert!(breadth_first_order(&parents) == [1, 4, 2, 3, 0]);
+
+    // matches the scene's own world transforms
+    let scene = Scene::parse(r#"(
+        camera: (translation: (0.0, 0.0, 0.0), z_x_angle: 0.0, y_xz_angle: 0.0, near_z: 0.1, far_z: 100.0),
+        materials: [],
+        objects: [
+            (name: "a", transform: (translation: (1.0, 2.0, 3.0), rotation: (0.7071, 0.7071, 0.0, 0.0), scale: (1.0, 1.0, 1.0))),
+            (name: "b", parent: Some(0), transform: (translation: (0.0, 1.0, 0.0), rotation: (1.0, 0.0, 0.0, 0.0), scale: (2.0, 2.0, 2.0))),
+        ],
+    )"#).unwrap();
+    let transforms = TransformHierarchy::from_scene(&scene);
+    for (world, expected) in transforms.get_worlds().iter().zip(scene.world_transforms()) {
+        assert!((0..3).all(|axis| close(world.axis(axis), expected.axis(axis))));
+        assert!(close(world.translation(), expected.translation()));
+    }
+}
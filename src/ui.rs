@@ -0,0 +1,56 @@
+// Screen ui placed relative to a corner, an edge or the center of the screen, in logical pixels
+// so it keeps its size across dpi scales. Placing ui every frame from the current size lays it out
+// again whenever the window resizes or moves to a monitor with another scale:
+//
+//     let [x, y] = Anchor::TopRight.place([-16.0, 16.0], [200.0, 24.0], app.get_ui_size());
+//     app.sprite_renderer.submit([Sprite { facing: SpriteFacing::Screen, position: [x, y, 0.0], size: [200.0, 24.0], ..health_bar }]);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Anchor {
+    #[default]
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    /// of the screen and of the element, 0 at the left or top, 1 at the right or bottom
+    fn fraction(self) -> [f32; 2] {
+        match self {
+            Anchor::TopLeft => [0.0, 0.0],
+            Anchor::Top => [0.5, 0.0],
+            Anchor::TopRight => [1.0, 0.0],
+            Anchor::Left => [0.0, 0.5],
+            Anchor::Center => [0.5, 0.5],
+            Anchor::Right => [1.0, 0.5],
+            Anchor::BottomLeft => [0.0, 1.0],
+            Anchor::Bottom => [0.5, 1.0],
+            Anchor::BottomRight => [1.0, 1.0],
+        }
+    }
+
+    /// center of an element of `size` whose same point, e.g. its top right corner for `TopRight`,
+    /// is `offset` from the anchor on a screen of `screen_size`, all in logical pixels, y down
+    pub fn place(self, offset: [f32; 2], size: [f32; 2], screen_size: [f32; 2]) -> [f32; 2] {
+        let fraction = self.fraction();
+        [0, 1].map(|axis| {
+            fraction[axis] * screen_size[axis] + offset[axis] + (0.5 - fraction[axis]) * size[axis]
+        })
+    }
+}
+
+#[test]
+fn test_anchor() {
+    let screen_size = [800.0, 600.0];
+    assert!(Anchor::TopLeft.place([10.0, 10.0], [100.0, 20.0], screen_size) == [60.0, 20.0]);
+    assert!(Anchor::Center.place([0.0, 0.0], [100.0, 20.0], screen_size) == [400.0, 300.0]);
+    // the corner stays 10 pixels in from the bottom right as the screen grows
+    assert!(Anchor::BottomRight.place([-10.0, -10.0], [100.0, 20.0], screen_size) == [740.0, 580.0]);
+    assert!(Anchor::BottomRight.place([-10.0, -10.0], [100.0, 20.0], [1600.0, 900.0]) == [1540.0, 880.0]);
+}
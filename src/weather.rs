@@ -0,0 +1,121 @@
+// Weather state, precipitation, wind and how wet surfaces are.
+// The renderer draws the precipitation, shades surfaces by wetness and hands the wind
+// to every shader through the per frame uniform buffer, so anything swaying in it
+// (foliage, cloth, particles) moves with the same gusts
+
+use std::f32::consts::TAU;
+
+use crate::math::Vector;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Precipitation {
+    #[default]
+    None,
+    Rain,
+    Snow,
+}
+
+impl Precipitation {
+    /// None, Rain, Snow, None...
+    pub fn next(self) -> Self {
+        match self {
+            Precipitation::None => Precipitation::Rain,
+            Precipitation::Rain => Precipitation::Snow,
+            Precipitation::Snow => Precipitation::None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Wind {
+    /// in the xz plane, normalized
+    pub direction: (f32, f32),
+    /// m/s
+    pub speed: f32,
+    /// m/s added on top at the peak of a gust
+    pub gust_speed: f32,
+    /// gusts per second
+    pub gust_frequency: f32,
+}
+
+impl Default for Wind {
+    fn default() -> Self {
+        Self {
+            direction: (1.0, 0.0),
+            speed: 1.0,
+            gust_speed: 2.0,
+            gust_frequency: 0.2,
+        }
+    }
+}
+
+impl Wind {
+    pub fn velocity(&self, time: f32) -> Vector {
+        let phase = time * self.gust_frequency * TAU;
+        // two incommensurate waves so gusts don't repeat noticeably
+        let gust = (0.5 + 0.5 * phase.sin()) * (0.5 + 0.5 * (phase * 0.37 + 1.3).sin());
+        let speed = self.speed + self.gust_speed * gust;
+        Vector::new(self.direction.0 * speed, 0.0, self.direction.1 * speed)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Weather {
+    pub precipitation: Precipitation,
+    /// 0..=1, scales the particle count and how fast surfaces get wet
+    pub intensity: f32,
+    pub wind: Wind,
+    /// 0 dry to 1 soaked, follows the precipitation over time
+    pub wetness: f32,
+    /// seconds of full intensity rain to soak a dry surface
+    pub soak_secs: f32,
+    /// seconds for a soaked surface to dry
+    pub dry_secs: f32,
+}
+
+impl Default for Weather {
+    fn default() -> Self {
+        Self {
+            precipitation: Precipitation::None,
+            intensity: 0.5,
+            wind: Wind::default(),
+            wetness: 0.0,
+            soak_secs: 30.0,
+            dry_secs: 120.0,
+        }
+    }
+}
+
+impl Weather {
+    pub fn update(&mut self, dt: f32) {
+        // snow settles instead of wetting
+        let rate = match self.precipitation {
+            Precipitation::Rain => self.intensity / self.soak_secs,
+            Precipitation::None | Precipitation::Snow => -1.0 / self.dry_secs,
+        };
+        self.wetness = (self.wetness + rate * dt).clamp(0.0, 1.0);
+    }
+}
+
+#[test]
+fn test_weather() {
+    let mut weather = Weather {
+        precipitation: Precipitation::Rain,
+        intensity: 1.0,
+        ..Default::default()
+    };
+    weather.update(weather.soak_secs / 2.0);
+    assert!((weather.wetness - 0.5).abs() < 1e-5);
+    weather.update(weather.soak_secs);
+    assert!(weather.wetness == 1.0);
+
+    weather.precipitation = Precipitation::Snow;
+    weather.update(weather.dry_secs);
+    assert!(weather.wetness == 0.0);
+
+    let wind = Wind::default();
+    for i in 0..100 {
+        let speed = wind.velocity(i as f32 * 0.7).x;
+        assert!(speed >= wind.speed && speed <= wind.speed + wind.gust_speed);
+    }
+}
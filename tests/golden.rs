@@ -0,0 +1,52 @@
+// Golden image tests of a lit cube drawn by each render path, ignored by default since they need
+// a vulkan device and a display, see src/golden.rs for running them and updating the goldens:
+//
+//     cargo test --test golden -- --ignored
+
+use ash_engine::{
+    golden::{check_golden, render_scene, Tolerance},
+    light::DirectionalLight,
+    math::{ModelMat, Vector},
+    renderer::{material::Material, RenderPath, VkApp},
+};
+
+/// frames rendered before the one compared, for the per frame resources to settle
+const FRAME_COUNT: u32 = 3;
+
+/// a unit cube turned to show three faces, four units in front of the camera under the default light
+fn lit_cube(app: &mut VkApp, render_path: RenderPath) -> impl FnMut(&mut VkApp) {
+    app.set_render_path(render_path);
+    app.camera.translation = Vector::new(0.0, 0.0, -4.0);
+    app.camera.z_x_angle = 0.0;
+    app.camera.y_xz_angle = 0.0;
+    app.camera.roll = 0.0;
+    app.light = DirectionalLight::default();
+
+    let handle = app.load_geometry("primitives/cube").unwrap();
+    let cube = *app.geometry_assets.get(handle).unwrap();
+    let fallback_texture = app.get_fallback_texture().index() as u32;
+    let material = app.material_system.create_material(Material {
+        diffuse_texture: fallback_texture,
+        normal_texture: fallback_texture,
+        albedo: [0.8, 0.3, 0.2, 1.0],
+        ..Default::default()
+    });
+    let mut transform = ModelMat::identity();
+    transform.rotate(0.6, 0.0, 0.0, 1.0).rotate(0.5, 0.0, 1.0, 0.0);
+
+    move |app: &mut VkApp| app.submit_draw(cube, material, transform)
+}
+
+#[test]
+#[ignore]
+fn golden_lit_cube_forward() {
+    let (width, height, pixels) = render_scene(FRAME_COUNT, |app| lit_cube(app, RenderPath::Forward));
+    check_golden("lit_cube_forward", width, height, &pixels, &Tolerance::default());
+}
+
+#[test]
+#[ignore]
+fn golden_lit_cube_deferred() {
+    let (width, height, pixels) = render_scene(FRAME_COUNT, |app| lit_cube(app, RenderPath::Deferred));
+    check_golden("lit_cube_deferred", width, height, &pixels, &Tolerance::default());
+}